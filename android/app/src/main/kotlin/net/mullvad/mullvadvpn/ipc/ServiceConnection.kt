@@ -56,7 +56,7 @@ class ServiceConnection(context: Context, scope: CoroutineScope) {
                 val tunnelStateEvents = subscribeToState(
                     Event.TunnelStateChange::class,
                     scope,
-                    TunnelState.Disconnected
+                    TunnelState.Disconnected()
                 ) { tunnelState }
 
                 tunnelState = tunnelStateEvents
@@ -9,5 +9,6 @@ enum class DeviceEventCause : Parcelable {
     LoggedOut,
     Revoked,
     Updated,
-    RotatedKey
+    RotatedKey,
+    RepairedKey
 }
@@ -4,12 +4,14 @@ import android.os.Parcelable
 import kotlinx.parcelize.Parcelize
 import net.mullvad.talpid.net.TunnelEndpoint
 import net.mullvad.talpid.tunnel.ActionAfterDisconnect
+import net.mullvad.talpid.tunnel.DisconnectReason
 import net.mullvad.talpid.tunnel.ErrorState
 import net.mullvad.talpid.tunnel.ErrorStateCause
+import net.mullvad.talpid.tunnel.TunnelHealth
 
 sealed class TunnelState() : Parcelable {
     @Parcelize
-    object Disconnected : TunnelState(), Parcelable
+    class Disconnected(val reason: DisconnectReason? = null) : TunnelState(), Parcelable
 
     @Parcelize
     class Connecting(
@@ -20,7 +22,8 @@ sealed class TunnelState() : Parcelable {
     @Parcelize
     class Connected(
         val endpoint: TunnelEndpoint,
-        val location: GeoIpLocation?
+        val location: GeoIpLocation?,
+        val health: TunnelHealth = TunnelHealth.Healthy
     ) : TunnelState(), Parcelable
 
     @Parcelize
@@ -52,7 +55,7 @@ sealed class TunnelState() : Parcelable {
 
         fun fromString(description: String, endpoint: TunnelEndpoint?): TunnelState {
             return when (description) {
-                DISCONNECTED -> TunnelState.Disconnected
+                DISCONNECTED -> TunnelState.Disconnected()
                 CONNECTING -> TunnelState.Connecting(endpoint, null)
                 CONNECTED -> TunnelState.Connected(endpoint!!, null)
                 RECONNECTING -> TunnelState.Disconnecting(ActionAfterDisconnect.Reconnect)
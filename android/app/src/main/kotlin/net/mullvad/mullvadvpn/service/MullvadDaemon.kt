@@ -29,7 +29,7 @@ class MullvadDaemon(
     protected var daemonInterfaceAddress = 0L
 
     val onSettingsChange = EventNotifier<Settings?>(null)
-    var onTunnelStateChange = EventNotifier<TunnelState>(TunnelState.Disconnected)
+    var onTunnelStateChange = EventNotifier<TunnelState>(TunnelState.Disconnected())
 
     var onAppVersionInfoChange: ((AppVersionInfo) -> Unit)? = null
     var onRelayListChange: ((RelayList) -> Unit)? = null
@@ -53,7 +53,7 @@ class MullvadDaemon(
 
         onSettingsChange.notify(getSettings())
 
-        onTunnelStateChange.notify(getState() ?: TunnelState.Disconnected)
+        onTunnelStateChange.notify(getState() ?: TunnelState.Disconnected())
     }
 
     fun connect() {
@@ -198,7 +198,7 @@ class MullvadVpnService : TalpidVpnService() {
     override fun onTaskRemoved(rootIntent: Intent?) {
         connectionProxy.onStateChange.latestEvent.let { tunnelState ->
             Log.d(TAG, "Task removed (tunnelState=$tunnelState)")
-            if (tunnelState == TunnelState.Disconnected) {
+            if (tunnelState is TunnelState.Disconnected) {
                 notificationManager.cancelNotification()
                 stop()
             }
@@ -47,7 +47,7 @@ class LocationInfoCache(private val endpoint: ServiceEndpoint) {
         endpoint.sendEvent(Event.NewLocation(newLocation))
     }
 
-    var state by observable<TunnelState>(TunnelState.Disconnected) { _, _, newState ->
+    var state by observable<TunnelState>(TunnelState.Disconnected()) { _, _, newState ->
         when (newState) {
             is TunnelState.Disconnected -> {
                 location = lastKnownRealLocation
@@ -79,7 +79,7 @@ class ConnectFragment : BaseFragment(), NavigationBarPainter {
         val view = inflater.inflate(R.layout.connect, container, false)
 
         headerBar = view.findViewById<HeaderBar>(R.id.header_bar).apply {
-            tunnelState = TunnelState.Disconnected
+            tunnelState = TunnelState.Disconnected()
         }
 
         accountExpiryNotification.onClick = {
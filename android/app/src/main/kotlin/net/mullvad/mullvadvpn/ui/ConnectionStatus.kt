@@ -6,6 +6,7 @@ import android.widget.TextView
 import net.mullvad.mullvadvpn.R
 import net.mullvad.mullvadvpn.model.TunnelState
 import net.mullvad.talpid.tunnel.ActionAfterDisconnect
+import net.mullvad.talpid.tunnel.TunnelHealth
 
 class ConnectionStatus(val parentView: View, context: Context) {
     private val spinner: View = parentView.findViewById(R.id.connecting_spinner)
@@ -26,7 +27,7 @@ class ConnectionStatus(val parentView: View, context: Context) {
             }
             is TunnelState.Disconnected -> disconnected()
             is TunnelState.Connecting -> connecting()
-            is TunnelState.Connected -> connected()
+            is TunnelState.Connected -> connected(state.health)
             is TunnelState.Error -> errorState(state.errorState.isBlocking)
         }
     }
@@ -45,11 +46,15 @@ class ConnectionStatus(val parentView: View, context: Context) {
         text.setText(R.string.creating_secure_connection)
     }
 
-    private fun connected() {
+    private fun connected(health: TunnelHealth = TunnelHealth.Healthy) {
         spinner.visibility = View.GONE
 
         text.setTextColor(securedTextColor)
-        text.setText(R.string.secure_connection)
+        if (health == TunnelHealth.Healthy) {
+            text.setText(R.string.secure_connection)
+        } else {
+            text.setText(R.string.unstable_secure_connection)
+        }
     }
 
     private fun errorState(isBlocking: Boolean) {
@@ -46,7 +46,7 @@ class LocationInfo(
             updateOutAddress(value)
         }
 
-    var state: TunnelState = TunnelState.Disconnected
+    var state: TunnelState = TunnelState.Disconnected()
         set(value) {
             field = value
 
@@ -67,7 +67,7 @@ class WelcomeFragment : BaseFragment() {
         val view = inflater.inflate(R.layout.welcome, container, false)
 
         headerBar = view.findViewById<HeaderBar>(R.id.header_bar).apply {
-            tunnelState = TunnelState.Disconnected
+            tunnelState = TunnelState.Disconnected()
         }
 
         accountLabel = view.findViewById<TextView>(R.id.account_number).apply {
@@ -23,6 +23,7 @@ fun ErrorStateCause.errorMessageId(): Int {
                 ParameterGenerationError.CustomTunnelHostResultionError -> {
                     R.string.custom_tunnel_host_resolution_error
                 }
+                ParameterGenerationError.MissingRelayCapabilities -> R.string.no_matching_relay
             }
         }
         is ErrorStateCause.VpnPermissionDenied -> R.string.vpn_permission_denied_error
@@ -0,0 +1,16 @@
+package net.mullvad.talpid.tunnel
+
+import android.os.Parcelable
+import kotlinx.parcelize.Parcelize
+
+@Parcelize
+enum class DisconnectReason : Parcelable {
+    UserRequested,
+    LoggedOut,
+    AutoConnectPolicy,
+    Shutdown,
+    TunnelError,
+    AuthFailed,
+    DeviceRevoked,
+    Offline
+}
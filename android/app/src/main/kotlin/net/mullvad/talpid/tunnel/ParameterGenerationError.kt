@@ -1,5 +1,9 @@
 package net.mullvad.talpid.tunnel
 
 enum class ParameterGenerationError {
-    NoMatchingRelay, NoMatchingBridgeRelay, NoWireguardKey, CustomTunnelHostResultionError
+    NoMatchingRelay,
+    NoMatchingBridgeRelay,
+    NoWireguardKey,
+    CustomTunnelHostResultionError,
+    MissingRelayCapabilities
 }
@@ -0,0 +1,12 @@
+package net.mullvad.talpid.tunnel
+
+import android.os.Parcelable
+import kotlinx.parcelize.Parcelize
+
+@Parcelize
+enum class TunnelHealth : Parcelable {
+    Healthy,
+    Unstable,
+    HighLatency,
+    PacketLoss
+}
@@ -103,7 +103,7 @@ class DeviceRevokedViewModelTest {
     fun testGoToLoginWhenDisconnected() {
         // Arrange
         val mockedContainer = mockk<ServiceConnectionContainer>().also {
-            every { it.connectionProxy.state } returns TunnelState.Disconnected
+            every { it.connectionProxy.state } returns TunnelState.Disconnected()
             every { it.connectionProxy.disconnect() } just Runs
             every { mockedAccountRepository.logout() } just Runs
         }
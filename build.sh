@@ -128,6 +128,12 @@ else
 fi
 
 if [[ "$IS_RELEASE" == "true" ]]; then
+    if [[ -z ${MULLVAD_RELAY_LIST_SIGNING_KEY-} ]]; then
+        log_error "The variable MULLVAD_RELAY_LIST_SIGNING_KEY is not set. It needs to contain the"
+        log_error "hex-encoded public key that relay lists are signed with."
+        exit 1
+    fi
+
     log_info "Removing old Rust build artifacts..."
     cargo clean
 
@@ -208,6 +214,10 @@ function build {
         sign_win "build/lib/x86_64-pc-windows-msvc/libwg.dll"
     fi
 
+    log_header "Building lyrebird$for_target_string"
+
+    ./lyrebird/build-lyrebird.sh "$ENV_TARGET"
+
     log_header "Building Rust code in $RUST_BUILD_MODE mode using $RUSTC_VERSION$for_target_string"
 
     local cargo_target_arg=()
@@ -275,6 +285,17 @@ function build {
             sign_win "$destination"
         fi
     done
+
+    # The pluggable transport client used for obfs4 bridges. It is already stripped.
+    local lyrebird_binary="lyrebird"
+    if [[ "$(uname -s)" == "MINGW"* ]]; then
+        lyrebird_binary="lyrebird.exe"
+    fi
+    log_info "Copying lyrebird => $destination_dir/$lyrebird_binary"
+    cp "build/bin/$ENV_TARGET/$lyrebird_binary" "$destination_dir/$lyrebird_binary"
+    if [[ "$SIGN" == "true" && "$(uname -s)" == "MINGW"* ]]; then
+        sign_win "$destination_dir/$lyrebird_binary"
+    fi
 }
 
 if [[ "$(uname -s)" == "MINGW"* ]]; then
@@ -15,7 +15,7 @@ import {
 import { Scheduler } from '../shared/scheduler';
 import AccountDataCache from './account-data-cache';
 import { DaemonRpc } from './daemon-rpc';
-import { InvalidAccountError } from './errors';
+import { InvalidAccountError, PubkeyInUseError } from './errors';
 import { IpcMainEventChannel } from './ipc-event-channel';
 import { NotificationSender } from './notification-controller';
 import { TunnelStateProvider } from './tunnel-state';
@@ -167,6 +167,10 @@ export default class Account {
 
       if (error instanceof InvalidAccountError) {
         throw Error(messages.gettext('Invalid account number'));
+      } else if (error instanceof PubkeyInUseError) {
+        throw Error(
+          messages.gettext('The key of this device is already in use. Please try again.'),
+        );
       } else {
         throw error;
       }
@@ -11,13 +11,18 @@ import {
   AccountToken,
   AfterDisconnect,
   AuthFailedError,
+  AutoConnectRule,
   BridgeSettings,
   BridgeState,
+  ConflictKind,
+  ConnectingPhase,
   ConnectionConfig,
   Constraint,
+  CorruptionReason,
   DaemonEvent,
   DeviceEvent,
   DeviceState,
+  DisconnectReason,
   EndpointObfuscationType,
   ErrorState,
   ErrorStateCause,
@@ -30,9 +35,11 @@ import {
   IDeviceRemoval,
   IDnsOptions,
   ILocation,
+  ILocationSearchResult,
   IObfuscationEndpoint,
   IOpenVpnConstraints,
   IProxyEndpoint,
+  IRecordedEvent,
   IRelayListCity,
   IRelayListCountry,
   IRelayListHostname,
@@ -54,6 +61,7 @@ import {
   RelayProtocol,
   RelaySettings,
   RelaySettingsUpdate,
+  TunnelHealth,
   TunnelParameterError,
   TunnelProtocol,
   TunnelState,
@@ -65,6 +73,7 @@ import {
   CommunicationError,
   InvalidAccountError,
   ListDevicesError,
+  PubkeyInUseError,
   TooManyDevicesError,
 } from './errors';
 import { ManagementServiceClient } from './management_interface/management_interface_grpc_pb';
@@ -74,7 +83,16 @@ const DAEMON_RPC_PATH =
   process.platform === 'win32' ? 'unix:////./pipe/Mullvad VPN' : 'unix:///var/run/mullvad-vpn';
 
 const NETWORK_CALL_TIMEOUT = 10000;
+const ERROR_KIND_METADATA_KEY = 'mullvad-error-kind';
 const CHANNEL_STATE_TIMEOUT = 1000 * 60 * 60;
+// Pings are sent this often so that a connection that died while the computer was asleep is
+// detected, rather than leaving subscriptions waiting for events that never arrive.
+const KEEPALIVE_INTERVAL = 30000;
+const KEEPALIVE_TIMEOUT = 10000;
+const INITIAL_RECONNECT_BACKOFF = 500;
+const MAX_RECONNECT_BACKOFF = 3000;
+// Raised from the default of 4 MiB so that large responses, such as the relay list, fit.
+const MAX_RECEIVE_MESSAGE_LENGTH = 32 * 1024 * 1024;
 
 const noConnectionError = new Error('No connection established to daemon');
 const configNotSupported = new Error('Setting custom settings is not supported');
@@ -141,6 +159,8 @@ export class DaemonRpc {
   private nextSubscriptionId = 0;
   private subscriptions: Map<number, grpc.ClientReadableStream<grpcTypes.DaemonEvent>> = new Map();
   private reconnectionTimeout?: NodeJS.Timer;
+  private appForeground = false;
+  private appForegroundStream?: grpc.ClientWritableStream<BoolValue>;
 
   constructor() {
     this.client = new ManagementServiceClient(
@@ -166,6 +186,9 @@ export class DaemonRpc {
           this.isConnectedValue = true;
           this.connectionObservers.forEach((observer) => observer.onOpen());
           this.setChannelCallback();
+          if (this.appForeground) {
+            this.writeAppForeground();
+          }
           resolve();
         }
       });
@@ -178,6 +201,7 @@ export class DaemonRpc {
     for (const subscriptionId of this.subscriptions.keys()) {
       this.removeSubscription(subscriptionId);
     }
+    this.closeAppForegroundStream();
 
     this.client.close();
     if (this.reconnectionTimeout) {
@@ -209,8 +233,8 @@ export class DaemonRpc {
     } catch (e) {
       const error = e as grpc.ServiceError;
       if (error.code) {
-        switch (error.code) {
-          case grpc.status.UNAUTHENTICATED:
+        switch (getErrorKind(error)) {
+          case grpcTypes.ErrorKind.INVALID_ACCOUNT:
             throw new InvalidAccountError();
           default:
             throw new CommunicationError();
@@ -249,13 +273,11 @@ export class DaemonRpc {
       };
     } catch (e) {
       const error = e as grpc.ServiceError;
-      if (error.code) {
-        switch (error.code) {
-          case grpc.status.NOT_FOUND:
-            return { type: 'invalid' };
-          case grpc.status.RESOURCE_EXHAUSTED:
-            return { type: 'already_used' };
-        }
+      switch (getErrorKind(error)) {
+        case grpcTypes.ErrorKind.INVALID_VOUCHER:
+          return { type: 'invalid' };
+        case grpcTypes.ErrorKind.USED_VOUCHER:
+          return { type: 'already_used' };
       }
       return { type: 'error' };
     }
@@ -270,21 +292,41 @@ export class DaemonRpc {
     }
   }
 
+  public async searchLocations(query: string, locale: string): Promise<ILocationSearchResult[]> {
+    const search = new grpcTypes.LocationSearch();
+    search.setQuery(query);
+    search.setLocale(locale);
+
+    const response = await this.call<grpcTypes.LocationSearch, grpcTypes.LocationSearchResults>(
+      this.client.searchLocations,
+      search,
+    );
+    return response.getResultsList().map((result) => ({
+      location: convertFromLocation(result.getLocation()!.toObject()),
+      name: result.getName(),
+    }));
+  }
+
   public async createNewAccount(): Promise<string> {
     const response = await this.callEmpty<StringValue>(this.client.createNewAccount);
     return response.getValue();
   }
 
   public async loginAccount(accountToken: AccountToken): Promise<void> {
+    const loginRequest = new grpcTypes.LoginRequest();
+    loginRequest.setAccountToken(accountToken);
+
     try {
-      await this.callString(this.client.loginAccount, accountToken);
+      await this.call<grpcTypes.LoginRequest, Empty>(this.client.loginAccount, loginRequest);
     } catch (e) {
       const error = e as grpc.ServiceError;
-      switch (error.code) {
-        case grpc.status.RESOURCE_EXHAUSTED:
+      switch (getErrorKind(error)) {
+        case grpcTypes.ErrorKind.MAX_DEVICES_REACHED:
           throw new TooManyDevicesError();
-        case grpc.status.UNAUTHENTICATED:
+        case grpcTypes.ErrorKind.INVALID_ACCOUNT:
           throw new InvalidAccountError();
+        case grpcTypes.ErrorKind.PUBKEY_IN_USE:
+          throw new PubkeyInUseError();
         default:
           throw new CommunicationError();
       }
@@ -411,6 +453,11 @@ export class DaemonRpc {
           grpcTypes.ObfuscationSettings.SelectedObfuscation.UDP2TCP,
         );
         break;
+      case ObfuscationType.portHopping:
+        grpcObfuscationSettings.setSelectedObfuscation(
+          grpcTypes.ObfuscationSettings.SelectedObfuscation.PORT_HOPPING,
+        );
+        break;
     }
 
     if (obfuscationSettings.udp2tcpSettings) {
@@ -423,6 +470,12 @@ export class DaemonRpc {
       grpcObfuscationSettings.setUdp2tcp(grpcUdp2tcpSettings);
     }
 
+    if (obfuscationSettings.portHoppingSettings) {
+      const grpcPortHoppingSettings = new grpcTypes.PortHoppingSettings();
+      grpcPortHoppingSettings.setInterval(obfuscationSettings.portHoppingSettings.interval ?? 0);
+      grpcObfuscationSettings.setPortHopping(grpcPortHoppingSettings);
+    }
+
     await this.call<grpcTypes.ObfuscationSettings, Empty>(
       this.client.setObfuscationSettings,
       grpcObfuscationSettings,
@@ -455,7 +508,17 @@ export class DaemonRpc {
 
   public async getLocation(): Promise<ILocation> {
     const response = await this.callEmpty<grpcTypes.GeoIpLocation>(this.client.getCurrentLocation);
-    return response.toObject();
+    return convertFromGeoIpLocation(response.toObject());
+  }
+
+  public async getRecentEvents(): Promise<IRecordedEvent[]> {
+    const response = await this.callEmpty<grpcTypes.RecentEvents>(this.client.getRecentEvents);
+    return response.getEventsList().map((recorded) => ({
+      time: ensureExists(recorded.getTime(), "no 'time' field for event").toDate().toISOString(),
+      event: convertFromDaemonEvent(
+        ensureExists(recorded.getEvent(), "no 'event' field for recorded event"),
+      ),
+    }));
   }
 
   public async getState(): Promise<TunnelState> {
@@ -509,6 +572,33 @@ export class DaemonRpc {
     await this.callEmpty(this.client.clearAccountHistory);
   }
 
+  public async setAppForeground(foreground: boolean): Promise<void> {
+    this.appForeground = foreground;
+    if (!this.isConnected) {
+      throw noConnectionError;
+    }
+    this.writeAppForeground();
+  }
+
+  public async getFrontendValues(namespace: string): Promise<Map<string, string>> {
+    const response = await this.callString<grpcTypes.FrontendValues>(
+      this.client.getFrontendValues,
+      namespace,
+    );
+    return new Map(response.getValuesMap().getEntryList());
+  }
+
+  public async setFrontendValue(namespace: string, key: string, value?: string): Promise<void> {
+    const frontendValue = new grpcTypes.FrontendValue();
+    frontendValue.setNamespace(namespace);
+    frontendValue.setKey(key);
+    if (value !== undefined) {
+      frontendValue.setValue(new StringValue().setValue(value));
+    }
+
+    await this.call<grpcTypes.FrontendValue, Empty>(this.client.setFrontendValue, frontendValue);
+  }
+
   public async getCurrentVersion(): Promise<string> {
     const response = await this.callEmpty<StringValue>(this.client.getCurrentVersion);
     return response.getValue();
@@ -650,9 +740,33 @@ export class DaemonRpc {
     }
   }
 
+  // The daemon considers the app to be in the background once the stream is closed, so the same
+  // stream is used for as long as the connection lasts.
+  private writeAppForeground() {
+    if (!this.appForegroundStream) {
+      const stream = this.client.setAppForeground((error) => {
+        if (this.appForegroundStream === stream) {
+          this.appForegroundStream = undefined;
+        }
+        if (error && error.code !== grpc.status.CANCELLED) {
+          log.error(`App foreground stream closed - ${error.message}`);
+        }
+      });
+      this.appForegroundStream = stream;
+    }
+    this.appForegroundStream.write(new BoolValue().setValue(this.appForeground));
+  }
+
+  private closeAppForegroundStream() {
+    const stream = this.appForegroundStream;
+    this.appForegroundStream = undefined;
+    stream?.cancel();
+  }
+
   private onClose(error?: Error) {
     const wasConnected = this.isConnectedValue;
     this.isConnectedValue = false;
+    this.closeAppForegroundStream();
 
     this.connectionObservers.forEach((observer) => observer.onClose(wasConnected, error));
   }
@@ -680,10 +794,12 @@ export class DaemonRpc {
   private channelOptions(): grpc.ClientOptions {
     /* eslint-disable @typescript-eslint/naming-convention */
     return {
-      'grpc.max_reconnect_backoff_ms': 3000,
-      'grpc.initial_reconnect_backoff_ms': 3000,
-      'grpc.keepalive_time_ms': Math.pow(2, 30),
-      'grpc.keepalive_timeout_ms': Math.pow(2, 30),
+      'grpc.max_reconnect_backoff_ms': MAX_RECONNECT_BACKOFF,
+      'grpc.initial_reconnect_backoff_ms': INITIAL_RECONNECT_BACKOFF,
+      'grpc.keepalive_time_ms': KEEPALIVE_INTERVAL,
+      'grpc.keepalive_timeout_ms': KEEPALIVE_TIMEOUT,
+      'grpc.keepalive_permit_without_calls': 1,
+      'grpc.max_receive_message_length': MAX_RECEIVE_MESSAGE_LENGTH,
     };
     /* eslint-enable @typescript-eslint/naming-convention */
   }
@@ -749,8 +865,18 @@ export class DaemonRpc {
           log.error(`Failed to reconnect - ${error}`);
         });
       }
-    }, 3000);
+    }, MAX_RECONNECT_BACKOFF);
+  }
+}
+
+function getErrorKind(error: grpc.ServiceError): grpcTypes.ErrorKind | undefined {
+  const value = error.metadata?.get(ERROR_KIND_METADATA_KEY)[0];
+  if (value === undefined) {
+    return undefined;
   }
+
+  const kind = Number(value.toString());
+  return Object.values(grpcTypes.ErrorKind).includes(kind) ? kind : undefined;
 }
 
 function liftConstraint<T>(constraint: Constraint<T> | undefined): T | undefined {
@@ -834,8 +960,27 @@ function convertFromTunnelState(tunnelState: grpcTypes.TunnelState): TunnelState
   switch (tunnelState.getStateCase()) {
     case grpcTypes.TunnelState.StateCase.STATE_NOT_SET:
       return undefined;
-    case grpcTypes.TunnelState.StateCase.DISCONNECTED:
-      return { state: 'disconnected' };
+    case grpcTypes.TunnelState.StateCase.DISCONNECTED: {
+      const reasonMap: Record<
+        grpcTypes.TunnelState.Disconnected.DisconnectReason,
+        DisconnectReason | undefined
+      > = {
+        [grpcTypes.TunnelState.Disconnected.DisconnectReason.NONE]: undefined,
+        [grpcTypes.TunnelState.Disconnected.DisconnectReason.USER_REQUESTED]: 'user-requested',
+        [grpcTypes.TunnelState.Disconnected.DisconnectReason.LOGGED_OUT]: 'logged-out',
+        [grpcTypes.TunnelState.Disconnected.DisconnectReason.AUTO_CONNECT_POLICY]:
+          'auto-connect-policy',
+        [grpcTypes.TunnelState.Disconnected.DisconnectReason.SHUTDOWN]: 'shutdown',
+        [grpcTypes.TunnelState.Disconnected.DisconnectReason.TUNNEL_ERROR]: 'tunnel-error',
+        [grpcTypes.TunnelState.Disconnected.DisconnectReason.AUTH_FAILED]: 'auth-failed',
+        [grpcTypes.TunnelState.Disconnected.DisconnectReason.DEVICE_REVOKED]: 'device-revoked',
+        [grpcTypes.TunnelState.Disconnected.DisconnectReason.OFFLINE]: 'offline',
+      };
+      return {
+        state: 'disconnected',
+        reason: tunnelStateObject.disconnected && reasonMap[tunnelStateObject.disconnected.reason],
+      };
+    }
     case grpcTypes.TunnelState.StateCase.DISCONNECTING: {
       const detailsMap: Record<grpcTypes.AfterDisconnect, AfterDisconnect> = {
         [grpcTypes.AfterDisconnect.NOTHING]: 'nothing',
@@ -856,14 +1001,27 @@ function convertFromTunnelState(tunnelState: grpcTypes.TunnelState): TunnelState
           details: convertFromTunnelStateError(tunnelStateObject.error.errorState),
         }
       );
-    case grpcTypes.TunnelState.StateCase.CONNECTING:
+    case grpcTypes.TunnelState.StateCase.CONNECTING: {
+      const phaseMap: Record<grpcTypes.TunnelState.Connecting.Phase, ConnectingPhase> = {
+        [grpcTypes.TunnelState.Connecting.Phase.OPENING_DEVICE]: 'opening-device',
+        [grpcTypes.TunnelState.Connecting.Phase.HANDSHAKING]: 'handshaking',
+        [grpcTypes.TunnelState.Connecting.Phase.VERIFYING_CONNECTIVITY]: 'verifying-connectivity',
+      };
       return {
         state: 'connecting',
         details:
           tunnelStateObject.connecting?.relayInfo &&
           convertFromTunnelStateRelayInfo(tunnelStateObject.connecting.relayInfo),
+        phase: tunnelStateObject.connecting && phaseMap[tunnelStateObject.connecting.phase],
       };
+    }
     case grpcTypes.TunnelState.StateCase.CONNECTED: {
+      const healthMap: Record<grpcTypes.TunnelState.Connected.Health, TunnelHealth> = {
+        [grpcTypes.TunnelState.Connected.Health.HEALTHY]: 'healthy',
+        [grpcTypes.TunnelState.Connected.Health.UNSTABLE]: 'unstable',
+        [grpcTypes.TunnelState.Connected.Health.HIGH_LATENCY]: 'high-latency',
+        [grpcTypes.TunnelState.Connected.Health.PACKET_LOSS]: 'packet-loss',
+      };
       const relayInfo =
         tunnelStateObject.connected?.relayInfo &&
         convertFromTunnelStateRelayInfo(tunnelStateObject.connected.relayInfo);
@@ -871,6 +1029,7 @@ function convertFromTunnelState(tunnelState: grpcTypes.TunnelState): TunnelState
         relayInfo && {
           state: 'connected',
           details: relayInfo,
+          health: tunnelStateObject.connected && healthMap[tunnelStateObject.connected.health],
         }
       );
     }
@@ -972,6 +1131,8 @@ function convertFromParameterError(
       return TunnelParameterError.noWireguardKey;
     case grpcTypes.ErrorState.GenerationError.CUSTOM_TUNNEL_HOST_RESOLUTION_ERROR:
       return TunnelParameterError.customTunnelHostResolutionError;
+    case grpcTypes.ErrorState.GenerationError.MISSING_RELAY_CAPABILITIES:
+      return TunnelParameterError.missingRelayCapabilities;
   }
 }
 
@@ -981,6 +1142,7 @@ function convertFromTunnelStateRelayInfo(
   if (state.tunnelEndpoint) {
     return {
       ...state,
+      location: state.location && convertFromGeoIpLocation(state.location),
       endpoint: {
         ...state.tunnelEndpoint,
         tunnelType: convertFromTunnelType(state.tunnelEndpoint.tunnelType),
@@ -1048,6 +1210,10 @@ function convertFromSettings(settings: grpcTypes.Settings): ISettings | undefine
   const bridgeSettings = convertFromBridgeSettings(settingsObject.bridgeSettings!);
   const tunnelOptions = convertFromTunnelOptions(settingsObject.tunnelOptions!);
   const splitTunnel = settingsObject.splitTunnel ?? { enableExclusions: false, appsList: [] };
+  const splitTunnelMode =
+    settings.getSplitTunnelMode()?.getMode() === grpcTypes.SplitTunnelMode.Mode.INCLUDE
+      ? 'include'
+      : 'exclude';
   const obfuscationSettings = convertFromObfuscationSettings(settingsObject.obfuscationSettings);
   return {
     ...settings.toObject(),
@@ -1056,6 +1222,7 @@ function convertFromSettings(settings: grpcTypes.Settings): ISettings | undefine
     bridgeSettings,
     tunnelOptions,
     splitTunnel,
+    splitTunnelMode,
     obfuscationSettings,
   };
 }
@@ -1223,6 +1390,15 @@ function convertFromLocation(location: grpcTypes.RelayLocation.AsObject): RelayL
   return { country: location.country };
 }
 
+function convertFromGeoIpLocation(location: grpcTypes.GeoIpLocation.AsObject): ILocation {
+  return {
+    ...location,
+    provider: location.provider || undefined,
+    nearbyFallbackFrom: location.nearbyFallbackFrom || undefined,
+    ownership: convertFromOwnership(location.ownership),
+  };
+}
+
 function convertFromTunnelOptions(tunnelOptions: grpcTypes.TunnelOptions.AsObject): ITunnelOptions {
   return {
     openvpn: {
@@ -1264,6 +1440,9 @@ function convertFromObfuscationSettings(
     case grpcTypes.ObfuscationSettings.SelectedObfuscation.UDP2TCP:
       selectedObfuscationType = ObfuscationType.udp2tcp;
       break;
+    case grpcTypes.ObfuscationSettings.SelectedObfuscation.PORT_HOPPING:
+      selectedObfuscationType = ObfuscationType.portHopping;
+      break;
   }
 
   return {
@@ -1272,6 +1451,9 @@ function convertFromObfuscationSettings(
       obfuscationSettings?.udp2tcp && obfuscationSettings.udp2tcp.port !== 0
         ? { port: { only: obfuscationSettings.udp2tcp.port } }
         : { port: 'any' },
+    portHoppingSettings: {
+      interval: obfuscationSettings?.portHopping?.interval || undefined,
+    },
   };
 }
 
@@ -1306,6 +1488,37 @@ function convertFromDaemonEvent(data: grpcTypes.DaemonEvent): DaemonEvent {
     return { appVersionInfo: versionInfo.toObject() };
   }
 
+  const autoConnectDecision = data.getAutoConnectDecision();
+  if (autoConnectDecision !== undefined) {
+    return {
+      autoConnectDecision: {
+        connect: autoConnectDecision.getConnect(),
+        rule: convertFromAutoConnectRule(autoConnectDecision.getRule()),
+      },
+    };
+  }
+
+  const conflictingSoftware = data.getConflictingSoftware();
+  if (conflictingSoftware !== undefined) {
+    return {
+      conflictingSoftware: conflictingSoftware.getConflictsList().map((conflict) => ({
+        kind: convertFromConflictKind(conflict.getKind()),
+        name: conflict.getName(),
+      })),
+    };
+  }
+
+  const quarantinedFiles = data.getQuarantinedFiles();
+  if (quarantinedFiles !== undefined) {
+    return {
+      quarantinedFiles: quarantinedFiles.getFilesList().map((file) => ({
+        path: file.getPath(),
+        quarantinePath: file.getQuarantinePath() || undefined,
+        reason: convertFromCorruptionReason(file.getReason()),
+      })),
+    };
+  }
+
   // Handle unknown daemon events
   const keys = Object.entries(data.toObject())
     .filter(([, value]) => value !== undefined)
@@ -1313,6 +1526,39 @@ function convertFromDaemonEvent(data: grpcTypes.DaemonEvent): DaemonEvent {
   throw new Error(`Unknown daemon event received containing ${keys}`);
 }
 
+function convertFromAutoConnectRule(rule: grpcTypes.AutoConnectPolicy.Rule): AutoConnectRule {
+  switch (rule) {
+    case grpcTypes.AutoConnectPolicy.Rule.ALWAYS:
+      return 'always';
+    case grpcTypes.AutoConnectPolicy.Rule.NEVER:
+      return 'never';
+    case grpcTypes.AutoConnectPolicy.Rule.UNTRUSTED_WIFI_ONLY:
+      return 'untrusted-wifi-only';
+    case grpcTypes.AutoConnectPolicy.Rule.WIRED_EXEMPT:
+      return 'wired-exempt';
+  }
+}
+
+function convertFromConflictKind(kind: grpcTypes.ConflictingSoftware.Kind): ConflictKind {
+  switch (kind) {
+    case grpcTypes.ConflictingSoftware.Kind.TUNNEL_INTERFACE:
+      return 'tunnel-interface';
+    case grpcTypes.ConflictingSoftware.Kind.WFP_PROVIDER:
+      return 'wfp-provider';
+  }
+}
+
+function convertFromCorruptionReason(
+  reason: grpcTypes.QuarantinedFile.Reason,
+): CorruptionReason {
+  switch (reason) {
+    case grpcTypes.QuarantinedFile.Reason.CHECKSUM_MISMATCH:
+      return 'checksum-mismatch';
+    case grpcTypes.QuarantinedFile.Reason.PARSE_ERROR:
+      return 'parse-error';
+  }
+}
+
 function convertFromOwnership(ownership: grpcTypes.Ownership): Ownership {
   switch (ownership) {
     case grpcTypes.Ownership.ANY:
@@ -1540,6 +1786,8 @@ function convertFromDeviceEvent(deviceEvent: grpcTypes.DeviceEvent): DeviceEvent
       return { type: 'updated', deviceState: deviceState as LoggedInDeviceState };
     case grpcTypes.DeviceEvent.Cause.ROTATED_KEY:
       return { type: 'rotated_key', deviceState: deviceState as LoggedInDeviceState };
+    case grpcTypes.DeviceEvent.Cause.REPAIRED_KEY:
+      return { type: 'repaired_key', deviceState: deviceState as LoggedInDeviceState };
   }
 }
 
@@ -10,6 +10,7 @@ export function getDefaultSettings(): ISettings {
       enableExclusions: false,
       appsList: [],
     },
+    splitTunnelMode: 'exclude',
     relaySettings: {
       normal: {
         location: 'any',
@@ -65,6 +66,9 @@ export function getDefaultSettings(): ISettings {
       udp2tcpSettings: {
         port: 'any',
       },
+      portHoppingSettings: {
+        interval: 300,
+      },
     },
   };
 }
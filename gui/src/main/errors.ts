@@ -16,6 +16,12 @@ export class TooManyDevicesError extends Error {
   }
 }
 
+export class PubkeyInUseError extends Error {
+  constructor() {
+    super('The WireGuard key is already in use');
+  }
+}
+
 export class ListDevicesError extends Error {
   constructor() {
     super('Failed to fetch list of devices');
@@ -621,6 +621,19 @@ class ApplicationMain
           this.account.handleDeviceEvent(daemonEvent.device);
         } else if ('deviceRemoval' in daemonEvent) {
           IpcMainEventChannel.account.notifyDevices?.(daemonEvent.deviceRemoval);
+        } else if ('autoConnectDecision' in daemonEvent) {
+          const { connect, rule } = daemonEvent.autoConnectDecision;
+          log.info(`Auto-connect policy evaluated: connect=${connect}, rule=${rule}`);
+        } else if ('conflictingSoftware' in daemonEvent) {
+          const conflicts = daemonEvent.conflictingSoftware
+            .map(({ kind, name }) => `${name} (${kind})`)
+            .join(', ');
+          log.warn(`Detected potentially conflicting software: ${conflicts}`);
+        } else if ('quarantinedFiles' in daemonEvent) {
+          const files = daemonEvent.quarantinedFiles
+            .map(({ path, reason }) => `${path} (${reason})`)
+            .join(', ');
+          log.warn(`The daemon quarantined corrupt cache files: ${files}`);
         }
       },
       (error: Error) => {
@@ -691,6 +704,11 @@ class ApplicationMain
     }));
 
     IpcMainEventChannel.location.handleGet(() => this.daemonRpc.getLocation());
+    IpcMainEventChannel.location.handleSearch(({ query, locale }) =>
+      this.daemonRpc.searchLocations(query, locale),
+    );
+
+    IpcMainEventChannel.daemon.handleGetRecentEvents(() => this.daemonRpc.getRecentEvents());
 
     IpcMainEventChannel.tunnel.handleConnect(this.connectTunnel);
     IpcMainEventChannel.tunnel.handleReconnect(this.reconnectTunnel);
@@ -3,6 +3,7 @@ import { randomUUID } from 'crypto';
 import { app, shell } from 'electron';
 import * as path from 'path';
 
+import { ICollectProblemReportOptions } from '../shared/ipc-types';
 import log from '../shared/logging';
 import { IpcMainEventChannel } from './ipc-event-channel';
 import { resolveBin } from './proc';
@@ -19,7 +20,7 @@ export function registerIpcListeners() {
   );
 }
 
-function collectLogs(toRedact?: string): Promise<string> {
+function collectLogs({ toRedact, accountInfo }: ICollectProblemReportOptions): Promise<string> {
   const id = randomUUID();
   const reportPath = getProblemReportPath(id);
   const executable = resolveBin('mullvad-problem-report');
@@ -27,9 +28,15 @@ function collectLogs(toRedact?: string): Promise<string> {
   if (toRedact) {
     args.push('--redact', toRedact);
   }
+  if (accountInfo) {
+    args.push('--include-account');
+    if (accountInfo.deviceName) {
+      args.push('--device-name', accountInfo.deviceName);
+    }
+  }
 
   return new Promise((resolve, reject) => {
-    execFile(executable, args, { windowsHide: true }, (error, stdout, stderr) => {
+    const child = execFile(executable, args, { windowsHide: true }, (error, stdout, stderr) => {
       if (error) {
         log.error(
           `Failed to collect a problem report.
@@ -42,6 +49,9 @@ function collectLogs(toRedact?: string): Promise<string> {
         resolve(id);
       }
     });
+
+    // The account number is passed on stdin to keep it out of the process arguments.
+    child.stdin?.end(accountInfo ? `${accountInfo.accountToken}\n` : undefined);
   });
 }
 
@@ -320,9 +320,13 @@ export default class UserInterface implements WindowControllerDelegate {
       this.delegate.resetTunnelStateAnnouncements();
     });
 
+    this.windowController.window?.on('show', () => this.setAppForeground(true));
+
     // Use hide instead of blur to prevent the navigation reset from happening when bluring an
     // unpinned window.
     this.windowController.window?.on('hide', () => {
+      this.setAppForeground(false);
+
       if (process.env.NODE_ENV !== 'development' || !this.navigationResetDisabled) {
         this.blurNavigationResetScheduler.schedule(() => {
           this.windowController.webContents?.setBackgroundThrottling(false);
@@ -336,6 +340,15 @@ export default class UserInterface implements WindowControllerDelegate {
     });
   }
 
+  // The daemon polls the account more often while the window is visible.
+  private setAppForeground(foreground: boolean) {
+    this.daemonRpc
+      .setAppForeground(foreground)
+      .catch((error) =>
+        log.error(`Unable to notify daemon of window visibility: ${error.message}`),
+      );
+  }
+
   private setTrayContextMenu(isLoggedIn: boolean, tunnelState: TunnelState) {
     if (process.platform === 'linux') {
       this.tray.setContextMenu(
@@ -15,6 +15,7 @@ import {
   IDeviceRemoval,
   IDnsOptions,
   ILocation,
+  ILocationSearchResult,
   IRelayListWithEndpointData,
   ISettings,
   liftConstraint,
@@ -25,7 +26,12 @@ import {
 } from '../shared/daemon-rpc-types';
 import { messages, relayLocations } from '../shared/gettext';
 import { IGuiSettingsState, SYSTEM_PREFERRED_LOCALE_KEY } from '../shared/gui-settings-state';
-import { IChangelog, ICurrentAppVersionInfo, IHistoryObject } from '../shared/ipc-types';
+import {
+  IChangelog,
+  ICollectProblemReportOptions,
+  ICurrentAppVersionInfo,
+  IHistoryObject,
+} from '../shared/ipc-types';
 import log, { ConsoleOutput } from '../shared/logging';
 import { LogLevel } from '../shared/logging-types';
 import { Scheduler } from '../shared/scheduler';
@@ -288,6 +294,7 @@ export default class AppRenderer {
   public getDeviceState = () => IpcRendererEventChannel.account.getDeviceState();
   public removeDevice = (device: IDeviceRemoval) =>
     IpcRendererEventChannel.account.removeDevice(device);
+  public getRecentEvents = () => IpcRendererEventChannel.daemon.getRecentEvents();
   public connectTunnel = () => IpcRendererEventChannel.tunnel.connect();
   public disconnectTunnel = () => IpcRendererEventChannel.tunnel.disconnect();
   public reconnectTunnel = () => IpcRendererEventChannel.tunnel.reconnect();
@@ -320,8 +327,8 @@ export default class AppRenderer {
     IpcRendererEventChannel.windowsSplitTunneling.forgetManuallyAddedApplication(application);
   public setObfuscationSettings = (obfuscationSettings: ObfuscationSettings) =>
     IpcRendererEventChannel.settings.setObfuscationSettings(obfuscationSettings);
-  public collectProblemReport = (toRedact: string | undefined) =>
-    IpcRendererEventChannel.problemReport.collectLogs(toRedact);
+  public collectProblemReport = (options: ICollectProblemReportOptions) =>
+    IpcRendererEventChannel.problemReport.collectLogs(options);
   public viewLog = (path: string) => IpcRendererEventChannel.problemReport.viewLog(path);
   public quit = () => IpcRendererEventChannel.app.quit();
   public openUrl = (url: string) => IpcRendererEventChannel.app.openUrl(url);
@@ -403,6 +410,13 @@ export default class AppRenderer {
     return devices;
   };
 
+  public searchLocations = (
+    query: string,
+    locale: string,
+  ): Promise<Array<ILocationSearchResult>> => {
+    return IpcRendererEventChannel.location.search({ query, locale });
+  };
+
   public openLinkWithAuth = async (link: string): Promise<void> => {
     let token = '';
     try {
@@ -701,7 +715,7 @@ export default class AppRenderer {
           break;
 
         case 'connected':
-          actions.connection.connected(tunnelState.details);
+          actions.connection.connected(tunnelState.details, tunnelState.health);
           break;
 
         case 'disconnecting':
@@ -736,6 +750,7 @@ export default class AppRenderer {
     reduxSettings.updateBridgeState(newSettings.bridgeState);
     reduxSettings.updateDnsOptions(newSettings.tunnelOptions.dns);
     reduxSettings.updateSplitTunnelingState(newSettings.splitTunnel.enableExclusions);
+    reduxSettings.updateSplitTunnelMode(newSettings.splitTunnelMode);
     reduxSettings.updateObfuscationSettings(newSettings.obfuscationSettings);
 
     this.setRelaySettings(newSettings.relaySettings);
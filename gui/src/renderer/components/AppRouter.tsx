@@ -7,6 +7,7 @@ import { useAppContext } from '../context';
 import { ITransitionSpecification, transitions, useHistory } from '../lib/history';
 import { RoutePath } from '../lib/routes';
 import Account from './Account';
+import ConnectionLog from './ConnectionLog';
 import Debug from './Debug';
 import { DeviceRevokedView } from './DeviceRevokedView';
 import {
@@ -80,6 +81,7 @@ export default function AppRouter() {
             <Route exact path={RoutePath.splitTunneling} component={SplitTunnelingSettings} />
             <Route exact path={RoutePath.support} component={Support} />
             <Route exact path={RoutePath.problemReport} component={ProblemReport} />
+            <Route exact path={RoutePath.connectionLog} component={ConnectionLog} />
             <Route exact path={RoutePath.debug} component={Debug} />
             <Route exact path={RoutePath.selectLocation} component={SelectLocation} />
             <Route exact path={RoutePath.filter} component={Filter} />
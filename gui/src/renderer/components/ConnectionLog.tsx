@@ -0,0 +1,167 @@
+import { useEffect, useState } from 'react';
+import { sprintf } from 'sprintf-js';
+import styled from 'styled-components';
+
+import { colors } from '../../config.json';
+import { DaemonEvent, IRecordedEvent, TunnelState } from '../../shared/daemon-rpc-types';
+import { messages } from '../../shared/gettext';
+import log from '../../shared/logging';
+import { useAppContext } from '../context';
+import { useHistory } from '../lib/history';
+import { useSelector } from '../redux/store';
+import { measurements, smallText, tinyText } from './common-styles';
+import { BackAction } from './KeyboardNavigation';
+import { Layout, SettingsContainer } from './Layout';
+import {
+  NavigationBar,
+  NavigationContainer,
+  NavigationItems,
+  NavigationScrollbars,
+  TitleBarItem,
+} from './NavigationBar';
+import SettingsHeader, { HeaderSubTitle, HeaderTitle } from './SettingsHeader';
+
+const StyledContent = styled.div({
+  display: 'flex',
+  flexDirection: 'column',
+  flex: 1,
+  margin: `0 ${measurements.viewMargin} ${measurements.viewMargin}`,
+});
+
+const StyledEntry = styled.div({
+  display: 'flex',
+  flexDirection: 'column',
+  marginBottom: '8px',
+});
+
+const StyledTime = styled.span(tinyText, {
+  color: colors.white60,
+});
+
+const StyledDescription = styled.span(smallText, {
+  color: colors.white,
+});
+
+export default function ConnectionLog() {
+  const { pop } = useHistory();
+  const { getRecentEvents } = useAppContext();
+  const locale = useSelector((state) => state.userInterface.locale);
+  const [events, setEvents] = useState<IRecordedEvent[]>();
+
+  useEffect(() => {
+    getRecentEvents()
+      .then(setEvents)
+      .catch((e) => {
+        const error = e as Error;
+        log.error(`Failed to fetch recent events: ${error.message}`);
+        setEvents([]);
+      });
+  }, []);
+
+  // Newest first, and only the events that are meaningful to the user
+  const entries = (events ?? [])
+    .map((recorded) => ({ time: recorded.time, description: describeEvent(recorded.event) }))
+    .filter((entry): entry is { time: string; description: string } => !!entry.description)
+    .reverse();
+
+  return (
+    <BackAction action={pop}>
+      <Layout>
+        <SettingsContainer>
+          <NavigationContainer>
+            <NavigationBar>
+              <NavigationItems>
+                <TitleBarItem>
+                  {
+                    // TRANSLATORS: Title label in navigation bar
+                    messages.pgettext('connection-log-view', 'Connection log')
+                  }
+                </TitleBarItem>
+              </NavigationItems>
+            </NavigationBar>
+
+            <NavigationScrollbars>
+              <SettingsHeader>
+                <HeaderTitle>
+                  {messages.pgettext('connection-log-view', 'Connection log')}
+                </HeaderTitle>
+                <HeaderSubTitle>
+                  {messages.pgettext(
+                    'connection-log-view',
+                    'The most recent connection events, newest first.',
+                  )}
+                </HeaderSubTitle>
+              </SettingsHeader>
+
+              <StyledContent>
+                {events !== undefined && entries.length === 0 && (
+                  <StyledDescription>
+                    {messages.pgettext('connection-log-view', 'No events yet')}
+                  </StyledDescription>
+                )}
+                {entries.map((entry, index) => (
+                  <StyledEntry key={index}>
+                    <StyledTime>{new Date(entry.time).toLocaleString(locale)}</StyledTime>
+                    <StyledDescription>{entry.description}</StyledDescription>
+                  </StyledEntry>
+                ))}
+              </StyledContent>
+            </NavigationScrollbars>
+          </NavigationContainer>
+        </SettingsContainer>
+      </Layout>
+    </BackAction>
+  );
+}
+
+function describeEvent(event: DaemonEvent): string | undefined {
+  if ('tunnelState' in event) {
+    return describeTunnelState(event.tunnelState);
+  } else if ('autoConnectDecision' in event) {
+    return event.autoConnectDecision.connect
+      ? messages.pgettext('connection-log-view', 'Auto-connect decided to connect')
+      : messages.pgettext('connection-log-view', 'Auto-connect decided not to connect');
+  } else if ('conflictingSoftware' in event) {
+    return sprintf(
+      // TRANSLATORS: Available placeholders:
+      // TRANSLATORS: %(names)s - comma separated list of programs that interfere with the app
+      messages.pgettext('connection-log-view', 'Conflicting software detected: %(names)s'),
+      { names: event.conflictingSoftware.map((conflict) => conflict.name).join(', ') },
+    );
+  } else if ('quarantinedFiles' in event) {
+    return sprintf(
+      // TRANSLATORS: Available placeholders:
+      // TRANSLATORS: %(paths)s - comma separated list of files that were replaced
+      messages.pgettext('connection-log-view', 'Corrupt cache files replaced: %(paths)s'),
+      { paths: event.quarantinedFiles.map((file) => file.path).join(', ') },
+    );
+  } else {
+    return undefined;
+  }
+}
+
+function describeTunnelState(tunnelState: TunnelState): string {
+  switch (tunnelState.state) {
+    case 'connecting':
+      return messages.pgettext('connection-log-view', 'Connecting');
+    case 'connected': {
+      const hostname = tunnelState.details.location?.hostname;
+      return hostname
+        ? sprintf(
+            // TRANSLATORS: Available placeholders:
+            // TRANSLATORS: %(hostname)s - the hostname of the relay
+            messages.pgettext('connection-log-view', 'Connected to %(hostname)s'),
+            { hostname },
+          )
+        : messages.pgettext('connection-log-view', 'Connected');
+    }
+    case 'disconnecting':
+      return messages.pgettext('connection-log-view', 'Disconnecting');
+    case 'disconnected':
+      return messages.pgettext('connection-log-view', 'Disconnected');
+    case 'error':
+      return tunnelState.details.blockingError
+        ? messages.pgettext('connection-log-view', 'Failed to block traffic after an error')
+        : messages.pgettext('connection-log-view', 'Blocking traffic after an error');
+  }
+}
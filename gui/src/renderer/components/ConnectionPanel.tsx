@@ -5,6 +5,8 @@ import styled from 'styled-components';
 import { colors } from '../../config.json';
 import {
   EndpointObfuscationType,
+  ITunnelFeatures,
+  Ownership,
   ProxyType,
   proxyTypeToString,
   RelayProtocol,
@@ -44,6 +46,10 @@ interface IProps {
   hostname?: string;
   bridgeHostname?: string;
   entryHostname?: string;
+  provider?: string;
+  ownership?: Ownership;
+  features?: ITunnelFeatures;
+  nearbyFallback?: boolean;
   inAddress?: IInAddress;
   entryLocationInAddress?: IInAddress;
   bridgeInfo?: IBridgeData;
@@ -89,6 +95,7 @@ export default class ConnectionPanel extends React.Component<IProps> {
   public render() {
     const { outAddress } = this.props;
     const entryPoint = this.getEntryPoint();
+    const features = this.featuresLine();
 
     return (
       <Container className={this.props.className}>
@@ -126,6 +133,35 @@ export default class ConnectionPanel extends React.Component<IProps> {
                 </IpAddresses>
               </Row>
             )}
+
+            {this.props.provider && (
+              <Row>
+                <Caption>{messages.pgettext('connection-info', 'Provider')}</Caption>
+                <Text>{this.providerLine(this.props.provider)}</Text>
+              </Row>
+            )}
+
+            {features && (
+              <Row>
+                <Caption>{messages.pgettext('connection-info', 'Features')}</Caption>
+                <Text>{features}</Text>
+              </Row>
+            )}
+
+            {this.props.nearbyFallback && (
+              <Row>
+                <Text>
+                  {
+                    // TRANSLATORS: Shown when the relay is in a nearby location, because no relay
+                    // TRANSLATORS: in the selected location was available.
+                    messages.pgettext(
+                      'connection-info',
+                      'No relay available in the selected location. Using a nearby one.',
+                    )
+                  }
+                </Text>
+              </Row>
+            )}
           </React.Fragment>
         )}
       </Container>
@@ -174,6 +210,43 @@ export default class ConnectionPanel extends React.Component<IProps> {
     }
   }
 
+  private providerLine(provider: string) {
+    switch (this.props.ownership) {
+      case Ownership.mullvadOwned:
+        // TRANSLATORS: The provider of the exit relay, for relays owned by Mullvad
+        // TRANSLATORS: Available placeholders:
+        // TRANSLATORS: %(provider)s - the hosting provider of the relay
+        return sprintf(messages.pgettext('connection-info', '%(provider)s (Mullvad-owned)'), {
+          provider,
+        });
+      case Ownership.rented:
+        // TRANSLATORS: The provider of the exit relay, for relays that are rented
+        // TRANSLATORS: Available placeholders:
+        // TRANSLATORS: %(provider)s - the hosting provider of the relay
+        return sprintf(messages.pgettext('connection-info', '%(provider)s (rented)'), {
+          provider,
+        });
+      default:
+        return provider;
+    }
+  }
+
+  // Lists the optional features that are in use on the tunnel.
+  private featuresLine(): string | undefined {
+    const { features } = this.props;
+    if (!features) {
+      return undefined;
+    }
+
+    const names = [
+      features.quantumResistant && messages.pgettext('connection-info', 'Quantum resistance'),
+      features.daita && 'DAITA',
+      features.udp2tcp && 'UDP-over-TCP',
+    ].filter((name): name is string => typeof name === 'string');
+
+    return names.length > 0 ? names.join(', ') : undefined;
+  }
+
   private transportLine() {
     const { inAddress, bridgeInfo } = this.props;
 
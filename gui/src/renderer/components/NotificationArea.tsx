@@ -11,6 +11,7 @@ import {
   InconsistentVersionNotificationProvider,
   NotificationAction,
   ReconnectingNotificationProvider,
+  SplitTunnelIncludeModeNotificationProvider,
   UnsupportedVersionNotificationProvider,
   UpdateAvailableNotificationProvider,
 } from '../../shared/notifications/notification';
@@ -42,6 +43,7 @@ export default function NotificationArea(props: IProps) {
     (state: IReduxState) =>
       state.settings.splitTunneling && state.settings.splitTunnelingApplications.length > 0,
   );
+  const splitTunnelMode = useSelector((state: IReduxState) => state.settings.splitTunnelMode);
 
   const notificationProviders: InAppNotificationProvider[] = [
     new ConnectingNotificationProvider({ tunnelState }),
@@ -52,6 +54,7 @@ export default function NotificationArea(props: IProps) {
       hasExcludedApps,
     }),
     new ErrorNotificationProvider({ tunnelState, hasExcludedApps }),
+    new SplitTunnelIncludeModeNotificationProvider({ splitTunnelMode }),
     new InconsistentVersionNotificationProvider({ consistent: version.consistent }),
     new UnsupportedVersionNotificationProvider(version),
   ];
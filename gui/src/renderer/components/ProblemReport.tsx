@@ -21,6 +21,7 @@ import { useSelector } from '../redux/store';
 import support from '../redux/support/actions';
 import * as AppButton from './AppButton';
 import { AriaDescribed, AriaDescription, AriaDescriptionGroup } from './AriaGroup';
+import * as Cell from './cell';
 import ImageView from './ImageView';
 import { BackAction } from './KeyboardNavigation';
 import { Footer, Layout, SettingsContainer } from './Layout';
@@ -32,6 +33,7 @@ import {
   StyledEmail,
   StyledEmailInput,
   StyledForm,
+  StyledFormAccountRow,
   StyledFormEmailRow,
   StyledFormMessageRow,
   StyledMessageInput,
@@ -126,8 +128,9 @@ function Content() {
 
 function Form() {
   const { viewLog } = useAppContext();
-  const { email, setEmail, message, setMessage, onSend } = useProblemReportContext();
-  const { collectLog } = useCollectLog();
+  const { email, setEmail, message, setMessage, includeAccount, setIncludeAccount, onSend } =
+    useProblemReportContext();
+  const { collectLog } = useCollectLog(includeAccount);
 
   const [disableActions, setDisableActions] = useState(false);
 
@@ -142,7 +145,7 @@ function Form() {
     } finally {
       setDisableActions(false);
     }
-  }, []);
+  }, [collectLog]);
 
   const onChangeEmail = useCallback((event: ChangeEvent<HTMLInputElement>) => {
     setEmail(event.target.value);
@@ -174,6 +177,12 @@ function Form() {
             onChange={onChangeDescription}
           />
         </StyledFormMessageRow>
+        <StyledFormAccountRow>
+          <Cell.Label>
+            {messages.pgettext('support-view', 'Include account and device name')}
+          </Cell.Label>
+          <Cell.Switch isOn={includeAccount} onChange={setIncludeAccount} />
+        </StyledFormAccountRow>
       </StyledForm>
       <Footer>
         <AriaDescriptionGroup>
@@ -374,17 +383,27 @@ function OutdatedVersionWarningDialog() {
   );
 }
 
-const useCollectLog = () => {
+const useCollectLog = (includeAccount: boolean) => {
   const { collectProblemReport } = useAppContext();
   const accountHistory = useSelector((state) => state.account.accountHistory);
+  const accountToken = useSelector((state) => state.account.accountToken);
+  const deviceName = useSelector((state) => state.account.deviceName);
 
-  const collectLogPromise = useRef<Promise<string>>();
+  // Reports with and without account info are collected separately
+  const anonymousLogPromise = useRef<Promise<string>>();
+  const accountLogPromise = useRef<Promise<string>>();
 
   const collectLog = useCallback(async (): Promise<string> => {
+    const collectLogPromise = includeAccount ? accountLogPromise : anonymousLogPromise;
     if (collectLogPromise.current) {
       return collectLogPromise.current;
     } else {
-      const collectPromise = collectProblemReport(accountHistory);
+      const accountInfo = includeAccount && accountToken ? { accountToken, deviceName } : undefined;
+      // The account history is empty if the account token is only kept in memory.
+      const collectPromise = collectProblemReport({
+        toRedact: accountHistory ?? accountToken,
+        accountInfo,
+      });
       // save promise to prevent subsequent requests
       collectLogPromise.current = collectPromise;
 
@@ -396,7 +415,7 @@ const useCollectLog = () => {
         throw error;
       }
     }
-  }, [collectLogPromise]);
+  }, [includeAccount, accountToken, deviceName]);
 
   return { collectLog };
 };
@@ -408,6 +427,8 @@ type ProblemReportContextType = {
   setEmail: Dispatch<SetStateAction<string>>;
   message: string;
   setMessage: Dispatch<SetStateAction<string>>;
+  includeAccount: boolean;
+  setIncludeAccount: Dispatch<SetStateAction<boolean>>;
   onSend: () => Promise<void>;
 };
 
@@ -419,11 +440,12 @@ const ProblemReportContextProvider = ({ children }: { children: ReactNode }) =>
 
   const { email: defaultEmail, message: defaultMessage } = useSelector((state) => state.support);
 
-  const { collectLog } = useCollectLog();
-
   const [sendState, setSendState] = useState(SendState.initial);
   const [email, setEmail] = useState(defaultEmail);
   const [message, setMessage] = useState(defaultMessage);
+  const [includeAccount, setIncludeAccount] = useState(false);
+
+  const { collectLog } = useCollectLog(includeAccount);
 
   const sendReport = useCallback(async () => {
     try {
@@ -434,7 +456,7 @@ const ProblemReportContextProvider = ({ children }: { children: ReactNode }) =>
     } catch (error) {
       setSendState(SendState.failed);
     }
-  }, [email, message]);
+  }, [email, message, collectLog]);
 
   const onSend = useCallback(async () => {
     if (sendState === SendState.initial && email.length === 0) {
@@ -461,8 +483,28 @@ const ProblemReportContextProvider = ({ children }: { children: ReactNode }) =>
   }, [email, message]);
 
   const value: ProblemReportContextType = useMemo(
-    () => ({ sendState, setSendState, email, setEmail, message, setMessage, onSend }),
-    [sendState, setSendState, email, setEmail, message, setMessage, onSend],
+    () => ({
+      sendState,
+      setSendState,
+      email,
+      setEmail,
+      message,
+      setMessage,
+      includeAccount,
+      setIncludeAccount,
+      onSend,
+    }),
+    [
+      sendState,
+      setSendState,
+      email,
+      setEmail,
+      message,
+      setMessage,
+      includeAccount,
+      setIncludeAccount,
+      onSend,
+    ],
   );
   return <ProblemReportContext.Provider value={value}>{children}</ProblemReportContext.Provider>;
 };
@@ -1,6 +1,7 @@
 import styled from 'styled-components';
 
 import { colors } from '../../config.json';
+import * as Cell from './cell';
 import { hugeText, measurements, smallText } from './common-styles';
 
 export const StyledContentContainer = styled.div({
@@ -33,6 +34,12 @@ export const StyledFormMessageRow = styled.div({
   flex: 1,
 });
 
+export const StyledFormAccountRow = styled(Cell.Container)({
+  marginTop: '12px',
+  paddingLeft: '12px',
+  paddingRight: '12px',
+});
+
 const input = {
   flex: 1,
   borderRadius: '4px',
@@ -6,6 +6,7 @@ import { messages } from '../../shared/gettext';
 export enum SecuredDisplayStyle {
   secured,
   securedPq,
+  securedUnstable,
   blocked,
   securing,
   securingPq,
@@ -20,6 +21,7 @@ const securedDisplayStyleColorMap = {
   [SecuredDisplayStyle.unsecuring]: colors.white,
   [SecuredDisplayStyle.secured]: colors.green,
   [SecuredDisplayStyle.securedPq]: colors.green,
+  [SecuredDisplayStyle.securedUnstable]: colors.green,
   [SecuredDisplayStyle.blocked]: colors.white,
   [SecuredDisplayStyle.unsecured]: colors.red,
   [SecuredDisplayStyle.failedToSecure]: colors.red,
@@ -52,6 +54,9 @@ function getLabelText(displayStyle: SecuredDisplayStyle) {
     case SecuredDisplayStyle.securedPq:
       return messages.gettext('QUANTUM SECURE CONNECTION');
 
+    case SecuredDisplayStyle.securedUnstable:
+      return messages.gettext('SECURE CONNECTION (UNSTABLE)');
+
     case SecuredDisplayStyle.blocked:
       return messages.gettext('BLOCKED CONNECTION');
 
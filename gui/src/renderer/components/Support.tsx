@@ -61,6 +61,7 @@ export default function Support() {
               <StyledContent>
                 <Cell.Group>
                   <ProblemReportButton />
+                  <ConnectionLogButton />
                   <FaqButton />
                 </Cell.Group>
 
@@ -90,6 +91,20 @@ function ProblemReportButton() {
   );
 }
 
+function ConnectionLogButton() {
+  const history = useHistory();
+  const clickHandler = useCallback(() => history.push(RoutePath.connectionLog), [history]);
+
+  // TRANSLATORS: Navigation button to the view that lists recent connection events
+  const label = messages.pgettext('support-view', 'Connection log');
+
+  return (
+    <Cell.CellNavigationButton onClick={clickHandler}>
+      <Cell.Label>{label}</Cell.Label>
+    </Cell.CellNavigationButton>
+  );
+}
+
 function FaqButton() {
   const isOffline = useSelector((state) => state.connection.isBlocked);
   const { openUrl } = useAppContext();
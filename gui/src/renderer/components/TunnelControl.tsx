@@ -2,11 +2,12 @@ import * as React from 'react';
 import { sprintf } from 'sprintf-js';
 import styled from 'styled-components';
 
+import { colors } from '../../config.json';
 import { TunnelState } from '../../shared/daemon-rpc-types';
 import { messages, relayLocations } from '../../shared/gettext';
 import ConnectionPanelContainer from '../containers/ConnectionPanelContainer';
 import * as AppButton from './AppButton';
-import { hugeText, measurements, normalText } from './common-styles';
+import { hugeText, measurements, normalText, tinyText } from './common-styles';
 import ImageView from './ImageView';
 import { Footer } from './Layout';
 import Marquee from './Marquee';
@@ -30,6 +31,10 @@ const Secured = styled(SecuredLabel)(normalText, {
   lineHeight: '22px',
 });
 
+const Phase = styled.span(tinyText, {
+  color: colors.white60,
+});
+
 const Body = styled.div({
   display: 'flex',
   flexDirection: 'column',
@@ -66,6 +71,7 @@ export default class TunnelControl extends React.Component<ITunnelControlProps>
   public render() {
     let state = this.props.tunnelState.state;
     let pq = false;
+    let unstable = false;
 
     switch (this.props.tunnelState.state) {
       case 'disconnecting':
@@ -88,6 +94,7 @@ export default class TunnelControl extends React.Component<ITunnelControlProps>
         break;
       case 'connected':
         pq = this.props.tunnelState.details.endpoint.quantumResistant;
+        unstable = (this.props.tunnelState.health ?? 'healthy') !== 'healthy';
         break;
     }
 
@@ -98,6 +105,7 @@ export default class TunnelControl extends React.Component<ITunnelControlProps>
           <Wrapper>
             <Body>
               <Secured displayStyle={displayStyle} />
+              <Phase>{this.renderPhase()}</Phase>
               <Location>
                 {this.renderCountry()}
                 {this.renderCity()}
@@ -115,7 +123,10 @@ export default class TunnelControl extends React.Component<ITunnelControlProps>
       }
 
       case 'connected': {
-        const displayStyle = pq ? SecuredDisplayStyle.securedPq : SecuredDisplayStyle.secured;
+        let displayStyle = pq ? SecuredDisplayStyle.securedPq : SecuredDisplayStyle.secured;
+        if (unstable) {
+          displayStyle = SecuredDisplayStyle.securedUnstable;
+        }
         return (
           <Wrapper>
             <Body>
@@ -217,6 +228,23 @@ export default class TunnelControl extends React.Component<ITunnelControlProps>
     }
   }
 
+  private renderPhase() {
+    if (this.props.tunnelState.state !== 'connecting') {
+      return null;
+    }
+
+    switch (this.props.tunnelState.phase) {
+      case 'opening-device':
+        return messages.pgettext('tunnel-control', 'Starting tunnel');
+      case 'handshaking':
+        return messages.pgettext('tunnel-control', 'Exchanging keys with server');
+      case 'verifying-connectivity':
+        return messages.pgettext('tunnel-control', 'Verifying connection');
+      default:
+        return null;
+    }
+  }
+
   private renderCity() {
     const city = this.props.city === undefined ? '' : relayLocations.gettext(this.props.city);
     return (
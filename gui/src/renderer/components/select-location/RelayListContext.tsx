@@ -1,6 +1,8 @@
 import React, { useCallback, useContext, useEffect, useMemo, useState } from 'react';
 
 import { compareRelayLocation, RelayLocation } from '../../../shared/daemon-rpc-types';
+import log from '../../../shared/logging';
+import { useAppContext } from '../../context';
 import {
   EndpointType,
   filterLocations,
@@ -78,9 +80,10 @@ export function RelayListContextProvider(props: RelayListContextProviderProps) {
   }, [relaySettings?.ownership, relaySettings?.providers, relayListForEndpointType]);
 
   // Filters the relays based on the provided search term
+  const searchResults = useSearchResults(searchTerm);
   const relayListForSearch = useMemo(() => {
-    return searchForLocations(relayListForFilters, searchTerm);
-  }, [relayListForFilters, searchTerm]);
+    return searchForLocations(relayListForFilters, searchResults);
+  }, [relayListForFilters, searchResults]);
 
   const {
     expandedLocations,
@@ -88,7 +91,7 @@ export function RelayListContextProvider(props: RelayListContextProviderProps) {
     collapseLocation,
     onBeforeExpand,
     expandSearchResults,
-  } = useExpandedLocations(relayListForFilters);
+  } = useExpandedLocations(relayListForFilters, searchResults);
 
   // Prepares all relays and combines the data needed for rendering them
   const relayList = useRelayList(relayListForSearch, expandedLocations);
@@ -176,9 +179,50 @@ function useRelayList(
   }, [locale, expandedLocations, relayList, selectedLocation, disabledLocation]);
 }
 
+// Time to wait after the search term last changed before searching.
+const SEARCH_DEBOUNCE_MS = 200;
+
+// Returns the locations matching the search term. Matching is done by the daemon, which also
+// matches the translated names of countries and cities. Returns undefined when not searching.
+function useSearchResults(searchTerm: string): Array<RelayLocation> | undefined {
+  const { searchLocations } = useAppContext();
+  const locale = useSelector((state) => state.userInterface.locale);
+  const [searchResults, setSearchResults] = useState<Array<RelayLocation>>();
+
+  useEffect(() => {
+    if (searchTerm === '') {
+      setSearchResults(undefined);
+      return;
+    }
+
+    // Results of an outdated search term should be ignored if they arrive late
+    let outdated = false;
+    // Wait for the user to stop typing before asking the daemon
+    const timeout = setTimeout(() => {
+      searchLocations(searchTerm, locale)
+        .then((results) => {
+          if (!outdated) {
+            setSearchResults(results.map((result) => result.location));
+          }
+        })
+        .catch((error: Error) => log.error(`Failed to search for locations: ${error.message}`));
+    }, SEARCH_DEBOUNCE_MS);
+
+    return () => {
+      outdated = true;
+      clearTimeout(timeout);
+    };
+  }, [searchTerm, locale]);
+
+  return searchResults;
+}
+
 // Return all RelayLocations that should be expanded
-function useExpandedLocations(filteredLocations: Array<IRelayLocationRedux>) {
-  const { locationType, searchTerm } = useSelectLocationContext();
+function useExpandedLocations(
+  filteredLocations: Array<IRelayLocationRedux>,
+  searchResults?: Array<RelayLocation>,
+) {
+  const { locationType } = useSelectLocationContext();
   const { spacePreAllocationViewRef, scrollIntoView } = useScrollPositionContext();
   const relaySettings = useNormalRelaySettings();
   const bridgeSettings = useNormalBridgeSettings();
@@ -228,25 +272,25 @@ function useExpandedLocations(filteredLocations: Array<IRelayLocationRedux>) {
     (searchTerm: string) => {
       if (searchTerm === '') {
         setExpandedLocations(defaultExpandedLocations(relaySettings, bridgeSettings));
-      } else {
+      } else if (searchResults !== undefined) {
         setExpandedLocations((expandedLocations) => ({
           ...expandedLocations,
-          [locationType]: getLocationsExpandedBySearch(filteredLocations, searchTerm),
+          [locationType]: getLocationsExpandedBySearch(filteredLocations, searchResults),
         }));
       }
     },
-    [relaySettings, bridgeSettings, locationType, filteredLocations],
+    [relaySettings, bridgeSettings, locationType, filteredLocations, searchResults],
   );
 
-  // Expand locations when filters are changed
+  // Expand locations when filters are changed or when new search results arrive
   useEffect(() => {
-    if (searchTerm !== '') {
+    if (searchResults !== undefined) {
       setExpandedLocations((expandedLocations) => ({
         ...expandedLocations,
-        [locationType]: getLocationsExpandedBySearch(filteredLocations, searchTerm),
+        [locationType]: getLocationsExpandedBySearch(filteredLocations, searchResults),
       }));
     }
-  }, [filteredLocations]);
+  }, [filteredLocations, searchResults]);
 
   return {
     expandedLocations: expandedLocationsMap[locationType],
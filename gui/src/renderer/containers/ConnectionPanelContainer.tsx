@@ -99,6 +99,10 @@ const mapStateToProps = (state: IReduxState) => {
     hostname: state.connection.hostname,
     bridgeHostname: state.connection.bridgeHostname,
     entryHostname: state.connection.entryHostname,
+    provider: state.connection.provider,
+    ownership: state.connection.ownership,
+    features: state.connection.features,
+    nearbyFallback: state.connection.nearbyFallbackFrom !== undefined,
     inAddress,
     entryLocationInAddress,
     bridgeInfo,
@@ -1,4 +1,9 @@
-import { Ownership, RelayEndpointType, RelayLocation } from '../../shared/daemon-rpc-types';
+import {
+  compareRelayLocation,
+  Ownership,
+  RelayEndpointType,
+  RelayLocation,
+} from '../../shared/daemon-rpc-types';
 import { SpecialLocation } from '../components/select-location/select-location-types';
 import {
   IRelayLocationCityRedux,
@@ -88,16 +93,16 @@ function filterLocationsImpl(
 
 export function searchForLocations(
   countries: Array<IRelayLocationRedux>,
-  searchTerm: string,
+  searchResults?: Array<RelayLocation>,
 ): Array<IRelayLocationRedux> {
-  if (searchTerm === '') {
+  if (searchResults === undefined) {
     return countries;
   }
 
   return countries.reduce((countries, country) => {
-    const matchingCities = searchCities(country.cities, searchTerm);
+    const matchingCities = searchCities(country.cities, country.code, searchResults);
     const expanded = matchingCities.length > 0;
-    const match = search(searchTerm, country.code) || search(searchTerm, country.name);
+    const match = isSearchResult({ country: country.code }, searchResults);
     const resultingCities = match ? country.cities : matchingCities;
     return expanded || match ? [...countries, { ...country, cities: resultingCities }] : countries;
   }, [] as Array<IRelayLocationRedux>);
@@ -105,12 +110,15 @@ export function searchForLocations(
 
 function searchCities(
   cities: Array<IRelayLocationCityRedux>,
-  searchTerm: string,
+  countryCode: string,
+  searchResults: Array<RelayLocation>,
 ): Array<IRelayLocationCityRedux> {
   return cities.reduce((cities, city) => {
-    const matchingRelays = city.relays.filter((relay) => search(searchTerm, relay.hostname));
+    const matchingRelays = city.relays.filter((relay) =>
+      isSearchResult({ hostname: [countryCode, city.code, relay.hostname] }, searchResults),
+    );
     const expanded = matchingRelays.length > 0;
-    const match = search(searchTerm, city.code) || search(searchTerm, city.name);
+    const match = isSearchResult({ city: [countryCode, city.code] }, searchResults);
     const resultingRelays = match ? city.relays : matchingRelays;
     return expanded || match ? [...cities, { ...city, relays: resultingRelays }] : cities;
   }, [] as Array<IRelayLocationCityRedux>);
@@ -118,16 +126,16 @@ function searchCities(
 
 export function getLocationsExpandedBySearch(
   countries: Array<IRelayLocationRedux>,
-  searchTerm: string,
+  searchResults: Array<RelayLocation>,
 ): Array<RelayLocation> {
   return countries.reduce((locations, country) => {
-    const cityLocations = getCityLocationsExpandecBySearch(
+    const cityLocations = getCityLocationsExpandedBySearch(
       country.cities,
       country.code,
-      searchTerm,
+      searchResults,
     );
-    const cityMatches = country.cities.some(
-      (city) => search(searchTerm, city.code) || search(searchTerm, city.name),
+    const cityMatches = country.cities.some((city) =>
+      isSearchResult({ city: [country.code, city.code] }, searchResults),
     );
     const location = { country: country.code };
     const expanded = cityMatches || cityLocations.length > 0;
@@ -135,18 +143,24 @@ export function getLocationsExpandedBySearch(
   }, [] as Array<RelayLocation>);
 }
 
-function getCityLocationsExpandecBySearch(
+function getCityLocationsExpandedBySearch(
   cities: Array<IRelayLocationCityRedux>,
   countryCode: string,
-  searchTerm: string,
+  searchResults: Array<RelayLocation>,
 ): Array<RelayLocation> {
   return cities.reduce((locations, city) => {
-    const expanded = city.relays.filter((relay) => search(searchTerm, relay.hostname)).length > 0;
+    const expanded = city.relays.some((relay) =>
+      isSearchResult({ hostname: [countryCode, city.code, relay.hostname] }, searchResults),
+    );
     const location: RelayLocation = { city: [countryCode, city.code] };
     return expanded ? [...locations, location] : locations;
   }, [] as Array<RelayLocation>);
 }
 
+function isSearchResult(location: RelayLocation, searchResults: Array<RelayLocation>): boolean {
+  return searchResults.some((result) => compareRelayLocation(result, location));
+}
+
 function search(searchTerm: string, value: string): boolean {
   return value.toLowerCase().includes(searchTerm.toLowerCase());
 }
@@ -3,6 +3,7 @@ import {
   ErrorState,
   ILocation,
   ITunnelStateRelayInfo,
+  TunnelHealth,
 } from '../../../shared/daemon-rpc-types';
 
 interface IConnectingAction {
@@ -13,6 +14,7 @@ interface IConnectingAction {
 interface IConnectedAction {
   type: 'CONNECTED';
   details: ITunnelStateRelayInfo;
+  health?: TunnelHealth;
 }
 
 interface IDisconnectedAction {
@@ -55,10 +57,11 @@ function connecting(details?: ITunnelStateRelayInfo): IConnectingAction {
   };
 }
 
-function connected(details: ITunnelStateRelayInfo): IConnectedAction {
+function connected(details: ITunnelStateRelayInfo, health?: TunnelHealth): IConnectedAction {
   return {
     type: 'CONNECTED',
     details,
+    health,
   };
 }
 
@@ -1,4 +1,4 @@
-import { Ip, TunnelState } from '../../../shared/daemon-rpc-types';
+import { Ip, ITunnelFeatures, Ownership, TunnelState } from '../../../shared/daemon-rpc-types';
 import { ReduxAction } from '../store';
 
 export interface IConnectionReduxState {
@@ -9,6 +9,10 @@ export interface IConnectionReduxState {
   hostname?: string;
   bridgeHostname?: string;
   entryHostname?: string;
+  provider?: string;
+  ownership?: Ownership;
+  features?: ITunnelFeatures;
+  nearbyFallbackFrom?: string;
   latitude?: number;
   longitude?: number;
   country?: string;
@@ -23,6 +27,10 @@ const initialState: IConnectionReduxState = {
   hostname: undefined,
   bridgeHostname: undefined,
   entryHostname: undefined,
+  provider: undefined,
+  ownership: undefined,
+  features: undefined,
+  nearbyFallbackFrom: undefined,
   latitude: undefined,
   longitude: undefined,
   country: undefined,
@@ -46,6 +54,10 @@ export default function (
         hostname: action.newLocation.hostname,
         bridgeHostname: action.newLocation.bridgeHostname,
         entryHostname: action.newLocation.entryHostname,
+        provider: action.newLocation.provider,
+        ownership: action.newLocation.ownership,
+        features: action.newLocation.features,
+        nearbyFallbackFrom: action.newLocation.nearbyFallbackFrom,
       };
 
     case 'UPDATE_BLOCK_STATE':
@@ -60,7 +72,7 @@ export default function (
     case 'CONNECTED':
       return {
         ...state,
-        status: { state: 'connected', details: action.details },
+        status: { state: 'connected', details: action.details, health: action.health },
       };
 
     case 'DISCONNECTED':
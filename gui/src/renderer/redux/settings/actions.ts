@@ -4,6 +4,7 @@ import {
   IDnsOptions,
   IWireguardEndpointData,
   ObfuscationSettings,
+  SplitTunnelMode,
 } from '../../../shared/daemon-rpc-types';
 import { IGuiSettingsState } from '../../../shared/gui-settings-state';
 import { BridgeSettingsRedux, IRelayLocationRedux, RelaySettingsRedux } from './reducers';
@@ -83,6 +84,11 @@ export interface IUpdateSplitTunnelingStateAction {
   enabled: boolean;
 }
 
+export interface IUpdateSplitTunnelModeAction {
+  type: 'UPDATE_SPLIT_TUNNEL_MODE';
+  mode: SplitTunnelMode;
+}
+
 export interface ISetSplitTunnelingApplicationsAction {
   type: 'SET_SPLIT_TUNNELING_APPLICATIONS';
   applications: IWindowsApplication[];
@@ -109,6 +115,7 @@ export type SettingsAction =
   | IUpdateAutoStartAction
   | IUpdateDnsOptionsAction
   | IUpdateSplitTunnelingStateAction
+  | IUpdateSplitTunnelModeAction
   | ISetSplitTunnelingApplicationsAction
   | ISetObfuscationSettings;
 
@@ -221,6 +228,13 @@ function updateSplitTunnelingState(enabled: boolean): IUpdateSplitTunnelingState
   };
 }
 
+function updateSplitTunnelMode(mode: SplitTunnelMode): IUpdateSplitTunnelModeAction {
+  return {
+    type: 'UPDATE_SPLIT_TUNNEL_MODE',
+    mode,
+  };
+}
+
 function setSplitTunnelingApplications(
   applications: IWindowsApplication[],
 ): ISetSplitTunnelingApplicationsAction {
@@ -255,6 +269,7 @@ export default {
   updateAutoStart,
   updateDnsOptions,
   updateSplitTunnelingState,
+  updateSplitTunnelMode,
   setSplitTunnelingApplications,
   updateObfuscationSettings,
 };
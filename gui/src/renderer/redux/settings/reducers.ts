@@ -12,6 +12,7 @@ import {
   RelayEndpointType,
   RelayLocation,
   RelayProtocol,
+  SplitTunnelMode,
   TunnelProtocol,
 } from '../../../shared/daemon-rpc-types';
 import { IGuiSettingsState } from '../../../shared/gui-settings-state';
@@ -103,6 +104,7 @@ export interface ISettingsReduxState {
   };
   dns: IDnsOptions;
   splitTunneling: boolean;
+  splitTunnelMode: SplitTunnelMode;
   splitTunnelingApplications: IWindowsApplication[];
   obfuscationSettings: ObfuscationSettings;
 }
@@ -160,12 +162,16 @@ const initialState: ISettingsReduxState = {
     },
   },
   splitTunneling: false,
+  splitTunnelMode: 'exclude',
   splitTunnelingApplications: [],
   obfuscationSettings: {
     selectedObfuscation: ObfuscationType.auto,
     udp2tcpSettings: {
       port: 'any',
     },
+    portHoppingSettings: {
+      interval: 300,
+    },
   },
 };
 
@@ -270,6 +276,12 @@ export default function (
         splitTunneling: action.enabled,
       };
 
+    case 'UPDATE_SPLIT_TUNNEL_MODE':
+      return {
+        ...state,
+        splitTunnelMode: action.mode,
+      };
+
     case 'SET_SPLIT_TUNNELING_APPLICATIONS':
       return {
         ...state,
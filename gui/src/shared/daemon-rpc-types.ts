@@ -15,6 +15,15 @@ export interface ILocation {
   bridgeHostname?: string;
   entryHostname?: string;
   provider?: string;
+  ownership?: Ownership;
+  features?: ITunnelFeatures;
+  nearbyFallbackFrom?: string;
+}
+
+export interface ITunnelFeatures {
+  quantumResistant: boolean;
+  daita: boolean;
+  udp2tcp: boolean;
 }
 
 export enum FirewallPolicyErrorType {
@@ -53,6 +62,7 @@ export enum TunnelParameterError {
   noMatchingBridgeRelay,
   noWireguardKey,
   customTunnelHostResolutionError,
+  missingRelayCapabilities,
 }
 
 export type ErrorState =
@@ -157,17 +167,62 @@ export type DaemonEvent =
   | { relayList: IRelayListWithEndpointData }
   | { appVersionInfo: IAppVersionInfo }
   | { device: DeviceEvent }
-  | { deviceRemoval: Array<IDevice> };
+  | { deviceRemoval: Array<IDevice> }
+  | { autoConnectDecision: IAutoConnectDecision }
+  | { conflictingSoftware: Array<IConflictingSoftware> }
+  | { quarantinedFiles: Array<IQuarantinedFile> };
+
+export interface IRecordedEvent {
+  // When the daemon sent the event, as an ISO 8601 string
+  time: string;
+  event: DaemonEvent;
+}
+
+export type AutoConnectRule = 'always' | 'never' | 'untrusted-wifi-only' | 'wired-exempt';
+
+export interface IAutoConnectDecision {
+  connect: boolean;
+  rule: AutoConnectRule;
+}
+
+export type ConflictKind = 'tunnel-interface' | 'wfp-provider';
+
+export interface IConflictingSoftware {
+  kind: ConflictKind;
+  name: string;
+}
+
+export type CorruptionReason = 'checksum-mismatch' | 'parse-error';
+
+export interface IQuarantinedFile {
+  path: string;
+  quarantinePath?: string;
+  reason: CorruptionReason;
+}
 
 export interface ITunnelStateRelayInfo {
   endpoint: ITunnelEndpoint;
   location?: ILocation;
 }
 
+export type ConnectingPhase = 'opening-device' | 'handshaking' | 'verifying-connectivity';
+
+export type TunnelHealth = 'healthy' | 'unstable' | 'high-latency' | 'packet-loss';
+
+export type DisconnectReason =
+  | 'user-requested'
+  | 'logged-out'
+  | 'auto-connect-policy'
+  | 'shutdown'
+  | 'tunnel-error'
+  | 'auth-failed'
+  | 'device-revoked'
+  | 'offline';
+
 export type TunnelState =
-  | { state: 'disconnected' }
-  | { state: 'connecting'; details?: ITunnelStateRelayInfo }
-  | { state: 'connected'; details: ITunnelStateRelayInfo }
+  | { state: 'disconnected'; reason?: DisconnectReason }
+  | { state: 'connecting'; details?: ITunnelStateRelayInfo; phase?: ConnectingPhase }
+  | { state: 'connected'; details: ITunnelStateRelayInfo; health?: TunnelHealth }
   | { state: 'disconnecting'; details: AfterDisconnect }
   | { state: 'error'; details: ErrorState };
 
@@ -176,6 +231,16 @@ export type RelayLocation =
   | { city: [string, string] }
   | { country: string };
 
+export interface ILocationSearchQuery {
+  query: string;
+  locale: string;
+}
+
+export interface ILocationSearchResult {
+  location: RelayLocation;
+  name: string;
+}
+
 export interface IOpenVpnConstraints {
   port: Constraint<number>;
   protocol: Constraint<RelayProtocol>;
@@ -362,7 +427,10 @@ export type LoggedOutDeviceState = { type: 'logged out' | 'revoked' };
 export type DeviceState = LoggedInDeviceState | LoggedOutDeviceState;
 
 export type DeviceEvent =
-  | { type: 'logged in' | 'updated' | 'rotated_key'; deviceState: LoggedInDeviceState }
+  | {
+      type: 'logged in' | 'updated' | 'rotated_key' | 'repaired_key';
+      deviceState: LoggedInDeviceState;
+    }
   | { type: 'logged out' | 'revoked'; deviceState: LoggedOutDeviceState };
 
 export interface IDevice {
@@ -387,11 +455,15 @@ export interface ISettings {
   bridgeSettings: BridgeSettings;
   bridgeState: BridgeState;
   splitTunnel: SplitTunnelSettings;
+  splitTunnelMode: SplitTunnelMode;
   obfuscationSettings: ObfuscationSettings;
 }
 
 export type BridgeState = 'auto' | 'on' | 'off';
 
+// Only the daemon on Linux supports include mode
+export type SplitTunnelMode = 'exclude' | 'include';
+
 export type SplitTunnelSettings = {
   enableExclusions: boolean;
   appsList: string[];
@@ -401,15 +473,23 @@ export type Udp2TcpObfuscationSettings = {
   port: Constraint<number>;
 };
 
+export type PortHoppingSettings = {
+  // Seconds between port hops while connected. Undefined means that the port only changes on
+  // reconnect.
+  interval?: number;
+};
+
 export enum ObfuscationType {
   auto,
   off,
   udp2tcp,
+  portHopping,
 }
 
 export type ObfuscationSettings = {
   selectedObfuscation: ObfuscationType;
   udp2tcpSettings: Udp2TcpObfuscationSettings;
+  portHoppingSettings: PortHoppingSettings;
 };
 
 export interface IBridgeConstraints {
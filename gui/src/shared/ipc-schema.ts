@@ -13,6 +13,9 @@ import {
   IDeviceRemoval,
   IDnsOptions,
   ILocation,
+  ILocationSearchQuery,
+  ILocationSearchResult,
+  IRecordedEvent,
   IRelayListWithEndpointData,
   ISettings,
   ObfuscationSettings,
@@ -30,6 +33,7 @@ interface ILogEntry {
 import { invoke, invokeSync, notifyRenderer, send } from './ipc-helpers';
 import {
   IChangelog,
+  ICollectProblemReportOptions,
   ICurrentAppVersionInfo,
   IHistoryObject,
   IWindowShapeParameters,
@@ -126,6 +130,7 @@ export const ipcSchema = {
     isPerformingPostUpgrade: notifyRenderer<boolean>(),
     connected: notifyRenderer<void>(),
     disconnected: notifyRenderer<void>(),
+    getRecentEvents: invoke<void, IRecordedEvent[]>(),
   },
   relays: {
     '': notifyRenderer<IRelayListWithEndpointData>(),
@@ -144,6 +149,7 @@ export const ipcSchema = {
   },
   location: {
     get: invoke<void, ILocation>(),
+    search: invoke<ILocationSearchQuery, ILocationSearchResult[]>(),
   },
   tunnel: {
     '': notifyRenderer<TunnelState>(),
@@ -197,7 +203,7 @@ export const ipcSchema = {
     set: invoke<boolean, void>(),
   },
   problemReport: {
-    collectLogs: invoke<string | undefined, string>(),
+    collectLogs: invoke<ICollectProblemReportOptions, string>(),
     sendReport: invoke<{ email: string; message: string; savedReportId: string }, void>(),
     viewLog: invoke<string, string>(),
   },
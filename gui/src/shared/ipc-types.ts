@@ -15,6 +15,17 @@ export interface IWindowShapeParameters {
 
 export type IChangelog = Array<string>;
 
+export interface IProblemReportAccountInfo {
+  accountToken: string;
+  deviceName?: string;
+}
+
+export interface ICollectProblemReportOptions {
+  toRedact?: string;
+  // Reports are anonymous unless this is set.
+  accountInfo?: IProblemReportAccountInfo;
+}
+
 export interface LocationState {
   scrollPosition: [number, number];
   expandedSections: Record<string, boolean>;
@@ -31,6 +31,7 @@ export type LocalizationContexts =
   | 'split-tunneling-view'
   | 'split-tunneling-nav'
   | 'support-view'
+  | 'connection-log-view'
   | 'select-language-nav'
   | 'tray-icon-context-menu'
   | 'tray-icon-tooltip';
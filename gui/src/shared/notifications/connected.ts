@@ -11,6 +11,16 @@ export class ConnectedNotificationProvider implements SystemNotificationProvider
 
   public getSystemNotification() {
     if (this.context.state === 'connected') {
+      const health = this.context.health ?? 'healthy';
+      if (health !== 'healthy') {
+        return {
+          // TRANSLATORS: The message showed when the connected tunnel stops working well, because
+          // TRANSLATORS: of lost packets, high latency or no traffic being received.
+          message: messages.pgettext('notifications', 'Connection unstable'),
+          critical: false,
+        };
+      }
+
       let message = messages.pgettext('notifications', 'Connected');
       const location = this.context.details.location?.hostname;
       if (location) {
@@ -14,9 +14,39 @@ export class DisconnectedNotificationProvider implements SystemNotificationProvi
     this.context.tunnelState.state === 'disconnected' && !this.context.blockWhenDisconnected;
 
   public getSystemNotification() {
+    const failureMessage = this.getFailureMessage();
     return {
-      message: messages.pgettext('notifications', 'Disconnected and unsecure'),
-      critical: false,
+      message: failureMessage ?? messages.pgettext('notifications', 'Disconnected and unsecure'),
+      // The user did not ask for the tunnel to be disconnected when it failed
+      critical: failureMessage !== undefined,
     };
   }
+
+  private getFailureMessage(): string | undefined {
+    if (this.context.tunnelState.state !== 'disconnected') {
+      return undefined;
+    }
+
+    switch (this.context.tunnelState.reason) {
+      case 'tunnel-error':
+        return messages.pgettext(
+          'notifications',
+          'Disconnected and unsecure since the connection failed',
+        );
+      case 'auth-failed':
+        return messages.pgettext(
+          'notifications',
+          'Disconnected and unsecure since the account could not be used',
+        );
+      case 'device-revoked':
+        return messages.pgettext(
+          'notifications',
+          'Disconnected and unsecure since this device was removed from the account',
+        );
+      case 'offline':
+        return messages.pgettext('notifications', 'Disconnected since this device is offline');
+      default:
+        return undefined;
+    }
+  }
 }
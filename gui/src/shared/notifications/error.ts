@@ -194,5 +194,10 @@ function getTunnelParameterMessage(error: TunnelParameterError): string {
         'notifications',
         'Unable to resolve host of custom tunnel. Try changing your settings.',
       );
+    case TunnelParameterError.missingRelayCapabilities:
+      return messages.pgettext(
+        'notifications',
+        'No servers in your selected location support the features enabled in your settings.',
+      );
   }
 }
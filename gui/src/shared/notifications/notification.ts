@@ -43,5 +43,6 @@ export * from './disconnected';
 export * from './error';
 export * from './inconsistent-version';
 export * from './reconnecting';
+export * from './split-tunnel-include-mode';
 export * from './unsupported-version';
 export * from './update-available';
@@ -0,0 +1,34 @@
+import { sprintf } from 'sprintf-js';
+
+import { strings } from '../../config.json';
+import { SplitTunnelMode } from '../daemon-rpc-types';
+import { messages } from '../gettext';
+import { InAppNotification, InAppNotificationProvider } from './notification';
+
+interface SplitTunnelIncludeModeNotificationContext {
+  splitTunnelMode: SplitTunnelMode;
+}
+
+// In include mode, the traffic of every app that has not been included leaves the device outside
+// the tunnel, regardless of the tunnel state.
+export class SplitTunnelIncludeModeNotificationProvider implements InAppNotificationProvider {
+  public constructor(private context: SplitTunnelIncludeModeNotificationContext) {}
+
+  public mayDisplay() {
+    return this.context.splitTunnelMode === 'include';
+  }
+
+  public getInAppNotification(): InAppNotification {
+    return {
+      indicator: 'warning',
+      title: messages.pgettext('in-app-notifications', 'UNPROTECTED TRAFFIC'),
+      subtitle: sprintf(
+        messages.pgettext(
+          'in-app-notifications',
+          'Only apps included with %(splitTunneling)s use the VPN. Other traffic is unprotected.',
+        ),
+        { splitTunneling: strings.splitTunneling.toLowerCase() },
+      ),
+    };
+  }
+}
@@ -28,6 +28,8 @@ const config = {
     { from: distAssets('ca.crt'), to: '.' },
     { from: distAssets('relays.json'), to: '.' },
     { from: root('CHANGELOG.md'), to: '.' },
+    // Used by the daemon to search for locations by their translated names
+    { from: root('gui/locales'), to: 'locales', filter: ['*/relay-locations.po'] },
   ],
 
   directories: {
@@ -93,6 +95,7 @@ const config = {
       { from: distAssets(path.join('${env.TARGET_TRIPLE}', 'mullvad-problem-report')), to: '.' },
       { from: distAssets(path.join('${env.TARGET_TRIPLE}', 'mullvad-daemon')), to: '.' },
       { from: distAssets(path.join('${env.TARGET_TRIPLE}', 'mullvad-setup')), to: '.' },
+      { from: distAssets(path.join('${env.TARGET_TRIPLE}', 'lyrebird')), to: '.' },
       {
         from: distAssets(path.join('${env.TARGET_TRIPLE}', 'libtalpid_openvpn_plugin.dylib')),
         to: '.',
@@ -137,6 +140,7 @@ const config = {
       { from: distAssets('mullvad-problem-report.exe'), to: '.' },
       { from: distAssets('mullvad-daemon.exe'), to: '.' },
       { from: distAssets('talpid_openvpn_plugin.dll'), to: '.' },
+      { from: distAssets('lyrebird.exe'), to: '.' },
       {
         from: root(path.join('windows', 'winfw', 'bin', 'x64-${env.CPP_BUILD_MODE}', 'winfw.dll')),
         to: '.',
@@ -170,6 +174,7 @@ const config = {
     extraResources: [
       { from: distAssets(path.join(getLinuxTargetSubdir(), 'mullvad-problem-report')), to: '.' },
       { from: distAssets(path.join(getLinuxTargetSubdir(), 'mullvad-setup')), to: '.' },
+      { from: distAssets(path.join(getLinuxTargetSubdir(), 'lyrebird')), to: '.' },
       { from: distAssets(path.join(getLinuxTargetSubdir(), 'libtalpid_openvpn_plugin.so')), to: '.' },
       { from: distAssets(path.join('binaries', '${env.TARGET_TRIPLE}', 'openvpn')), to: '.' },
     ],
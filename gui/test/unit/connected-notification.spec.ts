@@ -0,0 +1,26 @@
+import { expect } from 'chai';
+import { it, describe } from 'mocha';
+import { ITunnelEndpoint, TunnelState } from '../../src/shared/daemon-rpc-types';
+import { ConnectedNotificationProvider } from '../../src/shared/notifications/connected';
+
+const details = { endpoint: {} as ITunnelEndpoint };
+
+function notification(tunnelState: TunnelState) {
+  return new ConnectedNotificationProvider(tunnelState).getSystemNotification();
+}
+
+describe('Connected notification', () => {
+  it('Should say connected when the tunnel is healthy', () => {
+    expect(notification({ state: 'connected', details })?.message).to.equal('Connected');
+    const healthy = notification({ state: 'connected', details, health: 'healthy' });
+    expect(healthy?.message).to.equal('Connected');
+  });
+
+  it('Should say unstable when the tunnel is not healthy', () => {
+    for (const health of ['unstable', 'high-latency', 'packet-loss'] as const) {
+      const { message, critical } = notification({ state: 'connected', details, health })!;
+      expect(message).to.equal('Connection unstable');
+      expect(critical).to.equal(false);
+    }
+  });
+});
@@ -0,0 +1,27 @@
+import { expect } from 'chai';
+import { it, describe } from 'mocha';
+import { TunnelState } from '../../src/shared/daemon-rpc-types';
+import { DisconnectedNotificationProvider } from '../../src/shared/notifications/disconnected';
+
+function notification(tunnelState: TunnelState) {
+  return new DisconnectedNotificationProvider({
+    tunnelState,
+    blockWhenDisconnected: false,
+  }).getSystemNotification();
+}
+
+describe('Disconnected notification', () => {
+  it('Should not be critical when the user disconnected', () => {
+    expect(notification({ state: 'disconnected' }).critical).to.equal(false);
+    const userRequested = notification({ state: 'disconnected', reason: 'user-requested' });
+    expect(userRequested.critical).to.equal(false);
+  });
+
+  it('Should be critical when the tunnel failed', () => {
+    for (const reason of ['tunnel-error', 'auth-failed', 'device-revoked', 'offline'] as const) {
+      const { message, critical } = notification({ state: 'disconnected', reason });
+      expect(critical).to.equal(true);
+      expect(message).to.not.equal('Disconnected and unsecure');
+    }
+  });
+});
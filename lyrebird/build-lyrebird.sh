@@ -0,0 +1,70 @@
+#!/usr/bin/env bash
+
+# This script is used to build the lyrebird pluggable transport client, which is used to reach the
+# API through obfs4 bridges. The binary is placed in `build/bin/<target triple>/`.
+
+set -eu
+
+LYREBIRD_REPO="https://gitlab.torproject.org/tpo/anti-censorship/pluggable-transports/lyrebird.git"
+LYREBIRD_VERSION="lyrebird-0.1.0"
+
+function host_target_triple {
+    local platform="$(uname -s)"
+    local arch="$(uname -m)"
+    if [[ ("${platform}" == "Linux") ]]; then
+        echo "${arch}-unknown-linux-gnu"
+    elif [[ ("${platform}" == "Darwin") ]]; then
+        if [[ ("${arch}" == "arm64") ]]; then
+            arch="aarch64"
+        fi
+        echo "${arch}-apple-darwin"
+    elif [[ ("${platform}" == "MINGW"* || "${platform}" == "MSYS_NT"*) ]]; then
+        echo "x86_64-pc-windows-msvc"
+    else
+        echo "Can't deduce target triple for $platform"
+        return 1
+    fi
+}
+
+function build_lyrebird {
+    local target_triple="$1"
+    local binary_name="lyrebird"
+
+    # lyrebird is pure Go, so it can be cross compiled without a C toolchain
+    export CGO_ENABLED=0
+    case "$target_triple" in
+        x86_64-unknown-linux-gnu) export GOOS=linux GOARCH=amd64;;
+        aarch64-unknown-linux-gnu) export GOOS=linux GOARCH=arm64;;
+        x86_64-apple-darwin) export GOOS=darwin GOARCH=amd64;;
+        aarch64-apple-darwin) export GOOS=darwin GOARCH=arm64;;
+        x86_64-pc-windows-msvc)
+            export GOOS=windows GOARCH=amd64
+            binary_name="lyrebird.exe"
+            ;;
+        *)
+            echo "Unsupported target: $target_triple"
+            return 1
+            ;;
+    esac
+
+    echo "Building lyrebird $LYREBIRD_VERSION for $target_triple"
+
+    local source_dir="../build/src/lyrebird"
+    if [[ ! -d "$source_dir" ]]; then
+        git clone --depth 1 --branch "$LYREBIRD_VERSION" "$LYREBIRD_REPO" "$source_dir"
+    fi
+
+    local target_dir="$(pwd)/../build/bin/$target_triple"
+    mkdir -p "$target_dir"
+
+    pushd "$source_dir"
+        git fetch --depth 1 origin tag "$LYREBIRD_VERSION"
+        git checkout -q "$LYREBIRD_VERSION"
+        go build -v -trimpath -ldflags "-s -w" -o "$target_dir/$binary_name" ./cmd/lyrebird
+    popd
+}
+
+# Ensure we are in the correct directory for the execution of this script
+script_dir="$( cd "$( dirname "${BASH_SOURCE[0]}" )" && pwd )"
+cd $script_dir
+build_lyrebird "${1:-$(host_target_triple)}"
@@ -0,0 +1,159 @@
+//! An in-memory implementation of the parts of the Mullvad REST API that the app uses: accounts,
+//! devices, vouchers, relays and problem reports. It serves plain HTTP, and is meant to be used
+//! through the API override of development builds, so that the daemon and the frontends can be
+//! run and tested without network access.
+
+use futures::channel::oneshot;
+use hyper::service::{make_service_fn, service_fn};
+use std::{
+    convert::Infallible,
+    io,
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+mod relays;
+mod server;
+pub mod state;
+
+pub use relays::RelayList;
+pub use state::State;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to listen on {}", _0)]
+    Bind(SocketAddr, #[error(source)] io::Error),
+
+    #[error(display = "Failed to start the HTTP server")]
+    Serve(#[error(source)] hyper::Error),
+}
+
+/// A running mock API server. It stops when this is dropped.
+pub struct MockServer {
+    local_addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    _shutdown_tx: oneshot::Sender<()>,
+}
+
+impl MockServer {
+    /// Starts serving requests on `addr`. Port 0 picks any free port, which can then be read from
+    /// [`MockServer::local_addr`]. Must be called from within a Tokio runtime.
+    pub fn spawn(addr: SocketAddr, state: State, relay_list: RelayList) -> Result<Self, Error> {
+        let listener = TcpListener::bind(addr).map_err(|error| Error::Bind(addr, error))?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|error| Error::Bind(addr, error))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|error| Error::Bind(addr, error))?;
+
+        let state = Arc::new(Mutex::new(state));
+        let context = Arc::new(server::Context {
+            state: state.clone(),
+            relay_list,
+            local_addr,
+        });
+        let make_service = make_service_fn(move |_| {
+            let context = context.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    server::handle_request(context.clone(), request)
+                }))
+            }
+        });
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let server = hyper::Server::from_tcp(listener)
+            .map_err(Error::Serve)?
+            .serve(make_service)
+            .with_graceful_shutdown(async move {
+                let _ = shutdown_rx.await;
+            });
+        tokio::spawn(async move {
+            if let Err(error) = server.await {
+                log::error!("Mock API server failed: {}", error);
+            }
+        });
+
+        log::info!("Mock API listening on {}", local_addr);
+        Ok(MockServer {
+            local_addr,
+            state,
+            _shutdown_tx: shutdown_tx,
+        })
+    }
+
+    /// Returns the address that the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Gives access to the accounts and other data of the server, for example to create
+    /// accounts or to inspect submitted problem reports.
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use mullvad_api::{
+        proxy::ApiConnectionMode, rest, AccountsProxy, ApiEndpoint, ApiError, DevicesProxy, API,
+    };
+    use talpid_types::net::wireguard::PublicKey;
+
+    /// Sends requests through the same client as the daemon, with the API overridden like in the
+    /// development builds that the mock is meant for.
+    #[tokio::test]
+    async fn test_real_client() {
+        let server = MockServer::spawn(
+            "127.0.0.1:0".parse().unwrap(),
+            State::default(),
+            RelayList::default(),
+        )
+        .unwrap();
+        let account = server
+            .state()
+            .create_account(Utc::now() + Duration::days(30));
+
+        API.override_init(ApiEndpoint {
+            host: "api.mullvad.net".to_owned(),
+            addr: server.local_addr(),
+            root_certificates: None,
+            disable_address_cache: true,
+            disable_tls: true,
+            force_direct_connection: true,
+        })
+        .expect("API endpoint is only set once");
+        let runtime = mullvad_api::Runtime::new(tokio::runtime::Handle::current()).unwrap();
+        let handle = runtime
+            .mullvad_rest_handle(ApiConnectionMode::Direct.into_repeat(), |_| async { true })
+            .await;
+
+        let expiry = AccountsProxy::new(handle.clone())
+            .get_expiry(account.clone())
+            .await
+            .unwrap();
+        assert_eq!(expiry, server.state().account(&account).unwrap().expiry);
+
+        let pubkey = PublicKey::from([1; 32]);
+        let (device, _) = DevicesProxy::new(handle.clone())
+            .create(account.clone(), pubkey.clone())
+            .await
+            .unwrap();
+        assert_eq!(device.pubkey, pubkey);
+        assert_eq!(server.state().account(&account).unwrap().devices.len(), 1);
+
+        let error = AccountsProxy::new(handle)
+            .get_expiry("1111222233334444".to_owned())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            rest::Error::ApiError(_, ApiError::InvalidAccount, _)
+        ));
+    }
+}
@@ -0,0 +1,86 @@
+use chrono::{Duration, Utc};
+use clap::{crate_authors, crate_description, crate_name, App, Arg};
+use mullvad_api_mock::{MockServer, RelayList, State};
+use std::{net::SocketAddr, path::Path, process};
+use talpid_types::ErrorExt;
+
+/// Time left on accounts that are created by the mock API or given on the command line.
+const ACCOUNT_TIME_DAYS: i64 = 30;
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let matches = App::new(crate_name!())
+        .version(mullvad_version::VERSION)
+        .author(crate_authors!())
+        .about(crate_description!())
+        .arg(
+            Arg::new("listen")
+                .long("listen")
+                .takes_value(true)
+                .default_value("127.0.0.1:8000")
+                .help("Address and port to serve the API on"),
+        )
+        .arg(
+            Arg::new("account")
+                .long("account")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("Account number that exists from the start"),
+        )
+        .arg(
+            Arg::new("voucher")
+                .long("voucher")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("Voucher code that can be redeemed once"),
+        )
+        .arg(
+            Arg::new("relay list")
+                .long("relay-list")
+                .takes_value(true)
+                .help(
+                    "File with a relay list in the format of the API, to serve instead of the \
+                     built-in one",
+                ),
+        )
+        .get_matches();
+
+    let addr: SocketAddr = matches.value_of_t_or_exit("listen");
+
+    let mut state = State::default();
+    let expiry = Utc::now() + Duration::days(ACCOUNT_TIME_DAYS);
+    for account in matches.values_of("account").into_iter().flatten() {
+        state.add_account(account.to_owned(), expiry);
+    }
+    for voucher in matches.values_of("voucher").into_iter().flatten() {
+        state.add_voucher(voucher.to_owned(), Duration::days(ACCOUNT_TIME_DAYS));
+    }
+
+    let relay_list = match matches.value_of("relay list") {
+        Some(path) => match RelayList::from_file(Path::new(path)).await {
+            Ok(relay_list) => relay_list,
+            Err(error) => {
+                eprintln!("Failed to read relay list from {}: {}", path, error);
+                process::exit(1);
+            }
+        },
+        None => RelayList::default(),
+    };
+
+    let server = match MockServer::spawn(addr, state, relay_list) {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("{}", error.display_chain());
+            process::exit(1);
+        }
+    };
+
+    println!("Run a development build of the daemon with these variables to use the mock API:");
+    println!("    MULLVAD_API_HOST=api.mullvad.net");
+    println!("    MULLVAD_API_ADDR={}", server.local_addr());
+    println!("    MULLVAD_API_DISABLE_TLS=1");
+
+    let _ = tokio::signal::ctrl_c().await;
+}
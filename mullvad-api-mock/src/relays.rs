@@ -0,0 +1,113 @@
+//! The relay list served by the mock API.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    io,
+    path::Path,
+};
+
+/// A relay list in the format used by the API, along with its ETag.
+pub struct RelayList {
+    body: Vec<u8>,
+    etag: String,
+}
+
+impl RelayList {
+    /// Serves the contents of `path` as is. The file must use the format of the API, not the
+    /// format of the relay list cache.
+    pub async fn from_file(path: &Path) -> io::Result<Self> {
+        Ok(Self::from_body(tokio::fs::read(path).await?))
+    }
+
+    fn from_body(body: Vec<u8>) -> Self {
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        let etag = format!("\"{:016x}\"", hasher.finish());
+        RelayList { body, etag }
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn etag(&self) -> &str {
+        &self.etag
+    }
+
+    /// Returns whether a client that has the relay list with the given tag is up to date. Weak
+    /// tags are accepted, since the client marks every tag that it stores as weak.
+    pub fn matches_etag(&self, etag: &str) -> bool {
+        etag.trim_start_matches("W/") == self.etag
+    }
+}
+
+impl Default for RelayList {
+    /// A small relay list with one relay of each kind.
+    fn default() -> Self {
+        let relay_list = serde_json::json!({
+            "locations": {
+                "se-got": {
+                    "city": "Gothenburg",
+                    "country": "Sweden",
+                    "latitude": 57.70887,
+                    "longitude": 11.97456,
+                },
+            },
+            "openvpn": {
+                "ports": [
+                    { "port": 1194, "protocol": "udp" },
+                    { "port": 443, "protocol": "tcp" },
+                ],
+                "relays": [{
+                    "hostname": "se-got-ovpn-001",
+                    "active": true,
+                    "owned": true,
+                    "location": "se-got",
+                    "provider": "Mock",
+                    "ipv4_addr_in": "192.0.2.1",
+                    "ipv6_addr_in": null,
+                    "weight": 100,
+                    "include_in_country": true,
+                }],
+            },
+            "wireguard": {
+                "port_ranges": [[53, 53], [4000, 33433], [33565, 51820], [52000, 60000]],
+                "ipv4_gateway": "10.64.0.1",
+                "ipv6_gateway": "fc00:bbbb:bbbb:bb01::1",
+                "relays": [{
+                    "hostname": "se-got-wg-001",
+                    "active": true,
+                    "owned": true,
+                    "location": "se-got",
+                    "provider": "Mock",
+                    "ipv4_addr_in": "192.0.2.2",
+                    "ipv6_addr_in": "2001:db8::2",
+                    "weight": 100,
+                    "include_in_country": true,
+                    "public_key": "i91v9z9w2H5oclsvc2Bi5tMWCVbNou66rjYryQPp4vA=",
+                }],
+            },
+            "bridge": {
+                "shadowsocks": [{
+                    "port": 443,
+                    "cipher": "aes-256-gcm",
+                    "password": "mullvad",
+                    "protocol": "tcp",
+                }],
+                "relays": [{
+                    "hostname": "se-got-br-001",
+                    "active": true,
+                    "owned": true,
+                    "location": "se-got",
+                    "provider": "Mock",
+                    "ipv4_addr_in": "192.0.2.3",
+                    "ipv6_addr_in": null,
+                    "weight": 100,
+                    "include_in_country": true,
+                }],
+            },
+        });
+        Self::from_body(serde_json::to_vec(&relay_list).unwrap())
+    }
+}
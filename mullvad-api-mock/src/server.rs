@@ -0,0 +1,248 @@
+//! Maps REST requests onto the in-memory [`State`].
+
+use crate::{
+    relays::RelayList,
+    state::{ProblemReport, State},
+};
+use chrono::Utc;
+use hyper::{body::Bytes, header, Body, HeaderMap, Method, Request, Response, StatusCode};
+use mullvad_api::ApiError;
+use mullvad_types::account::AccountToken;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use talpid_types::net::wireguard::PublicKey;
+
+/// Everything that request handlers have access to.
+pub(crate) struct Context {
+    pub state: Arc<Mutex<State>>,
+    pub relay_list: RelayList,
+    pub local_addr: SocketAddr,
+}
+
+/// A failed request.
+enum Error {
+    /// An error with a code that the app knows how to handle.
+    Api(ApiError),
+    /// The request was not understood.
+    BadRequest,
+    NotFound,
+}
+
+impl From<ApiError> for Error {
+    fn from(error: ApiError) -> Self {
+        Error::Api(error)
+    }
+}
+
+impl Error {
+    fn into_response(self) -> Response<Body> {
+        let error = match self {
+            Error::Api(error) => error,
+            Error::BadRequest => return empty_response(StatusCode::BAD_REQUEST),
+            Error::NotFound => return empty_response(StatusCode::NOT_FOUND),
+        };
+        let status = match error {
+            ApiError::InvalidAccount | ApiError::InvalidAccessToken => StatusCode::UNAUTHORIZED,
+            ApiError::DeviceNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let body = serde_json::json!({
+            "code": error.code(),
+            "error": format!("Mock API error: {}", error),
+        });
+        json_response(status, &body)
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub(crate) async fn handle_request(
+    context: Arc<Context>,
+    request: Request<Body>,
+) -> std::result::Result<Response<Body>, Infallible> {
+    let (parts, body) = request.into_parts();
+    log::debug!("{} {}", parts.method, parts.uri.path());
+
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(_) => return Ok(empty_response(StatusCode::BAD_REQUEST)),
+    };
+    let path: Vec<&str> = parts.uri.path().trim_matches('/').split('/').collect();
+
+    let response = route(&context, &parts.method, &path, &parts.headers, body);
+    Ok(response.unwrap_or_else(Error::into_response))
+}
+
+fn route(
+    context: &Context,
+    method: &Method,
+    path: &[&str],
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Result<Response<Body>> {
+    let mut state = context.state.lock().unwrap();
+
+    match (method, path) {
+        (&Method::POST, ["auth", "v1", "token"]) => {
+            #[derive(serde::Deserialize)]
+            struct AccessTokenRequest {
+                account_number: AccountToken,
+            }
+            let request: AccessTokenRequest = parse_json(&body)?;
+            let token = state.new_access_token(&request.account_number)?;
+            Ok(json_response(StatusCode::OK, &token))
+        }
+
+        (&Method::POST, ["accounts", "v1", "accounts"]) => {
+            let number = state.create_account(Utc::now());
+            log::info!("Created account {}", number);
+            Ok(json_response(
+                StatusCode::CREATED,
+                &serde_json::json!({ "number": number }),
+            ))
+        }
+        (&Method::GET, ["accounts", "v1", "accounts", "me"]) => {
+            let number = state.authenticate(access_token(headers)?)?;
+            let expiry = state
+                .account(&number)
+                .ok_or(ApiError::InvalidAccount)?
+                .expiry;
+            Ok(json_response(
+                StatusCode::OK,
+                &serde_json::json!({ "expiry": expiry }),
+            ))
+        }
+
+        (&Method::GET, ["accounts", "v1", "devices"]) => {
+            let number = state.authenticate(access_token(headers)?)?;
+            let account = state.account(&number).ok_or(ApiError::InvalidAccount)?;
+            Ok(json_response(StatusCode::OK, &account.devices))
+        }
+        (&Method::POST, ["accounts", "v1", "devices"]) => {
+            #[derive(serde::Deserialize)]
+            struct DeviceSubmission {
+                pubkey: PublicKey,
+                #[serde(default)]
+                hijack_dns: bool,
+            }
+            let number = state.authenticate(access_token(headers)?)?;
+            let submission: DeviceSubmission = parse_json(&body)?;
+            let device = state.create_device(&number, submission.pubkey, submission.hijack_dns)?;
+            log::info!("Created device {:?} on {}", device.device.name, number);
+            Ok(json_response(StatusCode::CREATED, &device))
+        }
+        (&Method::GET, ["accounts", "v1", "devices", id]) => {
+            let number = state.authenticate(access_token(headers)?)?;
+            let device = state.device(&number, &id.to_string())?;
+            Ok(json_response(StatusCode::OK, device))
+        }
+        (&Method::DELETE, ["accounts", "v1", "devices", id]) => {
+            let number = state.authenticate(access_token(headers)?)?;
+            state.remove_device(&number, &id.to_string())?;
+            Ok(empty_response(StatusCode::NO_CONTENT))
+        }
+        (&Method::PUT, ["accounts", "v1", "devices", id, "pubkey"]) => {
+            #[derive(serde::Deserialize)]
+            struct RotateDevicePubkey {
+                pubkey: PublicKey,
+            }
+            let number = state.authenticate(access_token(headers)?)?;
+            let request: RotateDevicePubkey = parse_json(&body)?;
+            let device = state.replace_pubkey(&number, &id.to_string(), request.pubkey)?;
+            Ok(json_response(StatusCode::OK, &device))
+        }
+
+        (&Method::POST, ["app", "v1", "submit-voucher"]) => {
+            #[derive(serde::Deserialize)]
+            struct VoucherSubmission {
+                voucher_code: String,
+            }
+            let number = state.authenticate(access_token(headers)?)?;
+            let request: VoucherSubmission = parse_json(&body)?;
+            let submission = state.submit_voucher(&number, &request.voucher_code)?;
+            Ok(json_response(StatusCode::OK, &submission))
+        }
+        (&Method::POST, ["app", "v1", "www-auth-token"]) => {
+            state.authenticate(access_token(headers)?)?;
+            Ok(json_response(
+                StatusCode::OK,
+                &serde_json::json!({ "auth_token": uuid::Uuid::new_v4().to_simple().to_string() }),
+            ))
+        }
+        (&Method::POST, ["app", "v1", "problem-report"]) => {
+            let report: ProblemReport = parse_json(&body)?;
+            state.add_problem_report(report);
+            Ok(empty_response(StatusCode::NO_CONTENT))
+        }
+
+        (&Method::GET, ["app", "v1", "relays"]) => {
+            let relay_list = &context.relay_list;
+            let up_to_date = headers
+                .get(header::IF_NONE_MATCH)
+                .and_then(|etag| etag.to_str().ok())
+                .map(|etag| relay_list.matches_etag(etag))
+                .unwrap_or(false);
+            if up_to_date {
+                return Ok(empty_response(StatusCode::NOT_MODIFIED));
+            }
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::ETAG, relay_list.etag())
+                .body(Body::from(relay_list.body().to_vec()))
+                .unwrap())
+        }
+        (&Method::GET, ["app", "v1", "api-addrs"]) => {
+            Ok(json_response(StatusCode::OK, &[context.local_addr]))
+        }
+        (&Method::GET, ["app", "v1", "releases", _platform, version]) => {
+            // Whatever version is asked about is the latest one
+            Ok(json_response(
+                StatusCode::OK,
+                &serde_json::json!({
+                    "supported": true,
+                    "latest": version,
+                    "latest_stable": version,
+                    "latest_beta": version,
+                }),
+            ))
+        }
+
+        _ => Err(Error::NotFound),
+    }
+}
+
+/// Returns the access token from the `Authorization` header.
+fn access_token(headers: &HeaderMap) -> Result<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(Error::Api(ApiError::InvalidAccessToken))
+}
+
+fn parse_json<T: DeserializeOwned>(body: &[u8]) -> Result<T> {
+    serde_json::from_slice(body).map_err(|error| {
+        log::warn!("Invalid request body: {}", error);
+        Error::BadRequest
+    })
+}
+
+fn json_response<T: Serialize + ?Sized>(status: StatusCode, body: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(body).unwrap()))
+        .unwrap()
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
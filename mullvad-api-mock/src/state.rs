@@ -0,0 +1,387 @@
+//! Accounts, devices and everything else that the mock API keeps track of. Nothing is persisted.
+
+use chrono::{DateTime, Duration, Utc};
+use ipnetwork::{Ipv4Network, Ipv6Network};
+use mullvad_api::ApiError;
+use mullvad_types::{
+    account::{AccessToken, AccessTokenData, AccountToken, VoucherSubmission},
+    device::{Device, DeviceId, DevicePort},
+};
+use rand::{seq::SliceRandom, Rng};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{Ipv4Addr, Ipv6Addr},
+};
+use talpid_types::net::wireguard::PublicKey;
+
+/// Number of devices that an account may have.
+pub const MAX_DEVICES: usize = 5;
+
+/// How long an access token may be used before a new one must be requested.
+const ACCESS_TOKEN_LIFETIME_HOURS: i64 = 1;
+
+const DEVICE_ADJECTIVES: &[&str] = &["Agile", "Brave", "Calm", "Eager", "Gentle", "Lucky"];
+const DEVICE_ANIMALS: &[&str] = &["Badger", "Heron", "Lynx", "Otter", "Panda", "Walrus"];
+
+/// A device as it is returned by the API, including its tunnel addresses.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MockDevice {
+    #[serde(flatten)]
+    pub device: Device,
+    pub ipv4_address: Ipv4Network,
+    pub ipv6_address: Ipv6Network,
+}
+
+#[derive(Debug)]
+pub struct Account {
+    pub expiry: DateTime<Utc>,
+    pub devices: Vec<MockDevice>,
+}
+
+/// A problem report that has been submitted to the mock API.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ProblemReport {
+    pub address: String,
+    pub message: String,
+    pub log: String,
+    pub metadata: BTreeMap<String, String>,
+}
+
+struct Voucher {
+    time_added: Duration,
+    used: bool,
+}
+
+#[derive(Default)]
+pub struct State {
+    accounts: HashMap<AccountToken, Account>,
+    access_tokens: HashMap<AccessToken, (AccountToken, DateTime<Utc>)>,
+    vouchers: HashMap<String, Voucher>,
+    problem_reports: Vec<ProblemReport>,
+    /// Used to give every device unique tunnel addresses.
+    allocated_addresses: u16,
+}
+
+impl State {
+    /// Creates an account with a random number.
+    pub fn create_account(&mut self, expiry: DateTime<Utc>) -> AccountToken {
+        let mut rng = rand::thread_rng();
+        loop {
+            let number: String = (0..16)
+                .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+                .collect();
+            if !self.accounts.contains_key(&number) {
+                self.add_account(number.clone(), expiry);
+                return number;
+            }
+        }
+    }
+
+    /// Adds an account with a known number, replacing any existing account with that number.
+    pub fn add_account(&mut self, number: AccountToken, expiry: DateTime<Utc>) {
+        self.accounts.insert(
+            number,
+            Account {
+                expiry,
+                devices: vec![],
+            },
+        );
+    }
+
+    pub fn account(&self, number: &str) -> Option<&Account> {
+        self.accounts.get(number)
+    }
+
+    pub fn set_expiry(&mut self, number: &str, expiry: DateTime<Utc>) -> Result<(), ApiError> {
+        self.account_mut(number)?.expiry = expiry;
+        Ok(())
+    }
+
+    /// Makes `code` a valid voucher that adds `time_added` to an account once.
+    pub fn add_voucher(&mut self, code: String, time_added: Duration) {
+        self.vouchers.insert(
+            code,
+            Voucher {
+                time_added,
+                used: false,
+            },
+        );
+    }
+
+    pub fn problem_reports(&self) -> &[ProblemReport] {
+        &self.problem_reports
+    }
+
+    pub fn add_problem_report(&mut self, report: ProblemReport) {
+        log::info!("Received problem report from {:?}", report.address);
+        self.problem_reports.push(report);
+    }
+
+    pub fn new_access_token(&mut self, number: &str) -> Result<AccessTokenData, ApiError> {
+        self.account_mut(number)?;
+        let access_token = format!("mock_at_{}", uuid::Uuid::new_v4().to_simple());
+        let expiry = Utc::now() + Duration::hours(ACCESS_TOKEN_LIFETIME_HOURS);
+        self.access_tokens
+            .insert(access_token.clone(), (number.to_owned(), expiry));
+        Ok(AccessTokenData {
+            access_token,
+            expiry,
+        })
+    }
+
+    /// Returns the account that an access token belongs to.
+    pub fn authenticate(&self, access_token: &str) -> Result<AccountToken, ApiError> {
+        match self.access_tokens.get(access_token) {
+            Some((number, expiry)) if *expiry > Utc::now() => Ok(number.clone()),
+            _ => Err(ApiError::InvalidAccessToken),
+        }
+    }
+
+    pub fn submit_voucher(
+        &mut self,
+        number: &str,
+        code: &str,
+    ) -> Result<VoucherSubmission, ApiError> {
+        self.account_mut(number)?;
+        let voucher = self
+            .vouchers
+            .get_mut(code)
+            .ok_or(ApiError::InvalidVoucher)?;
+        if voucher.used {
+            return Err(ApiError::VoucherUsed);
+        }
+        voucher.used = true;
+        let time_added = voucher.time_added;
+
+        let account = self.account_mut(number)?;
+        account.expiry = std::cmp::max(account.expiry, Utc::now()) + time_added;
+        Ok(VoucherSubmission {
+            time_added: time_added.num_seconds() as u64,
+            new_expiry: account.expiry,
+        })
+    }
+
+    pub fn create_device(
+        &mut self,
+        number: &str,
+        pubkey: PublicKey,
+        hijack_dns: bool,
+    ) -> Result<MockDevice, ApiError> {
+        if self.device_with_pubkey(&pubkey).is_some() {
+            return Err(ApiError::PubkeyInUse);
+        }
+        if self.account_mut(number)?.devices.len() >= MAX_DEVICES {
+            return Err(ApiError::MaxDevicesReached);
+        }
+
+        let (ipv4_address, ipv6_address) = self.allocate_addresses();
+        let device = MockDevice {
+            device: Device {
+                id: uuid::Uuid::new_v4().to_string(),
+                name: self.unused_device_name(number),
+                pubkey,
+                ports: vec![DevicePort {
+                    id: "mock".to_owned(),
+                }],
+                hijack_dns,
+                created: Utc::now(),
+            },
+            ipv4_address,
+            ipv6_address,
+        };
+        self.account_mut(number)?.devices.push(device.clone());
+        Ok(device)
+    }
+
+    pub fn device(&self, number: &str, id: &DeviceId) -> Result<&MockDevice, ApiError> {
+        self.accounts
+            .get(number)
+            .ok_or(ApiError::InvalidAccount)?
+            .devices
+            .iter()
+            .find(|device| &device.device.id == id)
+            .ok_or(ApiError::DeviceNotFound)
+    }
+
+    pub fn remove_device(&mut self, number: &str, id: &DeviceId) -> Result<(), ApiError> {
+        let devices = &mut self.account_mut(number)?.devices;
+        let len = devices.len();
+        devices.retain(|device| &device.device.id != id);
+        if devices.len() == len {
+            return Err(ApiError::DeviceNotFound);
+        }
+        Ok(())
+    }
+
+    pub fn replace_pubkey(
+        &mut self,
+        number: &str,
+        id: &DeviceId,
+        pubkey: PublicKey,
+    ) -> Result<MockDevice, ApiError> {
+        if let Some(device) = self.device_with_pubkey(&pubkey) {
+            if &device.device.id != id {
+                return Err(ApiError::PubkeyInUse);
+            }
+        }
+        let device = self
+            .account_mut(number)?
+            .devices
+            .iter_mut()
+            .find(|device| &device.device.id == id)
+            .ok_or(ApiError::DeviceNotFound)?;
+        device.device.pubkey = pubkey;
+        Ok(device.clone())
+    }
+
+    fn account_mut(&mut self, number: &str) -> Result<&mut Account, ApiError> {
+        self.accounts
+            .get_mut(number)
+            .ok_or(ApiError::InvalidAccount)
+    }
+
+    fn device_with_pubkey(&self, pubkey: &PublicKey) -> Option<&MockDevice> {
+        self.accounts
+            .values()
+            .flat_map(|account| account.devices.iter())
+            .find(|device| &device.device.pubkey == pubkey)
+    }
+
+    fn unused_device_name(&self, number: &str) -> String {
+        let mut rng = rand::thread_rng();
+        let taken = |name: &str| {
+            self.accounts
+                .get(number)
+                .map(|account| account.devices.iter().any(|d| d.device.name == name))
+                .unwrap_or(false)
+        };
+        loop {
+            let name = format!(
+                "{} {}",
+                DEVICE_ADJECTIVES.choose(&mut rng).unwrap(),
+                DEVICE_ANIMALS.choose(&mut rng).unwrap()
+            );
+            if !taken(&name) {
+                return name;
+            }
+        }
+    }
+
+    fn allocate_addresses(&mut self) -> (Ipv4Network, Ipv6Network) {
+        self.allocated_addresses = self.allocated_addresses.wrapping_add(1);
+        let [high, low] = self.allocated_addresses.to_be_bytes();
+        let ipv4 = Ipv4Addr::new(10, 64, high, low);
+        let ipv6 = Ipv6Addr::new(
+            0xfc00,
+            0xbbbb,
+            0xbbbb,
+            0xbb01,
+            0,
+            0,
+            0,
+            self.allocated_addresses,
+        );
+        (
+            Ipv4Network::new(ipv4, 32).unwrap(),
+            Ipv6Network::new(ipv6, 128).unwrap(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn new_pubkey(byte: u8) -> PublicKey {
+        PublicKey::from([byte; 32])
+    }
+
+    #[test]
+    fn test_device_limit() {
+        let mut state = State::default();
+        let account = state.create_account(Utc::now());
+
+        for i in 0..MAX_DEVICES {
+            state
+                .create_device(&account, new_pubkey(i as u8), false)
+                .unwrap();
+        }
+        assert!(matches!(
+            state.create_device(&account, new_pubkey(0xff), false),
+            Err(ApiError::MaxDevicesReached)
+        ));
+
+        let id = state.account(&account).unwrap().devices[0]
+            .device
+            .id
+            .clone();
+        state.remove_device(&account, &id).unwrap();
+        assert!(matches!(
+            state.device(&account, &id),
+            Err(ApiError::DeviceNotFound)
+        ));
+        state
+            .create_device(&account, new_pubkey(0xff), false)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_pubkey_in_use() {
+        let mut state = State::default();
+        let first_account = state.create_account(Utc::now());
+        let second_account = state.create_account(Utc::now());
+
+        let device = state
+            .create_device(&first_account, new_pubkey(1), false)
+            .unwrap();
+        assert!(matches!(
+            state.create_device(&second_account, new_pubkey(1), false),
+            Err(ApiError::PubkeyInUse)
+        ));
+
+        let other_device = state
+            .create_device(&second_account, new_pubkey(2), false)
+            .unwrap();
+        assert!(matches!(
+            state.replace_pubkey(&second_account, &other_device.device.id, new_pubkey(1)),
+            Err(ApiError::PubkeyInUse)
+        ));
+        assert_ne!(device.ipv4_address, other_device.ipv4_address);
+    }
+
+    #[test]
+    fn test_voucher() {
+        let mut state = State::default();
+        let account = state.create_account(Utc::now() - Duration::days(1));
+        state.add_voucher("VOUCHER".to_owned(), Duration::days(30));
+
+        assert!(matches!(
+            state.submit_voucher(&account, "INVALID"),
+            Err(ApiError::InvalidVoucher)
+        ));
+        let submission = state.submit_voucher(&account, "VOUCHER").unwrap();
+        assert_eq!(submission.time_added, 30 * 24 * 60 * 60);
+        assert!(submission.new_expiry > Utc::now() + Duration::days(29));
+        assert!(matches!(
+            state.submit_voucher(&account, "VOUCHER"),
+            Err(ApiError::VoucherUsed)
+        ));
+    }
+
+    #[test]
+    fn test_access_token() {
+        let mut state = State::default();
+        let account = state.create_account(Utc::now());
+
+        assert!(matches!(
+            state.new_access_token("0000000000000000"),
+            Err(ApiError::InvalidAccount)
+        ));
+        let token = state.new_access_token(&account).unwrap();
+        assert_eq!(state.authenticate(&token.access_token).unwrap(), account);
+        assert!(matches!(
+            state.authenticate("mock_at_invalid"),
+            Err(ApiError::InvalidAccessToken)
+        ));
+    }
+}
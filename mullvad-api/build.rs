@@ -0,0 +1,27 @@
+use std::env;
+
+const SIGNING_KEY_VAR: &str = "MULLVAD_RELAY_LIST_SIGNING_KEY";
+
+fn main() {
+    println!("cargo:rerun-if-env-changed={SIGNING_KEY_VAR}");
+
+    match env::var(SIGNING_KEY_VAR) {
+        Ok(key) => {
+            // An Ed25519 public key is 32 bytes
+            if key.len() != 64 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+                panic!("{SIGNING_KEY_VAR} must be a hex-encoded Ed25519 public key");
+            }
+        }
+        Err(_) => {
+            // Only builds that can be pointed at a test API may do without a key. Every relay
+            // list would be rejected otherwise.
+            let api_override = env::var_os("CARGO_FEATURE_API_OVERRIDE").is_some();
+            if !api_override && env::var("PROFILE").as_deref() == Ok("release") {
+                panic!(
+                    "{SIGNING_KEY_VAR} must be set for release builds without the api-override \
+                     feature"
+                );
+            }
+        }
+    }
+}
@@ -50,11 +50,11 @@ impl AccessTokenProxy {
 
     /// Remove an access token if the API response calls for it.
     pub fn check_response<T>(&self, account: &AccessToken, response: &Result<T, rest::Error>) {
-        if let Err(rest::Error::ApiError(_status, code)) = response {
-            if code == crate::INVALID_ACCESS_TOKEN {
-                log::debug!("Dropping invalid access token");
-                self.remove_token(account);
-            }
+        if let Err(rest::Error::ApiError(_status, crate::ApiError::InvalidAccessToken, _)) =
+            response
+        {
+            log::debug!("Dropping invalid access token");
+            self.remove_token(account);
         }
     }
 
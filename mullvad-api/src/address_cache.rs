@@ -1,10 +1,8 @@
 use super::API;
+use crate::cache_integrity;
+use mullvad_types::cache::CorruptionReason;
 use std::{io, net::SocketAddr, path::Path, sync::Arc};
-use tokio::{
-    fs,
-    io::{AsyncReadExt, AsyncWriteExt},
-    sync::Mutex,
-};
+use tokio::sync::Mutex;
 
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -80,15 +78,11 @@ impl AddressCache {
             None => return Ok(()),
         };
 
-        let mut file = crate::fs::AtomicFile::new(write_path.to_path_buf())
-            .await
-            .map_err(Error::Open)?;
         let mut contents = address.to_string();
         contents += "\n";
-        file.write_all(contents.as_bytes())
+        cache_integrity::write(write_path, contents.as_bytes())
             .await
-            .map_err(Error::Write)?;
-        file.finalize().await.map_err(Error::Write)
+            .map_err(Error::Write)
     }
 }
 
@@ -104,10 +98,20 @@ impl AddressCacheInner {
 }
 
 async fn read_address_file(path: &Path) -> Result<SocketAddr, Error> {
-    let mut file = fs::File::open(path).await.map_err(Error::Open)?;
-    let mut address = String::new();
-    file.read_to_string(&mut address)
+    let contents = cache_integrity::read(path)
         .await
-        .map_err(Error::Read)?;
-    address.trim().parse().map_err(|_| Error::Parse)
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::InvalidData => Error::Read(error),
+            _ => Error::Open(error),
+        })?;
+    let address = std::str::from_utf8(&contents)
+        .ok()
+        .and_then(|address| address.trim().parse().ok());
+    match address {
+        Some(address) => Ok(address),
+        None => {
+            cache_integrity::quarantine(path, contents, CorruptionReason::ParseError).await;
+            Err(Error::Parse)
+        }
+    }
 }
@@ -24,6 +24,7 @@ pub struct State {
     pause_background: bool,
     offline: bool,
     inactive: bool,
+    metered: bool,
 }
 
 impl State {
@@ -35,6 +36,13 @@ impl State {
         self.offline || self.pause_background || self.suspended || self.inactive
     }
 
+    /// Returns whether background requests that can be postponed, such as scheduled downloads,
+    /// should wait. This is the case when background requests are paused or the current network
+    /// should not be used for them.
+    pub fn is_deferrable_paused(&self) -> bool {
+        self.is_background_paused() || self.metered
+    }
+
     pub fn is_offline(&self) -> bool {
         self.offline
     }
@@ -200,6 +208,21 @@ impl ApiAvailabilityHandle {
         }
     }
 
+    /// Sets whether the current network should be avoided for deferrable background requests.
+    pub fn set_metered(&self, metered: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.metered != metered {
+            if metered {
+                log::debug!("Deferring scheduled API requests on the current network");
+            } else {
+                log::debug!("Resuming scheduled API requests on the current network");
+            }
+
+            state.metered = metered;
+            let _ = self.tx.send(*state);
+        }
+    }
+
     pub fn get_state(&self) -> State {
         *self.state.lock().unwrap()
     }
@@ -220,6 +243,10 @@ impl ApiAvailabilityHandle {
         self.wait_for_state(|state| !state.is_background_paused())
     }
 
+    pub fn wait_deferrable(&self) -> impl Future<Output = Result<(), Error>> {
+        self.wait_for_state(|state| !state.is_deferrable_paused())
+    }
+
     pub fn when_online<F: Future<Output = O>, O>(&self, task: F) -> impl Future<Output = O> {
         let wait_task = self.wait_for_state(|state| !state.is_offline());
         async move {
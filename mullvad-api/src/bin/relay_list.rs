@@ -2,7 +2,9 @@
 //! Used by the installer artifact packer to bundle the latest available
 //! relay list at the time of creating the installer.
 
-use mullvad_api::{self, proxy::ApiConnectionMode, rest::Error as RestError, RelayListProxy};
+use mullvad_api::{
+    self, proxy::ApiConnectionMode, rest::Error as RestError, RelayListKeys, RelayListProxy,
+};
 use std::process;
 use talpid_types::ErrorExt;
 
@@ -15,6 +17,7 @@ async fn main() {
         runtime
             .mullvad_rest_handle(ApiConnectionMode::Direct.into_repeat(), |_| async { true })
             .await,
+        RelayListKeys::embedded(),
     )
     .relay_list(None)
     .await;
@@ -32,6 +35,10 @@ async fn main() {
             );
             process::exit(3);
         }
+        Err(e @ RestError::SignatureError(_)) => {
+            eprintln!("{}", e.display_chain_with_msg("Failed to verify relay list"));
+            process::exit(4);
+        }
         Err(e) => {
             eprintln!("{}", e.display_chain_with_msg("Failed to fetch relay list"));
             process::exit(1);
@@ -0,0 +1,344 @@
+//! Integrity checks for cache files. A SHA-256 checksum is stored next to every cache file. Files
+//! that fail verification, or that cannot be parsed, are copied to a quarantine directory and
+//! removed, so that the cache is regenerated and the corrupt data can still be inspected.
+//!
+//! Quarantined files are recorded until they are collected using [`take_quarantined`].
+
+use memmap2::Mmap;
+use mullvad_types::cache::{CorruptionReason, QuarantinedFile};
+use once_cell::sync::Lazy;
+use std::{
+    fmt::Write as _,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use talpid_types::ErrorExt;
+use tokio::io::AsyncWriteExt;
+
+/// Name of the directory, next to the cache file, that corrupt files are moved to.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+const CHECKSUM_EXTENSION: &str = "sha256";
+
+static QUARANTINED: Lazy<Mutex<Vec<QuarantinedFile>>> = Lazy::new(|| Mutex::new(vec![]));
+
+/// Returns the files that have been quarantined since the last call.
+pub fn take_quarantined() -> Vec<QuarantinedFile> {
+    std::mem::take(&mut *QUARANTINED.lock().unwrap())
+}
+
+/// Reads a cache file and verifies it against its checksum. If the checksum does not match, the
+/// file is quarantined and an error of kind [`io::ErrorKind::InvalidData`] is returned.
+pub async fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || read_blocking(&path))
+        .await
+        .unwrap_or_else(|error| Err(io::Error::new(io::ErrorKind::Other, error)))
+}
+
+/// Blocking version of [`read`].
+pub fn read_blocking(path: &Path) -> io::Result<Vec<u8>> {
+    let contents = std::fs::read(path)?;
+    if !verify(path, &contents) {
+        quarantine_blocking(path, &contents, CorruptionReason::ChecksumMismatch);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Cache file does not match its checksum",
+        ));
+    }
+    Ok(contents)
+}
+
+/// Like [`read_blocking`], but maps the file into memory instead of reading it, so that large
+/// files such as the relay list are not copied to the heap. Callers must drop the mapping before
+/// quarantining the file, since mapped files cannot be removed on Windows.
+pub fn map_blocking(path: &Path) -> io::Result<Mmap> {
+    let file = std::fs::File::open(path)?;
+    // SAFETY: Cache files are never modified in place. `write` replaces them by renaming a new
+    // file over them, which leaves the mapped file intact.
+    let contents = unsafe { Mmap::map(&file)? };
+    if !verify(path, &contents) {
+        let copy = contents.to_vec();
+        drop(contents);
+        quarantine_blocking(path, &copy, CorruptionReason::ChecksumMismatch);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Cache file does not match its checksum",
+        ));
+    }
+    Ok(contents)
+}
+
+/// Returns whether `contents` match the checksum stored for `path`. Files without a checksum,
+/// such as those written by older versions, are assumed to be intact.
+pub fn verify(path: &Path, contents: &[u8]) -> bool {
+    match std::fs::read_to_string(checksum_path(path)) {
+        Ok(expected) => expected.trim() == checksum(contents),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => true,
+        Err(error) => {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg(&format!(
+                    "Failed to read checksum for {}",
+                    path.display()
+                ))
+            );
+            false
+        }
+    }
+}
+
+/// Verifies every file in `dir` that has a checksum, and quarantines those that do not match it.
+/// This is done after an unclean exit, so that all corrupt files are found and reported at once,
+/// instead of when they are first read.
+pub async fn verify_dir(dir: &Path) {
+    let dir = dir.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || -> io::Result<()> {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if !path.is_file() || !checksum_path(&path).exists() {
+                continue;
+            }
+            match read_blocking(&path) {
+                Ok(_) => (),
+                Err(error) if error.kind() == io::ErrorKind::InvalidData => (),
+                Err(error) => log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!("Failed to verify {}", path.display()))
+                ),
+            }
+        }
+        Ok(())
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => (),
+        Ok(Err(error)) => log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to verify the cache directory")
+        ),
+        Err(error) => log::error!("Failed to verify the cache directory: {}", error),
+    }
+}
+
+/// Atomically replaces the cache file at `path` with `contents`, and stores their checksum.
+pub async fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    remove_checksum(path).await?;
+    let mut file = crate::fs::AtomicFile::new(path.to_path_buf()).await?;
+    file.write_all(contents).await?;
+    file.finalize().await?;
+    write_checksum(path, contents).await
+}
+
+/// Removes the checksum of the cache file at `path`. This must be done before the file is
+/// modified, so that a crash before [`write_checksum`] completes does not leave a valid file with
+/// an outdated checksum. Files without a checksum are not verified.
+pub async fn remove_checksum(path: &Path) -> io::Result<()> {
+    match tokio::fs::remove_file(checksum_path(path)).await {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/// Stores the checksum of `contents`, which must be the new contents of the cache file at `path`.
+/// See [`remove_checksum`].
+pub async fn write_checksum(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = crate::fs::AtomicFile::new(checksum_path(path)).await?;
+    file.write_all(checksum(contents).as_bytes()).await?;
+    file.finalize().await
+}
+
+/// Saves a copy of a corrupt cache file in the quarantine directory, and removes the original
+/// along with its checksum.
+pub async fn quarantine(path: &Path, contents: Vec<u8>, reason: CorruptionReason) {
+    let path = path.to_path_buf();
+    if let Err(error) =
+        tokio::task::spawn_blocking(move || quarantine_blocking(&path, &contents, reason)).await
+    {
+        log::error!("Failed to quarantine cache file: {}", error);
+    }
+}
+
+/// Blocking version of [`quarantine`].
+pub fn quarantine_blocking(path: &Path, contents: &[u8], reason: CorruptionReason) {
+    log::error!("Quarantining corrupt cache file {}", path.display());
+
+    let quarantine_path = match save_copy(path, contents) {
+        Ok(quarantine_path) => Some(quarantine_path),
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to save a copy of the corrupt file")
+            );
+            None
+        }
+    };
+    for path in [path.to_path_buf(), checksum_path(path)] {
+        if let Err(error) = std::fs::remove_file(&path) {
+            if error.kind() != io::ErrorKind::NotFound {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!("Failed to remove {}", path.display()))
+                );
+            }
+        }
+    }
+
+    QUARANTINED.lock().unwrap().push(QuarantinedFile {
+        path: path.to_path_buf(),
+        quarantine_path,
+        reason,
+    });
+}
+
+/// Copies `contents` to the quarantine directory. Cache files may contain account data, so the
+/// copy and the directory are only accessible by their owner.
+fn save_copy(path: &Path, contents: &[u8]) -> io::Result<PathBuf> {
+    let parent = path.parent().unwrap_or_else(|| Path::new("."));
+    let quarantine_dir = parent.join(QUARANTINE_DIR);
+    let mut dir_builder = std::fs::DirBuilder::new();
+    dir_builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        dir_builder.mode(0o700);
+    }
+    dir_builder.create(&quarantine_dir)?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".{}", timestamp));
+
+    let quarantine_path = quarantine_dir.join(file_name);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&quarantine_path)?.write_all(contents)?;
+    Ok(quarantine_path)
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(CHECKSUM_EXTENSION);
+    path.with_file_name(file_name)
+}
+
+fn checksum(contents: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, contents);
+    let mut hex = String::with_capacity(2 * digest.as_ref().len());
+    for byte in digest.as_ref() {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_quarantine_on_checksum_mismatch() {
+        let dir = std::env::temp_dir().join(format!("cache-integrity-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache.json");
+
+        std::fs::write(&path, b"intact").unwrap();
+        std::fs::write(checksum_path(&path), checksum(b"intact")).unwrap();
+        assert_eq!(read_blocking(&path).unwrap(), b"intact");
+
+        std::fs::write(&path, b"corrupt").unwrap();
+        let error = read_blocking(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(!path.exists());
+        assert!(!checksum_path(&path).exists());
+
+        let quarantined = take_quarantined();
+        let entry = quarantined
+            .iter()
+            .find(|entry| entry.path == path)
+            .expect("file was not recorded as quarantined");
+        assert_eq!(entry.reason, CorruptionReason::ChecksumMismatch);
+        let copy = entry.quarantine_path.as_ref().unwrap();
+        assert_eq!(std::fs::read(copy).unwrap(), b"corrupt");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(copy), 0o600);
+            assert_eq!(mode(copy.parent().unwrap()), 0o700);
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_map_verifies_checksum() {
+        let dir = std::env::temp_dir().join(format!("cache-integrity-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache.json");
+
+        write(&path, b"intact").await.unwrap();
+        assert_eq!(&map_blocking(&path).unwrap()[..], b"intact");
+
+        std::fs::write(&path, b"corrupt").unwrap();
+        let error = map_blocking(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(!path.exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_write_without_checksum_is_intact() {
+        let dir = std::env::temp_dir().join(format!("cache-integrity-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache.json");
+
+        write(&path, b"old").await.unwrap();
+        assert_eq!(read_blocking(&path).unwrap(), b"old");
+
+        // Simulate a crash after the new contents were written but before the checksum was
+        // written.
+        remove_checksum(&path).await.unwrap();
+        std::fs::write(&path, b"new").unwrap();
+        assert_eq!(read_blocking(&path).unwrap(), b"new");
+
+        write(&path, b"newer").await.unwrap();
+        assert_eq!(read_blocking(&path).unwrap(), b"newer");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_verify_dir() {
+        let dir = std::env::temp_dir().join(format!("cache-integrity-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let intact = dir.join("intact.json");
+        let corrupt = dir.join("corrupt.json");
+        let unchecked = dir.join("unchecked.json");
+
+        write(&intact, b"intact").await.unwrap();
+        write(&corrupt, b"intact").await.unwrap();
+        std::fs::write(&corrupt, b"corrupt").unwrap();
+        std::fs::write(&unchecked, b"unchecked").unwrap();
+
+        verify_dir(&dir).await;
+
+        assert!(intact.exists());
+        assert!(!corrupt.exists());
+        assert!(unchecked.exists());
+        assert!(take_quarantined().iter().any(|entry| entry.path == corrupt));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
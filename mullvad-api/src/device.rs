@@ -118,6 +118,22 @@ impl DevicesProxy {
         }
     }
 
+    /// Fetches the device unless it matches `validators`.
+    pub fn get_if_modified(
+        &self,
+        account: AccountToken,
+        id: DeviceId,
+        validators: rest::CacheValidators,
+    ) -> impl Future<Output = Result<rest::ConditionalResponse<Device>, rest::Error>> {
+        rest::send_conditional_request(
+            &self.handle.factory,
+            self.handle.service.clone(),
+            &format!("{}/devices/{}", ACCOUNTS_URL_PREFIX, id),
+            Some((self.handle.token_store.clone(), account)),
+            validators,
+        )
+    }
+
     pub fn list(
         &self,
         account: AccountToken,
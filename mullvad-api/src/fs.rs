@@ -8,6 +8,8 @@ use tokio::{fs, io};
 /// Stores content in a temporary file before moving it to the
 /// final destination, ensuring that consumers of the file never
 /// end up with partial content. Must be moved with `finalize`.
+///
+/// On Unix, the file is only readable and writable by its owner.
 pub struct AtomicFile {
     file: Option<fs::File>,
     temp_path: PathBuf,
@@ -17,8 +19,12 @@ pub struct AtomicFile {
 impl AtomicFile {
     pub async fn new(target_path: PathBuf) -> io::Result<Self> {
         let temp_path = target_path.with_file_name(uuid::Uuid::new_v4().to_string());
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
         Ok(Self {
-            file: Some(fs::File::create(&temp_path).await?),
+            file: Some(options.open(&temp_path).await?),
             temp_path,
             target_path,
         })
@@ -0,0 +1,583 @@
+//! Sends API requests over HTTP/3 (QUIC) when the API is reached directly. QUIC often gets
+//! through deep packet inspection that interferes with TLS over TCP, and recovers faster from
+//! packet loss.
+//!
+//! Requests fall back on the regular TCP client if HTTP/3 fails, and HTTP/3 is then not tried
+//! again for a while. Note that the firewall only allows TCP traffic to the API in the states
+//! where it blocks other traffic, such as while connecting, so requests made then use TCP.
+//!
+//! HTTP/3 is also the only way to reach the API through Shadowsocks proxies that relay UDP. The
+//! QUIC connection is then made to a local socket whose datagrams are relayed through the proxy,
+//! and there is no fallback.
+
+#[cfg(target_os = "android")]
+use crate::https_client_with_sni::SocketBypassRequest;
+use crate::{
+    address_cache::AddressCache,
+    https_client_with_sni::HttpsConnectorWithSni,
+    rest::{self, Request, Response},
+};
+use bytes::{BufMut, Bytes};
+use futures::future;
+#[cfg(target_os = "android")]
+use futures::{
+    channel::{mpsc, oneshot},
+    SinkExt,
+};
+use h3::client::SendRequest;
+use hyper::{client::Client, header};
+use once_cell::sync::Lazy;
+use shadowsocks::{
+    config::ServerType,
+    context::Context as SsContext,
+    crypto::v1::CipherKind,
+    relay::{
+        socks5::Address,
+        udprelay::{proxy_socket::UdpSocketType, ProxySocket},
+    },
+    ServerConfig,
+};
+#[cfg(target_os = "android")]
+use std::os::unix::io::AsRawFd;
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use talpid_types::{net::openvpn::ShadowsocksProxySettings, ErrorExt};
+use tokio::{net::UdpSocket, task::JoinHandle, time::timeout};
+use tokio_rustls::rustls;
+
+/// ALPN protocol identifier of HTTP/3.
+const ALPN_PROTOCOL: &[u8] = b"h3";
+
+/// How long to wait for the QUIC handshake. This is kept short, since the request is sent over
+/// TCP afterwards if it fails.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long to only use TCP after HTTP/3 has failed.
+const FAILURE_BACKOFF: Duration = Duration::from_secs(5 * 60);
+/// Largest datagram that is relayed through a Shadowsocks proxy.
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+static TLS_CONFIG: Lazy<Arc<rustls::ClientConfig>> = Lazy::new(|| {
+    let mut config = rustls::ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_root_certificates(crate::tls_stream::read_cert_store())
+        .with_no_client_auth();
+    config.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+    Arc::new(config)
+});
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "No address is known for the API")]
+    NoAddress,
+
+    #[error(display = "Failed to open UDP socket")]
+    Bind(#[error(source)] io::Error),
+
+    #[error(display = "Failed to start QUIC connection")]
+    Connect(#[error(source)] quinn::ConnectError),
+
+    #[error(display = "QUIC connection failed")]
+    Connection(#[error(source)] quinn::ConnectionError),
+
+    #[error(display = "Timed out waiting for QUIC handshake")]
+    HandshakeTimeout,
+
+    #[error(display = "HTTP/3 error")]
+    Http3(#[error(source)] h3::Error),
+
+    #[error(display = "Unsupported Shadowsocks cipher: {}", _0)]
+    InvalidCipher(String),
+
+    #[error(display = "Failed to connect to Shadowsocks proxy")]
+    ProxyConnect(#[error(source)] io::Error),
+}
+
+type Sender = SendRequest<h3_quinn::OpenStreams, Bytes>;
+
+struct Connection {
+    endpoint: quinn::Endpoint,
+    sender: Sender,
+    /// Set once the connection has been closed by either side.
+    closed: Arc<AtomicBool>,
+    /// Relays the datagrams of the connection through a proxy, if one is used.
+    _forwarder: Option<Forwarder>,
+}
+
+impl Connection {
+    fn close(&self) {
+        self.endpoint.close(quinn::VarInt::from_u32(0), b"");
+    }
+}
+
+#[derive(Default)]
+struct State {
+    connection: Option<Connection>,
+    last_failure: Option<Instant>,
+}
+
+/// HTTP/3 client for a single host, reusing one QUIC connection for all requests.
+#[derive(Clone)]
+pub(crate) struct Http3Client {
+    hostname: String,
+    address_cache: AddressCache,
+    /// Shadowsocks proxy that relays UDP, through which the host is reached.
+    proxy: Option<ShadowsocksProxySettings>,
+    state: Arc<Mutex<State>>,
+    #[cfg(target_os = "android")]
+    socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+}
+
+impl Http3Client {
+    /// Returns a client for `hostname`, or `None` if HTTP/3 cannot be used with the current API
+    /// configuration.
+    pub fn new(
+        hostname: String,
+        address_cache: AddressCache,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> Option<Self> {
+        #[cfg(feature = "api-override")]
+        if crate::API.disable_tls {
+            return None;
+        }
+        Some(Self {
+            hostname,
+            address_cache,
+            proxy: None,
+            state: Arc::new(Mutex::new(State::default())),
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
+        })
+    }
+
+    /// Returns a client for the same host that connects through `proxy`, which must relay UDP.
+    pub fn with_proxy(&self, proxy: ShadowsocksProxySettings) -> Self {
+        Self {
+            hostname: self.hostname.clone(),
+            address_cache: self.address_cache.clone(),
+            proxy: Some(proxy),
+            state: Arc::new(Mutex::new(State::default())),
+            #[cfg(target_os = "android")]
+            socket_bypass_tx: self.socket_bypass_tx.clone(),
+        }
+    }
+
+    pub fn proxy(&self) -> Option<&ShadowsocksProxySettings> {
+        self.proxy.as_ref()
+    }
+
+    /// Sends `request` over HTTP/3, or using `fallback` if HTTP/3 fails or has recently failed.
+    /// Without a fallback, HTTP/3 is always tried.
+    pub async fn request(
+        &self,
+        request: Request,
+        fallback: Option<Client<HttpsConnectorWithSni, hyper::Body>>,
+    ) -> rest::Result<Response> {
+        let fallback = match fallback {
+            Some(fallback) => fallback,
+            None => return self.request_without_fallback(request).await,
+        };
+        if !self.should_try(&request) {
+            return fallback.request(request).await.map_err(rest::Error::from);
+        }
+
+        // The body is needed again if the request is sent over TCP
+        let (parts, body) = request.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+
+        match self.send(http3_request(&parts), body.clone()).await {
+            Ok(response) => return Ok(response),
+            Err(error) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("HTTP/3 request failed. Retrying over TCP")
+                );
+                self.on_failure();
+            }
+        }
+
+        let request = hyper::Request::from_parts(parts, hyper::Body::from(body));
+        fallback.request(request).await.map_err(rest::Error::from)
+    }
+
+    async fn request_without_fallback(&self, request: Request) -> rest::Result<Response> {
+        if !self.is_host(&request) {
+            return Err(rest::Error::UdpProxyUnsupported);
+        }
+        let (parts, body) = request.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        self.send(http3_request(&parts), body)
+            .await
+            .map_err(|error| {
+                self.on_failure();
+                rest::Error::Http3Error(error)
+            })
+    }
+
+    /// Closes the QUIC connection, and allows HTTP/3 to be tried again immediately.
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(connection) = state.connection.take() {
+            connection.close();
+        }
+        state.last_failure = None;
+    }
+
+    fn is_host(&self, request: &Request) -> bool {
+        request
+            .uri()
+            .host()
+            .map(|host| host.eq_ignore_ascii_case(&self.hostname))
+            .unwrap_or(false)
+    }
+
+    fn should_try(&self, request: &Request) -> bool {
+        let backing_off = self
+            .state
+            .lock()
+            .unwrap()
+            .last_failure
+            .map(|last_failure| last_failure.elapsed() < FAILURE_BACKOFF)
+            .unwrap_or(false);
+        self.is_host(request) && !backing_off
+    }
+
+    fn on_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(connection) = state.connection.take() {
+            connection.close();
+        }
+        state.last_failure = Some(Instant::now());
+    }
+
+    async fn send(&self, request: http::Request<()>, body: Bytes) -> Result<Response, Error> {
+        let mut sender = self.sender().await?;
+        let mut stream = sender.send_request(request).await.map_err(Error::Http3)?;
+        if !body.is_empty() {
+            stream.send_data(body).await.map_err(Error::Http3)?;
+        }
+        stream.finish().await.map_err(Error::Http3)?;
+
+        let response = stream.recv_response().await.map_err(Error::Http3)?;
+        let mut body = vec![];
+        while let Some(chunk) = stream.recv_data().await.map_err(Error::Http3)? {
+            body.put(chunk);
+        }
+        let (parts, ()) = response.into_parts();
+        Ok(hyper::Response::from_parts(parts, hyper::Body::from(body)))
+    }
+
+    /// Returns a request sender for the current connection, connecting first if there is none.
+    async fn sender(&self) -> Result<Sender, Error> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(connection) = &state.connection {
+                if !connection.closed.load(Ordering::Acquire) {
+                    return Ok(connection.sender.clone());
+                }
+            }
+            state.connection = None;
+        }
+
+        let address = self
+            .address_cache
+            .resolve_hostname(&self.hostname)
+            .await
+            .ok_or(Error::NoAddress)?;
+        let connection = connect(
+            address,
+            &self.hostname,
+            self.proxy.as_ref(),
+            #[cfg(target_os = "android")]
+            self.socket_bypass_tx.clone(),
+        )
+        .await?;
+        let sender = connection.sender.clone();
+        if let Some(old_connection) = self.state.lock().unwrap().connection.replace(connection) {
+            old_connection.close();
+        }
+        Ok(sender)
+    }
+}
+
+/// Returns the request to send over HTTP/3 for a request with the given parts.
+fn http3_request(parts: &http::request::Parts) -> http::Request<()> {
+    let mut request = http::Request::new(());
+    *request.method_mut() = parts.method.clone();
+    *request.uri_mut() = parts.uri.clone();
+    *request.headers_mut() = parts.headers.clone();
+    // The host is given by the `:authority` pseudo-header
+    request.headers_mut().remove(header::HOST);
+    request
+}
+
+async fn connect(
+    address: SocketAddr,
+    hostname: &str,
+    proxy: Option<&ShadowsocksProxySettings>,
+    #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+) -> Result<Connection, Error> {
+    let (quic_address, forwarder) = match proxy {
+        Some(proxy) => {
+            let (local_address, forwarder) = forward_through_proxy(
+                proxy,
+                address,
+                #[cfg(target_os = "android")]
+                socket_bypass_tx.clone(),
+            )
+            .await?;
+            (local_address, Some(forwarder))
+        }
+        None => (address, None),
+    };
+    let bind_address = match quic_address {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = std::net::UdpSocket::bind(bind_address).map_err(Error::Bind)?;
+    // Datagrams to a local forwarder must not leave the device
+    #[cfg(target_os = "android")]
+    if let (None, Some(socket_bypass_tx)) = (forwarder.as_ref(), socket_bypass_tx) {
+        bypass_socket(&socket, socket_bypass_tx).await;
+    }
+    let endpoint = quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        None,
+        socket,
+        Arc::new(quinn::TokioRuntime),
+    )
+    .map_err(Error::Bind)?;
+    let connecting = endpoint
+        .connect_with(
+            quinn::ClientConfig::new(TLS_CONFIG.clone()),
+            quic_address,
+            hostname,
+        )
+        .map_err(Error::Connect)?;
+    let connection = timeout(HANDSHAKE_TIMEOUT, connecting)
+        .await
+        .map_err(|_| Error::HandshakeTimeout)?
+        .map_err(Error::Connection)?;
+
+    let (mut driver, sender) = h3::client::new(h3_quinn::Connection::new(connection))
+        .await
+        .map_err(Error::Http3)?;
+    let closed = Arc::new(AtomicBool::new(false));
+    let driver_closed = closed.clone();
+    tokio::spawn(async move {
+        if let Err(error) = future::poll_fn(|cx| driver.poll_close(cx)).await {
+            log::trace!("HTTP/3 connection to API closed: {}", error);
+        }
+        driver_closed.store(true, Ordering::Release);
+    });
+    match proxy {
+        Some(proxy) => log::debug!(
+            "Opened HTTP/3 connection to API at {} through Shadowsocks {}/UDP",
+            address,
+            proxy.peer
+        ),
+        None => log::debug!("Opened HTTP/3 connection to API at {}", address),
+    }
+
+    Ok(Connection {
+        endpoint,
+        sender,
+        closed,
+        _forwarder: forwarder,
+    })
+}
+
+/// Excludes `socket` from the tunnel, so that it can reach the API in any tunnel state.
+#[cfg(target_os = "android")]
+async fn bypass_socket<S: AsRawFd>(
+    socket: &S,
+    mut socket_bypass_tx: mpsc::Sender<SocketBypassRequest>,
+) {
+    let (done_tx, done_rx) = oneshot::channel();
+    let _ = socket_bypass_tx.send((socket.as_raw_fd(), done_tx)).await;
+    if done_rx.await.is_err() {
+        log::error!("Failed to bypass socket, connection might fail");
+    }
+}
+
+/// Relays datagrams between a local socket and a destination through a Shadowsocks proxy. The
+/// relaying stops when this is dropped.
+struct Forwarder(JoinHandle<()>);
+
+impl Drop for Forwarder {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Starts relaying datagrams sent to the returned local address to `destination` through `proxy`.
+async fn forward_through_proxy(
+    proxy: &ShadowsocksProxySettings,
+    destination: SocketAddr,
+    #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+) -> Result<(SocketAddr, Forwarder), Error> {
+    let cipher = CipherKind::from_str(&proxy.cipher)
+        .map_err(|_| Error::InvalidCipher(proxy.cipher.clone()))?;
+    let config = ServerConfig::new(proxy.peer, proxy.password.clone(), cipher);
+
+    let bind_address = match proxy.peer {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = std::net::UdpSocket::bind(bind_address).map_err(Error::ProxyConnect)?;
+    #[cfg(target_os = "linux")]
+    if let Some(fwmark) = proxy.fwmark {
+        set_fwmark(&socket, fwmark).map_err(Error::ProxyConnect)?;
+    }
+    #[cfg(target_os = "android")]
+    if let Some(socket_bypass_tx) = socket_bypass_tx {
+        bypass_socket(&socket, socket_bypass_tx).await;
+    }
+    socket.set_nonblocking(true).map_err(Error::ProxyConnect)?;
+    socket.connect(proxy.peer).map_err(Error::ProxyConnect)?;
+    let socket = UdpSocket::from_std(socket).map_err(Error::ProxyConnect)?;
+    let proxy_socket = ProxySocket::from_socket(
+        UdpSocketType::Client,
+        SsContext::new_shared(ServerType::Local),
+        &config,
+        socket.into(),
+    );
+
+    let local_socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .map_err(Error::Bind)?;
+    let local_address = local_socket.local_addr().map_err(Error::Bind)?;
+
+    let task = tokio::spawn(relay_datagrams(local_socket, proxy_socket, destination));
+    Ok((local_address, Forwarder(task)))
+}
+
+/// Marks the traffic of `socket`, so that it is routed outside the tunnel.
+#[cfg(target_os = "linux")]
+fn set_fwmark(socket: &std::net::UdpSocket, mark: u32) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: `mark` is a valid `u32`, and the socket is open.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_MARK,
+            &mark as *const u32 as *const libc::c_void,
+            std::mem::size_of_val(&mark) as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Relays datagrams until the task is aborted. A datagram that cannot be received or sent, such
+/// as one that cannot be decrypted, is dropped without affecting the others.
+async fn relay_datagrams(
+    local_socket: UdpSocket,
+    proxy_socket: ProxySocket,
+    destination: SocketAddr,
+) {
+    let destination = Address::SocketAddress(destination);
+    // Replies are sent to wherever the last datagram came from
+    let mut client = None;
+    let mut local_buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    let mut proxy_buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        tokio::select! {
+            result = local_socket.recv_from(&mut local_buf) => {
+                let (len, source) = match result {
+                    Ok(received) => received,
+                    Err(error) => {
+                        log::debug!("Failed to receive datagram for proxy: {}", error);
+                        continue;
+                    }
+                };
+                client = Some(source);
+                if let Err(error) = proxy_socket.send(&destination, &local_buf[..len]).await {
+                    log::debug!("Failed to send datagram through proxy: {}", error);
+                }
+            }
+            result = proxy_socket.recv(&mut proxy_buf) => {
+                let len = match result {
+                    Ok((len, ..)) => len,
+                    Err(error) => {
+                        log::debug!("Failed to receive datagram from proxy: {}", error);
+                        continue;
+                    }
+                };
+                if let Some(client) = client {
+                    if let Err(error) = local_socket.send_to(&proxy_buf[..len], client).await {
+                        log::debug!("Failed to return datagram from proxy: {}", error);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Datagrams sent to the local address reach the destination through the proxy, and replies
+    /// come back the same way.
+    #[tokio::test]
+    async fn test_forward_through_proxy() {
+        let destination: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        let cipher = "aes-256-gcm";
+
+        let server_config = ServerConfig::new(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            "password".to_owned(),
+            CipherKind::from_str(cipher).unwrap(),
+        );
+        let server = ProxySocket::bind(SsContext::new_shared(ServerType::Server), &server_config)
+            .await
+            .unwrap();
+        let proxy = ShadowsocksProxySettings {
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+            peer: server.local_addr().unwrap(),
+            password: "password".to_owned(),
+            cipher: cipher.to_owned(),
+            protocol: talpid_types::net::TransportProtocol::Udp,
+        };
+
+        let (local_address, _forwarder) = forward_through_proxy(
+            &proxy,
+            destination,
+            #[cfg(target_os = "android")]
+            None,
+        )
+        .await
+        .unwrap();
+        let client = UdpSocket::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+            .await
+            .unwrap();
+        client.send_to(b"request", local_address).await.unwrap();
+
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        let (len, peer, address, ..) = server.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"request");
+        assert_eq!(address, Address::SocketAddress(destination));
+
+        server.send_to(peer, &address, b"response").await.unwrap();
+        let (len, source) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"response");
+        assert_eq!(source, local_address);
+    }
+}
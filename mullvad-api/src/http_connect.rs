@@ -0,0 +1,121 @@
+//! Client side of the HTTP `CONNECT` method, for reaching the API through an HTTP proxy. Basic
+//! authentication is used if credentials are given.
+
+use std::{io, net::SocketAddr};
+use talpid_types::net::openvpn::ProxyAuth;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Responses with larger headers than this are rejected.
+const MAX_RESPONSE_HEADER_LEN: usize = 8 * 1024;
+
+/// Asks the HTTP proxy at the other end of `stream` to open a tunnel to `target`. Once this
+/// returns, `stream` is connected to `target`.
+pub async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    target: SocketAddr,
+    auth: Option<&ProxyAuth>,
+) -> io::Result<()> {
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+    if let Some(auth) = auth {
+        let credentials = base64::encode(format!("{}:{}", auth.username, auth.password));
+        request.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    let header = read_response_header(stream).await?;
+    let status_line = header.lines().next().unwrap_or_default();
+    let mut parts = status_line.split_whitespace();
+    let (version, status) = (parts.next(), parts.next());
+    if !version.map(|v| v.starts_with("HTTP/1.")).unwrap_or(false) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an HTTP proxy",
+        ));
+    }
+    match status {
+        Some("200") => Ok(()),
+        Some("407") => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "HTTP proxy requires authentication",
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("HTTP proxy failed to connect: {status_line}"),
+        )),
+    }
+}
+
+/// Reads the response up to and including the empty line that ends the header. This is read one
+/// byte at a time, so that nothing that the target sends after the header is consumed.
+async fn read_response_header<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<String> {
+    let mut header = Vec::new();
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_RESPONSE_HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HTTP proxy response header is too large",
+            ));
+        }
+        header.push(stream.read_u8().await?);
+    }
+    String::from_utf8(header).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "HTTP proxy response header is not valid UTF-8",
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_with_auth() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let target: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        let auth = ProxyAuth {
+            username: "user".to_owned(),
+            password: "pass".to_owned(),
+        };
+
+        let proxy = tokio::spawn(async move {
+            let request = read_response_header(&mut server).await.unwrap();
+            assert_eq!(
+                request,
+                "CONNECT 192.0.2.1:443 HTTP/1.1\r\nHost: 192.0.2.1:443\r\n\
+                 Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+            );
+            server
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nhello")
+                .await
+                .unwrap();
+        });
+
+        connect(&mut client, target, Some(&auth)).await.unwrap();
+        proxy.await.unwrap();
+
+        // Data from the target must be left in the stream
+        let mut data = [0u8; 5];
+        client.read_exact(&mut data).await.unwrap();
+        assert_eq!(&data, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_connect_denied() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let target: SocketAddr = "192.0.2.1:443".parse().unwrap();
+
+        tokio::spawn(async move {
+            read_response_header(&mut server).await.unwrap();
+            server
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let error = connect(&mut client, target, None).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
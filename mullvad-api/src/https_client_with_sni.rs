@@ -1,7 +1,10 @@
 use crate::{
     abortable_stream::{AbortableStream, AbortableStreamHandle},
+    http_connect,
     proxy::{ApiConnection, ApiConnectionMode, ProxyConfig},
+    socks,
     tls_stream::TlsStream,
+    transport::{self, PluggableTransport},
     AddressCache,
 };
 use futures::{channel::mpsc, future, pin_mut, StreamExt};
@@ -26,20 +29,25 @@ use std::{
     fmt,
     future::Future,
     io,
-    net::{IpAddr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     pin::Pin,
     str::{self, FromStr},
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     task::{Context, Poll},
     time::Duration,
 };
-use talpid_types::ErrorExt;
+use talpid_types::{net::openvpn::ProxyAuth, tunnel::TunnelInterface, ErrorExt};
 
 use tokio::{
     net::{TcpSocket, TcpStream},
     time::timeout,
 };
 
+#[cfg(not(target_os = "android"))]
+use crate::resolver;
 #[cfg(feature = "api-override")]
 use crate::{proxy::ConnectionDecorator, API};
 
@@ -56,25 +64,39 @@ impl HttpsConnectorWithSniHandle {
         let _ = self.tx.unbounded_send(HttpsConnectorRequest::Reset);
     }
 
-    /// Change the proxy settings for the connector
-    pub fn set_connection_mode(&self, proxy: ApiConnectionMode) {
-        let _ = self
-            .tx
-            .unbounded_send(HttpsConnectorRequest::SetConnectionMode(proxy));
+    /// Stop all streams produced by this connector, and fail connection attempts that are in
+    /// progress or made later instead of retrying them.
+    pub fn close(&self) {
+        let _ = self.tx.unbounded_send(HttpsConnectorRequest::Close);
     }
 }
 
 enum HttpsConnectorRequest {
     Reset,
-    SetConnectionMode(ApiConnectionMode),
+    Close,
 }
 
 #[derive(Clone)]
 enum InnerConnectionMode {
     /// Connect directly to the target.
     Direct,
-    /// Connect to the destination via a proxy.
+    /// Connect to the destination via a Shadowsocks proxy.
     Proxied(ParsedShadowsocksConfig),
+    /// Connect to the destination via a SOCKS5 or HTTP proxy.
+    Forwarded {
+        protocol: ForwardingProtocol,
+        proxy: SocketAddr,
+        auth: Option<ProxyAuth>,
+    },
+    /// Connect to the destination via a censorship circumvention transport.
+    Pluggable(Arc<dyn PluggableTransport>),
+}
+
+/// Protocol used to ask a proxy to connect to the destination.
+#[derive(Clone, Copy)]
+enum ForwardingProtocol {
+    Socks5,
+    HttpConnect,
 }
 
 #[derive(Clone)]
@@ -110,6 +132,30 @@ impl TryFrom<ApiConnectionMode> for InnerConnectionMode {
                         .map_err(|_| ProxyConfigError::InvalidCipher(config.cipher))?,
                 })
             }
+            ApiConnectionMode::Proxied(ProxyConfig::Socks5Local(config)) => {
+                InnerConnectionMode::Forwarded {
+                    protocol: ForwardingProtocol::Socks5,
+                    proxy: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), config.port),
+                    auth: None,
+                }
+            }
+            ApiConnectionMode::Proxied(ProxyConfig::Socks5Remote(config)) => {
+                InnerConnectionMode::Forwarded {
+                    protocol: ForwardingProtocol::Socks5,
+                    proxy: config.address,
+                    auth: config.auth,
+                }
+            }
+            ApiConnectionMode::Proxied(ProxyConfig::HttpProxy(config)) => {
+                InnerConnectionMode::Forwarded {
+                    protocol: ForwardingProtocol::HttpConnect,
+                    proxy: config.address,
+                    auth: config.auth,
+                }
+            }
+            ApiConnectionMode::Proxied(ProxyConfig::Obfs4(config)) => {
+                InnerConnectionMode::Pluggable(Arc::new(config))
+            }
         })
     }
 }
@@ -122,6 +168,10 @@ pub struct HttpsConnectorWithSni {
     address_cache: AddressCache,
     abort_notify: Arc<tokio::sync::Notify>,
     proxy_context: SharedContext,
+    connections_opened: Arc<AtomicU64>,
+    /// Tunnel interface that connections are bound to. Pluggable transports open their own
+    /// connections, which are not bound.
+    bind_interface: Option<TunnelInterface>,
     #[cfg(target_os = "android")]
     socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
 }
@@ -129,6 +179,8 @@ pub struct HttpsConnectorWithSni {
 struct HttpsConnectorWithSniInner {
     stream_handles: Vec<AbortableStreamHandle>,
     proxy_config: InnerConnectionMode,
+    /// Set once the connector has been closed.
+    closed: bool,
 }
 
 #[cfg(target_os = "android")]
@@ -138,13 +190,23 @@ impl HttpsConnectorWithSni {
     pub fn new(
         sni_hostname: Option<String>,
         address_cache: AddressCache,
+        connection_mode: ApiConnectionMode,
+        bind_interface: Option<TunnelInterface>,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> (Self, HttpsConnectorWithSniHandle) {
         let (tx, mut rx) = mpsc::unbounded();
         let abort_notify = Arc::new(tokio::sync::Notify::new());
+        let proxy_config = InnerConnectionMode::try_from(connection_mode).unwrap_or_else(|error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to parse API proxy config")
+            );
+            InnerConnectionMode::Direct
+        });
         let inner = Arc::new(Mutex::new(HttpsConnectorWithSniInner {
             stream_handles: vec![],
-            proxy_config: InnerConnectionMode::Direct,
+            proxy_config,
+            closed: false,
         }));
 
         let inner_copy = inner.clone();
@@ -154,23 +216,9 @@ impl HttpsConnectorWithSni {
             while let Some(request) = rx.next().await {
                 let handles = {
                     let mut inner = inner_copy.lock().unwrap();
-
-                    if let HttpsConnectorRequest::SetConnectionMode(config) = request {
-                        match InnerConnectionMode::try_from(config) {
-                            Ok(config) => {
-                                inner.proxy_config = config;
-                            }
-                            Err(error) => {
-                                log::error!(
-                                    "{}",
-                                    error.display_chain_with_msg(
-                                        "Failed to parse new API proxy config"
-                                    )
-                                );
-                            }
-                        }
+                    if let HttpsConnectorRequest::Close = request {
+                        inner.closed = true;
                     }
-
                     std::mem::take(&mut inner.stream_handles)
                 };
                 for handle in handles {
@@ -187,6 +235,8 @@ impl HttpsConnectorWithSni {
                 address_cache,
                 abort_notify,
                 proxy_context: SsContext::new_shared(ServerType::Local),
+                connections_opened: Arc::new(AtomicU64::new(0)),
+                bind_interface,
                 #[cfg(target_os = "android")]
                 socket_bypass_tx,
             },
@@ -194,8 +244,17 @@ impl HttpsConnectorWithSni {
         )
     }
 
+    /// Returns the number of connections successfully established by this connector.
+    pub fn connections_opened(&self) -> u64 {
+        self.connections_opened.load(Ordering::Relaxed)
+    }
+
+    /// Connects to `addr`. If `bind_interface` is set, the socket is bound to that tunnel
+    /// interface, so that the connection cannot leave the tunnel. Otherwise, the socket bypasses
+    /// the tunnel on Android.
     async fn open_socket(
         addr: SocketAddr,
+        bind_interface: Option<&TunnelInterface>,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> std::io::Result<TcpStream> {
         let socket = match addr {
@@ -203,8 +262,13 @@ impl HttpsConnectorWithSni {
             SocketAddr::V6(_) => TcpSocket::new_v6()?,
         };
 
+        #[cfg(not(target_os = "android"))]
+        if let Some(interface) = bind_interface {
+            bind_to_interface(&socket, interface, addr)?;
+        }
+
         #[cfg(target_os = "android")]
-        if let Some(mut tx) = socket_bypass_tx {
+        if let (None, Some(mut tx)) = (bind_interface, socket_bypass_tx) {
             let (done_tx, done_rx) = oneshot::channel();
             let _ = tx.send((socket.as_raw_fd(), done_tx)).await;
             if let Err(_) = done_rx.await {
@@ -217,6 +281,34 @@ impl HttpsConnectorWithSni {
             .map_err(|err| io::Error::new(io::ErrorKind::TimedOut, err))?
     }
 
+    /// Connects to `addr`, which the firewall allows. If that fails and `api_host` is set, connects
+    /// to the address of the API host given by encrypted DNS instead, if it differs from `addr`.
+    #[cfg(not(target_os = "android"))]
+    async fn open_direct_socket(addr: SocketAddr, api_host: Option<&str>) -> io::Result<TcpStream> {
+        let error = match Self::open_socket(addr, None).await {
+            Ok(socket) => return Ok(socket),
+            Err(error) => error,
+        };
+        let api_host = match api_host {
+            Some(api_host) => api_host,
+            None => return Err(error),
+        };
+        let resolved_addr = match resolver::resolve(api_host).await {
+            Some(ip) => SocketAddr::new(IpAddr::V4(ip), addr.port()),
+            None => return Err(error),
+        };
+        if resolved_addr == addr {
+            return Err(error);
+        }
+        log::debug!(
+            "Failed to connect to API address {}: {}. Trying {} from encrypted DNS",
+            addr,
+            error,
+            resolved_addr
+        );
+        Self::open_socket(resolved_addr, None).await
+    }
+
     async fn resolve_address(address_cache: AddressCache, uri: Uri) -> io::Result<SocketAddr> {
         const DEFAULT_PORT: u16 = 443;
 
@@ -253,6 +345,99 @@ impl HttpsConnectorWithSni {
     }
 }
 
+/// Binds `socket` to the tunnel `interface`, so that a connection to `addr` is never routed
+/// outside the tunnel. On Linux, the socket is also marked so that split tunneling does not treat
+/// its traffic as excluded.
+#[cfg(not(target_os = "android"))]
+fn bind_to_interface(
+    socket: &TcpSocket,
+    interface: &TunnelInterface,
+    addr: SocketAddr,
+) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let name = interface.name.as_bytes();
+        // SAFETY: `name` is valid for `name.len()` bytes, and the socket is open.
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                name.as_ptr() as *const libc::c_void,
+                name.len() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mark = talpid_types::cgroup::TUNNEL_BOUND_SOCKET_MARK;
+        // SAFETY: `mark` is a valid `u32`, and the socket is open.
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_MARK,
+                &mark as *const u32 as *const libc::c_void,
+                std::mem::size_of_val(&mark) as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let name = std::ffi::CString::new(interface.name.as_str())
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        // SAFETY: `name` is a valid C string.
+        let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if index == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (level, option) = match addr {
+            SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_BOUND_IF),
+            SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_BOUND_IF),
+        };
+        // SAFETY: `index` is a valid `c_uint`, and the socket is open.
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                option,
+                &index as *const libc::c_uint as *const libc::c_void,
+                std::mem::size_of_val(&index) as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    // Windows uses the strong host model, so packets from an address of the tunnel interface are
+    // only sent on that interface.
+    #[cfg(windows)]
+    {
+        let ip = interface
+            .ips
+            .iter()
+            .find(|ip| ip.is_ipv4() == addr.is_ipv4())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    "The tunnel interface has no address of the same family",
+                )
+            })?;
+        socket.bind(SocketAddr::new(*ip, 0))?;
+    }
+    #[cfg(target_os = "linux")]
+    let _ = addr;
+    Ok(())
+}
+
 impl fmt::Debug for HttpsConnectorWithSni {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("HttpsConnectorWithSni").finish()
@@ -285,6 +470,8 @@ impl Service<Uri> for HttpsConnectorWithSni {
         #[cfg(target_os = "android")]
         let socket_bypass_tx = self.socket_bypass_tx.clone();
         let address_cache = self.address_cache.clone();
+        let connections_opened = self.connections_opened.clone();
+        let bind_interface = self.bind_interface.clone();
 
         let fut = async move {
             if uri.scheme() != Some(&Scheme::HTTPS) {
@@ -295,19 +482,44 @@ impl Service<Uri> for HttpsConnectorWithSni {
             }
 
             let hostname = sni_hostname?;
+            #[cfg(not(target_os = "android"))]
+            let encrypted_dns_host = uri
+                .host()
+                .filter(|host| {
+                    host.eq_ignore_ascii_case(&crate::API.host) && resolver::is_enabled()
+                })
+                .map(str::to_owned);
             let addr = Self::resolve_address(address_cache, uri).await?;
 
-            // Loop until we have established a connection. This starts over if a new endpoint
-            // is selected while connecting.
+            // Loop until we have established a connection. This starts over if the connector is
+            // reset while connecting, and gives up if it is closed.
             let stream = loop {
                 let notify = abort_notify.notified();
-                let config = { inner.lock().unwrap().proxy_config.clone() };
+                let config = {
+                    let inner = inner.lock().unwrap();
+                    if inner.closed {
+                        return Err(io::Error::new(
+                            io::ErrorKind::ConnectionAborted,
+                            "connector was closed",
+                        ));
+                    }
+                    inner.proxy_config.clone()
+                };
                 let stream_fut = async {
                     match config {
                         InnerConnectionMode::Direct => {
+                            #[cfg(not(target_os = "android"))]
+                            let socket = match &bind_interface {
+                                Some(interface) => Self::open_socket(addr, Some(interface)).await?,
+                                None => {
+                                    Self::open_direct_socket(addr, encrypted_dns_host.as_deref())
+                                        .await?
+                                }
+                            };
+                            #[cfg(target_os = "android")]
                             let socket = Self::open_socket(
                                 addr,
-                                #[cfg(target_os = "android")]
+                                bind_interface.as_ref(),
                                 socket_bypass_tx.clone(),
                             )
                             .await?;
@@ -322,6 +534,7 @@ impl Service<Uri> for HttpsConnectorWithSni {
                         InnerConnectionMode::Proxied(proxy_config) => {
                             let socket = Self::open_socket(
                                 proxy_config.peer,
+                                bind_interface.as_ref(),
                                 #[cfg(target_os = "android")]
                                 socket_bypass_tx.clone(),
                             )
@@ -343,19 +556,71 @@ impl Service<Uri> for HttpsConnectorWithSni {
                             let tls_stream = TlsStream::connect_https(proxy, &hostname).await?;
                             Ok(ApiConnection::new(Box::new(tls_stream)))
                         }
+                        InnerConnectionMode::Forwarded {
+                            protocol,
+                            proxy,
+                            auth,
+                        } => {
+                            let mut socket = Self::open_socket(
+                                proxy,
+                                bind_interface.as_ref(),
+                                #[cfg(target_os = "android")]
+                                socket_bypass_tx.clone(),
+                            )
+                            .await?;
+                            let handshake = async {
+                                match protocol {
+                                    ForwardingProtocol::Socks5 => {
+                                        socks::connect(&mut socket, addr, auth.as_ref()).await
+                                    }
+                                    ForwardingProtocol::HttpConnect => {
+                                        http_connect::connect(&mut socket, addr, auth.as_ref())
+                                            .await
+                                    }
+                                }
+                            };
+                            timeout(CONNECT_TIMEOUT, handshake)
+                                .await
+                                .map_err(|err| io::Error::new(io::ErrorKind::TimedOut, err))??;
+
+                            #[cfg(feature = "api-override")]
+                            if API.disable_tls {
+                                return Ok(ApiConnection::new(Box::new(socket)));
+                            }
+
+                            let tls_stream = TlsStream::connect_https(socket, &hostname).await?;
+                            Ok(ApiConnection::new(Box::new(tls_stream)))
+                        }
+                        InnerConnectionMode::Pluggable(pluggable_transport) => {
+                            let stream =
+                                timeout(CONNECT_TIMEOUT, transport::connect(&*pluggable_transport))
+                                    .await
+                                    .map_err(|err| {
+                                        io::Error::new(io::ErrorKind::TimedOut, err)
+                                    })??;
+
+                            #[cfg(feature = "api-override")]
+                            if API.disable_tls {
+                                return Ok(ApiConnection::new(Box::new(stream)));
+                            }
+
+                            let tls_stream = TlsStream::connect_https(stream, &hostname).await?;
+                            Ok(ApiConnection::new(Box::new(tls_stream)))
+                        }
                     }
                 };
 
                 pin_mut!(stream_fut);
                 pin_mut!(notify);
 
-                // Wait for connection. Abort and retry if we switched to a different server.
+                // Wait for connection. Abort and retry if the connector was reset or closed.
                 if let future::Either::Left((stream, _)) = future::select(stream_fut, notify).await
                 {
                     break stream?;
                 }
             };
 
+            connections_opened.fetch_add(1, Ordering::Relaxed);
             let (stream, socket_handle) = AbortableStream::new(stream);
 
             {
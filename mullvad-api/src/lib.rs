@@ -15,50 +15,108 @@ use std::{
     cell::Cell,
     collections::BTreeMap,
     future::Future,
+    io,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     ops::Deref,
-    path::Path,
+    path::{Path, PathBuf},
 };
-use talpid_types::ErrorExt;
+use talpid_types::{net::Endpoint, ErrorExt};
 
 pub mod availability;
 use availability::{ApiAvailability, ApiAvailabilityHandle};
 pub mod rest;
 
 mod abortable_stream;
+#[cfg(feature = "http3")]
+mod http3;
+mod http_connect;
 mod https_client_with_sni;
+pub mod pool;
 pub mod proxy;
+#[cfg(not(target_os = "android"))]
+mod resolver;
+mod socks;
 mod tls_stream;
+pub mod transport;
 #[cfg(target_os = "android")]
 pub use crate::https_client_with_sni::SocketBypassRequest;
 
 mod access;
 mod address_cache;
+pub mod cache_integrity;
 pub mod device;
 mod fs;
 mod relay_list;
+pub mod relay_list_signing;
 pub use address_cache::AddressCache;
 pub use device::DevicesProxy;
 pub use hyper::StatusCode;
 pub use relay_list::RelayListProxy;
+pub use relay_list_signing::RelayListKeys;
+#[cfg(not(target_os = "android"))]
+pub use resolver::trusted_resolver_endpoints;
+
+/// Error code returned in the body of a failed Mullvad API request.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(from = "String")]
+pub enum ApiError {
+    /// The voucher has already been used.
+    VoucherUsed,
+    /// The voucher code is invalid.
+    InvalidVoucher,
+    /// The account token is invalid.
+    InvalidAccount,
+    /// The device does not exist.
+    DeviceNotFound,
+    /// The access token is invalid.
+    InvalidAccessToken,
+    /// The account already has the maximum number of devices.
+    MaxDevicesReached,
+    /// The public key is already registered to another device.
+    PubkeyInUse,
+    /// The response did not contain an error code.
+    Unknown,
+    /// An error code not known to this version of the app.
+    Other(String),
+}
 
-/// Error code returned by the Mullvad API if the voucher has alreaby been used.
-pub const VOUCHER_USED: &str = "VOUCHER_USED";
-
-/// Error code returned by the Mullvad API if the voucher code is invalid.
-pub const INVALID_VOUCHER: &str = "INVALID_VOUCHER";
-
-/// Error code returned by the Mullvad API if the account token is invalid.
-pub const INVALID_ACCOUNT: &str = "INVALID_ACCOUNT";
-
-/// Error code returned by the Mullvad API if the device does not exist.
-pub const DEVICE_NOT_FOUND: &str = "DEVICE_NOT_FOUND";
+impl ApiError {
+    /// Returns the error code as sent by the API.
+    pub fn code(&self) -> &str {
+        match self {
+            ApiError::VoucherUsed => "VOUCHER_USED",
+            ApiError::InvalidVoucher => "INVALID_VOUCHER",
+            ApiError::InvalidAccount => "INVALID_ACCOUNT",
+            ApiError::DeviceNotFound => "DEVICE_NOT_FOUND",
+            ApiError::InvalidAccessToken => "INVALID_ACCESS_TOKEN",
+            ApiError::MaxDevicesReached => "MAX_DEVICES_REACHED",
+            ApiError::PubkeyInUse => "PUBKEY_IN_USE",
+            ApiError::Unknown => "UNKNOWN",
+            ApiError::Other(code) => code,
+        }
+    }
+}
 
-/// Error code returned by the Mullvad API if the access token is invalid.
-pub const INVALID_ACCESS_TOKEN: &str = "INVALID_ACCESS_TOKEN";
+impl From<String> for ApiError {
+    fn from(code: String) -> Self {
+        match code.as_str() {
+            "VOUCHER_USED" => ApiError::VoucherUsed,
+            "INVALID_VOUCHER" => ApiError::InvalidVoucher,
+            "INVALID_ACCOUNT" => ApiError::InvalidAccount,
+            "DEVICE_NOT_FOUND" => ApiError::DeviceNotFound,
+            "INVALID_ACCESS_TOKEN" => ApiError::InvalidAccessToken,
+            "MAX_DEVICES_REACHED" => ApiError::MaxDevicesReached,
+            "PUBKEY_IN_USE" => ApiError::PubkeyInUse,
+            _ => ApiError::Other(code),
+        }
+    }
+}
 
-pub const MAX_DEVICES_REACHED: &str = "MAX_DEVICES_REACHED";
-pub const PUBKEY_IN_USE: &str = "PUBKEY_IN_USE";
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
 
 pub const API_IP_CACHE_FILENAME: &str = "api-ip-address.txt";
 
@@ -100,11 +158,79 @@ impl<T> Deref for LazyManual<T> {
     }
 }
 
+/// Path of the API host configuration file that is chosen when building the app. In builds with
+/// the `api-override` feature, `MULLVAD_API_CONFIG_FILE` may also be set at runtime.
+const BUILD_TIME_API_CONFIG_FILE: Option<&str> = option_env!("MULLVAD_API_CONFIG_FILE");
+
+const API_HOST_DEFAULT: &str = "api.mullvad.net";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+enum ApiConfigError {
+    #[error(display = "Failed to read API config {}", _0)]
+    Read(String, #[error(source)] io::Error),
+
+    #[error(display = "Invalid API config {}", _0)]
+    Parse(String, #[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to read API root certificates {}", _0)]
+    ReadCertificates(String, #[error(source)] io::Error),
+}
+
+/// Alternative API host, read from the file given by `MULLVAD_API_CONFIG_FILE`. This lets builds
+/// for partners, or regional mirrors, use their own API servers.
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ApiHostConfig {
+    /// Hostname to use in API requests and for SNI.
+    host: String,
+    /// Address used to reach the API until the address cache has been updated.
+    address: SocketAddr,
+    /// PEM file containing the root certificates to trust instead of the built-in one. Relative
+    /// paths are relative to the directory of the configuration file.
+    root_certificates: Option<std::path::PathBuf>,
+}
+
+impl ApiHostConfig {
+    /// Reads the configuration file, if one is given when building or, in builds with the
+    /// `api-override` feature, at runtime.
+    fn load() -> Result<Option<(ApiHostConfig, Option<Vec<u8>>)>, ApiConfigError> {
+        #[cfg(feature = "api-override")]
+        let runtime_path = std::env::var_os("MULLVAD_API_CONFIG_FILE");
+        #[cfg(not(feature = "api-override"))]
+        let runtime_path: Option<std::ffi::OsString> = None;
+
+        let path = match (runtime_path, BUILD_TIME_API_CONFIG_FILE) {
+            (Some(path), _) => std::path::PathBuf::from(path),
+            (None, Some(path)) => std::path::PathBuf::from(path),
+            (None, None) => return Ok(None),
+        };
+        let contents = std::fs::read(&path)
+            .map_err(|error| ApiConfigError::Read(path.display().to_string(), error))?;
+        let config: ApiHostConfig = serde_json::from_slice(&contents)
+            .map_err(|error| ApiConfigError::Parse(path.display().to_string(), error))?;
+        let root_certificates = match config.root_certificates.as_ref() {
+            Some(cert_path) => {
+                let cert_path = path.parent().unwrap_or(Path::new("")).join(cert_path);
+                let certificates = std::fs::read(&cert_path).map_err(|error| {
+                    ApiConfigError::ReadCertificates(cert_path.display().to_string(), error)
+                })?;
+                Some(certificates)
+            }
+            None => None,
+        };
+        log::info!("Using API host {} from {}", config.host, path.display());
+        Ok(Some((config, root_certificates)))
+    }
+}
+
 /// A hostname and socketaddr to reach the Mullvad REST API over.
 #[derive(Debug)]
 pub struct ApiEndpoint {
     pub host: String,
     pub addr: SocketAddr,
+    /// PEM encoded root certificates to trust instead of the built-in one.
+    pub root_certificates: Option<Vec<u8>>,
     #[cfg(feature = "api-override")]
     pub disable_address_cache: bool,
     #[cfg(feature = "api-override")]
@@ -119,9 +245,9 @@ impl ApiEndpoint {
     /// # Panics
     ///
     /// Panics if `MULLVAD_API_ADDR` has invalid contents or if only one of
-    /// `MULLVAD_API_ADDR` or `MULLVAD_API_HOST` has been set but not the other.
+    /// `MULLVAD_API_ADDR` or `MULLVAD_API_HOST` has been set but not the other. An API config
+    /// file that cannot be used is logged and ignored.
     pub fn from_env_vars() -> ApiEndpoint {
-        const API_HOST_DEFAULT: &str = "api.mullvad.net";
         const API_IP_DEFAULT: IpAddr = IpAddr::V4(Ipv4Addr::new(45, 83, 223, 196));
         const API_PORT_DEFAULT: u16 = 443;
 
@@ -138,10 +264,10 @@ impl ApiEndpoint {
         let address_var = read_var("MULLVAD_API_ADDR");
         let disable_tls_var = read_var("MULLVAD_API_DISABLE_TLS");
 
-        #[cfg_attr(not(feature = "api-override"), allow(unused_mut))]
         let mut api = ApiEndpoint {
             host: API_HOST_DEFAULT.to_owned(),
             addr: SocketAddr::new(API_IP_DEFAULT, API_PORT_DEFAULT),
+            root_certificates: None,
             #[cfg(feature = "api-override")]
             disable_address_cache: false,
             #[cfg(feature = "api-override")]
@@ -149,6 +275,20 @@ impl ApiEndpoint {
             #[cfg(feature = "api-override")]
             force_direct_connection: false,
         };
+        match ApiHostConfig::load() {
+            Ok(Some((config, root_certificates))) => {
+                api.host = config.host;
+                api.addr = config.address;
+                api.root_certificates = root_certificates;
+            }
+            Ok(None) => (),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Ignoring API config. Using the default API host")
+                );
+            }
+        }
 
         #[cfg(feature = "api-override")]
         {
@@ -193,6 +333,30 @@ impl ApiEndpoint {
         }
         api
     }
+
+    /// Returns the name of the file that the API address is cached in. Hosts other than the
+    /// default one use their own file, so that their addresses are never used for the default
+    /// host, or vice versa.
+    pub fn address_cache_filename(&self) -> String {
+        address_cache_filename(&self.host)
+    }
+}
+
+fn address_cache_filename(host: &str) -> String {
+    if host.eq_ignore_ascii_case(API_HOST_DEFAULT) {
+        return API_IP_CACHE_FILENAME.to_owned();
+    }
+    let host: String = host
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("api-ip-address-{host}.txt")
 }
 
 /// A type that helps with the creation of API connections.
@@ -200,6 +364,9 @@ pub struct Runtime {
     handle: tokio::runtime::Handle,
     pub address_cache: AddressCache,
     api_availability: availability::ApiAvailability,
+    pool_config: pool::PoolConfig,
+    /// Directory that the connection mode of the API is saved to once it has worked.
+    connection_mode_cache_dir: Option<PathBuf>,
     #[cfg(target_os = "android")]
     socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
 }
@@ -218,13 +385,13 @@ pub enum Error {
 
 /// Closure that receives the next API (real or proxy) endpoint to use for `api.mullvad.net`.
 /// It should return a future that determines whether to reject the new endpoint or not.
-pub trait ApiEndpointUpdateCallback: Fn(SocketAddr) -> Self::AcceptedNewEndpoint {
+pub trait ApiEndpointUpdateCallback: Fn(Endpoint) -> Self::AcceptedNewEndpoint {
     type AcceptedNewEndpoint: Future<Output = bool> + Send;
 }
 
 impl<U, T: Future<Output = bool> + Send> ApiEndpointUpdateCallback for U
 where
-    U: Fn(SocketAddr) -> T,
+    U: Fn(Endpoint) -> T,
 {
     type AcceptedNewEndpoint = T;
 }
@@ -247,6 +414,8 @@ impl Runtime {
             handle,
             address_cache: AddressCache::new(None)?,
             api_availability: ApiAvailability::new(availability::State::default()),
+            pool_config: pool::PoolConfig::default(),
+            connection_mode_cache_dir: None,
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         })
@@ -254,6 +423,8 @@ impl Runtime {
 
     /// Create a new `Runtime` using the specified directories.
     /// Try to use the cache directory first, and fall back on the bundled address otherwise.
+    /// If `write_changes` is set, new API addresses and working connection modes are saved to the
+    /// cache directory.
     pub async fn with_cache(
         cache_dir: &Path,
         write_changes: bool,
@@ -269,7 +440,7 @@ impl Runtime {
             );
         }
 
-        let cache_file = cache_dir.join(API_IP_CACHE_FILENAME);
+        let cache_file = cache_dir.join(API.address_cache_filename());
         let write_file = if write_changes {
             Some(cache_file.clone().into_boxed_path())
         } else {
@@ -295,6 +466,8 @@ impl Runtime {
             handle,
             address_cache,
             api_availability: ApiAvailability::new(availability::State::default()),
+            pool_config: pool::PoolConfig::default(),
+            connection_mode_cache_dir: write_changes.then(|| cache_dir.to_owned()),
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         })
@@ -306,6 +479,7 @@ impl Runtime {
         sni_hostname: Option<String>,
         proxy_provider: T,
         new_address_callback: impl ApiEndpointUpdateCallback + Send + Sync + 'static,
+        connection_mode_cache_dir: Option<PathBuf>,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> rest::RequestServiceHandle {
         rest::RequestService::spawn(
@@ -314,6 +488,8 @@ impl Runtime {
             self.address_cache.clone(),
             proxy_provider,
             new_address_callback,
+            connection_mode_cache_dir,
+            self.pool_config,
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         )
@@ -333,6 +509,34 @@ impl Runtime {
                 Some(API.host.clone()),
                 proxy_provider,
                 new_address_callback,
+                self.connection_mode_cache_dir.clone(),
+                #[cfg(target_os = "android")]
+                self.socket_bypass_tx.clone(),
+            )
+            .await;
+        let factory = rest::RequestFactory::new(API.host.clone(), None);
+
+        rest::MullvadRestHandle::new(
+            service,
+            factory,
+            self.address_cache.clone(),
+            self.availability_handle(),
+        )
+    }
+
+    /// Returns a request factory for the master API that only uses `connection_mode`. This is for
+    /// checking whether the API can be reached in a particular way, so the endpoint is not
+    /// reported to a callback and the connection mode is never saved.
+    pub async fn fixed_mode_rest_handle(
+        &self,
+        connection_mode: ApiConnectionMode,
+    ) -> rest::MullvadRestHandle {
+        let service = self
+            .new_request_service(
+                Some(API.host.clone()),
+                connection_mode.into_repeat(),
+                |_| async { false },
+                None,
                 #[cfg(target_os = "android")]
                 self.socket_bypass_tx.clone(),
             )
@@ -353,12 +557,18 @@ impl Runtime {
             None,
             ApiConnectionMode::Direct.into_repeat(),
             |_| async { true },
+            None,
             #[cfg(target_os = "android")]
             None,
         )
         .await
     }
 
+    /// Sets the connection pool configuration used by request services created after this call.
+    pub fn set_pool_config(&mut self, config: pool::PoolConfig) {
+        self.pool_config = config;
+    }
+
     pub fn handle(&mut self) -> &mut tokio::runtime::Handle {
         &mut self.handle
     }
@@ -368,6 +578,11 @@ impl Runtime {
     }
 }
 
+#[derive(serde::Deserialize)]
+struct AccountExpiryResponse {
+    expiry: DateTime<Utc>,
+}
+
 #[derive(Clone)]
 pub struct AccountsProxy {
     handle: rest::MullvadRestHandle,
@@ -382,11 +597,6 @@ impl AccountsProxy {
         &self,
         account: AccountToken,
     ) -> impl Future<Output = Result<DateTime<Utc>, rest::Error>> {
-        #[derive(serde::Deserialize)]
-        struct AccountExpiryResponse {
-            expiry: DateTime<Utc>,
-        }
-
         let service = self.handle.service.clone();
         let factory = self.handle.factory.clone();
         let access_proxy = self.handle.token_store.clone();
@@ -406,6 +616,29 @@ impl AccountsProxy {
         }
     }
 
+    /// Fetches the expiry of the account unless it matches `validators`.
+    pub fn get_expiry_if_modified(
+        &self,
+        account: AccountToken,
+        validators: rest::CacheValidators,
+    ) -> impl Future<Output = Result<rest::ConditionalResponse<DateTime<Utc>>, rest::Error>> {
+        let response = rest::send_conditional_request::<AccountExpiryResponse>(
+            &self.handle.factory,
+            self.handle.service.clone(),
+            &format!("{}/accounts/me", ACCOUNTS_URL_PREFIX),
+            Some((self.handle.token_store.clone(), account)),
+            validators,
+        );
+        async move {
+            let response = response.await?;
+            Ok(rest::ConditionalResponse {
+                value: response.value.map(|account| account.expiry),
+                validators: response.validators,
+                poll_interval: response.poll_interval,
+            })
+        }
+    }
+
     pub fn create_account(&mut self) -> impl Future<Output = Result<AccountToken, rest::Error>> {
         #[derive(serde::Deserialize)]
         struct AccountCreationResponse {
@@ -550,6 +783,9 @@ pub struct AppVersionResponse {
     pub latest_beta: AppVersion,
     #[serde(default = "default_wg_threshold")]
     pub x_threshold_wg_default: f32,
+    /// New keys that may be used to sign the relay list.
+    #[serde(default)]
+    pub relay_list_keys: Vec<relay_list_signing::SignedKey>,
 }
 
 /// Temporary function that will be removed later. Used to generate default wg_threshold.
@@ -613,3 +849,24 @@ impl ApiProxy {
         rest::deserialize_body(response).await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_address_cache_filename_is_keyed_by_host() {
+        assert_eq!(
+            address_cache_filename(API_HOST_DEFAULT),
+            API_IP_CACHE_FILENAME
+        );
+        assert_eq!(
+            address_cache_filename("api.example.com"),
+            "api-ip-address-api.example.com.txt"
+        );
+        assert_eq!(
+            address_cache_filename("../Evil/host"),
+            "api-ip-address-.._evil_host.txt"
+        );
+    }
+}
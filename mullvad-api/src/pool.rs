@@ -0,0 +1,321 @@
+//! HTTP clients with a separate connection pool for each [`ApiConnectionMode`]. Idle connections
+//! are reused across requests, which saves a TLS handshake per request, but a connection is never
+//! used for a different connection mode than the one it was established with. Connections bound to
+//! a tunnel interface are kept in separate pools as well.
+
+#[cfg(target_os = "android")]
+use crate::https_client_with_sni::SocketBypassRequest;
+use crate::{
+    address_cache::AddressCache,
+    https_client_with_sni::{HttpsConnectorWithSni, HttpsConnectorWithSniHandle},
+    proxy::ApiConnectionMode,
+};
+#[cfg(target_os = "android")]
+use futures::channel::mpsc;
+use hyper::client::Client;
+use std::time::{Duration, Instant};
+use talpid_types::tunnel::TunnelInterface;
+
+/// Default maximum number of idle connections kept for each connection mode.
+pub const DEFAULT_MAX_IDLE_PER_MODE: usize = 4;
+/// Default time after which an idle connection is closed.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// Default maximum number of connection modes to keep pools for.
+pub const DEFAULT_MAX_MODES: usize = 4;
+
+/// Configures the connection pools used by a request service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Maximum number of idle connections to keep for each connection mode.
+    pub max_idle_per_mode: usize,
+    /// Time after which an idle connection is closed.
+    pub idle_timeout: Duration,
+    /// Maximum number of connection modes to keep pools for. When a pool is needed for another
+    /// mode, the least recently used pool is closed.
+    pub max_modes: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_per_mode: DEFAULT_MAX_IDLE_PER_MODE,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            max_modes: DEFAULT_MAX_MODES,
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Returns the default configuration, with each value overridden by
+    /// `MULLVAD_API_POOL_MAX_IDLE`, `MULLVAD_API_POOL_IDLE_TIMEOUT_SECS` and
+    /// `MULLVAD_API_POOL_MAX_MODES` if they are set. Invalid values are logged and ignored.
+    pub fn from_env_vars() -> Self {
+        fn read_var<T: std::str::FromStr>(key: &'static str) -> Option<T> {
+            let value = std::env::var(key).ok()?;
+            match value.parse() {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    log::warn!("Ignoring invalid value of {}: {}", key, value);
+                    None
+                }
+            }
+        }
+
+        let default = Self::default();
+        Self {
+            max_idle_per_mode: read_var("MULLVAD_API_POOL_MAX_IDLE")
+                .unwrap_or(default.max_idle_per_mode),
+            idle_timeout: read_var("MULLVAD_API_POOL_IDLE_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.idle_timeout),
+            max_modes: read_var("MULLVAD_API_POOL_MAX_MODES").unwrap_or(default.max_modes),
+        }
+    }
+}
+
+/// Usage counters for the connection pool of a single connection mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolMetrics {
+    pub connection_mode: ApiConnectionMode,
+    /// Name of the tunnel interface that the connections are bound to, if any.
+    pub interface: Option<String>,
+    /// Number of requests sent using the pool.
+    pub requests: u64,
+    /// Number of connections established by the pool. Requests that did not open a connection
+    /// reused an idle one.
+    pub connections_opened: u64,
+}
+
+struct Pool {
+    connection_mode: ApiConnectionMode,
+    interface: Option<TunnelInterface>,
+    client: Client<HttpsConnectorWithSni, hyper::Body>,
+    connector: HttpsConnectorWithSni,
+    connector_handle: HttpsConnectorWithSniHandle,
+    requests: u64,
+    last_used: Instant,
+}
+
+impl Pool {
+    fn metrics(&self) -> PoolMetrics {
+        PoolMetrics {
+            connection_mode: self.connection_mode.clone(),
+            interface: self
+                .interface
+                .as_ref()
+                .map(|interface| interface.name.clone()),
+            requests: self.requests,
+            connections_opened: self.connector.connections_opened(),
+        }
+    }
+}
+
+/// Set of connection pools, one per recently used [`ApiConnectionMode`].
+pub(crate) struct ConnectionPools {
+    pools: Vec<Pool>,
+    config: PoolConfig,
+    sni_hostname: Option<String>,
+    address_cache: AddressCache,
+    #[cfg(target_os = "android")]
+    socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+}
+
+impl ConnectionPools {
+    pub fn new(
+        config: PoolConfig,
+        sni_hostname: Option<String>,
+        address_cache: AddressCache,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> Self {
+        Self {
+            pools: vec![],
+            config,
+            sni_hostname,
+            address_cache,
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
+        }
+    }
+
+    /// Returns the client to use for a request using `connection_mode`, creating a new pool for
+    /// it if necessary. If `interface` is set, the connections are bound to that tunnel interface.
+    pub fn client(
+        &mut self,
+        connection_mode: &ApiConnectionMode,
+        interface: Option<&TunnelInterface>,
+    ) -> &Client<HttpsConnectorWithSni, hyper::Body> {
+        let index = match self.pools.iter().position(|pool| {
+            &pool.connection_mode == connection_mode && pool.interface.as_ref() == interface
+        }) {
+            Some(index) => index,
+            None => {
+                self.evict_least_recently_used();
+                self.pools
+                    .push(self.new_pool(connection_mode.clone(), interface.cloned()));
+                self.pools.len() - 1
+            }
+        };
+
+        let pool = &mut self.pools[index];
+        pool.requests += 1;
+        pool.last_used = Instant::now();
+        &pool.client
+    }
+
+    /// Closes the pools that use `connection_mode`, including connections in use by in-flight
+    /// requests. Connection attempts in progress fail rather than being retried with the old mode.
+    pub fn close_mode(&mut self, connection_mode: &ApiConnectionMode) {
+        self.pools.retain(|pool| {
+            if &pool.connection_mode != connection_mode {
+                return true;
+            }
+            log::debug!("Closing API connection pool for {}", pool.connection_mode);
+            pool.connector_handle.close();
+            false
+        });
+    }
+
+    /// Closes all connections in all pools.
+    pub fn reset(&self) {
+        for pool in &self.pools {
+            pool.connector_handle.reset();
+        }
+    }
+
+    pub fn metrics(&self) -> Vec<PoolMetrics> {
+        self.pools.iter().map(Pool::metrics).collect()
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        while !self.pools.is_empty() && self.pools.len() >= self.config.max_modes.max(1) {
+            let (index, _) = self
+                .pools
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, pool)| pool.last_used)
+                .unwrap();
+            let pool = self.pools.remove(index);
+            log::debug!("Closing API connection pool for {}", pool.connection_mode);
+            pool.connector_handle.close();
+        }
+    }
+
+    fn new_pool(
+        &self,
+        connection_mode: ApiConnectionMode,
+        interface: Option<TunnelInterface>,
+    ) -> Pool {
+        let (connector, connector_handle) = HttpsConnectorWithSni::new(
+            self.sni_hostname.clone(),
+            self.address_cache.clone(),
+            connection_mode.clone(),
+            interface.clone(),
+            #[cfg(target_os = "android")]
+            self.socket_bypass_tx.clone(),
+        );
+        let client = Client::builder()
+            .pool_max_idle_per_host(self.config.max_idle_per_mode)
+            .pool_idle_timeout(self.config.idle_timeout)
+            .build(connector.clone());
+        Pool {
+            connection_mode,
+            interface,
+            client,
+            connector,
+            connector_handle,
+            requests: 0,
+            last_used: Instant::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::proxy::ProxyConfig;
+    use talpid_types::net::openvpn::ShadowsocksProxySettings;
+
+    fn proxied(port: u16) -> ApiConnectionMode {
+        ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ShadowsocksProxySettings {
+            peer: std::net::SocketAddr::from(([127, 0, 0, 1], port)),
+            password: "mullvad".to_string(),
+            cipher: "aes-256-gcm".to_string(),
+            protocol: talpid_types::net::TransportProtocol::Tcp,
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_pools_are_reused_and_bounded() {
+        let mut pools = ConnectionPools::new(
+            PoolConfig {
+                max_modes: 2,
+                ..PoolConfig::default()
+            },
+            None,
+            AddressCache::new(None).unwrap(),
+            #[cfg(target_os = "android")]
+            None,
+        );
+
+        pools.client(&ApiConnectionMode::Direct, None);
+        pools.client(&proxied(1), None);
+        pools.client(&ApiConnectionMode::Direct, None);
+        assert_eq!(pools.metrics().len(), 2);
+
+        // The pool for `proxied(1)` is the least recently used one
+        pools.client(&proxied(2), None);
+        let metrics = pools.metrics();
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].connection_mode, ApiConnectionMode::Direct);
+        assert_eq!(metrics[0].requests, 2);
+        assert_eq!(metrics[1].connection_mode, proxied(2));
+        assert_eq!(metrics[1].requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_close_mode_removes_pool() {
+        let mut pools = ConnectionPools::new(
+            PoolConfig::default(),
+            None,
+            AddressCache::new(None).unwrap(),
+            #[cfg(target_os = "android")]
+            None,
+        );
+
+        pools.client(&ApiConnectionMode::Direct, None);
+        pools.client(&proxied(1), None);
+        pools.close_mode(&ApiConnectionMode::Direct);
+
+        let metrics = pools.metrics();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].connection_mode, proxied(1));
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_connections_use_separate_pools() {
+        let mut pools = ConnectionPools::new(
+            PoolConfig::default(),
+            None,
+            AddressCache::new(None).unwrap(),
+            #[cfg(target_os = "android")]
+            None,
+        );
+        let tunnel = TunnelInterface {
+            name: "wg-mullvad".to_owned(),
+            ips: vec!["10.64.0.2".parse().unwrap()],
+        };
+
+        pools.client(&ApiConnectionMode::Direct, None);
+        pools.client(&ApiConnectionMode::Direct, Some(&tunnel));
+        pools.client(&ApiConnectionMode::Direct, Some(&tunnel));
+
+        let metrics = pools.metrics();
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].interface, None);
+        assert_eq!(metrics[0].requests, 1);
+        assert_eq!(metrics[1].interface.as_deref(), Some("wg-mullvad"));
+        assert_eq!(metrics[1].requests, 2);
+    }
+}
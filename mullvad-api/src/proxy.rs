@@ -1,17 +1,24 @@
-use futures::Stream;
+use crate::{cache_integrity, transport::obfs4::Obfs4Settings};
+use futures::{Stream, StreamExt};
 use hyper::client::connect::Connected;
+use mullvad_types::cache::CorruptionReason;
 use serde::{Deserialize, Serialize};
 use std::{
     fmt, io,
-    net::SocketAddr,
     path::Path,
     pin::Pin,
     task::{self, Poll},
 };
-use talpid_types::{net::openvpn::ShadowsocksProxySettings, ErrorExt};
+use talpid_types::{
+    net::{
+        openvpn::{LocalProxySettings, RemoteProxySettings, ShadowsocksProxySettings},
+        Endpoint, TransportProtocol,
+    },
+    ErrorExt,
+};
 use tokio::{
     fs,
-    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+    io::{AsyncRead, AsyncWrite, ReadBuf},
 };
 
 const CURRENT_CONFIG_FILENAME: &str = "api-endpoint.json";
@@ -36,13 +43,26 @@ impl fmt::Display for ApiConnectionMode {
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ProxyConfig {
     Shadowsocks(ShadowsocksProxySettings),
+    /// SOCKS5 proxy listening on localhost, which forwards traffic to `peer`.
+    Socks5Local(LocalProxySettings),
+    /// SOCKS5 proxy on a remote host.
+    Socks5Remote(RemoteProxySettings),
+    /// HTTP proxy that supports the `CONNECT` method.
+    HttpProxy(RemoteProxySettings),
+    /// obfs4 bridge, reached through a pluggable transport client.
+    Obfs4(Obfs4Settings),
 }
 
 impl fmt::Display for ProxyConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self {
-            // TODO: Do not hardcode TCP
-            ProxyConfig::Shadowsocks(ss) => write!(f, "Shadowsocks {}/TCP", ss.peer),
+            ProxyConfig::Shadowsocks(ss) => write!(f, "Shadowsocks {}/{}", ss.peer, ss.protocol),
+            ProxyConfig::Socks5Local(local) => {
+                write!(f, "SOCKS5 localhost:{} => {}/TCP", local.port, local.peer)
+            }
+            ProxyConfig::Socks5Remote(remote) => write!(f, "SOCKS5 {}/TCP", remote.address),
+            ProxyConfig::HttpProxy(remote) => write!(f, "HTTP proxy {}/TCP", remote.address),
+            ProxyConfig::Obfs4(obfs4) => write!(f, "obfs4 {}/TCP", obfs4.peer),
         }
     }
 }
@@ -64,17 +84,25 @@ impl ApiConnectionMode {
     /// If the file does not exist, this returns `Ok(ApiConnectionMode::Direct)`.
     async fn from_cache(cache_dir: &Path) -> io::Result<Self> {
         let path = cache_dir.join(CURRENT_CONFIG_FILENAME);
-        match fs::read_to_string(path).await {
-            Ok(s) => serde_json::from_str(&s).map_err(|error| {
-                log::error!(
-                    "{}",
-                    error.display_chain_with_msg(&format!(
-                        "Failed to deserialize \"{}\"",
-                        CURRENT_CONFIG_FILENAME
+        match cache_integrity::read(&path).await {
+            Ok(contents) => match serde_json::from_slice(&contents) {
+                Ok(mode) => Ok(mode),
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "Failed to deserialize \"{}\"",
+                            CURRENT_CONFIG_FILENAME
+                        ))
+                    );
+                    cache_integrity::quarantine(&path, contents, CorruptionReason::ParseError)
+                        .await;
+                    Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "deserialization failed",
                     ))
-                );
-                io::Error::new(io::ErrorKind::Other, "deserialization failed")
-            }),
+                }
+            },
             Err(error) => {
                 if error.kind() == io::ErrorKind::NotFound {
                     Ok(ApiConnectionMode::Direct)
@@ -85,14 +113,34 @@ impl ApiConnectionMode {
         }
     }
 
-    /// Stores this config to `CURRENT_CONFIG_FILENAME`.
+    /// Returns whether this mode may be saved by [`ApiConnectionMode::save`]. HTTP proxies and
+    /// proxies that need a username and password are not written to the cache. obfs4 bridges are
+    /// saved along with their cert and IAT mode. The daemon removes the cache when the access
+    /// method of a bridge is removed or disabled.
+    pub fn is_cacheable(&self) -> bool {
+        match self {
+            ApiConnectionMode::Direct => true,
+            ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(_))
+            | ApiConnectionMode::Proxied(ProxyConfig::Socks5Local(_))
+            | ApiConnectionMode::Proxied(ProxyConfig::Obfs4(_)) => true,
+            ApiConnectionMode::Proxied(ProxyConfig::Socks5Remote(remote)) => remote.auth.is_none(),
+            ApiConnectionMode::Proxied(ProxyConfig::HttpProxy(_)) => false,
+        }
+    }
+
+    /// Stores this config to `CURRENT_CONFIG_FILENAME`. Modes that are not
+    /// [cacheable](ApiConnectionMode::is_cacheable) remove the file instead, so that an older
+    /// mode is not used once they are gone.
     pub async fn save(&self, cache_dir: &Path) -> io::Result<()> {
-        let mut file = crate::fs::AtomicFile::new(cache_dir.join(CURRENT_CONFIG_FILENAME)).await?;
-        let json = serde_json::to_string_pretty(self)
+        if !self.is_cacheable() {
+            Self::try_delete_cache(cache_dir).await;
+            return Ok(());
+        }
+        let path = cache_dir.join(CURRENT_CONFIG_FILENAME);
+        let mut json = serde_json::to_string_pretty(self)
             .map_err(|_| io::Error::new(io::ErrorKind::Other, "serialization failed"))?;
-        file.write_all(json.as_bytes()).await?;
-        file.write_all(b"\n").await?;
-        file.finalize().await
+        json.push('\n');
+        cache_integrity::write(&path, json.as_bytes()).await
     }
 
     /// Attempts to remove `CURRENT_CONFIG_FILENAME`, if it exists.
@@ -108,14 +156,34 @@ impl ApiConnectionMode {
         }
     }
 
-    /// Returns the remote address, or `None` for `ApiConnectionMode::Direct`.
-    pub fn get_endpoint(&self) -> Option<SocketAddr> {
+    /// Returns the remote endpoint, or `None` for `ApiConnectionMode::Direct`.
+    pub fn get_endpoint(&self) -> Option<Endpoint> {
         match self {
-            ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss)) => Some(ss.peer),
+            ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss)) => Some(ss.get_endpoint()),
+            ApiConnectionMode::Proxied(ProxyConfig::Socks5Local(local)) => Some(
+                Endpoint::from_socket_address(local.peer, TransportProtocol::Tcp),
+            ),
+            ApiConnectionMode::Proxied(ProxyConfig::Socks5Remote(remote))
+            | ApiConnectionMode::Proxied(ProxyConfig::HttpProxy(remote)) => {
+                Some(remote.get_endpoint())
+            }
+            ApiConnectionMode::Proxied(ProxyConfig::Obfs4(obfs4)) => Some(
+                Endpoint::from_socket_address(obfs4.peer, TransportProtocol::Tcp),
+            ),
             ApiConnectionMode::Direct => None,
         }
     }
 
+    /// Returns whether requests must be sent over HTTP/3, which is the case when the proxy only
+    /// relays UDP.
+    pub fn requires_http3(&self) -> bool {
+        matches!(
+            self,
+            ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss))
+                if ss.protocol == TransportProtocol::Udp
+        )
+    }
+
     pub fn is_proxy(&self) -> bool {
         *self != ApiConnectionMode::Direct
     }
@@ -125,6 +193,18 @@ impl ApiConnectionMode {
     pub fn into_repeat(self) -> impl Stream<Item = ApiConnectionMode> {
         futures::stream::repeat(self)
     }
+
+    /// Returns a stream that alternates between this config and a direct connection, starting
+    /// with this config. This is for clients that cannot select bridges by themselves, but should
+    /// still be able to use the proxy that the daemon last reached the API through.
+    pub fn into_repeat_with_direct(self) -> impl Stream<Item = ApiConnectionMode> {
+        let modes = if self.is_proxy() {
+            vec![self, ApiConnectionMode::Direct]
+        } else {
+            vec![self]
+        };
+        futures::stream::iter(modes).cycle()
+    }
 }
 
 /// Implements `hyper::client::connect::Connection` by wrapping a type.
@@ -217,3 +297,43 @@ impl hyper::client::connect::Connection for ApiConnection {
         self.0.connected()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_requires_http3() {
+        let shadowsocks = |protocol| {
+            ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ShadowsocksProxySettings {
+                peer: "192.0.2.1:443".parse().unwrap(),
+                password: "secret".to_owned(),
+                cipher: "aes-256-gcm".to_owned(),
+                protocol,
+                #[cfg(target_os = "linux")]
+                fwmark: None,
+            }))
+        };
+        assert!(shadowsocks(TransportProtocol::Udp).requires_http3());
+        assert!(!shadowsocks(TransportProtocol::Tcp).requires_http3());
+        assert!(!ApiConnectionMode::Direct.requires_http3());
+    }
+
+    #[tokio::test]
+    async fn test_cache_obfs4_bridge() {
+        let dir = std::env::temp_dir().join(format!("api-endpoint-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mode = ApiConnectionMode::Proxied(ProxyConfig::Obfs4(
+            "obfs4 192.0.2.1:443 cert=c2VjcmV0 iat-mode=1"
+                .parse()
+                .unwrap(),
+        ));
+        assert!(mode.is_cacheable());
+
+        mode.save(&dir).await.unwrap();
+        let cached = ApiConnectionMode::try_from_cache(&dir).await;
+        assert_eq!(cached, mode);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
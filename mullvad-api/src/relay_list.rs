@@ -1,6 +1,6 @@
 //! A module dedicated to retrieving the relay list from the Mullvad API.
 
-use crate::rest;
+use crate::{relay_list_signing::RelayListKeys, rest};
 
 use hyper::{header, Method, StatusCode};
 use mullvad_types::{location, relay_list};
@@ -10,6 +10,7 @@ use std::{
     collections::BTreeMap,
     future::Future,
     net::{Ipv4Addr, Ipv6Addr},
+    sync::Arc,
     time::Duration,
 };
 
@@ -17,14 +18,16 @@ use std::{
 #[derive(Clone)]
 pub struct RelayListProxy {
     handle: rest::MullvadRestHandle,
+    keys: RelayListKeys,
 }
 
 const RELAY_LIST_TIMEOUT: Duration = Duration::from_secs(15);
 
 impl RelayListProxy {
-    /// Construct a new relay list rest client
-    pub fn new(handle: rest::MullvadRestHandle) -> Self {
-        Self { handle }
+    /// Construct a new relay list rest client. Relay lists that are not signed by any of `keys`
+    /// are rejected.
+    pub fn new(handle: rest::MullvadRestHandle, keys: RelayListKeys) -> Self {
+        Self { handle, keys }
     }
 
     /// Fetch the relay list
@@ -34,6 +37,7 @@ impl RelayListProxy {
     ) -> impl Future<Output = Result<Option<relay_list::RelayList>, rest::Error>> {
         let service = self.handle.service.clone();
         let request = self.handle.factory.request("app/v1/relays", Method::GET);
+        let keys = self.keys.clone();
 
         async move {
             let mut request = request?;
@@ -62,11 +66,18 @@ impl RelayListProxy {
                     }
                 });
 
-            Ok(Some(
-                rest::deserialize_body::<ServerRelayList>(response)
-                    .await?
-                    .into_relay_list(etag),
-            ))
+            let signature = response
+                .headers()
+                .get(crate::relay_list_signing::SIGNATURE_HEADER)
+                .and_then(|signature| signature.to_str().ok())
+                .map(str::to_owned);
+
+            let body = rest::read_body(response).await?;
+            keys.verify(&body, signature.as_deref())?;
+
+            let relay_list: ServerRelayList =
+                serde_json::from_slice(&body).map_err(rest::Error::DeserializeError)?;
+            Ok(Some(relay_list.into_relay_list(etag)))
         }
     }
 }
@@ -164,7 +175,7 @@ fn into_mullvad_relay(
         provider: relay.provider,
         weight: relay.weight,
         endpoint_data,
-        location: Some(location),
+        location: Some(Arc::new(location)),
     }
 }
 
@@ -308,6 +319,9 @@ struct WireGuardRelay {
     #[serde(flatten)]
     relay: Relay,
     public_key: wireguard::PublicKey,
+    /// Optional features supported by the relay. Not included by older versions of the API.
+    #[serde(default)]
+    features: Option<relay_list::RelayCapabilities>,
 }
 
 impl WireGuardRelay {
@@ -317,6 +331,9 @@ impl WireGuardRelay {
             location,
             relay_list::RelayEndpointData::Wireguard(relay_list::WireguardRelayEndpointData {
                 public_key: self.public_key,
+                capabilities: self
+                    .features
+                    .unwrap_or_else(relay_list::RelayCapabilities::legacy),
             }),
         )
     }
@@ -0,0 +1,339 @@
+//! Verification of the detached Ed25519 signature that accompanies the relay list. The initial
+//! public key is embedded at build time using the `MULLVAD_RELAY_LIST_SIGNING_KEY` environment
+//! variable. Release builds without the `api-override` feature fail if it is not set. Additional
+//! keys can be announced through the version API, but are only trusted if they are signed by a
+//! key that is already trusted. Announced keys are saved in the cache directory, so that they are
+//! still trusted after a restart.
+//!
+//! Without any trusted key, every relay list is rejected. Only builds with the `api-override`
+//! feature accept unsigned relay lists in that case, so that they can be used with a test API.
+
+use ring::signature::{UnparsedPublicKey, ED25519};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use talpid_types::ErrorExt;
+use tokio::io::AsyncWriteExt;
+
+/// Hex-encoded Ed25519 public key that the relay list must be signed with.
+const EMBEDDED_KEY: Option<&str> = option_env!("MULLVAD_RELAY_LIST_SIGNING_KEY");
+
+/// Prefixed to a public key before it is signed, so that a relay list signature can never be
+/// mistaken for a key signature.
+const KEY_SIGNATURE_CONTEXT: &[u8] = b"mullvad-relay-list-signing-key:";
+
+/// Name of the file in the cache directory that stores the keys announced by the version API.
+const SIGNED_KEYS_FILENAME: &str = "relay-list-keys.json";
+
+/// Response header that contains the hex-encoded signature of the response body.
+pub const SIGNATURE_HEADER: &str = "M-Signature";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "No relay list signing key is trusted")]
+    NoTrustedKey,
+
+    #[error(display = "The relay list is not signed")]
+    MissingSignature,
+
+    #[error(display = "The relay list signature is not valid")]
+    InvalidSignature,
+
+    #[error(display = "The announced key is not signed by a trusted key")]
+    UntrustedKey,
+}
+
+/// A new signing key announced by the version API.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct SignedKey {
+    /// Hex-encoded Ed25519 public key.
+    pub key: String,
+    /// Hex-encoded signature of the key, made by a key that is already trusted.
+    pub signature: String,
+}
+
+/// The set of keys that are trusted to sign the relay list.
+#[derive(Clone, Debug)]
+pub struct RelayListKeys {
+    inner: Arc<Mutex<KeysInner>>,
+    /// Where announced keys are saved, if anywhere.
+    cache_path: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+struct KeysInner {
+    keys: Vec<Vec<u8>>,
+    /// Keys announced by the version API, in the order that they were first trusted.
+    signed_keys: Vec<SignedKey>,
+    /// Whether a relay list has been rejected since keys were last announced.
+    needs_refresh: bool,
+}
+
+impl RelayListKeys {
+    /// Returns a key set containing only the key embedded at build time, if any.
+    pub fn embedded() -> Self {
+        let keys = EMBEDDED_KEY
+            .map(|key| vec![hex::decode(key).expect("invalid embedded relay list signing key")])
+            .unwrap_or_default();
+        Self::from_keys(keys)
+    }
+
+    /// Returns a key set containing the key embedded at build time and the keys that were
+    /// announced earlier and saved in `cache_dir`. Newly announced keys are saved there too.
+    pub async fn load(cache_dir: &Path) -> Self {
+        Self::embedded()
+            .restore(cache_dir.join(SIGNED_KEYS_FILENAME))
+            .await
+    }
+
+    /// Trusts the keys saved in `cache_path`, and saves newly announced keys there.
+    async fn restore(mut self, cache_path: PathBuf) -> Self {
+        match tokio::fs::read(&cache_path).await {
+            Ok(contents) => match serde_json::from_slice::<Vec<SignedKey>>(&contents) {
+                Ok(signed_keys) => {
+                    for signed_key in &signed_keys {
+                        if let Err(error) = self.trust_signed_key(signed_key) {
+                            log::error!(
+                                "{}",
+                                error.display_chain_with_msg("Ignoring saved relay list key")
+                            );
+                        }
+                    }
+                }
+                Err(error) => log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to parse saved relay list keys")
+                ),
+            },
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => (),
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to read saved relay list keys")
+            ),
+        }
+
+        self.cache_path = Some(cache_path);
+        self
+    }
+
+    fn from_keys(keys: Vec<Vec<u8>>) -> Self {
+        RelayListKeys {
+            inner: Arc::new(Mutex::new(KeysInner {
+                keys,
+                signed_keys: vec![],
+                needs_refresh: false,
+            })),
+            cache_path: None,
+        }
+    }
+
+    /// Returns whether signatures are checked at all. This is only false for builds with the
+    /// `api-override` feature that were made without a signing key.
+    pub fn is_enforced(&self) -> bool {
+        !cfg!(feature = "api-override") || !self.inner.lock().unwrap().keys.is_empty()
+    }
+
+    /// Returns whether a relay list has been rejected since keys were last announced, which may
+    /// mean that the signing key has been rotated.
+    pub fn needs_refresh(&self) -> bool {
+        self.inner.lock().unwrap().needs_refresh
+    }
+
+    /// Checks that `signature` is a valid signature of `data` by any trusted key.
+    pub fn verify(&self, data: &[u8], signature: Option<&str>) -> Result<(), Error> {
+        if !self.is_enforced() {
+            return Ok(());
+        }
+        if self.inner.lock().unwrap().keys.is_empty() {
+            return Err(Error::NoTrustedKey);
+        }
+        let signature = signature.ok_or(Error::MissingSignature)?;
+        let signature = hex::decode(signature.trim()).map_err(|_| Error::InvalidSignature)?;
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.is_signed_by_trusted_key(data, &signature) {
+            Ok(())
+        } else {
+            inner.needs_refresh = true;
+            Err(Error::InvalidSignature)
+        }
+    }
+
+    /// Starts trusting a key announced by the version API, if it is signed by a trusted key. The
+    /// key is saved if it was not trusted before.
+    pub async fn add_signed_key(&self, signed_key: &SignedKey) -> Result<(), Error> {
+        if !self.trust_signed_key(signed_key)? {
+            return Ok(());
+        }
+        log::info!("Adding relay list signing key {}", signed_key.key);
+
+        if let Some(cache_path) = &self.cache_path {
+            let contents =
+                serde_json::to_vec_pretty(&self.inner.lock().unwrap().signed_keys).unwrap();
+            if let Err(error) = save_signed_keys(cache_path, &contents).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to save relay list signing key")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks the trusted keys as up to date, once the keys announced by the version API have been
+    /// added.
+    pub fn clear_needs_refresh(&self) {
+        self.inner.lock().unwrap().needs_refresh = false;
+    }
+
+    /// Trusts `signed_key` if it is signed by a trusted key. Returns whether it was not already
+    /// trusted.
+    fn trust_signed_key(&self, signed_key: &SignedKey) -> Result<bool, Error> {
+        let key = hex::decode(&signed_key.key).map_err(|_| Error::UntrustedKey)?;
+        let signature = hex::decode(&signed_key.signature).map_err(|_| Error::UntrustedKey)?;
+
+        let mut message = KEY_SIGNATURE_CONTEXT.to_vec();
+        message.extend_from_slice(&key);
+
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.is_signed_by_trusted_key(&message, &signature) {
+            return Err(Error::UntrustedKey);
+        }
+        if inner.keys.contains(&key) {
+            return Ok(false);
+        }
+        inner.keys.push(key);
+        inner.signed_keys.push(signed_key.clone());
+        Ok(true)
+    }
+}
+
+/// Replaces the saved keys atomically, so that keys are not lost if the daemon stops while they
+/// are being written.
+async fn save_signed_keys(cache_path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = crate::fs::AtomicFile::new(cache_path.to_path_buf()).await?;
+    file.write_all(contents).await?;
+    file.finalize().await
+}
+
+impl KeysInner {
+    fn is_signed_by_trusted_key(&self, message: &[u8], signature: &[u8]) -> bool {
+        self.keys.iter().any(|key| {
+            UnparsedPublicKey::new(&ED25519, key)
+                .verify(message, signature)
+                .is_ok()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+
+    fn new_key_pair() -> Ed25519KeyPair {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn test_verify_relay_list() {
+        let key_pair = new_key_pair();
+        let keys = RelayListKeys::from_keys(vec![key_pair.public_key().as_ref().to_vec()]);
+        let relay_list = b"{\"countries\": []}";
+        let signature = hex::encode(key_pair.sign(relay_list));
+
+        assert!(keys.verify(relay_list, Some(&signature)).is_ok());
+        assert!(matches!(
+            keys.verify(b"{\"countries\": null}", Some(&signature)),
+            Err(Error::InvalidSignature)
+        ));
+        assert!(matches!(
+            keys.verify(relay_list, None),
+            Err(Error::MissingSignature)
+        ));
+    }
+
+    #[test]
+    fn test_reject_without_trusted_key() {
+        let keys = RelayListKeys::from_keys(vec![]);
+        if cfg!(feature = "api-override") {
+            assert!(keys.verify(b"{}", None).is_ok());
+        } else {
+            assert!(matches!(keys.verify(b"{}", None), Err(Error::NoTrustedKey)));
+        }
+    }
+
+    fn sign_key(signing_key: &Ed25519KeyPair, key: &Ed25519KeyPair) -> SignedKey {
+        let mut message = KEY_SIGNATURE_CONTEXT.to_vec();
+        message.extend_from_slice(key.public_key().as_ref());
+        SignedKey {
+            key: hex::encode(key.public_key().as_ref()),
+            signature: hex::encode(signing_key.sign(&message)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_key_rotation() {
+        let old_key = new_key_pair();
+        let new_key = new_key_pair();
+        let keys = RelayListKeys::from_keys(vec![old_key.public_key().as_ref().to_vec()]);
+
+        let signed_key = sign_key(&old_key, &new_key);
+        let self_signed_key = sign_key(&new_key, &new_key);
+
+        let relay_list = b"{}";
+        let signature = hex::encode(new_key.sign(relay_list));
+
+        assert!(keys.add_signed_key(&self_signed_key).await.is_err());
+        assert!(keys.verify(relay_list, Some(&signature)).is_err());
+        assert!(keys.needs_refresh());
+
+        keys.add_signed_key(&signed_key).await.unwrap();
+        assert!(keys.verify(relay_list, Some(&signature)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_saved_keys_are_trusted_after_restart() {
+        let dir = std::env::temp_dir().join(format!("relay-list-keys-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache_path = dir.join(SIGNED_KEYS_FILENAME);
+
+        let old_key = new_key_pair();
+        let new_key = new_key_pair();
+        let newest_key = new_key_pair();
+        let embedded_key = vec![old_key.public_key().as_ref().to_vec()];
+
+        let keys = RelayListKeys::from_keys(embedded_key.clone())
+            .restore(cache_path.clone())
+            .await;
+        keys.add_signed_key(&sign_key(&old_key, &new_key))
+            .await
+            .unwrap();
+        keys.add_signed_key(&sign_key(&new_key, &newest_key))
+            .await
+            .unwrap();
+
+        let relay_list = b"{}";
+        let signature = hex::encode(newest_key.sign(relay_list));
+        let restored = RelayListKeys::from_keys(embedded_key)
+            .restore(cache_path.clone())
+            .await;
+        assert!(restored.verify(relay_list, Some(&signature)).is_ok());
+
+        // Saved keys are only trusted if they chain back to the embedded key
+        let other_key = new_key_pair();
+        let restored = RelayListKeys::from_keys(vec![other_key.public_key().as_ref().to_vec()])
+            .restore(cache_path)
+            .await;
+        assert!(restored.verify(relay_list, Some(&signature)).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
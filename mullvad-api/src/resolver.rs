@@ -0,0 +1,410 @@
+//! Resolves the API hostname with encrypted DNS, so that the API remains reachable when plain DNS
+//! is poisoned. Queries are sent with DNS over HTTPS (RFC 8484) or DNS over TLS (RFC 7858) to
+//! resolvers run by Mullvad, whose certificates are verified like those of the API.
+//!
+//! The resolvers are reached over IPv4 or IPv6, and are allowed by the firewall in every tunnel
+//! state. Only IPv4 addresses of the API are looked up, since that is what the address cache
+//! holds. Callers fall back on the address cache if no resolver can be reached.
+
+use crate::tls_stream::TlsStream;
+use hyper::{body::Body, Method, Request};
+use once_cell::sync::Lazy;
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use talpid_types::net::{Endpoint, TransportProtocol};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+const RESOLVER_IPV4: IpAddr = IpAddr::V4(Ipv4Addr::new(194, 242, 2, 2));
+const RESOLVER_IPV6: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2a07, 0xe340, 0, 0, 0, 0, 0, 2));
+const RESOLVER_HOSTNAME: &str = "dns.mullvad.net";
+
+/// Resolvers to try, in order. DNS over TLS is tried last, for networks that block
+/// DNS over HTTPS by its address.
+const TRUSTED_RESOLVERS: &[TrustedResolver] = &[
+    TrustedResolver {
+        address: RESOLVER_IPV4,
+        hostname: RESOLVER_HOSTNAME,
+        protocol: Protocol::Https,
+    },
+    TrustedResolver {
+        address: RESOLVER_IPV6,
+        hostname: RESOLVER_HOSTNAME,
+        protocol: Protocol::Https,
+    },
+    TrustedResolver {
+        address: RESOLVER_IPV4,
+        hostname: RESOLVER_HOSTNAME,
+        protocol: Protocol::Tls,
+    },
+    TrustedResolver {
+        address: RESOLVER_IPV6,
+        hostname: RESOLVER_HOSTNAME,
+        protocol: Protocol::Tls,
+    },
+];
+
+/// How long to wait for each resolver.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bounds on how long a successful answer is used, regardless of its TTL.
+const MIN_CACHE_TIME: Duration = Duration::from_secs(60);
+const MAX_CACHE_TIME: Duration = Duration::from_secs(60 * 60);
+/// How long to wait before trying again after every resolver failed.
+const FAILURE_CACHE_TIME: Duration = Duration::from_secs(5 * 60);
+
+const RECORD_TYPE_A: u16 = 1;
+const RECORD_CLASS_IN: u16 = 1;
+
+static CACHE: Lazy<Mutex<Option<CachedAnswer>>> = Lazy::new(|| Mutex::new(None));
+/// Held while resolvers are queried, so that only one lookup is in flight at a time.
+static QUERY_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+#[derive(Clone, Copy)]
+enum Protocol {
+    /// DNS over HTTPS.
+    Https,
+    /// DNS over TLS.
+    Tls,
+}
+
+impl Protocol {
+    fn port(self) -> u16 {
+        match self {
+            Protocol::Https => 443,
+            Protocol::Tls => 853,
+        }
+    }
+}
+
+struct TrustedResolver {
+    address: IpAddr,
+    hostname: &'static str,
+    protocol: Protocol,
+}
+
+struct CachedAnswer {
+    hostname: String,
+    address: Option<Ipv4Addr>,
+    expires: Instant,
+}
+
+/// Returns whether encrypted DNS should be used for the API host. It is not used if the API host
+/// has been replaced, since the resolvers could not be verified or would return the wrong address.
+pub(crate) fn is_enabled() -> bool {
+    #[cfg(feature = "api-override")]
+    if crate::API.disable_address_cache {
+        return false;
+    }
+    crate::API.root_certificates.is_none()
+}
+
+/// Returns the endpoints of the trusted resolvers, which the firewall must allow for encrypted
+/// DNS to work outside the tunnel. This is empty if encrypted DNS is not used.
+pub fn trusted_resolver_endpoints() -> Vec<Endpoint> {
+    if !is_enabled() {
+        return vec![];
+    }
+    TRUSTED_RESOLVERS
+        .iter()
+        .map(|resolver| {
+            Endpoint::from_socket_address(
+                SocketAddr::new(resolver.address, resolver.protocol.port()),
+                TransportProtocol::Tcp,
+            )
+        })
+        .collect()
+}
+
+/// Returns an IPv4 address of `hostname`, or `None` if none of the trusted resolvers can be
+/// reached or they have no address. Answers are cached. If another lookup is already in flight,
+/// this returns `None` instead of waiting for it.
+pub(crate) async fn resolve(hostname: &str) -> Option<Ipv4Addr> {
+    if let Some(address) = cached_answer(hostname) {
+        return address;
+    }
+
+    let _query_guard = QUERY_LOCK.try_lock().ok()?;
+    // Another lookup may have finished while the cache was unlocked
+    if let Some(address) = cached_answer(hostname) {
+        return address;
+    }
+
+    let (address, cache_time) = match resolve_uncached(hostname).await {
+        Some((address, ttl)) => (Some(address), ttl.clamp(MIN_CACHE_TIME, MAX_CACHE_TIME)),
+        None => (None, FAILURE_CACHE_TIME),
+    };
+    *CACHE.lock().unwrap() = Some(CachedAnswer {
+        hostname: hostname.to_owned(),
+        address,
+        expires: Instant::now() + cache_time,
+    });
+    address
+}
+
+/// Returns the cached answer for `hostname`, if it has not expired.
+fn cached_answer(hostname: &str) -> Option<Option<Ipv4Addr>> {
+    CACHE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .filter(|cached| cached.hostname == hostname && cached.expires > Instant::now())
+        .map(|cached| cached.address)
+}
+
+async fn resolve_uncached(hostname: &str) -> Option<(Ipv4Addr, Duration)> {
+    let query = build_query(hostname).ok()?;
+    for resolver in TRUSTED_RESOLVERS {
+        let response = match timeout(QUERY_TIMEOUT, send_query(resolver, &query)).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(error)) => {
+                log::debug!(
+                    "Encrypted DNS query to {} failed: {}",
+                    resolver.address,
+                    error
+                );
+                continue;
+            }
+            Err(_) => {
+                log::debug!("Encrypted DNS query to {} timed out", resolver.address);
+                continue;
+            }
+        };
+        match parse_response(&response) {
+            Ok(Some((address, ttl))) => {
+                log::debug!("Resolved {} to {} with encrypted DNS", hostname, address);
+                return Some((address, ttl));
+            }
+            Ok(None) => return None,
+            Err(error) => {
+                log::debug!(
+                    "Invalid encrypted DNS response from {}: {}",
+                    resolver.address,
+                    error
+                );
+            }
+        }
+    }
+    None
+}
+
+async fn send_query(resolver: &TrustedResolver, query: &[u8]) -> io::Result<Vec<u8>> {
+    match resolver.protocol {
+        Protocol::Https => {
+            let socket =
+                TcpStream::connect(SocketAddr::new(resolver.address, resolver.protocol.port()))
+                    .await?;
+            let stream = TlsStream::connect_https(socket, resolver.hostname).await?;
+            let (mut sender, connection) = hyper::client::conn::handshake(stream)
+                .await
+                .map_err(to_io_error)?;
+            tokio::spawn(connection);
+
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(format!("https://{}/dns-query", resolver.hostname))
+                .header(hyper::header::HOST, resolver.hostname)
+                .header(hyper::header::CONTENT_TYPE, "application/dns-message")
+                .header(hyper::header::ACCEPT, "application/dns-message")
+                .body(Body::from(query.to_vec()))
+                .map_err(to_io_error)?;
+            let response = sender.send_request(request).await.map_err(to_io_error)?;
+            if !response.status().is_success() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("unexpected status: {}", response.status()),
+                ));
+            }
+            let body = hyper::body::to_bytes(response.into_body())
+                .await
+                .map_err(to_io_error)?;
+            Ok(body.to_vec())
+        }
+        Protocol::Tls => {
+            let socket =
+                TcpStream::connect(SocketAddr::new(resolver.address, resolver.protocol.port()))
+                    .await?;
+            let mut stream = TlsStream::connect_https(socket, resolver.hostname).await?;
+            let query_len = u16::try_from(query.len()).map_err(to_io_error)?;
+            stream.write_all(&query_len.to_be_bytes()).await?;
+            stream.write_all(query).await?;
+            let response_len = stream.read_u16().await?;
+            let mut response = vec![0u8; usize::from(response_len)];
+            stream.read_exact(&mut response).await?;
+            Ok(response)
+        }
+    }
+}
+
+fn to_io_error<E: std::error::Error + Send + Sync + 'static>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error)
+}
+
+/// Builds a query for the A records of `hostname`. The ID is zero, as recommended for DNS over
+/// HTTPS, since responses are matched to queries by the transport.
+fn build_query(hostname: &str) -> Result<Vec<u8>, &'static str> {
+    let mut query = vec![
+        0, 0, // ID
+        1, 0, // Flags: recursion desired
+        0, 1, // One question
+        0, 0, 0, 0, 0, 0, // No answer, authority or additional records
+    ];
+    for label in hostname.trim_end_matches('.').split('.') {
+        let len = u8::try_from(label.len()).map_err(|_| "label is too long")?;
+        if len == 0 || len > 63 {
+            return Err("invalid label length");
+        }
+        query.push(len);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&RECORD_TYPE_A.to_be_bytes());
+    query.extend_from_slice(&RECORD_CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// Returns the first IPv4 address in `response`, and its TTL. Returns `None` if the name has no
+/// such address.
+fn parse_response(response: &[u8]) -> Result<Option<(Ipv4Addr, Duration)>, &'static str> {
+    let mut reader = Reader {
+        data: response,
+        position: 0,
+    };
+    let _id = reader.read_u16()?;
+    let flags = reader.read_u16()?;
+    if flags & 0x8000 == 0 {
+        return Err("not a response");
+    }
+    match flags & 0x000f {
+        0 => (),
+        // The name does not exist
+        3 => return Ok(None),
+        _ => return Err("error response"),
+    }
+    let question_count = reader.read_u16()?;
+    let answer_count = reader.read_u16()?;
+    reader.skip(4)?;
+
+    for _ in 0..question_count {
+        reader.skip_name()?;
+        reader.skip(4)?;
+    }
+    for _ in 0..answer_count {
+        reader.skip_name()?;
+        let record_type = reader.read_u16()?;
+        let record_class = reader.read_u16()?;
+        let ttl = reader.read_u32()?;
+        let data_len = usize::from(reader.read_u16()?);
+        let data = reader.read(data_len)?;
+        if record_type == RECORD_TYPE_A && record_class == RECORD_CLASS_IN && data_len == 4 {
+            let address = Ipv4Addr::new(data[0], data[1], data[2], data[3]);
+            return Ok(Some((address, Duration::from_secs(u64::from(ttl)))));
+        }
+    }
+    Ok(None)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let end = self.position.checked_add(len).ok_or("truncated message")?;
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or("truncated message")?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), &'static str> {
+        self.read(len).map(|_| ())
+    }
+
+    fn read_u16(&mut self) -> Result<u16, &'static str> {
+        let bytes = self.read(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, &'static str> {
+        let bytes = self.read(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Skips a possibly compressed name.
+    fn skip_name(&mut self) -> Result<(), &'static str> {
+        loop {
+            let len = self.read(1)?[0];
+            match len {
+                0 => return Ok(()),
+                // A pointer ends the name
+                len if len & 0xc0 == 0xc0 => return self.skip(1),
+                len => self.skip(usize::from(len))?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_query() {
+        let query = build_query("api.mullvad.net").unwrap();
+        assert_eq!(
+            query,
+            b"\0\0\x01\0\0\x01\0\0\0\0\0\0\x03api\x07mullvad\x03net\0\0\x01\0\x01"
+        );
+        assert!(build_query("api..net").is_err());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let mut response = build_query("api.mullvad.net").unwrap();
+        // Mark as a response with one answer
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 2;
+        // CNAME record, pointing to the question name
+        response.extend_from_slice(b"\xc0\x0c\0\x05\0\x01\0\0\0\x3c\0\x02\xc0\x0c");
+        // A record with a TTL of 300 seconds
+        response.extend_from_slice(b"\xc0\x0c\0\x01\0\x01\0\0\x01\x2c\0\x04\xc0\0\x02\x01");
+
+        assert_eq!(
+            parse_response(&response),
+            Ok(Some((
+                Ipv4Addr::new(192, 0, 2, 1),
+                Duration::from_secs(300)
+            )))
+        );
+
+        // Non-existent domain
+        response[3] = 0x83;
+        assert_eq!(parse_response(&response), Ok(None));
+
+        assert!(parse_response(&response[..response.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_trusted_resolver_endpoints() {
+        let endpoints = trusted_resolver_endpoints();
+        assert_eq!(endpoints.len(), TRUSTED_RESOLVERS.len());
+        assert!(endpoints
+            .iter()
+            .all(|endpoint| endpoint.protocol == TransportProtocol::Tcp));
+        assert!(endpoints.iter().any(|endpoint| endpoint.address.is_ipv6()));
+        assert!(endpoints
+            .iter()
+            .any(|endpoint| endpoint.address == SocketAddr::new(RESOLVER_IPV4, 853)));
+    }
+}
@@ -4,30 +4,39 @@ use crate::{
     access::AccessTokenProxy,
     address_cache::AddressCache,
     availability::ApiAvailabilityHandle,
-    https_client_with_sni::{HttpsConnectorWithSni, HttpsConnectorWithSniHandle},
+    pool::{ConnectionPools, PoolConfig, PoolMetrics},
     proxy::ApiConnectionMode,
+    ApiError,
 };
 use futures::{
     channel::{mpsc, oneshot},
     stream::StreamExt,
-    Stream, TryFutureExt,
+    Stream,
 };
 use hyper::{
-    client::Client,
     header::{self, HeaderValue},
     Method, Uri,
 };
 use mullvad_types::account::AccountToken;
 use std::{
     future::Future,
+    path::PathBuf,
     str::FromStr,
     sync::{Arc, Weak},
     time::Duration,
 };
-use talpid_types::ErrorExt;
+#[cfg(feature = "http3")]
+use talpid_types::net::openvpn::ShadowsocksProxySettings;
+use talpid_types::{
+    net::{Endpoint, TransportProtocol},
+    tunnel::TunnelInterface,
+    ErrorExt,
+};
 
 #[cfg(feature = "api-override")]
 use crate::API;
+#[cfg(feature = "http3")]
+use crate::{http3::Http3Client, proxy::ProxyConfig};
 
 pub use hyper::StatusCode;
 
@@ -70,24 +79,55 @@ pub enum Error {
     #[error(display = "Failed to receive response from rest client")]
     ReceiveError,
 
-    /// Unexpected response code
+    /// Unexpected response code. Contains the error code and message returned by the API, if any.
     #[error(display = "Unexpected response status code {} - {}", _0, _1)]
-    ApiError(StatusCode, String),
+    ApiError(StatusCode, ApiError, Option<String>),
 
     /// The string given was not a valid URI.
     #[error(display = "Not a valid URI")]
     UriError(#[error(source)] http::uri::InvalidUri),
+
+    /// The response was not signed by a trusted key.
+    #[error(display = "Failed to verify response signature")]
+    SignatureError(#[error(source)] crate::relay_list_signing::Error),
+
+    /// Only HTTP/3 requests to the API can be sent through proxies that relay UDP.
+    #[error(display = "The request cannot be sent through a UDP proxy")]
+    UdpProxyUnsupported,
+
+    /// The API responded with `304 Not Modified`, but there is no earlier version of the resource
+    /// to use.
+    #[error(display = "The resource was not modified, but it has not been fetched before")]
+    NotModified,
+
+    /// A request that could only be sent over HTTP/3 failed.
+    #[cfg(feature = "http3")]
+    #[error(display = "HTTP/3 request failed")]
+    Http3Error(#[error(source)] crate::http3::Error),
 }
 
 impl Error {
     pub fn is_network_error(&self) -> bool {
-        matches!(self, Error::HyperError(_) | Error::TimeoutError(_))
+        match self {
+            Error::HyperError(_) | Error::TimeoutError(_) | Error::UdpProxyUnsupported => true,
+            #[cfg(feature = "http3")]
+            Error::Http3Error(_) => true,
+            _ => false,
+        }
     }
 
     pub fn is_aborted(&self) -> bool {
         matches!(self, Error::Aborted)
     }
 
+    /// Returns the error code returned by the API, if this is an API error.
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            Error::ApiError(_status, code, _message) => Some(code),
+            _ => None,
+        }
+    }
+
     /// Returns a new instance for which `abortable_stream::Aborted` is mapped to `Self::Aborted`.
     fn map_aborted(self) -> Self {
         if let Error::HyperError(error) = &self {
@@ -119,12 +159,27 @@ pub(crate) struct RequestService<
 > {
     command_tx: Weak<mpsc::UnboundedSender<RequestCommand>>,
     command_rx: mpsc::UnboundedReceiver<RequestCommand>,
-    connector_handle: HttpsConnectorWithSniHandle,
-    client: hyper::Client<HttpsConnectorWithSni, hyper::Body>,
+    pools: ConnectionPools,
+    /// Tried before `pools` for direct connections, if HTTP/3 is enabled.
+    #[cfg(feature = "http3")]
+    http3: Option<Http3Client>,
+    /// Used instead of `pools` for proxies that relay UDP, which can only carry HTTP/3.
+    #[cfg(feature = "http3")]
+    udp_proxy: Option<Http3Client>,
+    connection_mode: ApiConnectionMode,
+    /// The tunnel interface that API sockets are bound to while the tunnel is up. The API is then
+    /// reached directly, and `connection_mode` is kept for when the tunnel goes down.
+    tunnel_interface: Option<TunnelInterface>,
+    /// Set if the API could not be reached directly through the tunnel. `connection_mode` is then
+    /// used through the tunnel until the tunnel interface changes.
+    tunnel_direct_failed: bool,
     proxy_config_provider: T,
     new_address_callback: F,
     address_cache: AddressCache,
     api_availability: ApiAvailabilityHandle,
+    /// Directory to save the connection mode to once a request has succeeded with it.
+    connection_mode_cache_dir: Option<PathBuf>,
+    saved_connection_mode: Option<ApiConnectionMode>,
 }
 
 impl<
@@ -139,13 +194,25 @@ impl<
         address_cache: AddressCache,
         mut proxy_config_provider: T,
         new_address_callback: F,
+        connection_mode_cache_dir: Option<PathBuf>,
+        pool_config: PoolConfig,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> RequestServiceHandle {
-        let (connector, connector_handle) = HttpsConnectorWithSni::new(
+        #[cfg(feature = "http3")]
+        let http3 = sni_hostname.clone().and_then(|hostname| {
+            Http3Client::new(
+                hostname,
+                address_cache.clone(),
+                #[cfg(target_os = "android")]
+                socket_bypass_tx.clone(),
+            )
+        });
+        let pools = ConnectionPools::new(
+            pool_config,
             sni_hostname,
             address_cache.clone(),
             #[cfg(target_os = "android")]
-            socket_bypass_tx.clone(),
+            socket_bypass_tx,
         );
 
         #[cfg(feature = "api-override")]
@@ -153,26 +220,34 @@ impl<
         #[cfg(not(feature = "api-override"))]
         let force_direct_connection = false;
 
+        let mut connection_mode = ApiConnectionMode::Direct;
         if force_direct_connection {
             log::debug!("API proxies are disabled");
         } else if let Some(config) = proxy_config_provider.next().await {
-            connector_handle.set_connection_mode(config);
+            connection_mode = config;
         }
 
         let (command_tx, command_rx) = mpsc::unbounded();
-        let client = Client::builder().build(connector);
 
         let command_tx = Arc::new(command_tx);
 
         let service = Self {
             command_tx: Arc::downgrade(&command_tx),
             command_rx,
-            connector_handle,
-            client,
+            pools,
+            #[cfg(feature = "http3")]
+            http3,
+            #[cfg(feature = "http3")]
+            udp_proxy: None,
+            connection_mode,
+            tunnel_interface: None,
+            tunnel_direct_failed: false,
             proxy_config_provider,
             new_address_callback,
             address_cache,
             api_availability,
+            connection_mode_cache_dir,
+            saved_connection_mode: None,
         };
         let handle = RequestServiceHandle { tx: command_tx };
         tokio::spawn(service.into_future());
@@ -188,8 +263,50 @@ impl<
                 let hyper_request = request.into_request();
 
                 let api_availability = self.api_availability.clone();
+                let connection_mode = self.active_connection_mode();
+                let bound_interface = self.tunnel_interface.clone();
+                let in_tunnel = bound_interface.is_some();
                 let suspend_fut = api_availability.wait_for_unsuspend();
-                let request_fut = self.client.request(hyper_request).map_err(Error::from);
+                // Proxies that relay UDP cannot carry the TCP connections of the pools
+                let client = if connection_mode.requires_http3() {
+                    None
+                } else {
+                    Some(
+                        self.pools
+                            .client(&connection_mode, bound_interface.as_ref())
+                            .clone(),
+                    )
+                };
+                #[cfg(feature = "http3")]
+                let request_fut = {
+                    let http3 = match &connection_mode {
+                        // QUIC sockets are not bound to the tunnel interface
+                        ApiConnectionMode::Direct if in_tunnel => None,
+                        ApiConnectionMode::Direct => self.http3.clone(),
+                        ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(settings))
+                            if client.is_none() =>
+                        {
+                            self.udp_proxy_client(settings)
+                        }
+                        ApiConnectionMode::Proxied(_) => None,
+                    };
+                    async move {
+                        match (http3, client) {
+                            (Some(http3), client) => http3.request(hyper_request, client).await,
+                            (None, Some(client)) => {
+                                client.request(hyper_request).await.map_err(Error::from)
+                            }
+                            (None, None) => Err(Error::UdpProxyUnsupported),
+                        }
+                    }
+                };
+                #[cfg(not(feature = "http3"))]
+                let request_fut = async move {
+                    match client {
+                        Some(client) => client.request(hyper_request).await.map_err(Error::from),
+                        None => Err(Error::UdpProxyUnsupported),
+                    }
+                };
 
                 let request_future = async move {
                     let _ = suspend_fut.await;
@@ -203,13 +320,26 @@ impl<
 
                     let response = flatten_result(response).map_err(|error| error.map_aborted());
 
-                    if let Err(err) = &response {
-                        if err.is_network_error() && !api_availability.get_state().is_offline() {
+                    match &response {
+                        // Results from inside the tunnel say nothing about the connection mode
+                        Ok(_) if in_tunnel => (),
+                        Ok(_) => {
+                            if let Some(tx) = tx {
+                                let _ = tx.unbounded_send(RequestCommand::ConnectionModeWorked(
+                                    connection_mode,
+                                ));
+                            }
+                        }
+                        Err(err)
+                            if err.is_network_error()
+                                && !api_availability.get_state().is_offline() =>
+                        {
                             log::error!("{}", err.display_chain_with_msg("HTTP request failed"));
                             if let Some(tx) = tx {
                                 let _ = tx.unbounded_send(RequestCommand::NextApiConfig);
                             }
                         }
+                        Err(_) => (),
                     }
 
                     if completion_tx.send(response).is_err() {
@@ -220,8 +350,27 @@ impl<
                 };
                 tokio::spawn(future);
             }
-            RequestCommand::Reset => {
-                self.connector_handle.reset();
+            RequestCommand::Reset => self.reset_connections(),
+            RequestCommand::PoolMetrics(tx) => {
+                let _ = tx.send(self.pools.metrics());
+            }
+            RequestCommand::SetTunnelInterface(interface) => {
+                if interface == self.tunnel_interface {
+                    return;
+                }
+                match &interface {
+                    Some(interface) => {
+                        log::debug!(
+                            "Sending API requests inside the tunnel on {}",
+                            interface.name
+                        )
+                    }
+                    None => log::debug!("Sending API requests outside the tunnel"),
+                }
+                self.tunnel_interface = interface;
+                self.tunnel_direct_failed = false;
+                // Connections opened on the other side of the tunnel no longer work
+                self.reset_connections();
             }
             RequestCommand::NextApiConfig => {
                 #[cfg(feature = "api-override")]
@@ -230,25 +379,108 @@ impl<
                     return;
                 }
 
+                // Connections bound to the tunnel need no firewall exception, so every connection
+                // mode can be tried through it, starting with the current one
+                if self.tunnel_interface.is_some() && !self.tunnel_direct_failed {
+                    log::warn!(
+                        "The API is unreachable directly through the tunnel. Using {} instead",
+                        self.connection_mode
+                    );
+                    self.tunnel_direct_failed = true;
+                    self.reset_connections();
+                    return;
+                }
+
                 if let Some(new_config) = self.proxy_config_provider.next().await {
                     let endpoint = match new_config.get_endpoint() {
                         Some(endpoint) => endpoint,
-                        None => self.address_cache.get_address().await,
+                        None => Endpoint::from_socket_address(
+                            self.address_cache.get_address().await,
+                            TransportProtocol::Tcp,
+                        ),
                     };
                     // Switch to new connection mode unless rejected by address change callback
                     if (self.new_address_callback)(endpoint).await {
-                        self.connector_handle.set_connection_mode(new_config);
+                        // Requests using the old connection mode are likely to fail as well, so
+                        // abort them rather than waiting for them to time out.
+                        let old_config = std::mem::replace(&mut self.connection_mode, new_config);
+                        self.pools.close_mode(&old_config);
+                        #[cfg(feature = "http3")]
+                        if let (Some(http3), ApiConnectionMode::Direct) = (&self.http3, &old_config)
+                        {
+                            http3.reset();
+                        }
+                        #[cfg(feature = "http3")]
+                        if let Some(udp_proxy) = self.udp_proxy.take() {
+                            udp_proxy.reset();
+                        }
+                    }
+                }
+            }
+            RequestCommand::ConnectionModeWorked(connection_mode) => {
+                if self.tunnel_interface.is_some()
+                    || connection_mode != self.connection_mode
+                    || self.saved_connection_mode.as_ref() == Some(&connection_mode)
+                {
+                    return;
+                }
+                if let Some(cache_dir) = &self.connection_mode_cache_dir {
+                    if let Err(error) = connection_mode.save(cache_dir).await {
+                        log::debug!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to save API connection mode")
+                        );
+                        return;
                     }
                 }
+                self.saved_connection_mode = Some(connection_mode);
             }
         }
     }
 
+    /// Returns the HTTP/3 client for the Shadowsocks proxy `settings`, which relays UDP. The
+    /// client is reused for as long as the proxy is.
+    #[cfg(feature = "http3")]
+    fn udp_proxy_client(&mut self, settings: &ShadowsocksProxySettings) -> Option<Http3Client> {
+        if let Some(udp_proxy) = &self.udp_proxy {
+            if udp_proxy.proxy() == Some(settings) {
+                return Some(udp_proxy.clone());
+            }
+        }
+        let udp_proxy = self.http3.as_ref()?.with_proxy(settings.clone());
+        if let Some(old_udp_proxy) = self.udp_proxy.replace(udp_proxy.clone()) {
+            old_udp_proxy.reset();
+        }
+        Some(udp_proxy)
+    }
+
+    /// Returns the connection mode to use for new requests.
+    fn active_connection_mode(&self) -> ApiConnectionMode {
+        if self.tunnel_interface.is_some() && !self.tunnel_direct_failed {
+            ApiConnectionMode::Direct
+        } else {
+            self.connection_mode.clone()
+        }
+    }
+
+    /// Closes all connections, including those used by in-flight requests.
+    fn reset_connections(&mut self) {
+        self.pools.reset();
+        #[cfg(feature = "http3")]
+        if let Some(http3) = &self.http3 {
+            http3.reset();
+        }
+        #[cfg(feature = "http3")]
+        if let Some(udp_proxy) = self.udp_proxy.take() {
+            udp_proxy.reset();
+        }
+    }
+
     async fn into_future(mut self) {
         while let Some(command) = self.command_rx.next().await {
             self.process_command(command).await;
         }
-        self.connector_handle.reset();
+        self.pools.reset();
     }
 }
 
@@ -273,6 +505,24 @@ impl RequestServiceHandle {
         completion_rx.await.map_err(|_| Error::ReceiveError)?
     }
 
+    /// Returns usage counters for the connection pools of the request service.
+    pub async fn pool_metrics(&self) -> Result<Vec<PoolMetrics>> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RequestCommand::PoolMetrics(tx))
+            .map_err(|_| Error::SendError)?;
+        rx.await.map_err(|_| Error::ReceiveError)
+    }
+
+    /// Sets the tunnel interface that API sockets are bound to, or `None` when the tunnel is down.
+    /// While it is set, the API is first reached directly through the tunnel instead of through a
+    /// proxy. If that fails, the other connection modes are tried through the tunnel as well.
+    pub fn set_tunnel_interface(&self, interface: Option<TunnelInterface>) {
+        let _ = self
+            .tx
+            .unbounded_send(RequestCommand::SetTunnelInterface(interface));
+    }
+
     /// Forcibly update the connection mode.
     pub async fn next_api_endpoint(&self) -> Result<()> {
         self.tx
@@ -288,7 +538,11 @@ pub(crate) enum RequestCommand {
         oneshot::Sender<std::result::Result<Response, Error>>,
     ),
     Reset,
+    SetTunnelInterface(Option<TunnelInterface>),
     NextApiConfig,
+    /// A request succeeded using the given connection mode.
+    ConnectionModeWorked(ApiConnectionMode),
+    PoolMetrics(oneshot::Sender<Vec<PoolMetrics>>),
 }
 
 /// A REST request that is sent to the RequestService to be executed.
@@ -383,7 +637,9 @@ impl From<Request> for RestRequest {
 
 #[derive(serde::Deserialize)]
 pub struct ErrorResponse {
-    pub code: String,
+    pub code: ApiError,
+    #[serde(default)]
+    pub message: Option<String>,
 }
 
 #[derive(Clone)]
@@ -549,21 +805,146 @@ pub fn send_json_request<B: serde::Serialize>(
     }
 }
 
+/// Validators of a previously fetched resource. They are sent with conditional requests, so that
+/// the API can respond with `304 Not Modified` instead of the resource if it has not changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CacheValidators {
+    fn from_response(response: &Response) -> Self {
+        let header_value = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        };
+        Self {
+            etag: header_value(header::ETAG),
+            last_modified: header_value(header::LAST_MODIFIED),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Response to a conditional request.
+#[derive(Debug, Clone)]
+pub struct ConditionalResponse<T> {
+    /// The resource, or `None` if it has not changed since the validators were obtained. It is
+    /// never `None` if no validators were sent.
+    pub value: Option<T>,
+    /// Validators to send the next time the resource is requested.
+    pub validators: CacheValidators,
+    /// How long the API asks clients to wait before requesting the resource again, if it says.
+    pub poll_interval: Option<Duration>,
+}
+
+/// Sends a GET request that only returns the resource at `uri` if it does not match `validators`.
+pub fn send_conditional_request<T: serde::de::DeserializeOwned>(
+    factory: &RequestFactory,
+    service: RequestServiceHandle,
+    uri: &str,
+    auth: Option<(AccessTokenProxy, AccountToken)>,
+    validators: CacheValidators,
+) -> impl Future<Output = Result<ConditionalResponse<T>>> {
+    let request = factory.get(uri);
+
+    async move {
+        let mut request = request?;
+        if let Some(etag) = &validators.etag {
+            request.add_header(header::IF_NONE_MATCH, etag)?;
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request.add_header(header::IF_MODIFIED_SINCE, last_modified)?;
+        }
+        if let Some((store, account)) = &auth {
+            let access_token = store.get_token(account).await?;
+            request.set_auth(Some(access_token))?;
+        }
+        let response = service.request(request).await?;
+        let result =
+            parse_rest_response(response, &[StatusCode::OK, StatusCode::NOT_MODIFIED]).await;
+
+        if let Some((store, account)) = &auth {
+            store.check_response(account, &result);
+        }
+
+        parse_conditional_response(result?, validators).await
+    }
+}
+
+/// Reads the response to a conditional request that was sent with `validators`.
+async fn parse_conditional_response<T: serde::de::DeserializeOwned>(
+    response: Response,
+    validators: CacheValidators,
+) -> Result<ConditionalResponse<T>> {
+    let poll_interval = poll_interval_hint(&response);
+    let new_validators = CacheValidators::from_response(&response);
+    if response.status() == StatusCode::NOT_MODIFIED {
+        // There is nothing to compare with if no validators were sent
+        if validators.is_empty() {
+            return Err(Error::NotModified);
+        }
+        return Ok(ConditionalResponse {
+            value: None,
+            validators: if new_validators.is_empty() {
+                validators
+            } else {
+                new_validators
+            },
+            poll_interval,
+        });
+    }
+    Ok(ConditionalResponse {
+        value: Some(deserialize_body(response).await?),
+        validators: new_validators,
+        poll_interval,
+    })
+}
+
+/// Returns the `max-age` of the `Cache-Control` header of `response`, which the API uses to tell
+/// clients how often to poll a resource.
+fn poll_interval_hint(response: &Response) -> Option<Duration> {
+    let cache_control = response
+        .headers()
+        .get(header::CACHE_CONTROL)?
+        .to_str()
+        .ok()?;
+    parse_max_age(cache_control)
+}
+
+fn parse_max_age(cache_control: &str) -> Option<Duration> {
+    cache_control.split(',').find_map(|directive| {
+        let (name, value) = directive.trim().split_once('=')?;
+        if !name.eq_ignore_ascii_case("max-age") {
+            return None;
+        }
+        value
+            .trim_matches('"')
+            .parse()
+            .ok()
+            .map(Duration::from_secs)
+    })
+}
+
 pub async fn deserialize_body<T: serde::de::DeserializeOwned>(response: Response) -> Result<T> {
-    let body_length = get_body_length(&response);
-    deserialize_body_inner(response, body_length).await
+    let body = read_body(response).await?;
+    serde_json::from_slice(&body).map_err(Error::DeserializeError)
 }
 
-async fn deserialize_body_inner<T: serde::de::DeserializeOwned>(
-    mut response: Response,
-    body_length: usize,
-) -> Result<T> {
-    let mut body: Vec<u8> = Vec::with_capacity(body_length);
+/// Reads the entire response body without interpreting it.
+pub async fn read_body(mut response: Response) -> Result<Vec<u8>> {
+    let mut body: Vec<u8> = Vec::with_capacity(get_body_length(&response));
     while let Some(chunk) = response.body_mut().next().await {
         body.extend(&chunk?);
     }
-
-    serde_json::from_slice(&body).map_err(Error::DeserializeError)
+    Ok(body)
 }
 
 fn get_body_length(response: &Response) -> usize {
@@ -603,13 +984,17 @@ pub async fn handle_error_response<T>(response: Response) -> Result<T> {
         hyper::StatusCode::METHOD_NOT_ALLOWED => "Method not allowed",
         status => match get_body_length(&response) {
             0 => status.canonical_reason().unwrap_or("Unexpected error"),
-            body_length => {
-                let err: ErrorResponse = deserialize_body_inner(response, body_length).await?;
-                return Err(Error::ApiError(status, err.code));
+            _ => {
+                let err: ErrorResponse = deserialize_body(response).await?;
+                return Err(Error::ApiError(status, err.code, err.message));
             }
         },
     };
-    Err(Error::ApiError(status, error_message.to_owned()))
+    Err(Error::ApiError(
+        status,
+        ApiError::Unknown,
+        Some(error_message.to_owned()),
+    ))
 }
 
 #[derive(Clone)]
@@ -706,3 +1091,137 @@ fn flatten_result<T, E>(
         Err(err) => Err(err),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_max_age() {
+        assert_eq!(
+            parse_max_age("private, max-age=300"),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(
+            parse_max_age("Max-Age=\"60\""),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(parse_max_age("no-cache"), None);
+        assert_eq!(parse_max_age("max-age=soon"), None);
+    }
+
+    fn conditional_response(
+        status: StatusCode,
+        etag: Option<&str>,
+        body: &'static str,
+    ) -> Response {
+        let mut response = hyper::Response::builder()
+            .status(status)
+            .header(header::CACHE_CONTROL, "max-age=600")
+            .header(header::CONTENT_LENGTH, body.len());
+        if let Some(etag) = etag {
+            response = response.header(header::ETAG, etag);
+        }
+        response.body(hyper::Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_conditional_response() {
+        let validators = CacheValidators {
+            etag: Some("\"1\"".to_owned()),
+            last_modified: None,
+        };
+
+        let response: ConditionalResponse<u32> = parse_conditional_response(
+            conditional_response(StatusCode::OK, Some("\"1\""), "42"),
+            CacheValidators::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.value, Some(42));
+        assert_eq!(response.validators, validators);
+        assert_eq!(response.poll_interval, Some(Duration::from_secs(600)));
+
+        // The validators that were sent are kept if the API does not return new ones
+        let response: ConditionalResponse<u32> = parse_conditional_response(
+            conditional_response(StatusCode::NOT_MODIFIED, None, ""),
+            validators.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.value, None);
+        assert_eq!(response.validators, validators);
+        assert_eq!(response.poll_interval, Some(Duration::from_secs(600)));
+
+        let response: ConditionalResponse<u32> = parse_conditional_response(
+            conditional_response(StatusCode::NOT_MODIFIED, Some("\"2\""), ""),
+            validators.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.value, None);
+        assert_eq!(response.validators.etag.as_deref(), Some("\"2\""));
+
+        let result: Result<ConditionalResponse<u32>> = parse_conditional_response(
+            conditional_response(StatusCode::NOT_MODIFIED, None, ""),
+            CacheValidators::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::NotModified)));
+    }
+
+    fn error_response(status: StatusCode, body: &'static str) -> Response {
+        hyper::Response::builder()
+            .status(status)
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(hyper::Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_error_body_is_decoded() {
+        let response = error_response(
+            StatusCode::BAD_REQUEST,
+            r#"{"code":"PUBKEY_IN_USE","message":"Key is registered"}"#,
+        );
+        match handle_error_response::<()>(response).await {
+            Err(Error::ApiError(status, code, message)) => {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                assert_eq!(code, ApiError::PubkeyInUse);
+                assert_eq!(message.as_deref(), Some("Key is registered"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let response = error_response(StatusCode::FORBIDDEN, r#"{"code":"MAX_DEVICES_REACHED"}"#);
+        match handle_error_response::<()>(response).await {
+            Err(Error::ApiError(_, code, message)) => {
+                assert_eq!(code, ApiError::MaxDevicesReached);
+                assert_eq!(message, None);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_error_codes() {
+        let response = error_response(StatusCode::BAD_REQUEST, r#"{"code":"NEW_ERROR"}"#);
+        match handle_error_response::<()>(response).await {
+            Err(Error::ApiError(_, code, _)) => {
+                assert_eq!(code, ApiError::Other("NEW_ERROR".to_owned()));
+                assert_eq!(code.code(), "NEW_ERROR");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Without a body, only the status is known
+        let response = error_response(StatusCode::NOT_FOUND, "");
+        match handle_error_response::<()>(response).await {
+            Err(Error::ApiError(_, code, message)) => {
+                assert_eq!(code, ApiError::Unknown);
+                assert_eq!(message.as_deref(), Some("Not Found"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
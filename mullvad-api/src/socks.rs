@@ -0,0 +1,187 @@
+//! Minimal SOCKS5 client (RFC 1928), for reaching the API through a proxy. Only the `CONNECT`
+//! command is supported, with username and password authentication (RFC 1929) if the proxy
+//! requires it.
+
+use std::{io, net::SocketAddr};
+use talpid_types::net::openvpn::ProxyAuth;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const VERSION: u8 = 5;
+const AUTH_VERSION: u8 = 1;
+
+const METHOD_NO_AUTH: u8 = 0;
+const METHOD_USERNAME_PASSWORD: u8 = 2;
+
+const COMMAND_CONNECT: u8 = 1;
+
+const ADDRESS_TYPE_IPV4: u8 = 1;
+const ADDRESS_TYPE_DOMAIN: u8 = 3;
+const ADDRESS_TYPE_IPV6: u8 = 4;
+
+/// Asks the SOCKS5 proxy at the other end of `stream` to connect to `target`. Once this returns,
+/// `stream` is connected to `target`.
+pub async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    target: SocketAddr,
+    auth: Option<&ProxyAuth>,
+) -> io::Result<()> {
+    let greeting: &[u8] = if auth.is_some() {
+        &[VERSION, 2, METHOD_NO_AUTH, METHOD_USERNAME_PASSWORD]
+    } else {
+        &[VERSION, 1, METHOD_NO_AUTH]
+    };
+    stream.write_all(greeting).await?;
+
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method).await?;
+    check_version(method[0])?;
+    match (method[1], auth) {
+        (METHOD_NO_AUTH, _) => (),
+        (METHOD_USERNAME_PASSWORD, Some(auth)) => authenticate(stream, auth).await?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "SOCKS5 proxy requires an unsupported authentication method",
+            ))
+        }
+    }
+
+    let mut request = vec![VERSION, COMMAND_CONNECT, 0];
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(ADDRESS_TYPE_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            request.push(ADDRESS_TYPE_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    // The reply ends with the address that the proxy bound to, which is not needed
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    check_version(reply[0])?;
+    if reply[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("SOCKS5 proxy failed to connect, reply code {}", reply[1]),
+        ));
+    }
+    let address_len = match reply[3] {
+        ADDRESS_TYPE_IPV4 => 4,
+        ADDRESS_TYPE_IPV6 => 16,
+        ADDRESS_TYPE_DOMAIN => usize::from(stream.read_u8().await?),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid SOCKS5 address type",
+            ))
+        }
+    };
+    let mut bound_address = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound_address).await?;
+
+    Ok(())
+}
+
+async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    auth: &ProxyAuth,
+) -> io::Result<()> {
+    let username = auth.username.as_bytes();
+    let password = auth.password.as_bytes();
+    let too_long = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "SOCKS5 username or password is too long",
+        )
+    };
+
+    let mut request = vec![AUTH_VERSION];
+    request.push(u8::try_from(username.len()).map_err(|_| too_long())?);
+    request.extend_from_slice(username);
+    request.push(u8::try_from(password.len()).map_err(|_| too_long())?);
+    request.extend_from_slice(password);
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "SOCKS5 authentication failed",
+        ));
+    }
+    Ok(())
+}
+
+fn check_version(version: u8) -> io::Result<()> {
+    if version != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a SOCKS5 proxy",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_with_auth() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let target: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        let auth = ProxyAuth {
+            username: "user".to_owned(),
+            password: "pass".to_owned(),
+        };
+
+        let proxy = tokio::spawn(async move {
+            let mut greeting = [0u8; 4];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 2, 0, 2]);
+            server.write_all(&[5, 2]).await.unwrap();
+
+            let mut auth_request = [0u8; 11];
+            server.read_exact(&mut auth_request).await.unwrap();
+            assert_eq!(&auth_request, b"\x01\x04user\x04pass");
+            server.write_all(&[1, 0]).await.unwrap();
+
+            let mut request = [0u8; 10];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, [5, 1, 0, 1, 192, 0, 2, 1, 1, 187]);
+            server
+                .write_all(&[5, 0, 0, 3, 4, b'h', b'o', b's', b't', 0, 80])
+                .await
+                .unwrap();
+        });
+
+        connect(&mut client, target, Some(&auth)).await.unwrap();
+        proxy.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_refused() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let target: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            server.write_all(&[5, 0]).await.unwrap();
+
+            let mut request = [0u8; 22];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[3], ADDRESS_TYPE_IPV6);
+            server.write_all(&[5, 5, 0, 1]).await.unwrap();
+        });
+
+        let error = connect(&mut client, target, None).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
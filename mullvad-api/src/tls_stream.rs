@@ -1,4 +1,5 @@
-//! Provides a TLS 1.3 stream with SNI and LE root cert only.
+//! Provides a TLS 1.3 stream with SNI and LE root cert only, unless other root certificates are
+//! given by the API host configuration.
 use std::{
     io::{self, ErrorKind},
     pin::Pin,
@@ -54,13 +55,17 @@ where
     }
 }
 
-fn read_cert_store() -> rustls::RootCertStore {
+pub(crate) fn read_cert_store() -> rustls::RootCertStore {
     let mut cert_store = rustls::RootCertStore::empty();
 
-    let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(LE_ROOT_CERT))
-        .expect("Failed to parse pem file");
+    let pem = crate::API
+        .root_certificates
+        .as_deref()
+        .unwrap_or(LE_ROOT_CERT);
+    let certs =
+        rustls_pemfile::certs(&mut std::io::BufReader::new(pem)).expect("Failed to parse pem file");
     let (num_certs_added, num_failures) = cert_store.add_parsable_certificates(&certs);
-    if num_failures > 0 || num_certs_added != 1 {
+    if num_failures > 0 || num_certs_added == 0 {
         panic!("Failed to add root cert");
     }
 
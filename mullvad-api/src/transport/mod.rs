@@ -0,0 +1,290 @@
+//! Censorship circumvention transports for API traffic, such as obfs4. The handshake and framing
+//! of a transport are implemented by an external client program that follows the Tor pluggable
+//! transport specification, version 1. The program is started when the first connection is made
+//! and exposes a SOCKS5 proxy on localhost, through which connections to bridges are made.
+//!
+//! The client program is `lyrebird`, which is built by `lyrebird/build-lyrebird.sh` and installed
+//! in the resource directory of the app.
+//! Only that path is allowed through the firewall on Windows, so it cannot be replaced by
+//! unprivileged users.
+
+use crate::socks;
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+use talpid_types::net::openvpn::ProxyAuth;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader, Lines},
+    net::TcpStream,
+    process::{Child, ChildStdout, Command},
+    sync::Mutex,
+    time::timeout,
+};
+
+pub mod obfs4;
+
+/// File name of the pluggable transport client program in the resource directory.
+#[cfg(not(windows))]
+const CLIENT_NAME: &str = "lyrebird";
+#[cfg(windows)]
+const CLIENT_NAME: &str = "lyrebird.exe";
+
+/// How long to wait for the client program to report its SOCKS5 proxy.
+const CLIENT_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// SOCKS5 usernames and passwords cannot be longer than this.
+const MAX_SOCKS_AUTH_LEN: usize = 255;
+
+/// Running client programs, by transport name.
+static CLIENTS: Lazy<Mutex<HashMap<&'static str, ClientProcess>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A transport that can be used through a pluggable transport client.
+pub trait PluggableTransport: fmt::Debug + Send + Sync {
+    /// Name of the transport, as understood by the client program.
+    fn name(&self) -> &'static str;
+
+    /// Address of the bridge.
+    fn peer(&self) -> SocketAddr;
+
+    /// Arguments for each connection to the bridge, such as keys.
+    fn arguments(&self) -> Vec<(&'static str, String)>;
+}
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "No pluggable transport client is installed at {}", _0)]
+    NoClient(String),
+
+    #[error(display = "Failed to start pluggable transport client")]
+    StartClient(#[error(source)] io::Error),
+
+    #[error(display = "Pluggable transport client failed: {}", _0)]
+    ClientFailed(String),
+
+    #[error(display = "Timed out waiting for pluggable transport client")]
+    ClientTimeout,
+
+    #[error(display = "Transport arguments are too long")]
+    ArgumentsTooLong,
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        io::Error::new(io::ErrorKind::Other, error)
+    }
+}
+
+/// Returns the path to the pluggable transport client program.
+pub fn client_path() -> PathBuf {
+    mullvad_paths::get_resource_dir().join(CLIENT_NAME)
+}
+
+/// Connects to the bridge of `transport`, starting the client program if needed.
+pub(crate) async fn connect(transport: &dyn PluggableTransport) -> io::Result<TcpStream> {
+    let proxy_address = {
+        let mut clients = CLIENTS.lock().await;
+        let running_client = clients
+            .get_mut(transport.name())
+            .and_then(|client| client.is_running().then(|| client.proxy_address));
+        match running_client {
+            Some(proxy_address) => proxy_address,
+            None => {
+                let client = ClientProcess::start(transport.name()).await?;
+                let proxy_address = client.proxy_address;
+                clients.insert(transport.name(), client);
+                proxy_address
+            }
+        }
+    };
+
+    let auth = socks_auth(&transport.arguments())?;
+    let mut stream = TcpStream::connect(proxy_address).await?;
+    socks::connect(&mut stream, transport.peer(), Some(&auth)).await?;
+    Ok(stream)
+}
+
+/// Encodes transport arguments as SOCKS5 credentials, as described in the pluggable transport
+/// specification.
+fn socks_auth(arguments: &[(&'static str, String)]) -> Result<ProxyAuth, Error> {
+    let mut encoded = String::new();
+    for (key, value) in arguments {
+        if !encoded.is_empty() {
+            encoded.push(';');
+        }
+        encoded.push_str(key);
+        encoded.push('=');
+        for c in value.chars() {
+            if matches!(c, '\\' | '=' | ';') {
+                encoded.push('\\');
+            }
+            encoded.push(c);
+        }
+    }
+
+    if encoded.len() <= MAX_SOCKS_AUTH_LEN {
+        return Ok(ProxyAuth {
+            username: encoded,
+            password: "\0".to_owned(),
+        });
+    }
+    if encoded.len() > 2 * MAX_SOCKS_AUTH_LEN || !encoded.is_char_boundary(MAX_SOCKS_AUTH_LEN) {
+        return Err(Error::ArgumentsTooLong);
+    }
+    let password = encoded.split_off(MAX_SOCKS_AUTH_LEN);
+    Ok(ProxyAuth {
+        username: encoded,
+        password,
+    })
+}
+
+/// A running pluggable transport client program. It is killed when this is dropped.
+struct ClientProcess {
+    child: Child,
+    proxy_address: SocketAddr,
+    state_dir: PathBuf,
+}
+
+impl ClientProcess {
+    async fn start(transport: &'static str) -> Result<Self, Error> {
+        let path = client_path();
+        if tokio::fs::metadata(&path).await.is_err() {
+            return Err(Error::NoClient(path.display().to_string()));
+        }
+        let state_dir = std::env::temp_dir().join(format!("mullvad-pt-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&state_dir)
+            .await
+            .map_err(Error::StartClient)?;
+
+        match Self::spawn(&path, transport, &state_dir).await {
+            Ok((child, proxy_address)) => Ok(ClientProcess {
+                child,
+                proxy_address,
+                state_dir,
+            }),
+            Err(error) => {
+                let _ = tokio::fs::remove_dir_all(&state_dir).await;
+                Err(error)
+            }
+        }
+    }
+
+    async fn spawn(
+        path: &Path,
+        transport: &'static str,
+        state_dir: &Path,
+    ) -> Result<(Child, SocketAddr), Error> {
+        log::debug!("Starting pluggable transport client {}", path.display());
+        let mut child = Command::new(path)
+            .env("TOR_PT_MANAGED_TRANSPORT_VER", "1")
+            .env("TOR_PT_CLIENT_TRANSPORTS", transport)
+            .env("TOR_PT_STATE_LOCATION", state_dir)
+            .env("TOR_PT_EXIT_ON_STDIN_CLOSE", "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(Error::StartClient)?;
+
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let proxy_address = timeout(
+            CLIENT_STARTUP_TIMEOUT,
+            read_proxy_address(&mut lines, transport),
+        )
+        .await
+        .map_err(|_| Error::ClientTimeout)??;
+        log::debug!("Pluggable transport client listens on {}", proxy_address);
+
+        // Keep reading the output, so that the client does not block on writing log messages
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                log::trace!("Pluggable transport client: {}", line);
+            }
+        });
+
+        Ok((child, proxy_address))
+    }
+
+    fn is_running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+impl Drop for ClientProcess {
+    fn drop(&mut self) {
+        let _ = self.child.start_kill();
+        let state_dir = std::mem::take(&mut self.state_dir);
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    let _ = tokio::fs::remove_dir_all(state_dir).await;
+                });
+            }
+            Err(_) => {
+                let _ = std::fs::remove_dir_all(state_dir);
+            }
+        }
+    }
+}
+
+/// Reads the configuration messages of the client program until it reports its SOCKS5 proxy for
+/// `transport`.
+async fn read_proxy_address(
+    lines: &mut Lines<BufReader<ChildStdout>>,
+    transport: &str,
+) -> Result<SocketAddr, Error> {
+    let mut proxy_address = None;
+    loop {
+        let line = lines
+            .next_line()
+            .await
+            .map_err(Error::StartClient)?
+            .ok_or_else(|| Error::ClientFailed("exited during startup".to_owned()))?;
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("CMETHOD") if words.next() == Some(transport) => {
+                if words.next() != Some("socks5") {
+                    return Err(Error::ClientFailed(line));
+                }
+                proxy_address = words.next().and_then(|address| address.parse().ok());
+            }
+            Some("CMETHODS") => {
+                return proxy_address.ok_or(Error::ClientFailed(line));
+            }
+            Some(message) if message.ends_with("-ERROR") => {
+                return Err(Error::ClientFailed(line));
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_socks_auth_encoding() {
+        let auth =
+            socks_auth(&[("cert", "a=b;c\\".to_owned()), ("iat-mode", "0".to_owned())]).unwrap();
+        assert_eq!(auth.username, "cert=a\\=b\\;c\\\\;iat-mode=0");
+        assert_eq!(auth.password, "\0");
+
+        let auth = socks_auth(&[("cert", "x".repeat(300))]).unwrap();
+        assert_eq!(auth.username.len(), MAX_SOCKS_AUTH_LEN);
+        assert_eq!(
+            auth.password.len(),
+            300 + "cert=".len() - MAX_SOCKS_AUTH_LEN
+        );
+
+        assert!(socks_auth(&[("cert", "x".repeat(600))]).is_err());
+    }
+}
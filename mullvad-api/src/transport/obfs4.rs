@@ -0,0 +1,22 @@
+//! The obfs4 transport.
+
+use super::PluggableTransport;
+use std::net::SocketAddr;
+pub use talpid_types::net::obfs4::{IatMode, Obfs4Settings};
+
+impl PluggableTransport for Obfs4Settings {
+    fn name(&self) -> &'static str {
+        "obfs4"
+    }
+
+    fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    fn arguments(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("cert", self.cert.clone()),
+            ("iat-mode", self.iat_mode.to_string()),
+        ]
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{format, json_output, new_rpc_client, Command, Result};
+use mullvad_management_interface::types;
+use mullvad_types::settings::ManagementAccessPolicy;
+
+pub struct Access;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Access {
+    fn name(&self) -> &'static str {
+        "access"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name())
+            .about("Control which local users may use the daemon")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::App::new("get").about("Display the current access policy"))
+            .subcommand(
+                clap::App::new("set")
+                    .about("Change the access policy. Only root may do this")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("unrestricted")
+                            .about("Let every user that can open the socket control the daemon"),
+                    )
+                    .subcommand(
+                        clap::App::new("restricted")
+                            .about(
+                                "Only let root and the given users and groups control the daemon. \
+                                 Other users may only view its state",
+                            )
+                            .arg(
+                                clap::Arg::new("control user")
+                                    .long("control-user")
+                                    .takes_value(true)
+                                    .multiple_occurrences(true)
+                                    .help("ID of a user that may control the daemon"),
+                            )
+                            .arg(
+                                clap::Arg::new("control group")
+                                    .long("control-group")
+                                    .takes_value(true)
+                                    .multiple_occurrences(true)
+                                    .help("ID of a group whose members may control the daemon"),
+                            )
+                            .arg(
+                                clap::Arg::new("read-only group")
+                                    .long("read-only-group")
+                                    .takes_value(true)
+                                    .multiple_occurrences(true)
+                                    .help(
+                                        "ID of a group whose members may view the state of the \
+                                         daemon. If omitted, every user may",
+                                    ),
+                            ),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            Some(("set", matches)) => match matches.subcommand() {
+                Some(("unrestricted", _)) => self.set(ManagementAccessPolicy::default()).await,
+                Some(("restricted", matches)) => {
+                    let ids = |name: &str| match matches.values_of_t::<u32>(name) {
+                        Ok(ids) => ids,
+                        Err(e) => match e.kind {
+                            clap::ErrorKind::ArgumentNotFound => vec![],
+                            _ => e.exit(),
+                        },
+                    };
+                    self.set(ManagementAccessPolicy {
+                        enabled: true,
+                        control_uids: ids("control user"),
+                        control_gids: ids("control group"),
+                        read_only_gids: ids("read-only group"),
+                    })
+                    .await
+                }
+                _ => unreachable!("No access policy given"),
+            },
+            Some(("get", _)) => self.get().await,
+            _ => unreachable!("No access command given"),
+        }
+    }
+}
+
+impl Access {
+    async fn set(&self, policy: ManagementAccessPolicy) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_management_access_policy(types::ManagementAccessPolicy::from(&policy))
+            .await?;
+        println!("Updated access policy");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let policy = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .management_access
+            .map(ManagementAccessPolicy::from)
+            .unwrap_or_default();
+        if json_output() {
+            format::print_json(&policy);
+            return Ok(());
+        }
+        println!("Access policy: {}", policy);
+        Ok(())
+    }
+}
@@ -1,8 +1,9 @@
-use crate::{new_rpc_client, Command, Error, Result};
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
 use itertools::Itertools;
 use mullvad_management_interface::{
-    types::{self, Timestamp},
-    Code, ManagementServiceClient, Status,
+    error_kind,
+    types::{self, ErrorKind, Timestamp},
+    ManagementServiceClient, Status,
 };
 use mullvad_types::{account::AccountToken, device::Device};
 use std::io::{self, Write};
@@ -30,11 +31,18 @@ impl Command for Account {
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(clap::App::new("create").about("Create and log in to a new account"))
             .subcommand(
-                clap::App::new("login").about("Log in to an account").arg(
-                    clap::Arg::new("account")
-                        .help("The Mullvad account token to configure the client with")
-                        .required(false),
-                ),
+                clap::App::new("login")
+                    .about("Log in to an account")
+                    .arg(
+                        clap::Arg::new("account")
+                            .help("The Mullvad account token to configure the client with")
+                            .required(false),
+                    )
+                    .arg(
+                        clap::Arg::new("in-memory").long("in-memory").help(
+                            "Keep the account token in memory only, without storing it on disk",
+                        ),
+                    ),
             )
             .subcommand(clap::App::new("logout").about("Log out of the current account"))
             .subcommand(
@@ -91,7 +99,11 @@ impl Command for Account {
         if let Some(_matches) = matches.subcommand_matches("create") {
             self.create().await
         } else if let Some(set_matches) = matches.subcommand_matches("login") {
-            self.login(parse_token_else_stdin(set_matches)).await
+            self.login(
+                parse_token_else_stdin(set_matches),
+                set_matches.is_present("in-memory"),
+            )
+            .await
         } else if let Some(_matches) = matches.subcommand_matches("logout") {
             self.logout().await
         } else if let Some(set_matches) = matches.subcommand_matches("get") {
@@ -118,11 +130,14 @@ impl Account {
         self.get(false).await
     }
 
-    async fn login(&self, token: AccountToken) -> Result<()> {
+    async fn login(&self, token: AccountToken, in_memory: bool) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        rpc.login_account(token.clone())
-            .await
-            .map_err(map_device_error)?;
+        rpc.login_account(types::LoginRequest {
+            account_token: token.clone(),
+            in_memory,
+        })
+        .await
+        .map_err(map_device_error)?;
         println!("Mullvad account \"{}\" set", token);
         Ok(())
     }
@@ -147,11 +162,30 @@ impl Account {
 
         use types::device_state::State;
 
-        match State::from_i32(state.state).unwrap() {
+        match State::from_i32(state.state).ok_or(Error::InvalidResponse("invalid device state"))? {
             State::LoggedIn => {
-                let device = state.device.expect("Device must be provided if logged in");
+                let device = state
+                    .device
+                    .ok_or(Error::InvalidResponse("missing device"))?;
+                let inner_device = Self::parse_device(device.device)?;
+                let expiry = rpc
+                    .get_account_data(device.account_token.clone())
+                    .await
+                    .map_err(|error| Error::RpcFailedExt("Failed to fetch account data", error))?
+                    .into_inner();
+                let expiry = Self::expiry_to_utc(expiry.expiry.as_ref())?;
+
+                if json_output() {
+                    format::print_json(&serde_json::json!({
+                        "state": "logged_in",
+                        "account_token": device.account_token,
+                        "device": inner_device,
+                        "expiry": expiry,
+                    }));
+                    return Ok(());
+                }
+
                 println!("Mullvad account: {}", device.account_token);
-                let inner_device = Device::try_from(device.device.unwrap()).unwrap();
                 println!("Device name    : {}", inner_device.pretty_name());
                 if verbose {
                     println!("Device id      : {}", inner_device.id);
@@ -164,19 +198,17 @@ impl Account {
                         println!("Device port    : {}", port);
                     }
                 }
-                let expiry = rpc
-                    .get_account_data(device.account_token)
-                    .await
-                    .map_err(|error| Error::RpcFailedExt("Failed to fetch account data", error))?
-                    .into_inner();
-                println!(
-                    "Expires at     : {}",
-                    Self::format_expiry(&expiry.expiry.unwrap())
-                );
+                println!("Expires at     : {}", expiry.with_timezone(&chrono::Local));
+            }
+            State::LoggedOut if json_output() => {
+                format::print_json(&serde_json::json!({ "state": "logged_out" }));
             }
             State::LoggedOut => {
                 println!("{}", NOT_LOGGED_IN_MESSAGE);
             }
+            State::Revoked if json_output() => {
+                format::print_json(&serde_json::json!({ "state": "revoked" }));
+            }
             State::Revoked => {
                 println!("{}", REVOKED_MESSAGE);
             }
@@ -196,12 +228,23 @@ impl Account {
 
         let verbose = matches.is_present("verbose");
 
-        println!("Devices on the account:");
         device_list
             .devices
             .sort_unstable_by_key(|dev| dev.created.as_ref().map(|dt| dt.seconds).unwrap_or(0));
-        for device in device_list.devices {
-            let device = Device::try_from(device.clone()).unwrap();
+
+        let devices = device_list
+            .devices
+            .into_iter()
+            .map(|device| Self::parse_device(Some(device)))
+            .collect::<Result<Vec<_>>>()?;
+
+        if json_output() {
+            format::print_json(&devices);
+            return Ok(());
+        }
+
+        println!("Devices on the account:");
+        for device in devices {
             if verbose {
                 println!();
                 println!("Name      : {}", device.pretty_name());
@@ -269,7 +312,10 @@ impl Account {
                 if state.state != types::device_state::State::LoggedIn as i32 {
                     return Err(Error::Other("Log in or specify an account"));
                 }
-                Ok(state.device.unwrap().account_token)
+                state
+                    .device
+                    .map(|device| device.account_token)
+                    .ok_or(Error::InvalidResponse("missing device"))
             }
         }
     }
@@ -287,13 +333,13 @@ impl Account {
                 );
                 println!(
                     "New expiry date: {}",
-                    Self::format_expiry(&submission.new_expiry.unwrap())
+                    Self::format_expiry(submission.new_expiry.as_ref())?
                 );
                 Ok(())
             }
             Err(err) => {
-                match err.code() {
-                    Code::NotFound | Code::ResourceExhausted => {
+                match error_kind(&err) {
+                    Some(ErrorKind::InvalidVoucher | ErrorKind::UsedVoucher) => {
                         eprintln!("Failed to submit voucher: {}", err.message());
                     }
                     _ => return Err(Error::RpcFailed(err)),
@@ -316,20 +362,33 @@ impl Account {
         }
     }
 
-    fn format_expiry(expiry: &Timestamp) -> String {
+    fn format_expiry(expiry: Option<&Timestamp>) -> Result<String> {
+        Ok(Self::expiry_to_utc(expiry)?
+            .with_timezone(&chrono::Local)
+            .to_string())
+    }
+
+    fn expiry_to_utc(expiry: Option<&Timestamp>) -> Result<chrono::DateTime<chrono::Utc>> {
+        let expiry = expiry.ok_or(Error::InvalidResponse("missing expiry date"))?;
         let ndt = chrono::NaiveDateTime::from_timestamp(expiry.seconds, expiry.nanos as u32);
-        let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
-        utc.with_timezone(&chrono::Local).to_string()
+        Ok(chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc))
+    }
+
+    fn parse_device(device: Option<types::Device>) -> Result<Device> {
+        device
+            .and_then(|device| Device::try_from(device).ok())
+            .ok_or(Error::InvalidResponse("invalid device"))
     }
 }
 
 fn map_device_error(error: Status) -> Error {
-    match error.code() {
-        Code::ResourceExhausted => Error::Other(TOO_MANY_DEVICES_ERROR),
-        Code::Unauthenticated => Error::Other(INVALID_ACCOUNT_ERROR),
-        Code::AlreadyExists => Error::Other(ALREADY_LOGGED_IN_ERROR),
-        Code::NotFound => Error::Other(DEVICE_NOT_FOUND_ERROR),
-        _other => Error::RpcFailed(error),
+    match error_kind(&error) {
+        Some(ErrorKind::MaxDevicesReached) => Error::Other(TOO_MANY_DEVICES_ERROR),
+        Some(ErrorKind::InvalidAccount) => Error::Other(INVALID_ACCOUNT_ERROR),
+        Some(ErrorKind::NoAccount) => Error::Other(NOT_LOGGED_IN_MESSAGE),
+        Some(ErrorKind::AlreadyLoggedIn) => Error::Other(ALREADY_LOGGED_IN_ERROR),
+        Some(ErrorKind::DeviceNotFound) => Error::Other(DEVICE_NOT_FOUND_ERROR),
+        _ => Error::RpcFailed(error),
     }
 }
 
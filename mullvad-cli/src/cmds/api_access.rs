@@ -0,0 +1,275 @@
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::{types, ManagementServiceClient};
+use mullvad_types::access_method::AccessMethod;
+use std::convert::TryFrom;
+use talpid_types::net::{
+    obfs4::{Obfs4Settings, ParseError},
+    openvpn,
+};
+
+pub struct ApiAccess;
+
+#[mullvad_management_interface::async_trait]
+impl Command for ApiAccess {
+    fn name(&self) -> &'static str {
+        "api-access"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name())
+            .about(
+                "Manage proxies that are tried, in addition to the built-in methods, when the \
+                 API cannot be reached",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::App::new("list").about("Display all access methods"))
+            .subcommand(
+                clap::App::new("add")
+                    .about("Add an access method")
+                    .arg(name_arg())
+                    .arg(clap::Arg::new("uri").required(true).index(2).help(
+                        "URI of the proxy: socks5://[username:password@]ip:port, \
+                         http://[username:password@]ip:port or a Shadowsocks URI. HTTP proxies \
+                         must support the CONNECT method. obfs4 bridges are given as quoted \
+                         bridge lines: \"obfs4 ip:port cert=<cert> iat-mode=<mode>\"",
+                    ))
+                    .arg(
+                        clap::Arg::new("disabled")
+                            .long("disabled")
+                            .help("Add the method without using it yet"),
+                    )
+                    .arg(clap::Arg::new("udp").long("udp").help(
+                        "Reach a Shadowsocks proxy over UDP. Requests are then sent over HTTP/3",
+                    )),
+            )
+            .subcommand(
+                clap::App::new("enable")
+                    .about("Use an access method when the API cannot be reached")
+                    .arg(name_arg()),
+            )
+            .subcommand(
+                clap::App::new("disable")
+                    .about("Stop using an access method, without removing it")
+                    .arg(name_arg()),
+            )
+            .subcommand(
+                clap::App::new("remove")
+                    .about("Remove an access method")
+                    .arg(name_arg()),
+            )
+            .subcommand(
+                clap::App::new("test")
+                    .about("Check whether the API can be reached using an access method")
+                    .arg(name_arg()),
+            )
+            .subcommand(
+                clap::App::new("pools").about("Display usage counters of the API connection pools"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            Some(("list", _)) => self.list().await,
+            Some(("add", matches)) => self.add(matches).await,
+            Some(("enable", matches)) => self.set_enabled(matches, true).await,
+            Some(("disable", matches)) => self.set_enabled(matches, false).await,
+            Some(("remove", matches)) => self.remove(matches).await,
+            Some(("test", matches)) => self.test(matches).await,
+            Some(("pools", _)) => self.pools().await,
+            _ => unreachable!("No API access command given"),
+        }
+    }
+}
+
+impl ApiAccess {
+    async fn list(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let methods = get_methods(&mut rpc).await?;
+        if json_output() {
+            format::print_json(&methods);
+            return Ok(());
+        }
+        if methods.is_empty() {
+            println!("No access methods");
+        }
+        for method in methods {
+            println!("{}", method);
+        }
+        Ok(())
+    }
+
+    async fn add(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let name = matches.value_of("name").unwrap().to_owned();
+        let uri = matches.value_of("uri").unwrap().to_owned();
+
+        let udp = matches.is_present("udp");
+
+        let mut rpc = new_rpc_client().await?;
+        let is_http = uri
+            .trim()
+            .get(..7)
+            .map(|scheme| scheme.eq_ignore_ascii_case("http://"))
+            .unwrap_or(false);
+        let is_obfs4 = uri.trim_start().starts_with("obfs4 ");
+        let proxy = if is_http || is_obfs4 {
+            if udp {
+                return Err(Error::InvalidCommand(
+                    "Only Shadowsocks proxies can be reached over UDP",
+                ));
+            }
+            if is_http {
+                types::api_access_method::Proxy::Http(parse_http_proxy(&uri)?)
+            } else {
+                types::api_access_method::Proxy::Obfs4(parse_obfs4_bridge(&uri)?)
+            }
+        } else {
+            match rpc.parse_bridge_uri(uri).await?.into_inner().r#type {
+                Some(types::bridge_settings::Type::Shadowsocks(settings)) => {
+                    types::api_access_method::Proxy::Shadowsocks(
+                        types::bridge_settings::ShadowsocksProxySettings { udp, ..settings },
+                    )
+                }
+                Some(types::bridge_settings::Type::Remote(_)) if udp => {
+                    return Err(Error::InvalidCommand(
+                        "Only Shadowsocks proxies can be reached over UDP",
+                    ))
+                }
+                Some(types::bridge_settings::Type::Remote(settings)) => {
+                    types::api_access_method::Proxy::Socks5(settings)
+                }
+                _ => {
+                    return Err(Error::InvalidCommand(
+                        "Only SOCKS5, HTTP, Shadowsocks and obfs4 proxies are supported",
+                    ))
+                }
+            }
+        };
+        rpc.add_api_access_method(types::ApiAccessMethod {
+            name: name.clone(),
+            enabled: !matches.is_present("disabled"),
+            proxy: Some(proxy),
+        })
+        .await?;
+        println!("Added access method \"{}\"", name);
+        Ok(())
+    }
+
+    async fn set_enabled(&self, matches: &clap::ArgMatches, enabled: bool) -> Result<()> {
+        let name = matches.value_of("name").unwrap();
+        let mut rpc = new_rpc_client().await?;
+        let mut method = get_methods(&mut rpc)
+            .await?
+            .into_iter()
+            .find(|method| method.name == name)
+            .ok_or(Error::InvalidCommand(
+                "There is no access method with that name",
+            ))?;
+        method.enabled = enabled;
+        rpc.update_api_access_method(types::ApiAccessMethodUpdate {
+            name: name.to_owned(),
+            method: Some(types::ApiAccessMethod::from(&method)),
+        })
+        .await?;
+        println!("{}", method);
+        Ok(())
+    }
+
+    async fn remove(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let name = matches.value_of("name").unwrap().to_owned();
+        let mut rpc = new_rpc_client().await?;
+        rpc.remove_api_access_method(name.clone()).await?;
+        println!("Removed access method \"{}\"", name);
+        Ok(())
+    }
+
+    async fn test(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let name = matches.value_of("name").unwrap().to_owned();
+        let mut rpc = new_rpc_client().await?;
+        println!("Testing access method \"{}\"...", name);
+        if rpc.test_api_access_method(name).await?.into_inner() {
+            println!("The API could be reached");
+            Ok(())
+        } else {
+            Err(Error::CommandFailed(
+                "The API could not be reached using the access method",
+            ))
+        }
+    }
+
+    async fn pools(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let pools = rpc.get_api_connection_pools(()).await?.into_inner().pools;
+        if json_output() {
+            let pools: Vec<_> = pools
+                .iter()
+                .map(|pool| {
+                    serde_json::json!({
+                        "connection_mode": pool.connection_mode,
+                        "interface": Some(&pool.interface).filter(|name| !name.is_empty()),
+                        "requests": pool.requests,
+                        "connections_opened": pool.connections_opened,
+                    })
+                })
+                .collect();
+            format::print_json(&pools);
+            return Ok(());
+        }
+        if pools.is_empty() {
+            println!("No connection pools");
+        }
+        for pool in pools {
+            let interface = if pool.interface.is_empty() {
+                String::new()
+            } else {
+                format!(" through {}", pool.interface)
+            };
+            println!(
+                "{}{}: {} requests, {} connections opened",
+                pool.connection_mode, interface, pool.requests, pool.connections_opened
+            );
+        }
+        Ok(())
+    }
+}
+
+fn parse_http_proxy(uri: &str) -> Result<types::bridge_settings::RemoteProxySettings> {
+    let settings = openvpn::parse_http_proxy_uri(uri).map_err(|error| {
+        eprintln!("{}", error);
+        Error::InvalidCommand("Invalid HTTP proxy URI")
+    })?;
+    Ok(types::bridge_settings::RemoteProxySettings {
+        address: settings.address.to_string(),
+        auth: settings
+            .auth
+            .map(|auth| types::bridge_settings::RemoteProxyAuth {
+                username: auth.username,
+                password: auth.password,
+            }),
+    })
+}
+
+fn parse_obfs4_bridge(bridge_line: &str) -> Result<types::api_access_method::Obfs4Settings> {
+    let settings: Obfs4Settings = bridge_line.parse().map_err(|error: ParseError| {
+        eprintln!("{}", error);
+        Error::InvalidCommand("Invalid obfs4 bridge line")
+    })?;
+    Ok(types::api_access_method::Obfs4Settings::from(&settings))
+}
+
+fn name_arg() -> clap::Arg<'static> {
+    clap::Arg::new("name")
+        .required(true)
+        .index(1)
+        .help("Name of the access method")
+}
+
+async fn get_methods(rpc: &mut ManagementServiceClient) -> Result<Vec<AccessMethod>> {
+    let methods = rpc
+        .get_settings(())
+        .await?
+        .into_inner()
+        .api_access_methods
+        .unwrap_or_default();
+    Vec::<AccessMethod>::try_from(methods)
+        .map_err(|_| Error::InvalidResponse("failed to parse access methods"))
+}
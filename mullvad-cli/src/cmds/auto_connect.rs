@@ -1,4 +1,7 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::types;
+use mullvad_types::auto_connect::{AutoConnectPolicy, AutoConnectRule};
+use std::convert::TryFrom;
 
 pub struct AutoConnect;
 
@@ -21,17 +24,69 @@ impl Command for AutoConnect {
                             .possible_values(["on", "off"]),
                     ),
             )
+            .subcommand(
+                clap::App::new("rule")
+                    .about("Set the rule that decides on which networks to auto-connect")
+                    .arg(clap::Arg::new("rule").required(true).possible_values([
+                        "always",
+                        "never",
+                        "untrusted-wifi-only",
+                        "wired-exempt",
+                    ])),
+            )
+            .subcommand(
+                clap::App::new("trusted-network")
+                    .about("Manage Wi-Fi networks on which not to auto-connect")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("add")
+                            .about("Trust a Wi-Fi network")
+                            .arg(clap::Arg::new("ssid").required(true)),
+                    )
+                    .subcommand(
+                        clap::App::new("remove")
+                            .about("Stop trusting a Wi-Fi network")
+                            .arg(clap::Arg::new("ssid").required(true)),
+                    ),
+            )
             .subcommand(clap::App::new("get").about("Display the current auto-connect setting"))
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
-        if let Some(set_matches) = matches.subcommand_matches("set") {
-            let auto_connect = set_matches.value_of("policy").expect("missing policy");
-            self.set(auto_connect == "on").await
-        } else if let Some(_matches) = matches.subcommand_matches("get") {
-            self.get().await
-        } else {
-            unreachable!("No auto-connect command given");
+        match matches.subcommand() {
+            Some(("set", matches)) => {
+                let auto_connect = matches.value_of("policy").expect("missing policy");
+                self.set(auto_connect == "on").await
+            }
+            Some(("rule", matches)) => {
+                let rule = match matches.value_of("rule").expect("missing rule") {
+                    "always" => AutoConnectRule::Always,
+                    "never" => AutoConnectRule::Never,
+                    "untrusted-wifi-only" => AutoConnectRule::UntrustedWifiOnly,
+                    "wired-exempt" => AutoConnectRule::WiredExempt,
+                    _ => unreachable!("invalid auto-connect rule"),
+                };
+                self.update_policy(|policy| policy.rule = rule).await
+            }
+            Some(("trusted-network", matches)) => match matches.subcommand() {
+                Some(("add", matches)) => {
+                    let ssid = matches.value_of("ssid").expect("missing SSID").to_owned();
+                    self.update_policy(|policy| {
+                        policy.trusted_networks.insert(ssid);
+                    })
+                    .await
+                }
+                Some(("remove", matches)) => {
+                    let ssid = matches.value_of("ssid").expect("missing SSID");
+                    self.update_policy(|policy| {
+                        policy.trusted_networks.remove(ssid);
+                    })
+                    .await
+                }
+                _ => unreachable!("No trusted-network command given"),
+            },
+            Some(("get", _)) => self.get().await,
+            _ => unreachable!("No auto-connect command given"),
         }
     }
 }
@@ -44,10 +99,45 @@ impl AutoConnect {
         Ok(())
     }
 
+    async fn update_policy(&self, update: impl FnOnce(&mut AutoConnectPolicy)) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut policy =
+            Self::parse_policy(rpc.get_settings(()).await?.into_inner().auto_connect_policy)?;
+        update(&mut policy);
+        rpc.set_auto_connect_policy(types::AutoConnectPolicy::from(&policy))
+            .await?;
+        println!("Changed auto-connect policy");
+        Ok(())
+    }
+
     async fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let auto_connect = rpc.get_settings(()).await?.into_inner().auto_connect;
-        println!("Autoconnect: {}", if auto_connect { "on" } else { "off" });
+        let settings = rpc.get_settings(()).await?.into_inner();
+        let policy = Self::parse_policy(settings.auto_connect_policy)?;
+        if json_output() {
+            format::print_json(&serde_json::json!({
+                "auto_connect": settings.auto_connect,
+                "policy": policy,
+            }));
+            return Ok(());
+        }
+        println!(
+            "Autoconnect: {}",
+            if settings.auto_connect { "on" } else { "off" }
+        );
+        println!("Rule: {}", policy.rule);
+        if !policy.trusted_networks.is_empty() {
+            println!("Trusted networks:");
+            for ssid in &policy.trusted_networks {
+                println!("\t{}", ssid);
+            }
+        }
         Ok(())
     }
+
+    fn parse_policy(policy: Option<types::AutoConnectPolicy>) -> Result<AutoConnectPolicy> {
+        policy
+            .and_then(|policy| AutoConnectPolicy::try_from(policy).ok())
+            .ok_or(Error::InvalidResponse("invalid auto-connect policy"))
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{new_rpc_client, Command, Error, Result};
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
 
 pub struct BetaProgram;
 
@@ -29,6 +29,10 @@ impl Command for BetaProgram {
             Some(("get", _)) => {
                 let mut rpc = new_rpc_client().await?;
                 let settings = rpc.get_settings(()).await?.into_inner();
+                if json_output() {
+                    format::print_json(&settings.show_beta_releases);
+                    return Ok(());
+                }
                 let enabled_str = if settings.show_beta_releases {
                     "on"
                 } else {
@@ -1,4 +1,4 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{format, json_output, new_rpc_client, Command, Result};
 
 pub struct BlockWhenDisconnected;
 
@@ -55,6 +55,10 @@ impl BlockWhenDisconnected {
             .await?
             .into_inner()
             .block_when_disconnected;
+        if json_output() {
+            format::print_json(&block_when_disconnected);
+            return Ok(());
+        }
         println!(
             "Network traffic will be {} when the VPN is disconnected",
             if block_when_disconnected {
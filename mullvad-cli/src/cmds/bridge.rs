@@ -1,10 +1,13 @@
-use crate::{location, new_rpc_client, Command, Error, Result};
+use crate::{format, json_output, location, new_rpc_client, Command, Error, Result};
 
 use mullvad_management_interface::types;
 use mullvad_types::relay_constraints::{
     BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
 };
-use talpid_types::net::openvpn::{self, SHADOWSOCKS_CIPHERS};
+use talpid_types::net::{
+    openvpn::{self, SHADOWSOCKS_CIPHERS},
+    TransportProtocol,
+};
 
 use std::{convert::TryFrom, net::SocketAddr};
 
@@ -178,6 +181,19 @@ fn create_set_custom_settings_subcommand() -> clap::App<'static> {
                         .index(4),
                 ),
         )
+        .subcommand(
+            clap::App::new("uri")
+                .about("Configure a SOCKS5 or Shadowsocks proxy from a URI given by a provider")
+                .arg(
+                    clap::Arg::new("uri")
+                        .help(
+                            "Specifies the proxy as 'socks5://[username:password@]ip:port' or \
+                             as an 'ss://' URI",
+                        )
+                        .required(true)
+                        .index(1),
+                ),
+        )
 }
 
 fn create_set_state_subcommand() -> clap::App<'static> {
@@ -213,11 +229,19 @@ impl Bridge {
     async fn handle_get() -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let settings = rpc.get_settings(()).await?.into_inner();
-        let bridge_settings = BridgeSettings::try_from(settings.bridge_settings.unwrap()).unwrap();
-        println!(
-            "Bridge state: {}",
-            BridgeState::try_from(settings.bridge_state.unwrap()).unwrap()
-        );
+        let bridge_settings = Self::parse_bridge_settings(settings.bridge_settings)?;
+        let bridge_state = settings
+            .bridge_state
+            .and_then(|state| BridgeState::try_from(state).ok())
+            .ok_or(Error::InvalidResponse("invalid bridge state"))?;
+        if json_output() {
+            format::print_json(&serde_json::json!({
+                "state": bridge_state,
+                "settings": bridge_settings,
+            }));
+            return Ok(());
+        }
+        println!("Bridge state: {}", bridge_state);
         match bridge_settings {
             BridgeSettings::Custom(proxy) => match proxy {
                 openvpn::ProxySettings::Local(local_proxy) => Self::print_local_proxy(&local_proxy),
@@ -269,7 +293,7 @@ impl Bridge {
         let mut rpc = new_rpc_client().await?;
         let settings = rpc.get_settings(()).await?.into_inner();
 
-        let bridge_settings = BridgeSettings::try_from(settings.bridge_settings.unwrap()).unwrap();
+        let bridge_settings = Self::parse_bridge_settings(settings.bridge_settings)?;
         let constraints = match bridge_settings {
             BridgeSettings::Normal(mut constraints) => {
                 if let Some(new_location) = location {
@@ -313,6 +337,12 @@ impl Bridge {
         Ok(())
     }
 
+    fn parse_bridge_settings(settings: Option<types::BridgeSettings>) -> Result<BridgeSettings> {
+        settings
+            .and_then(|settings| BridgeSettings::try_from(settings).ok())
+            .ok_or(Error::InvalidResponse("invalid bridge settings"))
+    }
+
     async fn handle_set_bridge_state(matches: &clap::ArgMatches) -> Result<()> {
         let state = match matches.value_of("policy").unwrap() {
             "auto" => BridgeState::Auto,
@@ -383,6 +413,7 @@ impl Bridge {
                 peer: SocketAddr::new(remote_ip, remote_port),
                 password,
                 cipher,
+                protocol: TransportProtocol::Tcp,
                 #[cfg(target_os = "linux")]
                 fwmark: None,
             };
@@ -396,6 +427,12 @@ impl Bridge {
                 packed_proxy,
             )))
             .await?;
+        } else if let Some(args) = matches.subcommand_matches("uri") {
+            let uri = args.value_of("uri").unwrap().to_owned();
+
+            let mut rpc = new_rpc_client().await?;
+            let settings = rpc.parse_bridge_uri(uri).await?.into_inner();
+            rpc.set_bridge_settings(settings).await?;
         } else {
             unreachable!("unhandled proxy type");
         }
@@ -461,6 +498,9 @@ impl Bridge {
         }
 
         countries.sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
+        if json_output() {
+            return super::relay::print_countries_json(countries);
+        }
         for mut country in countries {
             country
                 .cities
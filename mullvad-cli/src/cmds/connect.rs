@@ -13,12 +13,10 @@ impl Command for Connect {
     fn clap_subcommand(&self) -> clap::App<'static> {
         clap::App::new(self.name())
             .about("Command the client to start establishing a VPN tunnel")
-            .arg(
-                clap::Arg::new("wait")
-                    .long("wait")
-                    .short('w')
-                    .help("Wait until connected before exiting"),
-            )
+            .arg(clap::Arg::new("wait").long("wait").short('w').help(
+                "Wait until connected before exiting. If the tunnel fails, the exit \
+                 code is 2 if traffic is blocked and 3 otherwise",
+            ))
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
@@ -37,7 +35,9 @@ impl Command for Connect {
                     format::print_state(&state, false);
                     match state {
                         TunnelState::Connected { .. } => return Ok(()),
-                        TunnelState::Error(_) => return Err(Error::CommandFailed("connect")),
+                        TunnelState::Error(error_state) => {
+                            return Err(Error::TunnelErrorState(error_state))
+                        }
                         _ => {}
                     }
                 }
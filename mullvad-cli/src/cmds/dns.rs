@@ -1,7 +1,8 @@
-use crate::{new_rpc_client, Command, Result};
-use mullvad_management_interface::types;
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::{types, ManagementServiceClient};
 use mullvad_types::settings::{DnsOptions, DnsState};
-use std::{convert::TryInto, net::IpAddr};
+use std::{convert::TryFrom, net::IpAddr};
+use talpid_types::net::DnsBackendHealth;
 
 pub struct Dns;
 
@@ -16,6 +17,10 @@ impl Command for Dns {
             .about("Configure DNS servers to use when connected")
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(clap::App::new("get").about("Display the current DNS settings"))
+            .subcommand(
+                clap::App::new("check")
+                    .about("Check whether the system DNS backend is working as expected"),
+            )
             .subcommand(
                 clap::App::new("set")
                     .about("Set DNS servers to use")
@@ -60,7 +65,10 @@ impl Command for Dns {
                             .arg(
                                 clap::Arg::new("servers")
                                     .multiple_occurrences(true)
-                                    .help("One or more IP addresses pointing to DNS resolvers.")
+                                    .help(
+                                        "One or more IP addresses pointing to DNS resolvers, in \
+                                         order of preference",
+                                    )
                                     .required(true),
                             ),
                     ),
@@ -93,6 +101,7 @@ impl Command for Dns {
                 _ => unreachable!("No custom-dns server command given"),
             },
             Some(("get", _)) => self.get().await,
+            Some(("check", _)) => self.check().await,
             _ => unreachable!("No custom-dns command given"),
         }
     }
@@ -108,7 +117,7 @@ impl Dns {
         block_gambling: bool,
     ) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let settings = rpc.get_settings(()).await?.into_inner();
+        let options = get_dns_options(&mut rpc).await?;
         rpc.set_dns_options(types::DnsOptions {
             state: types::dns_options::DnsState::Default as i32,
             default_options: Some(types::DefaultDnsOptions {
@@ -118,7 +127,7 @@ impl Dns {
                 block_adult_content,
                 block_gambling,
             }),
-            ..settings.tunnel_options.unwrap().dns_options.unwrap()
+            ..options
         })
         .await?;
         println!("Updated DNS settings");
@@ -127,7 +136,7 @@ impl Dns {
 
     async fn set_custom(&self, servers: Option<Vec<IpAddr>>) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let settings = rpc.get_settings(()).await?.into_inner();
+        let options = get_dns_options(&mut rpc).await?;
         rpc.set_dns_options(types::DnsOptions {
             state: types::dns_options::DnsState::Custom as i32,
             custom_options: Some(types::CustomDnsOptions {
@@ -137,7 +146,7 @@ impl Dns {
                     .map(|a| a.to_string())
                     .collect(),
             }),
-            ..settings.tunnel_options.unwrap().dns_options.unwrap()
+            ..options
         })
         .await?;
         println!("Updated DNS settings");
@@ -146,16 +155,13 @@ impl Dns {
 
     async fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let options: DnsOptions = rpc
-            .get_settings(())
-            .await?
-            .into_inner()
-            .tunnel_options
-            .unwrap()
-            .dns_options
-            .unwrap()
-            .try_into()
-            .unwrap();
+        let options = DnsOptions::try_from(get_dns_options(&mut rpc).await?)
+            .map_err(|_| Error::InvalidResponse("invalid DNS options"))?;
+
+        if json_output() {
+            format::print_json(&options);
+            return Ok(());
+        }
 
         match options.state {
             DnsState::Default => {
@@ -179,4 +185,25 @@ impl Dns {
 
         Ok(())
     }
+
+    async fn check(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let health = DnsBackendHealth::try_from(rpc.check_dns_backend(()).await?.into_inner())
+            .map_err(|_| Error::InvalidResponse("invalid DNS backend health"))?;
+        if json_output() {
+            format::print_json(&health);
+            return Ok(());
+        }
+        println!("{}", health);
+        Ok(())
+    }
+}
+
+async fn get_dns_options(rpc: &mut ManagementServiceClient) -> Result<types::DnsOptions> {
+    rpc.get_settings(())
+        .await?
+        .into_inner()
+        .tunnel_options
+        .and_then(|options| options.dns_options)
+        .ok_or(Error::InvalidResponse("missing DNS options"))
 }
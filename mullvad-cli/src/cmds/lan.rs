@@ -1,7 +1,27 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::{types, ManagementServiceClient};
+use std::net::IpAddr;
+use talpid_types::net::AllowedLinkConfigTraffic;
 
 pub struct Lan;
 
+/// Kinds of DHCP and NDP traffic that can be allowed or blocked, and their descriptions.
+const LINK_CONFIG_KINDS: [(&str, &str); 4] = [
+    (
+        "dhcpv4",
+        "DHCPv4 requests broadcast by this computer, and the responses",
+    ),
+    (
+        "dhcpv6",
+        "DHCPv6 requests sent to the DHCPv6 multicast groups, and the responses",
+    ),
+    (
+        "dhcp-unicast",
+        "DHCP messages sent directly to a server, such as some lease renewals",
+    ),
+    ("ndp", "IPv6 router and neighbor discovery"),
+];
+
 #[mullvad_management_interface::async_trait]
 impl Command for Lan {
     fn name(&self) -> &'static str {
@@ -22,6 +42,92 @@ impl Command for Lan {
             .subcommand(
                 clap::App::new("get").about("Display the current local network sharing setting"),
             )
+            .subcommand(
+                clap::App::new("multicast")
+                    .about(
+                        "Control which multicast groups may be used while connected, even if \
+                         local network sharing is blocked. Only supported on Linux and macOS",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("set")
+                            .about("Replace the allowed multicast groups")
+                            .arg(
+                                clap::Arg::new("groups")
+                                    .multiple_values(true)
+                                    .help("Group addresses. Omit to disallow all groups"),
+                            ),
+                    )
+                    .subcommand(
+                        clap::App::new("get").about("Display the allowed multicast groups"),
+                    ),
+            )
+            .subcommand(
+                clap::App::new("hosts")
+                    .about(
+                        "Control which hosts on the local network are reachable even if local \
+                         network sharing is blocked",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("set")
+                            .about("Replace the allowed hosts")
+                            .arg(
+                                clap::Arg::new("addresses")
+                                    .multiple_values(true)
+                                    .help("Private IP addresses. Omit to disallow all hosts"),
+                            ),
+                    )
+                    .subcommand(clap::App::new("get").about("Display the allowed hosts")),
+            )
+            .subcommand(
+                clap::App::new("link-config")
+                    .about(
+                        "Control which DHCP and NDP traffic is allowed on all network interfaces, \
+                         even while other traffic is blocked. Only supported on Linux and macOS",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("set")
+                            .about(
+                                "Allow or block kinds of traffic. Kinds that are not given are \
+                                 left unchanged",
+                            )
+                            .setting(clap::AppSettings::ArgRequiredElseHelp)
+                            .args(LINK_CONFIG_KINDS.iter().map(|(name, help)| {
+                                clap::Arg::new(*name)
+                                    .long(*name)
+                                    .takes_value(true)
+                                    .possible_values(["allow", "block"])
+                                    .help(*help)
+                            })),
+                    )
+                    .subcommand(
+                        clap::App::new("get").about("Display which kinds of traffic are allowed"),
+                    ),
+            )
+            .subcommand(
+                clap::App::new("share")
+                    .about(
+                        "Let devices on a network interface use the tunnel through this computer, \
+                         by setting it as their gateway. Their traffic is only forwarded while \
+                         connected. Only supported on Linux",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("set")
+                            .about("Share the tunnel with the devices on an interface")
+                            .arg(
+                                clap::Arg::new("interface")
+                                    .required(true)
+                                    .help("Name of the interface, such as eth1"),
+                            ),
+                    )
+                    .subcommand(clap::App::new("off").about("Stop sharing the tunnel"))
+                    .subcommand(
+                        clap::App::new("get").about("Display the interface that is shared with"),
+                    ),
+            )
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
@@ -30,6 +136,64 @@ impl Command for Lan {
             self.set(allow_lan == "allow").await
         } else if let Some(_matches) = matches.subcommand_matches("get") {
             self.get().await
+        } else if let Some(multicast_matches) = matches.subcommand_matches("multicast") {
+            match multicast_matches.subcommand() {
+                Some(("set", set_matches)) => {
+                    let groups = set_matches
+                        .values_of("groups")
+                        .map(|groups| {
+                            groups
+                                .map(|group| match group.parse::<IpAddr>() {
+                                    Ok(addr) if addr.is_multicast() => Ok(addr),
+                                    _ => Err(Error::InvalidCommand("invalid multicast address")),
+                                })
+                                .collect::<Result<Vec<_>>>()
+                        })
+                        .transpose()?
+                        .unwrap_or_default();
+                    self.set_multicast_groups(groups).await
+                }
+                Some(("get", _)) => self.get_multicast_groups().await,
+                _ => unreachable!("No multicast command given"),
+            }
+        } else if let Some(hosts_matches) = matches.subcommand_matches("hosts") {
+            match hosts_matches.subcommand() {
+                Some(("set", set_matches)) => {
+                    let hosts = set_matches
+                        .values_of("addresses")
+                        .map(|hosts| {
+                            hosts
+                                .map(|host| {
+                                    host.parse::<IpAddr>()
+                                        .map_err(|_| Error::InvalidCommand("invalid IP address"))
+                                })
+                                .collect::<Result<Vec<_>>>()
+                        })
+                        .transpose()?
+                        .unwrap_or_default();
+                    self.set_lan_hosts(hosts).await
+                }
+                Some(("get", _)) => self.get_lan_hosts().await,
+                _ => unreachable!("No hosts command given"),
+            }
+        } else if let Some(link_config_matches) = matches.subcommand_matches("link-config") {
+            match link_config_matches.subcommand() {
+                Some(("set", set_matches)) => self.set_link_config(set_matches).await,
+                Some(("get", _)) => self.get_link_config().await,
+                _ => unreachable!("No link-config command given"),
+            }
+        } else if let Some(share_matches) = matches.subcommand_matches("share") {
+            match share_matches.subcommand() {
+                Some(("set", set_matches)) => {
+                    let interface = set_matches
+                        .value_of("interface")
+                        .expect("missing interface");
+                    self.set_shared_interface(Some(interface.to_owned())).await
+                }
+                Some(("off", _)) => self.set_shared_interface(None).await,
+                Some(("get", _)) => self.get_shared_interface().await,
+                _ => unreachable!("No share command given"),
+            }
         } else {
             unreachable!("No lan command given");
         }
@@ -47,10 +211,148 @@ impl Lan {
     async fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let allow_lan = rpc.get_settings(()).await?.into_inner().allow_lan;
+        if json_output() {
+            format::print_json(&allow_lan);
+            return Ok(());
+        }
         println!(
             "Local network sharing setting: {}",
             if allow_lan { "allow" } else { "block" }
         );
         Ok(())
     }
+
+    async fn set_multicast_groups(&self, groups: Vec<IpAddr>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_allowed_multicast_groups(types::MulticastGroups::from(&groups[..]))
+            .await?;
+        println!("Changed allowed multicast groups");
+        Ok(())
+    }
+
+    async fn get_multicast_groups(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let groups = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .allowed_multicast_groups
+            .map(|groups| groups.addresses)
+            .unwrap_or_default();
+        if json_output() {
+            format::print_json(&groups);
+            return Ok(());
+        }
+        if groups.is_empty() {
+            println!("No multicast groups are allowed");
+        }
+        for group in groups {
+            println!("{}", group);
+        }
+        Ok(())
+    }
+
+    async fn set_lan_hosts(&self, hosts: Vec<IpAddr>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_allowed_lan_hosts(types::LanHosts::from(&hosts[..]))
+            .await?;
+        println!("Changed allowed local network hosts");
+        Ok(())
+    }
+
+    async fn get_lan_hosts(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let hosts = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .allowed_lan_hosts
+            .map(|hosts| hosts.addresses)
+            .unwrap_or_default();
+        if json_output() {
+            format::print_json(&hosts);
+            return Ok(());
+        }
+        if hosts.is_empty() {
+            println!("No local network hosts are allowed");
+        }
+        for host in hosts {
+            println!("{}", host);
+        }
+        Ok(())
+    }
+
+    async fn set_link_config(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut allowed = get_link_config(&mut rpc).await?;
+        let update = |allow: &mut bool, name: &str| {
+            if let Some(policy) = matches.value_of(name) {
+                *allow = policy == "allow";
+            }
+        };
+        update(&mut allowed.dhcpv4, "dhcpv4");
+        update(&mut allowed.dhcpv6, "dhcpv6");
+        update(&mut allowed.dhcp_unicast, "dhcp-unicast");
+        update(&mut allowed.ndp, "ndp");
+        rpc.set_allowed_link_config_traffic(types::AllowedLinkConfigTraffic::from(allowed))
+            .await?;
+        println!("Changed allowed DHCP and NDP traffic");
+        Ok(())
+    }
+
+    async fn set_shared_interface(&self, interface: Option<String>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_connection_sharing(types::ConnectionSharing { interface })
+            .await?;
+        println!("Changed connection sharing setting");
+        Ok(())
+    }
+
+    async fn get_shared_interface(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let interface = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .connection_sharing
+            .and_then(|sharing| sharing.interface);
+        if json_output() {
+            format::print_json(&interface);
+            return Ok(());
+        }
+        match interface {
+            Some(interface) => println!("Sharing the tunnel with devices on {}", interface),
+            None => println!("The tunnel is not shared"),
+        }
+        Ok(())
+    }
+
+    async fn get_link_config(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let allowed = get_link_config(&mut rpc).await?;
+        if json_output() {
+            format::print_json(&allowed);
+            return Ok(());
+        }
+        let policies = [
+            allowed.dhcpv4,
+            allowed.dhcpv6,
+            allowed.dhcp_unicast,
+            allowed.ndp,
+        ];
+        for ((name, _), allow) in LINK_CONFIG_KINDS.iter().zip(policies) {
+            println!("{}: {}", name, if allow { "allow" } else { "block" });
+        }
+        Ok(())
+    }
+}
+
+async fn get_link_config(rpc: &mut ManagementServiceClient) -> Result<AllowedLinkConfigTraffic> {
+    Ok(rpc
+        .get_settings(())
+        .await?
+        .into_inner()
+        .allowed_link_config_traffic
+        .map(AllowedLinkConfigTraffic::from)
+        .unwrap_or_default())
 }
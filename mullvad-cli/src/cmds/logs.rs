@@ -0,0 +1,70 @@
+use crate::{new_rpc_client, Command, Result};
+use mullvad_management_interface::types::{self, Timestamp};
+
+pub struct Logs;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Logs {
+    fn name(&self) -> &'static str {
+        "logs"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name())
+            .about(
+                "Show messages from the daemon log as they are logged. Account numbers and \
+                 addresses are redacted",
+            )
+            .arg(
+                clap::Arg::new("level")
+                    .long("level")
+                    .takes_value(true)
+                    .default_value("info")
+                    .possible_values(["trace", "debug", "info", "warn", "error"])
+                    .help("Only show messages of this level or higher"),
+            )
+            .arg(
+                clap::Arg::new("module")
+                    .long("module")
+                    .takes_value(true)
+                    .help("Only show messages from this module, such as talpid_core::firewall"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let level = match matches.value_of("level").unwrap() {
+            "trace" => types::LogLevel::Trace,
+            "debug" => types::LogLevel::Debug,
+            "info" => types::LogLevel::Info,
+            "warn" => types::LogLevel::Warn,
+            "error" => types::LogLevel::Error,
+            _ => unreachable!("Unhandled log level"),
+        };
+        let filter = types::LogFilter {
+            level: i32::from(level),
+            module: matches.value_of("module").unwrap_or_default().to_owned(),
+        };
+
+        let mut rpc = new_rpc_client().await?;
+        let mut messages = rpc.tail_logs(filter).await?.into_inner();
+        while let Some(message) = messages.message().await? {
+            let level = types::LogLevel::from_i32(message.level).unwrap_or(types::LogLevel::Trace);
+            println!(
+                "[{}][{}][{}] {}",
+                message.time.as_ref().map(format_time).unwrap_or_default(),
+                message.module,
+                level.as_str_name(),
+                message.message
+            );
+        }
+        Ok(())
+    }
+}
+
+fn format_time(time: &Timestamp) -> String {
+    let ndt = chrono::NaiveDateTime::from_timestamp(time.seconds, time.nanos as u32);
+    let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
+    utc.with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M:%S%.3f")
+        .to_string()
+}
@@ -1,9 +1,17 @@
 use crate::Command;
 use std::collections::HashMap;
 
+#[cfg(unix)]
+mod access;
+#[cfg(unix)]
+pub use self::access::Access;
+
 mod account;
 pub use self::account::Account;
 
+mod api_access;
+pub use self::api_access::ApiAccess;
+
 mod auto_connect;
 pub use self::auto_connect::AutoConnect;
 
@@ -28,9 +36,21 @@ pub use self::dns::Dns;
 mod lan;
 pub use self::lan::Lan;
 
+mod logs;
+pub use self::logs::Logs;
+
+mod network_profile;
+pub use self::network_profile::NetworkProfile;
+
+mod notifications;
+pub use self::notifications::Notifications;
+
 mod obfuscation;
 pub use self::obfuscation::Obfuscation;
 
+mod privacy;
+pub use self::privacy::Privacy;
+
 mod reconnect;
 pub use self::reconnect::Reconnect;
 
@@ -57,7 +77,10 @@ pub use self::version::Version;
 /// Returns a map of all available subcommands with their name as key.
 pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
     let commands: Vec<Box<dyn Command>> = vec![
+        #[cfg(unix)]
+        Box::new(Access),
         Box::new(Account),
+        Box::new(ApiAccess),
         Box::new(AutoConnect),
         Box::new(BetaProgram),
         Box::new(BlockWhenDisconnected),
@@ -67,7 +90,11 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
         Box::new(Dns),
         Box::new(Reconnect),
         Box::new(Lan),
+        Box::new(Logs),
+        Box::new(NetworkProfile),
+        Box::new(Notifications),
         Box::new(Obfuscation),
+        Box::new(Privacy),
         Box::new(Relay),
         Box::new(Reset),
         #[cfg(any(target_os = "linux", windows))]
@@ -0,0 +1,188 @@
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::{types, ManagementServiceClient};
+use mullvad_types::{
+    network::{NetworkId, NetworkProfile as Profile},
+    relay_constraints::SelectedObfuscation,
+};
+use std::convert::TryFrom;
+
+pub struct NetworkProfile;
+
+#[mullvad_management_interface::async_trait]
+impl Command for NetworkProfile {
+    fn name(&self) -> &'static str {
+        "network-profile"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name())
+            .about(
+                "Manage connection parameters that are applied automatically while the device is \
+                 on a particular network",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::App::new("list").about("Display all network profiles"))
+            .subcommand(
+                network_args(clap::App::new("set"))
+                    .about(
+                        "Create or change the profile of a network. Parameters that are not \
+                         given are taken from the global settings",
+                    )
+                    .arg(
+                        clap::Arg::new("connect timeout")
+                            .long("connect-timeout")
+                            .takes_value(true)
+                            .help(
+                                "Seconds to wait for a WireGuard tunnel to start passing traffic. \
+                                 At least 4",
+                            ),
+                    )
+                    .arg(
+                        clap::Arg::new("keepalive")
+                            .long("keepalive")
+                            .takes_value(true)
+                            .help("Seconds between WireGuard keepalive packets, from 1 to 3600"),
+                    )
+                    .arg(
+                        clap::Arg::new("obfuscation")
+                            .long("obfuscation")
+                            .takes_value(true)
+                            .possible_values(["auto", "off", "udp2tcp", "port-hopping"])
+                            .help("Obfuscation mode to use for WireGuard connections"),
+                    )
+                    .arg(
+                        clap::Arg::new("mtu")
+                            .long("mtu")
+                            .takes_value(true)
+                            .help("MTU of the WireGuard tunnel, from 1280 to 1420"),
+                    ),
+            )
+            .subcommand(
+                network_args(clap::App::new("remove")).about("Remove the profile of a network"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            Some(("list", _)) => self.list().await,
+            Some(("set", matches)) => self.set(matches).await,
+            Some(("remove", matches)) => self.remove(matches).await,
+            _ => unreachable!("No network profile command given"),
+        }
+    }
+}
+
+impl NetworkProfile {
+    async fn list(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let profiles = get_profiles(&mut rpc).await?;
+        if json_output() {
+            format::print_json(&profiles);
+            return Ok(());
+        }
+        if profiles.is_empty() {
+            println!("No network profiles");
+        }
+        for profile in profiles {
+            println!("{}", profile);
+        }
+        Ok(())
+    }
+
+    async fn set(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let network = parse_network(matches)?;
+        let mut rpc = new_rpc_client().await?;
+        let mut profiles = get_profiles(&mut rpc).await?;
+
+        let index = match profiles
+            .iter()
+            .position(|profile| profile.network == network)
+        {
+            Some(index) => index,
+            None => {
+                profiles.push(Profile::new(network));
+                profiles.len() - 1
+            }
+        };
+        let profile = &mut profiles[index];
+        if matches.is_present("connect timeout") {
+            profile.connect_timeout = Some(matches.value_of_t_or_exit("connect timeout"));
+        }
+        if matches.is_present("keepalive") {
+            profile.wireguard_keepalive = Some(matches.value_of_t_or_exit("keepalive"));
+        }
+        if let Some(obfuscation) = matches.value_of("obfuscation") {
+            profile.obfuscation = Some(match obfuscation {
+                "auto" => SelectedObfuscation::Auto,
+                "off" => SelectedObfuscation::Off,
+                "udp2tcp" => SelectedObfuscation::Udp2Tcp,
+                "port-hopping" => SelectedObfuscation::PortHopping,
+                _ => unreachable!("Unhandled obfuscator mode"),
+            });
+        }
+        if matches.is_present("mtu") {
+            profile.mtu = Some(matches.value_of_t_or_exit("mtu"));
+        }
+        profile.validate().map_err(Error::InvalidCommand)?;
+        println!("{}", profile);
+
+        rpc.set_network_profiles(types::NetworkProfiles::from(&profiles[..]))
+            .await?;
+        Ok(())
+    }
+
+    async fn remove(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let network = parse_network(matches)?;
+        let mut rpc = new_rpc_client().await?;
+        let mut profiles = get_profiles(&mut rpc).await?;
+        let len = profiles.len();
+        profiles.retain(|profile| profile.network != network);
+        if profiles.len() == len {
+            return Err(Error::InvalidCommand(
+                "There is no profile for that network",
+            ));
+        }
+        rpc.set_network_profiles(types::NetworkProfiles::from(&profiles[..]))
+            .await?;
+        println!("Removed the profile of the {}", network);
+        Ok(())
+    }
+}
+
+fn network_args(app: clap::App<'static>) -> clap::App<'static> {
+    app.arg(
+        clap::Arg::new("network")
+            .required(true)
+            .index(1)
+            .possible_values(["wired", "wifi", "other"]),
+    )
+    .arg(
+        clap::Arg::new("ssid")
+            .long("ssid")
+            .takes_value(true)
+            .required_if_eq("network", "wifi")
+            .help("SSID of the Wi-Fi network"),
+    )
+}
+
+fn parse_network(matches: &clap::ArgMatches) -> Result<NetworkId> {
+    match matches.value_of("network").unwrap() {
+        "wired" => Ok(NetworkId::Wired),
+        "wifi" => Ok(NetworkId::Wifi {
+            ssid: matches.value_of("ssid").unwrap().to_owned(),
+        }),
+        "other" => Ok(NetworkId::Other),
+        _ => Err(Error::InvalidCommand("Unknown network type")),
+    }
+}
+
+async fn get_profiles(rpc: &mut ManagementServiceClient) -> Result<Vec<Profile>> {
+    let profiles = rpc
+        .get_settings(())
+        .await?
+        .into_inner()
+        .network_profiles
+        .unwrap_or_default();
+    Vec::<Profile>::try_from(profiles)
+        .map_err(|_| Error::InvalidResponse("failed to parse network profiles"))
+}
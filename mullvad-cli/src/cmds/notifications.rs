@@ -0,0 +1,69 @@
+use crate::{format, json_output, new_rpc_client, Command, Result};
+
+pub struct Notifications;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Notifications {
+    fn name(&self) -> &'static str {
+        "notifications"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name())
+            .about(
+                "Control whether the daemon shows notifications through the OS, such as when the \
+                 account is about to expire",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::App::new("set")
+                    .about("Change the system notifications setting")
+                    .arg(
+                        clap::Arg::new("policy")
+                            .required(true)
+                            .possible_values(["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                clap::App::new("get").about("Display the current system notifications setting"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let enabled = set_matches.value_of("policy").expect("missing policy");
+            self.set(enabled == "on").await
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get().await
+        } else {
+            unreachable!("No notifications command given");
+        }
+    }
+}
+
+impl Notifications {
+    async fn set(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_system_notifications(enabled).await?;
+        println!("Changed system notifications setting");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let enabled = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .system_notifications;
+        if json_output() {
+            format::print_json(&enabled);
+            return Ok(());
+        }
+        println!(
+            "System notifications: {}",
+            if enabled { "on" } else { "off" }
+        );
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
 
 use mullvad_management_interface::{types as grpc_types, ManagementServiceClient};
 
@@ -46,6 +46,7 @@ impl Obfuscation {
                     "auto" => SelectedObfuscation::Auto,
                     "off" => SelectedObfuscation::Off,
                     "udp2tcp" => SelectedObfuscation::Udp2Tcp,
+                    "port-hopping" => SelectedObfuscation::PortHopping,
                     _ => unreachable!("Unhandled obfuscator mode"),
                 };
                 Self::set_obfuscation_settings(&mut rpc, &settings).await?;
@@ -63,6 +64,23 @@ impl Obfuscation {
                 };
                 Self::set_obfuscation_settings(&mut rpc, &settings).await?;
             }
+            Some(("port-hopping", settings_matches)) => {
+                let interval: String = settings_matches.value_of_t_or_exit("interval");
+                let interval = if interval == "never" {
+                    None
+                } else {
+                    let interval = interval.parse::<std::num::NonZeroU32>().map_err(|_| {
+                        Error::InvalidCommand(
+                            "Invalid interval. Must be \"never\" or a positive number of seconds.",
+                        )
+                    })?;
+                    Some(interval.get())
+                };
+                let mut rpc = new_rpc_client().await?;
+                let mut settings = Self::get_obfuscation_settings(&mut rpc).await?;
+                settings.port_hopping.interval = interval;
+                Self::set_obfuscation_settings(&mut rpc, &settings).await?;
+            }
             _ => unreachable!("unhandled command"),
         }
         Ok(())
@@ -71,11 +89,19 @@ impl Obfuscation {
     async fn handle_get() -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let obfuscation_settings = Self::get_obfuscation_settings(&mut rpc).await?;
+        if json_output() {
+            format::print_json(&obfuscation_settings);
+            return Ok(());
+        }
         println!(
             "Obfuscation mode: {}",
             obfuscation_settings.selected_obfuscation
         );
         println!("udp2tcp settings: {}", obfuscation_settings.udp2tcp);
+        println!(
+            "port hopping settings: {}",
+            obfuscation_settings.port_hopping
+        );
         Ok(())
     }
 
@@ -84,13 +110,10 @@ impl Obfuscation {
     ) -> Result<ObfuscationSettings> {
         let settings = rpc.get_settings(()).await?.into_inner();
 
-        let obfuscation_settings = ObfuscationSettings::try_from(
-            settings
-                .obfuscation_settings
-                .expect("No obfuscation settings"),
-        )
-        .expect("failed to parse obfuscation settings");
-        Ok(obfuscation_settings)
+        settings
+            .obfuscation_settings
+            .and_then(|settings| ObfuscationSettings::try_from(settings).ok())
+            .ok_or(Error::InvalidResponse("invalid obfuscation settings"))
     }
 
     async fn set_obfuscation_settings(
@@ -116,7 +139,7 @@ fn create_obfuscation_set_subcommand() -> clap::App<'static> {
                     )
                     .required(true)
                     .index(1)
-                    .possible_values(["auto", "off", "udp2tcp"]),
+                    .possible_values(["auto", "off", "udp2tcp", "port-hopping"]),
             ),
         )
         .subcommand(
@@ -130,6 +153,20 @@ fn create_obfuscation_set_subcommand() -> clap::App<'static> {
                         .takes_value(true),
                 ),
         )
+        .subcommand(
+            clap::App::new("port-hopping")
+                .about("Specifies the config for WireGuard port hopping")
+                .setting(clap::AppSettings::ArgRequiredElseHelp)
+                .arg(
+                    clap::Arg::new("interval")
+                        .help(
+                            "Seconds to stay on a port while connected. Either 'never' or a \
+                            number of seconds. With 'never', the port only changes on reconnect",
+                        )
+                        .long("interval")
+                        .takes_value(true),
+                ),
+        )
 }
 
 fn create_obfuscation_get_subcommand() -> clap::App<'static> {
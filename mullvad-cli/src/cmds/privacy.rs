@@ -0,0 +1,132 @@
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::types;
+use mullvad_types::auto_connect::BackgroundWorkPolicy;
+
+pub struct Privacy;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Privacy {
+    fn name(&self) -> &'static str {
+        "privacy"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name())
+            .about(
+                "Control which requests the daemon makes that are not needed to connect, such as \
+                 version checks and location lookups",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::App::new("set")
+                    .about("Change the privacy settings")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("update-check")
+                            .about("Whether to check for new app versions")
+                            .arg(
+                                clap::Arg::new("policy")
+                                    .required(true)
+                                    .possible_values(["on", "off"]),
+                            ),
+                    )
+                    .subcommand(
+                        clap::App::new("location")
+                            .about(
+                                "Whether to look up the location of the device. If this is off, \
+                                 only the location of the relay is shown while connected",
+                            )
+                            .arg(
+                                clap::Arg::new("policy")
+                                    .required(true)
+                                    .possible_values(["on", "off"]),
+                            ),
+                    )
+                    .subcommand(
+                        clap::App::new("background-work")
+                            .about(
+                                "On which networks to periodically download the relay list and \
+                                 version information. Trusted networks are wired networks and \
+                                 the trusted Wi-Fi networks of the auto-connect policy",
+                            )
+                            .arg(
+                                clap::Arg::new("policy")
+                                    .required(true)
+                                    .possible_values(["always", "trusted-networks"]),
+                            ),
+                    ),
+            )
+            .subcommand(clap::App::new("get").about("Display the current privacy settings"))
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            Some(("set", set_matches)) => match set_matches.subcommand() {
+                Some(("update-check", matches)) => {
+                    let enabled = matches.value_of("policy").expect("missing policy") == "on";
+                    self.set(types::SettingsUpdate {
+                        check_for_updates: Some(enabled),
+                        ..Default::default()
+                    })
+                    .await
+                }
+                Some(("location", matches)) => {
+                    let enabled = matches.value_of("policy").expect("missing policy") == "on";
+                    self.set(types::SettingsUpdate {
+                        fetch_location: Some(enabled),
+                        ..Default::default()
+                    })
+                    .await
+                }
+                Some(("background-work", matches)) => {
+                    let policy = match matches.value_of("policy").expect("missing policy") {
+                        "always" => BackgroundWorkPolicy::Always,
+                        "trusted-networks" => BackgroundWorkPolicy::TrustedNetworksOnly,
+                        _ => unreachable!("invalid policy"),
+                    };
+                    self.set(types::SettingsUpdate {
+                        background_work: Some(types::BackgroundWorkPolicy::from(policy)),
+                        ..Default::default()
+                    })
+                    .await
+                }
+                _ => unreachable!("unhandled subcommand"),
+            },
+            Some(("get", _)) => self.get().await,
+            _ => unreachable!("No privacy command given"),
+        }
+    }
+}
+
+impl Privacy {
+    async fn set(&self, update: types::SettingsUpdate) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.apply_settings(update).await?;
+        println!("Changed privacy settings");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        let background_work = settings
+            .background_work
+            .map(BackgroundWorkPolicy::try_from)
+            .transpose()
+            .map_err(|_| Error::InvalidResponse("invalid background work policy"))?
+            .unwrap_or_default();
+        if json_output() {
+            format::print_json(&serde_json::json!({
+                "check_for_updates": settings.check_for_updates,
+                "fetch_location": settings.fetch_location,
+                "background_work": background_work,
+            }));
+            return Ok(());
+        }
+        let format = |enabled| if enabled { "on" } else { "off" };
+        println!("Update check: {}", format(settings.check_for_updates));
+        println!("Location lookup: {}", format(settings.fetch_location));
+        println!("Background downloads: {}", background_work);
+        Ok(())
+    }
+}
@@ -13,12 +13,10 @@ impl Command for Reconnect {
     fn clap_subcommand(&self) -> clap::App<'static> {
         clap::App::new(self.name())
             .about("Command the client to reconnect")
-            .arg(
-                clap::Arg::new("wait")
-                    .long("wait")
-                    .short('w')
-                    .help("Wait until reconnected before exiting"),
-            )
+            .arg(clap::Arg::new("wait").long("wait").short('w').help(
+                "Wait until reconnected before exiting. If the tunnel fails, the exit \
+                 code is 2 if traffic is blocked and 3 otherwise",
+            ))
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
@@ -37,7 +35,9 @@ impl Command for Reconnect {
                     format::print_state(&state, false);
                     match state {
                         TunnelState::Connected { .. } => return Ok(()),
-                        TunnelState::Error { .. } => return Err(Error::CommandFailed("reconnect")),
+                        TunnelState::Error(error_state) => {
+                            return Err(Error::TunnelErrorState(error_state))
+                        }
                         _ => {}
                     }
                 }
@@ -1,4 +1,4 @@
-use crate::{location, new_rpc_client, Command, Error, Result};
+use crate::{format, json_output, location, new_rpc_client, Command, Error, Result};
 use itertools::Itertools;
 use std::{
     convert::TryFrom,
@@ -8,7 +8,10 @@ use std::{
 };
 
 use mullvad_management_interface::{types, ManagementServiceClient};
-use mullvad_types::relay_constraints::{Constraint, RelaySettings};
+use mullvad_types::{
+    relay_constraints::{Constraint, RelaySettings},
+    relay_list::RelayListCountry,
+};
 use talpid_types::net::all_of_the_internet;
 
 pub struct Relay;
@@ -101,6 +104,15 @@ impl Command for Relay {
                         location::get_subcommand()
                             .about("Set country or city to select relays from. Use the 'list' \
                                    command to show available alternatives.")
+                            .mut_arg("location", |arg| arg.required_unless_present("search"))
+                            .arg(
+                                clap::Arg::new("search")
+                                    .help("Select the best match for a country, city or \
+                                           server name, such as 'gothenburg' or 'zürich'")
+                                    .long("search")
+                                    .takes_value(true)
+                                    .conflicts_with("location"),
+                            )
                     )
                     .subcommand(
                         clap::App::new("hostname")
@@ -134,6 +146,16 @@ impl Command for Relay {
                                 .required(true)
                             )
                     )
+                    .subcommand(
+                        clap::App::new("nearby-locations")
+                            .about("Select a relay in a nearby city when no relay in the selected \
+                                   location matches the other constraints.")
+                            .arg(
+                                clap::Arg::new("policy")
+                                .possible_values(["on", "off"])
+                                .required(true)
+                            )
+                    )
                     .subcommand(
                         clap::App::new("tunnel")
                             .about("Set tunnel protocol-specific constraints.")
@@ -176,7 +198,7 @@ impl Command for Relay {
                                         clap::Arg::new("entry location")
                                             .help("Entry endpoint to use. This can be 'any', 'none', or \
                                                    any location that is valid with 'set location', \
-                                                   such as 'se got'.")
+                                                   such as 'se/got'.")
                                             .long("entry-location")
                                             .min_values(1)
                                             .max_values(3),
@@ -191,6 +213,19 @@ impl Command for Relay {
                                     .index(1)
                                     .possible_values(["any", "wireguard", "openvpn", ]),
                                     )
+                                )
+                    .subcommand(clap::App::new("port-order")
+                                .about("Set the order in which tunnel protocols and ports are tried \
+                                       when they are not constrained")
+                                .arg(
+                                    clap::Arg::new("steps")
+                                    .required(true)
+                                    .multiple_values(true)
+                                    .help("Steps on the form <wireguard|openvpn>:<port|any>/<udp|tcp>, \
+                                          e.g. wireguard:51820/udp wireguard:443/tcp openvpn:443/tcp. \
+                                          WireGuard over TCP uses udp2tcp obfuscation. Use \"default\" \
+                                          to restore the built-in order."),
+                                    )
                                 ),
             )
             .subcommand(clap::App::new("get"))
@@ -239,6 +274,8 @@ impl Relay {
             self.set_providers(providers_matches).await
         } else if let Some(ownership_matches) = matches.subcommand_matches("ownership") {
             self.set_ownership(ownership_matches).await
+        } else if let Some(nearby_matches) = matches.subcommand_matches("nearby-locations") {
+            self.set_nearby_locations(nearby_matches).await
         } else if let Some(matches) = matches.subcommand_matches("tunnel") {
             if let Some(tunnel_matches) = matches.subcommand_matches("openvpn") {
                 self.set_openvpn_constraints(tunnel_matches).await
@@ -249,6 +286,8 @@ impl Relay {
             }
         } else if let Some(tunnel_matches) = matches.subcommand_matches("tunnel-protocol") {
             self.set_tunnel_protocol(tunnel_matches).await
+        } else if let Some(order_matches) = matches.subcommand_matches("port-order") {
+            self.set_port_order(order_matches).await
         } else {
             unreachable!("No set relay command given");
         }
@@ -421,6 +460,9 @@ impl Relay {
     }
 
     async fn set_location(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(query) = matches.value_of("search") {
+            return self.set_location_from_search(query).await;
+        }
         let location_constraint = location::get_constraint_from_args(matches);
         let mut found = false;
 
@@ -477,6 +519,41 @@ impl Relay {
         .await
     }
 
+    async fn set_location_from_search(&self, query: &str) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let results = rpc
+            .search_locations(types::LocationSearch {
+                query: query.to_owned(),
+                locale: String::new(),
+            })
+            .await?
+            .into_inner()
+            .results;
+
+        let best_match = results
+            .into_iter()
+            .find_map(|result| Some((result.location?, result.name)));
+        match best_match {
+            Some((location, name)) => {
+                println!("Setting location constraint to {}", name);
+                self.update_constraints(types::RelaySettingsUpdate {
+                    r#type: Some(types::relay_settings_update::Type::Normal(
+                        types::NormalRelaySettingsUpdate {
+                            location: Some(location),
+                            ..Default::default()
+                        },
+                    )),
+                })
+                .await
+            }
+            None => clap::Error::raw(
+                clap::ErrorKind::ValueValidation,
+                format!("No location matches '{}'", query),
+            )
+            .exit(),
+        }
+    }
+
     async fn set_providers(&self, matches: &clap::ArgMatches) -> Result<()> {
         let providers: Vec<String> = matches.values_of_t_or_exit("provider");
         let providers = if providers.get(0).map(String::as_str) == Some("any") {
@@ -511,6 +588,19 @@ impl Relay {
         .await
     }
 
+    async fn set_nearby_locations(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let nearby_locations = matches.value_of("policy").unwrap() == "on";
+        self.update_constraints(types::RelaySettingsUpdate {
+            r#type: Some(types::relay_settings_update::Type::Normal(
+                types::NormalRelaySettingsUpdate {
+                    nearby_locations: Some(nearby_locations),
+                    ..Default::default()
+                },
+            )),
+        })
+        .await
+    }
+
     async fn set_openvpn_constraints(&self, matches: &clap::ArgMatches) -> Result<()> {
         let mut openvpn_constraints = {
             let mut rpc = new_rpc_client().await?;
@@ -538,13 +628,12 @@ impl Relay {
             .await?
             .into_inner()
             .relay_settings
-            .unwrap()
-            .endpoint
-            .unwrap()
+            .and_then(|settings| settings.endpoint)
+            .ok_or(Error::InvalidResponse("missing relay settings"))?
         {
-            types::relay_settings::Endpoint::Normal(settings) => {
-                Ok(settings.openvpn_constraints.unwrap())
-            }
+            types::relay_settings::Endpoint::Normal(settings) => settings
+                .openvpn_constraints
+                .ok_or(Error::InvalidResponse("missing OpenVPN constraints")),
             types::relay_settings::Endpoint::Custom(_settings) => {
                 println!("Clearing custom tunnel constraints");
                 Ok(types::OpenvpnConstraints::default())
@@ -559,7 +648,7 @@ impl Relay {
             .await?
             .into_inner()
             .wireguard
-            .unwrap();
+            .ok_or(Error::InvalidResponse("missing WireGuard endpoint data"))?;
         let mut wireguard_constraints = self.get_wireguard_constraints(&mut rpc).await?;
 
         if let Some(port) = matches.value_of("port") {
@@ -598,9 +687,8 @@ impl Relay {
                     .await?
                     .into_inner()
                     .tunnel_options
-                    .unwrap()
-                    .wireguard
-                    .unwrap()
+                    .and_then(|options| options.wireguard)
+                    .ok_or(Error::InvalidResponse("missing WireGuard tunnel options"))?
                     .use_pq_safe_psk;
                 if use_pq_safe_psk {
                     return Err(Error::CommandFailed(
@@ -631,13 +719,12 @@ impl Relay {
             .await?
             .into_inner()
             .relay_settings
-            .unwrap()
-            .endpoint
-            .unwrap()
+            .and_then(|settings| settings.endpoint)
+            .ok_or(Error::InvalidResponse("missing relay settings"))?
         {
-            types::relay_settings::Endpoint::Normal(settings) => {
-                Ok(settings.wireguard_constraints.unwrap())
-            }
+            types::relay_settings::Endpoint::Normal(settings) => settings
+                .wireguard_constraints
+                .ok_or(Error::InvalidResponse("missing WireGuard constraints")),
             types::relay_settings::Endpoint::Custom(_settings) => {
                 println!("Clearing custom tunnel constraints");
                 Ok(types::WireguardConstraints::default())
@@ -667,19 +754,57 @@ impl Relay {
         .await
     }
 
+    async fn set_port_order(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let raw_steps: Vec<&str> = matches.values_of("steps").unwrap().collect();
+        let steps = if raw_steps == ["default"] {
+            vec![]
+        } else {
+            raw_steps
+                .into_iter()
+                .map(parse_port_selection_step)
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_auto_port_order(types::PortSelectionOrder { steps })
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to set port order", error))?;
+        println!("Updated automatic port order");
+        Ok(())
+    }
+
     async fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let relay_settings = rpc
-            .get_settings(())
-            .await?
-            .into_inner()
+        let settings = rpc.get_settings(()).await?.into_inner();
+
+        let relay_settings = settings
             .relay_settings
-            .unwrap();
+            .ok_or(Error::InvalidResponse("missing relay settings"))?;
+        let relay_settings = RelaySettings::try_from(relay_settings)
+            .map_err(|_| Error::InvalidResponse("invalid relay settings"))?;
+        let port_order = settings
+            .auto_port_order
+            .ok_or(Error::InvalidResponse("missing port order"))?;
+        let port_order = types::relay_constraints::try_port_selection_order_from_proto(port_order)
+            .map_err(|_| Error::InvalidResponse("invalid port order"))?;
 
-        println!(
-            "Current constraints: {}",
-            RelaySettings::try_from(relay_settings).unwrap()
-        );
+        if json_output() {
+            format::print_json(&serde_json::json!({
+                "relay_settings": relay_settings,
+                "auto_port_order": port_order,
+            }));
+            return Ok(());
+        }
+
+        println!("Current constraints: {}", relay_settings);
+        if port_order.is_empty() {
+            println!("Automatic port order: default");
+        } else {
+            println!("Automatic port order:");
+            for (index, step) in port_order.iter().enumerate() {
+                println!("\t{}. {}", index + 1, step);
+            }
+        }
 
         Ok(())
     }
@@ -687,6 +812,9 @@ impl Relay {
     async fn list(&self) -> Result<()> {
         let mut countries = Self::get_filtered_relays().await?;
         countries.sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
+        if json_output() {
+            return print_countries_json(countries);
+        }
         for mut country in countries {
             country
                 .cities
@@ -769,6 +897,17 @@ impl Relay {
     }
 }
 
+/// Prints the relays in `countries` as JSON, in the format of the relay list of the daemon.
+pub fn print_countries_json(countries: Vec<types::RelayListCountry>) -> Result<()> {
+    let countries = countries
+        .into_iter()
+        .map(RelayListCountry::try_from)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| Error::InvalidResponse("invalid relay list"))?;
+    format::print_json(&countries);
+    Ok(())
+}
+
 fn parse_port_constraint(raw_port: &str) -> Result<Constraint<u16>> {
     match raw_port.to_lowercase().as_str() {
         "any" => Ok(Constraint::Any),
@@ -797,19 +936,13 @@ fn parse_ip_version_constraint(raw_protocol: &str) -> Constraint<types::IpVersio
 }
 
 fn parse_entry_location_constraint<'a, T: Iterator<Item = &'a str>>(
-    mut location: T,
+    location: T,
 ) -> Option<types::RelayLocation> {
-    let country = location.next().unwrap();
-
-    if country == "none" {
+    let mut location = location.peekable();
+    if location.peek() == Some(&"none") {
         return None;
     }
-
-    Some(location::get_constraint(
-        country,
-        location.next(),
-        location.next(),
-    ))
+    Some(location::get_constraint(location))
 }
 
 fn parse_transport_port(
@@ -821,7 +954,8 @@ fn parse_transport_port(
         None => {
             if let Some(ref transport_port) = current_constraint {
                 Constraint::Only(
-                    types::TransportProtocol::from_i32(transport_port.protocol).unwrap(),
+                    types::TransportProtocol::from_i32(transport_port.protocol)
+                        .ok_or(Error::InvalidResponse("invalid transport protocol"))?,
                 )
             } else {
                 Constraint::Any
@@ -864,6 +998,39 @@ fn parse_transport_port(
     }
 }
 
+/// Parses a step on the form `<wireguard|openvpn>:<port|any>/<udp|tcp>`.
+fn parse_port_selection_step(raw_step: &str) -> Result<types::PortSelectionStep> {
+    const INVALID_STEP: &str =
+        "Invalid step. Must be on the form <wireguard|openvpn>:<port|any>/<udp|tcp>.";
+
+    let (tunnel_type, transport) = raw_step
+        .split_once(':')
+        .ok_or(Error::InvalidCommand(INVALID_STEP))?;
+    let (port, protocol) = transport
+        .split_once('/')
+        .ok_or(Error::InvalidCommand(INVALID_STEP))?;
+
+    let tunnel_type = match tunnel_type.to_lowercase().as_str() {
+        "wireguard" => types::TunnelType::Wireguard,
+        "openvpn" => types::TunnelType::Openvpn,
+        _ => return Err(Error::InvalidCommand(INVALID_STEP)),
+    };
+    let protocol = match protocol.to_lowercase().as_str() {
+        "udp" => types::TransportProtocol::Udp,
+        "tcp" => types::TransportProtocol::Tcp,
+        _ => return Err(Error::InvalidCommand(INVALID_STEP)),
+    };
+    let port = parse_port_constraint(port)?;
+
+    Ok(types::PortSelectionStep {
+        tunnel_type: tunnel_type as i32,
+        transport: Some(types::TransportPort {
+            protocol: protocol as i32,
+            port: port.map(u32::from).unwrap_or(0),
+        }),
+    })
+}
+
 pub fn parse_ownership_constraint(constraint: &str) -> types::Ownership {
     match constraint {
         "any" => types::Ownership::Any,
@@ -1,4 +1,5 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{format, json_output, new_rpc_client, Command, Result};
+use mullvad_management_interface::types::{split_tunnel_mode::Mode, SplitTunnelMode};
 
 pub struct SplitTunnel;
 
@@ -16,11 +17,13 @@ impl Command for SplitTunnel {
             )
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(create_pid_subcommand())
+            .subcommand(create_mode_subcommand())
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
         match matches.subcommand() {
             Some(("pid", pid_matches)) => Self::handle_pid_cmd(pid_matches).await,
+            Some(("mode", mode_matches)) => Self::handle_mode_cmd(mode_matches).await,
             _ => unreachable!("unhandled comand"),
         }
     }
@@ -28,7 +31,7 @@ impl Command for SplitTunnel {
 
 fn create_pid_subcommand() -> clap::App<'static> {
     clap::App::new("pid")
-        .about("Manage processes to exclude from the tunnel")
+        .about("Manage processes to exclude from the tunnel, or to include in it in include mode")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(clap::App::new("add").arg(clap::Arg::new("pid").required(true)))
         .subcommand(clap::App::new("delete").arg(clap::Arg::new("pid").required(true)))
@@ -36,6 +39,29 @@ fn create_pid_subcommand() -> clap::App<'static> {
         .subcommand(clap::App::new("list"))
 }
 
+fn create_mode_subcommand() -> clap::App<'static> {
+    clap::App::new("mode")
+        .about(
+            "Choose whether the added processes are excluded from the tunnel, or are the only \
+             processes that use it",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::App::new("set")
+                .about(
+                    "In include mode, only processes that have been added use the tunnel. All \
+                     other traffic leaves the device outside the tunnel, even while connecting or \
+                     blocking. Cannot be used while \"Always require VPN\" is enabled",
+                )
+                .arg(
+                    clap::Arg::new("mode")
+                        .required(true)
+                        .possible_values(&["exclude", "include"]),
+                ),
+        )
+        .subcommand(clap::App::new("get").about("Display the current mode"))
+}
+
 impl SplitTunnel {
     async fn handle_pid_cmd(matches: &clap::ArgMatches) -> Result<()> {
         match matches.subcommand() {
@@ -68,7 +94,22 @@ impl SplitTunnel {
                     .get_split_tunnel_processes(())
                     .await?
                     .into_inner();
-                println!("Excluded PIDs:");
+                let mode = get_mode().await?;
+                if json_output() {
+                    let mut pids = vec![];
+                    while let Some(pid) = pids_stream.message().await? {
+                        pids.push(pid);
+                    }
+                    format::print_json(&serde_json::json!({
+                        "mode": mode_name(mode),
+                        "pids": pids,
+                    }));
+                    return Ok(());
+                }
+                match mode {
+                    Mode::Exclude => println!("Excluded PIDs:"),
+                    Mode::Include => println!("Included PIDs:"),
+                }
 
                 while let Some(pid) = pids_stream.message().await? {
                     println!("    {}", pid);
@@ -79,4 +120,66 @@ impl SplitTunnel {
             _ => unreachable!("unhandled command"),
         }
     }
+
+    async fn handle_mode_cmd(matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            Some(("set", matches)) => {
+                let mode = match matches.value_of("mode").expect("missing mode") {
+                    "include" => Mode::Include,
+                    _ => Mode::Exclude,
+                };
+                new_rpc_client()
+                    .await?
+                    .set_split_tunnel_mode(SplitTunnelMode { mode: mode as i32 })
+                    .await?;
+                println!("Changed split tunneling mode");
+                if mode == Mode::Include {
+                    print_include_mode_warning();
+                }
+                Ok(())
+            }
+            Some(("get", _)) => {
+                let mode = get_mode().await?;
+                if json_output() {
+                    format::print_json(&mode_name(mode));
+                    return Ok(());
+                }
+                match mode {
+                    Mode::Exclude => println!("Split tunneling mode: exclude"),
+                    Mode::Include => {
+                        println!("Split tunneling mode: include");
+                        print_include_mode_warning();
+                    }
+                }
+                Ok(())
+            }
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+async fn get_mode() -> Result<Mode> {
+    let mode = new_rpc_client()
+        .await?
+        .get_settings(())
+        .await?
+        .into_inner()
+        .split_tunnel_mode
+        .and_then(|mode| Mode::from_i32(mode.mode))
+        .unwrap_or(Mode::Exclude);
+    Ok(mode)
+}
+
+fn mode_name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Exclude => "exclude",
+        Mode::Include => "include",
+    }
+}
+
+fn print_include_mode_warning() {
+    println!(
+        "WARNING: Only the processes that have been added use the tunnel. The traffic of all \
+         other processes is not protected, and is not blocked when the tunnel is down."
+    );
 }
@@ -1,6 +1,6 @@
 use std::{ffi::OsStr, path::Path};
 
-use crate::{new_rpc_client, Command, Result};
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
 
 pub struct SplitTunnel;
 
@@ -74,9 +74,13 @@ impl SplitTunnel {
                     .await?
                     .into_inner()
                     .split_tunnel
-                    .unwrap()
+                    .ok_or(Error::InvalidResponse("missing split tunnel settings"))?
                     .apps;
 
+                if json_output() {
+                    format::print_json(&paths);
+                    return Ok(());
+                }
                 println!("Excluded applications:");
                 for path in &paths {
                     println!("    {}", path);
@@ -114,6 +118,21 @@ impl SplitTunnel {
                     .await?
                     .into_inner();
 
+                if json_output() {
+                    let processes: Vec<_> = processes
+                        .processes
+                        .iter()
+                        .map(|process| {
+                            serde_json::json!({
+                                "pid": process.pid,
+                                "image": process.image,
+                                "inherited": process.inherited,
+                            })
+                        })
+                        .collect();
+                    format::print_json(&processes);
+                    return Ok(());
+                }
                 for process in &processes.processes {
                     let subproc = if process.inherited { "subprocess" } else { "" };
                     println!(
@@ -146,8 +165,12 @@ impl SplitTunnel {
             .await?
             .into_inner()
             .split_tunnel
-            .unwrap()
+            .ok_or(Error::InvalidResponse("missing split tunnel settings"))?
             .enable_exclusions;
+        if json_output() {
+            format::print_json(&enabled);
+            return Ok(());
+        }
         println!(
             "Split tunnel status: {}",
             if enabled { "on" } else { "off" }
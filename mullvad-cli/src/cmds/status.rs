@@ -1,8 +1,15 @@
-use crate::{format, new_rpc_client, Command, Error, Result};
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
+use futures::StreamExt;
 use mullvad_management_interface::{
-    types::daemon_event::Event as EventType, ManagementServiceClient,
+    connection_state_stream, error_kind,
+    types::{self, daemon_event::Event as EventType, ErrorKind, Timestamp},
+    ConnectionState, ManagementServiceClient,
 };
-use mullvad_types::{location::GeoIpLocation, states::TunnelState};
+use mullvad_types::{
+    location::GeoIpLocation, network::CurrentNetwork, relay_list::RelaySelectionHint,
+    states::TunnelState,
+};
+use talpid_types::tunnel::{ErrorStateCause, ParameterGenerationError};
 
 pub struct Status;
 
@@ -26,6 +33,12 @@ impl Command for Status {
                     .short('l')
                     .help("Prints the current location and IP. Based on GeoIP lookups"),
             )
+            .arg(
+                clap::Arg::new("network")
+                    .long("network")
+                    .short('n')
+                    .help("Prints the network that the device is on and its default routes"),
+            )
             .arg(
                 clap::Arg::new("debug")
                     .long("debug")
@@ -33,94 +46,189 @@ impl Command for Status {
                     .help("Enables debug output"),
             )
             .subcommand(clap::App::new("listen").about("Listen for VPN tunnel state changes"))
+            .subcommand(
+                clap::App::new("history")
+                    .about("Show recent VPN tunnel state changes, oldest first"),
+            )
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
         let debug = matches.is_present("debug");
         let verbose = matches.is_present("verbose");
         let show_full_location = matches.is_present("location");
+        let show_network = matches.is_present("network");
 
         let mut rpc = new_rpc_client().await?;
+
+        if matches.subcommand_matches("history").is_some() {
+            return print_history(&mut rpc, verbose).await;
+        }
+
         let state = rpc.get_tunnel_state(()).await?.into_inner();
 
-        if debug {
-            println!("Tunnel state: {:#?}", state);
+        if json_output() {
+            let state = parse_tunnel_state(state)?;
+            let location = if show_full_location {
+                get_location(&mut rpc).await?
+            } else {
+                None
+            };
+            let network = if show_network {
+                Some(get_network(&mut rpc).await?)
+            } else {
+                None
+            };
+            format::print_json(&serde_json::json!({
+                "state": state,
+                "location": location,
+                "network": network,
+            }));
         } else {
-            let state = TunnelState::try_from(state).expect("invalid tunnel state");
-            format::print_state(&state, verbose);
-        }
+            if debug {
+                println!("Tunnel state: {:#?}", state);
+            } else {
+                let state = parse_tunnel_state(state)?;
+                format::print_state(&state, verbose);
+                print_relay_selection_hint(&mut rpc, &state).await?;
+            }
+
+            if show_full_location {
+                print_location(&mut rpc).await?;
+            }
 
-        if show_full_location {
-            print_location(&mut rpc).await?;
+            if show_network {
+                print_network(&mut rpc).await?;
+            }
         }
 
         if matches.subcommand_matches("listen").is_some() {
-            let mut events = rpc.events_listen(()).await?.into_inner();
-
-            while let Some(event) = events.message().await? {
-                match event.event.unwrap() {
-                    EventType::TunnelState(new_state) => {
-                        let new_state =
-                            TunnelState::try_from(new_state).expect("invalid tunnel state");
-
-                        if debug {
-                            println!("New tunnel state: {:#?}", new_state);
-                        } else {
-                            format::print_state(&new_state, verbose);
-                        }
+            listen(debug, verbose, show_full_location).await?;
+        }
 
-                        match new_state {
-                            TunnelState::Connected { .. } | TunnelState::Disconnected => {
-                                if show_full_location {
-                                    print_location(&mut rpc).await?;
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                    EventType::Settings(settings) => {
-                        if debug {
-                            println!("New settings: {:#?}", settings);
-                        }
-                    }
-                    EventType::RelayList(relay_list) => {
-                        if debug {
-                            println!("New relay list: {:#?}", relay_list);
-                        }
-                    }
-                    EventType::VersionInfo(app_version_info) => {
-                        if debug {
-                            println!("New app version info: {:#?}", app_version_info);
-                        }
-                    }
-                    EventType::Device(device) => {
-                        if debug {
-                            println!("Device event: {:#?}", device);
-                        }
+        Ok(())
+    }
+}
+
+/// Prints tunnel state changes until the CLI is stopped. If the daemon goes away, for example while
+/// it is being upgraded, the CLI waits for it to return and carries on from its current state.
+async fn listen(debug: bool, verbose: bool, show_full_location: bool) -> Result<()> {
+    let connection_states = connection_state_stream();
+    futures::pin_mut!(connection_states);
+    let mut reconnecting = false;
+    while let Some(connection_state) = connection_states.next().await {
+        match connection_state {
+            ConnectionState::Connected(mut rpc) => {
+                if std::mem::take(&mut reconnecting) {
+                    let state = rpc.get_tunnel_state(()).await?.into_inner();
+                    let state = parse_tunnel_state(state)?;
+                    if json_output() {
+                        format::print_json(&state);
+                    } else {
+                        format::print_state(&state, verbose);
                     }
-                    EventType::RemoveDevice(device) => {
-                        if debug {
-                            println!("Remove device event: {:#?}", device);
+                }
+                print_events(&mut rpc, debug, verbose, show_full_location).await?;
+            }
+            ConnectionState::Disconnected => {
+                reconnecting = true;
+                if !json_output() {
+                    eprintln!("Lost connection to the daemon, waiting for it to return");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints daemon events until the connection to the daemon is lost.
+async fn print_events(
+    rpc: &mut ManagementServiceClient,
+    debug: bool,
+    verbose: bool,
+    show_full_location: bool,
+) -> Result<()> {
+    let mut events = match rpc.events_listen(()).await {
+        Ok(events) => events.into_inner(),
+        Err(_) => return Ok(()),
+    };
+
+    // The stream ends, or fails, when the daemon goes away
+    while let Ok(Some(event)) = events.message().await {
+        let event = match event.event {
+            Some(event) => event,
+            None => continue,
+        };
+        match event {
+            EventType::TunnelState(new_state) => {
+                let new_state = parse_tunnel_state(new_state)?;
+
+                if json_output() {
+                    format::print_json(&new_state);
+                    continue;
+                } else if debug {
+                    println!("New tunnel state: {:#?}", new_state);
+                } else {
+                    format::print_state(&new_state, verbose);
+                    print_relay_selection_hint(rpc, &new_state).await?;
+                }
+
+                match new_state {
+                    TunnelState::Connected { .. } | TunnelState::Disconnected { .. } => {
+                        if show_full_location {
+                            print_location(rpc).await?;
                         }
                     }
+                    _ => {}
+                }
+            }
+            EventType::Settings(settings) => {
+                if debug {
+                    println!("New settings: {:#?}", settings);
+                }
+            }
+            EventType::RelayList(relay_list) => {
+                if debug {
+                    println!("New relay list: {:#?}", relay_list);
+                }
+            }
+            EventType::VersionInfo(app_version_info) => {
+                if debug {
+                    println!("New app version info: {:#?}", app_version_info);
+                }
+            }
+            EventType::Device(device) => {
+                if debug {
+                    println!("Device event: {:#?}", device);
+                }
+            }
+            EventType::RemoveDevice(device) => {
+                if debug {
+                    println!("Remove device event: {:#?}", device);
                 }
             }
         }
+    }
 
-        Ok(())
+    Ok(())
+}
+
+/// Returns the current location, or `None` if it is not known.
+async fn get_location(rpc: &mut ManagementServiceClient) -> Result<Option<GeoIpLocation>> {
+    match rpc.get_current_location(()).await {
+        Ok(response) => GeoIpLocation::try_from(response.into_inner())
+            .map(Some)
+            .map_err(|_| Error::InvalidResponse("invalid location")),
+        Err(status) if error_kind(&status) == Some(ErrorKind::LocationUnavailable) => Ok(None),
+        Err(status) => Err(Error::RpcFailed(status)),
     }
 }
 
 async fn print_location(rpc: &mut ManagementServiceClient) -> Result<()> {
-    let location = match rpc.get_current_location(()).await {
-        Ok(response) => GeoIpLocation::try_from(response.into_inner()).expect("invalid geoip data"),
-        Err(status) => {
-            if status.code() == mullvad_management_interface::Code::NotFound {
-                println!("Location data unavailable");
-                return Ok(());
-            } else {
-                return Err(Error::RpcFailed(status));
-            }
+    let location = match get_location(rpc).await? {
+        Some(location) => location,
+        None => {
+            println!("Location data unavailable");
+            return Ok(());
         }
     };
     if let Some(ipv4) = location.ipv4 {
@@ -136,3 +244,138 @@ async fn print_location(rpc: &mut ManagementServiceClient) -> Result<()> {
     );
     Ok(())
 }
+
+/// Explains which constraints prevented a relay from being selected, if that is why the tunnel
+/// is blocked.
+async fn print_relay_selection_hint(
+    rpc: &mut ManagementServiceClient,
+    state: &TunnelState,
+) -> Result<()> {
+    let no_relay = match state {
+        TunnelState::Error(error_state) => matches!(
+            error_state.cause(),
+            ErrorStateCause::TunnelParameterError(
+                ParameterGenerationError::NoMatchingRelay
+                    | ParameterGenerationError::MissingRelayCapabilities
+            )
+        ),
+        _ => false,
+    };
+    if !no_relay {
+        return Ok(());
+    }
+    match rpc.explain_relay_selection(()).await {
+        Ok(response) => {
+            let hint = RelaySelectionHint::from(response.into_inner());
+            println!("No relay matches the constraints: {}", hint);
+            Ok(())
+        }
+        Err(status) if error_kind(&status) == Some(ErrorKind::CustomTunnelEndpoint) => Ok(()),
+        Err(status) => Err(Error::RpcFailed(status)),
+    }
+}
+
+/// Prints the events that the daemon has kept in memory.
+async fn print_history(rpc: &mut ManagementServiceClient, verbose: bool) -> Result<()> {
+    let events = rpc.get_recent_events(()).await?.into_inner().events;
+    if json_output() {
+        return print_history_json(events);
+    }
+    for recorded in events {
+        let time = recorded.time.as_ref().map(format_time).unwrap_or_default();
+        match recorded.event.and_then(|event| event.event) {
+            Some(EventType::TunnelState(state)) => {
+                let state = parse_tunnel_state(state)?;
+                print!("[{}] ", time);
+                format::print_state(&state, verbose);
+            }
+            Some(EventType::AutoConnectDecision(decision)) => {
+                let decision = if decision.connect {
+                    "connect"
+                } else {
+                    "don't connect"
+                };
+                println!("[{}] Auto-connect: {}", time, decision);
+            }
+            Some(EventType::ConflictingSoftware(list)) => {
+                for conflict in list.conflicts {
+                    println!("[{}] Conflicting software: {}", time, conflict.name);
+                }
+            }
+            Some(EventType::QuarantinedFiles(list)) => {
+                for file in list.files {
+                    println!("[{}] Corrupt cache file replaced: {}", time, file.path);
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn print_history_json(events: Vec<types::RecordedEvent>) -> Result<()> {
+    let mut entries = vec![];
+    for recorded in events {
+        let time = recorded.time.as_ref().map(timestamp_to_utc);
+        let event = match recorded.event.and_then(|event| event.event) {
+            Some(EventType::TunnelState(state)) => {
+                serde_json::json!({ "tunnel_state": parse_tunnel_state(state)? })
+            }
+            Some(EventType::AutoConnectDecision(decision)) => {
+                serde_json::json!({ "auto_connect": decision.connect })
+            }
+            Some(EventType::ConflictingSoftware(list)) => {
+                let names: Vec<_> = list.conflicts.into_iter().map(|c| c.name).collect();
+                serde_json::json!({ "conflicting_software": names })
+            }
+            Some(EventType::QuarantinedFiles(list)) => {
+                let paths: Vec<_> = list.files.into_iter().map(|file| file.path).collect();
+                serde_json::json!({ "quarantined_files": paths })
+            }
+            _ => continue,
+        };
+        entries.push(serde_json::json!({ "time": time, "event": event }));
+    }
+    format::print_json(&entries);
+    Ok(())
+}
+
+fn timestamp_to_utc(time: &Timestamp) -> chrono::DateTime<chrono::Utc> {
+    let ndt = chrono::NaiveDateTime::from_timestamp(time.seconds, time.nanos as u32);
+    chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc)
+}
+
+fn format_time(time: &Timestamp) -> String {
+    timestamp_to_utc(time)
+        .with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+async fn get_network(rpc: &mut ManagementServiceClient) -> Result<CurrentNetwork> {
+    CurrentNetwork::try_from(rpc.get_network_info(()).await?.into_inner())
+        .map_err(|_| Error::InvalidResponse("invalid network info"))
+}
+
+fn parse_tunnel_state(state: types::TunnelState) -> Result<TunnelState> {
+    TunnelState::try_from(state).map_err(|_| Error::InvalidResponse("invalid tunnel state"))
+}
+
+async fn print_network(rpc: &mut ManagementServiceClient) -> Result<()> {
+    let network = get_network(rpc).await?;
+
+    println!("Network: {}", network.network);
+    if let Some(bssid_hash) = &network.bssid_hash {
+        println!("BSSID hash: {}", bssid_hash);
+    }
+    match network.default_interface() {
+        Some(interface) => println!("Default interface: {}", interface),
+        None => println!("Default interface: none"),
+    }
+    for (family, route) in [("IPv4", &network.ipv4_route), ("IPv6", &network.ipv6_route)] {
+        if let Some(gateway) = route.as_ref().and_then(|route| route.gateway) {
+            println!("{} gateway: {}", family, gateway);
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,8 @@
-use crate::{new_rpc_client, Command, Error, Result};
-use mullvad_management_interface::types::{self, Timestamp, TunnelOptions};
-use mullvad_types::wireguard::DEFAULT_ROTATION_INTERVAL;
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::types::{self, Timestamp};
+use mullvad_types::{settings::TunnelOptions, wireguard::DEFAULT_ROTATION_INTERVAL};
 use std::{convert::TryFrom, time::Duration};
+use talpid_types::tunnel::ConnectionTimeouts;
 
 pub struct Tunnel;
 
@@ -18,6 +19,7 @@ impl Command for Tunnel {
             .subcommand(create_openvpn_subcommand())
             .subcommand(create_wireguard_subcommand())
             .subcommand(create_ipv6_subcommand())
+            .subcommand(create_timeouts_subcommand())
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
@@ -25,6 +27,12 @@ impl Command for Tunnel {
             Some(("openvpn", openvpn_matches)) => Self::handle_openvpn_cmd(openvpn_matches).await,
             Some(("wireguard", wg_matches)) => Self::handle_wireguard_cmd(wg_matches).await,
             Some(("ipv6", ipv6_matches)) => Self::handle_ipv6_cmd(ipv6_matches).await,
+            Some(("timeouts", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_timeouts_get().await,
+                Some(("set", matches)) => Self::process_timeouts_set(matches).await,
+                Some(("reset", _)) => Self::process_timeouts_reset().await,
+                _ => unreachable!("unhandled command"),
+            },
             _ => {
                 unreachable!("unhandled comand");
             }
@@ -37,6 +45,8 @@ fn create_wireguard_subcommand() -> clap::App<'static> {
         .about("Manage options for Wireguard tunnels")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_wireguard_mtu_subcommand())
+        .subcommand(create_wireguard_address_family_subcommand())
+        .subcommand(create_wireguard_dns_subcommand())
         .subcommand(create_wireguard_quantum_resistant_tunnel_subcommand())
         .subcommand(create_wireguard_keys_subcommand());
     #[cfg(windows)]
@@ -58,6 +68,56 @@ fn create_wireguard_mtu_subcommand() -> clap::App<'static> {
         .subcommand(clap::App::new("set").arg(clap::Arg::new("mtu").required(true)))
 }
 
+fn create_wireguard_address_family_subcommand() -> clap::App<'static> {
+    clap::App::new("address-family")
+        .about("Configure which IP versions to assign in-tunnel addresses for")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(clap::App::new("reset").about("Assign both an IPv4 and an IPv6 address"))
+        .subcommand(
+            clap::App::new("set").arg(
+                clap::Arg::new("family")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(["ipv4", "ipv6"])
+                    .help(
+                        "Only route traffic of this IP version through the tunnel. An IPv6-only \
+                         tunnel keeps its IPv4 address for reaching the relay",
+                    ),
+            ),
+        )
+}
+
+fn create_wireguard_dns_subcommand() -> clap::App<'static> {
+    clap::App::new("dns")
+        .about("Configure which in-tunnel DNS resolvers are used")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(
+            clap::App::new("set")
+                .arg(
+                    clap::Arg::new("ip version")
+                        .long("ip-version")
+                        .takes_value(true)
+                        .possible_values(["both", "ipv4", "ipv6"])
+                        .help(
+                            "Use the resolver of only one IP version, or both. The IPv4 resolver \
+                             is used if the tunnel has no IPv6 address",
+                        ),
+                )
+                .arg(
+                    clap::Arg::new("prefer ipv6")
+                        .long("prefer-ipv6")
+                        .takes_value(true)
+                        .possible_values(["on", "off"])
+                        .help(
+                            "List the IPv6 resolver before the IPv4 resolver. Custom DNS servers \
+                             keep their order",
+                        ),
+                ),
+        )
+}
+
 fn create_wireguard_quantum_resistant_tunnel_subcommand() -> clap::App<'static> {
     clap::App::new("quantum-resistant-tunnel")
         .about("EXPERIMENTAL: Enables quantum-resistant PSK exchange in the tunnel")
@@ -130,6 +190,47 @@ fn create_ipv6_subcommand() -> clap::App<'static> {
         )
 }
 
+fn create_timeouts_subcommand() -> clap::App<'static> {
+    let seconds_arg = |name: &'static str, help: &'static str| {
+        clap::Arg::new(name)
+            .long(name)
+            .takes_value(true)
+            .value_name("SECONDS")
+            .help(help)
+    };
+    clap::App::new("timeouts")
+        .about("Configure the timing of connection attempts")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(clap::App::new("reset").about("Use the default timeouts"))
+        .subcommand(
+            clap::App::new("set")
+                .arg(seconds_arg(
+                    "connecting-timeout",
+                    "How long an attempt may take before it is aborted, at least 4 seconds. 0 \
+                     disables the timeout",
+                ))
+                .arg(seconds_arg(
+                    "initial-retry-delay",
+                    "Delay before the first retry. It is doubled for every failed attempt",
+                ))
+                .arg(seconds_arg(
+                    "max-retry-delay",
+                    "Longest delay between two attempts",
+                ))
+                .arg(
+                    clap::Arg::new("offline-debounce")
+                        .long("offline-debounce")
+                        .takes_value(true)
+                        .value_name("MILLISECONDS")
+                        .help(
+                            "How long connectivity must stay lost or restored before it is \
+                             acted on, at most 10000. 0 acts on every change at once",
+                        ),
+                ),
+        )
+}
+
 impl Tunnel {
     async fn handle_openvpn_cmd(matches: &clap::ArgMatches) -> Result<()> {
         match matches.subcommand() {
@@ -158,6 +259,19 @@ impl Tunnel {
                 _ => unreachable!("unhandled command"),
             },
 
+            Some(("address-family", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_wireguard_address_family_get().await,
+                Some(("set", matches)) => Self::process_wireguard_address_family_set(matches).await,
+                Some(("reset", _)) => Self::process_wireguard_address_family_reset().await,
+                _ => unreachable!("unhandled command"),
+            },
+
+            Some(("dns", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_wireguard_dns_get().await,
+                Some(("set", matches)) => Self::process_wireguard_dns_set(matches).await,
+                _ => unreachable!("unhandled command"),
+            },
+
             Some(("key", matches)) => match matches.subcommand() {
                 Some(("check", _)) => Self::process_wireguard_key_check().await,
                 Some(("regenerate", _)) => Self::process_wireguard_key_generate().await,
@@ -192,15 +306,15 @@ impl Tunnel {
     }
 
     async fn process_wireguard_mtu_get() -> Result<()> {
-        let tunnel_options = Self::get_tunnel_options().await?;
-        let mtu = tunnel_options.wireguard.unwrap().mtu;
+        let mtu = Self::get_tunnel_options().await?.wireguard.options.mtu;
+        if json_output() {
+            format::print_json(&mtu);
+            return Ok(());
+        }
         println!(
             "mtu: {}",
-            if mtu != 0 {
-                mtu.to_string()
-            } else {
-                "unset".to_string()
-            },
+            mtu.map(|mtu| mtu.to_string())
+                .unwrap_or_else(|| "unset".to_string()),
         );
         Ok(())
     }
@@ -220,9 +334,101 @@ impl Tunnel {
         Ok(())
     }
 
+    async fn process_wireguard_address_family_get() -> Result<()> {
+        let address_family = Self::get_tunnel_options()
+            .await?
+            .wireguard
+            .options
+            .address_family;
+        if json_output() {
+            format::print_json(&address_family);
+            return Ok(());
+        }
+        match address_family {
+            Some(ip_version) => println!("address family: {}", ip_version),
+            None => println!("address family: IPv4 and IPv6"),
+        }
+        Ok(())
+    }
+
+    async fn process_wireguard_address_family_set(matches: &clap::ArgMatches) -> Result<()> {
+        let ip_version = match matches.value_of("family").unwrap() {
+            "ipv4" => types::IpVersion::V4,
+            "ipv6" => types::IpVersion::V6,
+            _ => unreachable!("unhandled IP version"),
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_address_family(types::IpVersionConstraint::from(ip_version))
+            .await?;
+        println!("Wireguard address family has been updated");
+        Ok(())
+    }
+
+    async fn process_wireguard_address_family_reset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.reset_wireguard_address_family(()).await?;
+        println!("Wireguard address family has been reset");
+        Ok(())
+    }
+
+    async fn process_wireguard_dns_get() -> Result<()> {
+        let options = Self::get_tunnel_options().await?.wireguard.options;
+        if json_output() {
+            format::print_json(&serde_json::json!({
+                "ip_version": options.dns_ip_version,
+                "prefer_ipv6": options.prefer_ipv6_dns,
+            }));
+            return Ok(());
+        }
+        match options.dns_ip_version {
+            Some(ip_version) => println!("resolvers: {}", ip_version),
+            None => println!("resolvers: IPv4 and IPv6"),
+        }
+        println!(
+            "prefer IPv6: {}",
+            if options.prefer_ipv6_dns { "on" } else { "off" }
+        );
+        Ok(())
+    }
+
+    async fn process_wireguard_dns_set(matches: &clap::ArgMatches) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut dns_options = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .tunnel_options
+            .and_then(|options| options.wireguard)
+            .ok_or(Error::InvalidResponse("missing WireGuard tunnel options"))?
+            .dns_options
+            .unwrap_or_default();
+        if let Some(ip_version) = matches.value_of("ip version") {
+            dns_options.ip_version = match ip_version {
+                "both" => None,
+                "ipv4" => Some(types::IpVersionConstraint::from(types::IpVersion::V4)),
+                "ipv6" => Some(types::IpVersionConstraint::from(types::IpVersion::V6)),
+                _ => unreachable!("unhandled IP version"),
+            };
+        }
+        if let Some(prefer_ipv6) = matches.value_of("prefer ipv6") {
+            dns_options.prefer_ipv6 = prefer_ipv6 == "on";
+        }
+        rpc.set_wireguard_dns_options(dns_options).await?;
+        println!("Wireguard DNS options have been updated");
+        Ok(())
+    }
+
     async fn process_wireguard_quantum_resistant_tunnel_get() -> Result<()> {
-        let tunnel_options = Self::get_tunnel_options().await?;
-        if tunnel_options.wireguard.unwrap().use_pq_safe_psk {
+        let use_pq_safe_psk = Self::get_tunnel_options()
+            .await?
+            .wireguard
+            .options
+            .use_pq_safe_psk;
+        if json_output() {
+            format::print_json(&use_pq_safe_psk);
+            return Ok(());
+        }
+        if use_pq_safe_psk {
             println!("enabled");
         } else {
             println!("disabled");
@@ -240,11 +446,12 @@ impl Tunnel {
             let multihop_is_enabled = settings
                 .into_inner()
                 .relay_settings
-                .unwrap()
-                .endpoint
+                .and_then(|settings| settings.endpoint)
                 .and_then(|endpoint| {
                     if let types::relay_settings::Endpoint::Normal(settings) = endpoint {
-                        Some(settings.wireguard_constraints.unwrap().use_multihop)
+                        settings
+                            .wireguard_constraints
+                            .map(|constraints| constraints.use_multihop)
                     } else {
                         None
                     }
@@ -263,8 +470,16 @@ impl Tunnel {
 
     #[cfg(windows)]
     async fn process_wireguard_use_wg_nt_get() -> Result<()> {
-        let tunnel_options = Self::get_tunnel_options().await?;
-        if tunnel_options.wireguard.unwrap().use_wireguard_nt {
+        let use_wireguard_nt = Self::get_tunnel_options()
+            .await?
+            .wireguard
+            .options
+            .use_wireguard_nt;
+        if json_output() {
+            format::print_json(&use_wireguard_nt);
+            return Ok(());
+        }
+        if use_wireguard_nt {
             println!("enabled");
         } else {
             println!("disabled");
@@ -287,19 +502,32 @@ impl Tunnel {
         let key = match key {
             Ok(response) => Some(response.into_inner()),
             Err(status) => {
-                if status.code() == mullvad_management_interface::Code::NotFound {
+                if mullvad_management_interface::error_kind(&status)
+                    == Some(types::ErrorKind::NoWireguardKey)
+                {
                     None
                 } else {
                     return Err(Error::RpcFailedExt("Failed to obtain key", status));
                 }
             }
         };
+        if json_output() {
+            let key = key
+                .map(|key| {
+                    let created = Self::key_timestamp(key.created.as_ref())?;
+                    Ok(serde_json::json!({
+                        "key": base64::encode(&key.key),
+                        "created": created,
+                    }))
+                })
+                .transpose()?;
+            format::print_json(&key);
+            return Ok(());
+        }
         if let Some(key) = key {
+            let created = Self::key_timestamp(key.created.as_ref())?;
             println!("Current key    : {}", base64::encode(&key.key));
-            println!(
-                "Key created on : {}",
-                Self::format_key_timestamp(&key.created.unwrap())
-            );
+            println!("Key created on : {}", created.with_timezone(&chrono::Local));
         } else {
             println!("No key is set");
             return Ok(());
@@ -315,10 +543,18 @@ impl Tunnel {
     }
 
     async fn process_wireguard_rotation_interval_get() -> Result<()> {
-        let tunnel_options = Self::get_tunnel_options().await?;
-        match tunnel_options.wireguard.unwrap().rotation_interval {
+        let rotation_interval = Self::get_tunnel_options()
+            .await?
+            .wireguard
+            .rotation_interval;
+        if json_output() {
+            let hours = rotation_interval.map(|interval| duration_hours(interval.as_duration()));
+            format::print_json(&hours);
+            return Ok(());
+        }
+        match rotation_interval {
             Some(interval) => {
-                let hours = duration_hours(&Duration::try_from(interval).unwrap());
+                let hours = duration_hours(interval.as_duration());
                 println!("Rotation interval: {} hour(s)", hours);
             }
             None => println!(
@@ -362,27 +598,28 @@ impl Tunnel {
     }
 
     async fn process_openvpn_mssfix_get() -> Result<()> {
-        let tunnel_options = Self::get_tunnel_options().await?;
-        let mssfix = tunnel_options.openvpn.unwrap().mssfix;
+        let mssfix = Self::get_tunnel_options().await?.openvpn.mssfix;
+        if json_output() {
+            format::print_json(&mssfix);
+            return Ok(());
+        }
         println!(
             "mssfix: {}",
-            if mssfix != 0 {
-                mssfix.to_string()
-            } else {
-                "unset".to_string()
-            },
+            mssfix
+                .map(|mssfix| mssfix.to_string())
+                .unwrap_or_else(|| "unset".to_string()),
         );
         Ok(())
     }
 
     async fn get_tunnel_options() -> Result<TunnelOptions> {
         let mut rpc = new_rpc_client().await?;
-        Ok(rpc
-            .get_settings(())
+        rpc.get_settings(())
             .await?
             .into_inner()
             .tunnel_options
-            .unwrap())
+            .and_then(|options| TunnelOptions::try_from(options).ok())
+            .ok_or(Error::InvalidResponse("invalid tunnel options"))
     }
 
     async fn process_openvpn_mssfix_unset() -> Result<()> {
@@ -401,15 +638,12 @@ impl Tunnel {
     }
 
     async fn process_ipv6_get() -> Result<()> {
-        let tunnel_options = Self::get_tunnel_options().await?;
-        println!(
-            "IPv6: {}",
-            if tunnel_options.generic.unwrap().enable_ipv6 {
-                "on"
-            } else {
-                "off"
-            }
-        );
+        let enable_ipv6 = Self::get_tunnel_options().await?.generic.enable_ipv6;
+        if json_output() {
+            format::print_json(&enable_ipv6);
+            return Ok(());
+        }
+        println!("IPv6: {}", if enable_ipv6 { "on" } else { "off" });
         Ok(())
     }
 
@@ -426,10 +660,61 @@ impl Tunnel {
         Ok(())
     }
 
-    fn format_key_timestamp(timestamp: &Timestamp) -> String {
+    async fn process_timeouts_get() -> Result<()> {
+        let timeouts = Self::get_timeouts().await?;
+        if json_output() {
+            format::print_json(&timeouts);
+            return Ok(());
+        }
+        println!("Connection timeouts: {}", timeouts);
+        Ok(())
+    }
+
+    async fn process_timeouts_set(matches: &clap::ArgMatches) -> Result<()> {
+        let mut timeouts = Self::get_timeouts().await?;
+        let value = |name| {
+            matches
+                .is_present(name)
+                .then(|| matches.value_of_t_or_exit::<u32>(name))
+        };
+        if let Some(secs) = value("connecting-timeout") {
+            timeouts.connecting_timeout_secs = secs;
+        }
+        if let Some(secs) = value("initial-retry-delay") {
+            timeouts.initial_retry_delay_secs = secs;
+        }
+        if let Some(secs) = value("max-retry-delay") {
+            timeouts.max_retry_delay_secs = secs;
+        }
+        if let Some(ms) = value("offline-debounce") {
+            timeouts.offline_debounce_ms = ms;
+        }
+        Self::set_timeouts(timeouts).await
+    }
+
+    async fn process_timeouts_reset() -> Result<()> {
+        Self::set_timeouts(ConnectionTimeouts::default()).await
+    }
+
+    async fn get_timeouts() -> Result<ConnectionTimeouts> {
+        Ok(Self::get_tunnel_options().await?.connection_timeouts)
+    }
+
+    async fn set_timeouts(timeouts: ConnectionTimeouts) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.apply_settings(types::SettingsUpdate {
+            connection_timeouts: Some(types::ConnectionTimeouts::from(timeouts)),
+            ..Default::default()
+        })
+        .await?;
+        println!("Set connection timeouts: {}", timeouts);
+        Ok(())
+    }
+
+    fn key_timestamp(timestamp: Option<&Timestamp>) -> Result<chrono::DateTime<chrono::Utc>> {
+        let timestamp = timestamp.ok_or(Error::InvalidResponse("missing key timestamp"))?;
         let ndt = chrono::NaiveDateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32);
-        let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
-        utc.with_timezone(&chrono::Local).to_string()
+        Ok(chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc))
     }
 }
 
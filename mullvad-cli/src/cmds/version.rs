@@ -1,4 +1,4 @@
-use crate::{new_rpc_client, Command, Error, Result};
+use crate::{format, json_output, new_rpc_client, Command, Error, Result};
 
 pub struct Version;
 
@@ -20,12 +20,28 @@ impl Command for Version {
             .await
             .map_err(|error| Error::RpcFailedExt("Failed to obtain current version", error))?
             .into_inner();
-        println!("{:21}: {}", "Current version", current_version);
         let version_info = rpc
             .get_version_info(())
             .await
             .map_err(|error| Error::RpcFailedExt("Failed to obtain version info", error))?
             .into_inner();
+        let settings = rpc.get_settings(()).await?.into_inner();
+
+        if json_output() {
+            let non_empty = |version: String| (!version.is_empty()).then(|| version);
+            format::print_json(&serde_json::json!({
+                "current": current_version,
+                "supported": version_info.supported,
+                "suggested_upgrade": non_empty(version_info.suggested_upgrade),
+                "latest_stable": non_empty(version_info.latest_stable),
+                "latest_beta": settings
+                    .show_beta_releases
+                    .then(|| version_info.latest_beta),
+            }));
+            return Ok(());
+        }
+
+        println!("{:21}: {}", "Current version", current_version);
         println!("{:21}: {}", "Is supported", version_info.supported);
 
         if !version_info.suggested_upgrade.is_empty() {
@@ -44,7 +60,6 @@ impl Command for Version {
             );
         }
 
-        let settings = rpc.get_settings(()).await?.into_inner();
         if settings.show_beta_releases {
             println!("{:21}: {}", "Latest beta version", version_info.latest_beta);
         };
@@ -1,28 +1,55 @@
 use mullvad_types::{auth_failed::AuthFailed, location::GeoIpLocation, states::TunnelState};
 use talpid_types::{
     net::{Endpoint, TunnelEndpoint},
-    tunnel::ErrorState,
+    tunnel::{ErrorState, TunnelHealth},
 };
 
+/// Prints `value` as JSON on a single line.
+pub fn print_json<T: serde::Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string(value).expect("failed to serialize output")
+    );
+}
+
 pub fn print_state(state: &TunnelState, verbose: bool) {
     use TunnelState::*;
 
     match state {
         Error(error) => print_error_state(error),
-        Connected { endpoint, location } => {
+        Connected {
+            endpoint,
+            location,
+            health,
+        } => {
+            let health = match health {
+                TunnelHealth::Healthy => String::new(),
+                health => format!(" ({health})"),
+            };
             println!(
-                "Connected to {}",
+                "Connected to {}{health}",
                 format_relay_connection(endpoint, location.as_ref(), verbose)
             );
         }
-        Connecting { endpoint, location } => {
+        Connecting {
+            endpoint,
+            location,
+            reconnect_reason,
+            phase,
+        } => {
             let ellipsis = if !verbose { "..." } else { "" };
+            let reason = reconnect_reason
+                .map(|reason| format!(" (reconnecting: {reason})"))
+                .unwrap_or_default();
             println!(
-                "Connecting to {}{ellipsis}",
+                "Connecting to {}{ellipsis} [{phase}]{reason}",
                 format_relay_connection(endpoint, location.as_ref(), verbose)
             );
         }
-        Disconnected => println!("Disconnected"),
+        Disconnected { reason } => match reason {
+            Some(reason) => println!("Disconnected ({reason})"),
+            None => println!("Disconnected"),
+        },
         Disconnecting(_) => println!("Disconnecting..."),
     }
 }
@@ -136,15 +163,51 @@ fn format_relay_connection(
             obfuscator_type = format!("\nObfuscator: {}", obfuscator.obfuscation_type);
         }
     }
+    let relay_info = location
+        .filter(|_| verbose)
+        .map(format_relay_info)
+        .unwrap_or_default();
 
     format!(
-        "{exit_endpoint}{first_hop}{bridge}{obfuscator}{tunnel_type}{quantum_resistant}{bridge_type}{obfuscator_type}",
+        "{exit_endpoint}{first_hop}{bridge}{obfuscator}{tunnel_type}{quantum_resistant}{bridge_type}{obfuscator_type}{relay_info}",
         first_hop = first_hop.unwrap_or_default(),
         bridge = bridge.unwrap_or_default(),
         obfuscator = obfuscator.unwrap_or_default(),
     )
 }
 
+/// Describes who runs the exit relay and which optional features are in use.
+fn format_relay_info(location: &GeoIpLocation) -> String {
+    let mut info = String::new();
+    if let Some(selected) = &location.nearby_fallback_from {
+        info.push_str(&format!(
+            "\nNo relay available in {selected}, using a relay in a nearby location"
+        ));
+    }
+    if let Some(provider) = &location.provider {
+        let ownership = match location.owned {
+            Some(true) => " (Mullvad-owned)",
+            Some(false) => " (rented)",
+            None => "",
+        };
+        info.push_str(&format!("\nRelay provider: {provider}{ownership}"));
+    }
+    if let Some(features) = location.features {
+        let features: Vec<&str> = [
+            (features.quantum_resistant, "quantum resistance"),
+            (features.daita, "DAITA"),
+            (features.udp2tcp, "UDP-over-TCP"),
+        ]
+        .into_iter()
+        .filter_map(|(active, name)| active.then(|| name))
+        .collect();
+        if !features.is_empty() {
+            info.push_str(&format!("\nActive features: {}", features.join(", ")));
+        }
+    }
+    info
+}
+
 fn format_endpoint(hostname: Option<&str>, endpoint: &Endpoint, verbose: bool) -> String {
     match (hostname, verbose) {
         (Some(hostname), true) => format!("{hostname} ({endpoint})"),
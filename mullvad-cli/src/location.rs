@@ -1,81 +1,31 @@
 use mullvad_management_interface::types::RelayLocation;
+use mullvad_types::relay_constraints::{Constraint, LocationConstraint};
 
 pub fn get_subcommand() -> clap::App<'static> {
-    clap::App::new("location")
-        .arg(
-            clap::Arg::new("country")
-                .help("The two letter country code, or 'any' for no preference.")
-                .required(true)
-                .index(1)
-                .validator(country_code_validator),
-        )
-        .arg(
-            clap::Arg::new("city")
-                .help("The three letter city code")
-                .index(2)
-                .validator(city_code_validator),
-        )
-        .arg(clap::Arg::new("hostname").help("The hostname").index(3))
+    clap::App::new("location").arg(
+        clap::Arg::new("location")
+            .help(
+                "The location, or 'any' for no preference. This is a two letter country code, \
+                 a country and city code such as 'se/got', or a hostname such as \
+                 'se-got-wg-001'. The parts may also be given separately, as in 'se got'.",
+            )
+            .required(true)
+            .min_values(1)
+            .max_values(3),
+    )
 }
 
 pub fn get_constraint_from_args(matches: &clap::ArgMatches) -> RelayLocation {
-    let country = matches.value_of("country").unwrap();
-    let city = matches.value_of("city");
-    let hostname = matches.value_of("hostname");
-    get_constraint(country, city, hostname)
+    get_constraint(matches.values_of("location").unwrap())
 }
 
-pub fn get_constraint<T: AsRef<str>>(
-    country: T,
-    city: Option<T>,
-    hostname: Option<T>,
-) -> RelayLocation {
-    let country_original = country.as_ref();
-    let country = country_original.to_lowercase();
-    let city = city.map(|s| s.as_ref().to_lowercase());
-    let hostname = hostname.map(|s| s.as_ref().to_lowercase());
-
-    match (country_original, city, hostname) {
-        ("any", None, None) => RelayLocation::default(),
-        ("any", ..) => clap::Error::raw(
-            clap::ErrorKind::InvalidValue,
-            "City can't be given when selecting 'any' country",
-        )
-        .exit(),
-        (_, None, None) => RelayLocation {
-            country,
-            ..Default::default()
-        },
-        (_, Some(city), None) => RelayLocation {
-            country,
-            city,
-            ..Default::default()
-        },
-        (_, Some(city), Some(hostname)) => RelayLocation {
-            country,
-            city,
-            hostname,
-        },
-        (..) => clap::Error::raw(
-            clap::ErrorKind::InvalidValue,
-            "Invalid country, city and hostname combination given",
-        )
-        .exit(),
-    }
-}
-
-pub fn country_code_validator(code: &str) -> std::result::Result<(), String> {
-    if code.len() == 2 || code == "any" {
-        Ok(())
-    } else {
-        Err(String::from("Country codes must be two letters, or 'any'."))
-    }
-}
-
-pub fn city_code_validator(code: &str) -> std::result::Result<(), String> {
-    if code.len() == 3 {
-        Ok(())
-    } else {
-        Err(String::from("City codes must be three letters"))
+/// Parses a location that is given either as a single shorthand or as separate country, city
+/// and hostname arguments.
+pub fn get_constraint<'a>(parts: impl Iterator<Item = &'a str>) -> RelayLocation {
+    let location = parts.collect::<Vec<_>>().join("/");
+    match location.parse::<Constraint<LocationConstraint>>() {
+        Ok(Constraint::Any) => RelayLocation::default(),
+        Ok(Constraint::Only(location)) => RelayLocation::from(location),
+        Err(error) => clap::Error::raw(clap::ErrorKind::InvalidValue, error).exit(),
     }
 }
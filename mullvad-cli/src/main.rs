@@ -4,8 +4,12 @@ use clap::{crate_authors, crate_description};
 #[cfg(all(unix, not(target_os = "android")))]
 use clap_complete::{generator::generate_to, Shell};
 use mullvad_management_interface::async_trait;
-use std::{collections::HashMap, io};
-use talpid_types::ErrorExt;
+use std::{
+    collections::HashMap,
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use talpid_types::{tunnel::ErrorState, ErrorExt};
 
 pub use mullvad_management_interface::{self, new_rpc_client};
 
@@ -16,8 +20,24 @@ mod state;
 
 pub const BIN_NAME: &str = "mullvad";
 
+/// Whether `--output json` was given. Commands that support it print their results as JSON.
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+pub fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Exit code when a command fails for any reason not listed below.
+const EXIT_FAILURE: i32 = 1;
+/// Exit code when the tunnel ends up in the error state with all traffic blocked.
+const EXIT_TUNNEL_ERROR_BLOCKING: i32 = 2;
+/// Exit code when the tunnel ends up in the error state without being able to block traffic.
+const EXIT_TUNNEL_ERROR_NOT_BLOCKING: i32 = 3;
+/// Exit code when the daemon could not be reached, or stopped responding.
+const EXIT_DAEMON_UNAVAILABLE: i32 = 4;
+
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
     #[error(display = "Failed to connect to daemon")]
@@ -42,9 +62,17 @@ pub enum Error {
     #[error(display = "Command failed: {}", _0)]
     CommandFailed(&'static str),
 
+    /// The daemon returned something that could not be parsed
+    #[error(display = "Invalid response from daemon: {}", _0)]
+    InvalidResponse(&'static str),
+
     #[error(display = "Failed to listen for status updates")]
     StatusListenerFailed,
 
+    /// The tunnel entered the error state while waiting for it to connect
+    #[error(display = "The tunnel entered the error state")]
+    TunnelErrorState(ErrorState),
+
     //#[cfg(all(unix, not(target_os = "android"))
     #[error(display = "Failed to generate shell completions")]
     CompletionsError(#[error(source, no_from)] io::Error),
@@ -53,6 +81,19 @@ pub enum Error {
     Other(&'static str),
 }
 
+impl Error {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Error::TunnelErrorState(state) if state.is_blocking() => EXIT_TUNNEL_ERROR_BLOCKING,
+            Error::TunnelErrorState(_) => EXIT_TUNNEL_ERROR_NOT_BLOCKING,
+            Error::DaemonNotRunning(_)
+            | Error::ManagementInterfaceError(_)
+            | Error::StatusListenerFailed => EXIT_DAEMON_UNAVAILABLE,
+            _ => EXIT_FAILURE,
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let exit_code = match run().await {
@@ -70,7 +111,7 @@ async fn main() {
                 ),
                 error => eprintln!("{}", error.display_chain()),
             }
-            1
+            error.exit_code()
         }
     };
     std::process::exit(exit_code);
@@ -102,6 +143,8 @@ async fn run() -> Result<()> {
     );
 
     let app_matches = app.get_matches();
+    JSON_OUTPUT.store(uses_json_output(&app_matches), Ordering::Relaxed);
+
     match app_matches.subcommand() {
         #[cfg(all(unix, not(target_os = "android")))]
         Some(("shell-completions", sub_matches)) => {
@@ -137,9 +180,32 @@ fn build_cli(commands: &HashMap<&'static str, Box<dyn Command>>) -> clap::App<'s
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .global_setting(clap::AppSettings::DisableHelpSubcommand)
         .global_setting(clap::AppSettings::DisableVersionFlag)
+        .arg(
+            clap::Arg::new("output")
+                .long("output")
+                .global(true)
+                .takes_value(true)
+                .possible_values(["text", "json"])
+                .default_value("text")
+                .help("Print results as text or as JSON"),
+        )
         .subcommands(commands.values().map(|cmd| cmd.clap_subcommand()))
 }
 
+/// The global `--output` argument may be given after any subcommand, so look for it at every
+/// level.
+fn uses_json_output(mut matches: &clap::ArgMatches) -> bool {
+    loop {
+        if matches.value_of("output") == Some("json") {
+            return true;
+        }
+        match matches.subcommand() {
+            Some((_, sub_matches)) => matches = sub_matches,
+            None => return false,
+        }
+    }
+}
+
 #[async_trait]
 pub trait Command {
     fn name(&self) -> &'static str;
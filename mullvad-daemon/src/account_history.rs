@@ -1,10 +1,11 @@
-use mullvad_types::account::AccountToken;
+use mullvad_api::cache_integrity;
+use mullvad_types::{account::AccountToken, cache::CorruptionReason};
 use regex::Regex;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use talpid_types::ErrorExt;
 use tokio::{
     fs,
-    io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    io::{self, AsyncSeekExt, AsyncWriteExt},
 };
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -29,6 +30,7 @@ static ACCOUNT_HISTORY_FILE: &str = "account-history.json";
 
 pub struct AccountHistory {
     file: io::BufWriter<fs::File>,
+    path: PathBuf,
     token: Option<AccountToken>,
 }
 
@@ -54,28 +56,33 @@ impl AccountHistory {
 
         let path = settings_dir.join(ACCOUNT_HISTORY_FILE);
         log::info!("Opening account history file in {}", path.display());
-        let mut reader = options
-            .write(true)
-            .create(true)
-            .read(true)
-            .open(path)
-            .await
-            .map(io::BufReader::new)
-            .map_err(Error::Read)?;
 
-        let mut buffer = String::new();
+        // The file is read before it is opened for writing, since a corrupt file is removed.
         let (token, should_save): (Option<AccountToken>, bool) =
-            match reader.read_to_string(&mut buffer).await {
-                Ok(_) if ACCOUNT_REGEX.is_match(&buffer) => (Some(buffer), false),
-                Ok(0) => (current_token, true),
-                Ok(_) | Err(_) => {
-                    log::warn!("Failed to parse account history");
-                    (current_token, true)
-                }
+            match cache_integrity::read(&path).await {
+                Ok(contents) if contents.is_empty() => (current_token, true),
+                Ok(contents) => match std::str::from_utf8(&contents) {
+                    Ok(token) if ACCOUNT_REGEX.is_match(token) => (Some(token.to_owned()), false),
+                    _ => {
+                        log::warn!("Failed to parse account history");
+                        cache_integrity::quarantine(&path, contents, CorruptionReason::ParseError)
+                            .await;
+                        (current_token, true)
+                    }
+                },
+                Err(error) if error.kind() == io::ErrorKind::NotFound => (current_token, true),
+                Err(error) if error.kind() == io::ErrorKind::InvalidData => (current_token, true),
+                Err(error) => return Err(Error::Read(error)),
             };
 
-        let file = io::BufWriter::new(reader.into_inner());
-        let mut history = AccountHistory { file, token };
+        let file = options
+            .write(true)
+            .create(true)
+            .open(&path)
+            .await
+            .map(io::BufWriter::new)
+            .map_err(Error::Read)?;
+        let mut history = AccountHistory { file, path, token };
         if should_save {
             if let Err(error) = history.save_to_disk().await {
                 log::error!(
@@ -105,6 +112,9 @@ impl AccountHistory {
     }
 
     async fn save_to_disk(&mut self) -> Result<()> {
+        cache_integrity::remove_checksum(&self.path)
+            .await
+            .map_err(Error::Write)?;
         self.file.get_mut().set_len(0).await.map_err(Error::Write)?;
         self.file
             .seek(io::SeekFrom::Start(0))
@@ -117,6 +127,44 @@ impl AccountHistory {
                 .map_err(Error::Write)?;
         }
         self.file.flush().await.map_err(Error::Write)?;
-        self.file.get_mut().sync_all().await.map_err(Error::Write)
+        self.file.get_mut().sync_all().await.map_err(Error::Write)?;
+
+        let contents = self.token.as_deref().unwrap_or_default();
+        cache_integrity::write_checksum(&self.path, contents.as_bytes())
+            .await
+            .map_err(Error::Write)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_corrupt_history_is_quarantined() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("account-history-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(ACCOUNT_HISTORY_FILE);
+
+        runtime.block_on(async {
+            let mut history = AccountHistory::new(&dir, None).await.unwrap();
+            history.set("1234123412341234".to_owned()).await.unwrap();
+            drop(history);
+
+            let history = AccountHistory::new(&dir, None).await.unwrap();
+            assert_eq!(history.get().as_deref(), Some("1234123412341234"));
+            drop(history);
+
+            std::fs::write(&path, "4321432143214321").unwrap();
+            let history = AccountHistory::new(&dir, None).await.unwrap();
+            assert_eq!(history.get(), None);
+        });
+
+        assert!(dir.join(cache_integrity::QUARANTINE_DIR).exists());
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
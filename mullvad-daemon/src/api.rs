@@ -1,5 +1,6 @@
 #[cfg(target_os = "android")]
-use crate::{DaemonCommand, DaemonEventSender};
+use crate::DaemonCommand;
+use crate::{DaemonEventSender, InternalDaemonEvent};
 use futures::{
     channel::{mpsc, oneshot},
     Future, Stream, StreamExt,
@@ -10,20 +11,15 @@ use mullvad_api::{
     ApiEndpointUpdateCallback,
 };
 use mullvad_relay_selector::RelaySelector;
+use mullvad_types::access_method::{AccessMethod, CustomProxy};
 use std::{
-    net::SocketAddr,
     path::PathBuf,
     pin::Pin,
     sync::{Arc, Mutex, Weak},
     task::Poll,
 };
-#[cfg(target_os = "android")]
-use talpid_core::mpsc::Sender;
-use talpid_core::tunnel_state_machine::TunnelCommand;
-use talpid_types::{
-    net::{openvpn::ProxySettings, AllowedEndpoint, Endpoint, TransportProtocol},
-    ErrorExt,
-};
+use talpid_core::{mpsc::Sender, tunnel_state_machine::TunnelCommand};
+use talpid_types::net::{openvpn::ProxySettings, AllowedEndpoint, Endpoint};
 
 /// A stream that returns the next API connection mode to use for reaching the API.
 ///
@@ -31,20 +27,51 @@ use talpid_types::{
 /// The API can be connected to either directly (i.e., [`ApiConnectionMode::Direct`])
 /// or from a bridge ([`ApiConnectionMode::Proxied`]).
 ///
-/// * Every 3rd attempt returns [`ApiConnectionMode::Direct`].
-/// * Any other attempt returns a configuration for the bridge that is closest to the selected relay
-///   location and matches all bridge constraints.
-/// * When no matching bridge is found, e.g. if the selected hosting providers don't match any
-///   bridge, [`ApiConnectionMode::Direct`] is returned.
+/// The modes are tried in this order, starting over once all have been tried:
+///
+/// 1. [`ApiConnectionMode::Direct`].
+/// 2. The proxy that the API was last reached through, as saved by `mullvad-api`. This is skipped
+///    if the API was last reached directly.
+/// 3. Every enabled access method that the user has added, in order.
+/// 4. Two bridges that are close to the selected relay location and match all bridge
+///    constraints. [`ApiConnectionMode::Direct`] is used instead if no bridge matches, e.g. if
+///    the selected hosting providers don't match any bridge.
+///
+/// The first mode returned is the one that last worked, so that a censored network does not have
+/// to be worked around again every time the daemon starts.
 pub struct ApiConnectionModeProvider {
     cache_dir: PathBuf,
 
     relay_selector: RelaySelector,
-    retry_attempt: u32,
+    access_methods: AccessMethodsHandle,
+    retry_attempt: usize,
+    /// Index of the next access method to try in the [`FallbackStep::AccessMethods`] step.
+    access_method_index: usize,
 
     current_task: Option<Pin<Box<dyn Future<Output = ApiConnectionMode> + Send>>>,
 }
 
+/// A step of the fallback chain of [`ApiConnectionModeProvider`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FallbackStep {
+    Direct,
+    CachedProxy,
+    /// Tries each enabled access method of the user in turn.
+    AccessMethods,
+    Bridge,
+}
+
+const FALLBACK_CHAIN: [FallbackStep; 5] = [
+    FallbackStep::Direct,
+    FallbackStep::CachedProxy,
+    FallbackStep::AccessMethods,
+    FallbackStep::Bridge,
+    FallbackStep::Bridge,
+];
+
+/// Start with the cached proxy, if there is one.
+const INITIAL_RETRY_ATTEMPT: usize = 1;
+
 impl Stream for ApiConnectionModeProvider {
     type Item = ApiConnectionMode;
 
@@ -63,69 +90,156 @@ impl Stream for ApiConnectionModeProvider {
             };
         }
 
-        // Create a new task.
-        let config = if Self::should_use_bridge(self.retry_attempt) {
-            self.relay_selector
-                .get_bridge_forced()
-                .map(|settings| match settings {
-                    ProxySettings::Shadowsocks(ss_settings) => {
-                        ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss_settings))
-                    }
-                    _ => {
-                        log::error!("Received unexpected proxy settings type");
-                        ApiConnectionMode::Direct
-                    }
-                })
-                .unwrap_or(ApiConnectionMode::Direct)
-        } else {
-            ApiConnectionMode::Direct
-        };
+        // Select the next connection mode
+        let step = FALLBACK_CHAIN[self.retry_attempt % FALLBACK_CHAIN.len()];
+        let first_attempt = self.retry_attempt == INITIAL_RETRY_ATTEMPT;
 
+        if step == FallbackStep::AccessMethods {
+            if let Some(proxy) = self.access_methods.get(self.access_method_index) {
+                self.access_method_index += 1;
+                return Poll::Ready(Some(ApiConnectionMode::Proxied(proxy)));
+            }
+            // Every access method has been tried
+            self.access_method_index = 0;
+            self.retry_attempt = self.retry_attempt.wrapping_add(1);
+            return self.poll_next(cx);
+        }
         self.retry_attempt = self.retry_attempt.wrapping_add(1);
 
-        let cache_dir = self.cache_dir.clone();
-        self.current_task = Some(Box::pin(async move {
-            if let Err(error) = config.save(&cache_dir).await {
-                log::debug!(
-                    "{}",
-                    error.display_chain_with_msg("Failed to save API endpoint")
-                );
+        let mode = match step {
+            FallbackStep::Direct => ApiConnectionMode::Direct,
+            FallbackStep::Bridge => self.get_bridge(),
+            FallbackStep::CachedProxy => {
+                // Without a cached proxy, start with a direct connection, or move on to the
+                // bridges if that has already been tried
+                let fallback = if first_attempt {
+                    ApiConnectionMode::Direct
+                } else {
+                    self.get_bridge()
+                };
+                let cache_dir = self.cache_dir.clone();
+                self.current_task = Some(Box::pin(async move {
+                    let cached_mode = ApiConnectionMode::try_from_cache(&cache_dir).await;
+                    if cached_mode.is_proxy() {
+                        cached_mode
+                    } else {
+                        fallback
+                    }
+                }));
+                return self.poll_next(cx);
             }
-            config
-        }));
+            FallbackStep::AccessMethods => unreachable!("access methods are handled above"),
+        };
 
-        self.poll_next(cx)
+        Poll::Ready(Some(mode))
     }
 }
 
 impl ApiConnectionModeProvider {
-    pub(crate) fn new(cache_dir: PathBuf, relay_selector: RelaySelector) -> Self {
+    pub(crate) fn new(
+        cache_dir: PathBuf,
+        relay_selector: RelaySelector,
+        access_methods: AccessMethodsHandle,
+    ) -> Self {
         Self {
             cache_dir,
 
             relay_selector,
-            retry_attempt: 0,
+            access_methods,
+            retry_attempt: INITIAL_RETRY_ATTEMPT,
+            access_method_index: 0,
 
             current_task: None,
         }
     }
 
-    fn should_use_bridge(retry_attempt: u32) -> bool {
-        retry_attempt % 3 > 0
+    /// Returns a bridge for the current relay settings, or a direct connection if there is none.
+    fn get_bridge(&self) -> ApiConnectionMode {
+        self.relay_selector
+            .get_bridge_forced()
+            .map(|settings| match settings {
+                ProxySettings::Shadowsocks(ss_settings) => {
+                    ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss_settings))
+                }
+                ProxySettings::Local(local_settings) => {
+                    ApiConnectionMode::Proxied(ProxyConfig::Socks5Local(local_settings))
+                }
+                ProxySettings::Remote(remote_settings) => {
+                    ApiConnectionMode::Proxied(ProxyConfig::Socks5Remote(remote_settings))
+                }
+            })
+            .unwrap_or(ApiConnectionMode::Direct)
+    }
+}
+
+/// The enabled access methods of the user, shared with [`ApiConnectionModeProvider`]. The daemon
+/// updates them when the settings change.
+#[derive(Clone, Default)]
+pub(crate) struct AccessMethodsHandle {
+    proxies: Arc<Mutex<Vec<ProxyConfig>>>,
+}
+
+impl AccessMethodsHandle {
+    pub fn new(methods: &[AccessMethod]) -> Self {
+        let handle = Self::default();
+        handle.set(methods);
+        handle
+    }
+
+    pub fn set(&self, methods: &[AccessMethod]) {
+        *self.proxies.lock().unwrap() = methods
+            .iter()
+            .filter(|method| method.enabled)
+            .filter(|method| {
+                let supported = cfg!(feature = "api-http3") || !method.proxy.requires_http3();
+                if !supported {
+                    log::warn!("Ignoring unsupported UDP access method \"{}\"", method.name);
+                }
+                supported
+            })
+            .map(|method| access_method_proxy(&method.proxy))
+            .collect();
+    }
+
+    fn get(&self, index: usize) -> Option<ProxyConfig> {
+        self.proxies.lock().unwrap().get(index).cloned()
+    }
+}
+
+pub(crate) fn access_method_proxy(proxy: &CustomProxy) -> ProxyConfig {
+    match proxy {
+        CustomProxy::Shadowsocks(settings) => ProxyConfig::Shadowsocks(settings.clone()),
+        CustomProxy::Socks5(settings) => ProxyConfig::Socks5Remote(settings.clone()),
+        CustomProxy::Http(settings) => ProxyConfig::HttpProxy(settings.clone()),
+        CustomProxy::Obfs4(settings) => ProxyConfig::Obfs4(settings.clone()),
     }
 }
 
 /// Notifies the tunnel state machine that the API (real or proxied) endpoint has
 /// changed. [ApiEndpointUpdaterHandle::callback()] creates a callback that may
 /// be passed to the `mullvad-api` runtime.
+#[derive(Clone)]
 pub(super) struct ApiEndpointUpdaterHandle {
     tunnel_cmd_tx: Arc<Mutex<Option<Weak<mpsc::UnboundedSender<TunnelCommand>>>>>,
+    endpoints: Arc<Mutex<AllowedApiEndpoints>>,
+    /// Held while an endpoint is allowed temporarily, so that only one is allowed at a time.
+    temporary_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+#[derive(Default)]
+struct AllowedApiEndpoints {
+    /// The endpoint last allowed through [ApiEndpointUpdaterHandle::callback()].
+    current: Option<Endpoint>,
+    /// Endpoint allowed alongside `current` while an access method is tested.
+    temporary: Option<Endpoint>,
 }
 
 impl ApiEndpointUpdaterHandle {
     pub fn new() -> Self {
         Self {
             tunnel_cmd_tx: Arc::new(Mutex::new(None)),
+            endpoints: Arc::new(Mutex::new(AllowedApiEndpoints::default())),
+            temporary_lock: Arc::new(tokio::sync::Mutex::new(())),
         }
     }
 
@@ -133,56 +247,123 @@ impl ApiEndpointUpdaterHandle {
         *self.tunnel_cmd_tx.lock().unwrap() = Some(tunnel_cmd_tx);
     }
 
+    /// Records the endpoint that the tunnel state machine was started with.
+    pub fn set_initial_endpoint(&self, endpoint: Endpoint) {
+        self.endpoints.lock().unwrap().current = Some(endpoint);
+    }
+
     pub fn callback(&self) -> impl ApiEndpointUpdateCallback {
-        let tunnel_tx = self.tunnel_cmd_tx.clone();
-        move |address: SocketAddr| {
-            let inner_tx = tunnel_tx.clone();
+        let handle = self.clone();
+        move |endpoint: Endpoint| {
+            let handle = handle.clone();
             async move {
-                let tunnel_tx = if let Some(Some(tunnel_tx)) = { inner_tx.lock().unwrap().as_ref() }
-                    .map(|tx: &Weak<mpsc::UnboundedSender<TunnelCommand>>| tx.upgrade())
-                {
-                    tunnel_tx
-                } else {
-                    log::error!("Rejecting allowed endpoint: Tunnel state machine is not running");
-                    return false;
-                };
-                let (result_tx, result_rx) = oneshot::channel();
-                let _ = tunnel_tx.unbounded_send(TunnelCommand::AllowEndpoint(
-                    get_allowed_endpoint(address),
-                    result_tx,
-                ));
-                // Wait for the firewall policy to be updated.
-                let _ = result_rx.await;
-                log::debug!("API endpoint: {}", address);
-                true
+                let temporary = handle.endpoints.lock().unwrap().temporary;
+                let allowed = handle.allow_endpoints(endpoint, temporary).await;
+                if allowed {
+                    handle.endpoints.lock().unwrap().current = Some(endpoint);
+                }
+                allowed
             }
         }
     }
-}
 
-pub(super) fn get_allowed_endpoint(api_address: SocketAddr) -> AllowedEndpoint {
-    let endpoint = Endpoint::from_socket_address(api_address, TransportProtocol::Tcp);
+    /// Allows `endpoint` alongside the current API endpoint while `future` runs. This is for
+    /// testing other ways of reaching the API without disrupting requests that are in flight.
+    pub async fn with_temporary_endpoint<F: Future>(
+        &self,
+        endpoint: Endpoint,
+        future: F,
+    ) -> F::Output {
+        let _guard = self.temporary_lock.lock().await;
+
+        self.set_temporary_endpoint(Some(endpoint)).await;
+        let output = future.await;
+        self.set_temporary_endpoint(None).await;
+
+        output
+    }
+
+    async fn set_temporary_endpoint(&self, temporary: Option<Endpoint>) {
+        let current = {
+            let mut endpoints = self.endpoints.lock().unwrap();
+            endpoints.temporary = temporary;
+            endpoints.current
+        };
+        match (current, temporary) {
+            (Some(current), _) => {
+                self.allow_endpoints(current, temporary).await;
+            }
+            (None, Some(temporary)) => {
+                self.allow_endpoints(temporary, None).await;
+            }
+            (None, None) => {
+                log::warn!("No API endpoint to restore");
+            }
+        }
+    }
 
+    async fn allow_endpoints(&self, endpoint: Endpoint, extra_endpoint: Option<Endpoint>) -> bool {
+        let tunnel_tx = self
+            .tunnel_cmd_tx
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|tx| tx.upgrade());
+        let tunnel_tx = match tunnel_tx {
+            Some(tunnel_tx) => tunnel_tx,
+            None => {
+                log::error!("Rejecting allowed endpoint: Tunnel state machine is not running");
+                return false;
+            }
+        };
+        let mut allowed_endpoint = get_allowed_endpoint(endpoint);
+        allowed_endpoint.extra_endpoint = extra_endpoint;
+        let (result_tx, result_rx) = oneshot::channel();
+        let _ = tunnel_tx.unbounded_send(TunnelCommand::AllowEndpoint(allowed_endpoint, result_tx));
+        // Wait for the firewall policy to be updated.
+        let _ = result_rx.await;
+        match extra_endpoint {
+            Some(extra_endpoint) => {
+                log::debug!("API endpoint: {} and {}", endpoint, extra_endpoint)
+            }
+            None => log::debug!("API endpoint: {}", endpoint),
+        }
+        true
+    }
+}
+
+pub(super) fn get_allowed_endpoint(endpoint: Endpoint) -> AllowedEndpoint {
     #[cfg(windows)]
     let daemon_exe = std::env::current_exe().expect("failed to obtain executable path");
     #[cfg(windows)]
-    let clients = vec![
+    let mut clients = vec![
         daemon_exe
             .parent()
             .expect("missing executable parent directory")
             .join("mullvad-problem-report.exe"),
         daemon_exe,
     ];
+    // Bridges for pluggable transports are contacted by the transport client
+    #[cfg(windows)]
+    clients.push(mullvad_api::transport::client_path());
 
     AllowedEndpoint {
         #[cfg(windows)]
         clients,
         endpoint,
+        extra_endpoint: None,
+        #[cfg(not(target_os = "android"))]
+        resolvers: mullvad_api::trusted_resolver_endpoints(),
+        // Encrypted DNS is not used for the API on Android
+        #[cfg(target_os = "android")]
+        resolvers: vec![],
     }
 }
 
+/// Passes on the connectivity reported by the tunnel state machine to the API and the daemon.
 pub(crate) fn forward_offline_state(
     api_availability: ApiAvailabilityHandle,
+    daemon_tx: DaemonEventSender,
     mut offline_state_rx: mpsc::UnboundedReceiver<bool>,
 ) {
     tokio::spawn(async move {
@@ -193,6 +374,7 @@ pub(crate) fn forward_offline_state(
         api_availability.set_offline(initial_state);
         while let Some(is_offline) = offline_state_rx.next().await {
             api_availability.set_offline(is_offline);
+            let _ = daemon_tx.send(InternalDaemonEvent::OfflineStateChanged(is_offline));
         }
     });
 }
@@ -213,3 +395,78 @@ pub(crate) fn create_bypass_tx(
     });
     Some(bypass_tx)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::settings::Settings;
+    use std::path::Path;
+    use talpid_types::net::openvpn::RemoteProxySettings;
+
+    fn socks5(port: u16) -> AccessMethod {
+        AccessMethod {
+            name: format!("proxy {}", port),
+            enabled: true,
+            proxy: CustomProxy::Socks5(RemoteProxySettings {
+                address: format!("192.0.2.1:{}", port).parse().unwrap(),
+                auth: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_fallback_chain_order() {
+        // No relays or cached mode can be read from here, so every bridge and cached proxy step
+        // falls back on a direct connection.
+        let missing_dir = Path::new("/nonexistent-mullvad-test-dir");
+        let relay_selector = RelaySelector::new(
+            crate::new_selector_config(&Settings::default(), &None),
+            missing_dir,
+            missing_dir,
+        );
+        let mut disabled = socks5(3);
+        disabled.enabled = false;
+        let methods = [socks5(1), disabled, socks5(2)];
+        let mut provider = ApiConnectionModeProvider::new(
+            missing_dir.to_path_buf(),
+            relay_selector,
+            AccessMethodsHandle::new(&methods),
+        );
+
+        let direct = ApiConnectionMode::Direct;
+        let method =
+            |index: usize| ApiConnectionMode::Proxied(access_method_proxy(&methods[index].proxy));
+        let expected = [
+            // Cached proxy
+            direct.clone(),
+            // Enabled access methods, in order
+            method(0),
+            method(2),
+            // Bridges
+            direct.clone(),
+            direct.clone(),
+            // Direct, then the cached proxy again
+            direct.clone(),
+            direct.clone(),
+            method(0),
+            method(2),
+        ];
+        for expected_mode in expected {
+            let mode = futures::executor::block_on(provider.next()).unwrap();
+            assert_eq!(mode, expected_mode);
+        }
+    }
+
+    #[test]
+    fn test_fallback_chain_starts_with_cached_proxy() {
+        assert_eq!(FALLBACK_CHAIN[0], FallbackStep::Direct);
+        assert_eq!(
+            FALLBACK_CHAIN[INITIAL_RETRY_ATTEMPT],
+            FallbackStep::CachedProxy
+        );
+        assert_eq!(
+            FALLBACK_CHAIN[INITIAL_RETRY_ATTEMPT + 1],
+            FallbackStep::AccessMethods
+        );
+    }
+}
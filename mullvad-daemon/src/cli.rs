@@ -1,14 +1,21 @@
 use clap::{crate_authors, crate_description, crate_name, App, Arg};
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub struct Config {
     pub log_level: log::LevelFilter,
     pub log_to_file: bool,
     pub log_stdout_timestamps: bool,
+    pub trace_file: Option<PathBuf>,
     pub run_as_service: bool,
     pub register_service: bool,
     #[cfg(target_os = "linux")]
     pub initialize_firewall_and_exit: bool,
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub self_check: bool,
+    /// Summary and body of a notification to show to the current user.
+    #[cfg(target_os = "linux")]
+    pub show_notification: Option<(String, String)>,
 }
 
 pub fn get_config() -> &'static Config {
@@ -29,19 +36,33 @@ pub fn create_config() -> Config {
     };
     let log_to_file = !matches.is_present("disable_log_to_file");
     let log_stdout_timestamps = !matches.is_present("disable_stdout_timestamps");
+    let trace_file = matches.value_of("trace_file").map(PathBuf::from);
 
     #[cfg(target_os = "linux")]
     let initialize_firewall_and_exit =
         cfg!(target_os = "linux") && matches.is_present("initialize-early-boot-firewall");
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    let self_check = matches.is_present("self-check");
+    #[cfg(target_os = "linux")]
+    let show_notification = matches.values_of("show-notification").map(|mut values| {
+        let summary = values.next().unwrap_or_default().to_owned();
+        let body = values.next().unwrap_or_default().to_owned();
+        (summary, body)
+    });
     let run_as_service = cfg!(windows) && matches.is_present("run_as_service");
     let register_service = cfg!(windows) && matches.is_present("register_service");
 
     Config {
         #[cfg(target_os = "linux")]
         initialize_firewall_and_exit,
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        self_check,
+        #[cfg(target_os = "linux")]
+        show_notification,
         log_level,
         log_to_file,
         log_stdout_timestamps,
+        trace_file,
         run_as_service,
         register_service,
     }
@@ -89,6 +110,13 @@ fn create_app() -> App<'static> {
             Arg::new("disable_stdout_timestamps")
                 .long("disable-stdout-timestamps")
                 .help("Don't log timestamps when logging to stdout, useful when running as a systemd service")
+        )
+        .arg(
+            Arg::new("trace_file")
+                .long("trace-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Write a JSON trace of tunnel state machine activity to the given file"),
         );
 
     if cfg!(windows) {
@@ -110,6 +138,22 @@ fn create_app() -> App<'static> {
                 .long("initialize-early-boot-firewall")
                 .help("Initialize firewall to be used during early boot and exit"),
         )
+        // Used by the daemon to show notifications as the user in front of the computer
+        .arg(
+            Arg::new("show-notification")
+                .long(mullvad_daemon::system_notifications::NOTIFY_ARG)
+                .number_of_values(2)
+                .value_names(&["SUMMARY", "BODY"])
+                .hide(true),
+        )
+    }
+
+    if cfg!(any(target_os = "linux", target_os = "macos")) {
+        app = app.arg(
+            Arg::new("self-check")
+                .long("self-check")
+                .help("Check that the firewall, DNS, tunnel devices and routing work, print a report and exit"),
+        )
     }
     app
 }
@@ -0,0 +1,166 @@
+//! Handles panics in the daemon. A redacted crash report of the first panic is written to the log
+//! directory, where it is included in problem reports.
+//!
+//! Panics in tasks and threads that can fail on their own are otherwise left alone. If the daemon
+//! itself panics while handling an event, it shuts down through its regular shutdown path, which
+//! restores DNS and routes, and keeps the firewall blocking if the tunnel was supposed to be
+//! secured. The target state is left on disk in that case, so that the next instance reconnects.
+//!
+//! A marker file is kept in the cache directory while the daemon is running. If it already exists
+//! on startup, the previous instance did not exit cleanly, either because it panicked or because
+//! it was killed. The cache is then checked for corrupt files before it is used.
+
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use talpid_types::ErrorExt;
+use tokio::{fs, io};
+
+/// Name of the marker file that exists in the cache directory while the daemon is running.
+const RUNNING_MARKER_FILENAME: &str = "daemon-running";
+
+#[cfg(not(target_os = "android"))]
+const CRASH_REPORT_FILENAME: &str = "daemon-crash.log";
+
+/// Set once the crash report has been written.
+#[cfg(not(target_os = "android"))]
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Set when the daemon shuts down because it panicked.
+static CRASHED: AtomicBool = AtomicBool::new(false);
+
+/// Installs a panic hook that runs after any existing hook. If `log_dir` is `None`, no crash
+/// report is written.
+#[cfg(not(target_os = "android"))]
+pub fn enable(log_dir: Option<std::path::PathBuf>) {
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        previous_hook(info);
+
+        // Later panics are most likely caused by the first one
+        if REPORTED.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if let Some(log_dir) = &log_dir {
+            write_crash_report(info, log_dir);
+        }
+    }));
+}
+
+#[cfg(not(target_os = "android"))]
+fn write_crash_report(info: &std::panic::PanicInfo<'_>, log_dir: &Path) {
+    let message = if let Some(message) = info.payload().downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = info.payload().downcast_ref::<String>() {
+        message.as_str()
+    } else {
+        "<unknown panic payload>"
+    };
+    let location = info
+        .location()
+        .map(|location| location.to_string())
+        .unwrap_or_else(|| "<unknown location>".to_owned());
+    let thread = std::thread::current();
+
+    let report = format_crash_report(thread.name().unwrap_or("<unnamed>"), &location, message);
+
+    let path = log_dir.join(CRASH_REPORT_FILENAME);
+    if let Err(error) = std::fs::write(&path, report) {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to write crash report")
+        );
+    }
+}
+
+/// Returns the redacted crash report of a panic.
+#[cfg(not(target_os = "android"))]
+fn format_crash_report(thread: &str, location: &str, message: &str) -> String {
+    let report = format!(
+        "{}\nMullvad VPN daemon {}\nThread '{}' panicked at {}:\n{}\n\n\
+         See daemon.log for the events leading up to the panic.\n",
+        chrono::Local::now().format(crate::logging::DATE_TIME_FORMAT_STR),
+        mullvad_version::VERSION,
+        thread,
+        location,
+        message,
+    );
+    mullvad_problem_report::redact(&report)
+}
+
+/// Records that the daemon is shutting down because it panicked.
+pub(crate) fn set_crashed() {
+    CRASHED.store(true, Ordering::SeqCst);
+}
+
+/// Returns whether the daemon is shutting down because of a panic.
+pub(crate) fn has_crashed() -> bool {
+    CRASHED.load(Ordering::SeqCst)
+}
+
+/// Creates the marker file that shows that the daemon is running. Returns whether it already
+/// existed, which means that the previous instance did not exit cleanly.
+pub(crate) async fn check_previous_run(cache_dir: &Path) -> bool {
+    let path = cache_dir.join(RUNNING_MARKER_FILENAME);
+    let previous_run_crashed = fs::metadata(&path).await.is_ok();
+    if let Err(error) = fs::write(&path, b"").await {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to create daemon marker file")
+        );
+    }
+    previous_run_crashed
+}
+
+/// Removes the marker file created by [`check_previous_run`], unless the daemon is exiting due to
+/// a panic.
+pub(crate) async fn mark_clean_exit(cache_dir: &Path) {
+    if has_crashed() {
+        return;
+    }
+    if let Err(error) = fs::remove_file(cache_dir.join(RUNNING_MARKER_FILENAME)).await {
+        if error.kind() != io::ErrorKind::NotFound {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove daemon marker file")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unclean_exit_is_detected() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("crash-handler-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        runtime.block_on(async {
+            assert!(!check_previous_run(&dir).await);
+            mark_clean_exit(&dir).await;
+            assert!(!check_previous_run(&dir).await);
+            assert!(check_previous_run(&dir).await);
+        });
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    #[cfg(not(target_os = "android"))]
+    fn test_crash_report_is_redacted() {
+        let report = format_crash_report(
+            "daemon",
+            "src/lib.rs:1:1",
+            "invalid account 1234123412341234",
+        );
+        assert!(report.contains("Thread 'daemon' panicked at src/lib.rs:1:1"));
+        assert!(!report.contains("1234123412341234"));
+    }
+}
@@ -2,11 +2,13 @@ use chrono::{DateTime, Utc};
 use futures::{
     channel::{mpsc, oneshot},
     stream::StreamExt,
+    TryFutureExt,
 };
 
-use mullvad_api::rest;
+use mullvad_api::{cache_integrity, rest};
 use mullvad_types::{
-    account::{AccountToken, VoucherSubmission},
+    account::{AccountToken, LoginMode, VoucherSubmission},
+    cache::CorruptionReason,
     device::{
         AccountAndDevice, Device, DeviceEvent, DeviceEventCause, DeviceId, DeviceName, DevicePort,
         DeviceState,
@@ -23,14 +25,22 @@ use std::{
     time::{Duration, SystemTime},
 };
 use talpid_core::mpsc::Sender;
-use talpid_types::{net::TunnelType, tunnel::TunnelStateTransition, ErrorExt};
+use talpid_types::{
+    net::TunnelType,
+    tunnel::{ConnectingPhase, ErrorStateCause, TunnelStateTransition},
+    ErrorExt,
+};
 use tokio::{
     fs,
-    io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    io::{self, AsyncSeekExt, AsyncWriteExt},
 };
 
 mod api;
+mod poller;
+mod sealed;
 mod service;
+pub(crate) use poller::{spawn_account_poller, AccountPollerHandle, ForegroundClient};
+use sealed::SealedDevice;
 pub(crate) use service::{AccountService, DeviceService};
 
 /// File that used to store account and device data.
@@ -46,6 +56,10 @@ const LOGOUT_TIMEOUT: Duration = Duration::from_secs(2);
 /// to set up a WireGuard tunnel.
 const WG_DEVICE_CHECK_THRESHOLD: usize = 2;
 
+/// Maximum number of encrypted devices to keep in the device cache. Each one occupies a device
+/// slot of its account until that account is entered again.
+const MAX_SEALED_DEVICES: usize = 4;
+
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
     #[error(display = "The account already has a maximum number of devices")]
@@ -64,6 +78,8 @@ pub enum Error {
     DeviceIoError(#[error(source)] io::Error),
     #[error(display = "Failed parse device cache")]
     ParseDeviceCache(#[error(source)] serde_json::Error),
+    #[error(display = "Failed to encrypt device data")]
+    SealDevice,
     #[error(display = "Unexpected HTTP request error")]
     OtherRestError(#[error(source)] rest::Error),
     #[error(display = "The device update task is not running")]
@@ -143,6 +159,10 @@ impl From<PrivateDeviceState> for DeviceState {
 pub struct PrivateAccountAndDevice {
     pub account_token: AccountToken,
     pub device: PrivateDevice,
+    /// Whether the account token may be written to disk. Never itself stored, since a plaintext
+    /// cache implies [`LoginMode::Remember`].
+    #[serde(skip)]
+    pub login_mode: LoginMode,
 }
 
 impl From<PrivateAccountAndDevice> for AccountAndDevice {
@@ -244,6 +264,8 @@ pub(crate) enum PrivateDeviceEvent {
     Updated(PrivateAccountAndDevice),
     /// The key was rotated.
     RotatedKey(PrivateAccountAndDevice),
+    /// The key was replaced because it did not match the key registered for the device.
+    RepairedKey(PrivateAccountAndDevice),
 }
 
 impl From<PrivateDeviceEvent> for DeviceEvent {
@@ -254,6 +276,7 @@ impl From<PrivateDeviceEvent> for DeviceEvent {
             PrivateDeviceEvent::Revoked => DeviceEventCause::Revoked,
             PrivateDeviceEvent::Updated(_) => DeviceEventCause::Updated,
             PrivateDeviceEvent::RotatedKey(_) => DeviceEventCause::RotatedKey,
+            PrivateDeviceEvent::RepairedKey(_) => DeviceEventCause::RepairedKey,
         };
         let new_state = DeviceState::from(event.state());
         DeviceEvent { cause, new_state }
@@ -266,6 +289,7 @@ impl PrivateDeviceEvent {
             PrivateDeviceEvent::Login(config) => PrivateDeviceState::LoggedIn(config),
             PrivateDeviceEvent::Updated(config) => PrivateDeviceState::LoggedIn(config),
             PrivateDeviceEvent::RotatedKey(config) => PrivateDeviceState::LoggedIn(config),
+            PrivateDeviceEvent::RepairedKey(config) => PrivateDeviceState::LoggedIn(config),
             PrivateDeviceEvent::Logout => PrivateDeviceState::LoggedOut,
             PrivateDeviceEvent::Revoked => PrivateDeviceState::Revoked,
         }
@@ -301,7 +325,7 @@ impl Error {
 type ResponseTx<T> = oneshot::Sender<Result<T, Error>>;
 
 enum AccountManagerCommand {
-    Login(AccountToken, ResponseTx<()>),
+    Login(AccountToken, LoginMode, ResponseTx<()>),
     Logout(ResponseTx<()>),
     SetData(PrivateAccountAndDevice, ResponseTx<()>),
     GetData(ResponseTx<PrivateDeviceState>),
@@ -322,8 +346,8 @@ pub(crate) struct AccountManagerHandle {
 }
 
 impl AccountManagerHandle {
-    pub async fn login(&self, token: AccountToken) -> Result<(), Error> {
-        self.send_command(|tx| AccountManagerCommand::Login(token, tx))
+    pub async fn login(&self, token: AccountToken, mode: LoginMode) -> Result<(), Error> {
+        self.send_command(|tx| AccountManagerCommand::Login(token, mode, tx))
             .await
     }
 
@@ -400,6 +424,9 @@ pub(crate) struct AccountManager {
     expiry_requests: Vec<ResponseTx<DateTime<Utc>>>,
     rotation_requests: Vec<ResponseTx<()>>,
     data_requests: Vec<ResponseTx<PrivateDeviceState>>,
+    /// Set when validation found that the local key is not the one registered for the device,
+    /// so that the following key rotation is reported as a repair.
+    key_mismatch: bool,
 }
 
 impl AccountManager {
@@ -432,6 +459,7 @@ impl AccountManager {
             expiry_requests: vec![],
             rotation_requests: vec![],
             data_requests: vec![],
+            key_mismatch: false,
         };
 
         tokio::spawn(manager.run(cmd_rx));
@@ -459,9 +487,20 @@ impl AccountManager {
                             shutdown_tx = Some(tx);
                             break;
                         }
-                        Some(AccountManagerCommand::Login(token, tx)) => {
+                        Some(AccountManagerCommand::Login(token, mode, tx)) => {
+                            if let Some(mut device) = self.cacher.take_sealed(&token) {
+                                log::debug!("Restoring encrypted device for the account");
+                                current_api_call.clear();
+                                device.login_mode = mode;
+                                let _ = tx.send(self.set(PrivateDeviceEvent::Login(device)).await);
+                                continue;
+                            }
                             let job = self.device_service
-                                .generate_for_account(token);
+                                .generate_for_account(token)
+                                .map_ok(move |mut device| {
+                                    device.login_mode = mode;
+                                    device
+                                });
                             current_api_call.set_login(Box::pin(job), tx);
                         }
                         Some(AccountManagerCommand::Logout(tx)) => {
@@ -741,7 +780,11 @@ impl AccountManager {
                         }
                     }
                 } else {
-                    log::debug!("Rotating invalid WireGuard key for device");
+                    log::warn!(
+                        "The WireGuard key does not match the key registered for the device. \
+                         Replacing it"
+                    );
+                    self.key_mismatch = true;
                 }
             }
             Err(Error::InvalidAccount) => {
@@ -778,11 +821,18 @@ impl AccountManager {
             .cloned()
             .expect("Received a key rotation result whilst having no data");
 
+        let repaired = std::mem::take(&mut self.key_mismatch);
+
         match api_result {
             Ok(wg_data) => {
                 log::debug!("Replacing WireGuard key");
                 config.device.wg_data = wg_data;
-                match self.set(PrivateDeviceEvent::RotatedKey(config)).await {
+                let event = if repaired {
+                    PrivateDeviceEvent::RepairedKey(config)
+                } else {
+                    PrivateDeviceEvent::RotatedKey(config)
+                };
+                match self.set(event).await {
                     Ok(_) => {
                         Self::drain_requests(&mut self.rotation_requests, || Ok(()));
                         Self::drain_requests(&mut self.validation_requests, || Ok(()));
@@ -1017,49 +1067,83 @@ impl AccountManager {
         self.cacher.finalize().await;
     }
 }
+/// Contents of the device cache file.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum CachedDeviceState {
+    /// Device state along with devices logged in using [`LoginMode::InMemory`]. These can only be
+    /// restored using their account tokens.
+    WithSealed {
+        device: PrivateDeviceState,
+        sealed: Vec<SealedDevice>,
+    },
+    Plain(PrivateDeviceState),
+}
+
 pub struct DeviceCacher {
     file: io::BufWriter<fs::File>,
     path: std::path::PathBuf,
+    /// Encrypted devices read from the cache, which have not been unlocked yet. They are kept
+    /// when logging in to other accounts, so that their device slots are reused once their
+    /// accounts are entered again.
+    sealed: Vec<SealedDevice>,
 }
 
 impl DeviceCacher {
     pub async fn new(settings_dir: &Path) -> Result<(DeviceCacher, PrivateDeviceState), Error> {
         let path = settings_dir.join(DEVICE_CACHE_FILENAME);
-        let cache_exists = path.is_file();
         let mut should_save = false;
 
-        let mut file = fs::OpenOptions::from(Self::file_options())
-            .write(true)
-            .read(true)
-            .create(true)
-            .open(&path)
-            .await?;
-
-        let device: PrivateDeviceState = if cache_exists {
-            let mut reader = io::BufReader::new(&mut file);
-            let mut buffer = String::new();
-            reader.read_to_string(&mut buffer).await?;
-            if !buffer.is_empty() {
-                serde_json::from_str(&buffer).unwrap_or_else(|error| {
+        let mut sealed = vec![];
+        let device: PrivateDeviceState = match cache_integrity::read(&path).await {
+            Ok(contents) if !contents.is_empty() => match serde_json::from_slice(&contents) {
+                Ok(CachedDeviceState::Plain(device)) => device,
+                Ok(CachedDeviceState::WithSealed {
+                    device,
+                    sealed: sealed_devices,
+                }) => {
+                    log::info!(
+                        "{} stored devices are locked until their accounts are entered",
+                        sealed_devices.len()
+                    );
+                    sealed = sealed_devices;
+                    device
+                }
+                Err(error) => {
                     should_save = true;
                     log::error!(
                         "{}",
-                        error.display_chain_with_msg("Wiping device config due to an error")
+                        error.display_chain_with_msg("Discarding device config due to an error")
                     );
+                    cache_integrity::quarantine(&path, contents, CorruptionReason::ParseError)
+                        .await;
                     PrivateDeviceState::LoggedOut
-                })
-            } else {
+                }
+            },
+            Ok(_) => {
                 should_save = true;
                 PrivateDeviceState::LoggedOut
             }
-        } else {
-            should_save = true;
-            PrivateDeviceState::LoggedOut
+            Err(error)
+                if error.kind() == io::ErrorKind::NotFound
+                    || error.kind() == io::ErrorKind::InvalidData =>
+            {
+                should_save = true;
+                PrivateDeviceState::LoggedOut
+            }
+            Err(error) => return Err(Error::DeviceIoError(error)),
         };
 
+        let file = fs::OpenOptions::from(Self::file_options())
+            .write(true)
+            .create(true)
+            .open(&path)
+            .await?;
+
         let mut store = DeviceCacher {
             file: io::BufWriter::new(file),
             path,
+            sealed,
         };
 
         if should_save {
@@ -1085,21 +1169,51 @@ impl DeviceCacher {
         options
     }
 
+    /// Removes and returns the encrypted device that belongs to `account_token`, if there is one.
+    fn take_sealed(&mut self, account_token: &AccountToken) -> Option<PrivateAccountAndDevice> {
+        let (index, device) = self
+            .sealed
+            .iter()
+            .enumerate()
+            .find_map(|(index, sealed)| Some((index, sealed.open(account_token)?)))?;
+        self.sealed.remove(index);
+        Some(device)
+    }
+
     pub async fn write(&mut self, device: &PrivateDeviceState) -> Result<(), Error> {
-        let data = serde_json::to_vec_pretty(&device).unwrap();
+        let mut sealed = self.sealed.clone();
+        let device = match device {
+            PrivateDeviceState::LoggedIn(device) if device.login_mode == LoginMode::InMemory => {
+                sealed.push(SealedDevice::seal(device)?);
+                PrivateDeviceState::LoggedOut
+            }
+            device => device.clone(),
+        };
+        while sealed.len() > MAX_SEALED_DEVICES {
+            log::warn!("Discarding the oldest locked device. Its device slot remains in use");
+            sealed.remove(0);
+            self.sealed.remove(0);
+        }
+        let data = if sealed.is_empty() {
+            serde_json::to_vec_pretty(&device).unwrap()
+        } else {
+            serde_json::to_vec_pretty(&CachedDeviceState::WithSealed { device, sealed }).unwrap()
+        };
 
+        cache_integrity::remove_checksum(&self.path).await?;
         self.file.get_mut().set_len(0).await?;
         self.file.seek(io::SeekFrom::Start(0)).await?;
         self.file.write_all(&data).await?;
         self.file.flush().await?;
         self.file.get_mut().sync_data().await?;
+        cache_integrity::write_checksum(&self.path, &data).await?;
 
         Ok(())
     }
 
     pub async fn remove(self) -> Result<(), Error> {
         let path = {
-            let DeviceCacher { path, file } = self;
+            let DeviceCacher { path, file, .. } = self;
             let std_file = file.into_inner().into_std().await;
             let _ = tokio::task::spawn_blocking(move || drop(std_file)).await;
             path
@@ -1115,7 +1229,7 @@ impl DeviceCacher {
 }
 
 /// Checks if the current device is valid if a WireGuard tunnel cannot be set up
-/// after multiple attempts.
+/// after multiple attempts, or if authentication fails.
 pub(crate) struct TunnelStateChangeHandler {
     manager: AccountManagerHandle,
     check_validity: Arc<AtomicBool>,
@@ -1133,35 +1247,26 @@ impl TunnelStateChangeHandler {
 
     pub fn handle_state_transition(&mut self, new_state: &TunnelStateTransition) {
         match new_state {
-            TunnelStateTransition::Connecting(endpoint) => {
+            TunnelStateTransition::Connecting(endpoint, _, ConnectingPhase::OpeningDevice) => {
                 if endpoint.tunnel_type != TunnelType::Wireguard {
                     return;
                 }
                 self.wg_retry_attempt = self.wg_retry_attempt.wrapping_add(1);
                 if self.wg_retry_attempt % WG_DEVICE_CHECK_THRESHOLD == 0 {
-                    let handle = self.manager.clone();
-                    let check_validity = self.check_validity.clone();
-                    tokio::spawn(async move {
-                        if !check_validity.swap(false, Ordering::SeqCst) {
-                            return;
-                        }
-                        if let Err(error) = Self::check_validity(handle).await {
-                            log::error!(
-                                "{}",
-                                error.display_chain_with_msg(
-                                    "Failed to check device or account validity"
-                                )
-                            );
-                            if error.is_network_error() || error.is_aborted() {
-                                check_validity.store(true, Ordering::SeqCst);
-                            }
-                        }
-                    });
+                    self.spawn_validity_check();
                 }
             }
+            TunnelStateTransition::Error(error_state)
+                if matches!(error_state.cause(), ErrorStateCause::AuthFailed(_)) =>
+            {
+                // The key may no longer match the device. The check is not re-armed here, so
+                // that repeated failures only cause a single check.
+                self.wg_retry_attempt = 0;
+                self.spawn_validity_check();
+            }
             TunnelStateTransition::Error(_)
-            | TunnelStateTransition::Connected(_)
-            | TunnelStateTransition::Disconnected => {
+            | TunnelStateTransition::Connected(..)
+            | TunnelStateTransition::Disconnected(_) => {
                 self.check_validity.store(true, Ordering::SeqCst);
                 self.wg_retry_attempt = 0;
             }
@@ -1169,6 +1274,25 @@ impl TunnelStateChangeHandler {
         }
     }
 
+    fn spawn_validity_check(&self) {
+        let handle = self.manager.clone();
+        let check_validity = self.check_validity.clone();
+        tokio::spawn(async move {
+            if !check_validity.swap(false, Ordering::SeqCst) {
+                return;
+            }
+            if let Err(error) = Self::check_validity(handle).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to check device or account validity")
+                );
+                if error.is_network_error() || error.is_aborted() {
+                    check_validity.store(true, Ordering::SeqCst);
+                }
+            }
+        });
+    }
+
     pub async fn check_validity(handle: AccountManagerHandle) -> Result<(), Error> {
         handle.validate_device().await?;
         handle.check_expiry().await.map(|_expiry| ())
@@ -0,0 +1,175 @@
+//! Polls the account expiry and the device in the background, so that changes made elsewhere,
+//! such as time being added to the account or the device being removed, are noticed without the
+//! user asking for them. Requests are conditional, so polls are cheap if nothing has changed.
+
+use futures::future::{self, Either};
+use mullvad_api::availability::ApiAvailabilityHandle;
+use std::{sync::Arc, time::Duration};
+use talpid_types::ErrorExt;
+use tokio::{sync::watch, time::Instant};
+
+use super::{AccountManagerHandle, Error, TunnelStateChangeHandler};
+
+/// How often to poll while a frontend is in the foreground, unless the API says otherwise.
+const FOREGROUND_POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Polls are never more frequent than this while no frontend is in the foreground.
+const BACKGROUND_POLL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Polls are never more frequent than this, regardless of what the API says.
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Handle to the account poller. The poller stops when all handles are dropped.
+#[derive(Clone)]
+pub(crate) struct AccountPollerHandle {
+    /// Number of frontends that are in the foreground.
+    foreground_tx: Arc<watch::Sender<usize>>,
+}
+
+impl AccountPollerHandle {
+    /// Registers a frontend, which starts out in the background.
+    pub fn foreground_client(&self) -> ForegroundClient {
+        ForegroundClient {
+            foreground: false,
+            foreground_tx: self.foreground_tx.clone(),
+        }
+    }
+}
+
+/// Whether a single frontend is in the foreground. Polls are more frequent while any frontend is.
+/// The frontend is considered to be in the background once this is dropped.
+pub struct ForegroundClient {
+    foreground: bool,
+    foreground_tx: Arc<watch::Sender<usize>>,
+}
+
+impl ForegroundClient {
+    pub fn set_foreground(&mut self, foreground: bool) {
+        if foreground == self.foreground {
+            return;
+        }
+        self.foreground = foreground;
+        self.foreground_tx.send_modify(|count| {
+            if foreground {
+                *count += 1;
+            } else {
+                *count -= 1;
+            }
+        });
+    }
+}
+
+impl Drop for ForegroundClient {
+    fn drop(&mut self) {
+        self.set_foreground(false);
+    }
+}
+
+pub(crate) fn spawn_account_poller(
+    manager: AccountManagerHandle,
+    api_availability: ApiAvailabilityHandle,
+) -> AccountPollerHandle {
+    let (foreground_tx, foreground_rx) = watch::channel(0);
+    tokio::spawn(run(manager, api_availability, foreground_rx));
+    AccountPollerHandle {
+        foreground_tx: Arc::new(foreground_tx),
+    }
+}
+
+async fn run(
+    manager: AccountManagerHandle,
+    api_availability: ApiAvailabilityHandle,
+    mut foreground_rx: watch::Receiver<usize>,
+) {
+    let mut last_poll = Instant::now();
+    loop {
+        let interval = poll_interval(
+            *foreground_rx.borrow() > 0,
+            manager.account_service.poll_interval_hint(),
+        );
+        let next_poll = Box::pin(tokio::time::sleep_until(last_poll + interval));
+        let foreground_changed = Box::pin(foreground_rx.changed());
+        match future::select(next_poll, foreground_changed).await {
+            Either::Left(_) => (),
+            // Compute the interval again
+            Either::Right((Ok(()), _)) => continue,
+            Either::Right((Err(_), _)) => return,
+        }
+        last_poll = Instant::now();
+
+        match manager.data().await {
+            Ok(data) if data.logged_in() => (),
+            Ok(_) => continue,
+            Err(_) => return,
+        }
+        let poll = TunnelStateChangeHandler::check_validity(manager.clone());
+        match api_availability.when_online(poll).await {
+            Ok(()) => (),
+            Err(Error::AccountManagerDown) => return,
+            Err(error) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to poll account and device")
+                );
+            }
+        }
+    }
+}
+
+fn poll_interval(foreground: bool, hint: Option<Duration>) -> Duration {
+    let interval = hint
+        .unwrap_or(FOREGROUND_POLL_INTERVAL)
+        .max(MIN_POLL_INTERVAL);
+    if foreground {
+        interval
+    } else {
+        interval.max(BACKGROUND_POLL_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_poll_interval() {
+        assert_eq!(poll_interval(true, None), FOREGROUND_POLL_INTERVAL);
+        assert_eq!(poll_interval(false, None), BACKGROUND_POLL_INTERVAL);
+
+        let hint = Duration::from_secs(15 * 60);
+        assert_eq!(poll_interval(true, Some(hint)), hint);
+        assert_eq!(poll_interval(false, Some(hint)), BACKGROUND_POLL_INTERVAL);
+        assert_eq!(
+            poll_interval(true, Some(Duration::from_secs(1))),
+            MIN_POLL_INTERVAL
+        );
+        assert_eq!(
+            poll_interval(false, Some(Duration::from_secs(2 * 60 * 60))),
+            Duration::from_secs(2 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn test_foreground_clients() {
+        let (foreground_tx, foreground_rx) = watch::channel(0);
+        let handle = AccountPollerHandle {
+            foreground_tx: Arc::new(foreground_tx),
+        };
+
+        let mut gui = handle.foreground_client();
+        let mut other_gui = handle.foreground_client();
+        gui.set_foreground(true);
+        gui.set_foreground(true);
+        other_gui.set_foreground(true);
+        assert_eq!(*foreground_rx.borrow(), 2);
+
+        // One frontend hiding does not affect the other
+        other_gui.set_foreground(false);
+        other_gui.set_foreground(false);
+        assert_eq!(*foreground_rx.borrow(), 1);
+
+        // A frontend that disconnects while visible is no longer in the foreground
+        drop(gui);
+        assert_eq!(*foreground_rx.borrow(), 0);
+        drop(other_gui);
+        assert_eq!(*foreground_rx.borrow(), 0);
+    }
+}
@@ -0,0 +1,144 @@
+//! Encrypted storage of the device data for accounts logged in using [`LoginMode::InMemory`].
+//! The data is encrypted with a key derived from the account token, so the device can only be
+//! restored once the user has entered the same account token again.
+
+use super::{Error, PrivateAccountAndDevice};
+use mullvad_types::account::{AccountToken, LoginMode};
+use rand::RngCore;
+use ring::{aead, pbkdf2};
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroU32;
+
+const SALT_LEN: usize = 16;
+const KEY_DERIVATION_ITERATIONS: u32 = 100_000;
+
+/// Device data that is encrypted using the account token.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SealedDevice {
+    salt: Vec<u8>,
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+impl SealedDevice {
+    /// Encrypts `device` using a key derived from its account token.
+    pub fn seal(device: &PrivateAccountAndDevice) -> Result<Self, Error> {
+        let mut salt = vec![0u8; SALT_LEN];
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        let mut rng = rand::rngs::OsRng;
+        rng.fill_bytes(&mut salt);
+        rng.fill_bytes(&mut nonce);
+
+        let mut ciphertext = serde_json::to_vec(device).map_err(Error::ParseDeviceCache)?;
+        derive_key(&device.account_token, &salt)
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::empty(),
+                &mut ciphertext,
+            )
+            .map_err(|_| Error::SealDevice)?;
+
+        Ok(SealedDevice {
+            salt,
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    /// Decrypts the device data. Returns `None` if the data was not sealed using `account_token`.
+    pub fn open(&self, account_token: &AccountToken) -> Option<PrivateAccountAndDevice> {
+        let nonce = aead::Nonce::try_assume_unique_for_key(&self.nonce).ok()?;
+        let mut buffer = self.ciphertext.clone();
+        let plaintext = derive_key(account_token, &self.salt)
+            .open_in_place(nonce, aead::Aad::empty(), &mut buffer)
+            .ok()?;
+
+        let mut device: PrivateAccountAndDevice = serde_json::from_slice(plaintext)
+            .map_err(|error| {
+                log::error!("Failed to deserialize sealed device: {}", error);
+            })
+            .ok()?;
+        if &device.account_token != account_token {
+            return None;
+        }
+        device.login_mode = LoginMode::InMemory;
+        Some(device)
+    }
+}
+
+fn derive_key(account_token: &AccountToken, salt: &[u8]) -> aead::LessSafeKey {
+    let mut key = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(KEY_DERIVATION_ITERATIONS).unwrap(),
+        salt,
+        account_token.as_bytes(),
+        &mut key,
+    );
+    let key = aead::UnboundKey::new(&aead::CHACHA20_POLY1305, &key)
+        .expect("key length must match the algorithm");
+    aead::LessSafeKey::new(key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::PrivateDevice;
+    use chrono::Utc;
+    use mullvad_types::wireguard::{AssociatedAddresses, WireguardData};
+    use talpid_types::net::wireguard::PrivateKey;
+
+    fn device(account_token: &str) -> PrivateAccountAndDevice {
+        PrivateAccountAndDevice {
+            account_token: account_token.to_owned(),
+            device: PrivateDevice {
+                id: "device-id".to_owned(),
+                name: "happy seal".to_owned(),
+                wg_data: WireguardData {
+                    private_key: PrivateKey::new_from_random(),
+                    addresses: AssociatedAddresses {
+                        ipv4_address: "10.64.0.2/32".parse().unwrap(),
+                        ipv6_address: "fc00:bbbb:bbbb:bb01::2/128".parse().unwrap(),
+                    },
+                    created: Utc::now(),
+                },
+                ports: vec![],
+                hijack_dns: false,
+                created: Utc::now(),
+            },
+            login_mode: LoginMode::InMemory,
+        }
+    }
+
+    #[test]
+    fn test_seal_round_trip() {
+        let device = device("1234123412341234");
+        let sealed = SealedDevice::seal(&device).unwrap();
+
+        // The device must survive being written to and read from the device cache
+        let sealed: SealedDevice =
+            serde_json::from_str(&serde_json::to_string(&sealed).unwrap()).unwrap();
+        assert_eq!(sealed.open(&device.account_token), Some(device));
+    }
+
+    #[test]
+    fn test_open_with_other_account() {
+        let sealed = SealedDevice::seal(&device("1234123412341234")).unwrap();
+        assert_eq!(sealed.open(&"4321432143214321".to_owned()), None);
+    }
+
+    #[test]
+    fn test_open_tampered() {
+        let device = device("1234123412341234");
+        let mut sealed = SealedDevice::seal(&device).unwrap();
+        sealed.ciphertext[0] ^= 1;
+        assert_eq!(sealed.open(&device.account_token), None);
+    }
+
+    #[test]
+    fn test_account_token_is_not_stored() {
+        let device = device("1234123412341234");
+        let stored = serde_json::to_string(&SealedDevice::seal(&device).unwrap()).unwrap();
+        assert!(!stored.contains(&device.account_token));
+    }
+}
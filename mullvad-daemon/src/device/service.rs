@@ -1,9 +1,13 @@
-use std::{future::Future, time::Duration};
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use chrono::{DateTime, Utc};
 use futures::future::{abortable, AbortHandle};
 use mullvad_types::{
-    account::{AccountToken, VoucherSubmission},
+    account::{AccountToken, LoginMode, VoucherSubmission},
     device::{Device, DeviceId},
     wireguard::WireguardData,
 };
@@ -13,7 +17,7 @@ use super::{Error, PrivateAccountAndDevice, PrivateDevice};
 use mullvad_api::{
     availability::ApiAvailabilityHandle,
     rest::{self, Error as RestError, MullvadRestHandle},
-    AccountsProxy, DevicesProxy,
+    AccountsProxy, ApiError, DevicesProxy,
 };
 use talpid_core::future_retry::{
     constant_interval, retry_future, retry_future_n, ExponentialBackoff, Jittered,
@@ -25,10 +29,59 @@ const RETRY_BACKOFF_INTERVAL_INITIAL: Duration = Duration::from_secs(4);
 const RETRY_BACKOFF_INTERVAL_FACTOR: u32 = 5;
 const RETRY_BACKOFF_INTERVAL_MAX: Duration = Duration::from_secs(24 * 60 * 60);
 
+/// The last version of a resource that was fetched from the API, and the validators needed to
+/// only fetch it again if it has changed.
+struct ConditionalCache<K, T> {
+    entry: Arc<Mutex<Option<(K, T, rest::CacheValidators)>>>,
+}
+
+impl<K, T> Clone for ConditionalCache<K, T> {
+    fn clone(&self) -> Self {
+        Self {
+            entry: self.entry.clone(),
+        }
+    }
+}
+
+impl<K: PartialEq, T: Clone> ConditionalCache<K, T> {
+    fn new() -> Self {
+        Self {
+            entry: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Returns the cached value for `key` and its validators.
+    fn get(&self, key: &K) -> Option<(T, rest::CacheValidators)> {
+        match &*self.entry.lock().unwrap() {
+            Some((cached_key, value, validators)) if cached_key == key => {
+                Some((value.clone(), validators.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Stores the value in `response`, or returns `cached` if the resource was not modified.
+    /// `cached` must be the value that the validators of the request were obtained with.
+    fn update(
+        &self,
+        key: K,
+        cached: Option<(T, rest::CacheValidators)>,
+        response: rest::ConditionalResponse<T>,
+    ) -> Result<T, RestError> {
+        let value = match (response.value, cached) {
+            (Some(value), _) | (None, Some((value, _))) => value,
+            (None, None) => return Err(RestError::NotModified),
+        };
+        *self.entry.lock().unwrap() = Some((key, value.clone(), response.validators));
+        Ok(value)
+    }
+}
+
 #[derive(Clone)]
 pub struct DeviceService {
     api_availability: ApiAvailabilityHandle,
     proxy: DevicesProxy,
+    cache: ConditionalCache<DeviceId, Device>,
 }
 
 impl DeviceService {
@@ -36,6 +89,7 @@ impl DeviceService {
         Self {
             proxy: DevicesProxy::new(handle),
             api_availability,
+            cache: ConditionalCache::new(),
         }
     }
 
@@ -70,6 +124,7 @@ impl DeviceService {
                         created: Utc::now(),
                     },
                 )?,
+                login_mode: LoginMode::default(),
             })
         }
     }
@@ -102,6 +157,7 @@ impl DeviceService {
                     created: Utc::now(),
                 },
             )?,
+            login_mode: LoginMode::default(),
         })
     }
 
@@ -247,17 +303,27 @@ impl DeviceService {
         .map_err(map_rest_error)
     }
 
+    /// Fetches the device, unless it has not changed since it was last fetched.
     pub async fn get(&self, token: AccountToken, device: DeviceId) -> Result<Device, Error> {
         let proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
-        retry_future_n(
-            move || proxy.get(token.clone(), device.clone()),
+        let cached = self.cache.get(&device);
+        let validators = cached
+            .as_ref()
+            .map(|(_, validators)| validators.clone())
+            .unwrap_or_default();
+        let device_id = device.clone();
+        let response = retry_future_n(
+            move || proxy.get_if_modified(token.clone(), device_id.clone(), validators.clone()),
             move |result| should_retry(result, &api_handle),
             constant_interval(RETRY_ACTION_INTERVAL),
             RETRY_ACTION_MAX_RETRIES,
         )
         .await
-        .map_err(map_rest_error)
+        .map_err(map_rest_error)?;
+        self.cache
+            .update(device, cached, response)
+            .map_err(map_rest_error)
     }
 }
 
@@ -266,6 +332,9 @@ pub struct AccountService {
     api_availability: ApiAvailabilityHandle,
     initial_check_abort_handle: AbortHandle,
     proxy: AccountsProxy,
+    expiry_cache: ConditionalCache<AccountToken, DateTime<Utc>>,
+    /// How often the API last asked for the account expiry to be polled.
+    poll_interval: Arc<Mutex<Option<Duration>>>,
 }
 
 impl AccountService {
@@ -294,22 +363,39 @@ impl AccountService {
         )
     }
 
+    /// Fetches the expiry of the account. It is only downloaded again if it has changed since it
+    /// was last fetched.
     pub async fn check_expiry(&self, token: AccountToken) -> Result<DateTime<Utc>, rest::Error> {
         let proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
+        let cached = self.expiry_cache.get(&token);
+        let validators = cached
+            .as_ref()
+            .map(|(_, validators)| validators.clone())
+            .unwrap_or_default();
+        let account = token.clone();
         let result = retry_future_n(
-            move || proxy.get_expiry(token.clone()),
+            move || proxy.get_expiry_if_modified(account.clone(), validators.clone()),
             move |result| should_retry(result, &api_handle),
             constant_interval(RETRY_ACTION_INTERVAL),
             RETRY_ACTION_MAX_RETRIES,
         )
-        .await;
+        .await
+        .and_then(|response| {
+            *self.poll_interval.lock().unwrap() = response.poll_interval;
+            self.expiry_cache.update(token, cached, response)
+        });
         if handle_expiry_result_inner(&result, &self.api_availability) {
             self.initial_check_abort_handle.abort();
         }
         result
     }
 
+    /// Returns how often the API has asked for the account expiry to be polled, if it has.
+    pub fn poll_interval_hint(&self) -> Option<Duration> {
+        *self.poll_interval.lock().unwrap()
+    }
+
     pub async fn check_expiry_2(&self, token: AccountToken) -> Result<DateTime<Utc>, Error> {
         self.check_expiry(token).await.map_err(map_rest_error)
     }
@@ -369,6 +455,8 @@ pub fn spawn_account_service(
         api_availability: api_availability_copy,
         initial_check_abort_handle,
         proxy: accounts_proxy_copy,
+        expiry_cache: ConditionalCache::new(),
+        poll_interval: Arc::new(Mutex::new(None)),
     }
 }
 
@@ -385,12 +473,9 @@ fn handle_expiry_result_inner(
             api_availability.pause_background();
             true
         }
-        Err(mullvad_api::rest::Error::ApiError(_status, code)) => {
-            if code == mullvad_api::INVALID_ACCOUNT {
-                api_availability.pause_background();
-                return true;
-            }
-            false
+        Err(mullvad_api::rest::Error::ApiError(_status, ApiError::InvalidAccount, _)) => {
+            api_availability.pause_background();
+            true
         }
         Err(_) => false,
     }
@@ -407,12 +492,15 @@ fn should_retry_backoff<T>(result: &Result<T, RestError>) -> bool {
     match result {
         Ok(_) => false,
         Err(error) => {
-            if let RestError::ApiError(status, code) = error {
+            if let RestError::ApiError(status, code, _) = error {
                 *status != rest::StatusCode::NOT_FOUND
-                    && code != mullvad_api::DEVICE_NOT_FOUND
-                    && code != mullvad_api::INVALID_ACCOUNT
-                    && code != mullvad_api::MAX_DEVICES_REACHED
-                    && code != mullvad_api::PUBKEY_IN_USE
+                    && !matches!(
+                        code,
+                        ApiError::DeviceNotFound
+                            | ApiError::InvalidAccount
+                            | ApiError::MaxDevicesReached
+                            | ApiError::PubkeyInUse
+                    )
             } else {
                 true
             }
@@ -422,12 +510,12 @@ fn should_retry_backoff<T>(result: &Result<T, RestError>) -> bool {
 
 fn map_rest_error(error: rest::Error) -> Error {
     match error {
-        RestError::ApiError(_status, ref code) => match code.as_str() {
-            mullvad_api::DEVICE_NOT_FOUND => Error::InvalidDevice,
-            mullvad_api::INVALID_ACCOUNT => Error::InvalidAccount,
-            mullvad_api::MAX_DEVICES_REACHED => Error::MaxDevicesReached,
-            mullvad_api::INVALID_VOUCHER => Error::InvalidVoucher,
-            mullvad_api::VOUCHER_USED => Error::UsedVoucher,
+        RestError::ApiError(_status, ref code, _) => match code {
+            ApiError::DeviceNotFound => Error::InvalidDevice,
+            ApiError::InvalidAccount => Error::InvalidAccount,
+            ApiError::MaxDevicesReached => Error::MaxDevicesReached,
+            ApiError::InvalidVoucher => Error::InvalidVoucher,
+            ApiError::VoucherUsed => Error::UsedVoucher,
             _ => Error::OtherRestError(error),
         },
         error => Error::OtherRestError(error),
@@ -443,3 +531,41 @@ fn retry_strategy() -> Jittered<ExponentialBackoff> {
         .max_delay(RETRY_BACKOFF_INTERVAL_MAX),
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(value: Option<u32>, etag: &str) -> rest::ConditionalResponse<u32> {
+        rest::ConditionalResponse {
+            value,
+            validators: rest::CacheValidators {
+                etag: Some(etag.to_owned()),
+                last_modified: None,
+            },
+            poll_interval: None,
+        }
+    }
+
+    #[test]
+    fn test_conditional_cache() {
+        let cache = ConditionalCache::new();
+        assert!(cache.get(&"a").is_none());
+
+        assert_eq!(cache.update("a", None, response(Some(1), "1")).unwrap(), 1);
+        let cached = cache.get(&"a");
+        assert_eq!(cached.as_ref().unwrap().1.etag.as_deref(), Some("1"));
+        assert!(cache.get(&"b").is_none());
+
+        // Not modified
+        assert_eq!(cache.update("a", cached, response(None, "2")).unwrap(), 1);
+        assert_eq!(cache.get(&"a").unwrap().1.etag.as_deref(), Some("2"));
+
+        // Not modified, but nothing to fall back on
+        assert!(matches!(
+            cache.update("b", None, response(None, "3")),
+            Err(RestError::NotModified)
+        ));
+        assert!(cache.get(&"a").is_some());
+    }
+}
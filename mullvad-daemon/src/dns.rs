@@ -45,10 +45,17 @@ pub fn addresses_from_options(options: &DnsOptions) -> Option<Vec<IpAddr>> {
             }
         }
         DnsState::Custom => {
-            if options.custom_options.addresses.is_empty() {
+            // The first occurrence of a resolver decides its position
+            let mut addresses = Vec::with_capacity(options.custom_options.addresses.len());
+            for address in &options.custom_options.addresses {
+                if !addresses.contains(address) {
+                    addresses.push(*address);
+                }
+            }
+            if addresses.is_empty() {
                 None
             } else {
-                Some(options.custom_options.addresses.clone())
+                Some(addresses)
             }
         }
     }
@@ -1,5 +1,6 @@
 use mullvad_daemon::settings::{self, SettingsPersister};
 use talpid_core::firewall::{self, Firewall, FirewallPolicy};
+use talpid_types::cgroup::SplitTunnelMode;
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -15,24 +16,38 @@ pub enum Error {
 
 pub async fn initialize_firewall() -> Result<(), Error> {
     let mut firewall = Firewall::new(mullvad_types::TUNNEL_FWMARK)?;
-    let allow_lan = get_allow_lan().await.unwrap_or_else(|err| {
-        log::info!(
-            "Not allowing LAN traffic due to failing to read settings: {}",
-            err
-        );
-        false
-    });
+    let (allow_lan, allowed_link_config_traffic, allowed_lan_hosts) = get_settings()
+        .await
+        .map(|settings| {
+            (
+                settings.allow_lan,
+                settings.allowed_link_config_traffic,
+                settings.allowed_lan_hosts,
+            )
+        })
+        .unwrap_or_else(|err| {
+            log::info!(
+                "Not allowing LAN traffic due to failing to read settings: {}",
+                err
+            );
+            (false, Default::default(), vec![])
+        });
     let policy = FirewallPolicy::Blocked {
         allow_lan,
         allowed_endpoint: None,
+        allowed_link_config_traffic,
+        allowed_lan_hosts,
+        // Block everything until the daemon is running, even if split tunneling is in include mode
+        split_tunnel_mode: SplitTunnelMode::Exclude,
+        shared_interface: None,
     };
     log::info!("Applying firewall policy {policy}");
     firewall.apply_policy(policy)?;
     Ok(())
 }
 
-async fn get_allow_lan() -> Result<bool, Error> {
+async fn get_settings() -> Result<mullvad_types::settings::Settings, Error> {
     let path = mullvad_paths::settings_dir()?;
     let settings = SettingsPersister::load(&path).await;
-    Ok(settings.allow_lan)
+    Ok(settings.to_settings())
 }
@@ -0,0 +1,240 @@
+//! Key/value store for preferences that belong to a frontend rather than to the daemon, such as
+//! which notifications have been dismissed. Keeping them in the daemon lets them survive a
+//! reinstall of the frontend. Each frontend uses its own namespace, and the size of the store is
+//! limited, since any local client may write to it.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use talpid_types::ErrorExt;
+use tokio::{
+    fs,
+    io::{self, AsyncWriteExt},
+};
+
+const FRONTEND_STORE_FILE: &str = "frontend-store.json";
+
+/// Maximum length of a namespace or key, in bytes.
+pub const MAX_NAME_LEN: usize = 64;
+/// Maximum length of a single value, in bytes.
+pub const MAX_VALUE_LEN: usize = 4 * 1024;
+/// Maximum total length of the keys and values in a namespace, in bytes.
+pub const MAX_NAMESPACE_SIZE: usize = 32 * 1024;
+/// Maximum number of namespaces.
+pub const MAX_NAMESPACES: usize = 8;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(
+        display = "Namespaces and keys must be between 1 and {} bytes",
+        MAX_NAME_LEN
+    )]
+    InvalidName,
+
+    #[error(display = "Values may not be longer than {} bytes", MAX_VALUE_LEN)]
+    ValueTooLong,
+
+    #[error(display = "The namespace would exceed {} bytes", MAX_NAMESPACE_SIZE)]
+    NamespaceFull,
+
+    #[error(display = "There may not be more than {} namespaces", MAX_NAMESPACES)]
+    TooManyNamespaces,
+
+    #[error(display = "Failed to serialize frontend store")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unable to write frontend store file")]
+    Write(#[error(source)] io::Error),
+}
+
+type Namespace = BTreeMap<String, String>;
+
+pub struct FrontendStore {
+    path: PathBuf,
+    namespaces: BTreeMap<String, Namespace>,
+}
+
+impl FrontendStore {
+    /// Loads the store from `settings_dir`. The store starts out empty if it cannot be read.
+    pub async fn new(settings_dir: &Path) -> FrontendStore {
+        let path = settings_dir.join(FRONTEND_STORE_FILE);
+        let namespaces = match fs::read_to_string(&path).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to parse frontend store")
+                );
+                BTreeMap::new()
+            }),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to read frontend store")
+                );
+                BTreeMap::new()
+            }
+        };
+        FrontendStore { path, namespaces }
+    }
+
+    /// Returns all values in `namespace`.
+    pub fn get(&self, namespace: &str) -> Namespace {
+        self.namespaces.get(namespace).cloned().unwrap_or_default()
+    }
+
+    /// Sets the value of `key` in `namespace`, or removes it if `value` is `None`.
+    pub async fn set(
+        &mut self,
+        namespace: String,
+        key: String,
+        value: Option<String>,
+    ) -> Result<()> {
+        if self.update(namespace, key, value)? {
+            self.save().await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Updates the store in memory. Returns whether anything changed.
+    fn update(&mut self, namespace: String, key: String, value: Option<String>) -> Result<bool> {
+        if !is_valid_name(&namespace) || !is_valid_name(&key) {
+            return Err(Error::InvalidName);
+        }
+
+        let value = match value {
+            Some(value) => value,
+            None => return Ok(self.remove(&namespace, &key)),
+        };
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::ValueTooLong);
+        }
+
+        match self.namespaces.get(&namespace) {
+            Some(entries) => {
+                if entries.get(&key) == Some(&value) {
+                    return Ok(false);
+                }
+                let size: usize = entries
+                    .iter()
+                    .filter(|(entry_key, _)| **entry_key != key)
+                    .map(|(entry_key, entry_value)| entry_key.len() + entry_value.len())
+                    .sum();
+                if size + key.len() + value.len() > MAX_NAMESPACE_SIZE {
+                    return Err(Error::NamespaceFull);
+                }
+            }
+            None if self.namespaces.len() >= MAX_NAMESPACES => {
+                return Err(Error::TooManyNamespaces);
+            }
+            None => (),
+        }
+
+        self.namespaces
+            .entry(namespace)
+            .or_default()
+            .insert(key, value);
+        Ok(true)
+    }
+
+    fn remove(&mut self, namespace: &str, key: &str) -> bool {
+        let entries = match self.namespaces.get_mut(namespace) {
+            Some(entries) => entries,
+            None => return false,
+        };
+        let removed = entries.remove(key).is_some();
+        if entries.is_empty() {
+            self.namespaces.remove(namespace);
+        }
+        removed
+    }
+
+    /// Removes all namespaces.
+    pub async fn clear(&mut self) -> Result<()> {
+        self.namespaces.clear();
+        self.save().await
+    }
+
+    async fn save(&self) -> Result<()> {
+        let buffer = serde_json::to_vec(&self.namespaces).map_err(Error::Serialize)?;
+        let mut options = fs::OpenOptions::new();
+        #[cfg(unix)]
+        {
+            options.mode(0o600);
+        }
+        let mut file = options
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)
+            .await
+            .map_err(Error::Write)?;
+        file.write_all(&buffer).await.map_err(Error::Write)?;
+        file.sync_all().await.map_err(Error::Write)
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LEN
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn set(store: &mut FrontendStore, namespace: &str, key: &str, value: &str) -> Result<bool> {
+        store.update(namespace.to_owned(), key.to_owned(), Some(value.to_owned()))
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut store = FrontendStore {
+            path: PathBuf::new(),
+            namespaces: BTreeMap::new(),
+        };
+        let max_value = "x".repeat(MAX_VALUE_LEN);
+
+        assert!(set(&mut store, "gui", "key", "value").unwrap());
+        assert!(!set(&mut store, "gui", "key", "value").unwrap());
+        assert!(matches!(
+            set(&mut store, "gui", "", "value"),
+            Err(Error::InvalidName)
+        ));
+        assert!(matches!(
+            set(&mut store, "gui", "key", &format!("{max_value}x")),
+            Err(Error::ValueTooLong)
+        ));
+
+        for i in 0..MAX_NAMESPACE_SIZE / MAX_VALUE_LEN - 1 {
+            set(&mut store, "gui", &i.to_string(), &max_value).unwrap();
+        }
+        assert!(matches!(
+            set(&mut store, "gui", "last", &max_value),
+            Err(Error::NamespaceFull)
+        ));
+        // The old value of a key does not count towards the limit
+        assert!(set(&mut store, "gui", "0", &"y".repeat(MAX_VALUE_LEN)).unwrap());
+        // Other namespaces have their own limit
+        assert!(set(&mut store, "cli", "last", &max_value).unwrap());
+
+        for i in 2..MAX_NAMESPACES {
+            set(&mut store, &i.to_string(), "key", "value").unwrap();
+        }
+        assert!(matches!(
+            set(&mut store, "other", "key", "value"),
+            Err(Error::TooManyNamespaces)
+        ));
+
+        // Removing the last key removes the namespace
+        assert!(store
+            .update("cli".to_owned(), "last".to_owned(), None)
+            .unwrap());
+        assert!(store.get("cli").is_empty());
+        set(&mut store, "other", "key", "value").unwrap();
+    }
+}
@@ -52,6 +52,35 @@ pub async fn send_location_request(
     }
 }
 
+/// Whether traffic of each IP version reaches the internet without passing through a relay.
+pub struct LeakTestResult {
+    pub ipv4_leaking: bool,
+    pub ipv6_leaking: bool,
+}
+
+/// Checks whether traffic exits through a Mullvad relay. Both IP versions are checked, whether
+/// IPv6 is enabled or not. An IP version that can't reach the internet at all is not leaking.
+pub async fn test_for_leaks(request_sender: RequestServiceHandle) -> LeakTestResult {
+    let (v4_result, v6_result) = join!(
+        send_location_request_internal(URI_V4, request_sender.clone()),
+        send_location_request_internal(URI_V6, request_sender),
+    );
+    LeakTestResult {
+        ipv4_leaking: is_leaking(v4_result, "IPv4"),
+        ipv6_leaking: is_leaking(v6_result, "IPv6"),
+    }
+}
+
+fn is_leaking(result: Result<AmIMullvad, Error>, version: &'static str) -> bool {
+    match result {
+        Ok(location) => !location.mullvad_exit_ip,
+        Err(error) => {
+            log_network_error(error, version);
+            false
+        }
+    }
+}
+
 async fn send_location_request_internal(
     uri: &'static str,
     service: RequestServiceHandle,
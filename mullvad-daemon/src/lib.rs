@@ -8,31 +8,39 @@ pub mod account_history;
 mod api;
 #[cfg(not(target_os = "android"))]
 mod cleanup;
+pub mod crash_handler;
 pub mod device;
 mod dns;
 pub mod exception_logging;
+pub mod frontend_store;
 mod geoip;
+mod location_search;
 pub mod logging;
 #[cfg(target_os = "macos")]
 mod macos;
 #[cfg(not(target_os = "android"))]
 pub mod management_interface;
 mod migrations;
+mod network_detection;
 #[cfg(not(target_os = "android"))]
 pub mod rpc_uniqueness_check;
 pub mod runtime;
 pub mod settings;
 pub mod shutdown;
+mod speed_test;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod state_hook;
+pub mod system_notifications;
 mod target_state;
 mod tunnel;
 pub mod version;
 mod version_check;
 
-use crate::target_state::PersistentTargetState;
+use crate::{shutdown::ShutdownStage, target_state::PersistentTargetState};
 use device::{AccountEvent, PrivateAccountAndDevice, PrivateDeviceEvent};
 use futures::{
     channel::{mpsc, oneshot},
-    future::{abortable, AbortHandle, Future, LocalBoxFuture},
+    future::{abortable, AbortHandle, Either, Future, LocalBoxFuture},
     StreamExt,
 };
 use mullvad_relay_selector::{
@@ -40,13 +48,20 @@ use mullvad_relay_selector::{
     RelaySelector, SelectorConfig,
 };
 use mullvad_types::{
-    account::{AccountData, AccountToken, VoucherSubmission},
+    access_method::{self, AccessMethod},
+    account::{AccountData, AccountToken, LoginMode, VoucherSubmission},
     auth_failed::AuthFailed,
+    auto_connect::{self, AutoConnectDecision, AutoConnectPolicy, NetworkInfo},
+    cache::QuarantinedFile,
     device::{Device, DeviceEvent, DeviceEventCause, DeviceId, DeviceState, RemoveDeviceEvent},
     location::GeoIpLocation,
-    relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
-    relay_list::RelayList,
-    settings::{DnsOptions, Settings},
+    network::{CurrentNetwork, NetworkProfile},
+    relay_constraints::{
+        BridgeSettings, BridgeState, ObfuscationSettings, PortSelectionStep, RelaySettings,
+        RelaySettingsUpdate, SelectedObfuscation,
+    },
+    relay_list::{LocationSearchResult, RelayList, RelaySelectionHint},
+    settings::{DnsOptions, ManagementAccessPolicy, Settings, SettingsUpdate, TunnelOptions},
     states::{TargetState, TunnelState},
     version::{AppVersion, AppVersionInfo},
     wireguard::{PublicKey, RotationInterval},
@@ -54,16 +69,19 @@ use mullvad_types::{
 use settings::SettingsPersister;
 #[cfg(target_os = "android")]
 use std::os::unix::io::RawFd;
-#[cfg(target_os = "windows")]
-use std::{collections::HashSet, ffi::OsString};
 use std::{
+    collections::BTreeMap,
     marker::PhantomData,
     mem,
+    net::IpAddr,
+    panic::AssertUnwindSafe,
     path::PathBuf,
     pin::Pin,
     sync::{Arc, Weak},
     time::Duration,
 };
+#[cfg(target_os = "windows")]
+use std::{collections::HashSet, ffi::OsString};
 #[cfg(any(target_os = "linux", windows))]
 use talpid_core::split_tunnel;
 use talpid_core::{
@@ -72,18 +90,38 @@ use talpid_core::{
 };
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
+#[cfg(target_os = "linux")]
+use talpid_types::cgroup::SplitTunnelMode;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use talpid_types::net::AllowedLinkConfigTraffic;
 use talpid_types::{
-    net::{TunnelEndpoint, TunnelType},
-    tunnel::{ErrorStateCause, TunnelStateTransition},
+    conflict::ConflictingSoftware,
+    net::{
+        wireguard::PrivateKey, DnsBackendHealth, Endpoint, IpVersion, TransportProtocol,
+        TunnelEndpoint, TunnelType,
+    },
+    tunnel::{
+        ConnectingPhase, DisconnectReason, ErrorStateCause, ReconnectReason, TunnelStateTransition,
+    },
     ErrorExt,
 };
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 use tokio::fs;
 use tokio::io;
 
-/// Delay between generating a new WireGuard key and reconnecting
+/// Delay between generating a new WireGuard key and giving it to the tunnel
 const WG_RECONNECT_DELAY: Duration = Duration::from_secs(4 * 60);
 
+/// Name of the file in the log directory that holds the result of the last DNS backend check.
+/// It is picked up by problem reports along with the other logs.
+const DNS_CHECK_FILENAME: &str = "dns-backend.log";
+
+/// How often the account expiry is fetched while system notifications are enabled.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How long a connected tunnel may stay unhealthy before it is reconnected.
+const UNHEALTHY_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
 pub type ResponseTx<T, E> = oneshot::Sender<Result<T, E>>;
 
 #[derive(err_derive::Error, Debug)]
@@ -154,6 +192,9 @@ pub enum Error {
     #[error(display = "Account history error")]
     AccountHistory(#[error(source)] account_history::Error),
 
+    #[error(display = "Frontend store error")]
+    FrontendStore(#[error(source)] frontend_store::Error),
+
     #[cfg(not(target_os = "android"))]
     #[error(display = "Factory reset partially failed: {}", _0)]
     FactoryResetError(&'static str),
@@ -161,6 +202,9 @@ pub enum Error {
     #[error(display = "Tunnel state machine error")]
     TunnelError(#[error(source)] tunnel_state_machine::Error),
 
+    #[error(display = "The tunnel is not connected")]
+    TunnelNotConnected,
+
     #[cfg(target_os = "macos")]
     #[error(display = "Failed to set exclusion group")]
     GroupIdError(#[error(source)] io::Error),
@@ -171,11 +215,17 @@ pub enum DaemonCommand {
     /// Set target state. Does nothing if the daemon already has the state that is being set.
     SetTargetState(oneshot::Sender<bool>, TargetState),
     /// Reconnect the tunnel, if one is connecting/connected.
-    Reconnect(oneshot::Sender<bool>),
+    Reconnect(oneshot::Sender<bool>, ReconnectReason),
     /// Request the current state.
     GetState(oneshot::Sender<TunnelState>),
     /// Get the current geographical location.
     GetCurrentLocation(oneshot::Sender<Option<GeoIpLocation>>),
+    /// Check whether traffic leaves the device outside the tunnel. Fails unless connected.
+    TestForLeaks(ResponseTx<geoip::LeakTestResult, Error>),
+    /// Measure the download speed from the API
+    RunSpeedTest(ResponseTx<speed_test::SpeedTestResult, Error>),
+    /// Send a collected problem report, along with an email address and a message
+    SendProblemReport(ResponseTx<(), Error>, String, String, String),
     CreateNewAccount(ResponseTx<String, Error>),
     /// Request the metadata for an account.
     GetAccountData(
@@ -190,33 +240,82 @@ pub enum DaemonCommand {
     GetAccountHistory(oneshot::Sender<Option<AccountToken>>),
     /// Remove the last used account, if there is one
     ClearAccountHistory(ResponseTx<(), Error>),
+    /// Get all values that a frontend has stored in the given namespace
+    GetFrontendValues(oneshot::Sender<BTreeMap<String, String>>, String),
+    /// Set or remove (if `None`) a value in a frontend namespace: namespace, key, value
+    SetFrontendValue(ResponseTx<(), Error>, String, String, Option<String>),
     /// Get the list of countries and cities where there are relays.
-    GetRelayLocations(oneshot::Sender<RelayList>),
+    GetRelayLocations(oneshot::Sender<Arc<RelayList>>),
+    /// Search for relay locations matching a query. Names are matched in English and in the
+    /// given locale.
+    SearchLocations(oneshot::Sender<Vec<LocationSearchResult>>, String, String),
+    /// Explain which constraints prevent a relay from being selected. `None` is returned if a
+    /// custom tunnel endpoint is used.
+    ExplainRelaySelection(oneshot::Sender<Option<RelaySelectionHint>>),
     /// Trigger an asynchronous relay list update. This returns before the relay list is actually
     /// updated.
     UpdateRelayLocations,
     /// Log in with a given account and create a new device.
-    LoginAccount(ResponseTx<(), Error>, AccountToken),
+    LoginAccount(ResponseTx<(), Error>, AccountToken, LoginMode),
     /// Log out of the current account and remove the device, if they exist.
     LogoutAccount(ResponseTx<(), Error>),
     /// Return the current device configuration.
     GetDevice(ResponseTx<DeviceState, Error>),
     /// Update/check the current device, if there is one.
     UpdateDevice(ResponseTx<(), Error>),
+    /// Register a frontend that reports whether it is in the foreground, which makes account
+    /// polls more frequent.
+    NewForegroundClient(oneshot::Sender<device::ForegroundClient>),
     /// Return all the devices for a given account token.
     ListDevices(ResponseTx<Vec<Device>, Error>, AccountToken),
     /// Remove device from a given account.
     RemoveDevice(ResponseTx<(), Error>, AccountToken, DeviceId),
     /// Place constraints on the type of tunnel and relay
     UpdateRelaySettings(ResponseTx<(), settings::Error>, RelaySettingsUpdate),
+    /// Apply several settings at once, reconnecting at most once.
+    ApplySettings(ResponseTx<(), settings::Error>, SettingsUpdate),
     /// Set the allow LAN setting.
     SetAllowLan(ResponseTx<(), settings::Error>, bool),
+    /// Set which multicast groups may be used while connected
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    SetAllowedMulticastGroups(ResponseTx<(), settings::Error>, Vec<IpAddr>),
+    /// Set which DHCP and NDP traffic the firewall allows on all interfaces
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    SetAllowedLinkConfigTraffic(ResponseTx<(), settings::Error>, AllowedLinkConfigTraffic),
+    /// Set which local hosts may be reached when LAN traffic is blocked
+    #[cfg(not(target_os = "android"))]
+    SetAllowedLanHosts(ResponseTx<(), settings::Error>, Vec<IpAddr>),
     /// Set the beta program setting.
     SetShowBetaReleases(ResponseTx<(), settings::Error>, bool),
+    /// Set whether the daemon shows notifications through the OS.
+    SetSystemNotifications(ResponseTx<(), settings::Error>, bool),
+    /// Set which local users may use the management interface.
+    SetManagementAccessPolicy(ResponseTx<(), settings::Error>, ManagementAccessPolicy),
+    /// Add a way of reaching the API.
+    AddApiAccessMethod(ResponseTx<(), settings::Error>, AccessMethod),
+    /// Replace the API access method with the given name.
+    UpdateApiAccessMethod(ResponseTx<(), settings::Error>, String, AccessMethod),
+    /// Remove the API access method with the given name.
+    RemoveApiAccessMethod(ResponseTx<(), settings::Error>, String),
+    /// Check whether the API can be reached using the access method with the given name.
+    TestApiAccessMethod(ResponseTx<bool, settings::Error>, String),
+    /// Get usage counters for the API connection pools.
+    GetApiConnectionPools(ResponseTx<Vec<mullvad_api::pool::PoolMetrics>, Error>),
     /// Set the block_when_disconnected setting.
     SetBlockWhenDisconnected(ResponseTx<(), settings::Error>, bool),
     /// Set the auto-connect setting.
     SetAutoConnect(ResponseTx<(), settings::Error>, bool),
+    /// Set the policy that decides on which networks to auto-connect.
+    SetAutoConnectPolicy(ResponseTx<(), settings::Error>, AutoConnectPolicy),
+    /// Update the network that the device is on and re-evaluate the auto-connect policy. Ignored
+    /// if the daemon detects the network itself.
+    SetCurrentNetwork(oneshot::Sender<()>, NetworkInfo),
+    /// Set the connection parameters to use on specific networks.
+    SetNetworkProfiles(ResponseTx<(), settings::Error>, Vec<NetworkProfile>),
+    /// Get the network that the device is on, along with the routes used outside the tunnel.
+    GetNetworkInfo(oneshot::Sender<CurrentNetwork>),
+    /// Check that the system DNS backend is working
+    CheckDnsBackend(oneshot::Sender<DnsBackendHealth>),
     /// Set the mssfix argument for OpenVPN
     SetOpenVpnMssfix(ResponseTx<(), settings::Error>, Option<u16>),
     /// Set proxy details for OpenVPN
@@ -232,6 +331,11 @@ pub enum DaemonCommand {
     /// Toggle macOS network check leak
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Set which IP versions to assign in-tunnel addresses for in wireguard tunnels
+    SetWireguardAddressFamily(ResponseTx<(), settings::Error>, Option<IpVersion>),
+    /// Set which in-tunnel resolvers to use for wireguard tunnels, and whether IPv6 resolvers
+    /// take precedence
+    SetWireguardDnsOptions(ResponseTx<(), settings::Error>, Option<IpVersion>, bool),
     /// Set automatic key rotation interval for wireguard tunnels
     SetWireguardRotationInterval(ResponseTx<(), settings::Error>, Option<RotationInterval>),
     /// Get the daemon settings
@@ -261,6 +365,12 @@ pub enum DaemonCommand {
     /// Clear list of processes excluded from the tunnel
     #[cfg(target_os = "linux")]
     ClearSplitTunnelProcesses(ResponseTx<(), split_tunnel::Error>),
+    /// Set whether split tunneling processes are excluded from or included in the tunnel
+    #[cfg(target_os = "linux")]
+    SetSplitTunnelMode(ResponseTx<(), settings::Error>, SplitTunnelMode),
+    /// Share the tunnel with the devices on a network interface, or stop sharing it
+    #[cfg(target_os = "linux")]
+    SetSharedInterface(ResponseTx<(), settings::Error>, Option<String>),
     /// Exclude traffic of an application from the tunnel
     #[cfg(windows)]
     AddSplitTunnelApp(ResponseTx<(), Error>, PathBuf),
@@ -284,6 +394,8 @@ pub enum DaemonCommand {
     CheckVolumes(ResponseTx<(), Error>),
     /// Register settings for WireGuard obfuscator
     SetObfuscationSettings(ResponseTx<(), settings::Error>, ObfuscationSettings),
+    /// Set the order in which tunnel protocols and ports are tried when they are not constrained
+    SetAutoPortOrder(ResponseTx<(), settings::Error>, Vec<PortSelectionStep>),
     /// Saves the target tunnel state and enters a blocking state. The state is restored
     /// upon restart.
     PrepareRestart,
@@ -308,6 +420,12 @@ pub(crate) enum InternalDaemonEvent {
     DeviceEvent(AccountEvent),
     /// Handles updates from versions without devices.
     DeviceMigrationEvent(Result<PrivateAccountAndDevice, device::Error>),
+    /// A new relay list was fetched.
+    RelayListUpdated(Arc<RelayList>),
+    /// The device went offline or came back online.
+    OfflineStateChanged(bool),
+    /// The daemon detected that the device moved to another network.
+    NetworkDetected(NetworkInfo),
     /// The split tunnel paths or state were updated.
     #[cfg(target_os = "windows")]
     ExcludedPathsEvent(ExcludedPathsUpdate, oneshot::Sender<Result<(), Error>>),
@@ -343,6 +461,12 @@ impl From<AccountEvent> for InternalDaemonEvent {
     }
 }
 
+impl From<Arc<RelayList>> for InternalDaemonEvent {
+    fn from(relay_list: Arc<RelayList>) -> Self {
+        InternalDaemonEvent::RelayListUpdated(relay_list)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum DaemonExecutionState {
     Running,
@@ -357,7 +481,7 @@ impl DaemonExecutionState {
         match self {
             Running => {
                 match tunnel_state {
-                    TunnelState::Disconnected => mem::replace(self, Finished),
+                    TunnelState::Disconnected { .. } => mem::replace(self, Finished),
                     _ => mem::replace(self, Exiting),
                 };
             }
@@ -514,7 +638,7 @@ pub trait EventListener {
     fn notify_settings(&self, settings: Settings);
 
     /// Notify that the relay list changed.
-    fn notify_relay_list(&self, relay_list: RelayList);
+    fn notify_relay_list(&self, relay_list: Arc<RelayList>);
 
     /// Notify that info about the latest available app version changed.
     /// Or some flag about the currently running version is changed.
@@ -525,6 +649,16 @@ pub trait EventListener {
 
     /// Notify that a device was revoked using `RemoveDevice`.
     fn notify_remove_device_event(&self, event: RemoveDeviceEvent);
+
+    /// Notify that the auto-connect policy was evaluated, and what the outcome was.
+    fn notify_auto_connect_decision(&self, decision: AutoConnectDecision);
+
+    /// Notify that software that may interfere with the tunnel, such as another VPN client, was
+    /// detected.
+    fn notify_conflicting_software(&self, conflicts: Vec<ConflictingSoftware>);
+
+    /// Notify that corrupt cache files were found and moved to a quarantine directory.
+    fn notify_quarantined_files(&self, files: Vec<QuarantinedFile>);
 }
 
 pub struct Daemon<L: EventListener> {
@@ -536,23 +670,56 @@ pub struct Daemon<L: EventListener> {
     rx: mpsc::UnboundedReceiver<InternalDaemonEvent>,
     tx: DaemonEventSender,
     reconnection_job: Option<AbortHandle>,
+    /// Why the tunnel is currently being reconnected, if it is. This is kept until the tunnel
+    /// has connected, so that retries are reported with the same reason.
+    reconnect_reason: Option<ReconnectReason>,
+    port_hop_job: Option<AbortHandle>,
+    unhealthy_reconnect_job: Option<AbortHandle>,
+    expiry_check_job: Option<AbortHandle>,
     event_listener: L,
     migration_complete: migrations::MigrationComplete,
     settings: SettingsPersister,
     account_history: account_history::AccountHistory,
+    frontend_store: frontend_store::FrontendStore,
     device_checker: device::TunnelStateChangeHandler,
     account_manager: device::AccountManagerHandle,
+    account_poller: device::AccountPollerHandle,
     api_runtime: mullvad_api::Runtime,
     api_handle: mullvad_api::rest::MullvadRestHandle,
+    /// Allows the API endpoint through the firewall.
+    api_endpoint_updater: api::ApiEndpointUpdaterHandle,
+    /// Access methods used by `api_handle`.
+    access_methods: api::AccessMethodsHandle,
+    /// Service for GeoIP lookups. Unlike `api_handle`, it never uses a proxy, since the lookup
+    /// would then return the location of the proxy.
+    geoip_service: mullvad_api::rest::RequestServiceHandle,
     version_updater_handle: version_check::VersionUpdaterHandle,
     relay_selector: RelaySelector,
     relay_list_updater: RelayListUpdaterHandle,
+    location_search: location_search::LocationSearch,
+    cache_dir: PathBuf,
     parameters_generator: tunnel::ParametersGenerator,
     app_version_info: Option<AppVersionInfo>,
     shutdown_tasks: Vec<Pin<Box<dyn Future<Output = ()>>>>,
+    /// Whether the firewall rules should be removed when the daemon exits. This is decided when
+    /// the shutdown is triggered.
+    reset_firewall_on_exit: bool,
     tunnel_state_machine_handle: TunnelStateMachineHandle,
     #[cfg(target_os = "windows")]
     volume_update_tx: mpsc::UnboundedSender<()>,
+    current_network: NetworkInfo,
+    /// Set once the daemon has detected the network itself. The network reported by frontends is
+    /// then ignored.
+    network_detected: bool,
+    /// The network profile that applies to `current_network`, if any.
+    network_profile: Option<NetworkProfile>,
+    /// File that the result of the last DNS backend check is written to.
+    dns_check_path: Option<PathBuf>,
+    /// Whether the tunnel was last secured by the auto-connect policy rather than by the user.
+    auto_connected: bool,
+    system_notifier: system_notifications::SystemNotifier,
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    state_hook: state_hook::StateHook,
 }
 
 impl<L> Daemon<L>
@@ -574,11 +741,15 @@ where
             macos::set_exclusion_gid().map_err(Error::GroupIdError)?
         };
 
-        mullvad_api::proxy::ApiConnectionMode::try_delete_cache(&cache_dir).await;
+        let previous_run_crashed = crash_handler::check_previous_run(&cache_dir).await;
+        if previous_run_crashed {
+            log::warn!("The previous daemon instance did not exit cleanly");
+            mullvad_api::cache_integrity::verify_dir(&cache_dir).await;
+        }
 
         let (internal_event_tx, internal_event_rx) = command_channel.destructure();
 
-        let api_runtime = mullvad_api::Runtime::with_cache(
+        let mut api_runtime = mullvad_api::Runtime::with_cache(
             &cache_dir,
             true,
             #[cfg(target_os = "android")]
@@ -586,6 +757,7 @@ where
         )
         .await
         .map_err(Error::InitRpcFactory)?;
+        api_runtime.set_pool_config(mullvad_api::pool::PoolConfig::from_env_vars());
 
         let api_availability = api_runtime.availability_handle();
         api_availability.suspend();
@@ -602,16 +774,23 @@ where
                 None
             });
         let settings = SettingsPersister::load(&settings_dir).await;
+        // The management interface restricts access until it knows the access policy
+        event_listener.notify_settings(settings.to_settings());
         let app_version_info = version_check::load_cache(&cache_dir).await;
 
         let initial_selector_config = new_selector_config(&settings, &app_version_info);
         let relay_selector = RelaySelector::new(initial_selector_config, &resource_dir, &cache_dir);
 
-        let proxy_provider =
-            api::ApiConnectionModeProvider::new(cache_dir.clone(), relay_selector.clone());
+        let access_methods = api::AccessMethodsHandle::new(&settings.api_access_methods);
+        let proxy_provider = api::ApiConnectionModeProvider::new(
+            cache_dir.clone(),
+            relay_selector.clone(),
+            access_methods.clone(),
+        );
         let api_handle = api_runtime
             .mullvad_rest_handle(proxy_provider, endpoint_updater.callback())
             .await;
+        let geoip_service = api_runtime.rest_handle().await;
 
         let migration_complete = if let Some(migration_data) = migration_data {
             migrations::migrate_device(
@@ -635,6 +814,8 @@ where
         )
         .await
         .map_err(Error::LoadAccountManager)?;
+        let account_poller =
+            device::spawn_account_poller(account_manager.clone(), api_availability.clone());
 
         let account_history = account_history::AccountHistory::new(
             &settings_dir,
@@ -642,8 +823,12 @@ where
         )
         .await
         .map_err(Error::LoadAccountHistory)?;
+        let frontend_store = frontend_store::FrontendStore::new(&settings_dir).await;
 
-        let target_state = if settings.auto_connect {
+        let auto_connect = settings
+            .auto_connect_policy
+            .evaluate(settings.auto_connect, &NetworkInfo::Unknown);
+        let target_state = if auto_connect.connect {
             log::info!("Automatically connecting since auto-connect is turned on");
             PersistentTargetState::force(&cache_dir, TargetState::Secured).await
         } else {
@@ -662,13 +847,28 @@ where
             vec![]
         };
 
-        let initial_api_endpoint =
-            api::get_allowed_endpoint(api_runtime.address_cache.get_address().await);
+        let dns_check_path = log_dir.as_ref().map(|dir| dir.join(DNS_CHECK_FILENAME));
+        let system_notifier =
+            system_notifications::SystemNotifier::new(settings.system_notifications);
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let state_hook = state_hook::StateHook::new(&settings_dir);
+
+        let initial_endpoint = Endpoint::from_socket_address(
+            api_runtime.address_cache.get_address().await,
+            TransportProtocol::Tcp,
+        );
+        endpoint_updater.set_initial_endpoint(initial_endpoint);
+        let initial_api_endpoint = api::get_allowed_endpoint(initial_endpoint);
         let parameters_generator = tunnel::ParametersGenerator::new(
             account_manager.clone(),
             relay_selector.clone(),
             settings.tunnel_options.clone(),
+            &cache_dir,
         );
+        // The saved tunnel may be the one that the previous instance crashed while using
+        if previous_run_crashed {
+            parameters_generator.discard_warm_tunnel().await;
+        }
         let (offline_state_tx, offline_state_rx) = mpsc::unbounded();
         #[cfg(target_os = "windows")]
         let (volume_update_tx, volume_update_rx) = mpsc::unbounded();
@@ -679,10 +879,22 @@ where
                 dns_servers: dns::addresses_from_options(&settings.tunnel_options.dns_options),
                 allowed_endpoint: initial_api_endpoint,
                 reset_firewall: *target_state != TargetState::Secured,
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                allowed_multicast_groups: settings.allowed_multicast_groups.clone(),
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                allowed_link_config_traffic: settings.allowed_link_config_traffic,
+                #[cfg(not(target_os = "android"))]
+                allowed_lan_hosts: settings.allowed_lan_hosts.clone(),
+                #[cfg(target_os = "linux")]
+                split_tunnel_mode: settings.split_tunnel_mode,
+                #[cfg(target_os = "linux")]
+                shared_interface: settings.shared_interface.clone(),
                 #[cfg(windows)]
                 exclude_paths,
+                connection_timeouts: settings.tunnel_options.connection_timeouts,
             },
             parameters_generator.clone(),
+            tunnel_state_machine::TunnelStateMachineBackends::default(),
             log_dir,
             resource_dir.clone(),
             internal_event_tx.to_specialized_sender(),
@@ -705,16 +917,29 @@ where
         endpoint_updater
             .set_tunnel_command_tx(Arc::downgrade(tunnel_state_machine_handle.command_tx()));
 
-        api::forward_offline_state(api_availability.clone(), offline_state_rx);
+        api::forward_offline_state(
+            api_availability.clone(),
+            internal_event_tx.clone(),
+            offline_state_rx,
+        );
+        network_detection::spawn_monitor(internal_event_tx.clone());
 
-        let relay_list_listener = event_listener.clone();
-        let on_relay_list_update = move |relay_list: &RelayList| {
-            relay_list_listener.notify_relay_list(relay_list.clone());
+        let relay_list_tx = internal_event_tx.to_specialized_sender();
+        let on_relay_list_update = move |relay_list: Arc<RelayList>| {
+            let _ = relay_list_tx.send(relay_list);
         };
 
+        let relay_list_keys = mullvad_api::RelayListKeys::load(&cache_dir).await;
+        if !relay_list_keys.is_enforced() {
+            log::warn!(
+                "No relay list signing key was embedded. Relay list signatures are not verified"
+            );
+        }
+
         let mut relay_list_updater = RelayListUpdater::spawn(
             relay_selector.clone(),
             api_handle.clone(),
+            relay_list_keys.clone(),
             &cache_dir,
             on_relay_list_update,
         );
@@ -726,38 +951,71 @@ where
             internal_event_tx.to_specialized_sender(),
             app_version_info.clone(),
             settings.show_beta_releases,
+            settings.check_for_updates,
+            relay_list_keys,
         );
         tokio::spawn(version_updater.run());
 
         // Attempt to download a fresh relay list
         relay_list_updater.update().await;
 
+        #[cfg(target_os = "linux")]
+        let exclude_pids = {
+            let mut exclude_pids =
+                split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?;
+            exclude_pids.set_mode(settings.split_tunnel_mode);
+            if settings.split_tunnel_mode == SplitTunnelMode::Include {
+                log_split_tunnel_include_mode_warning();
+            }
+            exclude_pids
+        };
+
         let daemon = Daemon {
-            tunnel_state: TunnelState::Disconnected,
+            tunnel_state: TunnelState::Disconnected { reason: None },
             target_state,
             state: DaemonExecutionState::Running,
             #[cfg(target_os = "linux")]
-            exclude_pids: split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?,
+            exclude_pids,
             rx: internal_event_rx,
             tx: internal_event_tx,
             reconnection_job: None,
+            reconnect_reason: None,
+            port_hop_job: None,
+            unhealthy_reconnect_job: None,
+            expiry_check_job: None,
             event_listener,
             migration_complete,
             settings,
             account_history,
+            frontend_store,
             device_checker: device::TunnelStateChangeHandler::new(account_manager.clone()),
             account_manager,
+            account_poller,
             api_runtime,
             api_handle,
+            api_endpoint_updater: endpoint_updater,
+            access_methods,
+            geoip_service,
             version_updater_handle,
             relay_selector,
             relay_list_updater,
+            location_search: location_search::LocationSearch::new(resource_dir),
+            cache_dir,
             parameters_generator,
             app_version_info,
             shutdown_tasks: vec![],
+            reset_firewall_on_exit: false,
             tunnel_state_machine_handle,
             #[cfg(target_os = "windows")]
             volume_update_tx,
+            current_network: NetworkInfo::Unknown,
+            network_detected: false,
+            network_profile: None,
+            dns_check_path,
+            auto_connected: auto_connect.connect,
+            system_notifier,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            state_hook,
         };
 
         api_availability.unsuspend();
@@ -768,13 +1026,32 @@ where
     /// Consume the `Daemon` and run the main event loop. Blocks until an error happens or a
     /// shutdown event is received.
     pub async fn run(mut self) -> Result<(), Error> {
+        let quarantined_files = mullvad_api::cache_integrity::take_quarantined();
+        if !quarantined_files.is_empty() {
+            for file in &quarantined_files {
+                log::warn!("Quarantined corrupt cache file: {}", file);
+            }
+            self.event_listener
+                .notify_quarantined_files(quarantined_files);
+        }
+
         if *self.target_state == TargetState::Secured {
+            self.parameters_generator
+                .load_warm_tunnel(
+                    &self.settings.get_relay_settings(),
+                    &self.settings.obfuscation_settings,
+                )
+                .await;
             self.connect_tunnel();
         }
 
+        if self.system_notifier.is_enabled() {
+            self.schedule_expiry_checks();
+        }
+
         while let Some(event) = self.rx.next().await {
-            self.handle_event(event).await;
-            if self.state == DaemonExecutionState::Finished {
+            self.handle_event_or_crash(event).await;
+            if !self.state.is_running() {
                 break;
             }
         }
@@ -783,15 +1060,45 @@ where
         Ok(())
     }
 
-    async fn finalize(self) {
+    /// Handles an event. If that panics, the state of the daemon can no longer be trusted, so it
+    /// is shut down. Panics in other tasks and threads do not affect the daemon.
+    async fn handle_event_or_crash(&mut self, event: InternalDaemonEvent) {
+        let result =
+            futures::FutureExt::catch_unwind(AssertUnwindSafe(self.handle_event(event))).await;
+        if result.is_err() && !crash_handler::has_crashed() {
+            crash_handler::set_crashed();
+            if self.state.is_running() {
+                log::warn!("Shutting down to restore the system state after a panic");
+                self.trigger_shutdown_event(false);
+            }
+        }
+    }
+
+    /// Shuts down the subsystems of the daemon in order. See [`ShutdownStage`].
+    async fn finalize(mut self) {
+        shutdown::run_stage(ShutdownStage::DisconnectTunnel, self.wait_for_disconnect()).await;
+
+        let cache_dir = self.cache_dir.clone();
+        let reset_firewall = self.reset_firewall_on_exit;
         let (event_listener, shutdown_tasks, api_runtime, tunnel_state_machine_handle) =
             self.shutdown();
-        for future in shutdown_tasks {
-            future.await;
+        shutdown::run_stage(ShutdownStage::PersistState, async {
+            for future in shutdown_tasks {
+                future.await;
+            }
+        })
+        .await;
+        let network_restored = shutdown::run_stage(
+            ShutdownStage::RestoreNetwork,
+            tunnel_state_machine_handle.join(),
+        )
+        .await;
+        // The state machine did not get to remove its firewall rules, so they would keep blocking
+        // traffic after the daemon has exited
+        if !network_restored && reset_firewall {
+            shutdown::reset_firewall();
         }
 
-        tunnel_state_machine_handle.try_join().await;
-
         drop(event_listener);
         drop(api_runtime);
 
@@ -801,6 +1108,19 @@ where
                 log::error!("Failed to remove old RPC socket: {}", err);
             }
         }
+
+        crash_handler::mark_clean_exit(&cache_dir).await;
+    }
+
+    /// Handles events until the tunnel has been disconnected. Commands are dropped, but tunnel
+    /// state transitions are still passed on to frontends.
+    async fn wait_for_disconnect(&mut self) {
+        while self.state != DaemonExecutionState::Finished {
+            match self.rx.next().await {
+                Some(event) => self.handle_event_or_crash(event).await,
+                None => break,
+            }
+        }
     }
 
     /// Shuts down the daemon without shutting down the underlying event listener and the shutdown
@@ -818,11 +1138,16 @@ where
             mut shutdown_tasks,
             api_runtime,
             tunnel_state_machine_handle,
-            target_state,
+            mut target_state,
             account_manager,
             ..
         } = self;
 
+        // Keep the target state so that the next instance reconnects if the tunnel was secured
+        // when the panic occurred
+        if crash_handler::has_crashed() {
+            target_state.lock();
+        }
         shutdown_tasks.push(Box::pin(target_state.finalize()));
         shutdown_tasks.push(Box::pin(account_manager.shutdown()));
 
@@ -847,6 +1172,9 @@ where
             }
             DeviceEvent(event) => self.handle_device_event(event).await,
             DeviceMigrationEvent(event) => self.handle_device_migration_event(event).await,
+            RelayListUpdated(relay_list) => self.handle_relay_list_update(relay_list),
+            OfflineStateChanged(is_offline) => self.handle_offline_state(is_offline).await,
+            NetworkDetected(network) => self.handle_network_detected(network).await,
             #[cfg(windows)]
             ExcludedPathsEvent(update, tx) => self.handle_new_excluded_paths(update, tx).await,
         }
@@ -856,20 +1184,90 @@ where
         &mut self,
         tunnel_state_transition: TunnelStateTransition,
     ) {
+        // A connected tunnel emits the transition again when its health changes, or when it is
+        // moved to another port. The tunnel stays up, so only those are updated.
+        if let (
+            TunnelState::Connected {
+                endpoint, health, ..
+            },
+            TunnelStateTransition::Connected(new_endpoint, new_health, _),
+        ) = (&mut self.tunnel_state, &tunnel_state_transition)
+        {
+            if health != new_health {
+                log::info!("Tunnel health: {}", new_health);
+                *health = *new_health;
+                self.update_unhealthy_reconnect_job();
+            }
+            if endpoint != new_endpoint {
+                log::info!("Tunnel moved to {}", new_endpoint);
+                *endpoint = *new_endpoint;
+                self.update_port_hop_job();
+            }
+            self.event_listener
+                .notify_new_state(self.tunnel_state.clone());
+            return;
+        }
+
+        // Likewise, a connection attempt emits the transition again when it reaches a new phase.
+        // Only a new attempt starts over from opening the device.
+        if let (
+            TunnelState::Connecting { phase, .. },
+            TunnelStateTransition::Connecting(_, _, new_phase),
+        ) = (&mut self.tunnel_state, &tunnel_state_transition)
+        {
+            if *new_phase != ConnectingPhase::OpeningDevice {
+                log::debug!("Connection attempt phase: {}", new_phase);
+                *phase = *new_phase;
+                self.event_listener
+                    .notify_new_state(self.tunnel_state.clone());
+                return;
+            }
+        }
+
         self.reset_rpc_sockets_on_tunnel_state_transition(&tunnel_state_transition)
             .await;
         self.device_checker
             .handle_state_transition(&tunnel_state_transition);
 
+        match tunnel_state_transition {
+            TunnelStateTransition::Connecting(_, Some(reason), _) => {
+                self.reconnect_reason = Some(reason);
+            }
+            TunnelStateTransition::Connecting(_, None, _)
+            | TunnelStateTransition::Disconnecting(_) => (),
+            _ => self.reconnect_reason = None,
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let tunnel_interface = match &tunnel_state_transition {
+            TunnelStateTransition::Connected(_, _, interface) => Some(interface.name.clone()),
+            _ => None,
+        };
+
         let tunnel_state = match tunnel_state_transition {
-            TunnelStateTransition::Disconnected => TunnelState::Disconnected,
-            TunnelStateTransition::Connecting(endpoint) => TunnelState::Connecting {
-                endpoint,
-                location: self.parameters_generator.get_last_location().await,
-            },
-            TunnelStateTransition::Connected(endpoint) => TunnelState::Connected {
+            TunnelStateTransition::Disconnected(reason) => {
+                if let Some(reason) = reason {
+                    log::info!("Disconnected, reason: {}", reason);
+                }
+                TunnelState::Disconnected { reason }
+            }
+            TunnelStateTransition::Connecting(endpoint, _, phase) => {
+                if let Some(reason) = self.reconnect_reason {
+                    if phase == ConnectingPhase::OpeningDevice {
+                        log::info!("Reconnecting, reason: {}", reason);
+                    }
+                }
+                TunnelState::Connecting {
+                    endpoint,
+                    location: self.parameters_generator.get_last_location().await,
+                    reconnect_reason: self.reconnect_reason,
+                    phase,
+                }
+            }
+            TunnelStateTransition::Connected(endpoint, health, _) => TunnelState::Connected {
                 endpoint,
                 location: self.parameters_generator.get_last_location().await,
+                health,
             },
             TunnelStateTransition::Disconnecting(after_disconnect) => {
                 TunnelState::Disconnecting(after_disconnect)
@@ -887,7 +1285,7 @@ where
         log::debug!("New tunnel state: {:?}", tunnel_state);
 
         match tunnel_state {
-            TunnelState::Disconnected => {
+            TunnelState::Disconnected { .. } => {
                 self.api_handle.availability.reset_inactivity_timer();
             }
             _ => {
@@ -895,9 +1293,19 @@ where
             }
         }
 
+        let mut failure_disconnect_reason = None;
         match tunnel_state {
-            TunnelState::Disconnected => self.state.disconnected(),
+            TunnelState::Disconnected { .. } => self.state.disconnected(),
+            // Check the DNS configuration that is in effect in the connected state, so that it
+            // can be included in problem reports.
+            TunnelState::Connected { .. } => {
+                tokio::spawn(Self::check_dns_backend(self.dns_check_path.clone()));
+            }
             TunnelState::Error(ref error_state) => {
+                if self.auto_connected && *self.target_state == TargetState::Secured {
+                    failure_disconnect_reason =
+                        auto_connect::failure_disconnect_reason(error_state);
+                }
                 if error_state.is_blocking() {
                     log::info!(
                         "Blocking all network connections, reason: {}",
@@ -910,33 +1318,60 @@ where
                     );
                 }
 
-                if let ErrorStateCause::AuthFailed(_) = error_state.cause() {
+                if let (ErrorStateCause::AuthFailed(_), None) =
+                    (error_state.cause(), failure_disconnect_reason)
+                {
                     // If time is added outside of the app, no notifications
                     // are received. So we must continually try to reconnect.
-                    self.schedule_reconnect(Duration::from_secs(60))
+                    self.schedule_reconnect(
+                        Duration::from_secs(60),
+                        ReconnectReason::AuthFailedRetry,
+                    )
                 }
             }
             _ => {}
         }
 
+        self.system_notifier.handle_tunnel_state(&tunnel_state);
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        self.state_hook
+            .handle_tunnel_state(&tunnel_state, tunnel_interface.as_deref());
         self.tunnel_state = tunnel_state.clone();
+        self.update_port_hop_job();
+        self.update_unhealthy_reconnect_job();
         self.event_listener.notify_new_state(tunnel_state);
+
+        // A tunnel that the user did not ask for is given up on rather than left failing
+        if let Some(reason) = failure_disconnect_reason {
+            log::info!("Disconnecting automatically connected tunnel: {}", reason);
+            self.set_target_state(TargetState::Unsecured, reason).await;
+        }
     }
 
     async fn reset_rpc_sockets_on_tunnel_state_transition(
         &mut self,
         tunnel_state_transition: &TunnelStateTransition,
     ) {
+        // API sockets bypass the tunnel on Android
+        #[cfg(not(target_os = "android"))]
+        self.api_handle
+            .service()
+            .set_tunnel_interface(match tunnel_state_transition {
+                TunnelStateTransition::Connected(_, _, interface) => Some(interface.clone()),
+                _ => None,
+            });
+
         match (&self.tunnel_state, &tunnel_state_transition) {
             // only reset the API sockets if when connected or leaving the connected state
-            (&TunnelState::Connected { .. }, _) | (_, &TunnelStateTransition::Connected(_)) => {
+            (&TunnelState::Connected { .. }, _) | (_, &TunnelStateTransition::Connected(..)) => {
                 self.api_handle.service().reset();
+                self.geoip_service.reset();
             }
             _ => (),
         };
     }
 
-    fn schedule_reconnect(&mut self, delay: Duration) {
+    fn schedule_reconnect(&mut self, delay: Duration, reason: ReconnectReason) {
         self.unschedule_reconnect();
 
         let daemon_command_tx = self.tx.to_specialized_sender();
@@ -944,7 +1379,7 @@ where
             tokio::time::sleep(delay).await;
             log::debug!("Attempting to reconnect");
             let (tx, rx) = oneshot::channel();
-            let _ = daemon_command_tx.send(DaemonCommand::Reconnect(tx));
+            let _ = daemon_command_tx.send(DaemonCommand::Reconnect(tx, reason));
             // suppress "unable to send" warning:
             let _ = rx.await;
         }));
@@ -953,12 +1388,158 @@ where
         self.reconnection_job = Some(abort_handle);
     }
 
+    /// Gives the new key to the tunnel after `delay`, which should be long enough for the relays
+    /// to learn about it. The tunnel is only restarted if the key cannot be replaced in place.
+    fn schedule_key_rotation(&mut self, delay: Duration, private_key: PrivateKey) {
+        self.unschedule_reconnect();
+
+        let tunnel_command_tx = Arc::downgrade(self.tunnel_state_machine_handle.command_tx());
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            log::debug!("Replacing the WireGuard key of the tunnel");
+            if let Some(tunnel_command_tx) = tunnel_command_tx.upgrade() {
+                let _ = tunnel_command_tx.unbounded_send(TunnelCommand::RotateKey(private_key));
+            }
+        }));
+
+        tokio::spawn(future);
+        self.reconnection_job = Some(abort_handle);
+    }
+
     fn unschedule_reconnect(&mut self) {
         if let Some(job) = self.reconnection_job.take() {
             job.abort();
         }
     }
 
+    /// Schedules the next port hop if port hopping is enabled and a WireGuard tunnel is
+    /// connected. Any previously scheduled hop is cancelled.
+    fn update_port_hop_job(&mut self) {
+        self.unschedule_port_hop();
+        if let TunnelState::Connected {
+            endpoint:
+                TunnelEndpoint {
+                    tunnel_type: TunnelType::Wireguard,
+                    ..
+                },
+            ..
+        } = self.tunnel_state
+        {
+            let obfuscation_settings = &self.settings.obfuscation_settings;
+            if self.selected_obfuscation() == SelectedObfuscation::PortHopping {
+                if let Some(interval) = obfuscation_settings
+                    .port_hopping
+                    .interval
+                    .filter(|interval| *interval > 0)
+                {
+                    self.schedule_port_hop(Duration::from_secs(u64::from(interval)));
+                }
+            }
+        }
+    }
+
+    /// Moves the tunnel to a new port on the same relay after `delay`. The tunnel is only
+    /// reconnected if the port cannot be changed in place.
+    fn schedule_port_hop(&mut self, delay: Duration) {
+        self.unschedule_port_hop();
+
+        let daemon_command_tx = self.tx.to_specialized_sender();
+        let tunnel_command_tx = Arc::downgrade(self.tunnel_state_machine_handle.command_tx());
+        let parameters_generator = self.parameters_generator.clone();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            let port = match parameters_generator.hop_port().await {
+                Some(port) => port,
+                None => return,
+            };
+            log::debug!("Hopping to WireGuard port {}", port);
+            let tunnel_command_tx = match tunnel_command_tx.upgrade() {
+                Some(tunnel_command_tx) => tunnel_command_tx,
+                None => return,
+            };
+            let (tx, rx) = oneshot::channel();
+            let _ = tunnel_command_tx.unbounded_send(TunnelCommand::HopPort(port, tx));
+            if rx.await.unwrap_or(false) {
+                return;
+            }
+            parameters_generator.request_port_hop().await;
+            let (tx, rx) = oneshot::channel();
+            let _ = daemon_command_tx.send(DaemonCommand::Reconnect(tx, ReconnectReason::PortHop));
+            // suppress "unable to send" warning:
+            let _ = rx.await;
+        }));
+
+        tokio::spawn(future);
+        self.port_hop_job = Some(abort_handle);
+    }
+
+    fn unschedule_port_hop(&mut self) {
+        if let Some(job) = self.port_hop_job.take() {
+            job.abort();
+        }
+    }
+
+    /// Schedules a reconnect for when the connected tunnel has stayed unhealthy for
+    /// `UNHEALTHY_RECONNECT_DELAY`. The reconnect is cancelled if the tunnel recovers or stops
+    /// being connected before then.
+    fn update_unhealthy_reconnect_job(&mut self) {
+        let unhealthy = matches!(
+            &self.tunnel_state,
+            TunnelState::Connected { health, .. } if !health.is_healthy()
+        );
+        if !unhealthy {
+            if let Some(job) = self.unhealthy_reconnect_job.take() {
+                job.abort();
+            }
+            return;
+        }
+        if self.unhealthy_reconnect_job.is_some() {
+            return;
+        }
+
+        let daemon_command_tx = self.tx.to_specialized_sender();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            tokio::time::sleep(UNHEALTHY_RECONNECT_DELAY).await;
+            log::info!("Reconnecting because the tunnel did not recover");
+            let (tx, rx) = oneshot::channel();
+            let _ =
+                daemon_command_tx.send(DaemonCommand::Reconnect(tx, ReconnectReason::Unhealthy));
+            // suppress "unable to send" warning:
+            let _ = rx.await;
+        }));
+
+        tokio::spawn(future);
+        self.unhealthy_reconnect_job = Some(abort_handle);
+    }
+
+    /// Periodically fetches the account expiry, so that the user is warned before it passes even
+    /// if no client asks for it.
+    fn schedule_expiry_checks(&mut self) {
+        self.unschedule_expiry_checks();
+
+        let account_manager = self.account_manager.clone();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            loop {
+                if let Err(error) = account_manager.check_expiry().await {
+                    log::debug!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to check account expiry")
+                    );
+                }
+                tokio::time::sleep(EXPIRY_CHECK_INTERVAL).await;
+            }
+        }));
+
+        tokio::spawn(future);
+        self.expiry_check_job = Some(abort_handle);
+    }
+
+    fn unschedule_expiry_checks(&mut self) {
+        if let Some(job) = self.expiry_check_job.take() {
+            job.abort();
+        }
+    }
+
     async fn handle_command(&mut self, command: DaemonCommand) {
         use self::DaemonCommand::*;
         if !self.state.is_running() {
@@ -972,33 +1553,74 @@ where
 
         match command {
             SetTargetState(tx, state) => self.on_set_target_state(tx, state).await,
-            Reconnect(tx) => self.on_reconnect(tx),
+            Reconnect(tx, reason) => self.on_reconnect(tx, reason),
             GetState(tx) => self.on_get_state(tx),
             GetCurrentLocation(tx) => self.on_get_current_location(tx).await,
+            TestForLeaks(tx) => self.on_test_for_leaks(tx),
+            RunSpeedTest(tx) => self.on_run_speed_test(tx),
+            SendProblemReport(tx, email, message, report) => {
+                self.on_send_problem_report(tx, email, message, report)
+            }
             CreateNewAccount(tx) => self.on_create_new_account(tx).await,
             GetAccountData(tx, account_token) => self.on_get_account_data(tx, account_token).await,
             GetWwwAuthToken(tx) => self.on_get_www_auth_token(tx).await,
             SubmitVoucher(tx, voucher) => self.on_submit_voucher(tx, voucher).await,
             GetRelayLocations(tx) => self.on_get_relay_locations(tx),
+            SearchLocations(tx, query, locale) => self.on_search_locations(tx, query, locale),
+            ExplainRelaySelection(tx) => self.on_explain_relay_selection(tx),
             UpdateRelayLocations => self.on_update_relay_locations().await,
-            LoginAccount(tx, account_token) => self.on_login_account(tx, account_token),
+            LoginAccount(tx, account_token, mode) => self.on_login_account(tx, account_token, mode),
             LogoutAccount(tx) => self.on_logout_account(tx),
             GetDevice(tx) => self.on_get_device(tx).await,
             UpdateDevice(tx) => self.on_update_device(tx).await,
+            NewForegroundClient(tx) => self.on_new_foreground_client(tx),
             ListDevices(tx, account_token) => self.on_list_devices(tx, account_token).await,
             RemoveDevice(tx, account_token, device_id) => {
                 self.on_remove_device(tx, account_token, device_id).await
             }
             GetAccountHistory(tx) => self.on_get_account_history(tx),
             ClearAccountHistory(tx) => self.on_clear_account_history(tx).await,
+            GetFrontendValues(tx, namespace) => self.on_get_frontend_values(tx, namespace),
+            SetFrontendValue(tx, namespace, key, value) => {
+                self.on_set_frontend_value(tx, namespace, key, value).await
+            }
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update).await,
+            ApplySettings(tx, update) => self.on_apply_settings(tx, update).await,
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan).await,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            SetAllowedMulticastGroups(tx, groups) => {
+                self.on_set_allowed_multicast_groups(tx, groups).await
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            SetAllowedLinkConfigTraffic(tx, allowed) => {
+                self.on_set_allowed_link_config_traffic(tx, allowed).await
+            }
+            #[cfg(not(target_os = "android"))]
+            SetAllowedLanHosts(tx, hosts) => self.on_set_allowed_lan_hosts(tx, hosts).await,
             SetShowBetaReleases(tx, enabled) => self.on_set_show_beta_releases(tx, enabled).await,
+            SetSystemNotifications(tx, enabled) => {
+                self.on_set_system_notifications(tx, enabled).await
+            }
+            SetManagementAccessPolicy(tx, policy) => {
+                self.on_set_management_access_policy(tx, policy).await
+            }
+            AddApiAccessMethod(tx, method) => self.on_add_api_access_method(tx, method).await,
+            UpdateApiAccessMethod(tx, name, method) => {
+                self.on_update_api_access_method(tx, name, method).await
+            }
+            RemoveApiAccessMethod(tx, name) => self.on_remove_api_access_method(tx, name).await,
+            TestApiAccessMethod(tx, name) => self.on_test_api_access_method(tx, name).await,
+            GetApiConnectionPools(tx) => self.on_get_api_connection_pools(tx),
             SetBlockWhenDisconnected(tx, block_when_disconnected) => {
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
                     .await
             }
             SetAutoConnect(tx, auto_connect) => self.on_set_auto_connect(tx, auto_connect).await,
+            SetAutoConnectPolicy(tx, policy) => self.on_set_auto_connect_policy(tx, policy).await,
+            SetCurrentNetwork(tx, network) => self.on_set_current_network(tx, network).await,
+            SetNetworkProfiles(tx, profiles) => self.on_set_network_profiles(tx, profiles).await,
+            GetNetworkInfo(tx) => self.on_get_network_info(tx),
+            CheckDnsBackend(tx) => self.on_check_dns_backend(tx),
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg).await,
             SetBridgeSettings(tx, bridge_settings) => {
                 self.on_set_bridge_settings(tx, bridge_settings).await
@@ -1010,6 +1632,14 @@ where
             }
             SetDnsOptions(tx, dns_servers) => self.on_set_dns_options(tx, dns_servers).await,
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu).await,
+            SetWireguardAddressFamily(tx, address_family) => {
+                self.on_set_wireguard_address_family(tx, address_family)
+                    .await
+            }
+            SetWireguardDnsOptions(tx, ip_version, prefer_ipv6) => {
+                self.on_set_wireguard_dns_options(tx, ip_version, prefer_ipv6)
+                    .await
+            }
             SetWireguardRotationInterval(tx, interval) => {
                 self.on_set_wireguard_rotation_interval(tx, interval).await
             }
@@ -1029,6 +1659,10 @@ where
             RemoveSplitTunnelProcess(tx, pid) => self.on_remove_split_tunnel_process(tx, pid),
             #[cfg(target_os = "linux")]
             ClearSplitTunnelProcesses(tx) => self.on_clear_split_tunnel_processes(tx),
+            #[cfg(target_os = "linux")]
+            SetSplitTunnelMode(tx, mode) => self.on_set_split_tunnel_mode(tx, mode).await,
+            #[cfg(target_os = "linux")]
+            SetSharedInterface(tx, interface) => self.on_set_shared_interface(tx, interface).await,
             #[cfg(windows)]
             AddSplitTunnelApp(tx, path) => self.on_add_split_tunnel_app(tx, path).await,
             #[cfg(windows)]
@@ -1046,6 +1680,7 @@ where
             SetObfuscationSettings(tx, settings) => {
                 self.on_set_obfuscation_settings(tx, settings).await
             }
+            SetAutoPortOrder(tx, port_order) => self.on_set_auto_port_order(tx, port_order).await,
             PrepareRestart => self.on_prepare_restart(),
             #[cfg(target_os = "android")]
             BypassSocket(fd, tx) => self.on_bypass_socket(fd, tx),
@@ -1054,39 +1689,53 @@ where
 
     fn handle_new_app_version_info(&mut self, app_version_info: AppVersionInfo) {
         self.app_version_info = Some(app_version_info.clone());
-        self.relay_selector
-            .set_config(new_selector_config(&self.settings, &self.app_version_info));
+        self.relay_selector.set_config(self.selector_config());
         self.event_listener.notify_app_version(app_version_info);
     }
 
     async fn handle_device_event(&mut self, event: AccountEvent) {
+        if let AccountEvent::Expiry(expiry) = &event {
+            self.system_notifier.handle_expiry(*expiry);
+        }
         match &event {
             AccountEvent::Device(PrivateDeviceEvent::Login(device)) => {
-                if let Err(error) = self.account_history.set(device.account_token.clone()).await {
-                    log::error!(
-                        "{}",
-                        error.display_chain_with_msg("Failed to update account history")
-                    );
+                if device.login_mode == LoginMode::Remember {
+                    if let Err(error) = self.account_history.set(device.account_token.clone()).await
+                    {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to update account history")
+                        );
+                    }
                 }
                 if *self.target_state == TargetState::Secured {
                     log::debug!("Initiating tunnel restart because the account token changed");
-                    self.reconnect_tunnel();
+                    self.reconnect_tunnel(ReconnectReason::AccountChanged);
                 }
             }
             AccountEvent::Device(PrivateDeviceEvent::Logout) => {
                 log::info!("Disconnecting because account token was cleared");
-                self.set_target_state(TargetState::Unsecured).await;
+                self.set_target_state(TargetState::Unsecured, DisconnectReason::LoggedOut)
+                    .await;
             }
             AccountEvent::Device(PrivateDeviceEvent::Revoked) => {
-                // If we're currently in a secured state, reconnect to make sure we immediately
-                // enter the error state.
-                if *self.target_state == TargetState::Secured {
+                if *self.target_state == TargetState::Secured && self.auto_connected {
+                    log::info!("Disconnecting because the device was revoked");
+                    self.set_target_state(TargetState::Unsecured, DisconnectReason::DeviceRevoked)
+                        .await;
+                } else if *self.target_state == TargetState::Secured {
+                    // If we're currently in a secured state, reconnect to make sure we
+                    // immediately enter the error state.
                     self.connect_tunnel();
                 }
             }
-            AccountEvent::Device(PrivateDeviceEvent::RotatedKey(_)) => {
+            AccountEvent::Device(PrivateDeviceEvent::RotatedKey(device))
+            | AccountEvent::Device(PrivateDeviceEvent::RepairedKey(device)) => {
                 if self.get_target_tunnel_type() == Some(TunnelType::Wireguard) {
-                    self.schedule_reconnect(WG_RECONNECT_DELAY);
+                    self.schedule_key_rotation(
+                        WG_RECONNECT_DELAY,
+                        device.device.wg_data.private_key.clone(),
+                    );
                 }
             }
             AccountEvent::Expiry(expiry) if *self.target_state == TargetState::Secured => {
@@ -1094,6 +1743,7 @@ where
                     if let TunnelState::Error(ref state) = self.tunnel_state {
                         if matches!(state.cause(), ErrorStateCause::AuthFailed(_)) {
                             log::debug!("Reconnecting since the account has time on it");
+                            self.reconnect_reason = Some(ReconnectReason::AccountCredited);
                             self.connect_tunnel();
                         }
                     }
@@ -1151,6 +1801,64 @@ where
         });
     }
 
+    fn handle_relay_list_update(&mut self, relay_list: Arc<RelayList>) {
+        self.reconnect_if_relay_unavailable(&relay_list);
+        self.event_listener.notify_relay_list(relay_list);
+    }
+
+    /// Connects the tunnel again if it was connected automatically, and disconnected when the
+    /// device went offline.
+    async fn handle_offline_state(&mut self, is_offline: bool) {
+        let disconnected_offline = matches!(
+            self.tunnel_state,
+            TunnelState::Disconnected {
+                reason: Some(DisconnectReason::Offline)
+            }
+        );
+        if !is_offline && disconnected_offline && self.state.is_running() {
+            log::debug!("Back online, evaluating the auto-connect policy");
+            self.evaluate_auto_connect_policy().await;
+        }
+    }
+
+    /// Reconnects if a relay that the tunnel uses has been removed from the relay list or is no
+    /// longer active, which happens when relays are taken down for maintenance.
+    fn reconnect_if_relay_unavailable(&mut self, relay_list: &RelayList) {
+        if let RelaySettings::CustomTunnelEndpoint(_) = self.settings.get_relay_settings() {
+            return;
+        }
+        let endpoint = match &self.tunnel_state {
+            TunnelState::Connected { endpoint, .. } | TunnelState::Connecting { endpoint, .. } => {
+                endpoint
+            }
+            _ => return,
+        };
+
+        let is_active_relay = |ip: IpAddr| {
+            relay_list
+                .countries
+                .iter()
+                .flat_map(|country| &country.cities)
+                .flat_map(|city| &city.relays)
+                .any(|relay| {
+                    relay.active
+                        && (IpAddr::V4(relay.ipv4_addr_in) == ip
+                            || relay.ipv6_addr_in.map(IpAddr::V6) == Some(ip))
+                })
+        };
+        let relay_ips: Vec<IpAddr> = std::iter::once(endpoint.endpoint.address.ip())
+            .chain(endpoint.entry_endpoint.map(|entry| entry.address.ip()))
+            .collect();
+
+        for ip in relay_ips {
+            if !is_active_relay(ip) {
+                log::info!("Relay {} is no longer available", ip);
+                self.reconnect_tunnel(ReconnectReason::RelayMaintenance);
+                return;
+            }
+        }
+    }
+
     #[cfg(windows)]
     async fn handle_new_excluded_paths(
         &mut self,
@@ -1183,15 +1891,19 @@ where
         new_target_state: TargetState,
     ) {
         if self.state.is_running() {
-            let state_change_initated = self.set_target_state(new_target_state).await;
+            self.auto_connected = false;
+            let state_change_initated = self
+                .set_target_state(new_target_state, DisconnectReason::UserRequested)
+                .await;
             Self::oneshot_send(tx, state_change_initated, "state change initiated");
         } else {
             log::warn!("Ignoring target state change request due to shutdown");
         }
     }
 
-    fn on_reconnect(&mut self, tx: oneshot::Sender<bool>) {
+    fn on_reconnect(&mut self, tx: oneshot::Sender<bool>, reason: ReconnectReason) {
         if *self.target_state == TargetState::Secured || self.tunnel_state.is_in_error_state() {
+            self.reconnect_reason = Some(reason);
             self.connect_tunnel();
             Self::oneshot_send(tx, true, "reconnect issued");
         } else {
@@ -1212,11 +1924,27 @@ where
     async fn on_get_current_location(&mut self, tx: oneshot::Sender<Option<GeoIpLocation>>) {
         use self::TunnelState::*;
 
+        if !self.settings.fetch_location {
+            // Only the location of the relay is known without a GeoIP lookup
+            let location = match &self.tunnel_state {
+                Connecting { location, .. } | Connected { location, .. } => location.clone(),
+                Disconnecting(..) => self.parameters_generator.get_last_location().await,
+                Disconnected { .. } | Error(_) => None,
+            };
+            Self::oneshot_send(tx, location, "current location");
+            return;
+        }
+
         match &self.tunnel_state {
-            Disconnected => {
+            Disconnected { .. } => {
                 let location = self.get_geo_location().await;
                 tokio::spawn(async {
-                    Self::oneshot_send(tx, location.await.ok(), "current location");
+                    Self::oneshot_send_cancellable(
+                        tx,
+                        async { location.await.ok() },
+                        "current location",
+                    )
+                    .await;
                 });
             }
             Connecting { location, .. } => {
@@ -1231,16 +1959,17 @@ where
                 let relay_location = location.clone();
                 let location_future = self.get_geo_location().await;
                 tokio::spawn(async {
-                    let location = location_future.await;
-                    Self::oneshot_send(
-                        tx,
-                        location.ok().map(|fetched_location| GeoIpLocation {
-                            ipv4: fetched_location.ipv4,
-                            ipv6: fetched_location.ipv6,
-                            ..relay_location.unwrap_or(fetched_location)
-                        }),
-                        "current location",
-                    );
+                    let location = async {
+                        location_future
+                            .await
+                            .ok()
+                            .map(|fetched_location| GeoIpLocation {
+                                ipv4: fetched_location.ipv4,
+                                ipv6: fetched_location.ipv6,
+                                ..relay_location.unwrap_or(fetched_location)
+                            })
+                    };
+                    Self::oneshot_send_cancellable(tx, location, "current location").await;
                 });
             }
             Error(_) => {
@@ -1250,8 +1979,64 @@ where
         }
     }
 
+    fn on_test_for_leaks(&self, tx: ResponseTx<geoip::LeakTestResult, Error>) {
+        if !self.tunnel_state.is_connected() {
+            Self::oneshot_send(
+                tx,
+                Err(Error::TunnelNotConnected),
+                "test_for_leaks response",
+            );
+            return;
+        }
+        let rest_service = self.geoip_service.clone();
+        tokio::spawn(async move {
+            Self::oneshot_send_cancellable(
+                tx,
+                async { Ok(geoip::test_for_leaks(rest_service).await) },
+                "test_for_leaks response",
+            )
+            .await;
+        });
+    }
+
+    fn on_run_speed_test(&self, tx: ResponseTx<speed_test::SpeedTestResult, Error>) {
+        let rest_service = self.geoip_service.clone();
+        tokio::spawn(async move {
+            Self::oneshot_send_cancellable(
+                tx,
+                async {
+                    speed_test::run(rest_service)
+                        .await
+                        .map_err(Error::RestError)
+                },
+                "run_speed_test response",
+            )
+            .await;
+        });
+    }
+
+    fn on_send_problem_report(
+        &self,
+        tx: ResponseTx<(), Error>,
+        email: String,
+        message: String,
+        report: String,
+    ) {
+        let proxy = mullvad_api::ProblemReportProxy::new(self.api_handle.clone());
+        tokio::spawn(async move {
+            let result = async move {
+                let metadata = mullvad_problem_report::report_metadata(&report);
+                proxy
+                    .problem_report(&email, &message, &report, &metadata)
+                    .await
+                    .map_err(Error::RestError)
+            };
+            Self::oneshot_send_cancellable(tx, result, "send_problem_report response").await;
+        });
+    }
+
     async fn get_geo_location(&mut self) -> impl Future<Output = Result<GeoIpLocation, ()>> {
-        let rest_service = self.api_runtime.rest_handle().await;
+        let rest_service = self.geoip_service.clone();
         let use_ipv6 = self.settings.tunnel_options.generic.enable_ipv6;
         async move {
             geoip::send_location_request(rest_service, use_ipv6)
@@ -1277,7 +2062,7 @@ where
                     .await
                     .map_err(Error::RestError)?;
                 account_manager
-                    .login(token.clone())
+                    .login(token.clone(), LoginMode::Remember)
                     .await
                     .map_err(|error| {
                         log::error!(
@@ -1299,13 +2084,14 @@ where
     ) {
         let account = self.account_manager.account_service.clone();
         tokio::spawn(async move {
-            let result = account.check_expiry(account_token).await;
-            Self::oneshot_send(
-                tx,
-                result.map(|expiry| AccountData { expiry }),
-                "account data",
-            );
-        });
+            let result = async {
+                account
+                    .check_expiry(account_token)
+                    .await
+                    .map(|expiry| AccountData { expiry })
+            };
+            Self::oneshot_send_cancellable(tx, result, "account data").await;
+        });
     }
 
     async fn on_get_www_auth_token(&mut self, tx: ResponseTx<String, Error>) {
@@ -1315,11 +2101,12 @@ where
                 .account_service
                 .get_www_auth_token(device.account_token);
             tokio::spawn(async {
-                Self::oneshot_send(
+                Self::oneshot_send_cancellable(
                     tx,
-                    future.await.map_err(Error::RestError),
+                    async { future.await.map_err(Error::RestError) },
                     "get_www_auth_token response",
-                );
+                )
+                .await;
             });
         } else {
             Self::oneshot_send(
@@ -1348,22 +2135,52 @@ where
         });
     }
 
-    fn on_get_relay_locations(&mut self, tx: oneshot::Sender<RelayList>) {
+    fn on_get_relay_locations(&mut self, tx: oneshot::Sender<Arc<RelayList>>) {
         Self::oneshot_send(tx, self.relay_selector.get_locations(), "relay locations");
     }
 
+    fn on_search_locations(
+        &mut self,
+        tx: oneshot::Sender<Vec<LocationSearchResult>>,
+        query: String,
+        locale: String,
+    ) {
+        let relay_list = self.relay_selector.get_locations();
+        let location_search = self.location_search.clone();
+        tokio::task::spawn_blocking(move || {
+            let results = location_search.search(&relay_list, &query, &locale);
+            Self::oneshot_send(tx, results, "location search results");
+        });
+    }
+
+    fn on_explain_relay_selection(&mut self, tx: oneshot::Sender<Option<RelaySelectionHint>>) {
+        Self::oneshot_send(
+            tx,
+            self.relay_selector.explain_selection(),
+            "relay selection hint",
+        );
+    }
+
     async fn on_update_relay_locations(&mut self) {
         self.relay_list_updater.update().await;
     }
 
-    fn on_login_account(&mut self, tx: ResponseTx<(), Error>, account_token: String) {
+    fn on_login_account(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        account_token: String,
+        mode: LoginMode,
+    ) {
         let account_manager = self.account_manager.clone();
         tokio::spawn(async move {
             let result = async {
-                account_manager.login(account_token).await.map_err(|error| {
-                    log::error!("{}", error.display_chain_with_msg("Login failed"));
-                    Error::LoginError(error)
-                })
+                account_manager
+                    .login(account_token, mode)
+                    .await
+                    .map_err(|error| {
+                        log::error!("{}", error.display_chain_with_msg("Login failed"));
+                        Error::LoginError(error)
+                    })
             };
             Self::oneshot_send(tx, result.await, "login_account response");
         });
@@ -1397,6 +2214,14 @@ where
         });
     }
 
+    fn on_new_foreground_client(&mut self, tx: oneshot::Sender<device::ForegroundClient>) {
+        Self::oneshot_send(
+            tx,
+            self.account_poller.foreground_client(),
+            "new_foreground_client response",
+        );
+    }
+
     async fn on_update_device(&mut self, tx: ResponseTx<(), Error>) {
         let account_manager = self.account_manager.clone();
         tokio::spawn(async move {
@@ -1415,14 +2240,17 @@ where
     async fn on_list_devices(&self, tx: ResponseTx<Vec<Device>, Error>, token: AccountToken) {
         let service = self.account_manager.device_service.clone();
         tokio::spawn(async move {
-            Self::oneshot_send(
+            Self::oneshot_send_cancellable(
                 tx,
-                service
-                    .list_devices(token)
-                    .await
-                    .map_err(Error::ListDevicesError),
+                async {
+                    service
+                        .list_devices(token)
+                        .await
+                        .map_err(Error::ListDevicesError)
+                },
                 "list_devices response",
-            );
+            )
+            .await;
         });
     }
 
@@ -1472,8 +2300,35 @@ where
         Self::oneshot_send(tx, result, "clear_account_history response");
     }
 
+    fn on_get_frontend_values(
+        &mut self,
+        tx: oneshot::Sender<BTreeMap<String, String>>,
+        namespace: String,
+    ) {
+        Self::oneshot_send(
+            tx,
+            self.frontend_store.get(&namespace),
+            "get_frontend_values response",
+        );
+    }
+
+    async fn on_set_frontend_value(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        namespace: String,
+        key: String,
+        value: Option<String>,
+    ) {
+        let result = self
+            .frontend_store
+            .set(namespace, key, value)
+            .await
+            .map_err(Error::FrontendStore);
+        Self::oneshot_send(tx, result, "set_frontend_value response");
+    }
+
     async fn on_get_version_info(&mut self, tx: oneshot::Sender<Option<AppVersionInfo>>) {
-        if self.app_version_info.is_none() {
+        if self.app_version_info.is_none() && self.settings.check_for_updates {
             log::debug!("No version cache found. Fetching new info");
             let mut handle = self.version_updater_handle.clone();
             tokio::spawn(async move {
@@ -1528,6 +2383,14 @@ where
             last_error = Err(Error::FactoryResetError("Failed to clear account history"));
         }
 
+        if let Err(error) = self.frontend_store.clear().await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to clear frontend store")
+            );
+            last_error = Err(Error::FactoryResetError("Failed to clear frontend store"));
+        }
+
         if let Err(e) = self.settings.reset().await {
             log::error!("Failed to reset settings: {}", e);
             last_error = Err(Error::FactoryResetError("Failed to reset settings"));
@@ -1590,6 +2453,62 @@ where
         Self::oneshot_send(tx, result, "clear_split_tunnel_processes response");
     }
 
+    #[cfg(target_os = "linux")]
+    async fn on_set_split_tunnel_mode(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        mode: SplitTunnelMode,
+    ) {
+        let save_result = self.settings.set_split_tunnel_mode(mode).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_split_tunnel_mode response");
+                if settings_changed {
+                    if mode == SplitTunnelMode::Include {
+                        log_split_tunnel_include_mode_warning();
+                    }
+                    self.exclude_pids.set_mode(mode);
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::SetSplitTunnelMode(mode));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_split_tunnel_mode response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_shared_interface(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        interface: Option<String>,
+    ) {
+        let save_result = self.settings.set_shared_interface(interface.clone()).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_shared_interface response");
+                if settings_changed {
+                    match &interface {
+                        Some(interface) => {
+                            log::info!("Sharing the tunnel with devices on {}", interface)
+                        }
+                        None => log::info!("Stopped sharing the tunnel"),
+                    }
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::SetSharedInterface(interface));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_shared_interface response");
+            }
+        }
+    }
+
     /// Update the split app paths in both the settings and tunnel
     #[cfg(windows)]
     async fn set_split_tunnel_paths(
@@ -1740,13 +2659,13 @@ where
                 Self::oneshot_send(tx, Ok(()), "use_wireguard_nt response");
                 if settings_changed {
                     self.parameters_generator
-                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .set_tunnel_options(&self.tunnel_options())
                         .await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                     if let Some(TunnelType::Wireguard) = self.get_target_tunnel_type() {
                         log::info!("Initiating tunnel restart");
-                        self.reconnect_tunnel();
+                        self.reconnect_tunnel(ReconnectReason::SettingsChanged);
                     }
                 }
             }
@@ -1779,10 +2698,9 @@ where
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.relay_selector
-                        .set_config(new_selector_config(&self.settings, &self.app_version_info));
+                    self.relay_selector.set_config(self.selector_config());
                     log::info!("Initiating tunnel restart because the relay settings changed");
-                    self.reconnect_tunnel();
+                    self.reconnect_tunnel(ReconnectReason::SettingsChanged);
                 }
             }
             Err(e) => {
@@ -1792,6 +2710,89 @@ where
         }
     }
 
+    async fn on_apply_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        update: SettingsUpdate,
+    ) {
+        let changes = match self.settings.apply_update(update).await {
+            Ok(changes) => {
+                Self::oneshot_send(tx, Ok(()), "apply_settings response");
+                changes
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "apply_settings response");
+                return;
+            }
+        };
+        if !changes.any() {
+            return;
+        }
+
+        let settings = self.settings.to_settings();
+        if changes.tunnel_options() {
+            self.parameters_generator
+                .set_tunnel_options(&self.tunnel_options())
+                .await;
+        }
+        if changes.relay_settings
+            || changes.bridge_state
+            || changes.obfuscation_settings
+            || changes.quantum_resistant
+        {
+            self.relay_selector.set_config(self.selector_config());
+        }
+        if changes.allow_lan {
+            self.send_tunnel_command(TunnelCommand::AllowLan(settings.allow_lan));
+        }
+        if changes.block_when_disconnected {
+            self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+                settings.block_when_disconnected,
+            ));
+        }
+        if changes.dns_options {
+            self.send_tunnel_command(TunnelCommand::Dns(dns::addresses_from_options(
+                &settings.tunnel_options.dns_options,
+            )));
+        }
+        if changes.connection_timeouts {
+            self.send_tunnel_command(TunnelCommand::ConnectionTimeouts(
+                settings.tunnel_options.connection_timeouts,
+            ));
+        }
+        if changes.show_beta_releases {
+            let mut handle = self.version_updater_handle.clone();
+            handle
+                .set_show_beta_releases(settings.show_beta_releases)
+                .await;
+        }
+        if changes.check_for_updates {
+            let mut handle = self.version_updater_handle.clone();
+            handle
+                .set_check_for_updates(settings.check_for_updates)
+                .await;
+        }
+        if changes.background_work {
+            self.apply_background_work_policy();
+        }
+        self.event_listener.notify_settings(settings);
+
+        let target_tunnel_type = self.get_target_tunnel_type();
+        let should_reconnect = changes.relay_settings
+            || changes.bridge_state
+            || changes.obfuscation_settings
+            || changes.enable_ipv6
+            || (changes.openvpn_mssfix && target_tunnel_type == Some(TunnelType::OpenVpn))
+            || (changes.quantum_resistant && target_tunnel_type == Some(TunnelType::Wireguard))
+            || (changes.wireguard_mtu
+                && self.get_connected_tunnel_type() == Some(TunnelType::Wireguard));
+        if should_reconnect {
+            log::info!("Initiating tunnel restart because the settings changed");
+            self.reconnect_tunnel(ReconnectReason::SettingsChanged);
+        }
+    }
+
     async fn on_set_allow_lan(&mut self, tx: ResponseTx<(), settings::Error>, allow_lan: bool) {
         let save_result = self.settings.set_allow_lan(allow_lan).await;
         match save_result {
@@ -1810,6 +2811,78 @@ where
         }
     }
 
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn on_set_allowed_multicast_groups(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        groups: Vec<IpAddr>,
+    ) {
+        let save_result = self
+            .settings
+            .set_allowed_multicast_groups(groups.clone())
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_allowed_multicast_groups response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::AllowMulticastGroups(groups));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allowed_multicast_groups response");
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn on_set_allowed_link_config_traffic(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        allowed: AllowedLinkConfigTraffic,
+    ) {
+        let save_result = self.settings.set_allowed_link_config_traffic(allowed).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_allowed_link_config_traffic response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::AllowLinkConfigTraffic(allowed));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allowed_link_config_traffic response");
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn on_set_allowed_lan_hosts(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        hosts: Vec<IpAddr>,
+    ) {
+        let save_result = self.settings.set_allowed_lan_hosts(hosts.clone()).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_allowed_lan_hosts response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::AllowLanHosts(hosts));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allowed_lan_hosts response");
+            }
+        }
+    }
+
     async fn on_set_show_beta_releases(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -1833,6 +2906,209 @@ where
         }
     }
 
+    async fn on_set_system_notifications(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_system_notifications(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_system_notifications response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.system_notifier.set_enabled(enabled);
+                    if enabled {
+                        self.schedule_expiry_checks();
+                    } else {
+                        self.unschedule_expiry_checks();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_system_notifications response");
+            }
+        }
+    }
+
+    async fn on_set_management_access_policy(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        policy: ManagementAccessPolicy,
+    ) {
+        let save_result = self.settings.set_management_access_policy(policy).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_management_access_policy response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_management_access_policy response");
+            }
+        }
+    }
+
+    async fn on_add_api_access_method(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        method: AccessMethod,
+    ) {
+        match self.settings.add_api_access_method(method).await {
+            Ok(()) => {
+                Self::oneshot_send(tx, Ok(()), "add_api_access_method response");
+                self.on_api_access_methods_changed();
+            }
+            Err(e) => {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Unable to add access method")
+                );
+                Self::oneshot_send(tx, Err(e), "add_api_access_method response");
+            }
+        }
+    }
+
+    async fn on_update_api_access_method(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        name: String,
+        method: AccessMethod,
+    ) {
+        let old_proxy = self
+            .settings
+            .api_access_methods
+            .iter()
+            .find(|existing| existing.name == name)
+            .map(|existing| existing.proxy.clone());
+        let new_name = method.name.clone();
+        match self.settings.update_api_access_method(&name, method).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "update_api_access_method response");
+                if settings_changed {
+                    let updated = self
+                        .settings
+                        .api_access_methods
+                        .iter()
+                        .find(|method| method.name == new_name);
+                    // The old proxy may be cached as the last working way of reaching the API
+                    let still_used = updated
+                        .map(|method| method.enabled && Some(&method.proxy) == old_proxy.as_ref());
+                    if still_used != Some(true) {
+                        self.invalidate_api_endpoint_cache().await;
+                    }
+                    self.on_api_access_methods_changed();
+                }
+            }
+            Err(e) => {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Unable to update access method")
+                );
+                Self::oneshot_send(tx, Err(e), "update_api_access_method response");
+            }
+        }
+    }
+
+    async fn on_remove_api_access_method(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        name: String,
+    ) {
+        match self.settings.remove_api_access_method(&name).await {
+            Ok(()) => {
+                Self::oneshot_send(tx, Ok(()), "remove_api_access_method response");
+                self.invalidate_api_endpoint_cache().await;
+                self.on_api_access_methods_changed();
+            }
+            Err(e) => {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Unable to remove access method")
+                );
+                Self::oneshot_send(tx, Err(e), "remove_api_access_method response");
+            }
+        }
+    }
+
+    /// Stops the API from being reached through a proxy that was only cached because it worked
+    /// before.
+    async fn invalidate_api_endpoint_cache(&self) {
+        mullvad_api::proxy::ApiConnectionMode::try_delete_cache(&self.cache_dir).await;
+    }
+
+    fn on_api_access_methods_changed(&mut self) {
+        self.access_methods.set(&self.settings.api_access_methods);
+        self.event_listener
+            .notify_settings(self.settings.to_settings());
+    }
+
+    /// Sends a request to the API through the given access method only. The firewall allows the
+    /// proxy for the duration of the request, alongside the endpoint currently used for the API.
+    fn on_get_api_connection_pools(
+        &self,
+        tx: ResponseTx<Vec<mullvad_api::pool::PoolMetrics>, Error>,
+    ) {
+        let service = self.api_handle.service();
+        tokio::spawn(async move {
+            let result = service.pool_metrics().await.map_err(Error::RestError);
+            Self::oneshot_send(tx, result, "get_api_connection_pools response");
+        });
+    }
+
+    async fn on_test_api_access_method(
+        &mut self,
+        tx: ResponseTx<bool, settings::Error>,
+        name: String,
+    ) {
+        let method = self
+            .settings
+            .api_access_methods
+            .iter()
+            .find(|method| method.name == name);
+        let method = match method {
+            Some(method) => method,
+            None => {
+                let error = settings::Error::AccessMethod(access_method::Error::NotFound(name));
+                Self::oneshot_send(tx, Err(error), "test_api_access_method response");
+                return;
+            }
+        };
+        if let Err(error) = settings::check_access_method_supported(method) {
+            Self::oneshot_send(tx, Err(error), "test_api_access_method response");
+            return;
+        }
+
+        let mode =
+            mullvad_api::proxy::ApiConnectionMode::Proxied(api::access_method_proxy(&method.proxy));
+        let endpoint = mode.get_endpoint();
+        let handle = self.api_runtime.fixed_mode_rest_handle(mode).await;
+        let endpoint_updater = self.api_endpoint_updater.clone();
+        tokio::spawn(async move {
+            let request = mullvad_api::ApiProxy::new(handle).get_api_addrs();
+            let result = match endpoint {
+                Some(endpoint) => {
+                    endpoint_updater
+                        .with_temporary_endpoint(endpoint, request)
+                        .await
+                }
+                None => request.await,
+            };
+            if let Err(error) = &result {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg(&format!("Access method \"{}\" failed", name))
+                );
+            }
+            Self::oneshot_send(tx, Ok(result.is_ok()), "test_api_access_method response");
+        });
+    }
+
     async fn on_set_block_when_disconnected(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -1881,6 +3157,208 @@ where
         }
     }
 
+    async fn on_set_auto_connect_policy(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        policy: AutoConnectPolicy,
+    ) {
+        let save_result = self.settings.set_auto_connect_policy(policy).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set auto-connect policy response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_background_work_policy();
+                    self.evaluate_auto_connect_policy().await;
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set auto-connect policy response");
+            }
+        }
+    }
+
+    async fn on_set_current_network(&mut self, tx: oneshot::Sender<()>, network: NetworkInfo) {
+        Self::oneshot_send(tx, (), "set current network response");
+        if self.network_detected {
+            log::debug!("Ignoring network reported by frontend: {}", network);
+            return;
+        }
+        self.set_current_network(network).await;
+    }
+
+    async fn handle_network_detected(&mut self, network: NetworkInfo) {
+        self.network_detected = true;
+        self.set_current_network(network).await;
+    }
+
+    async fn set_current_network(&mut self, network: NetworkInfo) {
+        if network != self.current_network {
+            log::debug!("Current network: {}", network);
+            self.current_network = network;
+            self.apply_network_profile().await;
+            self.apply_background_work_policy();
+            self.evaluate_auto_connect_policy().await;
+        }
+    }
+
+    async fn on_set_network_profiles(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        profiles: Vec<NetworkProfile>,
+    ) {
+        match self.settings.set_network_profiles(profiles).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_network_profiles response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_network_profile().await;
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_network_profiles response");
+            }
+        }
+    }
+
+    /// Applies the network profile that matches the current network, and reconnects if it
+    /// changes any connection parameters.
+    async fn apply_network_profile(&mut self) {
+        // Profiles are validated when they are set, but the settings file may have been edited
+        let profile = NetworkProfile::find(&self.settings.network_profiles, &self.current_network)
+            .filter(|profile| match profile.validate() {
+                Ok(()) => true,
+                Err(error) => {
+                    log::error!(
+                        "Ignoring invalid profile for {}: {}",
+                        profile.network,
+                        error
+                    );
+                    false
+                }
+            })
+            .cloned();
+        if profile == self.network_profile {
+            return;
+        }
+        match &profile {
+            Some(profile) => log::info!("Applying network profile for {}", profile),
+            None => log::info!("No network profile applies to the current network"),
+        }
+        self.network_profile = profile;
+
+        self.parameters_generator
+            .set_tunnel_options(&self.tunnel_options())
+            .await;
+        self.relay_selector.set_config(self.selector_config());
+        self.reconnect_tunnel(ReconnectReason::SettingsChanged);
+    }
+
+    fn on_get_network_info(&mut self, tx: oneshot::Sender<CurrentNetwork>) {
+        // The BSSID identifies a physical location, so it is only ever exposed as a hash.
+        let (network, bssid_hash) = match self.current_network.clone() {
+            NetworkInfo::Wifi { ssid, bssid } => (
+                NetworkInfo::Wifi { ssid, bssid: None },
+                bssid.as_deref().map(hash_bssid),
+            ),
+            network => (network, None),
+        };
+        let default_route = self.tunnel_state_machine_handle.default_route().clone();
+
+        tokio::spawn(async move {
+            let (ipv4_route, ipv6_route) = default_route.default_routes().await;
+            Self::oneshot_send(
+                tx,
+                CurrentNetwork {
+                    network,
+                    bssid_hash,
+                    ipv4_route,
+                    ipv6_route,
+                },
+                "get_network_info response",
+            );
+        });
+    }
+
+    fn on_check_dns_backend(&mut self, tx: oneshot::Sender<DnsBackendHealth>) {
+        let dns_check_path = self.dns_check_path.clone();
+        tokio::spawn(async move {
+            let health = Self::check_dns_backend(dns_check_path).await;
+            Self::oneshot_send(tx, health, "check_dns_backend response");
+        });
+    }
+
+    /// Checks the DNS backend and records the result in the log directory.
+    async fn check_dns_backend(dns_check_path: Option<PathBuf>) -> DnsBackendHealth {
+        let health = tokio::task::spawn_blocking(move || {
+            let health = talpid_core::dns::check_backend();
+            if let Some(path) = dns_check_path {
+                if let Err(error) = std::fs::write(&path, format!("{}\n", health)) {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to save DNS backend check")
+                    );
+                }
+            }
+            health
+        })
+        .await
+        .unwrap_or_else(|error| DnsBackendHealth {
+            backend: "unknown".to_owned(),
+            error: Some(format!("The check did not complete: {}", error)),
+            resolvers: vec![],
+            conflicting_managers: vec![],
+        });
+
+        if !health.is_healthy() {
+            log::warn!("{}", health);
+        }
+        health
+    }
+
+    /// Holds back scheduled downloads, such as relay list and version checks, unless the
+    /// background work policy allows them on the current network.
+    fn apply_background_work_policy(&self) {
+        let allowed = self
+            .settings
+            .background_work
+            .allows(&self.settings.auto_connect_policy, &self.current_network);
+        self.api_handle.availability.set_metered(!allowed);
+    }
+
+    /// Evaluates the auto-connect policy on the current network. The tunnel is only disconnected
+    /// if it was secured by the policy in the first place, so that user-initiated connections are
+    /// left alone.
+    async fn evaluate_auto_connect_policy(&mut self) {
+        let decision = self
+            .settings
+            .auto_connect_policy
+            .evaluate(self.settings.auto_connect, &self.current_network);
+        log::info!("Auto-connect: {}", decision);
+
+        if self.state.is_running() {
+            if decision.connect {
+                if *self.target_state == TargetState::Unsecured {
+                    self.set_target_state(
+                        TargetState::Secured,
+                        DisconnectReason::AutoConnectPolicy,
+                    )
+                    .await;
+                    self.auto_connected = true;
+                }
+            } else if self.auto_connected && *self.target_state == TargetState::Secured {
+                self.set_target_state(TargetState::Unsecured, DisconnectReason::AutoConnectPolicy)
+                    .await;
+            }
+        }
+
+        self.event_listener.notify_auto_connect_decision(decision);
+    }
+
     async fn on_set_openvpn_mssfix(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -1892,7 +3370,7 @@ where
                 Self::oneshot_send(tx, Ok(()), "set_openvpn_mssfix response");
                 if settings_changed {
                     self.parameters_generator
-                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .set_tunnel_options(&self.tunnel_options())
                         .await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
@@ -1900,7 +3378,7 @@ where
                         log::info!(
                             "Initiating tunnel restart because the OpenVPN mssfix setting changed"
                         );
-                        self.reconnect_tunnel();
+                        self.reconnect_tunnel(ReconnectReason::SettingsChanged);
                     }
                 }
             }
@@ -1921,12 +3399,11 @@ where
                 if settings_changes {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.relay_selector
-                        .set_config(new_selector_config(&self.settings, &self.app_version_info));
+                    self.relay_selector.set_config(self.selector_config());
                     if let Err(error) = self.api_handle.service().next_api_endpoint().await {
                         log::error!("Failed to rotate API endpoint: {}", error);
                     }
-                    self.reconnect_tunnel();
+                    self.reconnect_tunnel(ReconnectReason::SettingsChanged);
                 };
                 Self::oneshot_send(tx, Ok(()), "set_bridge_settings");
             }
@@ -1951,9 +3428,8 @@ where
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.relay_selector
-                        .set_config(new_selector_config(&self.settings, &self.app_version_info));
-                    self.reconnect_tunnel();
+                    self.relay_selector.set_config(self.selector_config());
+                    self.reconnect_tunnel(ReconnectReason::SettingsChanged);
                 }
                 Self::oneshot_send(tx, Ok(()), "set_obfuscation_settings");
             }
@@ -1967,6 +3443,30 @@ where
         }
     }
 
+    async fn on_set_auto_port_order(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        port_order: Vec<PortSelectionStep>,
+    ) {
+        match self.settings.set_auto_port_order(port_order).await {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.relay_selector.set_config(self.selector_config());
+                }
+                Self::oneshot_send(tx, Ok(()), "set_auto_port_order");
+            }
+            Err(err) => {
+                log::error!(
+                    "{}",
+                    err.display_chain_with_msg("Failed to set automatic port order")
+                );
+                Self::oneshot_send(tx, Err(err), "set_auto_port_order");
+            }
+        }
+    }
+
     async fn on_set_bridge_state(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -1977,10 +3477,9 @@ where
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.relay_selector
-                        .set_config(new_selector_config(&self.settings, &self.app_version_info));
+                    self.relay_selector.set_config(self.selector_config());
                     log::info!("Initiating tunnel restart because bridge state changed");
-                    self.reconnect_tunnel();
+                    self.reconnect_tunnel(ReconnectReason::SettingsChanged);
                 }
                 Ok(())
             }
@@ -2002,12 +3501,12 @@ where
                 Self::oneshot_send(tx, Ok(()), "set_enable_ipv6 response");
                 if settings_changed {
                     self.parameters_generator
-                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .set_tunnel_options(&self.tunnel_options())
                         .await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                     log::info!("Initiating tunnel restart because the enable IPv6 setting changed");
-                    self.reconnect_tunnel();
+                    self.reconnect_tunnel(ReconnectReason::SettingsChanged);
                 }
             }
             Err(e) => {
@@ -2031,13 +3530,14 @@ where
                 Self::oneshot_send(tx, Ok(()), "set_quantum_resistant_tunnel response");
                 if settings_changed {
                     self.parameters_generator
-                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .set_tunnel_options(&self.tunnel_options())
                         .await;
+                    self.relay_selector.set_config(self.selector_config());
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                     if self.get_target_tunnel_type() == Some(TunnelType::Wireguard) {
                         log::info!("Reconnecting because the PQ safety setting changed");
-                        self.reconnect_tunnel();
+                        self.reconnect_tunnel(ReconnectReason::SettingsChanged);
                     }
                 }
             }
@@ -2062,7 +3562,7 @@ where
                     let resolvers =
                         dns::addresses_from_options(&settings.tunnel_options.dns_options);
                     self.parameters_generator
-                        .set_tunnel_options(&settings.tunnel_options)
+                        .set_tunnel_options(&self.tunnel_options())
                         .await;
                     self.event_listener.notify_settings(settings);
                     self.send_tunnel_command(TunnelCommand::Dns(resolvers));
@@ -2086,7 +3586,7 @@ where
                 Self::oneshot_send(tx, Ok(()), "set_wireguard_mtu response");
                 if settings_changed {
                     self.parameters_generator
-                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .set_tunnel_options(&self.tunnel_options())
                         .await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
@@ -2094,7 +3594,7 @@ where
                         log::info!(
                             "Initiating tunnel restart because the WireGuard MTU setting changed"
                         );
-                        self.reconnect_tunnel();
+                        self.reconnect_tunnel(ReconnectReason::SettingsChanged);
                     }
                 }
             }
@@ -2105,6 +3605,73 @@ where
         }
     }
 
+    async fn on_set_wireguard_address_family(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        address_family: Option<IpVersion>,
+    ) {
+        let save_result = self
+            .settings
+            .set_wireguard_address_family(address_family)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_address_family response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.tunnel_options())
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if self.get_target_tunnel_type() == Some(TunnelType::Wireguard) {
+                        log::info!(
+                            "Initiating tunnel restart because the WireGuard address family changed"
+                        );
+                        self.reconnect_tunnel(ReconnectReason::SettingsChanged);
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_address_family response");
+            }
+        }
+    }
+
+    async fn on_set_wireguard_dns_options(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        ip_version: Option<IpVersion>,
+        prefer_ipv6: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_wireguard_dns_options(ip_version, prefer_ipv6)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_dns_options response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.tunnel_options())
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if self.get_target_tunnel_type() == Some(TunnelType::Wireguard) {
+                        log::info!(
+                            "Initiating tunnel restart because the WireGuard DNS options changed"
+                        );
+                        self.reconnect_tunnel(ReconnectReason::SettingsChanged);
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_dns_options response");
+            }
+        }
+    }
+
     async fn on_set_wireguard_rotation_interval(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2129,7 +3696,7 @@ where
                         );
                     }
                     self.parameters_generator
-                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .set_tunnel_options(&self.tunnel_options())
                         .await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
@@ -2174,18 +3741,53 @@ where
         }
     }
 
+    /// Drives `future` to completion and sends the result on `tx`, unless the receiver is dropped
+    /// first. That happens when the RPC client disconnects or the call times out, in which case
+    /// `future` is dropped, aborting the operation. This must only be used for operations that
+    /// are safe to abort at any point.
+    async fn oneshot_send_cancellable<T>(
+        mut tx: oneshot::Sender<T>,
+        future: impl Future<Output = T>,
+        msg: &'static str,
+    ) {
+        let result =
+            match futures::future::select(Box::pin(future), Box::pin(tx.cancellation())).await {
+                Either::Left((result, _)) => result,
+                Either::Right(_) => {
+                    log::debug!("Aborting {} since the receiver was dropped", msg);
+                    return;
+                }
+            };
+        Self::oneshot_send(tx, result, msg);
+    }
+
     fn trigger_shutdown_event(&mut self, user_init_shutdown: bool) {
-        // Block all traffic before shutting down to ensure that no traffic can leak on boot or
-        // shutdown.
-        if !user_init_shutdown
-            && (*self.target_state == TargetState::Secured || self.settings.auto_connect)
-        {
+        // Keep blocking all traffic after shutting down to ensure that no traffic can leak on
+        // boot or shutdown.
+        let keep_firewall = !user_init_shutdown
+            && (*self.target_state == TargetState::Secured || self.settings.auto_connect);
+        if keep_firewall {
             log::debug!("Blocking firewall during shutdown since system is going down");
-            self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(true));
+        }
+        self.reset_firewall_on_exit = !keep_firewall && !self.settings.block_when_disconnected;
+
+        // Let the next instance reconnect to the same relay, which shortens the time that
+        // traffic is blocked during upgrades.
+        if self.tunnel_state.is_connected() {
+            let parameters_generator = self.parameters_generator.clone();
+            let relay_settings = self.settings.get_relay_settings();
+            let obfuscation_settings = self.settings.obfuscation_settings.clone();
+            self.shutdown_tasks.push(Box::pin(async move {
+                parameters_generator
+                    .save_warm_tunnel(relay_settings, obfuscation_settings)
+                    .await;
+            }));
         }
 
+        // Commands from frontends are dropped from here on
+        log::debug!("Shutdown: no longer handling commands");
         self.state.shutdown(&self.tunnel_state);
-        self.disconnect_tunnel();
+        self.send_tunnel_command(TunnelCommand::Shutdown { keep_firewall });
     }
 
     fn on_prepare_restart(&mut self) {
@@ -2214,16 +3816,20 @@ where
 
     /// Set the target state of the client. If it changed trigger the operations needed to
     /// progress towards that state.
-    /// Returns a bool representing whether or not a state change was initiated.
-    async fn set_target_state(&mut self, new_state: TargetState) -> bool {
+    /// Returns a bool representing whether or not a state change was initiated. `reason` is
+    /// reported by the disconnected state if the tunnel is disconnected.
+    async fn set_target_state(&mut self, new_state: TargetState, reason: DisconnectReason) -> bool {
         if new_state != *self.target_state || self.tunnel_state.is_in_error_state() {
             log::debug!("Target state {:?} => {:?}", *self.target_state, new_state);
 
             self.target_state.set(new_state).await;
 
             match *self.target_state {
-                TargetState::Secured => self.connect_tunnel(),
-                TargetState::Unsecured => self.disconnect_tunnel(),
+                TargetState::Secured => {
+                    self.detect_conflicting_software();
+                    self.connect_tunnel()
+                }
+                TargetState::Unsecured => self.disconnect_tunnel(reason),
             }
             true
         } else {
@@ -2231,18 +3837,78 @@ where
         }
     }
 
+    /// Looks for other VPN clients in the background and warns about any that are found. This does
+    /// not prevent the tunnel from connecting.
+    fn detect_conflicting_software(&self) {
+        let event_listener = self.event_listener.clone();
+        tokio::spawn(async move {
+            let result =
+                tokio::task::spawn_blocking(talpid_platform_metadata::conflicting_software).await;
+            match result {
+                Ok(conflicts) if !conflicts.is_empty() => {
+                    for conflict in &conflicts {
+                        log::warn!("Detected potentially conflicting software: {}", conflict);
+                    }
+                    event_listener.notify_conflicting_software(conflicts);
+                }
+                Ok(_) => (),
+                Err(error) => {
+                    log::error!("Failed to detect conflicting software: {}", error);
+                }
+            }
+        });
+    }
+
+    /// Returns the tunnel options from the settings, with the active network profile applied.
+    fn tunnel_options(&self) -> TunnelOptions {
+        let mut tunnel_options = self.settings.tunnel_options.clone();
+        if let Some(profile) = &self.network_profile {
+            let wireguard = &mut tunnel_options.wireguard.options;
+            if profile.mtu.is_some() {
+                wireguard.mtu = profile.mtu;
+            }
+            wireguard.connect_timeout = profile
+                .connect_timeout
+                .map(|timeout| Duration::from_secs(u64::from(timeout)));
+            wireguard.persistent_keepalive = profile.wireguard_keepalive;
+        }
+        tunnel_options
+    }
+
+    /// Returns the relay selector config for the settings, with the active network profile
+    /// applied.
+    fn selector_config(&self) -> SelectorConfig {
+        let mut config = new_selector_config(&self.settings, &self.app_version_info);
+        config.obfuscation_settings.selected_obfuscation = self.selected_obfuscation();
+        config
+    }
+
+    fn selected_obfuscation(&self) -> SelectedObfuscation {
+        self.network_profile
+            .as_ref()
+            .and_then(|profile| profile.obfuscation)
+            .unwrap_or(self.settings.obfuscation_settings.selected_obfuscation)
+    }
+
     fn connect_tunnel(&mut self) {
         self.api_runtime.availability_handle().resume_background();
         self.send_tunnel_command(TunnelCommand::Connect);
     }
 
-    fn disconnect_tunnel(&mut self) {
-        self.send_tunnel_command(TunnelCommand::Disconnect);
+    /// Closes the tunnel. With lockdown mode enabled, the blocking policy is requested along with
+    /// the disconnect, so that it replaces the tunnel policy without traffic being let through.
+    fn disconnect_tunnel(&mut self, reason: DisconnectReason) {
+        self.send_tunnel_command(TunnelCommand::Disconnect {
+            reason,
+            keep_blocking: self.settings.block_when_disconnected,
+        });
     }
 
-    fn reconnect_tunnel(&mut self) {
+    fn reconnect_tunnel(&mut self, reason: ReconnectReason) {
         if *self.target_state == TargetState::Secured {
-            self.connect_tunnel();
+            self.reconnect_reason = Some(reason);
+            self.api_runtime.availability_handle().resume_background();
+            self.send_tunnel_command(TunnelCommand::Reconnect);
         }
     }
 
@@ -2273,10 +3939,14 @@ where
     }
 
     fn send_tunnel_command(&self, command: TunnelCommand) {
-        self.tunnel_state_machine_handle
+        if self
+            .tunnel_state_machine_handle
             .command_tx()
             .unbounded_send(command)
-            .expect("Tunnel state machine has stopped");
+            .is_err()
+        {
+            log::error!("Tunnel state machine has stopped. Dropping tunnel command");
+        }
     }
 
     pub fn shutdown_handle(&self) -> DaemonShutdownHandle {
@@ -2299,6 +3969,24 @@ impl DaemonShutdownHandle {
     }
 }
 
+#[cfg(target_os = "linux")]
+fn log_split_tunnel_include_mode_warning() {
+    log::warn!(
+        "Split tunneling is in include mode. Only processes that have been added will use the \
+         tunnel. All other traffic leaves the device unprotected, even when blocking"
+    );
+}
+
+/// Returns the hex-encoded SHA-256 hash of a BSSID, ignoring case.
+fn hash_bssid(bssid: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, bssid.to_lowercase().as_bytes());
+    digest
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
 fn new_selector_config(
     settings: &Settings,
     app_version_info: &Option<AppVersionInfo>,
@@ -2328,5 +4016,7 @@ fn new_selector_config(
         bridge_settings: settings.bridge_settings.clone(),
         obfuscation_settings: settings.obfuscation_settings.clone(),
         default_tunnel_type,
+        quantum_resistant: settings.tunnel_options.wireguard.options.use_pq_safe_psk,
+        auto_port_order: settings.auto_port_order.clone(),
     }
 }
@@ -0,0 +1,373 @@
+//! Search for relay locations by name or code. Names are matched both in English and in the
+//! language of the frontend, using the translations that are bundled with the app.
+
+use mullvad_types::{
+    relay_constraints::LocationConstraint,
+    relay_list::{LocationSearchResult, RelayList},
+};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use talpid_types::ErrorExt;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Directory in the resource directory that contains the translations of the GUI.
+const LOCALES_DIR: &str = "locales";
+/// Translations of country and city names, in gettext format.
+const RELAY_LOCATIONS_FILE: &str = "relay-locations.po";
+
+/// Queries shorter than this are only matched against the start of words, since a short
+/// subsequence matches almost every name.
+const MIN_SUBSEQUENCE_QUERY_LEN: usize = 3;
+
+/// How well a name matches a query. Lower is better.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Rank {
+    Exact,
+    Prefix,
+    WordPrefix,
+    Substring,
+    Subsequence,
+}
+
+/// Orders results of the same rank. Broader locations are listed first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Country,
+    City,
+    Relay,
+}
+
+type Translations = HashMap<String, String>;
+
+/// Searches relay locations. Translations are bundled with the app, so they are only read once
+/// per locale.
+#[derive(Clone)]
+pub struct LocationSearch {
+    resource_dir: PathBuf,
+    translations: Arc<Mutex<HashMap<String, Arc<Translations>>>>,
+}
+
+impl LocationSearch {
+    pub fn new(resource_dir: PathBuf) -> Self {
+        LocationSearch {
+            resource_dir,
+            translations: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the countries, cities and relays in `relay_list` that match `query`, with the
+    /// best matches first. Names are translated to `locale` if a translation is available. This
+    /// may read from disk, so it should not be called on an async executor.
+    pub fn search(
+        &self,
+        relay_list: &RelayList,
+        query: &str,
+        locale: &str,
+    ) -> Vec<LocationSearchResult> {
+        let translations = self.translations(locale);
+        search_with_translations(relay_list, query, &translations)
+    }
+
+    fn translations(&self, locale: &str) -> Arc<Translations> {
+        if let Some(translations) = self.translations.lock().get(locale) {
+            return translations.clone();
+        }
+        let translations = Arc::new(load_translations(&self.resource_dir, locale));
+        // Only locales that have translations are kept, so that arbitrary locales sent by
+        // clients don't grow the cache
+        if !translations.is_empty() {
+            self.translations
+                .lock()
+                .insert(locale.to_owned(), translations.clone());
+        }
+        translations
+    }
+}
+
+fn search_with_translations(
+    relay_list: &RelayList,
+    query: &str,
+    translations: &Translations,
+) -> Vec<LocationSearchResult> {
+    let query = normalize(query.trim());
+    if query.is_empty() {
+        return vec![];
+    }
+    let translate = |name: &String| translations.get(name).unwrap_or(name).clone();
+    let best_rank = |names: &[&str]| names.iter().filter_map(|name| rank(&query, name)).min();
+
+    let mut matches = vec![];
+    for country in &relay_list.countries {
+        let country_name = translate(&country.name);
+        if let Some(rank) = best_rank(&[&country.code[..], &country.name, &country_name]) {
+            matches.push((
+                rank,
+                Kind::Country,
+                LocationSearchResult {
+                    location: LocationConstraint::Country(country.code.clone()),
+                    name: country_name,
+                },
+            ));
+        }
+
+        for city in &country.cities {
+            let city_name = translate(&city.name);
+            if let Some(rank) = best_rank(&[&city.code[..], &city.name, &city_name]) {
+                matches.push((
+                    rank,
+                    Kind::City,
+                    LocationSearchResult {
+                        location: LocationConstraint::City(country.code.clone(), city.code.clone()),
+                        name: city_name,
+                    },
+                ));
+            }
+
+            for relay in &city.relays {
+                if let Some(rank) = rank(&query, &relay.hostname) {
+                    matches.push((
+                        rank,
+                        Kind::Relay,
+                        LocationSearchResult {
+                            location: LocationConstraint::Hostname(
+                                country.code.clone(),
+                                city.code.clone(),
+                                relay.hostname.clone(),
+                            ),
+                            name: relay.hostname.clone(),
+                        },
+                    ));
+                }
+            }
+        }
+    }
+
+    matches.sort_by(|(rank_a, kind_a, a), (rank_b, kind_b, b)| {
+        (rank_a, kind_a, &a.name).cmp(&(rank_b, kind_b, &b.name))
+    });
+    matches.into_iter().map(|(_, _, result)| result).collect()
+}
+
+/// Ranks how well `name` matches `query`, which must already be normalized. Returns `None` if
+/// it does not match at all.
+fn rank(query: &str, name: &str) -> Option<Rank> {
+    let name = normalize(name);
+    if name == query {
+        Some(Rank::Exact)
+    } else if name.starts_with(query) {
+        Some(Rank::Prefix)
+    } else if name
+        .match_indices(query)
+        .any(|(index, _)| name[..index].ends_with(|c: char| !c.is_alphanumeric()))
+    {
+        Some(Rank::WordPrefix)
+    } else if name.contains(query) {
+        Some(Rank::Substring)
+    } else if query.chars().count() >= MIN_SUBSEQUENCE_QUERY_LEN && is_subsequence(query, &name) {
+        Some(Rank::Subsequence)
+    } else {
+        None
+    }
+}
+
+fn is_subsequence(query: &str, name: &str) -> bool {
+    let mut name = name.chars();
+    query
+        .chars()
+        .all(|query_char| name.any(|name_char| name_char == query_char))
+}
+
+/// Lowercases `text` and strips diacritics, so that "zurich" matches "Zürich".
+fn normalize(text: &str) -> String {
+    text.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Loads the translated location names for `locale`, falling back on the language without a
+/// region. Returns an empty map if there are no translations.
+fn load_translations(resource_dir: &Path, locale: &str) -> Translations {
+    let is_valid_locale = !locale.is_empty()
+        && locale
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !is_valid_locale {
+        return HashMap::new();
+    }
+    let language = locale
+        .split(|c| c == '-' || c == '_')
+        .next()
+        .unwrap_or(locale);
+
+    for candidate in [locale, language] {
+        let path = translations_path(resource_dir, candidate);
+        match std::fs::read_to_string(&path) {
+            Ok(content) => return parse_po(&content),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to read location translations from {}",
+                        path.display()
+                    ))
+                );
+                break;
+            }
+        }
+    }
+    HashMap::new()
+}
+
+fn translations_path(resource_dir: &Path, locale: &str) -> PathBuf {
+    resource_dir
+        .join(LOCALES_DIR)
+        .join(locale)
+        .join(RELAY_LOCATIONS_FILE)
+}
+
+/// Parses the `msgid` and `msgstr` pairs of a gettext file. Only single-line strings are
+/// supported, which is all that location names use.
+fn parse_po(content: &str) -> Translations {
+    let unquote = |text: &str| -> Option<String> {
+        let text = text.trim().strip_prefix('"')?.strip_suffix('"')?;
+        Some(text.replace("\\\"", "\""))
+    };
+
+    let mut translations = HashMap::new();
+    let mut msgid = None;
+    for line in content.lines() {
+        if let Some(id) = line.strip_prefix("msgid ") {
+            msgid = unquote(id);
+        } else if let Some(translation) = line.strip_prefix("msgstr ") {
+            if let (Some(id), Some(translation)) = (msgid.take(), unquote(translation)) {
+                if !id.is_empty() && !translation.is_empty() {
+                    translations.insert(id, translation);
+                }
+            }
+        }
+    }
+    translations
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::relay_list::{Relay, RelayEndpointData, RelayListCity, RelayListCountry};
+
+    fn relay_list() -> RelayList {
+        let city = |name: &str, code: &str| RelayListCity {
+            name: name.to_owned(),
+            code: code.to_owned(),
+            latitude: 0.0,
+            longitude: 0.0,
+            relays: vec![],
+        };
+        RelayList {
+            countries: vec![
+                RelayListCountry {
+                    name: "Switzerland".to_owned(),
+                    code: "ch".to_owned(),
+                    cities: vec![city("Zurich", "zrh")],
+                },
+                RelayListCountry {
+                    name: "Sweden".to_owned(),
+                    code: "se".to_owned(),
+                    cities: vec![city("Gothenburg", "got"), city("Stockholm", "sto")],
+                },
+            ],
+            ..RelayList::empty()
+        }
+    }
+
+    fn locations(results: Vec<LocationSearchResult>) -> Vec<LocationConstraint> {
+        results.into_iter().map(|result| result.location).collect()
+    }
+
+    #[test]
+    fn test_ranking() {
+        let results = search_with_translations(&relay_list(), "s", &HashMap::new());
+        assert_eq!(
+            locations(results),
+            vec![
+                LocationConstraint::Country("se".to_owned()),
+                LocationConstraint::Country("ch".to_owned()),
+                LocationConstraint::City("se".to_owned(), "sto".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        let results = search_with_translations(&relay_list(), "gtbg", &HashMap::new());
+        assert_eq!(
+            locations(results),
+            vec![LocationConstraint::City("se".to_owned(), "got".to_owned())]
+        );
+        assert!(search_with_translations(&relay_list(), "gt", &HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_translated_names() {
+        let translations = parse_po(
+            "msgid \"\"\nmsgstr \"\"\n\"Language: de_DE\\n\"\n\n\
+             #. CH\nmsgid \"Switzerland\"\nmsgstr \"Schweiz\"\n\n\
+             #. CH ZRH\nmsgid \"Zurich\"\nmsgstr \"Zürich\"\n",
+        );
+        let results = search_with_translations(&relay_list(), "schw", &translations);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].name, "Schweiz");
+
+        let results = search_with_translations(&relay_list(), "zür", &translations);
+        assert_eq!(
+            locations(results),
+            vec![LocationConstraint::City("ch".to_owned(), "zrh".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_relay_match() {
+        let mut relay_list = relay_list();
+        relay_list.countries[1].cities[0].relays.push(Relay {
+            hostname: "se-got-wg-001".to_owned(),
+            ipv4_addr_in: "192.0.2.1".parse().unwrap(),
+            ipv6_addr_in: None,
+            include_in_country: true,
+            active: true,
+            owned: true,
+            provider: "provider".to_owned(),
+            weight: 1,
+            endpoint_data: RelayEndpointData::Openvpn,
+            location: None,
+        });
+        let results = search_with_translations(&relay_list, "got-wg", &HashMap::new());
+        assert_eq!(
+            locations(results),
+            vec![LocationConstraint::Hostname(
+                "se".to_owned(),
+                "got".to_owned(),
+                "se-got-wg-001".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_translations_are_cached() {
+        let resource_dir =
+            std::env::temp_dir().join(format!("location-search-{}", uuid::Uuid::new_v4()));
+        let path = translations_path(&resource_dir, "de");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "msgid \"Sweden\"\nmsgstr \"Schweden\"\n").unwrap();
+
+        let location_search = LocationSearch::new(resource_dir.clone());
+        let search = || location_search.search(&relay_list(), "schwed", "de-DE");
+        assert_eq!(search().len(), 1);
+        std::fs::remove_dir_all(&resource_dir).unwrap();
+        assert_eq!(search().len(), 1);
+    }
+}
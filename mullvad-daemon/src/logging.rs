@@ -2,8 +2,14 @@ use fern::{
     colors::{Color, ColoredLevelConfig},
     Output,
 };
-use std::{fmt, io, path::PathBuf};
+use lazy_static::lazy_static;
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 use talpid_core::logging::rotate_log;
+use tokio::sync::broadcast;
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -20,16 +26,14 @@ pub enum Error {
 
     #[error(display = "Unable to set logger")]
     SetLoggerError(#[error(source)] log::SetLoggerError),
+
+    #[error(display = "Unable to set trace subscriber")]
+    SetTraceSubscriberError(#[error(source)] tracing::subscriber::SetGlobalDefaultError),
 }
 
 pub const WARNING_SILENCED_CRATES: &[&str] = &["netlink_proto"];
 pub const SILENCED_CRATES: &[&str] = &[
     "h2",
-    "tokio_core",
-    "tokio_io",
-    "tokio_proto",
-    "tokio_reactor",
-    "tokio_threadpool",
     "tokio_util",
     "tower",
     "want",
@@ -60,7 +64,44 @@ const LINE_SEPARATOR: &str = "\n";
 #[cfg(windows)]
 const LINE_SEPARATOR: &str = "\r\n";
 
-const DATE_TIME_FORMAT_STR: &str = "[%Y-%m-%d %H:%M:%S%.3f]";
+pub(crate) const DATE_TIME_FORMAT_STR: &str = "[%Y-%m-%d %H:%M:%S%.3f]";
+
+/// Number of messages buffered for each subscriber of the log tail. Subscribers that fall further
+/// behind than this miss the oldest messages.
+const LOG_TAIL_CAPACITY: usize = 1024;
+
+lazy_static! {
+    static ref LOG_TAIL: broadcast::Sender<Arc<LogMessage>> =
+        broadcast::channel(LOG_TAIL_CAPACITY).0;
+}
+
+/// A message that has been logged by the daemon. See [`subscribe`].
+#[derive(Debug)]
+pub struct LogMessage {
+    pub time: chrono::DateTime<chrono::Utc>,
+    pub level: log::Level,
+    pub target: String,
+    pub message: String,
+}
+
+/// Returns a receiver of every message that is logged from now on, as long as it passes the level
+/// filter of the logger.
+pub fn subscribe() -> broadcast::Receiver<Arc<LogMessage>> {
+    LOG_TAIL.subscribe()
+}
+
+fn send_to_subscribers(record: &log::Record<'_>) {
+    // Skip the formatting unless someone is listening
+    if LOG_TAIL.receiver_count() == 0 {
+        return;
+    }
+    let _ = LOG_TAIL.send(Arc::new(LogMessage {
+        time: chrono::Utc::now(),
+        level: record.level(),
+        target: record.target().to_owned(),
+        message: record.args().to_string(),
+    }));
+}
 
 pub fn init_logger(
     log_level: log::LevelFilter,
@@ -85,7 +126,9 @@ pub fn init_logger(
     let stdout_dispatcher = fern::Dispatch::new()
         .format(move |out, message, record| stdout_formatter.output_msg(out, message, record))
         .chain(io::stdout());
-    top_dispatcher = top_dispatcher.chain(stdout_dispatcher);
+    top_dispatcher = top_dispatcher
+        .chain(stdout_dispatcher)
+        .chain(Output::call(send_to_subscribers));
 
     if let Some(ref log_file) = log_file {
         rotate_log(log_file).map_err(Error::RotateLog)?;
@@ -114,6 +157,32 @@ pub fn init_logger(
     Ok(())
 }
 
+/// Writes spans and events from the tunnel state machine to `trace_file` as JSON lines. Each state
+/// is a span, so a reconnect loop can be read as a timeline of states with their durations.
+pub fn init_trace_file(trace_file: &Path) -> Result<(), Error> {
+    use tracing_subscriber::{
+        filter::Targets,
+        fmt::{self, format::FmtSpan},
+        prelude::*,
+    };
+
+    let file = std::fs::File::create(trace_file).map_err(|source| Error::WriteFile {
+        path: trace_file.display().to_string(),
+        source,
+    })?;
+    let layer = fmt::layer()
+        .json()
+        .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
+        .with_current_span(true)
+        .with_span_list(false)
+        .with_writer(Mutex::new(file))
+        .with_filter(
+            Targets::new().with_target("talpid_core::tunnel_state_machine", tracing::Level::DEBUG),
+        );
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        .map_err(Error::SetTraceSubscriberError)
+}
+
 fn one_level_quieter(level: log::LevelFilter) -> log::LevelFilter {
     use log::LevelFilter::*;
     match level {
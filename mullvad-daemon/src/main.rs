@@ -1,7 +1,9 @@
 #![deny(rust_2018_idioms)]
 
+#[cfg(target_os = "linux")]
+use mullvad_daemon::system_notifications;
 use mullvad_daemon::{
-    logging,
+    crash_handler, logging,
     management_interface::{ManagementInterfaceEventBroadcaster, ManagementInterfaceServer},
     rpc_uniqueness_check,
     runtime::new_runtime_builder,
@@ -14,15 +16,30 @@ mod cli;
 #[cfg(target_os = "linux")]
 mod early_boot_firewall;
 mod exception_logging;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod self_check;
 #[cfg(windows)]
 mod system_service;
 
 const DAEMON_LOG_FILENAME: &str = "daemon.log";
 #[cfg(target_os = "linux")]
 const EARLY_BOOT_LOG_FILENAME: &str = "early-boot-fw.log";
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const SELF_CHECK_LOG_FILENAME: &str = "self-check.log";
 
 fn main() {
     let config = cli::get_config();
+
+    // This runs as an unprivileged user, so it must not touch the logs of the daemon
+    #[cfg(target_os = "linux")]
+    if let Some((summary, body)) = &config.show_notification {
+        if let Err(error) = system_notifications::show_as_current_user(summary, body) {
+            eprintln!("{}", error.display_chain());
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let log_dir = init_daemon_logging(config).unwrap_or_else(|error| {
         eprintln!("{}", error);
         std::process::exit(1)
@@ -30,6 +47,15 @@ fn main() {
 
     log::trace!("Using configuration: {:?}", config);
 
+    // Namespaces can only be entered while the process has a single thread
+    #[cfg(target_os = "linux")]
+    if config.self_check && running_as_admin() {
+        if let Err(error) = self_check::isolate() {
+            log::error!("{}", error);
+            std::process::exit(1);
+        }
+    }
+
     let runtime = new_runtime_builder().build().unwrap_or_else(|error| {
         eprintln!("{}", error.display_chain());
         std::process::exit(1);
@@ -49,7 +75,12 @@ fn main() {
 fn init_daemon_logging(config: &cli::Config) -> Result<Option<PathBuf>, String> {
     #[cfg(target_os = "linux")]
     if config.initialize_firewall_and_exit {
-        init_early_boot_logging(config);
+        init_secondary_logging(config, EARLY_BOOT_LOG_FILENAME);
+        return Ok(None);
+    }
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    if config.self_check {
+        init_secondary_logging(config, SELF_CHECK_LOG_FILENAME);
         return Ok(None);
     }
 
@@ -57,19 +88,30 @@ fn init_daemon_logging(config: &cli::Config) -> Result<Option<PathBuf>, String>
     let log_path = |filename| log_dir.as_ref().map(|dir| dir.join(filename));
 
     init_logger(config, log_path(DAEMON_LOG_FILENAME))?;
+    crash_handler::enable(log_dir.clone());
 
     if let Some(ref log_dir) = log_dir {
         log::info!("Logging to {}", log_dir.display());
     }
+    if let Some(ref trace_file) = config.trace_file {
+        logging::init_trace_file(trace_file)
+            .map_err(|e| e.display_chain_with_msg("Unable to initialize trace file"))?;
+        log::info!(
+            "Writing tunnel state machine trace to {}",
+            trace_file.display()
+        );
+    }
     Ok(log_dir)
 }
 
-#[cfg(target_os = "linux")]
-fn init_early_boot_logging(config: &cli::Config) {
+/// Initializes logging for modes that run something other than the daemon itself, so that they
+/// do not write to the daemon log.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn init_secondary_logging(config: &cli::Config, log_filename: &str) {
     // If it's possible to log to the filesystem - attempt to do so, but failing that mustn't stop
-    // the daemon from starting here.
+    // the mode from running.
     if let Ok(Some(log_dir)) = get_log_dir(config) {
-        if init_logger(config, Some(log_dir.join(EARLY_BOOT_LOG_FILENAME))).is_ok() {
+        if init_logger(config, Some(log_dir.join(log_filename))).is_ok() {
             return;
         }
     }
@@ -124,14 +166,39 @@ async fn run_platform(config: &cli::Config, log_dir: Option<PathBuf>) -> Result<
             .await
             .map_err(|err| format!("{}", err));
     }
+    if config.self_check {
+        return run_self_check().await;
+    }
+    run_standalone(log_dir).await
+}
+
+#[cfg(target_os = "macos")]
+async fn run_platform(config: &cli::Config, log_dir: Option<PathBuf>) -> Result<(), String> {
+    if config.self_check {
+        return run_self_check().await;
+    }
     run_standalone(log_dir).await
 }
 
-#[cfg(not(any(windows, target_os = "linux")))]
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
 async fn run_platform(_config: &cli::Config, log_dir: Option<PathBuf>) -> Result<(), String> {
     run_standalone(log_dir).await
 }
 
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+async fn run_self_check() -> Result<(), String> {
+    if !running_as_admin() {
+        return Err("The self-check must be run as root".to_owned());
+    }
+    // Outside of the namespaces used on Linux, the routes of the checks could interfere with
+    // those of a running daemon
+    #[cfg(target_os = "macos")]
+    if rpc_uniqueness_check::is_another_instance_running().await {
+        return Err("The daemon must be stopped before running the self-check".to_owned());
+    }
+    self_check::run().await
+}
+
 async fn run_standalone(log_dir: Option<PathBuf>) -> Result<(), String> {
     if rpc_uniqueness_check::is_another_instance_running().await {
         return Err("Another instance of the daemon is already running".to_owned());
@@ -1,35 +1,56 @@
-use crate::{account_history, device, settings, DaemonCommand, DaemonCommandSender, EventListener};
+use crate::{
+    account_history, device, frontend_store, logging, settings, DaemonCommand, DaemonCommandSender,
+    EventListener,
+};
 use futures::{
     channel::{mpsc, oneshot},
     StreamExt,
 };
-use mullvad_api::{rest::Error as RestError, StatusCode};
+use mullvad_api::{rest::Error as RestError, ApiError, StatusCode};
 use mullvad_management_interface::{
-    types::{self, daemon_event, management_service_server::ManagementService},
-    Code, Request, Response, Status,
+    types::{self, daemon_event, management_service_server::ManagementService, ErrorKind},
+    with_error_details, with_error_kind, AccessPolicyHandle, Code, EventSubscriptions, Request,
+    Response, SharedDaemonEvent, Status, Streaming,
 };
 use mullvad_paths;
 #[cfg(not(target_os = "android"))]
 use mullvad_types::settings::DnsOptions;
 use mullvad_types::{
-    account::AccountToken,
+    access_method::{self, AccessMethod},
+    account::{AccountToken, LoginMode},
+    auto_connect::{AutoConnectDecision, AutoConnectPolicy, NetworkInfo},
+    cache::QuarantinedFile,
+    network::NetworkProfile,
     relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
     relay_list::RelayList,
-    settings::Settings,
+    settings::{ManagementAccessPolicy, Settings, SettingsUpdate},
     states::{TargetState, TunnelState},
     version,
     wireguard::{RotationInterval, RotationIntervalError},
 };
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+#[cfg(not(target_os = "android"))]
+use std::net::IpAddr;
 #[cfg(windows)]
 use std::path::PathBuf;
 use std::{
+    collections::{HashMap, VecDeque},
     convert::{TryFrom, TryInto},
     sync::Arc,
     time::Duration,
 };
-use talpid_types::ErrorExt;
-use tokio_stream::wrappers::UnboundedReceiverStream;
+#[cfg(target_os = "linux")]
+use talpid_types::cgroup::SplitTunnelMode;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use talpid_types::net::AllowedLinkConfigTraffic;
+use talpid_types::{
+    conflict::ConflictingSoftware,
+    net::{openvpn, IpVersion},
+    tunnel::ReconnectReason,
+    ErrorExt,
+};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
 
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -41,12 +62,28 @@ pub enum Error {
 
 struct ManagementServiceImpl {
     daemon_tx: DaemonCommandSender,
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    event_history: Arc<Mutex<EventHistory>>,
+    event_subscriber: EventSubscriber,
 }
 
 pub type ServiceResult<T> = std::result::Result<Response<T>, Status>;
-type EventsListenerReceiver = UnboundedReceiverStream<Result<types::DaemonEvent, Status>>;
-type EventsListenerSender = tokio::sync::mpsc::UnboundedSender<Result<types::DaemonEvent, Status>>;
+type EventsListenerSender = tokio::sync::mpsc::UnboundedSender<SharedDaemonEvent>;
+
+/// Maximum time to wait for read-only RPCs that depend on the API. This is a backstop for
+/// clients that don't set a deadline of their own; requests with a shorter `grpc-timeout` are
+/// cancelled earlier by tonic. RPCs that change the account or device are not timed out, since
+/// the change may still be applied after the client has given up.
+const API_RPC_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maximum time that `connect_and_wait` waits for the tunnel to connect.
+const CONNECT_RPC_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Number of log messages that may be waiting to be sent to a single `tail_logs` client.
+const LOG_TAIL_BUFFER_SIZE: usize = 64;
+
+/// Number of events kept for `get_recent_events`. The oldest event is dropped when a new one is
+/// recorded.
+const EVENT_HISTORY_SIZE: usize = 100;
 
 const INVALID_VOUCHER_MESSAGE: &str = "This voucher code is invalid";
 const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
@@ -54,7 +91,8 @@ const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
 #[mullvad_management_interface::async_trait]
 impl ManagementService for ManagementServiceImpl {
     type GetSplitTunnelProcessesStream = UnboundedReceiverStream<Result<i32, Status>>;
-    type EventsListenStream = EventsListenerReceiver;
+    type EventsListenStream = futures::stream::Empty<Result<types::DaemonEvent, Status>>;
+    type TailLogsStream = ReceiverStream<Result<types::LogMessage, Status>>;
 
     // Control and get the tunnel state
     //
@@ -80,7 +118,7 @@ impl ManagementService for ManagementServiceImpl {
     async fn reconnect_tunnel(&self, _: Request<()>) -> ServiceResult<bool> {
         log::debug!("reconnect_tunnel");
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::Reconnect(tx))?;
+        self.send_command_to_daemon(DaemonCommand::Reconnect(tx, ReconnectReason::UserRequested))?;
         let reconnect_issued = self.wait_for_result(rx).await?;
         Ok(Response::new(reconnect_issued))
     }
@@ -93,16 +131,87 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(types::TunnelState::from(state)))
     }
 
+    async fn connect_and_wait(&self, _: Request<()>) -> ServiceResult<types::TunnelState> {
+        log::debug!("connect_and_wait");
+        // Subscribe before connecting, so that no state transition is missed
+        let mut events = self.event_subscriber.subscribe();
+
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetTargetState(tx, TargetState::Secured))?;
+        self.wait_for_result(rx).await?;
+
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetState(tx))?;
+        let state = types::TunnelState::from(self.wait_for_result(rx).await?);
+
+        tokio::time::timeout(CONNECT_RPC_TIMEOUT, wait_for_connection(&mut events, state))
+            .await
+            .map_err(|_| Status::deadline_exceeded("timed out waiting for the tunnel to connect"))?
+            .map(Response::new)
+    }
+
     // Control the daemon and receive events
     //
 
+    /// Never called, since the server answers `EventsListen` with streams from
+    /// [`EventSubscriber`] instead, which share every event between the listeners.
     async fn events_listen(&self, _: Request<()>) -> ServiceResult<Self::EventsListenStream> {
-        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        Err(Status::unimplemented(
+            "events are sent by the shared event stream",
+        ))
+    }
 
-        let mut subscriptions = self.subscriptions.write();
-        subscriptions.push(tx);
+    async fn get_recent_events(&self, _: Request<()>) -> ServiceResult<types::RecentEvents> {
+        log::debug!("get_recent_events");
+        let events = self.event_history.lock().events.iter().cloned().collect();
+        Ok(Response::new(types::RecentEvents { events }))
+    }
 
-        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    async fn test_for_leaks(&self, _: Request<()>) -> ServiceResult<types::LeakTestResult> {
+        log::debug!("test_for_leaks");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::TestForLeaks(tx))?;
+        let result = self
+            .wait_for_api_result(rx)
+            .await?
+            .map_err(map_daemon_error)?;
+        Ok(Response::new(types::LeakTestResult {
+            ipv4_leaking: result.ipv4_leaking,
+            ipv6_leaking: result.ipv6_leaking,
+        }))
+    }
+
+    async fn run_speed_test(&self, _: Request<()>) -> ServiceResult<types::SpeedTestResult> {
+        log::debug!("run_speed_test");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RunSpeedTest(tx))?;
+        let result = self
+            .wait_for_api_result(rx)
+            .await?
+            .map_err(map_daemon_error)?;
+        Ok(Response::new(types::SpeedTestResult {
+            bytes: result.bytes,
+            duration: types::Duration::try_from(result.duration).ok(),
+        }))
+    }
+
+    async fn send_problem_report(
+        &self,
+        request: Request<types::ProblemReport>,
+    ) -> ServiceResult<()> {
+        log::debug!("send_problem_report");
+        let report = request.into_inner();
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SendProblemReport(
+            tx,
+            report.email,
+            report.message,
+            report.report,
+        ))?;
+        self.wait_for_api_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
     }
 
     async fn prepare_restart(&self, _: Request<()>) -> ServiceResult<()> {
@@ -128,6 +237,46 @@ impl ManagementService for ManagementServiceImpl {
         }
     }
 
+    async fn tail_logs(
+        &self,
+        request: Request<types::LogFilter>,
+    ) -> ServiceResult<Self::TailLogsStream> {
+        let filter = request.into_inner();
+        log::debug!("tail_logs({:?})", filter);
+        let min_level = log::Level::from(filter.level());
+        let module = filter.module;
+
+        let mut messages = logging::subscribe();
+        let (tx, rx) = tokio::sync::mpsc::channel(LOG_TAIL_BUFFER_SIZE);
+        tokio::spawn(async move {
+            loop {
+                let message = match messages.recv().await {
+                    Ok(message) => message,
+                    // Messages are dropped rather than buffered for clients that cannot keep up
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if message.level > min_level || !is_in_module(&message.target, &module) {
+                    continue;
+                }
+                let message = types::LogMessage {
+                    time: Some(types::Timestamp {
+                        seconds: message.time.timestamp(),
+                        nanos: message.time.timestamp_subsec_nanos() as i32,
+                    }),
+                    level: i32::from(types::LogLevel::from(message.level)),
+                    module: message.target.clone(),
+                    message: mullvad_problem_report::redact(&message.message),
+                };
+                if tx.send(Ok(message)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
     async fn get_current_version(&self, _: Request<()>) -> ServiceResult<String> {
         log::debug!("get_current_version");
         let (tx, rx) = oneshot::channel();
@@ -143,7 +292,12 @@ impl ManagementService for ManagementServiceImpl {
         self.send_command_to_daemon(DaemonCommand::GetVersionInfo(tx))?;
         self.wait_for_result(rx)
             .await?
-            .ok_or_else(|| Status::not_found("no version cache"))
+            .ok_or_else(|| {
+                with_error_kind(
+                    Status::not_found("no version cache"),
+                    ErrorKind::NoVersionInfo,
+                )
+            })
             .map(types::AppVersionInfo::from)
             .map(Response::new)
     }
@@ -188,17 +342,59 @@ impl ManagementService for ManagementServiceImpl {
         self.send_command_to_daemon(DaemonCommand::GetRelayLocations(tx))?;
         self.wait_for_result(rx)
             .await
-            .map(|relays| Response::new(types::RelayList::from(relays)))
+            .map(|relays| Response::new(types::RelayList::from(RelayList::clone(&relays))))
+    }
+
+    async fn search_locations(
+        &self,
+        request: Request<types::LocationSearch>,
+    ) -> ServiceResult<types::LocationSearchResults> {
+        log::debug!("search_locations");
+
+        let request = request.into_inner();
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SearchLocations(
+            tx,
+            request.query,
+            request.locale,
+        ))?;
+        self.wait_for_result(rx).await.map(|results| {
+            Response::new(types::LocationSearchResults {
+                results: results
+                    .into_iter()
+                    .map(types::LocationSearchResult::from)
+                    .collect(),
+            })
+        })
+    }
+
+    async fn explain_relay_selection(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::RelaySelectionHint> {
+        log::debug!("explain_relay_selection");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ExplainRelaySelection(tx))?;
+        match self.wait_for_result(rx).await? {
+            Some(hint) => Ok(Response::new(types::RelaySelectionHint::from(hint))),
+            None => Err(with_error_kind(
+                Status::failed_precondition("a custom tunnel endpoint is in use"),
+                ErrorKind::CustomTunnelEndpoint,
+            )),
+        }
     }
 
     async fn get_current_location(&self, _: Request<()>) -> ServiceResult<types::GeoIpLocation> {
         log::debug!("get_current_location");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::GetCurrentLocation(tx))?;
-        let result = self.wait_for_result(rx).await?;
+        let result = self.wait_for_api_result(rx).await?;
         match result {
             Some(geoip) => Ok(Response::new(types::GeoIpLocation::from(geoip))),
-            None => Err(Status::not_found("no location was found")),
+            None => Err(with_error_kind(
+                Status::not_found("no location was found"),
+                ErrorKind::LocationUnavailable,
+            )),
         }
     }
 
@@ -234,6 +430,22 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_auto_port_order(
+        &self,
+        request: Request<types::PortSelectionOrder>,
+    ) -> ServiceResult<()> {
+        let port_order =
+            types::relay_constraints::try_port_selection_order_from_proto(request.into_inner())
+                .map_err(map_protobuf_type_err)?;
+        log::debug!("set_auto_port_order({:?})", port_order);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAutoPortOrder(tx, port_order))?;
+        let settings_result = self.wait_for_result(rx).await?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_bridge_state(&self, request: Request<types::BridgeState>) -> ServiceResult<()> {
         let bridge_state =
             BridgeState::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
@@ -247,6 +459,19 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn parse_bridge_uri(
+        &self,
+        request: Request<String>,
+    ) -> ServiceResult<types::BridgeSettings> {
+        log::debug!("parse_bridge_uri");
+        let settings = openvpn::parse_proxy_uri(&request.into_inner()).map_err(|error| {
+            with_error_kind(Status::invalid_argument(error), ErrorKind::InvalidArgument)
+        })?;
+        Ok(Response::new(types::BridgeSettings::from(
+            BridgeSettings::Custom(settings),
+        )))
+    }
+
     // Settings
     //
 
@@ -259,6 +484,18 @@ impl ManagementService for ManagementServiceImpl {
             .map(|settings| Response::new(types::Settings::from(&settings)))
     }
 
+    async fn apply_settings(&self, request: Request<types::SettingsUpdate>) -> ServiceResult<()> {
+        let update =
+            SettingsUpdate::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("apply_settings({:?})", update);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ApplySettings(tx, update))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_allow_lan(&self, request: Request<bool>) -> ServiceResult<()> {
         let allow_lan = request.into_inner();
         log::debug!("set_allow_lan({})", allow_lan);
@@ -270,6 +507,102 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn set_allowed_multicast_groups(
+        &self,
+        request: Request<types::MulticastGroups>,
+    ) -> ServiceResult<()> {
+        let groups =
+            Vec::<IpAddr>::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("set_allowed_multicast_groups({:?})", groups);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAllowedMulticastGroups(tx, groups))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    async fn set_allowed_multicast_groups(
+        &self,
+        _: Request<types::MulticastGroups>,
+    ) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "Multicast groups can only be allowed on Linux and macOS",
+        ))
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn set_allowed_link_config_traffic(
+        &self,
+        request: Request<types::AllowedLinkConfigTraffic>,
+    ) -> ServiceResult<()> {
+        let allowed = AllowedLinkConfigTraffic::from(request.into_inner());
+        log::debug!("set_allowed_link_config_traffic({})", allowed);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAllowedLinkConfigTraffic(tx, allowed))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    async fn set_allowed_link_config_traffic(
+        &self,
+        _: Request<types::AllowedLinkConfigTraffic>,
+    ) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "DHCP and NDP traffic can only be configured on Linux and macOS",
+        ))
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn set_allowed_lan_hosts(&self, request: Request<types::LanHosts>) -> ServiceResult<()> {
+        let hosts = Vec::<IpAddr>::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("set_allowed_lan_hosts({:?})", hosts);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAllowedLanHosts(tx, hosts))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(target_os = "android")]
+    async fn set_allowed_lan_hosts(&self, _: Request<types::LanHosts>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "LAN hosts can only be allowed on desktop platforms",
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_connection_sharing(
+        &self,
+        request: Request<types::ConnectionSharing>,
+    ) -> ServiceResult<()> {
+        let interface =
+            Option::<String>::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("set_connection_sharing({:?})", interface);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSharedInterface(tx, interface))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn set_connection_sharing(
+        &self,
+        _: Request<types::ConnectionSharing>,
+    ) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "Connection sharing is only supported on Linux",
+        ))
+    }
+
     async fn set_show_beta_releases(&self, request: Request<bool>) -> ServiceResult<()> {
         let enabled = request.into_inner();
         log::debug!("set_show_beta_releases({})", enabled);
@@ -281,6 +614,110 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_system_notifications(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_system_notifications({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSystemNotifications(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_management_access_policy(
+        &self,
+        request: Request<types::ManagementAccessPolicy>,
+    ) -> ServiceResult<()> {
+        let policy = ManagementAccessPolicy::from(request.into_inner());
+        log::debug!("set_management_access_policy({})", policy);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetManagementAccessPolicy(tx, policy))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn add_api_access_method(
+        &self,
+        request: Request<types::ApiAccessMethod>,
+    ) -> ServiceResult<()> {
+        let method = AccessMethod::try_from(request.into_inner())?;
+        log::debug!("add_api_access_method({})", method.name);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::AddApiAccessMethod(tx, method))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn update_api_access_method(
+        &self,
+        request: Request<types::ApiAccessMethodUpdate>,
+    ) -> ServiceResult<()> {
+        let update = request.into_inner();
+        let method = update
+            .method
+            .ok_or_else(|| Status::invalid_argument("missing access method"))?;
+        let method = AccessMethod::try_from(method)?;
+        log::debug!("update_api_access_method({})", update.name);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::UpdateApiAccessMethod(
+            tx,
+            update.name,
+            method,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn remove_api_access_method(&self, request: Request<String>) -> ServiceResult<()> {
+        let name = request.into_inner();
+        log::debug!("remove_api_access_method({})", name);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RemoveApiAccessMethod(tx, name))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn test_api_access_method(&self, request: Request<String>) -> ServiceResult<bool> {
+        let name = request.into_inner();
+        log::debug!("test_api_access_method({})", name);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::TestApiAccessMethod(tx, name))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn get_api_connection_pools(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ApiConnectionPools> {
+        log::debug!("get_api_connection_pools");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetApiConnectionPools(tx))?;
+        let pools = self.wait_for_result(rx).await?.map_err(map_daemon_error)?;
+        Ok(Response::new(types::ApiConnectionPools {
+            pools: pools
+                .into_iter()
+                .map(|pool| types::api_connection_pools::Pool {
+                    connection_mode: pool.connection_mode.to_string(),
+                    interface: pool.interface.unwrap_or_default(),
+                    requests: pool.requests,
+                    connections_opened: pool.connections_opened,
+                })
+                .collect(),
+        }))
+    }
+
     async fn set_block_when_disconnected(&self, request: Request<bool>) -> ServiceResult<()> {
         let block_when_disconnected = request.into_inner();
         log::debug!("set_block_when_disconnected({})", block_when_disconnected);
@@ -306,6 +743,60 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_auto_connect_policy(
+        &self,
+        request: Request<types::AutoConnectPolicy>,
+    ) -> ServiceResult<()> {
+        let policy =
+            AutoConnectPolicy::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("set_auto_connect_policy({:?})", policy);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAutoConnectPolicy(tx, policy))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_current_network(&self, request: Request<types::NetworkInfo>) -> ServiceResult<()> {
+        let network = NetworkInfo::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("set_current_network({})", network);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetCurrentNetwork(tx, network))?;
+        self.wait_for_result(rx).await.map(Response::new)
+    }
+
+    async fn get_network_info(&self, _: Request<()>) -> ServiceResult<types::CurrentNetwork> {
+        log::debug!("get_network_info");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetNetworkInfo(tx))?;
+        let network = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::CurrentNetwork::from(network)))
+    }
+
+    async fn set_network_profiles(
+        &self,
+        request: Request<types::NetworkProfiles>,
+    ) -> ServiceResult<()> {
+        let profiles =
+            Vec::<NetworkProfile>::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("set_network_profiles({:?})", profiles);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetNetworkProfiles(tx, profiles))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn check_dns_backend(&self, _: Request<()>) -> ServiceResult<types::DnsBackendHealth> {
+        log::debug!("check_dns_backend");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::CheckDnsBackend(tx))?;
+        let health = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::DnsBackendHealth::from(health)))
+    }
+
     async fn set_openvpn_mssfix(&self, request: Request<u32>) -> ServiceResult<()> {
         let mssfix = request.into_inner();
         let mssfix = if mssfix != 0 {
@@ -334,6 +825,61 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_wireguard_address_family(
+        &self,
+        request: Request<types::IpVersionConstraint>,
+    ) -> ServiceResult<()> {
+        let address_family =
+            IpVersion::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("set_wireguard_address_family({})", address_family);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardAddressFamily(
+            tx,
+            Some(address_family),
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn reset_wireguard_address_family(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("reset_wireguard_address_family");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardAddressFamily(tx, None))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_wireguard_dns_options(
+        &self,
+        request: Request<types::WireguardDnsOptions>,
+    ) -> ServiceResult<()> {
+        let options = request.into_inner();
+        let ip_version = options
+            .ip_version
+            .map(IpVersion::try_from)
+            .transpose()
+            .map_err(map_protobuf_type_err)?;
+        log::debug!(
+            "set_wireguard_dns_options({:?}, {})",
+            ip_version,
+            options.prefer_ipv6
+        );
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardDnsOptions(
+            tx,
+            ip_version,
+            options.prefer_ipv6,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_enable_ipv6(&self, request: Request<bool>) -> ServiceResult<()> {
         let enable_ipv6 = request.into_inner();
         log::debug!("set_enable_ipv6({})", enable_ipv6);
@@ -387,11 +933,16 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_daemon_error)
     }
 
-    async fn login_account(&self, request: Request<AccountToken>) -> ServiceResult<()> {
-        log::debug!("login_account");
-        let account_token = request.into_inner();
+    async fn login_account(&self, request: Request<types::LoginRequest>) -> ServiceResult<()> {
+        let request = request.into_inner();
+        let mode = if request.in_memory {
+            LoginMode::InMemory
+        } else {
+            LoginMode::Remember
+        };
+        log::debug!("login_account({:?})", mode);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::LoginAccount(tx, account_token))?;
+        self.send_command_to_daemon(DaemonCommand::LoginAccount(tx, request.account_token, mode))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
@@ -416,7 +967,7 @@ impl ManagementService for ManagementServiceImpl {
         let account_token = request.into_inner();
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::GetAccountData(tx, account_token))?;
-        let result = self.wait_for_result(rx).await?;
+        let result = self.wait_for_api_result(rx).await?;
         result
             .map(|account_data| {
                 Response::new(types::AccountData {
@@ -454,11 +1005,57 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_daemon_error)
     }
 
+    async fn set_app_foreground(&self, request: Request<Streaming<bool>>) -> ServiceResult<()> {
+        log::debug!("set_app_foreground");
+        let mut stream = request.into_inner();
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::NewForegroundClient(tx))?;
+        // The frontend is in the background once the stream ends, even if it ends with an error
+        let mut client = self.wait_for_result(rx).await?;
+        while let Some(foreground) = stream.message().await? {
+            client.set_foreground(foreground);
+        }
+        Ok(Response::new(()))
+    }
+
+    async fn get_frontend_values(
+        &self,
+        request: Request<String>,
+    ) -> ServiceResult<types::FrontendValues> {
+        let namespace = request.into_inner();
+        log::debug!("get_frontend_values({})", namespace);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetFrontendValues(tx, namespace))?;
+        self.wait_for_result(rx).await.map(|values| {
+            Response::new(types::FrontendValues {
+                values: values.into_iter().collect(),
+            })
+        })
+    }
+
+    async fn set_frontend_value(
+        &self,
+        request: Request<types::FrontendValue>,
+    ) -> ServiceResult<()> {
+        let types::FrontendValue {
+            namespace,
+            key,
+            value,
+        } = request.into_inner();
+        log::debug!("set_frontend_value({}, {})", namespace, key);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetFrontendValue(tx, namespace, key, value))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
     async fn get_www_auth_token(&self, _: Request<()>) -> ServiceResult<String> {
         log::debug!("get_www_auth_token");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::GetWwwAuthToken(tx))?;
-        let result = self.wait_for_result(rx).await?;
+        let result = self.wait_for_api_result(rx).await?;
         result.map(Response::new).map_err(|error| {
             log::error!(
                 "Unable to get account data from API: {}",
@@ -517,7 +1114,10 @@ impl ManagementService for ManagementServiceImpl {
         let (tx, rx) = oneshot::channel();
         let token = request.into_inner();
         self.send_command_to_daemon(DaemonCommand::ListDevices(tx, token))?;
-        let device = self.wait_for_result(rx).await?.map_err(map_daemon_error)?;
+        let device = self
+            .wait_for_api_result(rx)
+            .await?
+            .map_err(map_daemon_error)?;
         Ok(Response::new(types::DeviceList::from(device)))
     }
 
@@ -542,10 +1142,18 @@ impl ManagementService for ManagementServiceImpl {
         request: Request<types::Duration>,
     ) -> ServiceResult<()> {
         let interval: RotationInterval = Duration::try_from(request.into_inner())
-            .map_err(|_| Status::invalid_argument("unexpected negative rotation interval"))?
+            .map_err(|_| {
+                with_error_kind(
+                    Status::invalid_argument("unexpected negative rotation interval"),
+                    ErrorKind::InvalidArgument,
+                )
+            })?
             .try_into()
             .map_err(|error: RotationIntervalError| {
-                Status::invalid_argument(error.display_chain())
+                with_error_kind(
+                    Status::invalid_argument(error.display_chain()),
+                    ErrorKind::InvalidArgument,
+                )
             })?;
 
         log::debug!("set_wireguard_rotation_interval({:?})", interval);
@@ -587,7 +1195,10 @@ impl ManagementService for ManagementServiceImpl {
         let key = self.wait_for_result(rx).await?.map_err(map_daemon_error)?;
         match key {
             Some(key) => Ok(Response::new(types::PublicKey::from(key))),
-            None => Err(Status::not_found("no WireGuard key was found")),
+            None => Err(with_error_kind(
+                Status::not_found("no WireGuard key was found"),
+                ErrorKind::NoWireguardKey,
+            )),
         }
     }
 
@@ -603,10 +1214,12 @@ impl ManagementService for ManagementServiceImpl {
             log::debug!("get_split_tunnel_processes");
             let (tx, rx) = oneshot::channel();
             self.send_command_to_daemon(DaemonCommand::GetSplitTunnelProcesses(tx))?;
-            let pids = self
-                .wait_for_result(rx)
-                .await?
-                .map_err(|error| Status::failed_precondition(error.to_string()))?;
+            let pids = self.wait_for_result(rx).await?.map_err(|error| {
+                with_error_kind(
+                    Status::failed_precondition(error.to_string()),
+                    ErrorKind::SplitTunnelError,
+                )
+            })?;
 
             let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
             tokio::spawn(async move {
@@ -630,9 +1243,12 @@ impl ManagementService for ManagementServiceImpl {
         log::debug!("add_split_tunnel_process");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::AddSplitTunnelProcess(tx, pid))?;
-        self.wait_for_result(rx)
-            .await?
-            .map_err(|error| Status::failed_precondition(error.to_string()))?;
+        self.wait_for_result(rx).await?.map_err(|error| {
+            with_error_kind(
+                Status::failed_precondition(error.to_string()),
+                ErrorKind::SplitTunnelError,
+            )
+        })?;
         Ok(Response::new(()))
     }
     #[cfg(not(target_os = "linux"))]
@@ -646,9 +1262,12 @@ impl ManagementService for ManagementServiceImpl {
         log::debug!("remove_split_tunnel_process");
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::RemoveSplitTunnelProcess(tx, pid))?;
-        self.wait_for_result(rx)
-            .await?
-            .map_err(|error| Status::failed_precondition(error.to_string()))?;
+        self.wait_for_result(rx).await?.map_err(|error| {
+            with_error_kind(
+                Status::failed_precondition(error.to_string()),
+                ErrorKind::SplitTunnelError,
+            )
+        })?;
         Ok(Response::new(()))
     }
     #[cfg(not(target_os = "linux"))]
@@ -662,9 +1281,12 @@ impl ManagementService for ManagementServiceImpl {
             log::debug!("clear_split_tunnel_processes");
             let (tx, rx) = oneshot::channel();
             self.send_command_to_daemon(DaemonCommand::ClearSplitTunnelProcesses(tx))?;
-            self.wait_for_result(rx)
-                .await?
-                .map_err(|error| Status::failed_precondition(error.to_string()))?;
+            self.wait_for_result(rx).await?.map_err(|error| {
+                with_error_kind(
+                    Status::failed_precondition(error.to_string()),
+                    ErrorKind::SplitTunnelError,
+                )
+            })?;
             Ok(Response::new(()))
         }
         #[cfg(not(target_os = "linux"))]
@@ -673,6 +1295,28 @@ impl ManagementService for ManagementServiceImpl {
         }
     }
 
+    #[cfg(target_os = "linux")]
+    async fn set_split_tunnel_mode(
+        &self,
+        request: Request<types::SplitTunnelMode>,
+    ) -> ServiceResult<()> {
+        let mode =
+            SplitTunnelMode::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        log::debug!("set_split_tunnel_mode({})", mode);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSplitTunnelMode(tx, mode))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_split_tunnel_mode(&self, _: Request<types::SplitTunnelMode>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "Split tunneling modes are only supported on Linux",
+        ))
+    }
+
     #[cfg(windows)]
     async fn add_split_tunnel_app(&self, request: Request<String>) -> ServiceResult<()> {
         log::debug!("add_split_tunnel_app");
@@ -816,6 +1460,41 @@ impl ManagementServiceImpl {
     async fn wait_for_result<T>(&self, rx: oneshot::Receiver<T>) -> Result<T, Status> {
         rx.await.map_err(|_| Status::internal("sender was dropped"))
     }
+
+    /// Like [`Self::wait_for_result`], but gives up after [`API_RPC_TIMEOUT`]. Dropping `rx`
+    /// lets the daemon abort the underlying operation, if it supports that.
+    async fn wait_for_api_result<T>(&self, rx: oneshot::Receiver<T>) -> Result<T, Status> {
+        tokio::time::timeout(API_RPC_TIMEOUT, self.wait_for_result(rx))
+            .await
+            .map_err(|_| Status::deadline_exceeded("timed out waiting for the daemon"))?
+    }
+}
+
+/// Waits for tunnel states from `events` until `state` is connected or has failed. The state
+/// may still be disconnected when the target state has just been changed, so a disconnected
+/// state only ends the wait once connecting has started.
+async fn wait_for_connection(
+    events: &mut tokio::sync::mpsc::UnboundedReceiver<SharedDaemonEvent>,
+    mut state: types::TunnelState,
+) -> Result<types::TunnelState, Status> {
+    use types::tunnel_state::State;
+
+    let mut started_connecting = false;
+    loop {
+        match state.state {
+            Some(State::Connected(_)) | Some(State::Error(_)) => return Ok(state),
+            Some(State::Disconnected(_)) if started_connecting => return Ok(state),
+            Some(State::Connecting(_)) => started_connecting = true,
+            _ => (),
+        }
+        let event = events
+            .recv()
+            .await
+            .ok_or_else(|| Status::internal("the event listener was dropped"))?;
+        if let Some(daemon_event::Event::TunnelState(new_state)) = &event.event().event {
+            state = new_state.clone();
+        }
+    }
 }
 
 pub struct ManagementInterfaceServer(());
@@ -825,19 +1504,31 @@ impl ManagementInterfaceServer {
         tunnel_tx: DaemonCommandSender,
     ) -> Result<(String, ManagementInterfaceEventBroadcaster), Error> {
         let subscriptions = Arc::<RwLock<Vec<EventsListenerSender>>>::default();
+        let event_history = Arc::<Mutex<EventHistory>>::default();
 
         let socket_path = mullvad_paths::get_rpc_socket_path()
             .to_string_lossy()
             .to_string();
 
         let (server_abort_tx, server_abort_rx) = mpsc::channel(0);
+        let event_subscriber = EventSubscriber {
+            subscriptions: subscriptions.clone(),
+            event_history: event_history.clone(),
+        };
         let server = ManagementServiceImpl {
             daemon_tx: tunnel_tx,
-            subscriptions: subscriptions.clone(),
+            event_history: event_history.clone(),
+            event_subscriber: event_subscriber.clone(),
         };
-        let join_handle = mullvad_management_interface::spawn_rpc_server(server, async move {
-            server_abort_rx.into_future().await;
-        })
+        let access_policy = AccessPolicyHandle::default();
+        let join_handle = mullvad_management_interface::spawn_rpc_server(
+            server,
+            event_subscriber,
+            async move {
+                server_abort_rx.into_future().await;
+            },
+            access_policy.clone(),
+        )
         .await
         .map_err(Error::SetupError)?;
 
@@ -852,16 +1543,42 @@ impl ManagementInterfaceServer {
             socket_path,
             ManagementInterfaceEventBroadcaster {
                 subscriptions,
+                event_history,
+                access_policy,
                 _close_handle: server_abort_tx,
             },
         ))
     }
 }
 
+/// Registers the clients that listen for events.
+#[derive(Clone)]
+struct EventSubscriber {
+    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    event_history: Arc<Mutex<EventHistory>>,
+}
+
+impl EventSubscriptions for EventSubscriber {
+    fn subscribe(&self) -> tokio::sync::mpsc::UnboundedReceiver<SharedDaemonEvent> {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let mut subscriptions = self.subscriptions.write();
+        // Cache files are checked before any frontend can connect, so the result is repeated to
+        // every new listener.
+        if let Some(event) = self.event_history.lock().quarantined_files.clone() {
+            let _ = tx.send(SharedDaemonEvent::new(event));
+        }
+        subscriptions.push(tx);
+        rx
+    }
+}
+
 /// A handle that allows broadcasting messages to all subscribers of the management interface.
 #[derive(Clone)]
 pub struct ManagementInterfaceEventBroadcaster {
     subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    event_history: Arc<Mutex<EventHistory>>,
+    access_policy: AccessPolicyHandle,
     _close_handle: mpsc::Sender<()>,
 }
 
@@ -877,6 +1594,10 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
 
     /// Sends settings to all `settings` subscribers of the management interface.
     fn notify_settings(&self, settings: Settings) {
+        self.access_policy.set(settings.management_access.clone());
+        if !self.has_subscribers() {
+            return;
+        }
         log::debug!("Broadcasting new settings");
         self.notify(types::DaemonEvent {
             event: Some(daemon_event::Event::Settings(types::Settings::from(
@@ -886,11 +1607,14 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
     }
 
     /// Sends relays to all subscribers of the management interface.
-    fn notify_relay_list(&self, relay_list: RelayList) {
+    fn notify_relay_list(&self, relay_list: Arc<RelayList>) {
+        if !self.has_subscribers() {
+            return;
+        }
         log::debug!("Broadcasting new relay list");
         self.notify(types::DaemonEvent {
             event: Some(daemon_event::Event::RelayList(types::RelayList::from(
-                relay_list,
+                RelayList::clone(&relay_list),
             ))),
         })
     }
@@ -921,13 +1645,98 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
             )),
         })
     }
+
+    fn notify_auto_connect_decision(&self, decision: AutoConnectDecision) {
+        log::debug!("Broadcasting auto-connect decision");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::AutoConnectDecision(
+                types::AutoConnectDecision::from(decision),
+            )),
+        })
+    }
+
+    fn notify_conflicting_software(&self, conflicts: Vec<ConflictingSoftware>) {
+        log::debug!("Broadcasting conflicting software");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::ConflictingSoftware(
+                types::ConflictingSoftwareList::from(conflicts),
+            )),
+        })
+    }
+
+    fn notify_quarantined_files(&self, files: Vec<QuarantinedFile>) {
+        log::debug!("Broadcasting quarantined cache files");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::QuarantinedFiles(
+                types::QuarantinedFileList::from(files),
+            )),
+        })
+    }
 }
 
 impl ManagementInterfaceEventBroadcaster {
+    /// Used to skip converting large events that nobody would receive.
+    fn has_subscribers(&self) -> bool {
+        !self.subscriptions.read().is_empty()
+    }
+
     fn notify(&self, value: types::DaemonEvent) {
+        self.event_history.lock().record(&value);
+        let value = SharedDaemonEvent::new(value);
         let mut subscriptions = self.subscriptions.write();
         // TODO: using write-lock everywhere. use a mutex instead?
-        subscriptions.retain(|tx| tx.send(Ok(value.clone())).is_ok());
+        subscriptions.retain(|tx| tx.send(value.clone()).is_ok());
+    }
+}
+
+/// The most recent events sent by [`ManagementInterfaceEventBroadcaster`], so that clients can
+/// see what happened before they started listening.
+#[derive(Default)]
+struct EventHistory {
+    events: VecDeque<types::RecordedEvent>,
+    /// The most recent list of quarantined cache files, which is sent to new listeners.
+    quarantined_files: Option<types::DaemonEvent>,
+}
+
+impl EventHistory {
+    /// Records `event` if it is worth keeping. Large events that only describe the current
+    /// configuration are skipped, as are device events, which contain the account number.
+    fn record(&mut self, event: &types::DaemonEvent) {
+        let keep = matches!(
+            event.event,
+            Some(
+                daemon_event::Event::TunnelState(_)
+                    | daemon_event::Event::AutoConnectDecision(_)
+                    | daemon_event::Event::ConflictingSoftware(_)
+                    | daemon_event::Event::QuarantinedFiles(_)
+            )
+        );
+        if !keep {
+            return;
+        }
+        if let Some(daemon_event::Event::QuarantinedFiles(_)) = event.event {
+            self.quarantined_files = Some(event.clone());
+        }
+        if self.events.len() >= EVENT_HISTORY_SIZE {
+            self.events.pop_front();
+        }
+        let now = chrono::Utc::now();
+        self.events.push_back(types::RecordedEvent {
+            time: Some(types::Timestamp {
+                seconds: now.timestamp(),
+                nanos: now.timestamp_subsec_nanos() as i32,
+            }),
+            event: Some(event.clone()),
+        });
+    }
+}
+
+/// Returns whether a log target is `module` or one of its submodules. Every target is in the empty
+/// module.
+fn is_in_module(target: &str, module: &str) -> bool {
+    match target.strip_prefix(module) {
+        Some(rest) => module.is_empty() || rest.is_empty() || rest.starts_with("::"),
+        None => false,
     }
 }
 
@@ -938,7 +1747,10 @@ fn map_daemon_error(error: crate::Error) -> Status {
     match error {
         DaemonError::RestError(error) => map_rest_error(&error),
         DaemonError::SettingsError(error) => map_settings_error(error),
-        DaemonError::AlreadyLoggedIn => Status::already_exists(error.to_string()),
+        DaemonError::AlreadyLoggedIn => with_error_kind(
+            Status::already_exists(error.to_string()),
+            ErrorKind::AlreadyLoggedIn,
+        ),
         DaemonError::LoginError(error) => map_device_error(&error),
         DaemonError::LogoutError(error) => map_device_error(&error),
         DaemonError::KeyRotationError(error) => map_device_error(&error),
@@ -949,10 +1761,16 @@ fn map_daemon_error(error: crate::Error) -> Status {
         #[cfg(windows)]
         DaemonError::SplitTunnelError(error) => map_split_tunnel_error(error),
         DaemonError::AccountHistory(error) => map_account_history_error(error),
-        DaemonError::NoAccountToken | DaemonError::NoAccountTokenHistory => {
-            Status::unauthenticated(error.to_string())
-        }
-        error => Status::unknown(error.to_string()),
+        DaemonError::FrontendStore(error) => map_frontend_store_error(error),
+        DaemonError::TunnelNotConnected => with_error_kind(
+            Status::failed_precondition(error.to_string()),
+            ErrorKind::TunnelNotConnected,
+        ),
+        DaemonError::NoAccountToken | DaemonError::NoAccountTokenHistory => with_error_kind(
+            Status::unauthenticated(error.to_string()),
+            ErrorKind::NoAccount,
+        ),
+        error => with_error_kind(Status::unknown(error.to_string()), ErrorKind::UnknownError),
     }
 }
 
@@ -961,7 +1779,7 @@ fn map_daemon_error(error: crate::Error) -> Status {
 fn map_split_tunnel_error(error: talpid_core::split_tunnel::Error) -> Status {
     use talpid_core::split_tunnel::Error;
 
-    match &error {
+    let status = match &error {
         Error::RegisterIps(io_error) | Error::SetConfiguration(io_error) => {
             if io_error.kind() == std::io::ErrorKind::NotFound {
                 Status::not_found(format!("{}: {}", error, io_error))
@@ -970,20 +1788,61 @@ fn map_split_tunnel_error(error: talpid_core::split_tunnel::Error) -> Status {
             }
         }
         _ => Status::unknown(error.to_string()),
-    }
+    };
+    with_error_kind(status, ErrorKind::SplitTunnelError)
 }
 
 /// Converts a REST API error into a tonic status.
 fn map_rest_error(error: &RestError) -> Status {
     match error {
-        RestError::ApiError(status, message)
-            if *status == StatusCode::UNAUTHORIZED || *status == StatusCode::FORBIDDEN =>
-        {
-            Status::new(Code::Unauthenticated, message)
+        RestError::ApiError(status, code, message) => {
+            let message = match message {
+                Some(message) => format!("{}: {}", code, message),
+                None => code.to_string(),
+            };
+            let (status, kind) = match code {
+                ApiError::InvalidAccount | ApiError::InvalidAccessToken => {
+                    (Status::unauthenticated(message), ErrorKind::InvalidAccount)
+                }
+                ApiError::MaxDevicesReached => (
+                    Status::resource_exhausted(message),
+                    ErrorKind::MaxDevicesReached,
+                ),
+                ApiError::DeviceNotFound => (Status::not_found(message), ErrorKind::DeviceNotFound),
+                ApiError::InvalidVoucher => (
+                    Status::not_found(INVALID_VOUCHER_MESSAGE),
+                    ErrorKind::InvalidVoucher,
+                ),
+                ApiError::VoucherUsed => (
+                    Status::resource_exhausted(USED_VOUCHER_MESSAGE),
+                    ErrorKind::UsedVoucher,
+                ),
+                ApiError::PubkeyInUse => (Status::already_exists(message), ErrorKind::PubkeyInUse),
+                ApiError::Unknown | ApiError::Other(_)
+                    if *status == StatusCode::UNAUTHORIZED || *status == StatusCode::FORBIDDEN =>
+                {
+                    (Status::unauthenticated(message), ErrorKind::InvalidAccount)
+                }
+                ApiError::Unknown | ApiError::Other(_) => (
+                    Status::unknown(format!("REST error: {}", error)),
+                    ErrorKind::UnknownError,
+                ),
+            };
+            let metadata = HashMap::from([("api_code".to_owned(), code.code().to_owned())]);
+            with_error_details(status, kind, metadata)
         }
-        RestError::TimeoutError(_elapsed) => Status::deadline_exceeded("API request timed out"),
-        RestError::HyperError(_) => Status::unavailable("Cannot reach the API"),
-        error => Status::unknown(format!("REST error: {}", error)),
+        RestError::TimeoutError(_elapsed) => with_error_kind(
+            Status::deadline_exceeded("API request timed out"),
+            ErrorKind::ApiTimeout,
+        ),
+        RestError::HyperError(_) => with_error_kind(
+            Status::unavailable("Cannot reach the API"),
+            ErrorKind::ApiUnreachable,
+        ),
+        error => with_error_kind(
+            Status::unknown(format!("REST error: {}", error)),
+            ErrorKind::UnknownError,
+        ),
     }
 }
 
@@ -993,48 +1852,144 @@ fn map_settings_error(error: settings::Error) -> Status {
         settings::Error::DeleteError(..)
         | settings::Error::WriteError(..)
         | settings::Error::ReadError(..)
-        | settings::Error::SetPermissions(..) => {
-            Status::new(Code::FailedPrecondition, error.to_string())
-        }
-        settings::Error::SerializeError(..) | settings::Error::ParseError(..) => {
-            Status::new(Code::Internal, error.to_string())
+        | settings::Error::SetPermissions(..) => with_error_kind(
+            Status::new(Code::FailedPrecondition, error.to_string()),
+            ErrorKind::SettingsIoError,
+        ),
+        settings::Error::SerializeError(..) | settings::Error::ParseError(..) => with_error_kind(
+            Status::new(Code::Internal, error.to_string()),
+            ErrorKind::SettingsFormatError,
+        ),
+        settings::Error::IncompatibleBridgeState
+        | settings::Error::IncompatibleSplitTunnelMode
+        | settings::Error::InvalidSetting(_) => with_error_kind(
+            Status::new(Code::InvalidArgument, error.to_string()),
+            ErrorKind::InvalidArgument,
+        ),
+        settings::Error::AccessMethod(ref cause) => {
+            let code = match cause {
+                access_method::Error::NotFound(_) => Code::NotFound,
+                _ => Code::InvalidArgument,
+            };
+            with_error_kind(
+                Status::new(code, error.display_chain()),
+                ErrorKind::InvalidArgument,
+            )
         }
     }
 }
 
 /// Converts an instance of [`mullvad_daemon::device::Error`] into a tonic status.
 fn map_device_error(error: &device::Error) -> Status {
-    match error {
-        device::Error::MaxDevicesReached => Status::new(Code::ResourceExhausted, error.to_string()),
-        device::Error::InvalidAccount => Status::new(Code::Unauthenticated, error.to_string()),
-        device::Error::InvalidDevice | device::Error::NoDevice => {
-            Status::new(Code::NotFound, error.to_string())
-        }
-        device::Error::InvalidVoucher => Status::new(Code::NotFound, INVALID_VOUCHER_MESSAGE),
-        device::Error::UsedVoucher => Status::new(Code::ResourceExhausted, USED_VOUCHER_MESSAGE),
-        device::Error::DeviceIoError(ref _error) => {
-            Status::new(Code::Unavailable, error.to_string())
-        }
-        device::Error::OtherRestError(error) => map_rest_error(error),
-        device::Error::ResponseFailure(error) => map_device_error(error.unpack()),
-        _ => Status::new(Code::Unknown, error.to_string()),
-    }
+    let (status, kind) = match error {
+        device::Error::MaxDevicesReached => (
+            Status::new(Code::ResourceExhausted, error.to_string()),
+            ErrorKind::MaxDevicesReached,
+        ),
+        device::Error::InvalidAccount => (
+            Status::new(Code::Unauthenticated, error.to_string()),
+            ErrorKind::InvalidAccount,
+        ),
+        device::Error::InvalidDevice | device::Error::NoDevice => (
+            Status::new(Code::NotFound, error.to_string()),
+            ErrorKind::DeviceNotFound,
+        ),
+        device::Error::InvalidVoucher => (
+            Status::new(Code::NotFound, INVALID_VOUCHER_MESSAGE),
+            ErrorKind::InvalidVoucher,
+        ),
+        device::Error::UsedVoucher => (
+            Status::new(Code::ResourceExhausted, USED_VOUCHER_MESSAGE),
+            ErrorKind::UsedVoucher,
+        ),
+        device::Error::DeviceIoError(ref _error) => (
+            Status::new(Code::Unavailable, error.to_string()),
+            ErrorKind::UnknownError,
+        ),
+        device::Error::OtherRestError(error) => return map_rest_error(error),
+        device::Error::ResponseFailure(error) => return map_device_error(error.unpack()),
+        _ => (
+            Status::new(Code::Unknown, error.to_string()),
+            ErrorKind::UnknownError,
+        ),
+    };
+    with_error_kind(status, kind)
 }
 
 /// Converts an instance of [`mullvad_daemon::account_history::Error`] into a tonic status.
 fn map_account_history_error(error: account_history::Error) -> Status {
-    match error {
+    let status = match error {
         account_history::Error::Read(..) | account_history::Error::Write(..) => {
             Status::new(Code::FailedPrecondition, error.to_string())
         }
         account_history::Error::Serialize(..) | account_history::Error::WriteCancelled(..) => {
             Status::new(Code::Internal, error.to_string())
         }
-    }
+    };
+    with_error_kind(status, ErrorKind::AccountHistoryError)
+}
+
+/// Converts an instance of [`mullvad_daemon::frontend_store::Error`] into a tonic status.
+fn map_frontend_store_error(error: frontend_store::Error) -> Status {
+    let status = match error {
+        frontend_store::Error::InvalidName | frontend_store::Error::ValueTooLong => {
+            Status::invalid_argument(error.to_string())
+        }
+        frontend_store::Error::NamespaceFull | frontend_store::Error::TooManyNamespaces => {
+            Status::resource_exhausted(error.to_string())
+        }
+        frontend_store::Error::Serialize(..) | frontend_store::Error::Write(..) => {
+            Status::internal(error.to_string())
+        }
+    };
+    with_error_kind(status, ErrorKind::FrontendStoreError)
 }
 
 fn map_protobuf_type_err(err: types::FromProtobufTypeError) -> Status {
     match err {
-        types::FromProtobufTypeError::InvalidArgument(err) => Status::invalid_argument(err),
+        types::FromProtobufTypeError::InvalidArgument(err) => {
+            with_error_kind(Status::invalid_argument(err), ErrorKind::InvalidArgument)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_in_module() {
+        assert!(is_in_module("mullvad_daemon", ""));
+        assert!(is_in_module("mullvad_daemon", "mullvad_daemon"));
+        assert!(is_in_module("mullvad_daemon::tunnel", "mullvad_daemon"));
+        assert!(!is_in_module("mullvad_daemon_extra", "mullvad_daemon"));
+        assert!(!is_in_module("mullvad_daemon", "mullvad_daemon::tunnel"));
+        assert!(!is_in_module("talpid_core::firewall", "mullvad_daemon"));
+    }
+
+    #[test]
+    fn test_event_history() {
+        let tunnel_state = types::DaemonEvent {
+            event: Some(daemon_event::Event::TunnelState(
+                types::TunnelState::default(),
+            )),
+        };
+        let settings = types::DaemonEvent {
+            event: Some(daemon_event::Event::Settings(types::Settings::default())),
+        };
+
+        let mut history = EventHistory::default();
+        history.record(&settings);
+        assert!(history.events.is_empty());
+
+        history.record(&tunnel_state);
+        assert_eq!(history.events.len(), 1);
+        assert_eq!(history.events[0].event.as_ref(), Some(&tunnel_state));
+        assert!(history.events[0].time.is_some());
+
+        for _ in 0..EVENT_HISTORY_SIZE {
+            history.record(&tunnel_state);
+        }
+        assert_eq!(history.events.len(), EVENT_HISTORY_SIZE);
     }
 }
@@ -11,7 +11,10 @@ use crate::{
     device::{self, DeviceService, PrivateAccountAndDevice, PrivateDevice},
     DaemonEventSender, InternalDaemonEvent,
 };
-use mullvad_types::{account::AccountToken, wireguard::WireguardData};
+use mullvad_types::{
+    account::{AccountToken, LoginMode},
+    wireguard::WireguardData,
+};
 use std::time::Duration;
 use talpid_core::mpsc::Sender;
 use talpid_types::ErrorExt;
@@ -82,6 +85,7 @@ async fn cache_from_wireguard_key(
             return Ok(PrivateAccountAndDevice {
                 account_token,
                 device: PrivateDevice::try_from_device(device, wg_data)?,
+                login_mode: LoginMode::default(),
             });
         }
     }
@@ -0,0 +1,70 @@
+//! Detects the network that the device is on, so that network profiles and the auto-connect
+//! policy apply without a frontend running. On platforms where this is not supported, the network
+//! reported over the management interface is used instead.
+
+use crate::DaemonEventSender;
+
+#[cfg(target_os = "linux")]
+pub(crate) fn spawn_monitor(daemon_tx: DaemonEventSender) {
+    use crate::InternalDaemonEvent;
+    use mullvad_types::auto_connect::NetworkInfo;
+    use std::time::Duration;
+    use talpid_core::mpsc::Sender;
+    use talpid_dbus::network_manager::{NetworkManager, PrimaryConnection};
+
+    /// NetworkManager is polled, since the primary connection can change without the device
+    /// going offline, such as when roaming between access points.
+    const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+    let network_manager = match NetworkManager::new() {
+        Ok(network_manager) => network_manager,
+        Err(error) => {
+            log::debug!(
+                "Not detecting the current network since NetworkManager is unavailable: {}",
+                error
+            );
+            return;
+        }
+    };
+    if network_manager.ensure_network_manager_exists().is_err() {
+        log::debug!("Not detecting the current network since NetworkManager is not running");
+        return;
+    }
+
+    std::thread::spawn(move || {
+        let mut last_network = None;
+        while !daemon_tx.is_closed() {
+            let network = match network_manager.primary_connection() {
+                Ok(None) => Some(NetworkInfo::Unknown),
+                Ok(Some(PrimaryConnection::Wired)) => Some(NetworkInfo::Wired),
+                Ok(Some(PrimaryConnection::Wifi { ssid, bssid })) => {
+                    Some(NetworkInfo::Wifi { ssid, bssid })
+                }
+                Ok(Some(PrimaryConnection::Other)) => Some(NetworkInfo::Other),
+                // The tunnel is not a network of its own. The network below it remains current.
+                Ok(Some(PrimaryConnection::Tunnel)) => None,
+                Err(error) => {
+                    log::trace!("Failed to query NetworkManager for the network: {}", error);
+                    None
+                }
+            };
+            if let Some(network) = network {
+                if last_network.as_ref() != Some(&network) {
+                    if daemon_tx
+                        .send(InternalDaemonEvent::NetworkDetected(network.clone()))
+                        .is_err()
+                    {
+                        break;
+                    }
+                    last_network = Some(network);
+                }
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn spawn_monitor(_daemon_tx: DaemonEventSender) {
+    log::debug!("Using the network reported over the management interface");
+}
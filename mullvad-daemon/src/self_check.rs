@@ -0,0 +1,269 @@
+//! Exercises the parts of the system that the daemon depends on, and reports which of them work.
+//! Every change is reverted before moving on to the next check.
+//!
+//! On Linux, the checks run in their own network and mount namespaces, which [`isolate`] sets up,
+//! so that the firewall rules, routes and DNS configuration of the host are never touched. macOS
+//! has no such namespaces, so only the checks that do not affect the rest of the system are run
+//! there.
+
+use ipnetwork::IpNetwork;
+use std::{
+    collections::HashSet,
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+};
+#[cfg(target_os = "linux")]
+use talpid_core::{
+    dns::DnsMonitor,
+    firewall::{Firewall, FirewallPolicy},
+};
+use talpid_routing::{Node, RequiredRoute, RouteManager};
+use talpid_tunnel::tun_provider::{Tun, TunConfig, TunProvider};
+use talpid_types::ErrorExt;
+
+#[cfg(target_os = "linux")]
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/// Address assigned to the temporary tunnel device.
+const TUN_ADDRESS: Ipv4Addr = Ipv4Addr::new(10, 255, 255, 254);
+
+/// TEST-NET-1 (RFC 5737), which is routed through the temporary tunnel device.
+const TEST_NETWORK: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 0);
+
+enum Outcome {
+    Pass,
+    Fail(String),
+    Skipped(&'static str),
+}
+
+impl<E: ErrorExt> From<Result<(), E>> for Outcome {
+    fn from(result: Result<(), E>) -> Self {
+        match result {
+            Ok(()) => Outcome::Pass,
+            Err(error) => Outcome::Fail(error.display_chain()),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Pass => write!(f, "PASS"),
+            Outcome::Fail(error) => write!(f, "FAIL: {}", error),
+            Outcome::Skipped(reason) => write!(f, "SKIPPED: {}", reason),
+        }
+    }
+}
+
+/// Moves the process into new network and mount namespaces, so that the checks cannot affect the
+/// host. `/etc/resolv.conf` is replaced by a private copy, and DNS is managed by editing that
+/// file, since the other DNS backends configure services that are shared with the host. This must
+/// be called before any threads are started.
+#[cfg(target_os = "linux")]
+pub fn isolate() -> Result<(), String> {
+    use nix::{
+        mount::{mount, MsFlags},
+        sched::{unshare, CloneFlags},
+    };
+
+    unshare(CloneFlags::CLONE_NEWNET | CloneFlags::CLONE_NEWNS)
+        .map_err(|error| format!("Failed to create namespaces for the self-check: {}", error))?;
+    // Keep the mounts below from propagating to the host
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )
+    .map_err(|error| format!("Failed to make the mounts private: {}", error))?;
+
+    let resolv_conf_copy = std::env::temp_dir().join(format!(
+        "mullvad-self-check-resolv.conf.{}",
+        std::process::id()
+    ));
+    std::fs::copy(RESOLV_CONF_PATH, &resolv_conf_copy)
+        .map_err(|error| format!("Failed to copy {}: {}", RESOLV_CONF_PATH, error))?;
+    let result = mount(
+        Some(&resolv_conf_copy),
+        RESOLV_CONF_PATH,
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    );
+    // The mount keeps the copy alive
+    let _ = std::fs::remove_file(&resolv_conf_copy);
+    result.map_err(|error| format!("Failed to replace {}: {}", RESOLV_CONF_PATH, error))?;
+
+    std::env::set_var("TALPID_DNS_MODULE", "static-file");
+    Ok(())
+}
+
+/// Runs every check and prints a report. Returns an error if any check failed.
+pub async fn run() -> Result<(), String> {
+    // The simulated backends accept everything, so the checks would pass without testing anything
+    if talpid_core::tunnel::fake::is_enabled() {
+        return Err(
+            "The self-check cannot run while TALPID_FAKE_TUNNEL is set, since the firewall, DNS \
+             and tunnel devices are simulated"
+                .to_owned(),
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    let firewall = check_firewall();
+    #[cfg(target_os = "macos")]
+    let firewall = Outcome::Skipped(NOT_ISOLATED);
+    let mut report = vec![("Firewall apply/rollback", firewall)];
+
+    match create_tun() {
+        Ok(tun) => {
+            report.push(("TUN device creation", Outcome::Pass));
+            let (routes, dns) = check_routes_and_dns(&tun).await;
+            report.push(("Route add/remove", routes));
+            report.push(("DNS set/restore", dns));
+        }
+        Err(error) => {
+            report.push(("TUN device creation", Outcome::Fail(error.display_chain())));
+            report.push(("Route add/remove", Outcome::Skipped("no tunnel device")));
+            report.push(("DNS set/restore", Outcome::Skipped("no tunnel device")));
+        }
+    }
+
+    for (name, outcome) in &report {
+        println!("{:<24} {}", name, outcome);
+    }
+    summarize(&report)
+}
+
+/// Why checks that would change the configuration of the whole system are skipped on macOS.
+#[cfg(target_os = "macos")]
+const NOT_ISOLATED: &str = "cannot be run without changing the configuration of the host";
+
+/// Returns an error if any check in `report` failed. Skipped checks are not failures.
+fn summarize(report: &[(&str, Outcome)]) -> Result<(), String> {
+    let failures = report
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, Outcome::Fail(_)))
+        .count();
+    if failures > 0 {
+        return Err(format!("{} of {} checks failed", failures, report.len()));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn check_firewall() -> Outcome {
+    let mut firewall = match Firewall::new(mullvad_types::TUNNEL_FWMARK) {
+        Ok(firewall) => firewall,
+        Err(error) => return Outcome::Fail(error.display_chain()),
+    };
+    let policy = FirewallPolicy::Blocked {
+        allow_lan: false,
+        allowed_endpoint: None,
+        allowed_link_config_traffic: Default::default(),
+        allowed_lan_hosts: vec![],
+        split_tunnel_mode: Default::default(),
+        shared_interface: None,
+    };
+    let result = firewall
+        .apply_policy(policy)
+        .and_then(|()| firewall.reset_policy());
+    Outcome::from(result)
+}
+
+fn create_tun() -> Result<Tun, talpid_tunnel::tun_provider::Error> {
+    TunProvider::new().get_tun(TunConfig {
+        addresses: vec![IpAddr::V4(TUN_ADDRESS)],
+        dns_servers: vec![],
+        routes: vec![],
+        mtu: 1380,
+    })
+}
+
+async fn check_routes_and_dns(tun: &Tun) -> (Outcome, Outcome) {
+    let mut route_manager = match RouteManager::new(
+        HashSet::new(),
+        #[cfg(target_os = "linux")]
+        mullvad_types::TUNNEL_FWMARK,
+        #[cfg(target_os = "linux")]
+        mullvad_types::TUNNEL_TABLE_ID,
+    )
+    .await
+    {
+        Ok(route_manager) => route_manager,
+        Err(error) => {
+            return (
+                Outcome::Fail(error.display_chain()),
+                Outcome::Skipped("no route manager"),
+            )
+        }
+    };
+
+    let route = RequiredRoute::new(
+        IpNetwork::new(IpAddr::V4(TEST_NETWORK), 24).unwrap(),
+        Node::device(tun.interface_name().to_owned()),
+    );
+    let routes = match route_manager
+        .add_routes([route].into_iter().collect())
+        .await
+    {
+        Ok(()) => Outcome::from(route_manager.clear_routes()),
+        Err(error) => Outcome::Fail(error.display_chain()),
+    };
+
+    #[cfg(target_os = "linux")]
+    let dns = check_dns(tun.interface_name().to_owned(), &route_manager).await;
+    #[cfg(target_os = "macos")]
+    let dns = Outcome::Skipped(NOT_ISOLATED);
+
+    route_manager.stop().await;
+    (routes, dns)
+}
+
+#[cfg(target_os = "linux")]
+async fn check_dns(interface: String, route_manager: &RouteManager) -> Outcome {
+    let route_manager_handle = match route_manager.handle() {
+        Ok(handle) => handle,
+        Err(error) => return Outcome::Fail(error.display_chain()),
+    };
+    let runtime = tokio::runtime::Handle::current();
+
+    // The static file backend blocks on the runtime, so it cannot be used from its worker threads
+    tokio::task::spawn_blocking(move || {
+        let mut dns_monitor = match DnsMonitor::new(runtime, route_manager_handle) {
+            Ok(dns_monitor) => dns_monitor,
+            Err(error) => return Outcome::Fail(error.display_chain()),
+        };
+        let result = dns_monitor
+            .set(&interface, &[IpAddr::V4(TUN_ADDRESS)])
+            .and_then(|()| dns_monitor.reset());
+        Outcome::from(result)
+    })
+    .await
+    .unwrap_or_else(|error| Outcome::Fail(error.display_chain()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_skipped_checks_are_not_failures() {
+        let report = [
+            ("Passing", Outcome::Pass),
+            ("Skipped", Outcome::Skipped("not available")),
+        ];
+        assert!(summarize(&report).is_ok());
+    }
+
+    #[test]
+    fn test_failed_checks_are_counted() {
+        let report = [
+            ("Passing", Outcome::Pass),
+            ("Failing", Outcome::Fail("error".to_owned())),
+            ("Skipped", Outcome::Skipped("no tunnel device")),
+        ];
+        assert_eq!(summarize(&report).unwrap_err(), "1 of 3 checks failed");
+    }
+}
@@ -1,18 +1,29 @@
 #[cfg(not(target_os = "android"))]
 use futures::TryFutureExt;
 use mullvad_types::{
-    relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
-    settings::{DnsOptions, Settings},
+    access_method::{self, AccessMethod},
+    auto_connect::AutoConnectPolicy,
+    network::NetworkProfile,
+    relay_constraints::{
+        BridgeSettings, BridgeState, ObfuscationSettings, PortSelectionStep, RelaySettingsUpdate,
+    },
+    settings::{DnsOptions, ManagementAccessPolicy, Settings, SettingsChanges, SettingsUpdate},
     wireguard::RotationInterval,
 };
 use rand::Rng;
 #[cfg(target_os = "windows")]
 use std::collections::HashSet;
+#[cfg(not(target_os = "android"))]
+use std::net::IpAddr;
 use std::{
     ops::Deref,
     path::{Path, PathBuf},
 };
-use talpid_types::ErrorExt;
+#[cfg(target_os = "linux")]
+use talpid_types::cgroup::SplitTunnelMode;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use talpid_types::net::AllowedLinkConfigTraffic;
+use talpid_types::{net::IpVersion, ErrorExt};
 use tokio::{
     fs,
     io::{self, AsyncWriteExt},
@@ -41,6 +52,21 @@ pub enum Error {
 
     #[error(display = "Unable to set settings file permissions")]
     SetPermissions(#[error(source)] io::Error),
+
+    #[error(display = "Bridge mode cannot be enabled while OpenVPN uses UDP")]
+    IncompatibleBridgeState,
+
+    #[error(
+        display = "Split tunneling include mode cannot be used while \"Always require VPN\" is \
+                   enabled"
+    )]
+    IncompatibleSplitTunnelMode,
+
+    #[error(display = "Invalid API access method")]
+    AccessMethod(#[error(source)] access_method::Error),
+
+    #[error(display = "Invalid setting: {}", _0)]
+    InvalidSetting(String),
 }
 
 #[derive(Debug)]
@@ -87,6 +113,12 @@ impl SettingsPersister {
         if crate::version::is_beta_version() {
             should_save |= Self::update_field(&mut settings.show_beta_releases, true);
         }
+        // Older versions allowed bridge mode to be turned on while OpenVPN used UDP
+        if settings.has_incompatible_bridge_state() {
+            log::warn!("Bridge mode is on while OpenVPN uses UDP. Setting bridge mode to auto");
+            should_save |= settings.set_bridge_state(BridgeState::Auto);
+        }
+        should_save |= settings.sanitize();
 
         let mut persister = SettingsPersister { settings, path };
 
@@ -206,8 +238,9 @@ impl SettingsPersister {
         &mut self,
         update: RelaySettingsUpdate,
     ) -> Result<bool, Error> {
-        let should_save = self.settings.update_relay_settings(update);
-        self.update(should_save).await
+        let mut settings = self.settings.clone();
+        let should_save = settings.update_relay_settings(update);
+        self.update_checked(settings, should_save).await
     }
 
     pub async fn set_allow_lan(&mut self, allow_lan: bool) -> Result<bool, Error> {
@@ -215,15 +248,71 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub async fn set_allowed_multicast_groups(
+        &mut self,
+        groups: Vec<IpAddr>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.allowed_multicast_groups, groups);
+        self.update(should_save).await
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub async fn set_allowed_link_config_traffic(
+        &mut self,
+        allowed: AllowedLinkConfigTraffic,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.allowed_link_config_traffic, allowed);
+        self.update(should_save).await
+    }
+
+    #[cfg(not(target_os = "android"))]
+    pub async fn set_allowed_lan_hosts(&mut self, hosts: Vec<IpAddr>) -> Result<bool, Error> {
+        if let Some(host) = hosts
+            .iter()
+            .find(|host| !talpid_types::net::is_lan_address(host))
+        {
+            return Err(Error::InvalidSetting(format!(
+                "{} is not a private or link-local address",
+                host
+            )));
+        }
+        let should_save = Self::update_field(&mut self.settings.allowed_lan_hosts, hosts);
+        self.update(should_save).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn set_split_tunnel_mode(&mut self, mode: SplitTunnelMode) -> Result<bool, Error> {
+        let mut settings = self.settings.clone();
+        let should_save = Self::update_field(&mut settings.split_tunnel_mode, mode);
+        self.update_checked(settings, should_save).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn set_shared_interface(&mut self, interface: Option<String>) -> Result<bool, Error> {
+        if let Some(interface) = &interface {
+            if !talpid_types::net::is_valid_interface_name(interface) {
+                return Err(Error::InvalidSetting(format!(
+                    "\"{}\" is not a network interface name",
+                    interface
+                )));
+            }
+        }
+        let should_save = Self::update_field(&mut self.settings.shared_interface, interface);
+        self.update(should_save).await
+    }
+
     pub async fn set_block_when_disconnected(
         &mut self,
         block_when_disconnected: bool,
     ) -> Result<bool, Error> {
+        let mut settings = self.settings.clone();
         let should_save = Self::update_field(
-            &mut self.settings.block_when_disconnected,
+            &mut settings.block_when_disconnected,
             block_when_disconnected,
         );
-        self.update(should_save).await
+        self.update_checked(settings, should_save).await
     }
 
     pub async fn set_auto_connect(&mut self, auto_connect: bool) -> Result<bool, Error> {
@@ -231,6 +320,23 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_auto_connect_policy(
+        &mut self,
+        auto_connect_policy: AutoConnectPolicy,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.auto_connect_policy, auto_connect_policy);
+        self.update(should_save).await
+    }
+
+    pub async fn set_network_profiles(
+        &mut self,
+        network_profiles: Vec<NetworkProfile>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.network_profiles, network_profiles);
+        self.update(should_save).await
+    }
+
     pub async fn set_openvpn_mssfix(&mut self, openvpn_mssfix: Option<u16>) -> Result<bool, Error> {
         let should_save = Self::update_field(
             &mut self.settings.tunnel_options.openvpn.mssfix,
@@ -264,6 +370,10 @@ impl SettingsPersister {
     }
 
     pub async fn set_dns_options(&mut self, options: DnsOptions) -> Result<bool, Error> {
+        options
+            .custom_options
+            .validate()
+            .map_err(|error| Error::InvalidSetting(error.to_owned()))?;
         let should_save =
             Self::update_field(&mut self.settings.tunnel_options.dns_options, options);
         self.update(should_save).await
@@ -275,6 +385,33 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_wireguard_address_family(
+        &mut self,
+        address_family: Option<IpVersion>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self
+                .settings
+                .tunnel_options
+                .wireguard
+                .options
+                .address_family,
+            address_family,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_wireguard_dns_options(
+        &mut self,
+        ip_version: Option<IpVersion>,
+        prefer_ipv6: bool,
+    ) -> Result<bool, Error> {
+        let options = &mut self.settings.tunnel_options.wireguard.options;
+        let ip_version_changed = Self::update_field(&mut options.dns_ip_version, ip_version);
+        let preference_changed = Self::update_field(&mut options.prefer_ipv6_dns, prefer_ipv6);
+        self.update(ip_version_changed || preference_changed).await
+    }
+
     pub async fn set_wireguard_rotation_interval(
         &mut self,
         interval: Option<RotationInterval>,
@@ -295,6 +432,52 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_system_notifications(
+        &mut self,
+        system_notifications: bool,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.system_notifications,
+            system_notifications,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_management_access_policy(
+        &mut self,
+        policy: ManagementAccessPolicy,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.management_access, policy);
+        self.update(should_save).await
+    }
+
+    pub async fn add_api_access_method(&mut self, method: AccessMethod) -> Result<(), Error> {
+        check_access_method_supported(&method)?;
+        access_method::add(&mut self.settings.api_access_methods, method)
+            .map_err(Error::AccessMethod)?;
+        self.save().await
+    }
+
+    /// Replaces the API access method named `name`.
+    pub async fn update_api_access_method(
+        &mut self,
+        name: &str,
+        method: AccessMethod,
+    ) -> Result<bool, Error> {
+        check_access_method_supported(&method)?;
+        let previous_methods = self.settings.api_access_methods.clone();
+        access_method::update(&mut self.settings.api_access_methods, name, method)
+            .map_err(Error::AccessMethod)?;
+        let should_save = self.settings.api_access_methods != previous_methods;
+        self.update(should_save).await
+    }
+
+    pub async fn remove_api_access_method(&mut self, name: &str) -> Result<(), Error> {
+        access_method::remove(&mut self.settings.api_access_methods, name)
+            .map_err(Error::AccessMethod)?;
+        self.save().await
+    }
+
     pub async fn set_bridge_settings(
         &mut self,
         bridge_settings: BridgeSettings,
@@ -304,8 +487,9 @@ impl SettingsPersister {
     }
 
     pub async fn set_bridge_state(&mut self, bridge_state: BridgeState) -> Result<bool, Error> {
-        let should_save = self.settings.set_bridge_state(bridge_state);
-        self.update(should_save).await
+        let mut settings = self.settings.clone();
+        let should_save = settings.set_bridge_state(bridge_state);
+        self.update_checked(settings, should_save).await
     }
 
     #[cfg(windows)]
@@ -359,6 +543,47 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_auto_port_order(
+        &mut self,
+        auto_port_order: Vec<PortSelectionStep>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.auto_port_order, auto_port_order);
+        self.update(should_save).await
+    }
+
+    /// Applies all changes in `update` and saves the result. If the settings cannot be saved, none
+    /// of the changes are kept.
+    pub async fn apply_update(&mut self, update: SettingsUpdate) -> Result<SettingsChanges, Error> {
+        if let Some(timeouts) = &update.connection_timeouts {
+            timeouts
+                .validate()
+                .map_err(|error| Error::InvalidSetting(error.to_owned()))?;
+        }
+        if let Some(dns_options) = &update.dns_options {
+            dns_options
+                .custom_options
+                .validate()
+                .map_err(|error| Error::InvalidSetting(error.to_owned()))?;
+        }
+        let previous_settings = self.settings.clone();
+        let changes = self.settings.apply_update(update);
+        if self.settings.has_incompatible_bridge_state() {
+            self.settings = previous_settings;
+            return Err(Error::IncompatibleBridgeState);
+        }
+        if self.settings.has_incompatible_split_tunnel_mode() {
+            self.settings = previous_settings;
+            return Err(Error::IncompatibleSplitTunnelMode);
+        }
+        if changes.any() {
+            if let Err(error) = self.save().await {
+                self.settings = previous_settings;
+                return Err(error);
+            }
+        }
+        Ok(changes)
+    }
+
     async fn update(&mut self, should_save: bool) -> Result<bool, Error> {
         if should_save {
             self.save().await.map(|_| true)
@@ -366,6 +591,31 @@ impl SettingsPersister {
             Ok(false)
         }
     }
+
+    /// Replaces the settings with `settings` unless they combine bridge mode with UDP, or
+    /// split tunneling include mode with `block_when_disconnected`.
+    async fn update_checked(
+        &mut self,
+        settings: Settings,
+        should_save: bool,
+    ) -> Result<bool, Error> {
+        if settings.has_incompatible_bridge_state() {
+            return Err(Error::IncompatibleBridgeState);
+        }
+        if settings.has_incompatible_split_tunnel_mode() {
+            return Err(Error::IncompatibleSplitTunnelMode);
+        }
+        self.settings = settings;
+        self.update(should_save).await
+    }
+}
+
+/// Rejects access methods that this build cannot use. Proxies that only relay UDP need HTTP/3.
+pub fn check_access_method_supported(method: &AccessMethod) -> Result<(), Error> {
+    if method.proxy.requires_http3() && !cfg!(feature = "api-http3") {
+        return Err(Error::AccessMethod(access_method::Error::UdpUnsupported));
+    }
+    Ok(())
 }
 
 impl Deref for SettingsPersister {
@@ -1,3 +1,11 @@
+use std::{
+    fmt,
+    future::Future,
+    time::{Duration, Instant},
+};
+use talpid_core::firewall::Firewall;
+use talpid_types::ErrorExt;
+
 #[cfg(unix)]
 mod platform {
     use simple_signal::Signal;
@@ -55,3 +63,122 @@ pub fn is_shutdown_user_initiated() -> bool {
 }
 
 pub use self::platform::*;
+
+/// Stages of a daemon shutdown, in the order that they are run. Commands from frontends are
+/// dropped before the first stage, so that the state cannot be changed while shutting down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ShutdownStage {
+    /// Waiting for the tunnel to be disconnected.
+    DisconnectTunnel,
+    /// Finishing pending tasks, such as saving the target state and the tunnel to reuse on the
+    /// next start.
+    PersistState,
+    /// Waiting for the tunnel state machine to restore DNS and routes, and to remove the firewall
+    /// rules unless traffic should stay blocked.
+    RestoreNetwork,
+}
+
+impl ShutdownStage {
+    /// How long the stage may take before the shutdown moves on to the next stage.
+    fn timeout(&self) -> Duration {
+        match self {
+            ShutdownStage::DisconnectTunnel => Duration::from_secs(10),
+            ShutdownStage::PersistState => Duration::from_secs(5),
+            ShutdownStage::RestoreNetwork => Duration::from_secs(5),
+        }
+    }
+}
+
+impl fmt::Display for ShutdownStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            ShutdownStage::DisconnectTunnel => "disconnecting tunnel",
+            ShutdownStage::PersistState => "persisting state",
+            ShutdownStage::RestoreNetwork => "restoring network configuration",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Runs a stage of the shutdown. If the stage times out, it is abandoned so that the later
+/// stages still get to run. Returns false if the stage timed out.
+pub(crate) async fn run_stage(
+    stage: ShutdownStage,
+    stage_future: impl Future<Output = ()>,
+) -> bool {
+    run_stage_with_timeout(stage, stage.timeout(), stage_future).await
+}
+
+async fn run_stage_with_timeout(
+    stage: ShutdownStage,
+    timeout: Duration,
+    stage_future: impl Future<Output = ()>,
+) -> bool {
+    log::debug!("Shutdown: {}", stage);
+    let start = Instant::now();
+    match tokio::time::timeout(timeout, stage_future).await {
+        Ok(()) => {
+            log::debug!(
+                "Shutdown: finished {} in {} ms",
+                stage,
+                start.elapsed().as_millis()
+            );
+            true
+        }
+        Err(_) => {
+            log::error!(
+                "Shutdown: {} timed out after {} ms",
+                stage,
+                timeout.as_millis()
+            );
+            false
+        }
+    }
+}
+
+/// Removes the firewall rules of a tunnel state machine that did not exit in time, so that they
+/// do not keep blocking traffic after the daemon has exited.
+pub(crate) fn reset_firewall() {
+    log::warn!("Shutdown: removing the firewall rules of the tunnel state machine");
+    let result = Firewall::new(
+        #[cfg(target_os = "linux")]
+        mullvad_types::TUNNEL_FWMARK,
+    )
+    .and_then(|mut firewall| firewall.reset_policy());
+    if let Err(error) = result {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to reset firewall policy")
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::future;
+
+    #[tokio::test]
+    async fn test_stage_finishes() {
+        assert!(
+            run_stage_with_timeout(
+                ShutdownStage::PersistState,
+                Duration::from_millis(100),
+                future::ready(()),
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stage_times_out() {
+        assert!(
+            !run_stage_with_timeout(
+                ShutdownStage::RestoreNetwork,
+                Duration::from_millis(100),
+                future::pending(),
+            )
+            .await
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use mullvad_api::{
+    rest::{self, Error, RequestServiceHandle, RestRequest},
+    StatusCode,
+};
+use std::time::{Duration, Instant};
+
+/// The relay list is the largest response that the API serves, so it is downloaded to measure
+/// the speed.
+const SPEED_TEST_URI: &str = "https://api.mullvad.net/app/v1/relays";
+const SPEED_TEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The amount of data downloaded by a speed test, and the time that it took.
+pub struct SpeedTestResult {
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+/// Measures the download speed from the API. The request is sent like a GeoIP lookup, so it
+/// goes through the tunnel if there is one.
+pub async fn run(service: RequestServiceHandle) -> Result<SpeedTestResult, Error> {
+    let mut request = RestRequest::get(SPEED_TEST_URI)?;
+    request.set_timeout(SPEED_TEST_TIMEOUT);
+
+    let start = Instant::now();
+    let response = service.request(request).await?;
+    if response.status() != StatusCode::OK {
+        return rest::handle_error_response(response).await;
+    }
+    let body = rest::read_body(response).await?;
+    Ok(SpeedTestResult {
+        bytes: body.len() as u64,
+        duration: start.elapsed(),
+    })
+}
@@ -0,0 +1,261 @@
+//! Runs a program chosen by the administrator on every tunnel state change, so that custom
+//! routing, notifications or monitoring can be hooked into the daemon. The program is placed in
+//! the settings directory, and is given the new state in its environment:
+//!
+//! - `MULLVAD_TUNNEL_STATE`: `disconnected`, `connecting`, `connected`, `disconnecting` or `error`.
+//! - `MULLVAD_RELAY_IP`, `MULLVAD_RELAY_PORT` and `MULLVAD_RELAY_PROTOCOL`: the endpoint of the
+//!   relay, while connecting or connected.
+//! - `MULLVAD_TUNNEL_TYPE`: `wireguard` or `openvpn`, while connecting or connected.
+//! - `MULLVAD_TUNNEL_INTERFACE`: the name of the tunnel interface, while connected.
+//! - `MULLVAD_ERROR_CAUSE`: why the error state was entered.
+//!
+//! Since the daemon runs the hook with full privileges, it is only run if it is a regular file
+//! that is owned by root and is not writable by anyone else. The hook is looked up again on every
+//! state change, so it can be added or removed while the daemon is running. A hook that has not
+//! exited after [`HOOK_TIMEOUT`] is killed.
+
+use mullvad_types::states::TunnelState;
+use std::{io, os::unix::fs::MetadataExt, path::Path, time::Duration};
+use talpid_types::{net::TunnelType, ErrorExt};
+use tokio::{
+    process::Command,
+    sync::mpsc::{self, error::TrySendError},
+};
+
+/// Name of the hook program in the settings directory.
+const HOOK_FILENAME: &str = "tunnel-state-hook";
+
+/// How long the hook may run before it is killed.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of state changes that may wait for the hook. Further changes are dropped until the hook
+/// catches up.
+const HOOK_QUEUE_SIZE: usize = 16;
+
+type Environment = Vec<(&'static str, String)>;
+
+/// Runs the hook program, if there is one. Hooks run one at a time, in the order of the state
+/// changes.
+pub struct StateHook {
+    tx: mpsc::Sender<Environment>,
+    /// Environment of the last queued run. The connecting state is emitted again as the attempt
+    /// progresses, which should not run the hook again.
+    last_env: Option<Environment>,
+}
+
+impl StateHook {
+    pub fn new(settings_dir: &Path) -> Self {
+        let path = settings_dir.join(HOOK_FILENAME);
+        let (tx, mut rx) = mpsc::channel(HOOK_QUEUE_SIZE);
+        tokio::spawn(async move {
+            while let Some(env) = rx.recv().await {
+                run_hook(&path, env).await;
+            }
+        });
+        StateHook { tx, last_env: None }
+    }
+
+    /// Queues a run of the hook for `state`. `interface` is the name of the tunnel interface, if
+    /// the tunnel is connected.
+    pub fn handle_tunnel_state(&mut self, state: &TunnelState, interface: Option<&str>) {
+        let env = environment(state, interface);
+        if self.last_env.as_ref() == Some(&env) {
+            return;
+        }
+        match self.tx.try_send(env.clone()) {
+            Ok(()) => self.last_env = Some(env),
+            Err(TrySendError::Full(_)) => {
+                log::warn!("Tunnel state hook is not keeping up, skipping a state change");
+            }
+            Err(TrySendError::Closed(_)) => (),
+        }
+    }
+}
+
+fn environment(state: &TunnelState, interface: Option<&str>) -> Environment {
+    let (name, endpoint) = match state {
+        TunnelState::Disconnected { .. } => ("disconnected", None),
+        TunnelState::Connecting { endpoint, .. } => ("connecting", Some(endpoint)),
+        TunnelState::Connected { endpoint, .. } => ("connected", Some(endpoint)),
+        TunnelState::Disconnecting(..) => ("disconnecting", None),
+        TunnelState::Error(..) => ("error", None),
+    };
+
+    let mut env = vec![("MULLVAD_TUNNEL_STATE", name.to_owned())];
+    if let Some(endpoint) = endpoint {
+        let address = endpoint.endpoint.address;
+        let tunnel_type = match endpoint.tunnel_type {
+            TunnelType::Wireguard => "wireguard",
+            TunnelType::OpenVpn => "openvpn",
+        };
+        env.push(("MULLVAD_RELAY_IP", address.ip().to_string()));
+        env.push(("MULLVAD_RELAY_PORT", address.port().to_string()));
+        env.push((
+            "MULLVAD_RELAY_PROTOCOL",
+            endpoint.endpoint.protocol.to_string().to_lowercase(),
+        ));
+        env.push(("MULLVAD_TUNNEL_TYPE", tunnel_type.to_owned()));
+    }
+    if let (TunnelState::Connected { .. }, Some(interface)) = (state, interface) {
+        env.push(("MULLVAD_TUNNEL_INTERFACE", interface.to_owned()));
+    }
+    if let TunnelState::Error(error_state) = state {
+        env.push(("MULLVAD_ERROR_CAUSE", error_state.cause().to_string()));
+    }
+    env
+}
+
+/// Returns whether the hook exists and may be run. The hook itself must not be a symlink, since
+/// the owner and mode of the target are not what is checked.
+fn is_trusted_hook(path: &Path) -> bool {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata)
+            if !metadata.file_type().is_file()
+                || metadata.uid() != 0
+                || metadata.mode() & 0o022 != 0 =>
+        {
+            log::error!(
+                "Not running {}, since it must be a regular file that is owned by root and only \
+                 writable by root",
+                path.display()
+            );
+            false
+        }
+        Ok(_) => true,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => false,
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg(&format!("Failed to access {}", path.display()))
+            );
+            false
+        }
+    }
+}
+
+async fn run_hook(path: &Path, env: Environment) {
+    if !is_trusted_hook(path) {
+        return;
+    }
+
+    let mut child = match Command::new(path).envs(env).kill_on_drop(true).spawn() {
+        Ok(child) => child,
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to run tunnel state hook")
+            );
+            return;
+        }
+    };
+
+    match tokio::time::timeout(HOOK_TIMEOUT, child.wait()).await {
+        Ok(Ok(status)) if !status.success() => {
+            log::warn!("Tunnel state hook failed: {}", status);
+        }
+        Ok(Ok(_)) => (),
+        Ok(Err(error)) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to wait for tunnel state hook")
+            );
+        }
+        Err(_) => {
+            log::warn!(
+                "Tunnel state hook did not exit within {} seconds, killing it",
+                HOOK_TIMEOUT.as_secs()
+            );
+            if let Err(error) = child.kill().await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to kill tunnel state hook")
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use talpid_types::{
+        net::{Endpoint, TransportProtocol, TunnelEndpoint},
+        tunnel::ConnectingPhase,
+    };
+
+    fn endpoint() -> TunnelEndpoint {
+        TunnelEndpoint {
+            endpoint: Endpoint::new(Ipv4Addr::new(10, 0, 0, 1), 51820, TransportProtocol::Udp),
+            tunnel_type: TunnelType::Wireguard,
+            quantum_resistant: false,
+            proxy: None,
+            obfuscation: None,
+            entry_endpoint: None,
+        }
+    }
+
+    fn connecting(phase: ConnectingPhase) -> TunnelState {
+        TunnelState::Connecting {
+            endpoint: endpoint(),
+            location: None,
+            reconnect_reason: None,
+            phase,
+        }
+    }
+
+    #[test]
+    fn test_environment() {
+        let connected = TunnelState::Connected {
+            endpoint: endpoint(),
+            location: None,
+            health: Default::default(),
+        };
+        assert_eq!(
+            environment(&connected, Some("wg0-mullvad")),
+            vec![
+                ("MULLVAD_TUNNEL_STATE", "connected".to_owned()),
+                ("MULLVAD_RELAY_IP", "10.0.0.1".to_owned()),
+                ("MULLVAD_RELAY_PORT", "51820".to_owned()),
+                ("MULLVAD_RELAY_PROTOCOL", "udp".to_owned()),
+                ("MULLVAD_TUNNEL_TYPE", "wireguard".to_owned()),
+                ("MULLVAD_TUNNEL_INTERFACE", "wg0-mullvad".to_owned()),
+            ]
+        );
+        assert_eq!(
+            environment(&TunnelState::Disconnected { reason: None }, None),
+            vec![("MULLVAD_TUNNEL_STATE", "disconnected".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_connecting_progress_runs_hook_once() {
+        let (tx, mut rx) = mpsc::channel(HOOK_QUEUE_SIZE);
+        let mut hook = StateHook { tx, last_env: None };
+
+        hook.handle_tunnel_state(&connecting(ConnectingPhase::OpeningDevice), None);
+        hook.handle_tunnel_state(&connecting(ConnectingPhase::Handshaking), None);
+        hook.handle_tunnel_state(&TunnelState::Disconnected { reason: None }, None);
+        hook.handle_tunnel_state(&connecting(ConnectingPhase::OpeningDevice), None);
+
+        let states: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|env| env[0].1.clone())
+            .collect();
+        assert_eq!(states, ["connecting", "disconnected", "connecting"]);
+    }
+
+    #[test]
+    fn test_symlinked_hook_is_not_trusted() {
+        let dir = std::env::temp_dir().join(format!("state-hook-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let target = dir.join("target");
+        std::fs::write(&target, "#!/bin/sh\n").unwrap();
+        let hook = dir.join(HOOK_FILENAME);
+
+        assert!(!is_trusted_hook(&hook));
+        std::os::unix::fs::symlink(&target, &hook).unwrap();
+        assert!(!is_trusted_hook(&hook));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,336 @@
+//! Shows notifications through the notification system of the OS, for installations where the
+//! GUI, which normally shows them, is not running. Notifications are only shown to the user in
+//! front of the computer, and not to other users that are logged in.
+//!
+//! The daemon runs as a system service, which cannot reach the desktop of a user directly:
+//!
+//! - On Linux, the session bus only accepts connections from its own user. The daemon runs itself
+//!   as the user of each active local session, with [`NOTIFY_ARG`], to send the notification.
+//! - On macOS, `osascript` is run as the console user in their login session.
+//! - On Windows, the service runs in session 0, where toasts cannot be shown. A message box is
+//!   shown in the active console session instead.
+
+use chrono::{DateTime, Utc};
+use mullvad_types::states::TunnelState;
+#[cfg(windows)]
+use std::io;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::process::{Command, ExitStatus};
+use std::sync::Arc;
+use talpid_types::ErrorExt;
+
+#[cfg(target_os = "linux")]
+const APP_NAME: &str = "Mullvad VPN";
+
+/// Long name of the argument that makes the daemon show a notification to the current user and
+/// exit. It is followed by the summary and the body. Only used on Linux.
+pub const NOTIFY_ARG: &str = "show-notification";
+
+/// Notifications are shown when the account has less time left than this.
+const CLOSE_TO_EXPIRY_DAYS: i64 = 3;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to show notification over D-Bus")]
+    Dbus(#[error(source)] talpid_dbus::notifications::Error),
+
+    #[error(display = "No user is logged in to a local session")]
+    NoSessions,
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[error(display = "Failed to run the notifier")]
+    RunNotifier(#[error(source)] std::io::Error),
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[error(display = "The notifier failed: {}", _0)]
+    NotifierFailed(ExitStatus),
+
+    #[cfg(windows)]
+    #[error(display = "Failed to show a message in the console session")]
+    SendMessage(#[error(source)] io::Error),
+}
+
+/// A way of showing notifications to the user.
+pub trait NotificationBackend: Send + Sync {
+    fn show(&self, summary: &str, body: &str) -> Result<(), Error>;
+}
+
+/// Runs the daemon as the user of each active local session, which sends the notification to the
+/// session bus of that user.
+#[cfg(target_os = "linux")]
+struct DbusBackend {
+    daemon_path: std::path::PathBuf,
+}
+
+#[cfg(target_os = "linux")]
+impl NotificationBackend for DbusBackend {
+    fn show(&self, summary: &str, body: &str) -> Result<(), Error> {
+        use std::os::unix::process::CommandExt;
+
+        let uids = talpid_dbus::notifications::active_session_users().map_err(Error::Dbus)?;
+        if uids.is_empty() {
+            return Err(Error::NoSessions);
+        }
+        for uid in uids {
+            let user = match nix::unistd::User::from_uid(nix::unistd::Uid::from_raw(uid)) {
+                Ok(Some(user)) => user,
+                _ => {
+                    log::warn!("Failed to look up user {}", uid);
+                    continue;
+                }
+            };
+            // Supplementary groups are dropped since only the user ID and group are set
+            let status = Command::new(&self.daemon_path)
+                .arg(format!("--{}", NOTIFY_ARG))
+                .arg(summary)
+                .arg(body)
+                .env_clear()
+                .env(
+                    "DBUS_SESSION_BUS_ADDRESS",
+                    talpid_dbus::notifications::session_bus_address(uid),
+                )
+                .gid(user.gid.as_raw())
+                .uid(uid)
+                .status()
+                .map_err(Error::RunNotifier)?;
+            if !status.success() {
+                return Err(Error::NotifierFailed(status));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Shows a notification on the session bus of the current user. This is what the daemon does
+/// when it is run with [`NOTIFY_ARG`].
+#[cfg(target_os = "linux")]
+pub fn show_as_current_user(summary: &str, body: &str) -> Result<(), Error> {
+    talpid_dbus::notifications::notify(APP_NAME, summary, body).map_err(Error::Dbus)
+}
+
+/// Runs `osascript` as the console user, in their login session.
+#[cfg(target_os = "macos")]
+struct OsascriptBackend;
+
+#[cfg(target_os = "macos")]
+impl NotificationBackend for OsascriptBackend {
+    fn show(&self, summary: &str, body: &str) -> Result<(), Error> {
+        use std::os::unix::fs::MetadataExt;
+
+        // The console is owned by root while no user is logged in
+        let uid = std::fs::metadata("/dev/console")
+            .map_err(Error::RunNotifier)?
+            .uid();
+        if uid == 0 {
+            return Err(Error::NoSessions);
+        }
+        let status = Command::new("/bin/launchctl")
+            .arg("asuser")
+            .arg(uid.to_string())
+            .args(["/usr/bin/sudo", "-u"])
+            .arg(format!("#{}", uid))
+            .args([
+                "/usr/bin/osascript",
+                "-e",
+                "on run argv",
+                "-e",
+                "display notification (item 2 of argv) with title (item 1 of argv)",
+                "-e",
+                "end run",
+                summary,
+                body,
+            ])
+            .status()
+            .map_err(Error::RunNotifier)?;
+        if !status.success() {
+            return Err(Error::NotifierFailed(status));
+        }
+        Ok(())
+    }
+}
+
+/// Shows a message box in the active console session.
+#[cfg(windows)]
+struct MessageBoxBackend;
+
+#[cfg(windows)]
+impl NotificationBackend for MessageBoxBackend {
+    fn show(&self, summary: &str, body: &str) -> Result<(), Error> {
+        use windows_sys::Win32::{
+            System::RemoteDesktop::{
+                WTSGetActiveConsoleSessionId, WTSSendMessageW, WTS_CURRENT_SERVER_HANDLE,
+            },
+            UI::WindowsAndMessaging::{MB_ICONINFORMATION, MB_OK, MB_SETFOREGROUND},
+        };
+
+        let session_id = unsafe { WTSGetActiveConsoleSessionId() };
+        if session_id == u32::MAX {
+            return Err(Error::NoSessions);
+        }
+        let title: Vec<u16> = summary.encode_utf16().collect();
+        let message: Vec<u16> = body.encode_utf16().collect();
+        let mut response = 0;
+        // The lengths are in bytes. The call returns without waiting for the user to respond.
+        let result = unsafe {
+            WTSSendMessageW(
+                WTS_CURRENT_SERVER_HANDLE,
+                session_id,
+                title.as_ptr(),
+                (title.len() * 2) as u32,
+                message.as_ptr(),
+                (message.len() * 2) as u32,
+                MB_OK | MB_ICONINFORMATION | MB_SETFOREGROUND,
+                0,
+                &mut response,
+                0,
+            )
+        };
+        if result == 0 {
+            return Err(Error::SendMessage(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the backend available on this system, if any.
+#[cfg(target_os = "linux")]
+fn default_backend() -> Option<Arc<dyn NotificationBackend>> {
+    match std::env::current_exe() {
+        Ok(daemon_path) => Some(Arc::new(DbusBackend { daemon_path })),
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to find the daemon executable")
+            );
+            None
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn default_backend() -> Option<Arc<dyn NotificationBackend>> {
+    Some(Arc::new(OsascriptBackend))
+}
+
+#[cfg(windows)]
+fn default_backend() -> Option<Arc<dyn NotificationBackend>> {
+    Some(Arc::new(MessageBoxBackend))
+}
+
+#[cfg(target_os = "android")]
+fn default_backend() -> Option<Arc<dyn NotificationBackend>> {
+    None
+}
+
+/// Decides which daemon events are worth a notification, and forwards them to a
+/// [`NotificationBackend`].
+pub struct SystemNotifier {
+    backend: Option<Arc<dyn NotificationBackend>>,
+    enabled: bool,
+    /// The expiry that the user was last notified about, and whether it had passed.
+    notified_expiry: Option<(DateTime<Utc>, bool)>,
+    in_error_state: bool,
+}
+
+impl SystemNotifier {
+    pub fn new(enabled: bool) -> Self {
+        let backend = default_backend();
+        if enabled && backend.is_none() {
+            log::warn!("System notifications are enabled, but are not supported on this system");
+        }
+        Self {
+            backend,
+            enabled,
+            notified_expiry: None,
+            in_error_state: false,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn handle_expiry(&mut self, expiry: DateTime<Utc>) {
+        let remaining = expiry - Utc::now();
+        let expired = remaining <= chrono::Duration::zero();
+        if !expired && remaining > chrono::Duration::days(CLOSE_TO_EXPIRY_DAYS) {
+            return;
+        }
+        if self.notified_expiry == Some((expiry, expired)) {
+            return;
+        }
+        self.notified_expiry = Some((expiry, expired));
+
+        if expired {
+            self.show(
+                "Account credit has expired",
+                "Buy more credit to continue using the VPN.",
+            );
+        } else {
+            self.show(
+                "Account credit expires soon",
+                &format!(
+                    "Account credit expires in {}. Buy more credit.",
+                    format_remaining(remaining)
+                ),
+            );
+        }
+    }
+
+    pub fn handle_tunnel_state(&mut self, state: &TunnelState) {
+        let was_in_error_state = self.in_error_state;
+        self.in_error_state = matches!(state, TunnelState::Error(_));
+        if was_in_error_state {
+            return;
+        }
+        if let TunnelState::Error(error_state) = state {
+            if error_state.is_blocking() {
+                self.show(
+                    "Blocking internet",
+                    &format!("Blocking all network connections: {}", error_state.cause()),
+                );
+            } else {
+                self.show(
+                    "Failed to block internet",
+                    &format!("Your connection may be leaking: {}", error_state.cause()),
+                );
+            }
+        }
+    }
+
+    fn show(&self, summary: &str, body: &str) {
+        if !self.enabled {
+            return;
+        }
+        let backend = match &self.backend {
+            Some(backend) => backend.clone(),
+            None => return,
+        };
+        let summary = summary.to_owned();
+        let body = body.to_owned();
+        tokio::task::spawn_blocking(move || {
+            if let Err(error) = backend.show(&summary, &body) {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to show system notification")
+                );
+            }
+        });
+    }
+}
+
+fn format_remaining(remaining: chrono::Duration) -> String {
+    match (remaining.num_days(), remaining.num_hours()) {
+        (1, _) => "1 day".to_owned(),
+        (days, _) if days > 1 => format!("{} days", days),
+        (_, 1) => "1 hour".to_owned(),
+        (_, hours) if hours > 1 => format!("{} hours", hours),
+        _ => "less than an hour".to_owned(),
+    }
+}
@@ -1,14 +1,26 @@
-use std::{future::Future, pin::Pin, sync::Arc};
+use std::{
+    future::Future,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use tokio::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io, sync::Mutex};
 
 use mullvad_relay_selector::{RelaySelector, SelectedBridge, SelectedObfuscator, SelectedRelay};
 use mullvad_types::{
-    endpoint::MullvadEndpoint, location::GeoIpLocation, relay_list::Relay, settings::TunnelOptions,
+    endpoint::MullvadEndpoint,
+    location::GeoIpLocation,
+    relay_constraints::{ObfuscationSettings, RelaySettings},
+    relay_list::{Relay, RelayCapabilities},
+    settings::TunnelOptions,
 };
 use talpid_core::tunnel_state_machine::TunnelParametersGenerator;
 use talpid_types::{
-    net::{wireguard, TunnelParameters},
+    net::{obfuscation::ObfuscatorConfig, wireguard, IpVersion, TunnelParameters},
     tunnel::ParameterGenerationError,
     ErrorExt,
 };
@@ -29,10 +41,21 @@ pub enum Error {
     #[error(display = "No bridge available")]
     NoBridgeAvailable,
 
+    #[error(display = "No relay supports the features required by the settings")]
+    MissingRelayCapabilities,
+
     #[error(display = "Failed to resolve hostname for custom relay")]
     ResolveCustomHostname,
 }
 
+/// File in the cache directory that the last WireGuard tunnel is saved to on shutdown.
+const WARM_TUNNEL_FILE: &str = "last-wireguard-tunnel.json";
+/// How long after shutdown a saved tunnel may still be reused. This is long enough to cover an
+/// upgrade, but short enough that the relay is unlikely to have changed.
+const WARM_TUNNEL_MAX_AGE: Duration = Duration::from_secs(5 * 60);
+/// How many times a relay is picked again when the picked relay should be avoided.
+const MAX_RELAY_ROTATION_PICKS: usize = 10;
+
 #[derive(Clone)]
 pub(crate) struct ParametersGenerator(Arc<Mutex<InnerParametersGenerator>>);
 
@@ -40,8 +63,33 @@ struct InnerParametersGenerator {
     relay_selector: RelaySelector,
     tunnel_options: TunnelOptions,
     account_manager: AccountManagerHandle,
+    warm_tunnel_path: PathBuf,
 
     last_generated_relays: Option<LastSelectedRelays>,
+    /// The selected location, if the last relays were picked in a nearby location because no
+    /// relay in it matched the constraints.
+    last_nearby_fallback: Option<String>,
+    last_wireguard_parameters: Option<wireguard::TunnelParameters>,
+    port_hop_pending: bool,
+    warm_tunnel: Option<WarmTunnel>,
+}
+
+/// The parts of a WireGuard tunnel that were decided by relay selection, along with the settings
+/// that they were selected with. Keys are not included: the device key is read from the account
+/// on startup, and preshared keys are never persisted.
+#[derive(Serialize, Deserialize)]
+struct WarmTunnel {
+    saved_at: SystemTime,
+    relay_settings: RelaySettings,
+    obfuscation_settings: ObfuscationSettings,
+    peer: wireguard::PeerConfig,
+    exit_peer: Option<wireguard::PeerConfig>,
+    ipv4_gateway: Ipv4Addr,
+    ipv6_gateway: Option<Ipv6Addr>,
+    obfuscation: Option<ObfuscatorConfig>,
+    wg_entry: Option<Relay>,
+    wg_exit: Relay,
+    obfuscator: Option<Relay>,
 }
 
 impl ParametersGenerator {
@@ -50,17 +98,179 @@ impl ParametersGenerator {
         account_manager: AccountManagerHandle,
         relay_selector: RelaySelector,
         tunnel_options: TunnelOptions,
+        cache_dir: &Path,
     ) -> Self {
         Self(Arc::new(Mutex::new(InnerParametersGenerator {
             tunnel_options,
             relay_selector,
 
             account_manager,
+            warm_tunnel_path: cache_dir.join(WARM_TUNNEL_FILE),
 
             last_generated_relays: None,
+            last_nearby_fallback: None,
+            last_wireguard_parameters: None,
+            port_hop_pending: false,
+            warm_tunnel: None,
         })))
     }
 
+    /// Saves the last WireGuard tunnel, so that the next daemon instance can reconnect to the
+    /// same relay without running relay selection. Does nothing if the last tunnel was not a
+    /// WireGuard tunnel to a Mullvad relay.
+    pub async fn save_warm_tunnel(
+        &self,
+        relay_settings: RelaySettings,
+        obfuscation_settings: ObfuscationSettings,
+    ) {
+        let inner = self.0.lock().await;
+        let (parameters, (wg_entry, wg_exit, obfuscator)) = match (
+            &inner.last_wireguard_parameters,
+            &inner.last_generated_relays,
+        ) {
+            (
+                Some(parameters),
+                Some(LastSelectedRelays::WireGuard {
+                    wg_entry,
+                    wg_exit,
+                    obfuscator,
+                }),
+            ) => (parameters, (wg_entry, wg_exit, obfuscator)),
+            _ => return,
+        };
+        let warm_tunnel = WarmTunnel {
+            saved_at: SystemTime::now(),
+            relay_settings,
+            obfuscation_settings,
+            peer: parameters.connection.peer.clone(),
+            exit_peer: parameters.connection.exit_peer.clone(),
+            ipv4_gateway: parameters.connection.ipv4_gateway,
+            ipv6_gateway: parameters.connection.ipv6_gateway,
+            obfuscation: parameters.obfuscation.clone(),
+            wg_entry: wg_entry.clone(),
+            wg_exit: wg_exit.clone(),
+            obfuscator: obfuscator.clone(),
+        };
+
+        let result = match serde_json::to_string(&warm_tunnel) {
+            Ok(data) => fs::write(&inner.warm_tunnel_path, data).await,
+            Err(error) => Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+        };
+        match result {
+            Ok(()) => log::debug!("Saved WireGuard tunnel for reconnecting after restart"),
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to save WireGuard tunnel")
+            ),
+        }
+    }
+
+    /// Removes a tunnel saved by [`Self::save_warm_tunnel`] without using it.
+    pub async fn discard_warm_tunnel(&self) {
+        let inner = self.0.lock().await;
+        if let Err(error) = fs::remove_file(&inner.warm_tunnel_path).await {
+            if error.kind() != io::ErrorKind::NotFound {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to remove saved WireGuard tunnel")
+                );
+            }
+        }
+    }
+
+    /// Loads a tunnel saved by [`Self::save_warm_tunnel`] and removes it from the cache. It is
+    /// used the next time tunnel parameters are generated, unless it is too old, was saved with
+    /// other settings, or a relay that it uses is no longer available.
+    pub async fn load_warm_tunnel(
+        &self,
+        relay_settings: &RelaySettings,
+        obfuscation_settings: &ObfuscationSettings,
+    ) {
+        let mut inner = self.0.lock().await;
+        let warm_tunnel_path = inner.warm_tunnel_path.clone();
+        let content = match fs::read_to_string(&warm_tunnel_path).await {
+            Ok(content) => content,
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read saved WireGuard tunnel")
+                    );
+                }
+                return;
+            }
+        };
+        if let Err(error) = fs::remove_file(&warm_tunnel_path).await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove saved WireGuard tunnel")
+            );
+        }
+
+        let warm_tunnel: WarmTunnel = match serde_json::from_str(&content) {
+            Ok(warm_tunnel) => warm_tunnel,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to parse saved WireGuard tunnel")
+                );
+                return;
+            }
+        };
+
+        let is_fresh = warm_tunnel
+            .saved_at
+            .elapsed()
+            .map(|age| age <= WARM_TUNNEL_MAX_AGE)
+            .unwrap_or(false);
+        if !is_fresh {
+            log::debug!("Ignoring saved WireGuard tunnel since it is too old");
+            return;
+        }
+        if warm_tunnel.relay_settings != *relay_settings
+            || warm_tunnel.obfuscation_settings != *obfuscation_settings
+        {
+            log::debug!("Ignoring saved WireGuard tunnel since the settings have changed");
+            return;
+        }
+
+        let relay_list = inner.relay_selector.get_locations();
+        let is_active = |saved: &Relay| {
+            relay_list
+                .countries
+                .iter()
+                .flat_map(|country| &country.cities)
+                .flat_map(|city| &city.relays)
+                .any(|relay| relay.active && relay.hostname == saved.hostname)
+        };
+        let relays_available = std::iter::once(&warm_tunnel.wg_exit)
+            .chain(&warm_tunnel.wg_entry)
+            .chain(&warm_tunnel.obfuscator)
+            .all(is_active);
+        if !relays_available {
+            log::debug!("Ignoring saved WireGuard tunnel since its relay is unavailable");
+            return;
+        }
+
+        log::info!(
+            "Reconnecting to {} from before the restart",
+            warm_tunnel.wg_exit.hostname
+        );
+        inner.warm_tunnel = Some(warm_tunnel);
+    }
+
+    /// Moves the entry peer of the last WireGuard tunnel parameters to a new port, and returns
+    /// the port. Returns `None` if the port cannot be changed.
+    pub async fn hop_port(&self) -> Option<u16> {
+        self.0.lock().await.hop_port()
+    }
+
+    /// Makes the next generated tunnel parameters reuse the last WireGuard parameters, for when
+    /// the tunnel cannot be moved to the port from [`Self::hop_port`] without reconnecting.
+    pub async fn request_port_hop(&self) {
+        self.0.lock().await.port_hop_pending = true;
+    }
+
     /// Sets the tunnel options to use when generating new tunnel parameters.
     pub async fn set_tunnel_options(&self, tunnel_options: &TunnelOptions) {
         self.0.lock().await.tunnel_options = tunnel_options.clone();
@@ -76,7 +286,8 @@ impl ParametersGenerator {
         let bridge_hostname;
         let entry_hostname;
         let obfuscator_hostname;
-        let location;
+        let exit_relay;
+        let features;
         let take_hostname =
             |relay: &Option<Relay>| relay.as_ref().map(|relay| relay.hostname.clone());
 
@@ -90,7 +301,16 @@ impl ParametersGenerator {
                 hostname = exit.hostname.clone();
                 obfuscator_hostname = take_hostname(obfuscator);
                 bridge_hostname = None;
-                location = exit.location.as_ref().cloned().unwrap();
+                exit_relay = exit;
+                features = RelayCapabilities {
+                    quantum_resistant: inner
+                        .last_wireguard_parameters
+                        .as_ref()
+                        .map(|params| params.options.use_pq_safe_psk)
+                        .unwrap_or(false),
+                    daita: false,
+                    udp2tcp: obfuscator.is_some(),
+                };
             }
             #[cfg(not(target_os = "android"))]
             LastSelectedRelays::OpenVpn { relay, bridge } => {
@@ -98,10 +318,12 @@ impl ParametersGenerator {
                 bridge_hostname = take_hostname(bridge);
                 entry_hostname = None;
                 obfuscator_hostname = None;
-                location = relay.location.as_ref().cloned().unwrap();
+                exit_relay = relay;
+                features = RelayCapabilities::default();
             }
         };
 
+        let location = exit_relay.location.as_deref().cloned().unwrap();
         Some(GeoIpLocation {
             ipv4: None,
             ipv6: None,
@@ -114,16 +336,55 @@ impl ParametersGenerator {
             bridge_hostname,
             entry_hostname,
             obfuscator_hostname,
+            provider: Some(exit_relay.provider.clone()),
+            owned: Some(exit_relay.owned),
+            features: Some(features),
+            nearby_fallback_from: inner.last_nearby_fallback.clone(),
         })
     }
 }
 
 impl InnerParametersGenerator {
-    async fn generate(&mut self, retry_attempt: u32) -> Result<TunnelParameters, Error> {
-        let _data = self.device().await?;
-        match self.relay_selector.get_relay(retry_attempt) {
+    async fn generate(
+        &mut self,
+        retry_attempt: u32,
+        avoid_relay: Option<IpAddr>,
+    ) -> Result<TunnelParameters, Error> {
+        let data = self.device().await?;
+        if let Some(warm_tunnel) = self.warm_tunnel.take() {
+            return Ok(self.restore_warm_tunnel(warm_tunnel, data).into());
+        }
+        if std::mem::take(&mut self.port_hop_pending) {
+            if let Some(mut parameters) = self.last_wireguard_parameters.clone() {
+                // The device key may have been rotated since the parameters were generated.
+                parameters.connection.tunnel.private_key = data.device.wg_data.private_key;
+                return Ok(parameters.into());
+            }
+        }
+        let mut selection = self.relay_selector.get_relay(retry_attempt);
+        if let Some(avoid_relay) = avoid_relay {
+            // Relays are picked at random, so a few picks are enough to find another one, unless
+            // no other relay matches the settings
+            for _ in 0..MAX_RELAY_ROTATION_PICKS {
+                let is_avoided = matches!(
+                    &selection,
+                    Ok((SelectedRelay::Normal(selected), ..))
+                        if relay_has_address(
+                            selected.entry_relay.as_ref().unwrap_or(&selected.exit_relay),
+                            avoid_relay,
+                        )
+                );
+                if !is_avoided {
+                    break;
+                }
+                selection = self.relay_selector.get_relay(retry_attempt);
+            }
+        }
+        match selection {
             Ok((SelectedRelay::Custom(custom_relay), _bridge, _obfsucator)) => {
                 self.last_generated_relays = None;
+                self.last_nearby_fallback = None;
+                self.last_wireguard_parameters = None;
                 custom_relay
                     // TODO: generate proxy settings for custom tunnels
                     .to_tunnel_parameters(self.tunnel_options.clone(), None)
@@ -133,6 +394,10 @@ impl InnerParametersGenerator {
                     })
             }
             Ok((SelectedRelay::Normal(constraints), bridge, obfuscator)) => {
+                self.last_nearby_fallback = constraints
+                    .nearby_fallback_from
+                    .as_ref()
+                    .map(ToString::to_string);
                 self.create_tunnel_parameters(
                     &constraints.exit_relay,
                     &constraints.entry_relay,
@@ -143,6 +408,10 @@ impl InnerParametersGenerator {
                 .await
             }
             Err(mullvad_relay_selector::Error::NoBridge) => Err(Error::NoBridgeAvailable),
+            Err(mullvad_relay_selector::Error::MissingCapabilities(_))
+            | Err(mullvad_relay_selector::Error::UnsupportedObfuscationPort(_)) => {
+                Err(Error::MissingRelayCapabilities)
+            }
             Err(_error) => Err(Error::NoRelayAvailable),
         }
     }
@@ -172,6 +441,7 @@ impl InnerParametersGenerator {
                     relay: relay.clone(),
                     bridge: bridge_relay,
                 });
+                self.last_wireguard_parameters = None;
 
                 Ok(openvpn::TunnelParameters {
                     config: openvpn::ConnectionConfig::new(
@@ -192,14 +462,6 @@ impl InnerParametersGenerator {
                 unreachable!("OpenVPN is not supported on Android");
             }
             MullvadEndpoint::Wireguard(endpoint) => {
-                let tunnel = wireguard::TunnelConfig {
-                    private_key: data.device.wg_data.private_key,
-                    addresses: vec![
-                        data.device.wg_data.addresses.ipv4_address.ip().into(),
-                        data.device.wg_data.addresses.ipv6_address.ip().into(),
-                    ],
-                };
-
                 let (obfuscator_relay, obfuscator_config) = match obfuscator {
                     Some(obfuscator) => (Some(obfuscator.relay), Some(obfuscator.config)),
                     None => (None, None),
@@ -211,25 +473,98 @@ impl InnerParametersGenerator {
                     obfuscator: obfuscator_relay,
                 });
 
-                Ok(wireguard::TunnelParameters {
-                    connection: wireguard::ConnectionConfig {
-                        tunnel,
-                        peer: endpoint.peer,
-                        exit_peer: endpoint.exit_peer,
-                        ipv4_gateway: endpoint.ipv4_gateway,
-                        ipv6_gateway: Some(endpoint.ipv6_gateway),
-                        #[cfg(target_os = "linux")]
-                        fwmark: Some(mullvad_types::TUNNEL_FWMARK),
-                    },
-                    options: self.tunnel_options.wireguard.options.clone(),
-                    generic_options: self.tunnel_options.generic.clone(),
-                    obfuscation: obfuscator_config,
-                }
-                .into())
+                Ok(self
+                    .wireguard_parameters(
+                        data,
+                        endpoint.peer,
+                        endpoint.exit_peer,
+                        endpoint.ipv4_gateway,
+                        Some(endpoint.ipv6_gateway),
+                        obfuscator_config,
+                    )
+                    .into())
             }
         }
     }
 
+    /// Recreates the tunnel parameters of a saved tunnel, using the current device key and
+    /// tunnel options.
+    fn restore_warm_tunnel(
+        &mut self,
+        warm_tunnel: WarmTunnel,
+        data: PrivateAccountAndDevice,
+    ) -> wireguard::TunnelParameters {
+        self.last_nearby_fallback = None;
+        self.last_generated_relays = Some(LastSelectedRelays::WireGuard {
+            wg_entry: warm_tunnel.wg_entry,
+            wg_exit: warm_tunnel.wg_exit,
+            obfuscator: warm_tunnel.obfuscator,
+        });
+        self.wireguard_parameters(
+            data,
+            warm_tunnel.peer,
+            warm_tunnel.exit_peer,
+            warm_tunnel.ipv4_gateway,
+            warm_tunnel.ipv6_gateway,
+            warm_tunnel.obfuscation,
+        )
+    }
+
+    fn wireguard_parameters(
+        &mut self,
+        data: PrivateAccountAndDevice,
+        peer: wireguard::PeerConfig,
+        exit_peer: Option<wireguard::PeerConfig>,
+        ipv4_gateway: Ipv4Addr,
+        ipv6_gateway: Option<Ipv6Addr>,
+        obfuscation: Option<ObfuscatorConfig>,
+    ) -> wireguard::TunnelParameters {
+        let address_family = self.tunnel_options.wireguard.options.address_family;
+        let tunnel = wireguard::TunnelConfig {
+            private_key: data.device.wg_data.private_key,
+            addresses: data.device.wg_data.addresses.tunnel_ips(address_family),
+        };
+        let mut generic_options = self.tunnel_options.generic.clone();
+        match address_family {
+            // Without an in-tunnel IPv6 address, there is nothing to route or resolve over IPv6.
+            Some(IpVersion::V4) => generic_options.enable_ipv6 = false,
+            Some(IpVersion::V6) => generic_options.enable_ipv6 = true,
+            None => (),
+        }
+
+        let parameters = wireguard::TunnelParameters {
+            connection: wireguard::ConnectionConfig {
+                tunnel,
+                peer,
+                exit_peer,
+                ipv4_gateway,
+                ipv6_gateway,
+                #[cfg(target_os = "linux")]
+                fwmark: Some(mullvad_types::TUNNEL_FWMARK),
+            },
+            options: self.tunnel_options.wireguard.options.clone(),
+            generic_options,
+            obfuscation,
+        };
+        self.last_wireguard_parameters = Some(parameters.clone());
+        parameters
+    }
+
+    /// Moves the entry peer of the last WireGuard tunnel parameters to a new port.
+    fn hop_port(&mut self) -> Option<u16> {
+        let parameters = self.last_wireguard_parameters.as_mut()?;
+        if parameters.obfuscation.is_some() {
+            return None;
+        }
+        let endpoint = &mut parameters.connection.peer.endpoint;
+        let port = self
+            .relay_selector
+            .get_hopped_wireguard_port(endpoint.port())?;
+        log::debug!("Hopping from port {} to {}", endpoint.port(), port);
+        endpoint.set_port(port);
+        Some(port)
+    }
+
     async fn device(&self) -> Result<PrivateAccountAndDevice, Error> {
         self.account_manager
             .data()
@@ -241,19 +576,31 @@ impl InnerParametersGenerator {
     }
 }
 
+/// Returns whether `address` is an address that `relay` accepts tunnels on.
+fn relay_has_address(relay: &Relay, address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => relay.ipv4_addr_in == address,
+        IpAddr::V6(address) => relay.ipv6_addr_in == Some(address),
+    }
+}
+
 impl TunnelParametersGenerator for ParametersGenerator {
     fn generate(
         &mut self,
         retry_attempt: u32,
+        avoid_relay: Option<IpAddr>,
     ) -> Pin<Box<dyn Future<Output = Result<TunnelParameters, ParameterGenerationError>>>> {
         let generator = self.0.clone();
         Box::pin(async move {
             let mut inner = generator.lock().await;
             inner
-                .generate(retry_attempt)
+                .generate(retry_attempt, avoid_relay)
                 .await
                 .map_err(|error| match error {
                     Error::NoBridgeAvailable => ParameterGenerationError::NoMatchingBridgeRelay,
+                    Error::MissingRelayCapabilities => {
+                        ParameterGenerationError::MissingRelayCapabilities
+                    }
                     Error::ResolveCustomHostname => {
                         ParameterGenerationError::CustomTunnelHostResultionError
                     }
@@ -4,7 +4,9 @@ use futures::{
     stream::FusedStream,
     FutureExt, SinkExt, StreamExt, TryFutureExt,
 };
-use mullvad_api::{availability::ApiAvailabilityHandle, rest::MullvadRestHandle, AppVersionProxy};
+use mullvad_api::{
+    availability::ApiAvailabilityHandle, rest::MullvadRestHandle, AppVersionProxy, RelayListKeys,
+};
 use mullvad_types::version::{AppVersionInfo, ParsedAppVersion};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -98,9 +100,12 @@ pub(crate) struct VersionUpdater {
     last_app_version_info: Option<AppVersionInfo>,
     platform_version: String,
     show_beta_releases: bool,
+    /// Whether to check for new versions in the background.
+    check_for_updates: bool,
     rx: Option<mpsc::Receiver<VersionUpdaterCommand>>,
     availability_handle: ApiAvailabilityHandle,
     internal_done_tx: Option<oneshot::Sender<AppVersionInfo>>,
+    relay_list_keys: RelayListKeys,
 }
 
 #[derive(Clone)]
@@ -110,6 +115,7 @@ pub(crate) struct VersionUpdaterHandle {
 
 enum VersionUpdaterCommand {
     SetShowBetaReleases(bool),
+    SetCheckForUpdates(bool),
     RunVersionCheck(oneshot::Sender<AppVersionInfo>),
 }
 
@@ -127,6 +133,20 @@ impl VersionUpdaterHandle {
         }
     }
 
+    /// Enables or disables background version checks. Disabling them also cancels any check that
+    /// is in progress. While they are disabled, the version API is only queried for new relay list
+    /// signing keys, and only after a relay list has been rejected.
+    pub async fn set_check_for_updates(&mut self, check_for_updates: bool) {
+        if self
+            .tx
+            .send(VersionUpdaterCommand::SetCheckForUpdates(check_for_updates))
+            .await
+            .is_err()
+        {
+            log::error!("Version updater already down, can't send new `check_for_updates` state");
+        }
+    }
+
     pub async fn run_version_check(&mut self) -> Result<AppVersionInfo, Error> {
         let (done_tx, done_rx) = oneshot::channel();
         if self
@@ -150,6 +170,8 @@ impl VersionUpdater {
         update_sender: DaemonEventSender<AppVersionInfo>,
         last_app_version_info: Option<AppVersionInfo>,
         show_beta_releases: bool,
+        check_for_updates: bool,
+        relay_list_keys: RelayListKeys,
     ) -> (Self, VersionUpdaterHandle) {
         api_handle.factory.timeout = DOWNLOAD_TIMEOUT;
         let version_proxy = AppVersionProxy::new(api_handle);
@@ -165,9 +187,11 @@ impl VersionUpdater {
                 last_app_version_info,
                 platform_version,
                 show_beta_releases,
+                check_for_updates,
                 rx: Some(rx),
                 availability_handle,
                 internal_done_tx: None,
+                relay_list_keys,
             },
             VersionUpdaterHandle { tx },
         )
@@ -223,7 +247,7 @@ impl VersionUpdater {
         let version_proxy = self.version_proxy.clone();
         let platform_version = self.platform_version.clone();
         let download_future_factory = move || {
-            let when_available = api_handle.wait_background();
+            let when_available = api_handle.wait_deferrable();
             let request = version_proxy.version_check(
                 mullvad_version::VERSION.to_owned(),
                 PLATFORM,
@@ -267,6 +291,19 @@ impl VersionUpdater {
         Ok(())
     }
 
+    /// Starts trusting any new relay list signing keys announced in `response`.
+    async fn add_relay_list_keys(&self, response: &mullvad_api::AppVersionResponse) {
+        for signed_key in &response.relay_list_keys {
+            if let Err(error) = self.relay_list_keys.add_signed_key(signed_key).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Ignoring relay list signing key")
+                );
+            }
+        }
+        self.relay_list_keys.clear_needs_refresh();
+    }
+
     fn response_to_version_info(
         &mut self,
         response: mullvad_api::AppVersionResponse,
@@ -381,6 +418,15 @@ impl VersionUpdater {
                                 }).await;
                             }
                         }
+                        Some(VersionUpdaterCommand::SetCheckForUpdates(check_for_updates)) => {
+                            self.check_for_updates = check_for_updates;
+                            if !check_for_updates {
+                                log::info!("Background version checks are disabled");
+                                version_check = futures::future::Fuse::terminated();
+                                self.internal_done_tx = None;
+                                check_delay = next_delay();
+                            }
+                        }
                         Some(VersionUpdaterCommand::RunVersionCheck(done_tx)) => {
                             if self.update_sender.is_closed() {
                                 return;
@@ -399,6 +445,12 @@ impl VersionUpdater {
                     if rx.is_terminated() || self.update_sender.is_closed() {
                         return;
                     }
+                    // The signing keys are only announced by the version API, so it is still
+                    // queried if a relay list was rejected, but only to update the keys
+                    if !self.check_for_updates && !self.relay_list_keys.needs_refresh() {
+                        check_delay = next_delay();
+                        continue;
+                    }
                     if self.internal_done_tx.is_some() {
                         // Sync check in progress
                         continue;
@@ -413,6 +465,11 @@ impl VersionUpdater {
 
                     match response {
                         Ok(version_info_response) => {
+                            self.add_relay_list_keys(&version_info_response).await;
+                            if !self.check_for_updates {
+                                check_delay = next_delay();
+                                continue;
+                            }
                             let new_version_info =
                                 self.response_to_version_info(version_info_response);
                             self.update_version_info(new_version_info).await;
@@ -60,6 +60,7 @@ pub const CLASSES: &[&str] = &[
     "net/mullvad/talpid/tun_provider/InetNetwork",
     "net/mullvad/talpid/tun_provider/TunConfig",
     "net/mullvad/talpid/tunnel/ActionAfterDisconnect",
+    "net/mullvad/talpid/tunnel/DisconnectReason",
     "net/mullvad/talpid/tunnel/ErrorState",
     "net/mullvad/talpid/tunnel/ErrorStateCause$AuthFailed",
     "net/mullvad/talpid/tunnel/ErrorStateCause$Ipv6Unavailable",
@@ -71,6 +72,7 @@ pub const CLASSES: &[&str] = &[
     "net/mullvad/talpid/tunnel/ErrorStateCause$InvalidDnsServers",
     "net/mullvad/talpid/tunnel/ErrorStateCause$VpnPermissionDenied",
     "net/mullvad/talpid/tunnel/ParameterGenerationError",
+    "net/mullvad/talpid/tunnel/TunnelHealth",
     "net/mullvad/talpid/ConnectivityListener",
     "net/mullvad/talpid/CreateTunResult$Success",
     "net/mullvad/talpid/CreateTunResult$InvalidDnsServers",
@@ -1,7 +1,7 @@
 use futures::{channel::oneshot, executor::block_on};
 use mullvad_daemon::{device, DaemonCommand, DaemonCommandSender};
 use mullvad_types::{
-    account::{AccountData, AccountToken, VoucherSubmission},
+    account::{AccountData, AccountToken, LoginMode, VoucherSubmission},
     device::{Device, DeviceState},
     location::GeoIpLocation,
     relay_constraints::RelaySettingsUpdate,
@@ -135,7 +135,8 @@ impl DaemonInterface {
 
         self.send_command(DaemonCommand::GetRelayLocations(tx))?;
 
-        Ok(block_on(rx).map_err(|_| Error::NoResponse)?)
+        let relay_list = block_on(rx).map_err(|_| Error::NoResponse)?;
+        Ok(RelayList::clone(&relay_list))
     }
 
     pub fn get_settings(&self) -> Result<Settings> {
@@ -167,7 +168,10 @@ impl DaemonInterface {
     pub fn reconnect(&self) -> Result<()> {
         let (tx, _) = oneshot::channel();
 
-        self.send_command(DaemonCommand::Reconnect(tx))?;
+        self.send_command(DaemonCommand::Reconnect(
+            tx,
+            talpid_types::tunnel::ReconnectReason::UserRequested,
+        ))?;
 
         Ok(())
     }
@@ -195,7 +199,11 @@ impl DaemonInterface {
     pub fn login_account(&self, account_token: String) -> Result<()> {
         let (tx, rx) = oneshot::channel();
 
-        self.send_command(DaemonCommand::LoginAccount(tx, account_token))?;
+        self.send_command(DaemonCommand::LoginAccount(
+            tx,
+            account_token,
+            LoginMode::Remember,
+        ))?;
 
         block_on(rx)
             .map_err(|_| Error::NoResponse)?
@@ -7,14 +7,19 @@ use jnix::{
 };
 use mullvad_daemon::EventListener;
 use mullvad_types::{
+    auto_connect::AutoConnectDecision,
+    cache::QuarantinedFile,
     device::{DeviceEvent, RemoveDeviceEvent},
     relay_list::RelayList,
     settings::Settings,
     states::TunnelState,
     version::AppVersionInfo,
 };
-use std::{sync::mpsc, thread};
-use talpid_types::ErrorExt;
+use std::{
+    sync::{mpsc, Arc},
+    thread,
+};
+use talpid_types::{conflict::ConflictingSoftware, ErrorExt};
 
 #[derive(Debug, err_derive::Error)]
 #[error(no_from)]
@@ -56,8 +61,8 @@ impl EventListener for JniEventListener {
         let _ = self.0.send(Event::Settings(settings));
     }
 
-    fn notify_relay_list(&self, relay_list: RelayList) {
-        let _ = self.0.send(Event::RelayList(relay_list));
+    fn notify_relay_list(&self, relay_list: Arc<RelayList>) {
+        let _ = self.0.send(Event::RelayList(RelayList::clone(&relay_list)));
     }
 
     fn notify_app_version(&self, app_version_info: AppVersionInfo) {
@@ -71,6 +76,18 @@ impl EventListener for JniEventListener {
     fn notify_remove_device_event(&self, event: RemoveDeviceEvent) {
         let _ = self.0.send(Event::RemoveDeviceEvent(event));
     }
+
+    fn notify_auto_connect_decision(&self, _decision: AutoConnectDecision) {
+        // The Android app evaluates auto-connect on its own, so there is nothing to forward.
+    }
+
+    fn notify_conflicting_software(&self, _conflicts: Vec<ConflictingSoftware>) {
+        // Android only allows one VPN to be active at a time, so nothing is ever detected.
+    }
+
+    fn notify_quarantined_files(&self, _files: Vec<QuarantinedFile>) {
+        // The app has no UI for this. The files are already logged by the daemon.
+    }
 }
 
 struct JniEventHandler<'env> {
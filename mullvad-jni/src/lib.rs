@@ -18,7 +18,7 @@ use jnix::{
     },
     FromJava, IntoJava, JnixEnv,
 };
-use mullvad_api::{rest::Error as RestError, StatusCode};
+use mullvad_api::{rest::Error as RestError, ApiError, StatusCode};
 use mullvad_daemon::{
     device, exception_logging, logging, runtime::new_runtime_builder, version, Daemon,
     DaemonCommandChannel,
@@ -82,7 +82,12 @@ impl From<Result<AccountData, daemon_interface::Error>> for GetAccountDataResult
         match result {
             Ok(account_data) => GetAccountDataResult::Ok(account_data),
             Err(error) => match error {
-                daemon_interface::Error::RpcError(RestError::ApiError(status, _code))
+                daemon_interface::Error::RpcError(RestError::ApiError(
+                    _,
+                    ApiError::InvalidAccount | ApiError::InvalidAccessToken,
+                    _,
+                )) => GetAccountDataResult::InvalidAccount,
+                daemon_interface::Error::RpcError(RestError::ApiError(status, _, _))
                     if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN =>
                 {
                     GetAccountDataResult::InvalidAccount
@@ -181,13 +186,11 @@ impl From<Result<VoucherSubmission, daemon_interface::Error>> for VoucherSubmiss
 impl From<daemon_interface::Error> for VoucherSubmissionError {
     fn from(error: daemon_interface::Error) -> Self {
         match error {
-            daemon_interface::Error::RpcError(RestError::ApiError(_, code)) => {
-                match code.as_str() {
-                    mullvad_api::INVALID_VOUCHER => VoucherSubmissionError::InvalidVoucher,
-                    mullvad_api::VOUCHER_USED => VoucherSubmissionError::VoucherAlreadyUsed,
-                    _ => VoucherSubmissionError::RpcError,
-                }
-            }
+            daemon_interface::Error::RpcError(RestError::ApiError(_, code, _)) => match code {
+                ApiError::InvalidVoucher => VoucherSubmissionError::InvalidVoucher,
+                ApiError::VoucherUsed => VoucherSubmissionError::VoucherAlreadyUsed,
+                _ => VoucherSubmissionError::RpcError,
+            },
             daemon_interface::Error::RpcError(_) => VoucherSubmissionError::RpcError,
             _ => VoucherSubmissionError::OtherError,
         }
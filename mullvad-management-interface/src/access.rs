@@ -0,0 +1,285 @@
+use futures::future::{self, Either};
+use mullvad_types::settings::{AccessRole, ManagementAccessPolicy};
+#[cfg(unix)]
+use std::{collections::HashMap, sync::RwLock};
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tonic::{
+    body::BoxBody,
+    codegen::http::{Request, Response},
+    Status,
+};
+use tower::{Layer, Service};
+
+/// RPCs that only query the state of the daemon, and are therefore available to users with the
+/// [`AccessRole::ReadOnly`] role. Calls that reveal the account number, the device or proxy
+/// credentials are not included, and neither is the event stream, which carries the same data.
+/// `SetAppForeground` is included since every GUI makes it, and it only affects how often the
+/// account is polled.
+const READ_ONLY_METHODS: &[&str] = &[
+    "GetTunnelState",
+    "GetCurrentVersion",
+    "GetVersionInfo",
+    "IsPerformingPostUpgrade",
+    "GetRelayLocations",
+    "GetCurrentLocation",
+    "GetNetworkInfo",
+    "CheckDnsBackend",
+    "GetSplitTunnelProcesses",
+    "GetExcludedProcesses",
+    "SetAppForeground",
+];
+
+/// RPCs that require the [`AccessRole::Admin`] role, since they decide who may do what.
+const ADMIN_METHODS: &[&str] = &["SetManagementAccessPolicy"];
+
+/// Credentials of the process on the other end of a management interface connection.
+#[derive(Debug, Clone)]
+#[cfg_attr(windows, allow(dead_code))]
+pub(crate) struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Name of the user, which is looked up when the client connects.
+    pub user_name: Option<Arc<str>>,
+}
+
+impl PeerCredentials {
+    /// Looks up the name of the user with the ID `uid`. This blocks, so it is done on a thread
+    /// where that is allowed.
+    #[cfg(unix)]
+    pub async fn new(uid: u32, gid: u32) -> Self {
+        use nix::unistd::{Uid, User};
+
+        let user_name = tokio::task::spawn_blocking(move || {
+            User::from_uid(Uid::from_raw(uid))
+                .ok()
+                .flatten()
+                .map(|user| Arc::from(user.name))
+        })
+        .await
+        .ok()
+        .flatten();
+        Self {
+            uid,
+            gid,
+            user_name,
+        }
+    }
+}
+
+/// An access policy along with the members of the groups it mentions.
+#[cfg(unix)]
+#[derive(Debug)]
+struct ResolvedPolicy {
+    policy: ManagementAccessPolicy,
+    /// Names of the members of each group in `policy`. These are looked up when the policy is
+    /// set, rather than for each request, since the lookups may block.
+    group_members: HashMap<u32, Vec<String>>,
+}
+
+#[cfg(unix)]
+impl ResolvedPolicy {
+    fn new(policy: ManagementAccessPolicy) -> Self {
+        use nix::unistd::{Gid, Group};
+
+        let group_members = policy
+            .control_gids
+            .iter()
+            .chain(&policy.read_only_gids)
+            .filter_map(|gid| {
+                let group = Group::from_gid(Gid::from_raw(*gid)).ok().flatten()?;
+                Some((*gid, group.mem))
+            })
+            .collect();
+        Self {
+            policy,
+            group_members,
+        }
+    }
+
+    /// Returns the groups that the peer belongs to, out of those that are mentioned in the
+    /// policy.
+    fn peer_groups(&self, credentials: &PeerCredentials) -> Vec<u32> {
+        let mut gids = vec![credentials.gid];
+        let user_name = match &credentials.user_name {
+            Some(user_name) => user_name,
+            None => return gids,
+        };
+        for (gid, members) in &self.group_members {
+            if !gids.contains(gid)
+                && members
+                    .iter()
+                    .any(|member| member.as_str() == user_name.as_ref())
+            {
+                gids.push(*gid);
+            }
+        }
+        gids
+    }
+}
+
+/// The access policy enforced by the management interface server. Until a policy has been set,
+/// only root may do anything other than query the daemon.
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicyHandle(#[cfg(unix)] Arc<RwLock<Option<ResolvedPolicy>>>);
+
+impl AccessPolicyHandle {
+    /// Enforces `policy` from now on. Group memberships are looked up again only if the policy
+    /// has changed.
+    #[cfg(unix)]
+    pub fn set(&self, policy: ManagementAccessPolicy) {
+        if let Some(current) = &*self.0.read().unwrap() {
+            if current.policy == policy {
+                return;
+            }
+        }
+        let resolved = ResolvedPolicy::new(policy);
+        *self.0.write().unwrap() = Some(resolved);
+    }
+
+    /// The policy is not enforced on this platform.
+    #[cfg(windows)]
+    pub fn set(&self, _policy: ManagementAccessPolicy) {}
+
+    /// Returns the role of a client, or `None` if it may not use the management interface.
+    #[cfg(unix)]
+    fn role(&self, credentials: Option<&PeerCredentials>) -> Option<AccessRole> {
+        // Credentials are only missing if they could not be read from the socket
+        let credentials = credentials?;
+        let policy = self.0.read().unwrap();
+        match &*policy {
+            Some(resolved) => {
+                let gids = resolved.peer_groups(credentials);
+                resolved.policy.role(credentials.uid, &gids)
+            }
+            None if credentials.uid == 0 => Some(AccessRole::Admin),
+            None => Some(AccessRole::ReadOnly),
+        }
+    }
+
+    /// The credentials of clients are not checked on this platform, where the policy cannot be
+    /// changed either.
+    #[cfg(windows)]
+    fn role(&self, _credentials: Option<&PeerCredentials>) -> Option<AccessRole> {
+        Some(AccessRole::Control)
+    }
+}
+
+fn required_role(path: &str) -> AccessRole {
+    let method = path.rsplit('/').next().unwrap_or_default();
+    if READ_ONLY_METHODS.contains(&method) {
+        AccessRole::ReadOnly
+    } else if ADMIN_METHODS.contains(&method) {
+        AccessRole::Admin
+    } else {
+        AccessRole::Control
+    }
+}
+
+/// Rejects requests that the connecting user is not allowed to make.
+#[derive(Clone)]
+pub(crate) struct AuthorizationLayer {
+    policy: AccessPolicyHandle,
+}
+
+impl AuthorizationLayer {
+    pub fn new(policy: AccessPolicyHandle) -> Self {
+        Self { policy }
+    }
+}
+
+impl<S> Layer<S> for AuthorizationLayer {
+    type Service = AuthorizationService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthorizationService {
+            inner,
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct AuthorizationService<S> {
+    inner: S,
+    policy: AccessPolicyHandle,
+}
+
+impl<S, B> Service<Request<B>> for AuthorizationService<S>
+where
+    S: Service<Request<B>, Response = Response<BoxBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<S::Future, future::Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let credentials = request
+            .extensions()
+            .get::<Option<PeerCredentials>>()
+            .and_then(Option::as_ref);
+        let role = self.policy.role(credentials);
+        let required = required_role(request.uri().path());
+
+        if role.map(|role| role >= required).unwrap_or(false) {
+            Either::Left(self.inner.call(request))
+        } else {
+            log::warn!(
+                "Denied management interface request {} from a client with role {:?}",
+                request.uri().path(),
+                role
+            );
+            let status = Status::permission_denied("Not allowed by the management access policy");
+            Either::Right(future::ready(Ok(status.to_http())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_required_role() {
+        assert_eq!(
+            required_role("/mullvad_daemon.management_interface.ManagementService/GetTunnelState"),
+            AccessRole::ReadOnly
+        );
+        assert_eq!(
+            required_role("/mullvad_daemon.management_interface.ManagementService/GetSettings"),
+            AccessRole::Control
+        );
+        assert_eq!(
+            required_role(
+                "/mullvad_daemon.management_interface.ManagementService/SetAppForeground"
+            ),
+            AccessRole::ReadOnly
+        );
+        assert_eq!(
+            required_role("/mullvad_daemon.management_interface.ManagementService/GetDevice"),
+            AccessRole::Control
+        );
+        assert_eq!(
+            required_role(
+                "/mullvad_daemon.management_interface.ManagementService/SetManagementAccessPolicy"
+            ),
+            AccessRole::Admin
+        );
+        assert_eq!(
+            required_role(
+                "/mullvad_daemon.management_interface.ManagementService/DisconnectTunnel"
+            ),
+            AccessRole::Control
+        );
+        assert_eq!(
+            required_role("/mullvad_daemon.management_interface.ManagementService/GetWwwAuthToken"),
+            AccessRole::Control
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Connecting to the daemon. The management interface is looked for at every path returned by
+//! [`mullvad_paths::get_rpc_socket_paths`], and the first daemon that accepts the connection is
+//! used. Clients that outlive a daemon, for example across an upgrade, can follow the connection
+//! with [`connection_state_stream`], which retries with an increasing delay until it is back.
+
+use crate::{Error, ManagementServiceClient, KEEPALIVE_INTERVAL, KEEPALIVE_TIMEOUT};
+use futures::{future, Stream};
+use parity_tokio_ipc::Endpoint as IpcEndpoint;
+use std::{path::PathBuf, time::Duration};
+use tokio::sync::mpsc;
+use tonic::transport::{Endpoint, Uri};
+use tower::service_fn;
+
+/// Delay before the first retry when the daemon cannot be reached.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// The retry delay is doubled after every failed attempt, up to this.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The state of the connection to the daemon.
+#[derive(Debug, Clone)]
+pub enum ConnectionState {
+    /// Connected to a daemon. The client may be used until the next state is received.
+    Connected(ManagementServiceClient),
+    /// No daemon could be reached, or the connection was lost. A new connection is attempted in
+    /// the background.
+    Disconnected,
+}
+
+/// Connects to the first daemon that accepts the connection.
+pub async fn new_rpc_client() -> Result<ManagementServiceClient, Error> {
+    let mut last_error = None;
+    for path in mullvad_paths::get_rpc_socket_paths() {
+        match connect(path.clone()).await {
+            Ok(client) => return Ok(client),
+            Err(error) => {
+                log::trace!(
+                    "Failed to connect to management interface at {}: {}",
+                    path.display(),
+                    error
+                );
+                last_error = Some(error);
+            }
+        }
+    }
+    Err(last_error.expect("there is at least one socket path"))
+}
+
+/// Returns a stream that reports whether a daemon can be reached, reconnecting whenever the
+/// connection is lost. The first item is sent after the first connection attempt, and after that
+/// an item is only sent when the state changes. The connection is considered lost when the
+/// daemon stops sending events. Reconnection stops when the stream is dropped.
+pub fn connection_state_stream() -> impl Stream<Item = ConnectionState> {
+    let (state_tx, state_rx) = mpsc::unbounded_channel();
+    tokio::spawn(monitor_connection(state_tx));
+    futures::stream::unfold(state_rx, |mut state_rx| async move {
+        state_rx.recv().await.map(|state| (state, state_rx))
+    })
+}
+
+async fn monitor_connection(state_tx: mpsc::UnboundedSender<ConnectionState>) {
+    let mut delay = INITIAL_RETRY_DELAY;
+    let mut disconnected_sent = false;
+    loop {
+        if let Ok(mut client) = new_rpc_client().await {
+            if let Ok(events) = client.events_listen(()).await {
+                if state_tx.send(ConnectionState::Connected(client)).is_err() {
+                    return;
+                }
+                let mut events = events.into_inner();
+                let lost_connection =
+                    async move { while let Ok(Some(_)) = events.message().await {} };
+                futures::pin_mut!(lost_connection);
+                let stream_dropped = state_tx.closed();
+                futures::pin_mut!(stream_dropped);
+                if let future::Either::Right(_) =
+                    future::select(lost_connection, stream_dropped).await
+                {
+                    return;
+                }
+                log::debug!("Lost connection to the management interface");
+                disconnected_sent = false;
+                delay = INITIAL_RETRY_DELAY;
+            }
+        }
+
+        if !disconnected_sent {
+            if state_tx.send(ConnectionState::Disconnected).is_err() {
+                return;
+            }
+            disconnected_sent = true;
+        }
+        tokio::time::sleep(delay).await;
+        delay = next_retry_delay(delay);
+    }
+}
+
+fn next_retry_delay(delay: Duration) -> Duration {
+    (delay * 2).min(MAX_RETRY_DELAY)
+}
+
+async fn connect(ipc_path: PathBuf) -> Result<ManagementServiceClient, Error> {
+    // The URI will be ignored
+    let channel = Endpoint::from_static("lttp://[::]:50051")
+        .http2_keep_alive_interval(KEEPALIVE_INTERVAL)
+        .keep_alive_timeout(KEEPALIVE_TIMEOUT)
+        .keep_alive_while_idle(true)
+        .connect_with_connector(service_fn(move |_: Uri| {
+            IpcEndpoint::connect(ipc_path.clone())
+        }))
+        .await
+        .map_err(Error::GrpcTransportError)?;
+
+    Ok(ManagementServiceClient::new(channel))
+}
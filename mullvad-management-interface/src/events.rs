@@ -0,0 +1,185 @@
+//! Serves `EventsListen` with events that are shared between all subscribers. The generated
+//! service requires every subscriber stream to own its events, so each event would be copied and
+//! encoded once per client. Instead, [`SharedEventsLayer`] answers the call itself, and every
+//! event is encoded once and the encoded bytes are written to each client.
+
+use futures::{
+    future::{self, Either},
+    stream::{BoxStream, StreamExt},
+};
+use once_cell::sync::OnceCell;
+use prost::{bytes::BufMut, Message};
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+use tonic::{
+    body::BoxBody,
+    codec::{Codec, EncodeBuf, Encoder, ProstCodec},
+    codegen::{
+        http::{Request, Response},
+        Body, BoxFuture, StdError,
+    },
+    server::{Grpc, ServerStreamingService},
+    Status,
+};
+use tower::{Layer, Service};
+
+use crate::types;
+
+const EVENTS_LISTEN_PATH: &str =
+    "/mullvad_daemon.management_interface.ManagementService/EventsListen";
+
+/// An event that is sent to every client that listens for events.
+#[derive(Debug, Clone)]
+pub struct SharedDaemonEvent(Arc<SharedDaemonEventInner>);
+
+#[derive(Debug)]
+struct SharedDaemonEventInner {
+    event: types::DaemonEvent,
+    /// Set by the first client stream that sends the event.
+    encoded: OnceCell<Vec<u8>>,
+}
+
+impl SharedDaemonEvent {
+    pub fn new(event: types::DaemonEvent) -> Self {
+        Self(Arc::new(SharedDaemonEventInner {
+            event,
+            encoded: OnceCell::new(),
+        }))
+    }
+
+    pub fn event(&self) -> &types::DaemonEvent {
+        &self.0.event
+    }
+
+    fn encoded(&self) -> &[u8] {
+        self.0.encoded.get_or_init(|| self.0.event.encode_to_vec())
+    }
+}
+
+/// Registers new listeners for events.
+pub trait EventSubscriptions: Clone + Send + Sync + 'static {
+    /// Returns a channel on which every event from now on is received.
+    fn subscribe(&self) -> mpsc::UnboundedReceiver<SharedDaemonEvent>;
+}
+
+/// Answers `EventsListen` calls with streams from `subscriptions`. Other calls are passed on.
+#[derive(Clone)]
+pub(crate) struct SharedEventsLayer<E> {
+    subscriptions: E,
+}
+
+impl<E> SharedEventsLayer<E> {
+    pub fn new(subscriptions: E) -> Self {
+        Self { subscriptions }
+    }
+}
+
+impl<S, E: Clone> Layer<S> for SharedEventsLayer<E> {
+    type Service = SharedEventsService<S, E>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SharedEventsService {
+            inner,
+            subscriptions: self.subscriptions.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct SharedEventsService<S, E> {
+    inner: S,
+    subscriptions: E,
+}
+
+impl<S, E, B> Service<Request<B>> for SharedEventsService<S, E>
+where
+    S: Service<Request<B>, Response = Response<BoxBody>>,
+    E: EventSubscriptions,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<S::Future, BoxFuture<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        if request.uri().path() != EVENTS_LISTEN_PATH {
+            return Either::Left(self.inner.call(request));
+        }
+        let events_listen = EventsListen(self.subscriptions.clone());
+        Either::Right(Box::pin(async move {
+            let mut grpc = Grpc::new(SharedEventCodec);
+            Ok(grpc.server_streaming(events_listen, request).await)
+        }))
+    }
+}
+
+struct EventsListen<E>(E);
+
+impl<E: EventSubscriptions> ServerStreamingService<()> for EventsListen<E> {
+    type Response = SharedDaemonEvent;
+    type ResponseStream = BoxStream<'static, Result<SharedDaemonEvent, Status>>;
+    type Future = future::Ready<Result<tonic::Response<Self::ResponseStream>, Status>>;
+
+    fn call(&mut self, _: tonic::Request<()>) -> Self::Future {
+        let events = futures::stream::unfold(self.0.subscribe(), |mut events| async move {
+            events.recv().await.map(|event| (Ok(event), events))
+        });
+        future::ready(Ok(tonic::Response::new(events.boxed())))
+    }
+}
+
+/// Decodes the empty request like the generated service, and writes events that are already
+/// encoded.
+struct SharedEventCodec;
+
+impl Codec for SharedEventCodec {
+    type Encode = SharedDaemonEvent;
+    type Decode = ();
+    type Encoder = SharedEventEncoder;
+    type Decoder = <ProstCodec<(), ()> as Codec>::Decoder;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        SharedEventEncoder
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        ProstCodec::<(), ()>::default().decoder()
+    }
+}
+
+struct SharedEventEncoder;
+
+impl Encoder for SharedEventEncoder {
+    type Item = SharedDaemonEvent;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
+        dst.put_slice(item.encoded());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use types::daemon_event::Event;
+
+    #[test]
+    fn test_shared_event_is_encoded_once() {
+        let event = SharedDaemonEvent::new(types::DaemonEvent {
+            event: Some(Event::TunnelState(types::TunnelState::default())),
+        });
+        let subscriber_event = event.clone();
+
+        assert_eq!(event.encoded(), event.0.event.encode_to_vec());
+        assert!(std::ptr::eq(event.encoded(), subscriber_event.encoded()));
+    }
+}
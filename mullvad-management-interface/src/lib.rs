@@ -1,24 +1,95 @@
+mod access;
+mod client;
+mod events;
 pub mod types;
 
+pub use access::AccessPolicyHandle;
+use access::{AuthorizationLayer, PeerCredentials};
+pub use client::{connection_state_stream, new_rpc_client, ConnectionState};
+use events::SharedEventsLayer;
+pub use events::{EventSubscriptions, SharedDaemonEvent};
+use futures::Stream;
+#[cfg(windows)]
 use parity_tokio_ipc::Endpoint as IpcEndpoint;
-#[cfg(unix)]
-use std::{env, fs, os::unix::fs::PermissionsExt};
+use prost::Message;
 use std::{
+    collections::HashMap,
     future::Future,
     io,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
+#[cfg(unix)]
+use std::{env, fs, os::unix::fs::PermissionsExt};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
-use tonic::transport::{server::Connected, Endpoint, Server, Uri};
-use tower::service_fn;
+use tonic::{
+    metadata::MetadataValue,
+    transport::{server::Connected, Server},
+};
 
-pub use tonic::{async_trait, transport::Channel, Code, Request, Response, Status};
+pub use tonic::{async_trait, transport::Channel, Code, Request, Response, Status, Streaming};
 
 pub type ManagementServiceClient =
     types::management_service_client::ManagementServiceClient<Channel>;
 pub use types::management_service_server::{ManagementService, ManagementServiceServer};
 
+/// How often HTTP/2 pings are sent on a connection, so that a peer that has gone away, for
+/// example while the machine was asleep, is noticed instead of leaving streams open forever.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+/// How long to wait for a ping to be acknowledged before the connection is considered dead.
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Metadata key under which a [`types::ErrorKind`] is attached to failed RPCs.
+pub const ERROR_KIND_METADATA_KEY: &str = "mullvad-error-kind";
+
+/// Attaches a stable, machine-readable identifier of the error to `status`.
+pub fn with_error_kind(status: Status, kind: types::ErrorKind) -> Status {
+    with_error_details(status, kind, HashMap::new())
+}
+
+/// Attaches a stable, machine-readable identifier of the error to `status`, along with
+/// additional information about the error. These are stored both in the status details, as
+/// [`types::ErrorDetails`], and in the metadata, for clients that cannot decode the details.
+pub fn with_error_details(
+    status: Status,
+    kind: types::ErrorKind,
+    metadata: HashMap<String, String>,
+) -> Status {
+    let details = types::ErrorDetails {
+        kind: kind as i32,
+        metadata,
+    };
+    let mut status_metadata = status.metadata().clone();
+    status_metadata.insert(ERROR_KIND_METADATA_KEY, MetadataValue::from(kind as i32));
+    Status::with_details_and_metadata(
+        status.code(),
+        status.message(),
+        details.encode_to_vec().into(),
+        status_metadata,
+    )
+}
+
+/// Returns the details attached to `status` by [`with_error_details`] or [`with_error_kind`], if
+/// any.
+pub fn error_details(status: &Status) -> Option<types::ErrorDetails> {
+    if !status.details().is_empty() {
+        if let Ok(details) = types::ErrorDetails::decode(status.details()) {
+            return Some(details);
+        }
+    }
+    let value = status.metadata().get(ERROR_KIND_METADATA_KEY)?;
+    Some(types::ErrorDetails {
+        kind: value.to_str().ok()?.parse().ok()?,
+        metadata: HashMap::new(),
+    })
+}
+
+/// Returns the error identifier attached to `status` by [`with_error_kind`], if any.
+pub fn error_kind(status: &Status) -> Option<types::ErrorKind> {
+    types::ErrorKind::from_i32(error_details(status)?.kind)
+}
+
 #[cfg(unix)]
 lazy_static::lazy_static! {
     static ref MULLVAD_MANAGEMENT_SOCKET_GROUP: Option<String> = env::var("MULLVAD_MANAGEMENT_SOCKET_GROUP")
@@ -53,39 +124,67 @@ pub enum Error {
     SetGidError(#[error(source)] nix::Error),
 }
 
-pub async fn new_rpc_client() -> Result<ManagementServiceClient, Error> {
-    let ipc_path = mullvad_paths::get_rpc_socket_path();
-
-    // The URI will be ignored
-    let channel = Endpoint::from_static("lttp://[::]:50051")
-        .connect_with_connector(service_fn(move |_: Uri| {
-            IpcEndpoint::connect(ipc_path.clone())
-        }))
-        .await
-        .map_err(Error::GrpcTransportError)?;
-
-    Ok(ManagementServiceClient::new(channel))
-}
-
 pub type ServerJoinHandle = tokio::task::JoinHandle<Result<(), Error>>;
 
-pub async fn spawn_rpc_server<T: ManagementService, F: Future<Output = ()> + Send + 'static>(
+/// Starts the management interface server. Requests are authorized according to
+/// `access_policy` before they reach `service`. Clients that listen for events are given streams
+/// from `events` instead of from `service`, so that the events can be shared between them.
+pub async fn spawn_rpc_server<
+    T: ManagementService,
+    E: EventSubscriptions,
+    F: Future<Output = ()> + Send + 'static,
+>(
     service: T,
+    events: E,
     abort_rx: F,
+    access_policy: AccessPolicyHandle,
 ) -> std::result::Result<ServerJoinHandle, Error> {
-    use futures::stream::TryStreamExt;
-    use parity_tokio_ipc::SecurityAttributes;
-
     let socket_path = mullvad_paths::get_rpc_socket_path();
 
-    let mut endpoint = IpcEndpoint::new(socket_path.to_string_lossy().to_string());
-    endpoint.set_security_attributes(
-        SecurityAttributes::allow_everyone_create()
-            .map_err(Error::SecurityAttributes)?
-            .set_mode(0o766)
-            .map_err(Error::SecurityAttributes)?,
-    );
-    let incoming = endpoint.incoming().map_err(Error::StartServerError)?;
+    #[cfg(unix)]
+    let incoming = {
+        let listener =
+            tokio::net::UnixListener::bind(&socket_path).map_err(Error::StartServerError)?;
+        fs::set_permissions(&socket_path, PermissionsExt::from_mode(0o766))
+            .map_err(Error::PermissionsError)?;
+
+        futures::stream::unfold(listener, |listener| async move {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => {
+                    let credentials = match stream.peer_cred() {
+                        Ok(credentials) => {
+                            Some(PeerCredentials::new(credentials.uid(), credentials.gid()).await)
+                        }
+                        Err(error) => {
+                            log::error!("Failed to obtain peer credentials: {}", error);
+                            None
+                        }
+                    };
+                    Ok(StreamBox(stream, credentials))
+                }
+                Err(error) => Err(error),
+            };
+            Some((stream, listener))
+        })
+    };
+
+    #[cfg(windows)]
+    let incoming = {
+        use futures::stream::TryStreamExt;
+        use parity_tokio_ipc::SecurityAttributes;
+
+        let mut endpoint = IpcEndpoint::new(socket_path.to_string_lossy().to_string());
+        endpoint.set_security_attributes(
+            SecurityAttributes::allow_everyone_create()
+                .map_err(Error::SecurityAttributes)?
+                .set_mode(0o766)
+                .map_err(Error::SecurityAttributes)?,
+        );
+        endpoint
+            .incoming()
+            .map_err(Error::StartServerError)?
+            .map_ok(|stream| StreamBox(stream, None))
+    };
 
     #[cfg(unix)]
     if let Some(group_name) = &*MULLVAD_MANAGEMENT_SOCKET_GROUP {
@@ -97,22 +196,49 @@ pub async fn spawn_rpc_server<T: ManagementService, F: Future<Output = ()> + Sen
             .map_err(Error::PermissionsError)?;
     }
 
-    Ok(tokio::spawn(async move {
-        Server::builder()
-            .add_service(ManagementServiceServer::new(service))
-            .serve_with_incoming_shutdown(incoming.map_ok(StreamBox), abort_rx)
-            .await
-            .map_err(Error::GrpcTransportError)
-    }))
+    Ok(tokio::spawn(serve(
+        service,
+        events,
+        incoming,
+        abort_rx,
+        access_policy,
+    )))
 }
 
+async fn serve<T, E, F, I, IO>(
+    service: T,
+    events: E,
+    incoming: I,
+    abort_rx: F,
+    access_policy: AccessPolicyHandle,
+) -> Result<(), Error>
+where
+    T: ManagementService,
+    E: EventSubscriptions,
+    F: Future<Output = ()> + Send + 'static,
+    I: Stream<Item = io::Result<StreamBox<IO>>>,
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    Server::builder()
+        .http2_keepalive_interval(Some(KEEPALIVE_INTERVAL))
+        .http2_keepalive_timeout(Some(KEEPALIVE_TIMEOUT))
+        .layer(AuthorizationLayer::new(access_policy))
+        .layer(SharedEventsLayer::new(events))
+        .add_service(ManagementServiceServer::new(service))
+        .serve_with_incoming_shutdown(incoming, abort_rx)
+        .await
+        .map_err(Error::GrpcTransportError)
+}
+
+/// A connection to the management interface, along with the credentials of the connecting
+/// process, if they are known.
 #[derive(Debug)]
-struct StreamBox<T: AsyncRead + AsyncWrite>(pub T);
+struct StreamBox<T: AsyncRead + AsyncWrite>(pub T, Option<PeerCredentials>);
 impl<T: AsyncRead + AsyncWrite> Connected for StreamBox<T> {
-    type ConnectInfo = Option<()>;
+    type ConnectInfo = Option<PeerCredentials>;
 
     fn connect_info(&self) -> Self::ConnectInfo {
-        None
+        self.1.clone()
     }
 }
 impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for StreamBox<T> {
@@ -141,3 +267,40 @@ impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for StreamBox<T> {
         Pin::new(&mut self.0).poll_shutdown(cx)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_kind_roundtrip() {
+        let status = with_error_kind(
+            Status::not_found("This voucher code is invalid"),
+            types::ErrorKind::InvalidVoucher,
+        );
+        assert_eq!(error_kind(&status), Some(types::ErrorKind::InvalidVoucher));
+        assert_eq!(error_kind(&Status::not_found("no kind")), None);
+    }
+
+    #[test]
+    fn test_error_details_roundtrip() {
+        let metadata = HashMap::from([("api_code".to_owned(), "VOUCHER_USED".to_owned())]);
+        let status = with_error_details(
+            Status::resource_exhausted("This voucher code has already been used"),
+            types::ErrorKind::UsedVoucher,
+            metadata.clone(),
+        );
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        let details = error_details(&status).unwrap();
+        assert_eq!(details.kind(), types::ErrorKind::UsedVoucher);
+        assert_eq!(details.metadata, metadata);
+
+        // Older daemons only set the metadata
+        let mut status = Status::not_found("no key");
+        status.metadata_mut().insert(
+            ERROR_KIND_METADATA_KEY,
+            MetadataValue::from(types::ErrorKind::NoWireguardKey as i32),
+        );
+        assert_eq!(error_kind(&status), Some(types::ErrorKind::NoWireguardKey));
+    }
+}
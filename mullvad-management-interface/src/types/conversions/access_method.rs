@@ -0,0 +1,239 @@
+use crate::types::{proto, FromProtobufTypeError};
+use mullvad_types::access_method::{AccessMethod, CustomProxy};
+use proto::{
+    api_access_method::{self, Proxy},
+    bridge_settings,
+};
+use talpid_types::net::{
+    obfs4::{IatMode, Obfs4Settings},
+    openvpn::{ProxyAuth, RemoteProxySettings, ShadowsocksProxySettings},
+    TransportProtocol,
+};
+
+/// Passwords are left empty, since settings are readable by every frontend. An update with an
+/// empty password keeps the stored one.
+impl From<&AccessMethod> for proto::ApiAccessMethod {
+    fn from(method: &AccessMethod) -> Self {
+        let proxy = match &method.proxy {
+            CustomProxy::Shadowsocks(settings) => {
+                Proxy::Shadowsocks(bridge_settings::ShadowsocksProxySettings {
+                    peer: settings.peer.to_string(),
+                    password: String::new(),
+                    cipher: settings.cipher.clone(),
+                    udp: settings.protocol == TransportProtocol::Udp,
+                })
+            }
+            CustomProxy::Socks5(settings) => Proxy::Socks5(remote_proxy_without_password(settings)),
+            CustomProxy::Http(settings) => Proxy::Http(remote_proxy_without_password(settings)),
+            CustomProxy::Obfs4(settings) => {
+                Proxy::Obfs4(api_access_method::Obfs4Settings::from(settings))
+            }
+        };
+        Self {
+            name: method.name.clone(),
+            enabled: method.enabled,
+            proxy: Some(proxy),
+        }
+    }
+}
+
+impl TryFrom<proto::ApiAccessMethod> for AccessMethod {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(method: proto::ApiAccessMethod) -> Result<Self, Self::Error> {
+        let proxy = match method.proxy {
+            Some(Proxy::Shadowsocks(settings)) => {
+                let peer = settings.peer.parse().map_err(|_| {
+                    FromProtobufTypeError::InvalidArgument("failed to parse peer address")
+                })?;
+                CustomProxy::Shadowsocks(ShadowsocksProxySettings {
+                    #[cfg(target_os = "linux")]
+                    fwmark: Some(mullvad_types::TUNNEL_FWMARK),
+                    peer,
+                    password: settings.password,
+                    cipher: settings.cipher,
+                    protocol: if settings.udp {
+                        TransportProtocol::Udp
+                    } else {
+                        TransportProtocol::Tcp
+                    },
+                })
+            }
+            Some(Proxy::Socks5(settings)) => CustomProxy::Socks5(remote_proxy(settings)?),
+            Some(Proxy::Http(settings)) => CustomProxy::Http(remote_proxy(settings)?),
+            Some(Proxy::Obfs4(settings)) => CustomProxy::Obfs4(Obfs4Settings {
+                peer: settings.peer.parse().map_err(|_| {
+                    FromProtobufTypeError::InvalidArgument("failed to parse bridge address")
+                })?,
+                cert: settings.cert,
+                iat_mode: match settings.iat_mode {
+                    0 => IatMode::Disabled,
+                    1 => IatMode::Enabled,
+                    2 => IatMode::Paranoid,
+                    _ => return Err(FromProtobufTypeError::InvalidArgument("invalid iat-mode")),
+                },
+            }),
+            None => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "missing proxy for access method",
+                ))
+            }
+        };
+        Ok(Self {
+            name: method.name,
+            enabled: method.enabled,
+            proxy,
+        })
+    }
+}
+
+impl From<&Obfs4Settings> for proto::api_access_method::Obfs4Settings {
+    fn from(settings: &Obfs4Settings) -> Self {
+        Self {
+            peer: settings.peer.to_string(),
+            cert: settings.cert.clone(),
+            iat_mode: match settings.iat_mode {
+                IatMode::Disabled => 0,
+                IatMode::Enabled => 1,
+                IatMode::Paranoid => 2,
+            },
+        }
+    }
+}
+
+fn remote_proxy_without_password(
+    settings: &RemoteProxySettings,
+) -> bridge_settings::RemoteProxySettings {
+    bridge_settings::RemoteProxySettings {
+        address: settings.address.to_string(),
+        auth: settings
+            .auth
+            .as_ref()
+            .map(|auth| bridge_settings::RemoteProxyAuth {
+                username: auth.username.clone(),
+                password: String::new(),
+            }),
+    }
+}
+
+fn remote_proxy(
+    settings: bridge_settings::RemoteProxySettings,
+) -> Result<RemoteProxySettings, FromProtobufTypeError> {
+    let address = settings
+        .address
+        .parse()
+        .map_err(|_| FromProtobufTypeError::InvalidArgument("failed to parse proxy address"))?;
+    let auth = settings.auth.map(|auth| ProxyAuth {
+        username: auth.username,
+        password: auth.password,
+    });
+    Ok(RemoteProxySettings { address, auth })
+}
+
+impl From<&[AccessMethod]> for proto::ApiAccessMethods {
+    fn from(methods: &[AccessMethod]) -> Self {
+        Self {
+            methods: methods.iter().map(proto::ApiAccessMethod::from).collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::ApiAccessMethods> for Vec<AccessMethod> {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(methods: proto::ApiAccessMethods) -> Result<Self, Self::Error> {
+        methods
+            .methods
+            .into_iter()
+            .map(AccessMethod::try_from)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn shadowsocks(protocol: TransportProtocol) -> AccessMethod {
+        AccessMethod {
+            name: "proxy".to_owned(),
+            enabled: false,
+            proxy: CustomProxy::Shadowsocks(ShadowsocksProxySettings {
+                #[cfg(target_os = "linux")]
+                fwmark: Some(mullvad_types::TUNNEL_FWMARK),
+                peer: "192.0.2.1:443".parse().unwrap(),
+                password: "secret".to_owned(),
+                cipher: "aes-256-gcm".to_owned(),
+                protocol,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_without_passwords() {
+        for protocol in [TransportProtocol::Tcp, TransportProtocol::Udp] {
+            let method = shadowsocks(protocol);
+            let converted = AccessMethod::try_from(proto::ApiAccessMethod::from(&method)).unwrap();
+
+            let mut expected = method.clone();
+            if let CustomProxy::Shadowsocks(settings) = &mut expected.proxy {
+                settings.password = String::new();
+            }
+            assert_eq!(converted, expected);
+        }
+
+        let method = AccessMethod {
+            name: "socks".to_owned(),
+            enabled: true,
+            proxy: CustomProxy::Socks5(RemoteProxySettings {
+                address: "192.0.2.1:1080".parse().unwrap(),
+                auth: Some(ProxyAuth {
+                    username: "user".to_owned(),
+                    password: "secret".to_owned(),
+                }),
+            }),
+        };
+        let converted = proto::ApiAccessMethod::from(&method);
+        match converted.proxy {
+            Some(Proxy::Socks5(settings)) => {
+                let auth = settings.auth.unwrap();
+                assert_eq!(auth.username, "user");
+                assert!(auth.password.is_empty());
+            }
+            _ => panic!("expected a SOCKS5 proxy"),
+        }
+
+        let method = AccessMethod {
+            name: "http".to_owned(),
+            enabled: true,
+            proxy: CustomProxy::Http(RemoteProxySettings {
+                address: "192.0.2.1:3128".parse().unwrap(),
+                auth: None,
+            }),
+        };
+        let converted = AccessMethod::try_from(proto::ApiAccessMethod::from(&method)).unwrap();
+        assert_eq!(converted, method);
+
+        let method = AccessMethod {
+            name: "obfs4".to_owned(),
+            enabled: true,
+            proxy: CustomProxy::Obfs4(Obfs4Settings {
+                peer: "192.0.2.1:443".parse().unwrap(),
+                cert: "c2VjcmV0".to_owned(),
+                iat_mode: IatMode::Enabled,
+            }),
+        };
+        let converted = AccessMethod::try_from(proto::ApiAccessMethod::from(&method)).unwrap();
+        assert_eq!(converted, method);
+    }
+
+    #[test]
+    fn test_missing_proxy() {
+        let method = proto::ApiAccessMethod {
+            name: "proxy".to_owned(),
+            enabled: true,
+            proxy: None,
+        };
+        assert!(AccessMethod::try_from(method).is_err());
+    }
+}
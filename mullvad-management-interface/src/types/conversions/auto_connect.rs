@@ -0,0 +1,132 @@
+use crate::types::{proto, FromProtobufTypeError};
+use mullvad_types::auto_connect::{
+    AutoConnectDecision, AutoConnectPolicy, AutoConnectRule, BackgroundWorkPolicy, NetworkInfo,
+};
+
+impl From<AutoConnectRule> for proto::auto_connect_policy::Rule {
+    fn from(rule: AutoConnectRule) -> Self {
+        match rule {
+            AutoConnectRule::Always => proto::auto_connect_policy::Rule::Always,
+            AutoConnectRule::Never => proto::auto_connect_policy::Rule::Never,
+            AutoConnectRule::UntrustedWifiOnly => {
+                proto::auto_connect_policy::Rule::UntrustedWifiOnly
+            }
+            AutoConnectRule::WiredExempt => proto::auto_connect_policy::Rule::WiredExempt,
+        }
+    }
+}
+
+impl From<proto::auto_connect_policy::Rule> for AutoConnectRule {
+    fn from(rule: proto::auto_connect_policy::Rule) -> Self {
+        match rule {
+            proto::auto_connect_policy::Rule::Always => AutoConnectRule::Always,
+            proto::auto_connect_policy::Rule::Never => AutoConnectRule::Never,
+            proto::auto_connect_policy::Rule::UntrustedWifiOnly => {
+                AutoConnectRule::UntrustedWifiOnly
+            }
+            proto::auto_connect_policy::Rule::WiredExempt => AutoConnectRule::WiredExempt,
+        }
+    }
+}
+
+impl From<&AutoConnectPolicy> for proto::AutoConnectPolicy {
+    fn from(policy: &AutoConnectPolicy) -> Self {
+        Self {
+            rule: i32::from(proto::auto_connect_policy::Rule::from(policy.rule)),
+            trusted_networks: policy.trusted_networks.iter().cloned().collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::AutoConnectPolicy> for AutoConnectPolicy {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(policy: proto::AutoConnectPolicy) -> Result<Self, Self::Error> {
+        let rule = proto::auto_connect_policy::Rule::from_i32(policy.rule).ok_or(
+            FromProtobufTypeError::InvalidArgument("invalid auto-connect rule"),
+        )?;
+        Ok(Self {
+            rule: AutoConnectRule::from(rule),
+            trusted_networks: policy.trusted_networks.into_iter().collect(),
+        })
+    }
+}
+
+impl From<BackgroundWorkPolicy> for proto::BackgroundWorkPolicy {
+    fn from(policy: BackgroundWorkPolicy) -> Self {
+        use proto::background_work_policy::Policy;
+
+        let policy = match policy {
+            BackgroundWorkPolicy::Always => Policy::Always,
+            BackgroundWorkPolicy::TrustedNetworksOnly => Policy::TrustedNetworksOnly,
+        };
+        Self {
+            policy: i32::from(policy),
+        }
+    }
+}
+
+impl TryFrom<proto::BackgroundWorkPolicy> for BackgroundWorkPolicy {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(policy: proto::BackgroundWorkPolicy) -> Result<Self, Self::Error> {
+        use proto::background_work_policy::Policy;
+
+        match Policy::from_i32(policy.policy) {
+            Some(Policy::Always) => Ok(BackgroundWorkPolicy::Always),
+            Some(Policy::TrustedNetworksOnly) => Ok(BackgroundWorkPolicy::TrustedNetworksOnly),
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid background work policy",
+            )),
+        }
+    }
+}
+
+impl From<NetworkInfo> for proto::NetworkInfo {
+    fn from(network: NetworkInfo) -> Self {
+        use proto::network_info::NetworkType;
+
+        let (network_type, ssid, bssid) = match network {
+            NetworkInfo::Unknown => (NetworkType::Unknown, None, None),
+            NetworkInfo::Wired => (NetworkType::Wired, None, None),
+            NetworkInfo::Wifi { ssid, bssid } => (NetworkType::Wifi, ssid, bssid),
+            NetworkInfo::Other => (NetworkType::Other, None, None),
+        };
+        Self {
+            network_type: i32::from(network_type),
+            ssid,
+            bssid,
+        }
+    }
+}
+
+impl TryFrom<proto::NetworkInfo> for NetworkInfo {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(network: proto::NetworkInfo) -> Result<Self, Self::Error> {
+        use proto::network_info::NetworkType;
+
+        match NetworkType::from_i32(network.network_type) {
+            Some(NetworkType::Unknown) => Ok(NetworkInfo::Unknown),
+            Some(NetworkType::Wired) => Ok(NetworkInfo::Wired),
+            Some(NetworkType::Wifi) => Ok(NetworkInfo::Wifi {
+                ssid: network.ssid,
+                bssid: network.bssid,
+            }),
+            Some(NetworkType::Other) => Ok(NetworkInfo::Other),
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid network type",
+            )),
+        }
+    }
+}
+
+impl From<AutoConnectDecision> for proto::AutoConnectDecision {
+    fn from(decision: AutoConnectDecision) -> Self {
+        Self {
+            connect: decision.connect,
+            rule: i32::from(proto::auto_connect_policy::Rule::from(decision.rule)),
+            network: Some(proto::NetworkInfo::from(decision.network)),
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use crate::types::proto;
+use mullvad_types::cache::{CorruptionReason, QuarantinedFile};
+
+impl From<QuarantinedFile> for proto::QuarantinedFile {
+    fn from(file: QuarantinedFile) -> Self {
+        let reason = match file.reason {
+            CorruptionReason::ChecksumMismatch => proto::quarantined_file::Reason::ChecksumMismatch,
+            CorruptionReason::ParseError => proto::quarantined_file::Reason::ParseError,
+        };
+        Self {
+            path: file.path.to_string_lossy().into_owned(),
+            quarantine_path: file
+                .quarantine_path
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            reason: i32::from(reason),
+        }
+    }
+}
+
+impl From<Vec<QuarantinedFile>> for proto::QuarantinedFileList {
+    fn from(files: Vec<QuarantinedFile>) -> Self {
+        Self {
+            files: files
+                .into_iter()
+                .map(proto::QuarantinedFile::from)
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use crate::types::proto;
+use talpid_types::conflict::{ConflictKind, ConflictingSoftware};
+
+impl From<ConflictingSoftware> for proto::ConflictingSoftware {
+    fn from(conflict: ConflictingSoftware) -> Self {
+        let kind = match conflict.kind {
+            ConflictKind::TunnelInterface => proto::conflicting_software::Kind::TunnelInterface,
+            ConflictKind::WfpProvider => proto::conflicting_software::Kind::WfpProvider,
+        };
+        Self {
+            kind: i32::from(kind),
+            name: conflict.name,
+        }
+    }
+}
+
+impl From<Vec<ConflictingSoftware>> for proto::ConflictingSoftwareList {
+    fn from(conflicts: Vec<ConflictingSoftware>) -> Self {
+        Self {
+            conflicts: conflicts
+                .into_iter()
+                .map(proto::ConflictingSoftware::from)
+                .collect(),
+        }
+    }
+}
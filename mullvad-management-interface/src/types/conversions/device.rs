@@ -98,6 +98,7 @@ impl From<mullvad_types::device::DeviceEventCause> for proto::device_event::Caus
             MullvadEvent::Revoked => proto::device_event::Cause::Revoked,
             MullvadEvent::Updated => proto::device_event::Cause::Updated,
             MullvadEvent::RotatedKey => proto::device_event::Cause::RotatedKey,
+            MullvadEvent::RepairedKey => proto::device_event::Cause::RepairedKey,
         }
     }
 }
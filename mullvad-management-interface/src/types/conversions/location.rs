@@ -17,6 +17,14 @@ impl From<mullvad_types::location::GeoIpLocation> for proto::GeoIpLocation {
             bridge_hostname: geoip.bridge_hostname.unwrap_or_default(),
             entry_hostname: geoip.entry_hostname.unwrap_or_default(),
             obfuscator_hostname: geoip.obfuscator_hostname.unwrap_or_default(),
+            provider: geoip.provider.unwrap_or_default(),
+            ownership: i32::from(match geoip.owned {
+                None => proto::Ownership::Any,
+                Some(true) => proto::Ownership::MullvadOwned,
+                Some(false) => proto::Ownership::Rented,
+            }),
+            features: geoip.features.map(proto::RelayCapabilities::from),
+            nearby_fallback_from: geoip.nearby_fallback_from.unwrap_or_default(),
         }
     }
 }
@@ -41,6 +49,21 @@ impl TryFrom<proto::GeoIpLocation> for mullvad_types::location::GeoIpLocation {
             bridge_hostname: option_from_proto_string(geoip.bridge_hostname),
             entry_hostname: option_from_proto_string(geoip.entry_hostname),
             obfuscator_hostname: option_from_proto_string(geoip.obfuscator_hostname),
+            provider: option_from_proto_string(geoip.provider),
+            owned: match proto::Ownership::from_i32(geoip.ownership) {
+                Some(proto::Ownership::Any) => None,
+                Some(proto::Ownership::MullvadOwned) => Some(true),
+                Some(proto::Ownership::Rented) => Some(false),
+                None => {
+                    return Err(FromProtobufTypeError::InvalidArgument(
+                        "invalid ownership argument",
+                    ))
+                }
+            },
+            features: geoip
+                .features
+                .map(mullvad_types::relay_list::RelayCapabilities::from),
+            nearby_fallback_from: option_from_proto_string(geoip.nearby_fallback_from),
         })
     }
 }
@@ -0,0 +1,25 @@
+use crate::types::proto;
+
+impl From<log::Level> for proto::LogLevel {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Trace => proto::LogLevel::Trace,
+            log::Level::Debug => proto::LogLevel::Debug,
+            log::Level::Info => proto::LogLevel::Info,
+            log::Level::Warn => proto::LogLevel::Warn,
+            log::Level::Error => proto::LogLevel::Error,
+        }
+    }
+}
+
+impl From<proto::LogLevel> for log::Level {
+    fn from(level: proto::LogLevel) -> Self {
+        match level {
+            proto::LogLevel::Trace => log::Level::Trace,
+            proto::LogLevel::Debug => log::Level::Debug,
+            proto::LogLevel::Info => log::Level::Info,
+            proto::LogLevel::Warn => log::Level::Warn,
+            proto::LogLevel::Error => log::Level::Error,
+        }
+    }
+}
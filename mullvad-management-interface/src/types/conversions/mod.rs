@@ -1,9 +1,15 @@
 use std::str::FromStr;
 
+mod access_method;
+mod auto_connect;
+mod cache;
+mod conflict;
 mod custom_tunnel;
 mod device;
 mod location;
+mod logging;
 mod net;
+mod network;
 pub mod relay_constraints;
 mod relay_list;
 mod settings;
@@ -140,6 +140,20 @@ impl From<talpid_types::net::IpVersion> for proto::IpVersion {
     }
 }
 
+impl TryFrom<proto::IpVersionConstraint> for talpid_types::net::IpVersion {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(constraint: proto::IpVersionConstraint) -> Result<Self, Self::Error> {
+        match proto::IpVersion::from_i32(constraint.protocol) {
+            Some(proto::IpVersion::V4) => Ok(talpid_types::net::IpVersion::V4),
+            Some(proto::IpVersion::V6) => Ok(talpid_types::net::IpVersion::V6),
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid ip protocol version",
+            )),
+        }
+    }
+}
+
 impl From<proto::TransportProtocol> for talpid_types::net::TransportProtocol {
     fn from(protocol: proto::TransportProtocol) -> Self {
         match protocol {
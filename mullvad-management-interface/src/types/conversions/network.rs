@@ -0,0 +1,224 @@
+use crate::types::{proto, FromProtobufTypeError};
+use mullvad_types::{
+    auto_connect::NetworkInfo,
+    network::{CurrentNetwork, NetworkId, NetworkProfile},
+    relay_constraints::SelectedObfuscation,
+};
+use talpid_types::net::{DefaultRoute, DnsBackendHealth, InterfaceResolvers};
+
+impl From<DefaultRoute> for proto::DefaultRoute {
+    fn from(route: DefaultRoute) -> Self {
+        Self {
+            interface: route.interface,
+            gateway: route.gateway.map(|gateway| gateway.to_string()),
+        }
+    }
+}
+
+impl TryFrom<proto::DefaultRoute> for DefaultRoute {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(route: proto::DefaultRoute) -> Result<Self, Self::Error> {
+        let gateway = route
+            .gateway
+            .map(|gateway| gateway.parse())
+            .transpose()
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid gateway address"))?;
+        Ok(Self {
+            interface: route.interface,
+            gateway,
+        })
+    }
+}
+
+impl From<CurrentNetwork> for proto::CurrentNetwork {
+    fn from(network: CurrentNetwork) -> Self {
+        Self {
+            network: Some(proto::NetworkInfo::from(network.network)),
+            bssid_hash: network.bssid_hash,
+            ipv4_route: network.ipv4_route.map(proto::DefaultRoute::from),
+            ipv6_route: network.ipv6_route.map(proto::DefaultRoute::from),
+        }
+    }
+}
+
+impl TryFrom<proto::CurrentNetwork> for CurrentNetwork {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(network: proto::CurrentNetwork) -> Result<Self, Self::Error> {
+        Ok(Self {
+            network: NetworkInfo::try_from(
+                network
+                    .network
+                    .ok_or(FromProtobufTypeError::InvalidArgument("missing network"))?,
+            )?,
+            bssid_hash: network.bssid_hash,
+            ipv4_route: network.ipv4_route.map(DefaultRoute::try_from).transpose()?,
+            ipv6_route: network.ipv6_route.map(DefaultRoute::try_from).transpose()?,
+        })
+    }
+}
+
+impl From<&NetworkProfile> for proto::NetworkProfile {
+    fn from(profile: &NetworkProfile) -> Self {
+        use proto::{network_info::NetworkType, network_profile::Obfuscation};
+
+        let (network_type, ssid) = match &profile.network {
+            NetworkId::Wired => (NetworkType::Wired, String::new()),
+            NetworkId::Wifi { ssid } => (NetworkType::Wifi, ssid.clone()),
+            NetworkId::Other => (NetworkType::Other, String::new()),
+        };
+        let obfuscation = match profile.obfuscation {
+            None => Obfuscation::Default,
+            Some(SelectedObfuscation::Auto) => Obfuscation::Auto,
+            Some(SelectedObfuscation::Off) => Obfuscation::Off,
+            Some(SelectedObfuscation::Udp2Tcp) => Obfuscation::Udp2tcp,
+            Some(SelectedObfuscation::PortHopping) => Obfuscation::PortHopping,
+        };
+        Self {
+            network_type: i32::from(network_type),
+            ssid,
+            connect_timeout: profile.connect_timeout,
+            wireguard_keepalive: profile.wireguard_keepalive.map(u32::from),
+            obfuscation: i32::from(obfuscation),
+            mtu: profile.mtu.map(u32::from),
+        }
+    }
+}
+
+impl TryFrom<proto::NetworkProfile> for NetworkProfile {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(profile: proto::NetworkProfile) -> Result<Self, Self::Error> {
+        use proto::{network_info::NetworkType, network_profile::Obfuscation};
+
+        let network = match NetworkType::from_i32(profile.network_type) {
+            Some(NetworkType::Wired) => NetworkId::Wired,
+            Some(NetworkType::Wifi) if !profile.ssid.is_empty() => {
+                NetworkId::Wifi { ssid: profile.ssid }
+            }
+            Some(NetworkType::Wifi) => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "missing SSID for Wi-Fi network profile",
+                ))
+            }
+            Some(NetworkType::Other) => NetworkId::Other,
+            Some(NetworkType::Unknown) | None => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid network type for network profile",
+                ))
+            }
+        };
+        let obfuscation = match Obfuscation::from_i32(profile.obfuscation) {
+            Some(Obfuscation::Default) => None,
+            Some(Obfuscation::Auto) => Some(SelectedObfuscation::Auto),
+            Some(Obfuscation::Off) => Some(SelectedObfuscation::Off),
+            Some(Obfuscation::Udp2tcp) => Some(SelectedObfuscation::Udp2Tcp),
+            Some(Obfuscation::PortHopping) => Some(SelectedObfuscation::PortHopping),
+            None => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid obfuscation for network profile",
+                ))
+            }
+        };
+        let to_u16 = |value: Option<u32>, error| {
+            value
+                .map(u16::try_from)
+                .transpose()
+                .map_err(|_| FromProtobufTypeError::InvalidArgument(error))
+        };
+        let profile = Self {
+            network,
+            connect_timeout: profile.connect_timeout,
+            wireguard_keepalive: to_u16(profile.wireguard_keepalive, "invalid keepalive interval")?,
+            obfuscation,
+            mtu: to_u16(profile.mtu, "invalid MTU")?,
+        };
+        profile
+            .validate()
+            .map_err(FromProtobufTypeError::InvalidArgument)?;
+        Ok(profile)
+    }
+}
+
+impl From<&[NetworkProfile]> for proto::NetworkProfiles {
+    fn from(profiles: &[NetworkProfile]) -> Self {
+        Self {
+            profiles: profiles.iter().map(proto::NetworkProfile::from).collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::NetworkProfiles> for Vec<NetworkProfile> {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(profiles: proto::NetworkProfiles) -> Result<Self, Self::Error> {
+        profiles
+            .profiles
+            .into_iter()
+            .map(NetworkProfile::try_from)
+            .collect()
+    }
+}
+
+impl From<InterfaceResolvers> for proto::InterfaceResolvers {
+    fn from(resolvers: InterfaceResolvers) -> Self {
+        Self {
+            interface: resolvers.interface,
+            servers: resolvers
+                .servers
+                .iter()
+                .map(|server| server.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::InterfaceResolvers> for InterfaceResolvers {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(resolvers: proto::InterfaceResolvers) -> Result<Self, Self::Error> {
+        let servers = resolvers
+            .servers
+            .iter()
+            .map(|server| server.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid resolver address"))?;
+        Ok(Self {
+            interface: resolvers.interface,
+            servers,
+        })
+    }
+}
+
+impl From<DnsBackendHealth> for proto::DnsBackendHealth {
+    fn from(health: DnsBackendHealth) -> Self {
+        Self {
+            backend: health.backend,
+            error: health.error,
+            resolvers: health
+                .resolvers
+                .into_iter()
+                .map(proto::InterfaceResolvers::from)
+                .collect(),
+            conflicting_managers: health.conflicting_managers,
+        }
+    }
+}
+
+impl TryFrom<proto::DnsBackendHealth> for DnsBackendHealth {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(health: proto::DnsBackendHealth) -> Result<Self, Self::Error> {
+        Ok(Self {
+            backend: health.backend,
+            error: health.error,
+            resolvers: health
+                .resolvers
+                .into_iter()
+                .map(InterfaceResolvers::try_from)
+                .collect::<Result<_, _>>()?,
+            conflicting_managers: health.conflicting_managers,
+        })
+    }
+}
@@ -123,6 +123,7 @@ impl TryFrom<proto::RelaySettings> for mullvad_types::relay_constraints::RelaySe
                         tunnel_protocol,
                         wireguard_constraints,
                         openvpn_constraints,
+                        nearby_locations: settings.nearby_locations,
                     },
                 ))
             }
@@ -187,6 +188,7 @@ impl From<RelaySettingsUpdate> for proto::RelaySettingsUpdate {
                                     .map(proto::TransportPort::from),
                             },
                         ),
+                        nearby_locations: constraints.nearby_locations,
                     },
                 )),
             },
@@ -291,6 +293,7 @@ impl TryFrom<proto::RelaySettingsUpdate> for mullvad_types::relay_constraints::R
                         tunnel_protocol,
                         wireguard_constraints,
                         openvpn_constraints,
+                        nearby_locations: settings.nearby_locations,
                     },
                 ))
             }
@@ -320,10 +323,22 @@ impl From<&mullvad_types::relay_constraints::ObfuscationSettings> for proto::Obf
             SelectedObfuscation::Udp2Tcp => {
                 proto::obfuscation_settings::SelectedObfuscation::Udp2tcp
             }
+            SelectedObfuscation::PortHopping => {
+                proto::obfuscation_settings::SelectedObfuscation::PortHopping
+            }
         });
         Self {
             selected_obfuscation,
             udp2tcp: Some(proto::Udp2TcpObfuscationSettings::from(&settings.udp2tcp)),
+            port_hopping: Some(proto::PortHoppingSettings::from(&settings.port_hopping)),
+        }
+    }
+}
+
+impl From<&mullvad_types::relay_constraints::PortHoppingSettings> for proto::PortHoppingSettings {
+    fn from(settings: &mullvad_types::relay_constraints::PortHoppingSettings) -> Self {
+        Self {
+            interval: settings.interval.unwrap_or(0),
         }
     }
 }
@@ -379,6 +394,7 @@ impl From<mullvad_types::relay_constraints::BridgeSettings> for proto::BridgeSet
                         peer: proxy_settings.peer.to_string(),
                         password: proxy_settings.password.clone(),
                         cipher: proxy_settings.cipher,
+                        udp: proxy_settings.protocol == talpid_net::TransportProtocol::Udp,
                     })
                 }
             },
@@ -447,6 +463,8 @@ impl From<mullvad_types::relay_constraints::RelaySettings> for proto::RelaySetti
                             .option()
                             .map(proto::TransportPort::from),
                     }),
+
+                    nearby_locations: constraints.nearby_locations,
                 })
             }
         };
@@ -466,6 +484,30 @@ impl From<mullvad_types::relay_constraints::TransportPort> for proto::TransportP
     }
 }
 
+impl From<mullvad_types::relay_constraints::PortSelectionStep> for proto::PortSelectionStep {
+    fn from(step: mullvad_types::relay_constraints::PortSelectionStep) -> Self {
+        proto::PortSelectionStep {
+            tunnel_type: match step.tunnel_type {
+                TunnelType::Wireguard => i32::from(proto::TunnelType::Wireguard),
+                TunnelType::OpenVpn => i32::from(proto::TunnelType::Openvpn),
+            },
+            transport: Some(proto::TransportPort::from(step.transport)),
+        }
+    }
+}
+
+impl From<&[mullvad_types::relay_constraints::PortSelectionStep]> for proto::PortSelectionOrder {
+    fn from(steps: &[mullvad_types::relay_constraints::PortSelectionStep]) -> Self {
+        proto::PortSelectionOrder {
+            steps: steps
+                .iter()
+                .copied()
+                .map(proto::PortSelectionStep::from)
+                .collect(),
+        }
+    }
+}
+
 impl
     From<
         mullvad_types::relay_constraints::Constraint<
@@ -588,6 +630,11 @@ impl TryFrom<proto::BridgeSettings> for mullvad_types::relay_constraints::Bridge
                 Ok(mullvad_constraints::BridgeSettings::Custom(proxy_settings))
             }
             proto::bridge_settings::Type::Shadowsocks(proxy_settings) => {
+                if proxy_settings.udp {
+                    return Err(FromProtobufTypeError::InvalidArgument(
+                        "Shadowsocks bridges must use TCP",
+                    ));
+                }
                 let peer = proxy_settings.peer.parse().map_err(|_| {
                     FromProtobufTypeError::InvalidArgument("failed to parse peer address")
                 })?;
@@ -598,6 +645,7 @@ impl TryFrom<proto::BridgeSettings> for mullvad_types::relay_constraints::Bridge
                         peer,
                         password: proxy_settings.password,
                         cipher: proxy_settings.cipher,
+                        protocol: talpid_net::TransportProtocol::Tcp,
                     },
                 );
                 Ok(mullvad_constraints::BridgeSettings::Custom(proxy_settings))
@@ -617,6 +665,7 @@ impl TryFrom<proto::ObfuscationSettings> for mullvad_types::relay_constraints::O
                 Some(IpcSelectedObfuscation::Auto) => SelectedObfuscation::Auto,
                 Some(IpcSelectedObfuscation::Off) => SelectedObfuscation::Off,
                 Some(IpcSelectedObfuscation::Udp2tcp) => SelectedObfuscation::Udp2Tcp,
+                Some(IpcSelectedObfuscation::PortHopping) => SelectedObfuscation::PortHopping,
                 None => {
                     return Err(FromProtobufTypeError::InvalidArgument(
                         "invalid selected obfuscator",
@@ -635,13 +684,31 @@ impl TryFrom<proto::ObfuscationSettings> for mullvad_types::relay_constraints::O
             }
         };
 
+        let port_hopping = settings
+            .port_hopping
+            .map(mullvad_types::relay_constraints::PortHoppingSettings::from)
+            .unwrap_or_default();
+
         Ok(Self {
             selected_obfuscation,
             udp2tcp,
+            port_hopping,
         })
     }
 }
 
+impl From<proto::PortHoppingSettings> for mullvad_types::relay_constraints::PortHoppingSettings {
+    fn from(settings: proto::PortHoppingSettings) -> Self {
+        Self {
+            interval: if settings.interval == 0 {
+                None
+            } else {
+                Some(settings.interval)
+            },
+        }
+    }
+}
+
 impl TryFrom<&proto::Udp2TcpObfuscationSettings>
     for mullvad_types::relay_constraints::Udp2TcpObfuscationSettings
 {
@@ -694,6 +761,30 @@ impl TryFrom<proto::TransportPort> for mullvad_types::relay_constraints::Transpo
     }
 }
 
+impl TryFrom<proto::PortSelectionStep> for mullvad_types::relay_constraints::PortSelectionStep {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(step: proto::PortSelectionStep) -> Result<Self, Self::Error> {
+        Ok(mullvad_types::relay_constraints::PortSelectionStep {
+            tunnel_type: super::net::try_tunnel_type_from_i32(step.tunnel_type)?,
+            transport: mullvad_types::relay_constraints::TransportPort::try_from(
+                step.transport
+                    .ok_or(FromProtobufTypeError::InvalidArgument("missing transport"))?,
+            )?,
+        })
+    }
+}
+
+pub fn try_port_selection_order_from_proto(
+    order: proto::PortSelectionOrder,
+) -> Result<Vec<mullvad_types::relay_constraints::PortSelectionStep>, FromProtobufTypeError> {
+    order
+        .steps
+        .into_iter()
+        .map(mullvad_types::relay_constraints::PortSelectionStep::try_from)
+        .collect()
+}
+
 pub fn try_providers_constraint_from_proto(
     providers: &[String],
 ) -> Result<Constraint<mullvad_types::relay_constraints::Providers>, FromProtobufTypeError> {
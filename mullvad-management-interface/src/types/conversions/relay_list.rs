@@ -2,6 +2,7 @@ use crate::types::{
     conversions::{bytes_to_pubkey, option_from_proto_string, to_proto_any, try_from_proto_any},
     proto, FromProtobufTypeError,
 };
+use std::sync::Arc;
 
 impl From<mullvad_types::relay_list::RelayList> for proto::RelayList {
     fn from(relay_list: mullvad_types::relay_list::RelayList) -> Self {
@@ -92,6 +93,38 @@ impl From<mullvad_types::relay_list::RelayListCountry> for proto::RelayListCount
     }
 }
 
+impl TryFrom<proto::RelayListCountry> for mullvad_types::relay_list::RelayListCountry {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(country: proto::RelayListCountry) -> Result<Self, Self::Error> {
+        use mullvad_types::relay_list::{Relay, RelayListCity};
+
+        let cities = country
+            .cities
+            .into_iter()
+            .map(|city| {
+                Ok(RelayListCity {
+                    name: city.name,
+                    code: city.code,
+                    latitude: city.latitude,
+                    longitude: city.longitude,
+                    relays: city
+                        .relays
+                        .into_iter()
+                        .map(Relay::try_from)
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .collect::<Result<_, FromProtobufTypeError>>()?;
+
+        Ok(mullvad_types::relay_list::RelayListCountry {
+            name: country.name,
+            code: country.code,
+            cities,
+        })
+    }
+}
+
 impl From<mullvad_types::relay_list::Relay> for proto::Relay {
     fn from(relay: mullvad_types::relay_list::Relay) -> Self {
         use mullvad_types::relay_list::RelayEndpointData as MullvadEndpointData;
@@ -118,15 +151,16 @@ impl From<mullvad_types::relay_list::Relay> for proto::Relay {
                     "mullvad_daemon.management_interface/WireguardRelayEndpointData",
                     proto::WireguardRelayEndpointData {
                         public_key: data.public_key.as_bytes().to_vec(),
+                        capabilities: Some(proto::RelayCapabilities::from(data.capabilities)),
                     },
                 )),
                 _ => None,
             },
             location: relay.location.map(|location| proto::Location {
-                country: location.country,
-                country_code: location.country_code,
-                city: location.city,
-                city_code: location.city_code,
+                country: location.country.clone(),
+                country_code: location.country_code.clone(),
+                city: location.city.clone(),
+                city_code: location.city_code.clone(),
                 latitude: location.latitude,
                 longitude: location.longitude,
             }),
@@ -162,6 +196,10 @@ impl TryFrom<proto::Relay> for mullvad_types::relay_list::Relay {
                 MullvadEndpointData::Wireguard(
                     mullvad_types::relay_list::WireguardRelayEndpointData {
                         public_key: bytes_to_pubkey(&data.public_key)?,
+                        capabilities: data
+                            .capabilities
+                            .map(mullvad_types::relay_list::RelayCapabilities::from)
+                            .unwrap_or_else(mullvad_types::relay_list::RelayCapabilities::legacy),
                     },
                 )
             }
@@ -192,14 +230,79 @@ impl TryFrom<proto::Relay> for mullvad_types::relay_list::Relay {
             provider: relay.provider,
             weight: relay.weight,
             endpoint_data,
-            location: relay.location.map(|location| MullvadLocation {
-                country: location.country,
-                country_code: location.country_code,
-                city: location.city,
-                city_code: location.city_code,
-                latitude: location.latitude,
-                longitude: location.longitude,
+            location: relay.location.map(|location| {
+                Arc::new(MullvadLocation {
+                    country: location.country,
+                    country_code: location.country_code,
+                    city: location.city,
+                    city_code: location.city_code,
+                    latitude: location.latitude,
+                    longitude: location.longitude,
+                })
             }),
         })
     }
 }
+
+impl From<mullvad_types::relay_list::RelayCapabilities> for proto::RelayCapabilities {
+    fn from(capabilities: mullvad_types::relay_list::RelayCapabilities) -> Self {
+        proto::RelayCapabilities {
+            quantum_resistant: capabilities.quantum_resistant,
+            daita: capabilities.daita,
+            udp2tcp: capabilities.udp2tcp,
+        }
+    }
+}
+
+impl From<proto::RelayCapabilities> for mullvad_types::relay_list::RelayCapabilities {
+    fn from(capabilities: proto::RelayCapabilities) -> Self {
+        mullvad_types::relay_list::RelayCapabilities {
+            quantum_resistant: capabilities.quantum_resistant,
+            daita: capabilities.daita,
+            udp2tcp: capabilities.udp2tcp,
+        }
+    }
+}
+
+impl From<mullvad_types::relay_list::LocationSearchResult> for proto::LocationSearchResult {
+    fn from(result: mullvad_types::relay_list::LocationSearchResult) -> Self {
+        proto::LocationSearchResult {
+            location: Some(proto::RelayLocation::from(result.location)),
+            name: result.name,
+        }
+    }
+}
+
+impl From<mullvad_types::relay_list::RelaySelectionHint> for proto::RelaySelectionHint {
+    fn from(hint: mullvad_types::relay_list::RelaySelectionHint) -> Self {
+        proto::RelaySelectionHint {
+            total: hint.total,
+            steps: hint
+                .steps
+                .into_iter()
+                .map(|step| proto::relay_selection_hint::Step {
+                    constraint: step.constraint,
+                    removed: step.removed,
+                    remaining: step.remaining,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<proto::RelaySelectionHint> for mullvad_types::relay_list::RelaySelectionHint {
+    fn from(hint: proto::RelaySelectionHint) -> Self {
+        mullvad_types::relay_list::RelaySelectionHint {
+            total: hint.total,
+            steps: hint
+                .steps
+                .into_iter()
+                .map(|step| mullvad_types::relay_list::RelaySelectionStep {
+                    constraint: step.constraint,
+                    removed: step.removed,
+                    remaining: step.remaining,
+                })
+                .collect(),
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use crate::types::{proto, FromProtobufTypeError};
-use talpid_types::ErrorExt;
+use std::net::IpAddr;
+#[cfg(target_os = "linux")]
+use talpid_types::cgroup::SplitTunnelMode;
+use talpid_types::{net::AllowedLinkConfigTraffic, tunnel::ConnectionTimeouts, ErrorExt};
 
 impl From<&mullvad_types::settings::Settings> for proto::Settings {
     fn from(settings: &mullvad_types::settings::Settings) -> Self {
@@ -23,6 +26,18 @@ impl From<&mullvad_types::settings::Settings> for proto::Settings {
         #[cfg(not(windows))]
         let split_tunnel = None;
 
+        #[cfg(target_os = "linux")]
+        let split_tunnel_mode = Some(proto::SplitTunnelMode::from(settings.split_tunnel_mode));
+        #[cfg(not(target_os = "linux"))]
+        let split_tunnel_mode = None;
+
+        #[cfg(target_os = "linux")]
+        let connection_sharing = Some(proto::ConnectionSharing::from(
+            settings.shared_interface.clone(),
+        ));
+        #[cfg(not(target_os = "linux"))]
+        let connection_sharing = None;
+
         Self {
             relay_settings: Some(proto::RelaySettings::from(settings.get_relay_settings())),
             bridge_settings: Some(proto::BridgeSettings::from(
@@ -32,12 +47,183 @@ impl From<&mullvad_types::settings::Settings> for proto::Settings {
             allow_lan: settings.allow_lan,
             block_when_disconnected: settings.block_when_disconnected,
             auto_connect: settings.auto_connect,
+            auto_connect_policy: Some(proto::AutoConnectPolicy::from(
+                &settings.auto_connect_policy,
+            )),
             tunnel_options: Some(proto::TunnelOptions::from(&settings.tunnel_options)),
             show_beta_releases: settings.show_beta_releases,
+            check_for_updates: settings.check_for_updates,
+            fetch_location: settings.fetch_location,
+            background_work: Some(proto::BackgroundWorkPolicy::from(settings.background_work)),
             obfuscation_settings: Some(proto::ObfuscationSettings::from(
                 &settings.obfuscation_settings,
             )),
+            auto_port_order: Some(proto::PortSelectionOrder::from(
+                &settings.auto_port_order[..],
+            )),
             split_tunnel,
+            split_tunnel_mode,
+            connection_sharing,
+            system_notifications: settings.system_notifications,
+            allowed_multicast_groups: Some(proto::MulticastGroups::from(
+                &settings.allowed_multicast_groups[..],
+            )),
+            network_profiles: Some(proto::NetworkProfiles::from(&settings.network_profiles[..])),
+            allowed_link_config_traffic: Some(proto::AllowedLinkConfigTraffic::from(
+                settings.allowed_link_config_traffic,
+            )),
+            allowed_lan_hosts: Some(proto::LanHosts::from(&settings.allowed_lan_hosts[..])),
+            management_access: Some(proto::ManagementAccessPolicy::from(
+                &settings.management_access,
+            )),
+            api_access_methods: Some(proto::ApiAccessMethods::from(
+                &settings.api_access_methods[..],
+            )),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<SplitTunnelMode> for proto::SplitTunnelMode {
+    fn from(mode: SplitTunnelMode) -> Self {
+        let mode = match mode {
+            SplitTunnelMode::Exclude => proto::split_tunnel_mode::Mode::Exclude,
+            SplitTunnelMode::Include => proto::split_tunnel_mode::Mode::Include,
+        };
+        Self { mode: mode as i32 }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl TryFrom<proto::SplitTunnelMode> for SplitTunnelMode {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(mode: proto::SplitTunnelMode) -> Result<Self, Self::Error> {
+        match proto::split_tunnel_mode::Mode::from_i32(mode.mode) {
+            Some(proto::split_tunnel_mode::Mode::Exclude) => Ok(SplitTunnelMode::Exclude),
+            Some(proto::split_tunnel_mode::Mode::Include) => Ok(SplitTunnelMode::Include),
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid split tunneling mode",
+            )),
+        }
+    }
+}
+
+impl From<&[IpAddr]> for proto::MulticastGroups {
+    fn from(groups: &[IpAddr]) -> Self {
+        Self {
+            addresses: groups.iter().map(|addr| addr.to_string()).collect(),
+        }
+    }
+}
+
+impl From<&[IpAddr]> for proto::LanHosts {
+    fn from(hosts: &[IpAddr]) -> Self {
+        Self {
+            addresses: hosts.iter().map(|addr| addr.to_string()).collect(),
+        }
+    }
+}
+
+impl From<AllowedLinkConfigTraffic> for proto::AllowedLinkConfigTraffic {
+    fn from(allowed: AllowedLinkConfigTraffic) -> Self {
+        Self {
+            dhcpv4: allowed.dhcpv4,
+            dhcpv6: allowed.dhcpv6,
+            dhcp_unicast: allowed.dhcp_unicast,
+            ndp: allowed.ndp,
+        }
+    }
+}
+
+impl From<proto::AllowedLinkConfigTraffic> for AllowedLinkConfigTraffic {
+    fn from(allowed: proto::AllowedLinkConfigTraffic) -> Self {
+        Self {
+            dhcpv4: allowed.dhcpv4,
+            dhcpv6: allowed.dhcpv6,
+            dhcp_unicast: allowed.dhcp_unicast,
+            ndp: allowed.ndp,
+        }
+    }
+}
+
+impl TryFrom<proto::MulticastGroups> for Vec<IpAddr> {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(groups: proto::MulticastGroups) -> Result<Self, Self::Error> {
+        groups
+            .addresses
+            .iter()
+            .map(|addr| {
+                let addr: IpAddr = super::arg_from_str(addr, "invalid IP address")?;
+                if !addr.is_multicast() {
+                    return Err(FromProtobufTypeError::InvalidArgument(
+                        "not a multicast address",
+                    ));
+                }
+                Ok(addr)
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<proto::LanHosts> for Vec<IpAddr> {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(hosts: proto::LanHosts) -> Result<Self, Self::Error> {
+        hosts
+            .addresses
+            .iter()
+            .map(|addr| {
+                let addr: IpAddr = super::arg_from_str(addr, "invalid IP address")?;
+                if !talpid_types::net::is_lan_address(&addr) {
+                    return Err(FromProtobufTypeError::InvalidArgument(
+                        "not a private or link-local address",
+                    ));
+                }
+                Ok(addr)
+            })
+            .collect()
+    }
+}
+
+impl From<Option<String>> for proto::ConnectionSharing {
+    fn from(interface: Option<String>) -> Self {
+        Self { interface }
+    }
+}
+
+impl TryFrom<proto::ConnectionSharing> for Option<String> {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(sharing: proto::ConnectionSharing) -> Result<Self, Self::Error> {
+        match sharing.interface {
+            Some(interface) if !talpid_types::net::is_valid_interface_name(&interface) => Err(
+                FromProtobufTypeError::InvalidArgument("invalid network interface name"),
+            ),
+            interface => Ok(interface),
+        }
+    }
+}
+
+impl From<&mullvad_types::settings::ManagementAccessPolicy> for proto::ManagementAccessPolicy {
+    fn from(policy: &mullvad_types::settings::ManagementAccessPolicy) -> Self {
+        Self {
+            enabled: policy.enabled,
+            control_uids: policy.control_uids.clone(),
+            control_gids: policy.control_gids.clone(),
+            read_only_gids: policy.read_only_gids.clone(),
+        }
+    }
+}
+
+impl From<proto::ManagementAccessPolicy> for mullvad_types::settings::ManagementAccessPolicy {
+    fn from(policy: proto::ManagementAccessPolicy) -> Self {
+        Self {
+            enabled: policy.enabled,
+            control_uids: policy.control_uids,
+            control_gids: policy.control_gids,
+            read_only_gids: policy.read_only_gids,
         }
     }
 }
@@ -87,6 +273,23 @@ impl From<&mullvad_types::settings::TunnelOptions> for proto::TunnelOptions {
                 #[cfg(not(windows))]
                 use_wireguard_nt: false,
                 use_pq_safe_psk: options.wireguard.options.use_pq_safe_psk,
+                address_family: options
+                    .wireguard
+                    .options
+                    .address_family
+                    .map(|version| {
+                        proto::IpVersionConstraint::from(proto::IpVersion::from(version))
+                    }),
+                dns_options: Some(proto::WireguardDnsOptions {
+                    ip_version: options
+                        .wireguard
+                        .options
+                        .dns_ip_version
+                        .map(|version| {
+                            proto::IpVersionConstraint::from(proto::IpVersion::from(version))
+                        }),
+                    prefer_ipv6: options.wireguard.options.prefer_ipv6_dns,
+                }),
             }),
             generic: Some(proto::tunnel_options::GenericOptions {
                 enable_ipv6: options.generic.enable_ipv6,
@@ -95,10 +298,41 @@ impl From<&mullvad_types::settings::TunnelOptions> for proto::TunnelOptions {
             dns_options: Some(proto::DnsOptions::from(&options.dns_options)),
             #[cfg(target_os = "android")]
             dns_options: None,
+            connection_timeouts: Some(proto::ConnectionTimeouts::from(
+                options.connection_timeouts,
+            )),
+        }
+    }
+}
+
+impl From<ConnectionTimeouts> for proto::ConnectionTimeouts {
+    fn from(timeouts: ConnectionTimeouts) -> Self {
+        Self {
+            connecting_timeout: timeouts.connecting_timeout_secs,
+            initial_retry_delay: timeouts.initial_retry_delay_secs,
+            max_retry_delay: timeouts.max_retry_delay_secs,
+            offline_debounce_ms: timeouts.offline_debounce_ms,
         }
     }
 }
 
+impl TryFrom<proto::ConnectionTimeouts> for ConnectionTimeouts {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(timeouts: proto::ConnectionTimeouts) -> Result<Self, Self::Error> {
+        let timeouts = Self {
+            connecting_timeout_secs: timeouts.connecting_timeout,
+            initial_retry_delay_secs: timeouts.initial_retry_delay,
+            max_retry_delay_secs: timeouts.max_retry_delay,
+            offline_debounce_ms: timeouts.offline_debounce_ms,
+        };
+        timeouts
+            .validate()
+            .map_err(FromProtobufTypeError::InvalidArgument)?;
+        Ok(timeouts)
+    }
+}
+
 impl TryFrom<proto::TunnelOptions> for mullvad_types::settings::TunnelOptions {
     type Error = FromProtobufTypeError;
 
@@ -115,6 +349,7 @@ impl TryFrom<proto::TunnelOptions> for mullvad_types::settings::TunnelOptions {
             .ok_or(FromProtobufTypeError::InvalidArgument(
                 "missing openvpn tunnel options",
             ))?;
+        let wireguard_dns_options = wireguard_options.dns_options.clone().unwrap_or_default();
         let generic_options = options
             .generic
             .ok_or(FromProtobufTypeError::InvalidArgument(
@@ -142,6 +377,17 @@ impl TryFrom<proto::TunnelOptions> for mullvad_types::settings::TunnelOptions {
                         None
                     },
                     use_pq_safe_psk: wireguard_options.use_pq_safe_psk,
+                    address_family: wireguard_options
+                        .address_family
+                        .map(net::IpVersion::try_from)
+                        .transpose()?,
+                    dns_ip_version: wireguard_dns_options
+                        .ip_version
+                        .map(net::IpVersion::try_from)
+                        .transpose()?,
+                    prefer_ipv6_dns: wireguard_dns_options.prefer_ipv6,
+                    connect_timeout: None,
+                    persistent_keepalive: None,
                     #[cfg(windows)]
                     use_wireguard_nt: wireguard_options.use_wireguard_nt,
                 },
@@ -165,6 +411,69 @@ impl TryFrom<proto::TunnelOptions> for mullvad_types::settings::TunnelOptions {
             },
             #[cfg(not(target_os = "android"))]
             dns_options: mullvad_types::settings::DnsOptions::try_from(dns_options)?,
+            connection_timeouts: options
+                .connection_timeouts
+                .map(ConnectionTimeouts::try_from)
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl TryFrom<proto::SettingsUpdate> for mullvad_types::settings::SettingsUpdate {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(update: proto::SettingsUpdate) -> Result<Self, Self::Error> {
+        use mullvad_types::relay_constraints::{
+            BridgeState, ObfuscationSettings, RelaySettingsUpdate,
+        };
+
+        fn try_optional_u16(
+            value: Option<u32>,
+            error: &'static str,
+        ) -> Result<Option<Option<u16>>, FromProtobufTypeError> {
+            value
+                .map(|value| match value {
+                    0 => Ok(None),
+                    value => u16::try_from(value)
+                        .map(Some)
+                        .map_err(|_| FromProtobufTypeError::InvalidArgument(error)),
+                })
+                .transpose()
+        }
+
+        Ok(Self {
+            relay_settings: update
+                .relay_settings
+                .map(RelaySettingsUpdate::try_from)
+                .transpose()?,
+            bridge_state: update.bridge_state.map(BridgeState::try_from).transpose()?,
+            obfuscation_settings: update
+                .obfuscation_settings
+                .map(ObfuscationSettings::try_from)
+                .transpose()?,
+            allow_lan: update.allow_lan,
+            block_when_disconnected: update.block_when_disconnected,
+            auto_connect: update.auto_connect,
+            show_beta_releases: update.show_beta_releases,
+            check_for_updates: update.check_for_updates,
+            fetch_location: update.fetch_location,
+            background_work: update
+                .background_work
+                .map(mullvad_types::auto_connect::BackgroundWorkPolicy::try_from)
+                .transpose()?,
+            enable_ipv6: update.enable_ipv6,
+            dns_options: update
+                .dns_options
+                .map(mullvad_types::settings::DnsOptions::try_from)
+                .transpose()?,
+            openvpn_mssfix: try_optional_u16(update.openvpn_mssfix, "invalid OpenVPN mssfix")?,
+            wireguard_mtu: try_optional_u16(update.wireguard_mtu, "invalid WireGuard MTU")?,
+            quantum_resistant: update.quantum_resistant_tunnel,
+            connection_timeouts: update
+                .connection_timeouts
+                .map(ConnectionTimeouts::try_from)
+                .transpose()?,
         })
     }
 }
@@ -202,6 +511,20 @@ impl TryFrom<proto::DnsOptions> for mullvad_types::settings::DnsOptions {
                     "missing default DNS options",
                 ))?;
 
+        let custom_options = MullvadCustomDnsOptions {
+            addresses: custom_options
+                .addresses
+                .into_iter()
+                .map(|addr| {
+                    addr.parse()
+                        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid IP address"))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        custom_options
+            .validate()
+            .map_err(FromProtobufTypeError::InvalidArgument)?;
+
         Ok(MullvadDnsOptions {
             state,
             default_options: MullvadDefaultDnsOptions {
@@ -211,17 +534,7 @@ impl TryFrom<proto::DnsOptions> for mullvad_types::settings::DnsOptions {
                 block_adult_content: default_options.block_adult_content,
                 block_gambling: default_options.block_gambling,
             },
-            custom_options: MullvadCustomDnsOptions {
-                addresses: custom_options
-                    .addresses
-                    .into_iter()
-                    .map(|addr| {
-                        addr.parse().map_err(|_| {
-                            FromProtobufTypeError::InvalidArgument("invalid IP address")
-                        })
-                    })
-                    .collect::<Result<Vec<_>, _>>()?,
-            },
+            custom_options,
         })
     }
 }
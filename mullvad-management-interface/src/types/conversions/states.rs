@@ -34,25 +34,35 @@ impl From<mullvad_types::states::TunnelState> for proto::TunnelState {
             };
 
         let state = match state {
-            MullvadTunnelState::Disconnected => {
-                proto::tunnel_state::State::Disconnected(proto::tunnel_state::Disconnected {})
-            }
-            MullvadTunnelState::Connecting { endpoint, location } => {
-                proto::tunnel_state::State::Connecting(proto::tunnel_state::Connecting {
-                    relay_info: Some(proto::TunnelStateRelayInfo {
-                        tunnel_endpoint: Some(proto::TunnelEndpoint::from(endpoint)),
-                        location: location.map(proto::GeoIpLocation::from),
-                    }),
-                })
-            }
-            MullvadTunnelState::Connected { endpoint, location } => {
-                proto::tunnel_state::State::Connected(proto::tunnel_state::Connected {
-                    relay_info: Some(proto::TunnelStateRelayInfo {
-                        tunnel_endpoint: Some(proto::TunnelEndpoint::from(endpoint)),
-                        location: location.map(proto::GeoIpLocation::from),
-                    }),
+            MullvadTunnelState::Disconnected { reason } => {
+                proto::tunnel_state::State::Disconnected(proto::tunnel_state::Disconnected {
+                    reason: i32::from(disconnect_reason_to_proto(reason)),
                 })
             }
+            MullvadTunnelState::Connecting {
+                endpoint,
+                location,
+                reconnect_reason,
+                phase,
+            } => proto::tunnel_state::State::Connecting(proto::tunnel_state::Connecting {
+                relay_info: Some(proto::TunnelStateRelayInfo {
+                    tunnel_endpoint: Some(proto::TunnelEndpoint::from(endpoint)),
+                    location: location.map(proto::GeoIpLocation::from),
+                }),
+                reconnect_reason: i32::from(reconnect_reason_to_proto(reconnect_reason)),
+                phase: i32::from(connecting_phase_to_proto(phase)),
+            }),
+            MullvadTunnelState::Connected {
+                endpoint,
+                location,
+                health,
+            } => proto::tunnel_state::State::Connected(proto::tunnel_state::Connected {
+                relay_info: Some(proto::TunnelStateRelayInfo {
+                    tunnel_endpoint: Some(proto::TunnelEndpoint::from(endpoint)),
+                    location: location.map(proto::GeoIpLocation::from),
+                }),
+                health: i32::from(tunnel_health_to_proto(health)),
+            }),
             MullvadTunnelState::Disconnecting(after_disconnect) => {
                 proto::tunnel_state::State::Disconnecting(proto::tunnel_state::Disconnecting {
                     after_disconnect: match after_disconnect {
@@ -128,6 +138,9 @@ impl From<mullvad_types::states::TunnelState> for proto::TunnelState {
                             talpid_tunnel::ParameterGenerationError::CustomTunnelHostResultionError => {
                                 i32::from(GenerationError::CustomTunnelHostResolutionError)
                             }
+                            talpid_tunnel::ParameterGenerationError::MissingRelayCapabilities => {
+                                i32::from(GenerationError::MissingRelayCapabilities)
+                            }
                         }
                             } else {
                                 0
@@ -193,18 +206,26 @@ impl TryFrom<proto::TunnelState> for mullvad_types::states::TunnelState {
         use talpid_types::{net as talpid_net, tunnel as talpid_tunnel};
 
         let state = match state.state {
-            Some(proto::tunnel_state::State::Disconnected(_)) => MullvadState::Disconnected,
+            Some(proto::tunnel_state::State::Disconnected(proto::tunnel_state::Disconnected {
+                reason,
+            })) => MullvadState::Disconnected {
+                reason: disconnect_reason_from_proto(reason)?,
+            },
             Some(proto::tunnel_state::State::Connecting(proto::tunnel_state::Connecting {
                 relay_info:
                     Some(proto::TunnelStateRelayInfo {
                         tunnel_endpoint: Some(tunnel_endpoint),
                         location,
                     }),
+                reconnect_reason,
+                phase,
             })) => MullvadState::Connecting {
                 endpoint: talpid_net::TunnelEndpoint::try_from(tunnel_endpoint)?,
                 location: location
                     .map(mullvad_types::location::GeoIpLocation::try_from)
                     .transpose()?,
+                reconnect_reason: reconnect_reason_from_proto(reconnect_reason)?,
+                phase: connecting_phase_from_proto(phase)?,
             },
             Some(proto::tunnel_state::State::Connected(proto::tunnel_state::Connected {
                 relay_info:
@@ -212,11 +233,13 @@ impl TryFrom<proto::TunnelState> for mullvad_types::states::TunnelState {
                         tunnel_endpoint: Some(tunnel_endpoint),
                         location,
                     }),
+                health,
             })) => MullvadState::Connected {
                 endpoint: talpid_net::TunnelEndpoint::try_from(tunnel_endpoint)?,
                 location: location
                     .map(mullvad_types::location::GeoIpLocation::try_from)
                     .transpose()?,
+                health: tunnel_health_from_proto(health)?,
             },
             Some(proto::tunnel_state::State::Disconnecting(
                 proto::tunnel_state::Disconnecting { after_disconnect },
@@ -284,6 +307,7 @@ impl TryFrom<proto::TunnelState> for mullvad_types::states::TunnelState {
                             Some(proto::error_state::GenerationError::NoMatchingBridgeRelay) => talpid_tunnel::ParameterGenerationError::NoMatchingBridgeRelay,
                             Some(proto::error_state::GenerationError::NoMatchingRelay) => talpid_tunnel::ParameterGenerationError::NoMatchingRelay,
                             Some(proto::error_state::GenerationError::NoWireguardKey) => talpid_tunnel::ParameterGenerationError::NoWireguardKey,
+                            Some(proto::error_state::GenerationError::MissingRelayCapabilities) => talpid_tunnel::ParameterGenerationError::MissingRelayCapabilities,
                             _ => return Err(FromProtobufTypeError::InvalidArgument(
                                 "invalid parameter error",
                             )),
@@ -355,3 +379,209 @@ fn try_firewall_policy_error_from_i32(
         )),
     }
 }
+
+fn reconnect_reason_to_proto(
+    reason: Option<talpid_types::tunnel::ReconnectReason>,
+) -> proto::tunnel_state::connecting::ReconnectReason {
+    use proto::tunnel_state::connecting::ReconnectReason as ProtoReason;
+    use talpid_types::tunnel::ReconnectReason;
+
+    match reason {
+        None => ProtoReason::None,
+        Some(ReconnectReason::UserRequested) => ProtoReason::UserRequested,
+        Some(ReconnectReason::SettingsChanged) => ProtoReason::SettingsChanged,
+        Some(ReconnectReason::KeyRotated) => ProtoReason::KeyRotated,
+        Some(ReconnectReason::AccountChanged) => ProtoReason::AccountChanged,
+        Some(ReconnectReason::AccountCredited) => ProtoReason::AccountCredited,
+        Some(ReconnectReason::AuthFailedRetry) => ProtoReason::AuthFailedRetry,
+        Some(ReconnectReason::RelayMaintenance) => ProtoReason::RelayMaintenance,
+        Some(ReconnectReason::ConnectivityLost) => ProtoReason::ConnectivityLost,
+        Some(ReconnectReason::PortHop) => ProtoReason::PortHop,
+        Some(ReconnectReason::Unhealthy) => ProtoReason::Unhealthy,
+    }
+}
+
+fn reconnect_reason_from_proto(
+    reason: i32,
+) -> Result<Option<talpid_types::tunnel::ReconnectReason>, FromProtobufTypeError> {
+    use proto::tunnel_state::connecting::ReconnectReason as ProtoReason;
+    use talpid_types::tunnel::ReconnectReason;
+
+    let reason = match ProtoReason::from_i32(reason) {
+        Some(ProtoReason::None) => return Ok(None),
+        Some(ProtoReason::UserRequested) => ReconnectReason::UserRequested,
+        Some(ProtoReason::SettingsChanged) => ReconnectReason::SettingsChanged,
+        Some(ProtoReason::KeyRotated) => ReconnectReason::KeyRotated,
+        Some(ProtoReason::AccountChanged) => ReconnectReason::AccountChanged,
+        Some(ProtoReason::AccountCredited) => ReconnectReason::AccountCredited,
+        Some(ProtoReason::AuthFailedRetry) => ReconnectReason::AuthFailedRetry,
+        Some(ProtoReason::RelayMaintenance) => ReconnectReason::RelayMaintenance,
+        Some(ProtoReason::ConnectivityLost) => ReconnectReason::ConnectivityLost,
+        Some(ProtoReason::PortHop) => ReconnectReason::PortHop,
+        Some(ProtoReason::Unhealthy) => ReconnectReason::Unhealthy,
+        None => {
+            return Err(FromProtobufTypeError::InvalidArgument(
+                "invalid reconnect reason",
+            ))
+        }
+    };
+    Ok(Some(reason))
+}
+
+fn disconnect_reason_to_proto(
+    reason: Option<talpid_types::tunnel::DisconnectReason>,
+) -> proto::tunnel_state::disconnected::DisconnectReason {
+    use proto::tunnel_state::disconnected::DisconnectReason as ProtoReason;
+    use talpid_types::tunnel::DisconnectReason;
+
+    match reason {
+        None => ProtoReason::None,
+        Some(DisconnectReason::UserRequested) => ProtoReason::UserRequested,
+        Some(DisconnectReason::LoggedOut) => ProtoReason::LoggedOut,
+        Some(DisconnectReason::AutoConnectPolicy) => ProtoReason::AutoConnectPolicy,
+        Some(DisconnectReason::Shutdown) => ProtoReason::Shutdown,
+        Some(DisconnectReason::TunnelError) => ProtoReason::TunnelError,
+        Some(DisconnectReason::AuthFailed) => ProtoReason::AuthFailed,
+        Some(DisconnectReason::DeviceRevoked) => ProtoReason::DeviceRevoked,
+        Some(DisconnectReason::Offline) => ProtoReason::Offline,
+    }
+}
+
+fn disconnect_reason_from_proto(
+    reason: i32,
+) -> Result<Option<talpid_types::tunnel::DisconnectReason>, FromProtobufTypeError> {
+    use proto::tunnel_state::disconnected::DisconnectReason as ProtoReason;
+    use talpid_types::tunnel::DisconnectReason;
+
+    let reason = match ProtoReason::from_i32(reason) {
+        Some(ProtoReason::None) => return Ok(None),
+        Some(ProtoReason::UserRequested) => DisconnectReason::UserRequested,
+        Some(ProtoReason::LoggedOut) => DisconnectReason::LoggedOut,
+        Some(ProtoReason::AutoConnectPolicy) => DisconnectReason::AutoConnectPolicy,
+        Some(ProtoReason::Shutdown) => DisconnectReason::Shutdown,
+        Some(ProtoReason::TunnelError) => DisconnectReason::TunnelError,
+        Some(ProtoReason::AuthFailed) => DisconnectReason::AuthFailed,
+        Some(ProtoReason::DeviceRevoked) => DisconnectReason::DeviceRevoked,
+        Some(ProtoReason::Offline) => DisconnectReason::Offline,
+        None => {
+            return Err(FromProtobufTypeError::InvalidArgument(
+                "invalid disconnect reason",
+            ))
+        }
+    };
+    Ok(Some(reason))
+}
+
+fn connecting_phase_to_proto(
+    phase: talpid_types::tunnel::ConnectingPhase,
+) -> proto::tunnel_state::connecting::Phase {
+    use proto::tunnel_state::connecting::Phase as ProtoPhase;
+    use talpid_types::tunnel::{ConnectingPhase, DisconnectReason, TunnelHealth};
+
+    match phase {
+        ConnectingPhase::OpeningDevice => ProtoPhase::OpeningDevice,
+        ConnectingPhase::Handshaking => ProtoPhase::Handshaking,
+        ConnectingPhase::VerifyingConnectivity => ProtoPhase::VerifyingConnectivity,
+    }
+}
+
+fn connecting_phase_from_proto(
+    phase: i32,
+) -> Result<talpid_types::tunnel::ConnectingPhase, FromProtobufTypeError> {
+    use proto::tunnel_state::connecting::Phase as ProtoPhase;
+    use talpid_types::tunnel::ConnectingPhase;
+
+    match ProtoPhase::from_i32(phase) {
+        Some(ProtoPhase::OpeningDevice) => Ok(ConnectingPhase::OpeningDevice),
+        Some(ProtoPhase::Handshaking) => Ok(ConnectingPhase::Handshaking),
+        Some(ProtoPhase::VerifyingConnectivity) => Ok(ConnectingPhase::VerifyingConnectivity),
+        None => Err(FromProtobufTypeError::InvalidArgument(
+            "invalid connecting phase",
+        )),
+    }
+}
+
+fn tunnel_health_to_proto(
+    health: talpid_types::tunnel::TunnelHealth,
+) -> proto::tunnel_state::connected::Health {
+    use proto::tunnel_state::connected::Health as ProtoHealth;
+    use talpid_types::tunnel::TunnelHealth;
+
+    match health {
+        TunnelHealth::Healthy => ProtoHealth::Healthy,
+        TunnelHealth::Unstable => ProtoHealth::Unstable,
+        TunnelHealth::HighLatency => ProtoHealth::HighLatency,
+        TunnelHealth::PacketLoss => ProtoHealth::PacketLoss,
+    }
+}
+
+fn tunnel_health_from_proto(
+    health: i32,
+) -> Result<talpid_types::tunnel::TunnelHealth, FromProtobufTypeError> {
+    use proto::tunnel_state::connected::Health as ProtoHealth;
+    use talpid_types::tunnel::TunnelHealth;
+
+    match ProtoHealth::from_i32(health) {
+        Some(ProtoHealth::Healthy) => Ok(TunnelHealth::Healthy),
+        Some(ProtoHealth::Unstable) => Ok(TunnelHealth::Unstable),
+        Some(ProtoHealth::HighLatency) => Ok(TunnelHealth::HighLatency),
+        Some(ProtoHealth::PacketLoss) => Ok(TunnelHealth::PacketLoss),
+        None => Err(FromProtobufTypeError::InvalidArgument(
+            "invalid tunnel health",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::tunnel::ConnectingPhase;
+
+    #[test]
+    fn test_connecting_phase_round_trip() {
+        for phase in [
+            ConnectingPhase::OpeningDevice,
+            ConnectingPhase::Handshaking,
+            ConnectingPhase::VerifyingConnectivity,
+        ] {
+            let converted =
+                connecting_phase_from_proto(i32::from(connecting_phase_to_proto(phase)));
+            assert_eq!(converted.unwrap(), phase);
+        }
+        assert!(connecting_phase_from_proto(i32::MAX).is_err());
+    }
+
+    #[test]
+    fn test_tunnel_health_round_trip() {
+        for health in [
+            TunnelHealth::Healthy,
+            TunnelHealth::Unstable,
+            TunnelHealth::HighLatency,
+            TunnelHealth::PacketLoss,
+        ] {
+            let converted = tunnel_health_from_proto(i32::from(tunnel_health_to_proto(health)));
+            assert_eq!(converted.unwrap(), health);
+        }
+        assert!(tunnel_health_from_proto(i32::MAX).is_err());
+    }
+
+    #[test]
+    fn test_disconnect_reason_round_trip() {
+        for reason in [
+            None,
+            Some(DisconnectReason::UserRequested),
+            Some(DisconnectReason::LoggedOut),
+            Some(DisconnectReason::AutoConnectPolicy),
+            Some(DisconnectReason::Shutdown),
+            Some(DisconnectReason::TunnelError),
+            Some(DisconnectReason::AuthFailed),
+            Some(DisconnectReason::DeviceRevoked),
+            Some(DisconnectReason::Offline),
+        ] {
+            let converted =
+                disconnect_reason_from_proto(i32::from(disconnect_reason_to_proto(reason)));
+            assert_eq!(converted.unwrap(), reason);
+        }
+        assert!(disconnect_reason_from_proto(i32::MAX).is_err());
+    }
+}
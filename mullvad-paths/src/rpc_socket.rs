@@ -1,5 +1,10 @@
 use std::{env, path::PathBuf};
 
+/// Directory under which some containers, such as Toolbox, Distrobox and Flatpak sandboxes with
+/// host access, expose the root filesystem of the host.
+#[cfg(target_os = "linux")]
+const HOST_ROOT_DIR: &str = "/run/host";
+
 pub fn get_rpc_socket_path() -> PathBuf {
     match env::var_os("MULLVAD_RPC_SOCKET_PATH") {
         Some(path) => PathBuf::from(path),
@@ -21,3 +26,72 @@ pub fn get_default_rpc_socket_path() -> PathBuf {
         PathBuf::from(format!("{}/rpc-socket", crate::APP_PATH))
     }
 }
+
+/// Returns the paths where clients should look for the management interface, in the order they
+/// should be tried. If `MULLVAD_RPC_SOCKET_PATH` is set, only that path is returned.
+///
+/// On Linux, the socket is also looked for under `/run`, since `/var/run` is not always a link to
+/// it inside containers, and under the host root of containers that remap it.
+pub fn get_rpc_socket_paths() -> Vec<PathBuf> {
+    if let Some(path) = env::var_os("MULLVAD_RPC_SOCKET_PATH") {
+        return vec![PathBuf::from(path)];
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let host_root = std::path::Path::new(HOST_ROOT_DIR);
+        linux_rpc_socket_paths(Some(host_root).filter(|host_root| host_root.is_dir()))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        vec![get_default_rpc_socket_path()]
+    }
+}
+
+/// Returns the socket paths to try on Linux, first on this system and then under `host_root`.
+#[cfg(target_os = "linux")]
+fn linux_rpc_socket_paths(host_root: Option<&std::path::Path>) -> Vec<PathBuf> {
+    let paths = [
+        get_default_rpc_socket_path(),
+        PathBuf::from("/run/mullvad-vpn"),
+    ];
+    let mut candidates = paths.to_vec();
+    if let Some(host_root) = host_root {
+        candidates.extend(
+            paths
+                .iter()
+                .map(|path| host_root.join(path.strip_prefix("/").unwrap_or(path))),
+        );
+    }
+    candidates
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_rpc_socket_paths_order() {
+        assert_eq!(
+            linux_rpc_socket_paths(None),
+            [
+                PathBuf::from("/var/run/mullvad-vpn"),
+                PathBuf::from("/run/mullvad-vpn"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rpc_socket_paths_under_host_root() {
+        assert_eq!(
+            linux_rpc_socket_paths(Some(Path::new("/run/host"))),
+            [
+                PathBuf::from("/var/run/mullvad-vpn"),
+                PathBuf::from("/run/mullvad-vpn"),
+                PathBuf::from("/run/host/var/run/mullvad-vpn"),
+                PathBuf::from("/run/host/run/mullvad-vpn"),
+            ]
+        );
+    }
+}
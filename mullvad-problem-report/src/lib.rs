@@ -77,6 +77,9 @@ pub enum Error {
 
     #[error(display = "Unable to find cache directory")]
     ObtainCacheDirectory(#[error(source)] mullvad_paths::Error),
+
+    #[error(display = "Failed to read the account number from standard input")]
+    ReadAccountNumber(#[error(source)] io::Error),
 }
 
 /// These are errors that can happen during problem report collection.
@@ -106,13 +109,25 @@ pub enum LogError {
     NoLocalAppDataDir,
 }
 
+/// Identifies the account and device that a report is collected on. Reports are anonymous unless
+/// this is passed to [`collect_report`].
+#[derive(Debug, Clone)]
+pub struct AccountInfo {
+    pub account_token: String,
+    pub device_name: Option<String>,
+}
+
 pub fn collect_report(
     extra_logs: &[&Path],
     output_path: &Path,
     redact_custom_strings: Vec<String>,
+    account_info: Option<&AccountInfo>,
     #[cfg(target_os = "android")] android_log_dir: &Path,
 ) -> Result<(), Error> {
     let mut problem_report = ProblemReport::new(redact_custom_strings);
+    if let Some(account_info) = account_info {
+        problem_report.add_account_info(account_info);
+    }
 
     let daemon_logs_dir = {
         #[cfg(target_os = "android")]
@@ -291,7 +306,7 @@ async fn send_problem_report_inner(
     report_content: &str,
     cache_dir: &Path,
 ) -> Result<(), Error> {
-    let metadata = ProblemReport::parse_metadata(report_content).unwrap_or_else(metadata::collect);
+    let metadata = report_metadata(report_content);
     let api_runtime = mullvad_api::Runtime::with_cache(
         cache_dir,
         false,
@@ -306,7 +321,7 @@ async fn send_problem_report_inner(
             .mullvad_rest_handle(
                 ApiConnectionMode::try_from_cache(cache_dir)
                     .await
-                    .into_repeat(),
+                    .into_repeat_with_direct(),
                 |_| async { true },
             )
             .await,
@@ -336,6 +351,11 @@ async fn send_problem_report_inner(
     Err(Error::SendFailedTooManyTimes)
 }
 
+/// Returns the metadata of a collected report, or of this device if the report has none.
+pub fn report_metadata(report_content: &str) -> BTreeMap<String, String> {
+    ProblemReport::parse_metadata(report_content).unwrap_or_else(metadata::collect)
+}
+
 fn write_problem_report(path: &Path, problem_report: &ProblemReport) -> io::Result<()> {
     let file = File::create(path)?;
     let mut permissions = file.metadata()?.permissions();
@@ -345,6 +365,15 @@ fn write_problem_report(path: &Path, problem_report: &ProblemReport) -> io::Resu
     Ok(())
 }
 
+/// Removes account numbers, the home directory, IP and MAC addresses and GUIDs from `input`, the
+/// same way as in the logs of a problem report.
+pub fn redact(input: &str) -> String {
+    let out1 = ProblemReport::redact_account_number(input);
+    let out2 = ProblemReport::redact_home_dir(&out1);
+    let out3 = ProblemReport::redact_network_info(&out2);
+    ProblemReport::redact_guids(&out3).into_owned()
+}
+
 #[derive(Debug)]
 struct ProblemReport {
     metadata: BTreeMap<String, String>,
@@ -398,6 +427,14 @@ impl ProblemReport {
         }
     }
 
+    /// Add a hash of the account number and the device name to the metadata.
+    pub fn add_account_info(&mut self, account_info: &AccountInfo) {
+        self.metadata.extend(metadata::account(
+            &account_info.account_token,
+            account_info.device_name.as_deref(),
+        ));
+    }
+
     /// Attach an error to the report.
     pub fn add_error(&mut self, message: &'static str, error: &impl ErrorExt) {
         let redacted_error = self.redact(&error.display_chain());
@@ -405,11 +442,7 @@ impl ProblemReport {
     }
 
     fn redact(&self, input: &str) -> String {
-        let out1 = Self::redact_account_number(input);
-        let out2 = Self::redact_home_dir(&out1);
-        let out3 = Self::redact_network_info(&out2);
-        let out4 = Self::redact_guids(&out3);
-        self.redact_custom_strings(&out4).to_string()
+        self.redact_custom_strings(&redact(input)).to_string()
     }
 
     fn redact_account_number(input: &str) -> Cow<'_, str> {
@@ -711,6 +744,36 @@ mod tests {
         assert_eq!(input, res);
     }
 
+    #[test]
+    fn account_info_is_hashed() {
+        let mut report = ProblemReport::new(Vec::new());
+        report.add_account_info(&AccountInfo {
+            account_token: "1234567890123456".to_owned(),
+            device_name: Some("Happy\nOtter".to_owned()),
+        });
+        let mut report_data = Vec::new();
+        report
+            .write_to(&mut report_data)
+            .expect("Unable to write report to vector");
+        let report_string = std::str::from_utf8(&report_data).expect("Report is not correct UTF-8");
+
+        assert!(!report_string.contains("1234567890123456"));
+        let parsed_metadata = ProblemReport::parse_metadata(report_string)
+            .expect("Unable to parse metadata from report");
+        assert_eq!(parsed_metadata["account-hash"].len(), 64);
+        assert_eq!(parsed_metadata["account-hash-salt"].len(), 32);
+        assert_eq!(parsed_metadata["device-name"], "Happy Otter");
+    }
+
+    #[test]
+    fn account_hash_is_salted_per_report() {
+        let first = metadata::account("1234567890123456", None);
+        let second = metadata::account("1234567890123456", None);
+
+        assert_ne!(first["account-hash-salt"], second["account-hash-salt"]);
+        assert_ne!(first["account-hash"], second["account-hash"]);
+    }
+
     #[test]
     fn parse_metadata() {
         let report = ProblemReport::new(Vec::new());
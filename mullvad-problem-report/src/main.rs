@@ -1,8 +1,8 @@
 #![deny(rust_2018_idioms)]
 
 use clap::{crate_authors, crate_name};
-use mullvad_problem_report::{collect_report, Error};
-use std::{env, path::Path, process};
+use mullvad_problem_report::{collect_report, AccountInfo, Error};
+use std::{env, io, path::Path, process};
 use talpid_types::ErrorExt;
 
 fn main() {
@@ -55,6 +55,23 @@ fn run() -> Result<(), Error> {
                         .multiple_occurrences(true)
                         .multiple_values(true)
                         .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::new("account")
+                        .help(
+                            "Read an account number from standard input and include a salted \
+                             hash of it in the report, so that support can match the report \
+                             against the account. Reports are anonymous by default.",
+                        )
+                        .long("include-account"),
+                )
+                .arg(
+                    clap::Arg::new("device_name")
+                        .help("Include the name of the device in the report")
+                        .long("device-name")
+                        .value_name("NAME")
+                        .takes_value(true)
+                        .requires("account"),
                 ),
         )
         .subcommand(
@@ -98,7 +115,26 @@ fn run() -> Result<(), Error> {
             .map(|os_values| os_values.map(Path::new).collect())
             .unwrap_or_else(Vec::new);
         let output_path = Path::new(collect_matches.value_of_os("output").unwrap());
-        collect_report(&extra_logs, output_path, redact_custom_strings)?;
+        let account_info = if collect_matches.is_present("account") {
+            // The account number is not passed as an argument, since those are visible to other
+            // users on the system
+            let mut account_token = String::new();
+            io::stdin()
+                .read_line(&mut account_token)
+                .map_err(Error::ReadAccountNumber)?;
+            Some(AccountInfo {
+                account_token: account_token.trim().to_owned(),
+                device_name: collect_matches.value_of("device_name").map(str::to_owned),
+            })
+        } else {
+            None
+        };
+        collect_report(
+            &extra_logs,
+            output_path,
+            redact_custom_strings,
+            account_info.as_ref(),
+        )?;
 
         let expanded_output_path = output_path
             .canonicalize()
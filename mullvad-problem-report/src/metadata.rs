@@ -1,4 +1,8 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, num::NonZeroU32};
+
+/// Number of PBKDF2 iterations used to hash account numbers. Account numbers are short enough to
+/// be guessed, so every guess is made expensive.
+const ACCOUNT_HASH_ITERATIONS: u32 = 100_000;
 
 pub fn collect() -> BTreeMap<String, String> {
     let mut metadata = BTreeMap::new();
@@ -9,5 +13,46 @@ pub fn collect() -> BTreeMap<String, String> {
     );
     metadata.insert("os".to_owned(), talpid_platform_metadata::version());
     metadata.extend(talpid_platform_metadata::extra_metadata());
+
+    let conflicts = talpid_platform_metadata::conflicting_software();
+    if !conflicts.is_empty() {
+        let conflicts: Vec<String> = conflicts.iter().map(ToString::to_string).collect();
+        metadata.insert("conflicting-software".to_owned(), conflicts.join("; "));
+    }
     metadata
 }
+
+/// Returns metadata that lets support match a report against an account and device. The account
+/// number itself is only included as a hash, salted with a random salt that is unique to the
+/// report, so that reports can't be linked to each other.
+pub fn account(account_token: &str, device_name: Option<&str>) -> BTreeMap<String, String> {
+    let salt = uuid::Uuid::new_v4();
+    let mut metadata = BTreeMap::new();
+    metadata.insert("account-hash-salt".to_owned(), to_hex(salt.as_bytes()));
+    metadata.insert(
+        "account-hash".to_owned(),
+        hash_account(salt.as_bytes(), account_token),
+    );
+    if let Some(device_name) = device_name {
+        // Line breaks would end the metadata section of the report
+        let device_name = device_name.replace(['\r', '\n'], " ");
+        metadata.insert("device-name".to_owned(), device_name);
+    }
+    metadata
+}
+
+fn hash_account(salt: &[u8], account_token: &str) -> String {
+    let mut hash = [0u8; ring::digest::SHA256_OUTPUT_LEN];
+    ring::pbkdf2::derive(
+        ring::pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(ACCOUNT_HASH_ITERATIONS).unwrap(),
+        salt,
+        account_token.as_bytes(),
+        &mut hash,
+    );
+    to_hex(&hash)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -3,16 +3,21 @@
 
 use chrono::{DateTime, Local};
 use ipnetwork::IpNetwork;
+use mullvad_api::cache_integrity;
 use mullvad_types::{
+    cache::CorruptionReason,
     endpoint::{MullvadEndpoint, MullvadWireguardEndpoint},
     location::{Coordinates, Location},
     relay_constraints::{
         BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, LocationConstraint,
-        Match, ObfuscationSettings, OpenVpnConstraints, Ownership, Providers, RelayConstraints,
-        RelaySettings, SelectedObfuscation, Set, TransportPort, Udp2TcpObfuscationSettings,
-        WireguardConstraints,
+        Match, ObfuscationSettings, OpenVpnConstraints, Ownership, PortSelectionStep, Providers,
+        RelayConstraints, RelaySettings, SelectedObfuscation, Set, TransportPort,
+        Udp2TcpObfuscationSettings, WireguardConstraints,
+    },
+    relay_list::{
+        BridgeEndpointData, Relay, RelayCapabilities, RelayEndpointData, RelayList,
+        RelaySelectionHint, RelaySelectionStep,
     },
-    relay_list::{BridgeEndpointData, Relay, RelayEndpointData, RelayList},
     CustomTunnelEndpoint,
 };
 use parking_lot::{Mutex, MutexGuard};
@@ -32,8 +37,10 @@ use talpid_types::{
     ErrorExt,
 };
 
+use match_cache::MatchCache;
 use matcher::{BridgeMatcher, EndpointMatcher, OpenVpnMatcher, RelayMatcher, WireguardMatcher};
 
+mod match_cache;
 mod matcher;
 pub mod updater;
 
@@ -51,6 +58,13 @@ const MIN_BRIDGE_COUNT: usize = 5;
 /// Max distance of bridges to consider for selection (km).
 const MAX_BRIDGE_DISTANCE: f64 = 1500f64;
 
+/// Max number of cities to try when no relay in the selected location matches the constraints.
+const MAX_NEARBY_CITIES: usize = 8;
+
+/// Max distance of cities to try when no relay in the selected location matches the constraints
+/// (km).
+const MAX_NEARBY_DISTANCE: f64 = 1500f64;
+
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
 pub enum Error {
@@ -69,6 +83,15 @@ pub enum Error {
     #[error(display = "No obfuscators matching current constraints")]
     NoObfuscator,
 
+    #[error(
+        display = "No relays matching current constraints support the required features: {}",
+        _0
+    )]
+    MissingCapabilities(RelayCapabilities),
+
+    #[error(display = "The relays do not accept udp2tcp traffic on port {}", _0)]
+    UnsupportedObfuscationPort(u16),
+
     #[error(display = "Failure in serialization of the relay list")]
     Serialize(#[error(source)] serde_json::Error),
 
@@ -78,16 +101,19 @@ pub enum Error {
 
 struct ParsedRelays {
     last_updated: SystemTime,
-    locations: RelayList,
-    relays: Vec<Relay>,
+    /// The relays, with their locations filled in. Relays in the same city share a single
+    /// [`Location`]. The list is shared with consumers rather than copied for each of them.
+    relay_list: Arc<RelayList>,
+    /// Relays matching recently used constraints. This is discarded along with the relay list.
+    match_cache: MatchCache,
 }
 
 impl ParsedRelays {
     pub fn empty() -> Self {
         ParsedRelays {
             last_updated: time::UNIX_EPOCH,
-            locations: RelayList::empty(),
-            relays: Vec::new(),
+            relay_list: Arc::new(RelayList::empty()),
+            match_cache: MatchCache::default(),
         }
     }
 
@@ -100,34 +126,26 @@ impl ParsedRelays {
                 .extend(UDP2TCP_PORTS.into_iter());
         }
 
-        let mut relays = Vec::new();
-        for country in &relay_list.countries {
-            let country_name = country.name.clone();
-            let country_code = country.code.clone();
-            for city in &country.cities {
-                let city_name = city.name.clone();
-                let city_code = city.code.clone();
-                let latitude = city.latitude;
-                let longitude = city.longitude;
-                for relay in &city.relays {
-                    let mut relay_with_location = relay.clone();
-                    relay_with_location.location = Some(Location {
-                        country: country_name.clone(),
-                        country_code: country_code.clone(),
-                        city: city_name.clone(),
-                        city_code: city_code.clone(),
-                        latitude,
-                        longitude,
-                    });
-                    relays.push(relay_with_location);
+        for country in &mut relay_list.countries {
+            for city in &mut country.cities {
+                let location = Arc::new(Location {
+                    country: country.name.clone(),
+                    country_code: country.code.clone(),
+                    city: city.name.clone(),
+                    city_code: city.code.clone(),
+                    latitude: city.latitude,
+                    longitude: city.longitude,
+                });
+                for relay in &mut city.relays {
+                    relay.location = Some(location.clone());
                 }
             }
         }
 
         ParsedRelays {
             last_updated,
-            locations: relay_list,
-            relays,
+            relay_list: Arc::new(relay_list),
+            match_cache: MatchCache::default(),
         }
     }
 
@@ -141,6 +159,27 @@ impl ParsedRelays {
         Ok(Self::from_relay_list(relay_list, last_modified))
     }
 
+    /// Reads relays from the cache. The cache file is memory-mapped rather than read, so that it
+    /// is not kept on the heap while it is parsed. The file is quarantined if it is corrupt.
+    fn from_cache_file(path: &Path) -> Result<Self, Error> {
+        log::debug!("Reading relays from {}", path.display());
+        let last_modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(Error::OpenRelayCache)?;
+        let contents = cache_integrity::map_blocking(path).map_err(Error::OpenRelayCache)?;
+        let relay_list = match serde_json::from_slice(&contents) {
+            Ok(relay_list) => relay_list,
+            Err(error) => {
+                let copy = contents.to_vec();
+                drop(contents);
+                cache_integrity::quarantine_blocking(path, &copy, CorruptionReason::ParseError);
+                return Err(Error::Serialize(error));
+            }
+        };
+
+        Ok(Self::from_relay_list(relay_list, last_modified))
+    }
+
     fn open_file(path: &Path) -> io::Result<(SystemTime, std::fs::File)> {
         let file = std::fs::File::open(path)?;
         let last_modified = file.metadata()?.modified()?;
@@ -151,16 +190,29 @@ impl ParsedRelays {
         self.last_updated
     }
 
-    pub fn locations(&self) -> &RelayList {
-        &self.locations
+    pub fn relay_list(&self) -> Arc<RelayList> {
+        self.relay_list.clone()
     }
 
-    pub fn relays(&self) -> &Vec<Relay> {
-        &self.relays
+    pub fn relays(&self) -> impl Iterator<Item = &Relay> + Clone {
+        self.relay_list.relays()
     }
 
     pub fn tag(&self) -> Option<&str> {
-        self.locations.etag.as_deref()
+        self.relay_list.etag.as_deref()
+    }
+
+    /// Returns the relays matching `matcher`, reusing the result from an earlier call with the
+    /// same constraints if possible.
+    fn matching_relays<T: EndpointMatcher>(
+        &mut self,
+        matcher: &RelayMatcher<T>,
+    ) -> Arc<Vec<Relay>> {
+        let relay_list = &self.relay_list;
+        self.match_cache
+            .get_or_insert_with(matcher.cache_key(), || {
+                matcher.filter_matching_relay_list(relay_list.relays())
+            })
     }
 }
 
@@ -171,6 +223,50 @@ pub struct SelectorConfig {
     pub bridge_settings: BridgeSettings,
     pub obfuscation_settings: ObfuscationSettings,
     pub default_tunnel_type: TunnelType,
+    /// Only select WireGuard relays that can negotiate a quantum-resistant PSK.
+    pub quantum_resistant: bool,
+    /// Tunnel protocols and ports to cycle through on retries. The built-in order is used if this
+    /// is empty.
+    pub auto_port_order: Vec<PortSelectionStep>,
+}
+
+impl SelectorConfig {
+    /// Returns the features that a WireGuard relay must support given the current settings.
+    fn required_capabilities(&self) -> RelayCapabilities {
+        RelayCapabilities {
+            quantum_resistant: self.quantum_resistant,
+            udp2tcp: self.obfuscation_settings.selected_obfuscation == SelectedObfuscation::Udp2Tcp,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the tunnel protocols and ports to cycle through on retries. When port hopping is
+    /// enabled, every attempt uses a random port from the ranges advertised by the WireGuard
+    /// relays.
+    fn port_order(&self) -> Vec<PortSelectionStep> {
+        if self.obfuscation_settings.selected_obfuscation == SelectedObfuscation::PortHopping {
+            return vec![PortSelectionStep {
+                tunnel_type: TunnelType::Wireguard,
+                transport: TransportPort {
+                    protocol: TransportProtocol::Udp,
+                    port: Constraint::Any,
+                },
+            }];
+        }
+        self.auto_port_order.clone()
+    }
+
+    /// Returns whether a bridge should be used when the bridge state is auto. Bridges are used on
+    /// every attempt if TCP has been selected for OpenVPN, and otherwise only on some retries.
+    fn use_auto_bridge(&self, retry_attempt: u32) -> bool {
+        let tcp_selected = match &self.relay_settings {
+            RelaySettings::Normal(constraints) => {
+                constraints.openvpn_constraints.transport_protocol() == Some(TransportProtocol::Tcp)
+            }
+            RelaySettings::CustomTunnelEndpoint(_) => false,
+        };
+        tcp_selected || RelaySelector::should_use_bridge(retry_attempt)
+    }
 }
 
 #[derive(Clone)]
@@ -194,7 +290,7 @@ impl RelaySelector {
             });
         log::info!(
             "Initialized with {} cached relays from {}",
-            unsynchronized_parsed_relays.relays().len(),
+            unsynchronized_parsed_relays.relays().count(),
             DateTime::<Local>::from(unsynchronized_parsed_relays.last_updated())
                 .format(DATE_TIME_FORMAT_STR)
         );
@@ -207,12 +303,13 @@ impl RelaySelector {
 
     pub fn set_config(&mut self, config: SelectorConfig) {
         *self.config.lock() = config;
+        self.parsed_relays.lock().match_cache.clear();
     }
 
-    /// Returns all countries and cities. The cities in the object returned does not have any
-    /// relays in them.
-    pub fn get_locations(&mut self) -> RelayList {
-        self.parsed_relays.lock().locations().clone()
+    /// Returns all countries, cities and relays. The list is shared rather than copied, and is
+    /// not affected by later updates.
+    pub fn get_locations(&self) -> Arc<RelayList> {
+        self.parsed_relays.lock().relay_list()
     }
 
     /// Returns a random relay and relay endpoint matching the current constraints.
@@ -233,12 +330,53 @@ impl RelaySelector {
                 Ok((SelectedRelay::Custom(custom_relay.clone()), None, None))
             }
             RelaySettings::Normal(constraints) => {
-                let relay = self.get_tunnel_endpoint(
-                    constraints,
-                    config.bridge_state,
-                    retry_attempt,
-                    config.default_tunnel_type,
-                )?;
+                let required_capabilities = config.required_capabilities();
+                let port_order = config.port_order();
+                let relay = self
+                    .get_tunnel_endpoint(
+                        constraints,
+                        config.bridge_state,
+                        retry_attempt,
+                        config.default_tunnel_type,
+                        required_capabilities,
+                        &port_order,
+                    )
+                    .or_else(|error| match error {
+                        Error::NoRelay if constraints.nearby_locations => self
+                            .get_nearby_tunnel_endpoint(
+                                constraints,
+                                config.bridge_state,
+                                retry_attempt,
+                                config.default_tunnel_type,
+                                required_capabilities,
+                                &port_order,
+                            ),
+                        error => Err(error),
+                    })
+                    .map_err(|error| match error {
+                        Error::NoRelay => {
+                            let missing =
+                                self.missing_capabilities(constraints, required_capabilities);
+                            if missing.is_empty() {
+                                Error::NoRelay
+                            } else {
+                                log::warn!(
+                                    "No relays matching {} support {}",
+                                    constraints,
+                                    missing
+                                );
+                                Error::MissingCapabilities(missing)
+                            }
+                        }
+                        error => error,
+                    })?;
+                if matches!(relay.endpoint, MullvadEndpoint::Wireguard(..)) {
+                    log::debug!(
+                        "Selected relay supports: {}. Obfuscation ports: {:?}",
+                        relay.capabilities,
+                        self.parsed_relays.lock().relay_list.wireguard.udp2tcp_ports
+                    );
+                }
                 let bridge = match relay.endpoint {
                     MullvadEndpoint::OpenVpn(endpoint)
                         if endpoint.protocol == TransportProtocol::Tcp =>
@@ -278,6 +416,8 @@ impl RelaySelector {
         bridge_state: BridgeState,
         retry_attempt: u32,
         default_tunnel_type: TunnelType,
+        required_capabilities: RelayCapabilities,
+        port_order: &[PortSelectionStep],
     ) -> Result<NormalSelectedRelay, Error> {
         match relay_constraints.tunnel_protocol {
             Constraint::Only(TunnelType::OpenVpn) => self.get_openvpn_endpoint(
@@ -287,6 +427,7 @@ impl RelaySelector {
                 relay_constraints.openvpn_constraints,
                 bridge_state,
                 retry_attempt,
+                port_order,
             ),
 
             Constraint::Only(TunnelType::Wireguard) => self.get_wireguard_endpoint(
@@ -295,16 +436,271 @@ impl RelaySelector {
                 &relay_constraints.ownership,
                 &relay_constraints.wireguard_constraints,
                 retry_attempt,
+                required_capabilities,
+                port_order,
             ),
             Constraint::Any => self.get_any_tunnel_endpoint(
                 relay_constraints,
                 bridge_state,
                 retry_attempt,
                 default_tunnel_type,
+                required_capabilities,
+                port_order,
             ),
         }
     }
 
+    /// Returns a relay in one of the cities closest to the selected location, trying the nearest
+    /// city first. This is used when no relay in the location itself matches the constraints.
+    fn get_nearby_tunnel_endpoint(
+        &self,
+        relay_constraints: &RelayConstraints,
+        bridge_state: BridgeState,
+        retry_attempt: u32,
+        default_tunnel_type: TunnelType,
+        required_capabilities: RelayCapabilities,
+        port_order: &[PortSelectionStep],
+    ) -> Result<NormalSelectedRelay, Error> {
+        let location = match &relay_constraints.location {
+            Constraint::Only(location) => location,
+            Constraint::Any => return Err(Error::NoRelay),
+        };
+        for city in self.nearby_cities(location) {
+            let nearby_constraints = RelayConstraints {
+                location: Constraint::Only(city),
+                ..relay_constraints.clone()
+            };
+            if let Ok(mut relay) = self.get_tunnel_endpoint(
+                &nearby_constraints,
+                bridge_state,
+                retry_attempt,
+                default_tunnel_type,
+                required_capabilities,
+                port_order,
+            ) {
+                log::info!(
+                    "No relay in {} matches the constraints. Selected a relay in nearby location {}",
+                    location,
+                    nearby_constraints.location,
+                );
+                relay.nearby_fallback_from = Some(location.clone());
+                return Ok(relay);
+            }
+        }
+        Err(Error::NoRelay)
+    }
+
+    /// Returns the cities closest to the relays in `location`, nearest first. Cities in
+    /// `location` and cities further away than [`MAX_NEARBY_DISTANCE`] are not included.
+    fn nearby_cities(&self, location: &LocationConstraint) -> Vec<LocationConstraint> {
+        let parsed_relays = self.parsed_relays.lock();
+        let (in_location, others): (Vec<&Relay>, Vec<&Relay>) = parsed_relays
+            .relays()
+            .filter(|relay| relay.location.is_some())
+            .partition(|relay| location.matches_with_opts(relay, true));
+        let locations: Vec<&Location> = in_location
+            .iter()
+            .filter_map(|relay| relay.location.as_deref())
+            .collect();
+        if locations.is_empty() {
+            return vec![];
+        }
+        let midpoint = Coordinates::midpoint(locations);
+
+        let mut cities: Vec<(f64, &Location)> = others
+            .iter()
+            .filter_map(|relay| relay.location.as_deref())
+            .map(|city| (city.distance_from(&midpoint), city))
+            .filter(|(distance, _)| *distance <= MAX_NEARBY_DISTANCE)
+            .collect();
+        cities.sort_by_key(|(distance, _)| *distance as usize);
+        cities.dedup_by(|(_, a), (_, b)| a.has_same_city(b));
+        cities
+            .into_iter()
+            .take(MAX_NEARBY_CITIES)
+            .map(|(_, city)| {
+                LocationConstraint::City(city.country_code.clone(), city.city_code.clone())
+            })
+            .collect()
+    }
+
+    /// Returns the capabilities in `required_capabilities` that prevent every WireGuard relay
+    /// matching the location, provider, and ownership constraints from being used. With multihop,
+    /// the entry and exit relays are checked separately. Nothing is missing if OpenVPN is used,
+    /// since the capabilities only apply to WireGuard.
+    fn missing_capabilities(
+        &self,
+        relay_constraints: &RelayConstraints,
+        required_capabilities: RelayCapabilities,
+    ) -> RelayCapabilities {
+        if relay_constraints.tunnel_protocol == Constraint::Only(TunnelType::OpenVpn) {
+            return RelayCapabilities::default();
+        }
+
+        let parsed_relays = self.parsed_relays.lock();
+        let missing_in = |location: &Constraint<LocationConstraint>| {
+            let candidates = parsed_relays.relays().filter(|relay| {
+                relay.active
+                    && matches!(relay.endpoint_data, RelayEndpointData::Wireguard(_))
+                    && location.matches_with_opts(relay, true)
+                    && relay_constraints.providers.matches(relay)
+                    && relay_constraints.ownership.matches(relay)
+            });
+            Self::missing_in_candidates(candidates, required_capabilities)
+        };
+
+        let missing = missing_in(&relay_constraints.location);
+        if relay_constraints.wireguard_constraints.use_multihop {
+            missing.union(&missing_in(
+                &relay_constraints.wireguard_constraints.entry_location,
+            ))
+        } else {
+            missing
+        }
+    }
+
+    /// Returns the capabilities in `required_capabilities` that none of `candidates` support.
+    /// If every capability is supported by some candidate, but no candidate supports all of them,
+    /// they are all reported as missing, since they cannot be used together.
+    fn missing_in_candidates<'a>(
+        candidates: impl Iterator<Item = &'a Relay>,
+        required_capabilities: RelayCapabilities,
+    ) -> RelayCapabilities {
+        let mut supported = RelayCapabilities::default();
+        let mut any_candidate = false;
+        for relay in candidates {
+            let capabilities = relay.endpoint_data.capabilities();
+            if capabilities.supports(&required_capabilities) {
+                return RelayCapabilities::default();
+            }
+            supported = supported.union(&capabilities);
+            any_candidate = true;
+        }
+        if !any_candidate {
+            // No relay matches the other constraints, so the capabilities are not to blame
+            return RelayCapabilities::default();
+        }
+        let missing = supported.missing(&required_capabilities);
+        if missing.is_empty() {
+            required_capabilities
+        } else {
+            missing
+        }
+    }
+
+    /// Explains why no relay matches the current constraints. The constraints are applied to the
+    /// active relays one at a time, and each one that removes relays is listed. Returns `None` if
+    /// a custom tunnel endpoint is used.
+    pub fn explain_selection(&self) -> Option<RelaySelectionHint> {
+        let config = self.config.lock();
+        let constraints = match &config.relay_settings {
+            RelaySettings::Normal(constraints) => constraints,
+            RelaySettings::CustomTunnelEndpoint(_) => return None,
+        };
+        let required_capabilities = config.required_capabilities();
+        let parsed_relays = self.parsed_relays.lock();
+        let relay_list = &parsed_relays.relay_list;
+        let is_wireguard =
+            |relay: &Relay| matches!(relay.endpoint_data, RelayEndpointData::Wireguard(_));
+
+        let mut candidates: Vec<&Relay> = parsed_relays
+            .relays()
+            .filter(|relay| relay.active && relay.endpoint_data != RelayEndpointData::Bridge)
+            .collect();
+        let mut hint = RelaySelectionHint {
+            total: candidates.len() as u32,
+            steps: vec![],
+        };
+        let mut apply = |constraint: String, keep: &dyn Fn(&Relay) -> bool| {
+            let before = candidates.len();
+            candidates.retain(|relay| keep(relay));
+            if candidates.len() < before {
+                hint.steps.push(RelaySelectionStep {
+                    constraint,
+                    removed: (before - candidates.len()) as u32,
+                    remaining: candidates.len() as u32,
+                });
+            }
+        };
+
+        if let Constraint::Only(tunnel_type) = constraints.tunnel_protocol {
+            apply(
+                format!("tunnel protocol {}", tunnel_type),
+                &|relay| match tunnel_type {
+                    TunnelType::Wireguard => is_wireguard(relay),
+                    TunnelType::OpenVpn => relay.endpoint_data == RelayEndpointData::Openvpn,
+                },
+            );
+        }
+        if let Constraint::Only(location) = &constraints.location {
+            apply(location.to_string(), &|relay| {
+                location.matches_with_opts(relay, true)
+            });
+        }
+        if let Constraint::Only(providers) = &constraints.providers {
+            apply(providers.to_string(), &|relay| providers.matches(relay));
+        }
+        if let Constraint::Only(ownership) = constraints.ownership {
+            apply(format!("only {}", ownership), &|relay| {
+                ownership.matches(relay)
+            });
+        }
+        if !required_capabilities.is_empty() {
+            apply(
+                format!("required features ({})", required_capabilities),
+                &|relay| {
+                    !is_wireguard(relay)
+                        || relay
+                            .endpoint_data
+                            .capabilities()
+                            .supports(&required_capabilities)
+                },
+            );
+        }
+
+        let wireguard_constraints = &constraints.wireguard_constraints;
+        if let Constraint::Only(port) = wireguard_constraints.port {
+            let port_available = relay_list
+                .wireguard
+                .port_ranges
+                .iter()
+                .any(|(first, last)| (*first..=*last).contains(&port));
+            apply(format!("WireGuard port {}", port), &|relay| {
+                !is_wireguard(relay) || port_available
+            });
+        }
+        if wireguard_constraints.ip_version == Constraint::Only(IpVersion::V6) {
+            apply("WireGuard over IPv6".to_owned(), &|relay| {
+                !is_wireguard(relay) || relay.ipv6_addr_in.is_some()
+            });
+        }
+        if wireguard_constraints.use_multihop {
+            if let Constraint::Only(entry_location) = &wireguard_constraints.entry_location {
+                let entry_available = parsed_relays.relays().any(|relay| {
+                    relay.active
+                        && is_wireguard(relay)
+                        && entry_location.matches_with_opts(relay, true)
+                        && constraints.providers.matches(relay)
+                        && constraints.ownership.matches(relay)
+                });
+                apply(format!("entry {}", entry_location), &|relay| {
+                    !is_wireguard(relay) || entry_available
+                });
+            }
+        }
+
+        let openvpn_matcher =
+            OpenVpnMatcher::new(constraints.openvpn_constraints, relay_list.openvpn.clone());
+        if !openvpn_matcher.matches(&relay_list.openvpn) {
+            apply(
+                format!("OpenVPN {}", constraints.openvpn_constraints),
+                &|relay| relay.endpoint_data != RelayEndpointData::Openvpn,
+            );
+        }
+
+        Some(hint)
+    }
+
     /// Returns the average location of relays that match the given constraints.
     /// This returns none if the location is `any` or if no relays match the constraints.
     pub fn get_relay_midpoint(&self, relay_constraints: &RelayConstraints) -> Option<Coordinates> {
@@ -315,29 +711,33 @@ impl RelaySelector {
         let (openvpn_data, wireguard_data) = {
             let relays = self.parsed_relays.lock();
             (
-                relays.locations.openvpn.clone(),
-                relays.locations.wireguard.clone(),
+                relays.relay_list.openvpn.clone(),
+                relays.relay_list.wireguard.clone(),
             )
         };
 
         let matcher = RelayMatcher::new(relay_constraints.clone(), openvpn_data, wireguard_data);
 
-        let parsed_relays = self.parsed_relays.lock();
-        let mut matching_locations: Vec<Location> = matcher
-            .filter_matching_relay_list(parsed_relays.relays())
-            .into_iter()
-            .filter_map(|relay| relay.location)
+        let mut matching_locations: Vec<Arc<Location>> = self
+            .parsed_relays
+            .lock()
+            .matching_relays(&matcher)
+            .iter()
+            .filter_map(|relay| relay.location.clone())
             .collect();
         matching_locations.dedup_by(|a, b| a.has_same_city(b));
 
         if matching_locations.is_empty() {
             return None;
         }
-        Some(Coordinates::midpoint(&matching_locations))
+        Some(Coordinates::midpoint(
+            matching_locations.iter().map(|location| &**location),
+        ))
     }
 
     /// Returns an OpenVpn endpoint, should only ever be used when the user has specified the tunnel
     /// protocol as only OpenVPN.
+    #[allow(clippy::too_many_arguments)]
     fn get_openvpn_endpoint(
         &self,
         location: &Constraint<LocationConstraint>,
@@ -346,6 +746,7 @@ impl RelaySelector {
         openvpn_constraints: OpenVpnConstraints,
         bridge_state: BridgeState,
         retry_attempt: u32,
+        port_order: &[PortSelectionStep],
     ) -> Result<NormalSelectedRelay, Error> {
         let mut relay_matcher = RelayMatcher {
             location: location.clone(),
@@ -353,7 +754,7 @@ impl RelaySelector {
             ownership: *ownership,
             endpoint_matcher: OpenVpnMatcher::new(
                 openvpn_constraints,
-                self.parsed_relays.lock().locations.openvpn.clone(),
+                self.parsed_relays.lock().relay_list.openvpn.clone(),
             ),
         };
 
@@ -371,7 +772,7 @@ impl RelaySelector {
         let mut preferred_relay_matcher = relay_matcher.clone();
 
         let (preferred_port, preferred_protocol) =
-            Self::preferred_openvpn_constraints(retry_attempt);
+            Self::preferred_openvpn_constraints(port_order, retry_attempt);
         let should_try_preferred =
             match &mut preferred_relay_matcher.endpoint_matcher.constraints.port {
                 any @ Constraint::Any => {
@@ -406,7 +807,8 @@ impl RelaySelector {
     ) -> Result<NormalSelectedRelay, Error> {
         let mut exit_matcher = RelayMatcher {
             location: exit_location,
-            endpoint_matcher: self.wireguard_exit_matcher(),
+            endpoint_matcher: self
+                .wireguard_exit_matcher(entry_matcher.endpoint_matcher.capabilities),
             ..entry_matcher.clone()
         };
 
@@ -453,6 +855,7 @@ impl RelaySelector {
 
     /// Returns a WireGuard endpoint, should only ever be used when the user has specified the
     /// tunnel protocol as only WireGuard.
+    #[allow(clippy::too_many_arguments)]
     fn get_wireguard_endpoint(
         &self,
         location: &Constraint<LocationConstraint>,
@@ -460,22 +863,27 @@ impl RelaySelector {
         ownership: &Constraint<Ownership>,
         wireguard_constraints: &WireguardConstraints,
         retry_attempt: u32,
+        required_capabilities: RelayCapabilities,
+        port_order: &[PortSelectionStep],
     ) -> Result<NormalSelectedRelay, Error> {
         let mut entry_relay_matcher = RelayMatcher {
             location: location.clone(),
             providers: providers.clone(),
             ownership: *ownership,
-            endpoint_matcher: WireguardMatcher::new(
-                wireguard_constraints.clone(),
-                self.parsed_relays.lock().locations.wireguard.clone(),
-            ),
+            endpoint_matcher: WireguardMatcher {
+                capabilities: required_capabilities,
+                ..WireguardMatcher::new(
+                    wireguard_constraints.clone(),
+                    self.parsed_relays.lock().relay_list.wireguard.clone(),
+                )
+            },
         };
 
         let mut preferred_matcher: RelayMatcher<WireguardMatcher> = entry_relay_matcher.clone();
         preferred_matcher.endpoint_matcher.port = preferred_matcher
             .endpoint_matcher
             .port
-            .or(Self::preferred_wireguard_port(retry_attempt));
+            .or(Self::preferred_wireguard_port(port_order, retry_attempt));
 
         if !wireguard_constraints.use_multihop {
             return self
@@ -487,7 +895,7 @@ impl RelaySelector {
         entry_relay_matcher.endpoint_matcher.port = entry_relay_matcher
             .endpoint_matcher
             .port
-            .or(Self::preferred_wireguard_port(retry_attempt));
+            .or(Self::preferred_wireguard_port(port_order, retry_attempt));
         self.get_wireguard_multi_hop_endpoint(entry_relay_matcher, location.clone())
     }
 
@@ -495,16 +903,18 @@ impl RelaySelector {
     fn get_multihop_tunnel_endpoint_internal(
         &self,
         relay_constraints: &RelayConstraints,
+        required_capabilities: RelayCapabilities,
     ) -> Result<NormalSelectedRelay, Error> {
         let (openvpn_data, wireguard_data) = {
             let relays = self.parsed_relays.lock();
             (
-                relays.locations.openvpn.clone(),
-                relays.locations.wireguard.clone(),
+                relays.relay_list.openvpn.clone(),
+                relays.relay_list.wireguard.clone(),
             )
         };
         let mut matcher =
             RelayMatcher::new(relay_constraints.clone(), openvpn_data, wireguard_data);
+        matcher.endpoint_matcher.wireguard.capabilities = required_capabilities;
 
         let mut selected_entry_relay = None;
         let mut selected_entry_endpoint = None;
@@ -519,7 +929,7 @@ impl RelaySelector {
 
         // Pick the entry relay first if its location constraint is a subset of the exit location.
         if relay_constraints.wireguard_constraints.use_multihop {
-            matcher.endpoint_matcher.wireguard = self.wireguard_exit_matcher();
+            matcher.endpoint_matcher.wireguard = self.wireguard_exit_matcher(required_capabilities);
             if relay_constraints
                 .wireguard_constraints
                 .entry_location
@@ -568,6 +978,9 @@ impl RelaySelector {
                     );
 
                     selected_relay.endpoint = MullvadEndpoint::Wireguard(entry_endpoint);
+                    selected_relay.capabilities = selected_relay
+                        .capabilities
+                        .intersection(&entry_relay.endpoint_data.capabilities());
                     selected_relay.entry_relay = Some(entry_relay);
                 }
                 _ => return Err(Error::NoRelay),
@@ -585,21 +998,28 @@ impl RelaySelector {
         bridge_state: BridgeState,
         retry_attempt: u32,
         default_tunnel_type: TunnelType,
+        required_capabilities: RelayCapabilities,
+        port_order: &[PortSelectionStep],
     ) -> Result<NormalSelectedRelay, Error> {
         let preferred_constraints = self.preferred_constraints(
             relay_constraints,
             bridge_state,
             retry_attempt,
             default_tunnel_type,
+            port_order,
         );
 
-        if let Ok(result) = self.get_multihop_tunnel_endpoint_internal(&preferred_constraints) {
+        if let Ok(result) = self
+            .get_multihop_tunnel_endpoint_internal(&preferred_constraints, required_capabilities)
+        {
             log::debug!(
                 "Relay matched on highest preference for retry attempt {}",
                 retry_attempt
             );
             Ok(result)
-        } else if let Ok(result) = self.get_multihop_tunnel_endpoint_internal(relay_constraints) {
+        } else if let Ok(result) =
+            self.get_multihop_tunnel_endpoint_internal(relay_constraints, required_capabilities)
+        {
             log::debug!(
                 "Relay matched on second preference for retry attempt {}",
                 retry_attempt
@@ -618,6 +1038,7 @@ impl RelaySelector {
         bridge_state: BridgeState,
         retry_attempt: u32,
         default_tunnel_type: TunnelType,
+        port_order: &[PortSelectionStep],
     ) -> RelayConstraints {
         let (preferred_port, preferred_protocol, preferred_tunnel) = self
             .preferred_tunnel_constraints(
@@ -626,6 +1047,7 @@ impl RelaySelector {
                 &original_constraints.location,
                 &original_constraints.providers,
                 &original_constraints.ownership,
+                port_order,
             );
 
         let mut relay_constraints = original_constraints.clone();
@@ -671,7 +1093,7 @@ impl RelaySelector {
                     });
                 } else if openvpn_constraints.port.is_any() {
                     let (preferred_port, preferred_protocol) =
-                        Self::preferred_openvpn_constraints(retry_attempt);
+                        Self::preferred_openvpn_constraints(port_order, retry_attempt);
                     openvpn_constraints.port = Constraint::Only(TransportPort {
                         protocol: preferred_protocol,
                         port: preferred_port,
@@ -683,7 +1105,7 @@ impl RelaySelector {
                     original_constraints.wireguard_constraints.clone();
                 if relay_constraints.wireguard_constraints.port.is_any() {
                     relay_constraints.wireguard_constraints.port =
-                        Self::preferred_wireguard_port(retry_attempt);
+                        Self::preferred_wireguard_port(port_order, retry_attempt);
                 }
             }
         };
@@ -695,10 +1117,7 @@ impl RelaySelector {
         &self,
         matcher: &RelayMatcher<WireguardMatcher>,
     ) -> Result<(Relay, MullvadWireguardEndpoint), Error> {
-        let matching_relays: Vec<Relay> = matcher
-            .filter_matching_relay_list(self.parsed_relays.lock().relays())
-            .into_iter()
-            .collect();
+        let matching_relays = self.parsed_relays.lock().matching_relays(matcher);
 
         let relay = self
             .pick_random_relay(&matching_relays)
@@ -746,7 +1165,7 @@ impl RelaySelector {
                             relay,
                         })))
                     }
-                    BridgeState::Auto if Self::should_use_bridge(retry_attempt) => Ok(self
+                    BridgeState::Auto if config.use_auto_bridge(retry_attempt) => Ok(self
                         .get_proxy_settings(&bridge_constraints, Some(location))
                         .map(|(settings, relay)| {
                             SelectedBridge::Normal(NormalSelectedBridge { settings, relay })
@@ -756,7 +1175,7 @@ impl RelaySelector {
             }
             BridgeSettings::Custom(bridge_settings) => match config.bridge_state {
                 BridgeState::On => Ok(Some(SelectedBridge::Custom(bridge_settings.clone()))),
-                BridgeState::Auto if Self::should_use_bridge(retry_attempt) => {
+                BridgeState::Auto if config.use_auto_bridge(retry_attempt) => {
                     Ok(Some(SelectedBridge::Custom(bridge_settings.clone())))
                 }
                 BridgeState::Auto | BridgeState::Off => Ok(None),
@@ -813,8 +1232,7 @@ impl RelaySelector {
             ownership: constraints.ownership,
             endpoint_matcher: BridgeMatcher(()),
         };
-        let matching_relays: Vec<Relay> =
-            matcher.filter_matching_relay_list(self.parsed_relays.lock().relays());
+        let matching_relays = self.parsed_relays.lock().matching_relays(&matcher);
 
         if matching_relays.is_empty() {
             return None;
@@ -830,10 +1248,10 @@ impl RelaySelector {
             }
 
             let mut matching_relays: Vec<RelayWithDistance> = matching_relays
-                .into_iter()
+                .iter()
                 .map(|relay| RelayWithDistance {
                     distance: relay.location.as_ref().unwrap().distance_from(&location),
-                    relay,
+                    relay: relay.clone(),
                 })
                 .collect();
             matching_relays
@@ -864,8 +1282,12 @@ impl RelaySelector {
             self.pick_random_relay(&matching_relays).cloned()
         };
         relay.and_then(|relay| {
-            self.pick_random_bridge(&self.parsed_relays.lock().locations.bridge, &relay)
-                .map(|bridge| (bridge, relay.clone()))
+            self.pick_random_bridge(
+                &self.parsed_relays.lock().relay_list.bridge,
+                &relay,
+                &constraints.transport_protocol,
+            )
+            .map(|bridge| (bridge, relay.clone()))
         })
     }
 
@@ -878,6 +1300,52 @@ impl RelaySelector {
         self.get_obfuscator_inner(&self.config.lock(), relay, endpoint, retry_attempt)
     }
 
+    /// Returns a random WireGuard port other than `current_port` from the ranges advertised by
+    /// the relays. Used to move an existing tunnel to a new port when port hopping. Returns
+    /// `None` if the user has chosen a port, or uses a custom relay.
+    pub fn get_hopped_wireguard_port(&self, current_port: u16) -> Option<u16> {
+        match &self.config.lock().relay_settings {
+            RelaySettings::Normal(constraints)
+                if constraints.wireguard_constraints.port.is_any() => {}
+            _ => return None,
+        }
+
+        let port_ranges = self
+            .parsed_relays
+            .lock()
+            .relay_list
+            .wireguard
+            .port_ranges
+            .clone();
+        // The current port is left out of every range that contains it.
+        let candidates_in_range = |&(start, end): &(u16, u16)| -> u64 {
+            let ports = u64::from(end - start) + 1;
+            if (start..=end).contains(&current_port) {
+                ports - 1
+            } else {
+                ports
+            }
+        };
+        let candidate_count: u64 = port_ranges.iter().map(candidates_in_range).sum();
+        if candidate_count == 0 {
+            return None;
+        }
+
+        let mut port_index = rand::thread_rng().gen_range(0..candidate_count);
+        for range in &port_ranges {
+            let candidates = candidates_in_range(range);
+            if port_index < candidates {
+                let port = range.0 + port_index as u16;
+                if (range.0..=range.1).contains(&current_port) && port >= current_port {
+                    return Some(port + 1);
+                }
+                return Some(port);
+            }
+            port_index -= candidates;
+        }
+        None
+    }
+
     fn get_obfuscator_inner(
         &self,
         config: &MutexGuard<'_, SelectorConfig>,
@@ -891,17 +1359,22 @@ impl RelaySelector {
                 relay,
                 endpoint,
                 retry_attempt,
+                &Self::wireguard_port_order(config),
             )),
-            SelectedObfuscation::Off => Ok(None),
-            SelectedObfuscation::Udp2Tcp => Ok(Some(
-                self.get_udp2tcp_obfuscator(
-                    &config.obfuscation_settings.udp2tcp,
-                    relay,
-                    endpoint,
-                    retry_attempt,
-                )
-                .ok_or(Error::NoObfuscator)?,
-            )),
+            SelectedObfuscation::Off | SelectedObfuscation::PortHopping => Ok(None),
+            SelectedObfuscation::Udp2Tcp => {
+                let settings = &config.obfuscation_settings.udp2tcp;
+                match self.get_udp2tcp_obfuscator(settings, relay, endpoint, retry_attempt) {
+                    Some(obfuscator) => Ok(Some(obfuscator)),
+                    None => match settings.port {
+                        Constraint::Only(port) => {
+                            log::warn!("The relays do not accept udp2tcp traffic on port {}", port);
+                            Err(Error::UnsupportedObfuscationPort(port))
+                        }
+                        Constraint::Any => Err(Error::NoObfuscator),
+                    },
+                }
+            }
         }
     }
 
@@ -911,7 +1384,21 @@ impl RelaySelector {
         relay: &Relay,
         endpoint: &MullvadWireguardEndpoint,
         retry_attempt: u32,
+        port_order: &[PortSelectionStep],
     ) -> Option<SelectedObfuscator> {
+        if let Some(step) = Self::custom_port_step(port_order, retry_attempt) {
+            if !step.uses_udp2tcp() {
+                return None;
+            }
+            return self.get_udp2tcp_obfuscator(
+                &Udp2TcpObfuscationSettings {
+                    port: step.transport.port,
+                },
+                relay,
+                endpoint,
+                retry_attempt,
+            );
+        }
         if !self.should_use_auto_obfuscator(retry_attempt) {
             return None;
         }
@@ -927,6 +1414,20 @@ impl RelaySelector {
         )
     }
 
+    /// Returns the steps of the custom port order that apply to WireGuard connections. If the
+    /// tunnel protocol is unconstrained, OpenVPN steps are kept so that retry attempts map to the
+    /// same steps as when the relay was selected.
+    fn wireguard_port_order(config: &SelectorConfig) -> Vec<PortSelectionStep> {
+        match &config.relay_settings {
+            RelaySettings::Normal(constraints)
+                if constraints.tunnel_protocol == Constraint::Only(TunnelType::Wireguard) =>
+            {
+                Self::port_order_for(&config.auto_port_order, TunnelType::Wireguard)
+            }
+            _ => config.auto_port_order.clone(),
+        }
+    }
+
     fn should_use_auto_obfuscator(&self, retry_attempt: u32) -> bool {
         self.get_auto_obfuscator_retry_attempt(retry_attempt)
             .is_some()
@@ -946,7 +1447,7 @@ impl RelaySelector {
         endpoint: &MullvadWireguardEndpoint,
         retry_attempt: u32,
     ) -> Option<SelectedObfuscator> {
-        let udp2tcp_ports = &self.parsed_relays.lock().locations.wireguard.udp2tcp_ports;
+        let udp2tcp_ports = &self.parsed_relays.lock().relay_list.wireguard.udp2tcp_ports;
         let udp2tcp_endpoint = if obfuscation_settings.port.is_only() {
             udp2tcp_ports
                 .iter()
@@ -973,39 +1474,49 @@ impl RelaySelector {
         location_constraint: &Constraint<LocationConstraint>,
         providers_constraint: &Constraint<Providers>,
         ownership_constraint: &Constraint<Ownership>,
+        port_order: &[PortSelectionStep],
     ) -> (Constraint<u16>, TransportProtocol, TunnelType) {
+        // A custom order takes precedence over the default tunnel type. If the location does not
+        // support the tunnel type of the step, the caller falls back on the original constraints.
+        if let Some(step) = Self::custom_port_step(port_order, retry_attempt) {
+            let port = if step.uses_udp2tcp() {
+                Constraint::Any
+            } else {
+                step.transport.port
+            };
+            return (port, step.transport.protocol, step.tunnel_type);
+        }
+
         match default_tunnel_type {
             TunnelType::OpenVpn => {
-                let location_supports_openvpn =
-                    self.parsed_relays.lock().relays().iter().any(|relay| {
-                        relay.active
-                            && relay.endpoint_data == RelayEndpointData::Openvpn
-                            && location_constraint.matches_with_opts(relay, true)
-                            && providers_constraint.matches(relay)
-                            && ownership_constraint.matches(relay)
-                    });
+                let location_supports_openvpn = self.parsed_relays.lock().relays().any(|relay| {
+                    relay.active
+                        && relay.endpoint_data == RelayEndpointData::Openvpn
+                        && location_constraint.matches_with_opts(relay, true)
+                        && providers_constraint.matches(relay)
+                        && ownership_constraint.matches(relay)
+                });
 
                 if location_supports_openvpn {
                     let (preferred_port, preferred_protocol) =
-                        Self::preferred_openvpn_constraints(retry_attempt);
+                        Self::preferred_openvpn_constraints(port_order, retry_attempt);
                     return (preferred_port, preferred_protocol, TunnelType::OpenVpn);
                 }
             }
             TunnelType::Wireguard => {
-                let location_supports_wireguard =
-                    self.parsed_relays.lock().relays().iter().any(|relay| {
-                        relay.active
-                            && matches!(relay.endpoint_data, RelayEndpointData::Wireguard(_))
-                            && location_constraint.matches_with_opts(relay, true)
-                            && providers_constraint.matches(relay)
-                            && ownership_constraint.matches(relay)
-                    });
+                let location_supports_wireguard = self.parsed_relays.lock().relays().any(|relay| {
+                    relay.active
+                        && matches!(relay.endpoint_data, RelayEndpointData::Wireguard(_))
+                        && location_constraint.matches_with_opts(relay, true)
+                        && providers_constraint.matches(relay)
+                        && ownership_constraint.matches(relay)
+                });
 
                 // If location does not support WireGuard, defer to preferred OpenVPN tunnel
                 // constraints
                 if !location_supports_wireguard {
                     let (preferred_port, preferred_protocol) =
-                        Self::preferred_openvpn_constraints(retry_attempt);
+                        Self::preferred_openvpn_constraints(port_order, retry_attempt);
                     return (preferred_port, preferred_protocol, TunnelType::OpenVpn);
                 }
             }
@@ -1027,13 +1538,48 @@ impl RelaySelector {
             ),
             _ => {
                 let (preferred_port, preferred_protocol) =
-                    Self::preferred_openvpn_constraints(retry_attempt - 2);
+                    Self::preferred_openvpn_constraints(port_order, retry_attempt - 2);
                 (preferred_port, preferred_protocol, TunnelType::OpenVpn)
             }
         }
     }
 
-    fn preferred_wireguard_port(retry_attempt: u32) -> Constraint<u16> {
+    /// Returns the step of a custom port order to use for `retry_attempt`, cycling through the
+    /// steps. Returns `None` if `port_order` is empty.
+    fn custom_port_step(
+        port_order: &[PortSelectionStep],
+        retry_attempt: u32,
+    ) -> Option<PortSelectionStep> {
+        if port_order.is_empty() {
+            return None;
+        }
+        Some(port_order[retry_attempt as usize % port_order.len()])
+    }
+
+    fn port_order_for(
+        port_order: &[PortSelectionStep],
+        tunnel_type: TunnelType,
+    ) -> Vec<PortSelectionStep> {
+        port_order
+            .iter()
+            .filter(|step| step.tunnel_type == tunnel_type)
+            .copied()
+            .collect()
+    }
+
+    fn preferred_wireguard_port(
+        port_order: &[PortSelectionStep],
+        retry_attempt: u32,
+    ) -> Constraint<u16> {
+        let wireguard_order = Self::port_order_for(port_order, TunnelType::Wireguard);
+        if let Some(step) = Self::custom_port_step(&wireguard_order, retry_attempt) {
+            // The port is used by the obfuscator when tunneling over TCP.
+            if step.uses_udp2tcp() {
+                return Constraint::Any;
+            }
+            return step.transport.port;
+        }
+
         // This ensures that if after the first 2 failed attempts the daemon does not
         // connect, then afterwards 2 of each 4 successive attempts will try to connect
         // on port 53.
@@ -1043,7 +1589,15 @@ impl RelaySelector {
         }
     }
 
-    fn preferred_openvpn_constraints(retry_attempt: u32) -> (Constraint<u16>, TransportProtocol) {
+    fn preferred_openvpn_constraints(
+        port_order: &[PortSelectionStep],
+        retry_attempt: u32,
+    ) -> (Constraint<u16>, TransportProtocol) {
+        let openvpn_order = Self::port_order_for(port_order, TunnelType::OpenVpn);
+        if let Some(step) = Self::custom_port_step(&openvpn_order, retry_attempt) {
+            return (step.transport.port, step.transport.protocol);
+        }
+
         // Prefer UDP by default. But if that has failed a couple of times, then try TCP port
         // 443, which works for many with UDP problems. After that, just alternate
         // between protocols.
@@ -1066,10 +1620,7 @@ impl RelaySelector {
         &self,
         matcher: &RelayMatcher<T>,
     ) -> Result<NormalSelectedRelay, Error> {
-        let matching_relays: Vec<Relay> = matcher
-            .filter_matching_relay_list(self.parsed_relays.lock().relays())
-            .into_iter()
-            .collect();
+        let matching_relays = self.parsed_relays.lock().matching_relays(matcher);
 
         self.pick_random_relay(&matching_relays)
             .and_then(|selected_relay| {
@@ -1119,16 +1670,22 @@ impl RelaySelector {
         }
     }
 
-    /// Picks a random bridge from a relay.
+    /// Picks a random bridge from a relay, using an endpoint of the given protocol.
     fn pick_random_bridge(
         &self,
         data: &BridgeEndpointData,
         relay: &Relay,
+        protocol: &Constraint<TransportProtocol>,
     ) -> Option<ProxySettings> {
         if relay.endpoint_data != RelayEndpointData::Bridge {
             return None;
         }
-        data.shadowsocks
+        let endpoints: Vec<_> = data
+            .shadowsocks
+            .iter()
+            .filter(|endpoint| protocol.matches_eq(&endpoint.protocol))
+            .collect();
+        endpoints
             .choose(&mut rand::thread_rng())
             .map(|shadowsocks_endpoint| {
                 log::info!(
@@ -1153,7 +1710,7 @@ impl RelaySelector {
     ) -> Result<ParsedRelays, Error> {
         // prefer the resource path's relay list if the cached one doesn't exist or was modified
         // before the resource one was created.
-        let cached_relays = ParsedRelays::from_file(cache_path);
+        let cached_relays = ParsedRelays::from_cache_file(cache_path);
         let bundled_relays = match ParsedRelays::from_file(resource_path) {
             Ok(bundled_relays) => bundled_relays,
             Err(e) => {
@@ -1173,11 +1730,12 @@ impl RelaySelector {
         }
     }
 
-    fn wireguard_exit_matcher(&self) -> WireguardMatcher {
+    fn wireguard_exit_matcher(&self, required_capabilities: RelayCapabilities) -> WireguardMatcher {
         let mut tunnel =
-            WireguardMatcher::from_endpoint(self.parsed_relays.lock().locations.wireguard.clone());
+            WireguardMatcher::from_endpoint(self.parsed_relays.lock().relay_list.wireguard.clone());
         tunnel.ip_version = WIREGUARD_EXIT_IP_VERSION;
         tunnel.port = WIREGUARD_EXIT_PORT;
+        tunnel.capabilities = required_capabilities;
         tunnel
     }
 }
@@ -1205,6 +1763,11 @@ pub struct NormalSelectedRelay {
     pub exit_relay: Relay,
     pub endpoint: MullvadEndpoint,
     pub entry_relay: Option<Relay>,
+    /// Optional features supported by all selected relays.
+    pub capabilities: RelayCapabilities,
+    /// The selected location, if no relay in it matched the constraints and the exit relay was
+    /// picked in a nearby location instead.
+    pub nearby_fallback_from: Option<LocationConstraint>,
 }
 
 #[derive(Debug)]
@@ -1216,9 +1779,11 @@ pub struct SelectedObfuscator {
 impl NormalSelectedRelay {
     fn new(endpoint: MullvadEndpoint, exit_relay: Relay) -> Self {
         Self {
+            capabilities: exit_relay.endpoint_data.capabilities(),
             exit_relay,
             endpoint,
             entry_relay: None,
+            nearby_fallback_from: None,
         }
     }
 
@@ -1228,9 +1793,14 @@ impl NormalSelectedRelay {
         entry: Relay,
     ) -> Self {
         Self {
+            capabilities: exit_relay
+                .endpoint_data
+                .capabilities()
+                .intersection(&entry.endpoint_data.capabilities()),
             exit_relay,
             endpoint: MullvadEndpoint::Wireguard(endpoint),
             entry_relay: Some(entry),
+            nearby_fallback_from: None,
         }
     }
 }
@@ -1275,6 +1845,7 @@ mod test {
                                     weight: 1,
                                     endpoint_data: RelayEndpointData::Wireguard(WireguardRelayEndpointData {
                                         public_key: PublicKey::from_base64("BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=").unwrap(),
+                                        capabilities: RelayCapabilities::legacy(),
                                     }),
                                     location: None,
                                 },
@@ -1289,6 +1860,7 @@ mod test {
                                     weight: 1,
                                     endpoint_data: RelayEndpointData::Wireguard(WireguardRelayEndpointData {
                                         public_key: PublicKey::from_base64("BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=").unwrap(),
+                                        capabilities: RelayCapabilities::legacy(),
                                     }),
                                     location: None,
                                 },
@@ -1406,6 +1978,8 @@ mod test {
                 },
                 bridge_state: BridgeState::Auto,
                 default_tunnel_type: default_tunnel_type(),
+                quantum_resistant: false,
+                auto_port_order: Vec::new(),
             })),
         }
     }
@@ -1435,6 +2009,7 @@ mod test {
             BridgeState::Off,
             0,
             TunnelType::Wireguard,
+            &[],
         );
         assert_eq!(
             preferred.tunnel_protocol,
@@ -1448,6 +2023,8 @@ mod test {
                     BridgeState::Off,
                     attempt,
                     TunnelType::Wireguard,
+                    RelayCapabilities::default(),
+                    &[],
                 )
                 .is_ok());
         }
@@ -1469,6 +2046,7 @@ mod test {
             BridgeState::Off,
             0,
             TunnelType::Wireguard,
+            &[],
         );
         assert_eq!(
             preferred.tunnel_protocol,
@@ -1482,6 +2060,8 @@ mod test {
                     BridgeState::Off,
                     attempt,
                     TunnelType::Wireguard,
+                    RelayCapabilities::default(),
+                    &[],
                 )
                 .is_ok());
         }
@@ -1496,6 +2076,7 @@ mod test {
                     BridgeState::Off,
                     attempt,
                     TunnelType::OpenVpn,
+                    &[],
                 );
                 assert_eq!(
                     preferred.tunnel_protocol,
@@ -1506,6 +2087,8 @@ mod test {
                     BridgeState::Off,
                     attempt,
                     TunnelType::OpenVpn,
+                    RelayCapabilities::default(),
+                    &[],
                 ) {
                     Ok(result) if matches!(result.endpoint, MullvadEndpoint::OpenVpn(_)) => (),
                     _ => panic!("OpenVPN endpoint was not selected"),
@@ -1545,6 +2128,8 @@ mod test {
                 BridgeState::Off,
                 0,
                 TunnelType::Wireguard,
+                RelayCapabilities::default(),
+                &[],
             )
             .is_err());
 
@@ -1557,6 +2142,8 @@ mod test {
                 BridgeState::Off,
                 0,
                 TunnelType::Wireguard,
+                RelayCapabilities::default(),
+                &[],
             )
             .is_ok());
     }
@@ -1586,7 +2173,14 @@ mod test {
 
         // The exit must not equal the entry
         let exit_relay = relay_selector
-            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, TunnelType::OpenVpn)
+            .get_tunnel_endpoint(
+                &relay_constraints,
+                BridgeState::Off,
+                0,
+                TunnelType::OpenVpn,
+                RelayCapabilities::default(),
+                &[],
+            )
             .map_err(|error| error.to_string())?
             .exit_relay;
 
@@ -1606,6 +2200,8 @@ mod test {
                 BridgeState::Off,
                 0,
                 TunnelType::Wireguard,
+                RelayCapabilities::default(),
+                &[],
             )
             .map_err(|error| error.to_string())?;
 
@@ -1722,6 +2318,8 @@ mod test {
                     BridgeState::Auto,
                     retry_attempt,
                     default_tunnel_type(),
+                    RelayCapabilities::default(),
+                    &[],
                 );
 
                 println!("relay: {relay:?}, constraints: {relay_constraints:?}");
@@ -1770,6 +2368,7 @@ mod test {
             BridgeState::On,
             0,
             TunnelType::Wireguard,
+            &[],
         );
         assert_eq!(
             preferred.tunnel_protocol,
@@ -1800,6 +2399,7 @@ mod test {
             BridgeState::On,
             0,
             TunnelType::Wireguard,
+            &[],
         );
         assert_eq!(
             preferred.tunnel_protocol,
@@ -1823,6 +2423,7 @@ mod test {
                 BridgeState::On,
                 0,
                 TunnelType::Wireguard,
+                &[],
             );
             assert_eq!(
                 preferred.tunnel_protocol,
@@ -1834,6 +2435,7 @@ mod test {
             BridgeState::On,
             2,
             TunnelType::Wireguard,
+            &[],
         );
         assert_eq!(
             preferred.tunnel_protocol,
@@ -1865,7 +2467,7 @@ mod test {
 
         let relay_selector = new_relay_selector();
 
-        let result = relay_selector.get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, default_tunnel_type())
+        let result = relay_selector.get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, default_tunnel_type(), RelayCapabilities::default(), &[])
             .expect("Failed to get relay when tunnel constraints are set to Any and retrying the selection");
         // Windows will ignore WireGuard until WireGuard is supported well enough
         // TODO: Remove this caveat once Windows defaults to using WireGuard
@@ -1895,6 +2497,7 @@ mod test {
         openvpn_constraints: OpenVpnConstraints {
             port: Constraint::Any,
         },
+        nearby_locations: false,
     };
 
     const WIREGUARD_SINGLEHOP_CONSTRAINTS: RelayConstraints = RelayConstraints {
@@ -1911,13 +2514,14 @@ mod test {
         openvpn_constraints: OpenVpnConstraints {
             port: Constraint::Any,
         },
+        nearby_locations: false,
     };
 
     #[test]
     fn test_selecting_wireguard_location_will_consider_multihop() {
         let relay_selector = new_relay_selector();
 
-        let result = relay_selector.get_tunnel_endpoint(&WIREGUARD_MULTIHOP_CONSTRAINTS, BridgeState::Off, 0, default_tunnel_type())
+        let result = relay_selector.get_tunnel_endpoint(&WIREGUARD_MULTIHOP_CONSTRAINTS, BridgeState::Off, 0, default_tunnel_type(), RelayCapabilities::default(), &[])
             .expect("Failed to get relay when tunnel constraints are set to default WireGuard multihop constraints");
 
         assert!(result.entry_relay.is_some());
@@ -1928,7 +2532,7 @@ mod test {
     fn test_selecting_wg_endpoint_with_udp2tcp_obfuscation() {
         let relay_selector = new_relay_selector();
 
-        let result = relay_selector.get_tunnel_endpoint(&WIREGUARD_SINGLEHOP_CONSTRAINTS, BridgeState::Off, 0, default_tunnel_type())
+        let result = relay_selector.get_tunnel_endpoint(&WIREGUARD_SINGLEHOP_CONSTRAINTS, BridgeState::Off, 0, default_tunnel_type(), RelayCapabilities::default(), &[])
             .expect("Failed to get relay when tunnel constraints are set to default WireGuard constraints");
 
         assert!(result.entry_relay.is_none());
@@ -1957,7 +2561,7 @@ mod test {
     fn test_selecting_wg_endpoint_with_auto_obfuscation() {
         let relay_selector = new_relay_selector();
 
-        let result = relay_selector.get_tunnel_endpoint(&WIREGUARD_SINGLEHOP_CONSTRAINTS, BridgeState::Off, 0, default_tunnel_type())
+        let result = relay_selector.get_tunnel_endpoint(&WIREGUARD_SINGLEHOP_CONSTRAINTS, BridgeState::Off, 0, default_tunnel_type(), RelayCapabilities::default(), &[])
             .expect("Failed to get relay when tunnel constraints are set to default WireGuard constraints");
 
         assert!(result.entry_relay.is_none());
@@ -2002,6 +2606,8 @@ mod test {
                     BridgeState::Off,
                     attempt,
                     TunnelType::Wireguard,
+                    RelayCapabilities::default(),
+                    &[],
                 )
                 .expect("Failed to select a WireGuard relay");
             assert!(result.entry_relay.is_none());
@@ -2031,6 +2637,57 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_port_hopping() {
+        let relay_selector = new_relay_selector();
+        {
+            let mut config = relay_selector.config.lock();
+            config.relay_settings = RelaySettings::Normal(WIREGUARD_SINGLEHOP_CONSTRAINTS);
+            config.obfuscation_settings = ObfuscationSettings {
+                selected_obfuscation: SelectedObfuscation::PortHopping,
+                ..ObfuscationSettings::default()
+            };
+        }
+
+        let port_ranges = [(53, 53), (4000, 33433), (33565, 51820), (52000, 60000)];
+        let in_port_ranges = |port: u16| {
+            port_ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&port))
+        };
+
+        for attempt in 0..100 {
+            let (relay, _bridge, obfuscator) = relay_selector
+                .get_relay(attempt)
+                .expect("Failed to select a WireGuard relay");
+            assert!(obfuscator.is_none());
+            let port = match relay {
+                SelectedRelay::Normal(relay) => {
+                    relay.endpoint.unwrap_wireguard().peer.endpoint.port()
+                }
+                SelectedRelay::Custom(_) => unreachable!("Not a custom relay"),
+            };
+            assert!(in_port_ranges(port));
+
+            let hopped_port = relay_selector
+                .get_hopped_wireguard_port(port)
+                .expect("Failed to select a new port");
+            assert_ne!(hopped_port, port);
+            assert!(in_port_ranges(hopped_port));
+        }
+        assert_ne!(relay_selector.get_hopped_wireguard_port(4000), Some(4000));
+        assert_ne!(relay_selector.get_hopped_wireguard_port(60000), Some(60000));
+
+        // A port chosen by the user is kept
+        {
+            let mut config = relay_selector.config.lock();
+            let mut constraints = WIREGUARD_SINGLEHOP_CONSTRAINTS;
+            constraints.wireguard_constraints.port = Constraint::Only(51820);
+            config.relay_settings = RelaySettings::Normal(constraints);
+        }
+        assert_eq!(relay_selector.get_hopped_wireguard_port(51820), None);
+    }
+
     #[test]
     fn test_ownership() {
         let relay_selector = new_relay_selector();
@@ -2038,7 +2695,14 @@ mod test {
         for i in 0..10 {
             constraints.ownership = Constraint::Only(Ownership::MullvadOwned);
             let relay = relay_selector
-                .get_tunnel_endpoint(&constraints, BridgeState::Auto, i, TunnelType::Wireguard)
+                .get_tunnel_endpoint(
+                    &constraints,
+                    BridgeState::Auto,
+                    i,
+                    TunnelType::Wireguard,
+                    RelayCapabilities::default(),
+                    &[],
+                )
                 .unwrap();
             assert!(matches!(
                 relay,
@@ -2050,7 +2714,14 @@ mod test {
 
             constraints.ownership = Constraint::Only(Ownership::Rented);
             let relay = relay_selector
-                .get_tunnel_endpoint(&constraints, BridgeState::Auto, i, TunnelType::Wireguard)
+                .get_tunnel_endpoint(
+                    &constraints,
+                    BridgeState::Auto,
+                    i,
+                    TunnelType::Wireguard,
+                    RelayCapabilities::default(),
+                    &[],
+                )
                 .unwrap();
             assert!(matches!(
                 relay,
@@ -2123,7 +2794,14 @@ mod test {
                 Providers::new(EXPECTED_PROVIDERS.into_iter().map(|p| p.to_owned())).unwrap(),
             );
             let relay = relay_selector
-                .get_tunnel_endpoint(&constraints, BridgeState::Auto, i, TunnelType::Wireguard)
+                .get_tunnel_endpoint(
+                    &constraints,
+                    BridgeState::Auto,
+                    i,
+                    TunnelType::Wireguard,
+                    RelayCapabilities::default(),
+                    &[],
+                )
                 .unwrap();
             assert!(
                 EXPECTED_PROVIDERS.contains(&relay.exit_relay.provider.as_str()),
@@ -2167,6 +2845,28 @@ mod test {
             let (_relay, bridge, _obfs) = relay_selector.get_relay(i).unwrap();
             assert!(bridge.is_none());
         }
+
+        // Verify that bridges are always used when TCP is selected for OpenVPN
+        {
+            let mut config = relay_selector.config.lock();
+            config.relay_settings =
+                config
+                    .relay_settings
+                    .merge(RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+                        tunnel_protocol: Some(Constraint::Only(TunnelType::OpenVpn)),
+                        openvpn_constraints: Some(OpenVpnConstraints {
+                            port: Constraint::Only(TransportPort {
+                                protocol: TransportProtocol::Tcp,
+                                port: Constraint::Any,
+                            }),
+                        }),
+                        ..Default::default()
+                    }));
+        }
+        for i in 0..5 {
+            let (_relay, bridge, _obfs) = relay_selector.get_relay(i).unwrap();
+            assert!(bridge.is_some());
+        }
     }
 
     /// Ensure that `include_in_country` is ignored if all relays have it set to false (i.e., some
@@ -2200,6 +2900,7 @@ mod test {
                                         "BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=",
                                     )
                                     .unwrap(),
+                                    capabilities: RelayCapabilities::legacy(),
                                 },
                             ),
                             location: None,
@@ -2219,6 +2920,7 @@ mod test {
                                         "BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=",
                                     )
                                     .unwrap(),
+                                    capabilities: RelayCapabilities::legacy(),
                                 },
                             ),
                             location: None,
@@ -2279,4 +2981,372 @@ mod test {
             }) if hostname == expected_relay.hostname
         ))
     }
+
+    /// If no relay in the selected location matches the constraints, a relay in a nearby city
+    /// must only be selected when `nearby_locations` is enabled.
+    #[test]
+    fn test_nearby_locations() {
+        let relay = |hostname: &str, provider: &str| Relay {
+            hostname: hostname.to_string(),
+            ipv4_addr_in: "185.213.154.68".parse().unwrap(),
+            ipv6_addr_in: None,
+            include_in_country: true,
+            active: true,
+            owned: true,
+            provider: provider.to_string(),
+            weight: 1,
+            endpoint_data: RelayEndpointData::Wireguard(WireguardRelayEndpointData {
+                public_key: PublicKey::from_base64("BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=")
+                    .unwrap(),
+                capabilities: RelayCapabilities::legacy(),
+            }),
+            location: None,
+        };
+        let city = |name: &str, code: &str, latitude, longitude, relays| RelayListCity {
+            name: name.to_string(),
+            code: code.to_string(),
+            latitude,
+            longitude,
+            relays,
+        };
+        let country = |name: &str, code: &str, cities| RelayListCountry {
+            name: name.to_string(),
+            code: code.to_string(),
+            cities,
+        };
+
+        let mut relay_list = RELAYS.clone();
+        relay_list.countries = vec![
+            country(
+                "Sweden",
+                "se",
+                vec![city(
+                    "Gothenburg",
+                    "got",
+                    57.70887,
+                    11.97456,
+                    vec![relay("se-got-wg-001", "provider0")],
+                )],
+            ),
+            country(
+                "USA",
+                "us",
+                vec![city(
+                    "New York",
+                    "nyc",
+                    40.73061,
+                    -73.935242,
+                    vec![relay("us-nyc-wg-001", "provider1")],
+                )],
+            ),
+            country(
+                "Norway",
+                "no",
+                vec![city(
+                    "Oslo",
+                    "osl",
+                    59.91273,
+                    10.74609,
+                    vec![relay("no-osl-wg-001", "provider1")],
+                )],
+            ),
+        ];
+
+        let relay_selector = new_relay_selector_with_relays(relay_list);
+        let mut constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".to_owned())),
+            providers: Constraint::Only(
+                Providers::new(std::iter::once("provider1".to_owned())).unwrap(),
+            ),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..Default::default()
+        };
+
+        relay_selector.config.lock().relay_settings = RelaySettings::Normal(constraints.clone());
+        assert!(relay_selector.get_relay(0).is_err());
+
+        constraints.nearby_locations = true;
+        relay_selector.config.lock().relay_settings = RelaySettings::Normal(constraints);
+        let (relay, ..) = relay_selector
+            .get_relay(0)
+            .expect("expected relay in nearby location");
+
+        assert!(matches!(
+            relay,
+            SelectedRelay::Normal(NormalSelectedRelay {
+                exit_relay: Relay {
+                    hostname,
+                    ..
+                },
+                nearby_fallback_from: Some(_),
+                ..
+            }) if hostname == "no-osl-wg-001"
+        ))
+    }
+
+    /// Ensure that only relays supporting the features required by the settings are selected,
+    /// and that a specific error is returned if no such relay exists.
+    #[test]
+    fn test_required_capabilities() {
+        let mut relay_list = RELAYS.clone();
+        let relays = &mut relay_list.countries[0].cities[0].relays;
+        let pq_relay = relays[1].hostname.clone();
+        if let RelayEndpointData::Wireguard(data) = &mut relays[0].endpoint_data {
+            data.capabilities.quantum_resistant = false;
+        }
+
+        let relay_selector = new_relay_selector_with_relays(relay_list.clone());
+        {
+            let mut config = relay_selector.config.lock();
+            config.quantum_resistant = true;
+            config.relay_settings = RelaySettings::Normal(RelayConstraints {
+                tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+                ..Default::default()
+            });
+        }
+
+        for attempt in 0..10 {
+            let (relay, ..) = relay_selector.get_relay(attempt).expect("expected match");
+            match relay {
+                SelectedRelay::Normal(relay) => {
+                    assert_eq!(relay.exit_relay.hostname, pq_relay);
+                    assert!(relay.capabilities.quantum_resistant);
+                }
+                SelectedRelay::Custom(_) => panic!("expected a normal relay"),
+            }
+        }
+
+        let relays = &mut relay_list.countries[0].cities[0].relays;
+        if let RelayEndpointData::Wireguard(data) = &mut relays[1].endpoint_data {
+            data.capabilities.quantum_resistant = false;
+        }
+        *relay_selector.parsed_relays.lock() =
+            ParsedRelays::from_relay_list(relay_list, SystemTime::now());
+
+        assert!(matches!(
+            relay_selector.get_relay(0),
+            Err(Error::MissingCapabilities(RelayCapabilities {
+                quantum_resistant: true,
+                ..
+            }))
+        ));
+    }
+
+    /// Ensure that capabilities that are each supported by some relay, but never together, are
+    /// all reported as missing, and that nothing is reported as missing for OpenVPN.
+    #[test]
+    fn test_missing_capabilities() {
+        let mut relay_list = RELAYS.clone();
+        let relays = &mut relay_list.countries[0].cities[0].relays;
+        if let RelayEndpointData::Wireguard(data) = &mut relays[0].endpoint_data {
+            data.capabilities.udp2tcp = false;
+        }
+        if let RelayEndpointData::Wireguard(data) = &mut relays[1].endpoint_data {
+            data.capabilities.quantum_resistant = false;
+        }
+
+        let relay_selector = new_relay_selector_with_relays(relay_list);
+        let required = RelayCapabilities {
+            quantum_resistant: true,
+            udp2tcp: true,
+            ..Default::default()
+        };
+        let mut constraints = RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..Default::default()
+        };
+        assert_eq!(
+            relay_selector.missing_capabilities(&constraints, required),
+            required
+        );
+
+        constraints.tunnel_protocol = Constraint::Only(TunnelType::OpenVpn);
+        assert!(relay_selector
+            .missing_capabilities(&constraints, required)
+            .is_empty());
+    }
+
+    /// Ensure that a udp2tcp port that the relays do not accept traffic on is reported as such.
+    #[test]
+    fn test_unsupported_obfuscation_port() {
+        let relay_selector = new_relay_selector();
+        {
+            let mut config = relay_selector.config.lock();
+            config.relay_settings = RelaySettings::Normal(RelayConstraints {
+                tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+                ..Default::default()
+            });
+            config.obfuscation_settings = ObfuscationSettings {
+                selected_obfuscation: SelectedObfuscation::Udp2Tcp,
+                udp2tcp: Udp2TcpObfuscationSettings {
+                    port: Constraint::Only(1),
+                },
+                ..ObfuscationSettings::default()
+            };
+        }
+
+        assert!(matches!(
+            relay_selector.get_relay(0),
+            Err(Error::UnsupportedObfuscationPort(1))
+        ));
+    }
+
+    #[test]
+    fn test_explain_selection() {
+        let relay_selector = new_relay_selector();
+        relay_selector.config.lock().relay_settings = RelaySettings::Normal(RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ownership: Constraint::Only(Ownership::Rented),
+            wireguard_constraints: WireguardConstraints {
+                port: Constraint::Only(70),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert!(matches!(relay_selector.get_relay(0), Err(Error::NoRelay)));
+
+        let hint = relay_selector.explain_selection().unwrap();
+        assert_eq!(hint.total, 4);
+        assert_eq!(
+            hint.steps
+                .iter()
+                .map(|step| (step.removed, step.remaining))
+                .collect::<Vec<_>>(),
+            vec![(2, 2), (1, 1), (1, 0)],
+        );
+        assert_eq!(
+            hint.to_string(),
+            "tunnel protocol WireGuard removed 2 relays, only rented servers removed 1 relay, \
+             WireGuard port 70 removed the remaining 1"
+        );
+    }
+
+    #[test]
+    fn test_custom_port_order() {
+        let relay_selector = new_relay_selector();
+        let port_order = [
+            PortSelectionStep {
+                tunnel_type: TunnelType::Wireguard,
+                transport: TransportPort {
+                    protocol: TransportProtocol::Udp,
+                    port: Constraint::Only(51820),
+                },
+            },
+            PortSelectionStep {
+                tunnel_type: TunnelType::Wireguard,
+                transport: TransportPort {
+                    protocol: TransportProtocol::Tcp,
+                    port: Constraint::Only(443),
+                },
+            },
+            PortSelectionStep {
+                tunnel_type: TunnelType::OpenVpn,
+                transport: TransportPort {
+                    protocol: TransportProtocol::Tcp,
+                    port: Constraint::Only(443),
+                },
+            },
+        ];
+
+        let result = relay_selector
+            .get_tunnel_endpoint(
+                &WIREGUARD_SINGLEHOP_CONSTRAINTS,
+                BridgeState::Off,
+                0,
+                default_tunnel_type(),
+                RelayCapabilities::default(),
+                &port_order,
+            )
+            .expect("expected a WireGuard relay");
+        assert_eq!(result.endpoint.to_endpoint().address.port(), 51820);
+
+        {
+            let mut config = relay_selector.config.lock();
+            config.relay_settings = RelaySettings::Normal(WIREGUARD_SINGLEHOP_CONSTRAINTS);
+            config.obfuscation_settings = ObfuscationSettings {
+                selected_obfuscation: SelectedObfuscation::Auto,
+                ..ObfuscationSettings::default()
+            };
+            config.auto_port_order = port_order.to_vec();
+        }
+
+        let endpoint = result.endpoint.unwrap_wireguard();
+        assert!(relay_selector
+            .get_obfuscator(&result.exit_relay, endpoint, 0)
+            .unwrap()
+            .is_none());
+        let obfuscator = relay_selector
+            .get_obfuscator(&result.exit_relay, endpoint, 1)
+            .unwrap()
+            .expect("expected udp2tcp on the second step");
+        assert!(matches!(
+            obfuscator.config,
+            ObfuscatorConfig::Udp2Tcp { endpoint } if endpoint.port() == 443
+        ));
+
+        // OpenVPN steps are only used when the tunnel protocol is unconstrained.
+        let preferred = relay_selector.preferred_constraints(
+            &RelayConstraints::default(),
+            BridgeState::Off,
+            2,
+            default_tunnel_type(),
+            &port_order,
+        );
+        assert_eq!(
+            preferred.tunnel_protocol,
+            Constraint::Only(TunnelType::OpenVpn)
+        );
+        assert_eq!(
+            preferred.openvpn_constraints.port,
+            Constraint::Only(TransportPort {
+                protocol: TransportProtocol::Tcp,
+                port: Constraint::Only(443),
+            })
+        );
+    }
+
+    #[test]
+    fn test_match_cache() {
+        let mut relay_selector = new_relay_selector();
+
+        relay_selector.get_relay(0).expect("expected match");
+        let cached_entries = relay_selector.parsed_relays.lock().match_cache.len();
+        assert!(cached_entries > 0);
+
+        relay_selector.get_relay(0).expect("expected match");
+        assert_eq!(
+            relay_selector.parsed_relays.lock().match_cache.len(),
+            cached_entries,
+            "the same constraints should reuse cached relays"
+        );
+
+        let config = relay_selector.config.lock().clone();
+        relay_selector.set_config(config);
+        assert_eq!(relay_selector.parsed_relays.lock().match_cache.len(), 0);
+    }
+
+    #[test]
+    fn test_relays_in_city_share_location() {
+        let parsed_relays = ParsedRelays::from_relay_list(RELAYS.clone(), time::UNIX_EPOCH);
+        for city in parsed_relays
+            .relay_list
+            .countries
+            .iter()
+            .flat_map(|country| &country.cities)
+        {
+            let locations: Vec<_> = city
+                .relays
+                .iter()
+                .map(|relay| relay.location.as_ref().expect("relay has no location"))
+                .collect();
+            for location in &locations {
+                assert_eq!(location.city_code, city.code);
+                assert!(Arc::ptr_eq(location, locations[0]));
+            }
+        }
+        assert!(Arc::ptr_eq(
+            &parsed_relays.relay_list(),
+            &parsed_relays.relay_list()
+        ));
+    }
 }
@@ -0,0 +1,44 @@
+use mullvad_types::relay_list::Relay;
+use std::{collections::HashMap, sync::Arc};
+
+/// Maximum number of constraint sets to remember matching relays for. A connection attempt only
+/// uses a handful, so this is only reached if the constraints keep changing.
+const MAX_ENTRIES: usize = 64;
+
+/// Remembers which relays match a set of constraints, so that retries and repeated queries do not
+/// have to filter the whole relay list again. Entries are keyed by a hash of the constraints, as
+/// computed by [`crate::matcher::RelayMatcher::cache_key`].
+///
+/// A cache belongs to a single relay list, and must be cleared if the relays change.
+#[derive(Default)]
+pub struct MatchCache {
+    entries: HashMap<u64, Arc<Vec<Relay>>>,
+}
+
+impl MatchCache {
+    /// Returns the relays stored for `key`, or filters them using `filter` if there are none.
+    pub fn get_or_insert_with(
+        &mut self,
+        key: u64,
+        filter: impl FnOnce() -> Vec<Relay>,
+    ) -> Arc<Vec<Relay>> {
+        if let Some(relays) = self.entries.get(&key) {
+            return relays.clone();
+        }
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.clear();
+        }
+        let relays = Arc::new(filter());
+        self.entries.insert(key, relays.clone());
+        relays
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
@@ -5,14 +5,19 @@ use mullvad_types::{
         RelayConstraints, WireguardConstraints,
     },
     relay_list::{
-        OpenVpnEndpoint, OpenVpnEndpointData, Relay, RelayEndpointData, WireguardEndpointData,
+        OpenVpnEndpoint, OpenVpnEndpointData, Relay, RelayCapabilities, RelayEndpointData,
+        WireguardEndpointData,
     },
 };
 use rand::{
     seq::{IteratorRandom, SliceRandom},
     Rng,
 };
-use std::net::{IpAddr, SocketAddr};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    net::{IpAddr, SocketAddr},
+};
 use talpid_types::net::{all_of_the_internet, wireguard, Endpoint, IpVersion, TunnelType};
 
 #[derive(Clone)]
@@ -60,10 +65,11 @@ impl RelayMatcher<WireguardMatcher> {
 impl<T: EndpointMatcher> RelayMatcher<T> {
     /// Filter a list of relays and their endpoints based on constraints.
     /// Only relays with (and including) matching endpoints are returned.
-    pub fn filter_matching_relay_list(&self, relays: &[Relay]) -> Vec<Relay> {
-        let matches = relays
-            .iter()
-            .filter(|relay| self.pre_filter_matching_relay(relay));
+    pub fn filter_matching_relay_list<'a>(
+        &self,
+        relays: impl Iterator<Item = &'a Relay> + Clone,
+    ) -> Vec<Relay> {
+        let matches = relays.filter(|relay| self.pre_filter_matching_relay(relay));
 
         let ignore_include_in_country = !matches.clone().any(|relay| relay.include_in_country);
 
@@ -91,6 +97,18 @@ impl<T: EndpointMatcher> RelayMatcher<T> {
     pub fn mullvad_endpoint(&self, relay: &Relay) -> Option<MullvadEndpoint> {
         self.endpoint_matcher.mullvad_endpoint(relay)
     }
+
+    /// Returns a hash of everything that affects which relays
+    /// [`Self::filter_matching_relay_list`] returns for a given relay list.
+    pub fn cache_key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        std::any::type_name::<T>().hash(&mut hasher);
+        self.location.hash(&mut hasher);
+        self.providers.hash(&mut hasher);
+        self.ownership.hash(&mut hasher);
+        self.endpoint_matcher.hash_filter(&mut hasher);
+        hasher.finish()
+    }
 }
 
 /// EndpointMatcher allows to abstract over different tunnel-specific or bridge constraints.
@@ -102,6 +120,9 @@ pub trait EndpointMatcher: Clone {
     /// Constructs a MullvadEndpoint for a given Relay using extra data from the relay matcher
     /// itself.
     fn mullvad_endpoint(&self, relay: &Relay) -> Option<MullvadEndpoint>;
+    /// Feeds everything that [`Self::is_matching_relay`] depends on into `state`, except for data
+    /// that comes from the relay list.
+    fn hash_filter<H: Hasher>(&self, state: &mut H);
 }
 
 impl EndpointMatcher for OpenVpnMatcher {
@@ -122,6 +143,10 @@ impl EndpointMatcher for OpenVpnMatcher {
             ))
         })
     }
+
+    fn hash_filter<H: Hasher>(&self, state: &mut H) {
+        self.constraints.hash(state);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -203,6 +228,12 @@ impl EndpointMatcher for AnyTunnelMatcher {
         #[cfg(target_os = "android")]
         self.wireguard.mullvad_endpoint(relay)
     }
+
+    fn hash_filter<H: Hasher>(&self, state: &mut H) {
+        self.tunnel_type.hash(state);
+        self.wireguard.hash_filter(state);
+        self.openvpn.hash_filter(state);
+    }
 }
 
 #[derive(Default, Clone)]
@@ -212,6 +243,8 @@ pub struct WireguardMatcher {
     pub peer: Option<Relay>,
     pub port: Constraint<u16>,
     pub ip_version: Constraint<IpVersion>,
+    /// Features that the relay must support.
+    pub capabilities: RelayCapabilities,
 
     pub data: WireguardEndpointData,
 }
@@ -222,6 +255,7 @@ impl WireguardMatcher {
             peer: None,
             port: constraints.port,
             ip_version: constraints.ip_version,
+            capabilities: RelayCapabilities::default(),
             data,
         }
     }
@@ -311,6 +345,10 @@ impl EndpointMatcher for WireguardMatcher {
             .map(|peer_relay| peer_relay.hostname == relay.hostname)
             .unwrap_or(false)
             && matches!(relay.endpoint_data, RelayEndpointData::Wireguard(..))
+            && relay
+                .endpoint_data
+                .capabilities()
+                .supports(&self.capabilities)
     }
 
     fn mullvad_endpoint(&self, relay: &Relay) -> Option<MullvadEndpoint> {
@@ -319,6 +357,11 @@ impl EndpointMatcher for WireguardMatcher {
         }
         self.wg_data_to_endpoint(relay, &self.data)
     }
+
+    fn hash_filter<H: Hasher>(&self, state: &mut H) {
+        self.peer.as_ref().map(|peer| &peer.hostname).hash(state);
+        self.capabilities.hash(state);
+    }
 }
 
 #[derive(Clone)]
@@ -332,4 +375,6 @@ impl EndpointMatcher for BridgeMatcher {
     fn mullvad_endpoint(&self, _relay: &Relay) -> Option<MullvadEndpoint> {
         None
     }
+
+    fn hash_filter<H: Hasher>(&self, _state: &mut H) {}
 }
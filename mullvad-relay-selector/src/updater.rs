@@ -1,10 +1,13 @@
 use super::{Error, ParsedRelays};
 use futures::{
     channel::mpsc,
-    future::{Fuse, FusedFuture},
+    future::{Either, Fuse, FusedFuture},
     Future, FutureExt, SinkExt, StreamExt,
 };
-use mullvad_api::{availability::ApiAvailabilityHandle, rest::MullvadRestHandle, RelayListProxy};
+use mullvad_api::{
+    availability::ApiAvailabilityHandle, cache_integrity, rest::MullvadRestHandle, RelayListKeys,
+    RelayListProxy,
+};
 use mullvad_types::relay_list::RelayList;
 use parking_lot::Mutex;
 use std::{
@@ -14,7 +17,6 @@ use std::{
 };
 use talpid_core::future_retry::{retry_future, ExponentialBackoff, Jittered};
 use talpid_types::ErrorExt;
-use tokio::fs::File;
 
 /// How often the updater should wake up to check the cache of the in-memory cache of relays.
 /// This check is very cheap. The only reason to not have it very often is because if downloading
@@ -51,7 +53,7 @@ pub struct RelayListUpdater {
     api_client: RelayListProxy,
     cache_path: PathBuf,
     parsed_relays: Arc<Mutex<ParsedRelays>>,
-    on_update: Box<dyn Fn(&RelayList) + Send + 'static>,
+    on_update: Box<dyn Fn(Arc<RelayList>) + Send + 'static>,
     last_check: SystemTime,
     api_availability: ApiAvailabilityHandle,
 }
@@ -60,12 +62,13 @@ impl RelayListUpdater {
     pub fn spawn(
         selector: super::RelaySelector,
         api_handle: MullvadRestHandle,
+        relay_list_keys: RelayListKeys,
         cache_dir: &Path,
-        on_update: impl Fn(&RelayList) + Send + 'static,
+        on_update: impl Fn(Arc<RelayList>) + Send + 'static,
     ) -> RelayListUpdaterHandle {
         let (tx, cmd_rx) = mpsc::channel(1);
         let api_availability = api_handle.availability.clone();
-        let api_client = RelayListProxy::new(api_handle);
+        let api_client = RelayListProxy::new(api_handle, relay_list_keys);
         let updater = RelayListUpdater {
             api_client,
             cache_path: cache_dir.join(super::RELAYS_FILENAME),
@@ -90,7 +93,7 @@ impl RelayListUpdater {
                 _check_update = next_check => {
                     if download_future.is_terminated() && self.should_update() {
                         let tag = self.parsed_relays.lock().tag().map(|tag| tag.to_string());
-                        download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.api_client.clone(), tag).fuse());
+                        download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.api_client.clone(), tag, true).fuse());
                         self.last_check = SystemTime::now();
                     }
                 },
@@ -103,7 +106,7 @@ impl RelayListUpdater {
                     match cmd {
                         Some(()) => {
                             let tag = self.parsed_relays.lock().tag().map(|tag| tag.to_string());
-                            download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.api_client.clone(), tag).fuse());
+                            download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.api_client.clone(), tag, false).fuse());
                             self.last_check = SystemTime::now();
                         },
                         None => {
@@ -151,9 +154,15 @@ impl RelayListUpdater {
         api_handle: ApiAvailabilityHandle,
         proxy: RelayListProxy,
         tag: Option<String>,
+        deferrable: bool,
     ) -> impl Future<Output = Result<Option<RelayList>, mullvad_api::Error>> + 'static {
         let download_futures = move || {
-            let available = api_handle.wait_background();
+            // Scheduled updates may be held back on some networks, but requested ones may not
+            let available = if deferrable {
+                Either::Left(api_handle.wait_deferrable())
+            } else {
+                Either::Right(api_handle.wait_background())
+            };
             let req = proxy.relay_list(tag.clone());
             async move {
                 available.await?;
@@ -183,26 +192,24 @@ impl RelayListUpdater {
         let new_parsed_relays = ParsedRelays::from_relay_list(new_relay_list, SystemTime::now());
         log::info!(
             "Downloaded relay inventory has {} relays",
-            new_parsed_relays.relays().len()
+            new_parsed_relays.relays().count()
         );
 
-        let mut parsed_relays = self.parsed_relays.lock();
-        *parsed_relays = new_parsed_relays;
-        (self.on_update)(parsed_relays.locations());
+        let relay_list = {
+            let mut parsed_relays = self.parsed_relays.lock();
+            *parsed_relays = new_parsed_relays;
+            parsed_relays.relay_list()
+        };
+        (self.on_update)(relay_list);
         Ok(())
     }
 
     /// Write a `RelayList` to the cache file.
     async fn cache_relays(cache_path: &Path, relays: &RelayList) -> Result<(), Error> {
         log::debug!("Writing relays cache to {}", cache_path.display());
-        let mut file = File::create(cache_path)
-            .await
-            .map_err(Error::OpenRelayCache)?;
         let bytes = serde_json::to_vec_pretty(relays).map_err(Error::Serialize)?;
-        let mut slice: &[u8] = bytes.as_slice();
-        let _ = tokio::io::copy(&mut slice, &mut file)
+        cache_integrity::write(cache_path, &bytes)
             .await
-            .map_err(Error::WriteRelayCache)?;
-        Ok(())
+            .map_err(Error::WriteRelayCache)
     }
 }
@@ -169,7 +169,7 @@ async fn remove_device() -> Result<(), Error> {
                 .mullvad_rest_handle(
                     ApiConnectionMode::try_from_cache(&cache_path)
                         .await
-                        .into_repeat(),
+                        .into_repeat_with_direct(),
                     |_| async { true },
                 )
                 .await,
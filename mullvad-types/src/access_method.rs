@@ -0,0 +1,292 @@
+//! Proxies that the user has added for reaching the API, in addition to the built-in ways of
+//! reaching it.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use talpid_types::net::{
+    obfs4::Obfs4Settings,
+    openvpn::{RemoteProxySettings, ShadowsocksProxySettings, SHADOWSOCKS_CIPHERS},
+    TransportProtocol,
+};
+
+/// Maximum length of the name of an access method, in bytes.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// A user-defined way of reaching the API.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AccessMethod {
+    /// Identifies the method. No two methods have the same name.
+    pub name: String,
+    /// Whether the method is tried when the API cannot be reached.
+    pub enabled: bool,
+    pub proxy: CustomProxy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CustomProxy {
+    Shadowsocks(ShadowsocksProxySettings),
+    Socks5(RemoteProxySettings),
+    /// HTTP proxy that supports the `CONNECT` method.
+    Http(RemoteProxySettings),
+    /// obfs4 bridge, reached through the pluggable transport client that is installed with the
+    /// app.
+    Obfs4(Obfs4Settings),
+}
+
+#[derive(err_derive::Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    #[error(
+        display = "Access method names must be between 1 and {} bytes",
+        MAX_NAME_LEN
+    )]
+    InvalidName,
+
+    #[error(display = "There is already an access method named \"{}\"", _0)]
+    DuplicateName(String),
+
+    #[error(display = "There is no access method named \"{}\"", _0)]
+    NotFound(String),
+
+    #[error(display = "Unsupported Shadowsocks cipher: {}", _0)]
+    InvalidCipher(String),
+
+    #[error(display = "Shadowsocks over UDP is not supported by this build")]
+    UdpUnsupported,
+}
+
+impl AccessMethod {
+    /// Checks that the method could be used. Names are not compared to those of other methods.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.name.is_empty() || self.name.len() > MAX_NAME_LEN {
+            return Err(Error::InvalidName);
+        }
+        if let CustomProxy::Shadowsocks(settings) = &self.proxy {
+            if !SHADOWSOCKS_CIPHERS.contains(&settings.cipher.as_str()) {
+                return Err(Error::InvalidCipher(settings.cipher.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AccessMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.proxy)?;
+        if !self.enabled {
+            write!(f, " (disabled)")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CustomProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CustomProxy::Shadowsocks(settings) => {
+                write!(
+                    f,
+                    "Shadowsocks {}/{} ({})",
+                    settings.peer, settings.protocol, settings.cipher
+                )
+            }
+            CustomProxy::Socks5(settings) => {
+                write!(f, "SOCKS5 {}", settings.address)?;
+                if settings.auth.is_some() {
+                    write!(f, " with authentication")?;
+                }
+                Ok(())
+            }
+            CustomProxy::Http(settings) => {
+                write!(f, "HTTP proxy {}", settings.address)?;
+                if settings.auth.is_some() {
+                    write!(f, " with authentication")?;
+                }
+                Ok(())
+            }
+            CustomProxy::Obfs4(settings) => {
+                write!(f, "obfs4 {}", settings.peer)
+            }
+        }
+    }
+}
+
+impl CustomProxy {
+    /// Returns whether requests must be sent over HTTP/3 to use the proxy, which is the case if
+    /// it only relays UDP.
+    pub fn requires_http3(&self) -> bool {
+        matches!(
+            self,
+            CustomProxy::Shadowsocks(settings) if settings.protocol == TransportProtocol::Udp
+        )
+    }
+}
+
+/// Adds `method` to `methods`.
+pub fn add(methods: &mut Vec<AccessMethod>, method: AccessMethod) -> Result<(), Error> {
+    method.validate()?;
+    if methods.iter().any(|existing| existing.name == method.name) {
+        return Err(Error::DuplicateName(method.name));
+    }
+    methods.push(method);
+    Ok(())
+}
+
+impl CustomProxy {
+    /// Takes any password that is left empty from `existing`, if it is the same kind of proxy.
+    /// Frontends are never sent passwords, so they leave them empty when a method is updated.
+    fn keep_passwords(&mut self, existing: &CustomProxy) {
+        match (self, existing) {
+            (CustomProxy::Shadowsocks(settings), CustomProxy::Shadowsocks(existing)) => {
+                if settings.password.is_empty() {
+                    settings.password = existing.password.clone();
+                }
+            }
+            (CustomProxy::Socks5(settings), CustomProxy::Socks5(existing))
+            | (CustomProxy::Http(settings), CustomProxy::Http(existing)) => {
+                if let (Some(auth), Some(existing)) = (&mut settings.auth, &existing.auth) {
+                    if auth.password.is_empty() && auth.username == existing.username {
+                        auth.password = existing.password.clone();
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Replaces the method named `name` with `method`, which may have a different name. Passwords
+/// left empty in `method` are kept from the existing method.
+pub fn update(
+    methods: &mut [AccessMethod],
+    name: &str,
+    mut method: AccessMethod,
+) -> Result<(), Error> {
+    method.validate()?;
+    if method.name != name && methods.iter().any(|existing| existing.name == method.name) {
+        return Err(Error::DuplicateName(method.name));
+    }
+    let existing = methods
+        .iter_mut()
+        .find(|existing| existing.name == name)
+        .ok_or_else(|| Error::NotFound(name.to_owned()))?;
+    method.proxy.keep_passwords(&existing.proxy);
+    *existing = method;
+    Ok(())
+}
+
+/// Removes the method named `name` from `methods`.
+pub fn remove(methods: &mut Vec<AccessMethod>, name: &str) -> Result<(), Error> {
+    let len = methods.len();
+    methods.retain(|method| method.name != name);
+    if methods.len() == len {
+        return Err(Error::NotFound(name.to_owned()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::openvpn::ProxyAuth;
+
+    fn socks5(name: &str) -> AccessMethod {
+        AccessMethod {
+            name: name.to_owned(),
+            enabled: true,
+            proxy: CustomProxy::Socks5(RemoteProxySettings {
+                address: "192.0.2.1:1080".parse().unwrap(),
+                auth: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_unique_names() {
+        let mut methods = vec![];
+        add(&mut methods, socks5("first")).unwrap();
+        add(&mut methods, socks5("second")).unwrap();
+        assert_eq!(
+            add(&mut methods, socks5("first")),
+            Err(Error::DuplicateName("first".to_owned()))
+        );
+        assert_eq!(add(&mut methods, socks5("")), Err(Error::InvalidName));
+
+        assert_eq!(
+            update(&mut methods, "first", socks5("second")),
+            Err(Error::DuplicateName("second".to_owned()))
+        );
+        update(&mut methods, "first", socks5("renamed")).unwrap();
+        assert_eq!(methods[0].name, "renamed");
+
+        assert_eq!(
+            remove(&mut methods, "first"),
+            Err(Error::NotFound("first".to_owned()))
+        );
+        remove(&mut methods, "renamed").unwrap();
+        assert_eq!(methods, vec![socks5("second")]);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut methods = vec![socks5("first"), socks5("second"), socks5("third")];
+        remove(&mut methods, "second").unwrap();
+        assert_eq!(methods, vec![socks5("first"), socks5("third")]);
+        assert_eq!(
+            remove(&mut methods, "second"),
+            Err(Error::NotFound("second".to_owned()))
+        );
+        remove(&mut methods, "first").unwrap();
+        remove(&mut methods, "third").unwrap();
+        assert!(methods.is_empty());
+    }
+
+    #[test]
+    fn test_requires_http3() {
+        let shadowsocks = |protocol| {
+            CustomProxy::Shadowsocks(ShadowsocksProxySettings {
+                peer: "192.0.2.1:443".parse().unwrap(),
+                password: "secret".to_owned(),
+                cipher: "aes-256-gcm".to_owned(),
+                protocol,
+                #[cfg(target_os = "linux")]
+                fwmark: None,
+            })
+        };
+        assert!(shadowsocks(TransportProtocol::Udp).requires_http3());
+        assert!(!shadowsocks(TransportProtocol::Tcp).requires_http3());
+        assert!(!socks5("proxy").proxy.requires_http3());
+    }
+
+    #[test]
+    fn test_update_keeps_passwords() {
+        let with_auth = |password: &str| {
+            let mut method = socks5("proxy");
+            if let CustomProxy::Socks5(settings) = &mut method.proxy {
+                settings.auth = Some(ProxyAuth {
+                    username: "user".to_owned(),
+                    password: password.to_owned(),
+                });
+            }
+            method
+        };
+        let mut methods = vec![with_auth("secret")];
+
+        let mut disabled = with_auth("");
+        disabled.enabled = false;
+        update(&mut methods, "proxy", disabled).unwrap();
+        assert!(!methods[0].enabled);
+        assert_eq!(methods[0].proxy, with_auth("secret").proxy);
+
+        update(&mut methods, "proxy", with_auth("changed")).unwrap();
+        assert_eq!(methods[0].proxy, with_auth("changed").proxy);
+
+        // Passwords are not taken from another kind of proxy
+        let mut http = with_auth("");
+        if let CustomProxy::Socks5(settings) = http.proxy {
+            http.proxy = CustomProxy::Http(settings);
+        }
+        update(&mut methods, "proxy", http.clone()).unwrap();
+        assert_eq!(methods[0].proxy, http.proxy);
+    }
+}
@@ -9,6 +9,17 @@ pub type AccountToken = String;
 /// Identifier used to authenticate a Mullvad account.
 pub type AccessToken = String;
 
+/// Controls whether the account token is written to disk when logging in.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum LoginMode {
+    /// Store the account token along with the device, and add it to the account history.
+    #[default]
+    Remember,
+    /// Keep the account token in memory only. The device is stored encrypted with a key derived
+    /// from the account token, so the account token has to be entered again after a restart.
+    InMemory,
+}
+
 /// Account expiration info returned by the API via `/v1/me`.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 #[cfg_attr(target_os = "android", derive(IntoJava))]
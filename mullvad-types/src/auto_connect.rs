@@ -0,0 +1,287 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, fmt};
+use talpid_types::tunnel::{DisconnectReason, ErrorState, ErrorStateCause};
+
+/// Rule that decides whether the daemon should secure the connection automatically on the
+/// current network.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoConnectRule {
+    /// Connect automatically on all networks.
+    #[default]
+    Always,
+    /// Never connect automatically.
+    Never,
+    /// Only connect automatically on Wi-Fi networks that are not trusted.
+    UntrustedWifiOnly,
+    /// Connect automatically on all networks except wired ones and trusted Wi-Fi networks.
+    WiredExempt,
+}
+
+impl fmt::Display for AutoConnectRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutoConnectRule::Always => "always".fmt(f),
+            AutoConnectRule::Never => "never".fmt(f),
+            AutoConnectRule::UntrustedWifiOnly => "untrusted Wi-Fi only".fmt(f),
+            AutoConnectRule::WiredExempt => "wired exempt".fmt(f),
+        }
+    }
+}
+
+/// Decides when to connect automatically, based on the network the device is on.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AutoConnectPolicy {
+    pub rule: AutoConnectRule,
+    /// SSIDs of Wi-Fi networks on which to never connect automatically, unless the rule is
+    /// [`AutoConnectRule::Always`].
+    pub trusted_networks: BTreeSet<String>,
+}
+
+/// Decides on which networks the daemon may download data that is not needed right away, such as
+/// the relay list and version information.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackgroundWorkPolicy {
+    /// Download in the background on all networks.
+    #[default]
+    Always,
+    /// Only download in the background on wired networks and trusted Wi-Fi networks, to save
+    /// data on metered networks. Downloads are also done on unknown networks, since the network
+    /// is not reported on all platforms.
+    TrustedNetworksOnly,
+}
+
+impl BackgroundWorkPolicy {
+    /// Returns whether background work may be done on `network`, which is trusted if it is in
+    /// the trusted networks of `auto_connect_policy`.
+    pub fn allows(&self, auto_connect_policy: &AutoConnectPolicy, network: &NetworkInfo) -> bool {
+        match self {
+            BackgroundWorkPolicy::Always => true,
+            BackgroundWorkPolicy::TrustedNetworksOnly => match network {
+                NetworkInfo::Unknown | NetworkInfo::Wired => true,
+                NetworkInfo::Wifi { .. } => auto_connect_policy.is_trusted(network),
+                NetworkInfo::Other => false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for BackgroundWorkPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackgroundWorkPolicy::Always => "always".fmt(f),
+            BackgroundWorkPolicy::TrustedNetworksOnly => "trusted networks only".fmt(f),
+        }
+    }
+}
+
+/// The kind of network that the device is currently on.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkInfo {
+    /// No information about the network is available.
+    #[default]
+    Unknown,
+    Wired,
+    Wifi {
+        /// SSID of the network, if known.
+        ssid: Option<String>,
+        /// BSSID of the access point, if known.
+        #[serde(default)]
+        bssid: Option<String>,
+    },
+    /// Any other kind of network, such as a cellular network.
+    Other,
+}
+
+impl fmt::Display for NetworkInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkInfo::Unknown => "unknown network".fmt(f),
+            NetworkInfo::Wired => "wired network".fmt(f),
+            NetworkInfo::Wifi {
+                ssid: Some(ssid), ..
+            } => write!(f, "Wi-Fi network \"{}\"", ssid),
+            NetworkInfo::Wifi { ssid: None, .. } => "Wi-Fi network".fmt(f),
+            NetworkInfo::Other => "other network".fmt(f),
+        }
+    }
+}
+
+/// Outcome of evaluating an [`AutoConnectPolicy`] on a network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoConnectDecision {
+    /// Whether the connection should be secured.
+    pub connect: bool,
+    /// The rule that made the decision.
+    pub rule: AutoConnectRule,
+    /// The network that the policy was evaluated on.
+    pub network: NetworkInfo,
+}
+
+impl AutoConnectPolicy {
+    /// Returns whether to connect automatically on `network`. If `enabled` is false, auto-connect
+    /// is turned off and the policy behaves as if its rule was [`AutoConnectRule::Never`].
+    pub fn evaluate(&self, enabled: bool, network: &NetworkInfo) -> AutoConnectDecision {
+        let rule = if enabled {
+            self.rule
+        } else {
+            AutoConnectRule::Never
+        };
+        let connect = match rule {
+            AutoConnectRule::Always => true,
+            AutoConnectRule::Never => false,
+            AutoConnectRule::UntrustedWifiOnly => {
+                matches!(network, NetworkInfo::Wifi { .. }) && !self.is_trusted(network)
+            }
+            AutoConnectRule::WiredExempt => {
+                !matches!(network, NetworkInfo::Wired) && !self.is_trusted(network)
+            }
+        };
+        AutoConnectDecision {
+            connect,
+            rule,
+            network: network.clone(),
+        }
+    }
+
+    /// Returns whether `network` is a trusted Wi-Fi network.
+    pub fn is_trusted(&self, network: &NetworkInfo) -> bool {
+        match network {
+            NetworkInfo::Wifi {
+                ssid: Some(ssid), ..
+            } => self.trusted_networks.contains(ssid),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for AutoConnectDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} on {} due to rule \"{}\"",
+            if self.connect {
+                "connect"
+            } else {
+                "don't connect"
+            },
+            self.network,
+            self.rule,
+        )
+    }
+}
+
+/// Returns why a tunnel that was connected automatically should be disconnected rather than be
+/// left in the error state `error_state`, if it should. This is the case if the account cannot be
+/// used, if the device is offline, or if traffic could not be blocked. Other failures keep
+/// blocking traffic, as they would for a tunnel that the user connected.
+pub fn failure_disconnect_reason(error_state: &ErrorState) -> Option<DisconnectReason> {
+    match error_state.cause() {
+        ErrorStateCause::AuthFailed(_) => Some(DisconnectReason::AuthFailed),
+        ErrorStateCause::IsOffline => Some(DisconnectReason::Offline),
+        _ if !error_state.is_blocking() => Some(DisconnectReason::TunnelError),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::tunnel::FirewallPolicyError;
+
+    fn wifi(ssid: &str) -> NetworkInfo {
+        NetworkInfo::Wifi {
+            ssid: Some(ssid.to_owned()),
+            bssid: None,
+        }
+    }
+
+    fn policy(rule: AutoConnectRule) -> AutoConnectPolicy {
+        AutoConnectPolicy {
+            rule,
+            trusted_networks: ["home".to_owned()].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn test_disabled_never_connects() {
+        let decision = policy(AutoConnectRule::Always).evaluate(false, &wifi("cafe"));
+        assert!(!decision.connect);
+        assert_eq!(decision.rule, AutoConnectRule::Never);
+    }
+
+    #[test]
+    fn test_always_ignores_trusted_networks() {
+        let policy = policy(AutoConnectRule::Always);
+        assert!(policy.evaluate(true, &wifi("home")).connect);
+        assert!(policy.evaluate(true, &NetworkInfo::Unknown).connect);
+    }
+
+    #[test]
+    fn test_untrusted_wifi_only() {
+        let policy = policy(AutoConnectRule::UntrustedWifiOnly);
+        assert!(policy.evaluate(true, &wifi("cafe")).connect);
+        assert!(
+            policy
+                .evaluate(
+                    true,
+                    &NetworkInfo::Wifi {
+                        ssid: None,
+                        bssid: None
+                    }
+                )
+                .connect
+        );
+        assert!(!policy.evaluate(true, &wifi("home")).connect);
+        assert!(!policy.evaluate(true, &NetworkInfo::Wired).connect);
+        assert!(!policy.evaluate(true, &NetworkInfo::Unknown).connect);
+    }
+
+    #[test]
+    fn test_wired_exempt() {
+        let policy = policy(AutoConnectRule::WiredExempt);
+        assert!(!policy.evaluate(true, &NetworkInfo::Wired).connect);
+        assert!(!policy.evaluate(true, &wifi("home")).connect);
+        assert!(policy.evaluate(true, &wifi("cafe")).connect);
+        assert!(policy.evaluate(true, &NetworkInfo::Other).connect);
+        assert!(policy.evaluate(true, &NetworkInfo::Unknown).connect);
+    }
+
+    #[test]
+    fn test_background_work_on_trusted_networks_only() {
+        let policy = policy(AutoConnectRule::Never);
+        let background_work = BackgroundWorkPolicy::TrustedNetworksOnly;
+        assert!(background_work.allows(&policy, &wifi("home")));
+        assert!(!background_work.allows(&policy, &wifi("cafe")));
+        assert!(background_work.allows(&policy, &NetworkInfo::Wired));
+        assert!(!background_work.allows(&policy, &NetworkInfo::Other));
+        assert!(background_work.allows(&policy, &NetworkInfo::Unknown));
+        assert!(BackgroundWorkPolicy::Always.allows(&policy, &NetworkInfo::Other));
+    }
+
+    #[test]
+    fn test_failure_disconnect_reason() {
+        let reason = |cause, block_failure| {
+            failure_disconnect_reason(&ErrorState::new(cause, block_failure))
+        };
+        assert_eq!(
+            reason(ErrorStateCause::AuthFailed(None), None),
+            Some(DisconnectReason::AuthFailed)
+        );
+        assert_eq!(
+            reason(ErrorStateCause::IsOffline, None),
+            Some(DisconnectReason::Offline)
+        );
+        assert_eq!(reason(ErrorStateCause::StartTunnelError, None), None);
+        assert_eq!(
+            reason(
+                ErrorStateCause::StartTunnelError,
+                Some(FirewallPolicyError::Generic)
+            ),
+            Some(DisconnectReason::TunnelError)
+        );
+    }
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::PathBuf};
+
+/// A cache file that failed its integrity check and was moved out of the way so that it can be
+/// regenerated.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct QuarantinedFile {
+    /// Path of the cache file.
+    pub path: PathBuf,
+    /// Where a copy of the corrupt file was saved, if that succeeded.
+    pub quarantine_path: Option<PathBuf>,
+    pub reason: CorruptionReason,
+}
+
+/// Why a cache file was considered corrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CorruptionReason {
+    /// The contents do not match the checksum stored next to the file.
+    ChecksumMismatch,
+    /// The checksum matched, or was missing, but the contents could not be parsed.
+    ParseError,
+}
+
+impl fmt::Display for QuarantinedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.reason {
+            CorruptionReason::ChecksumMismatch => "checksum mismatch",
+            CorruptionReason::ParseError => "parse error",
+        };
+        write!(f, "{} ({})", self.path.display(), reason)?;
+        if let Some(quarantine_path) = &self.quarantine_path {
+            write!(f, ", moved to {}", quarantine_path.display())?;
+        }
+        Ok(())
+    }
+}
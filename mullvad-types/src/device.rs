@@ -117,6 +117,8 @@ pub enum DeviceEventCause {
     Updated,
     /// The key was rotated.
     RotatedKey,
+    /// The key was replaced because it did not match the key registered for the device.
+    RepairedKey,
 }
 
 /// Emitted when logging in or out of an account, or when the device changes.
@@ -1,10 +1,14 @@
 #![deny(rust_2018_idioms)]
 
+pub mod access_method;
 pub mod account;
 pub mod auth_failed;
+pub mod auto_connect;
+pub mod cache;
 pub mod device;
 pub mod endpoint;
 pub mod location;
+pub mod network;
 pub mod relay_constraints;
 pub mod relay_list;
 pub mod settings;
@@ -1,3 +1,4 @@
+use crate::relay_list::RelayCapabilities;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
@@ -64,8 +65,8 @@ impl Coordinates {
     /// axis is assumed to equal the semi-major (equatorial) axis.
     ///
     /// https://en.wikipedia.org/wiki/Spherical_coordinate_system#Cartesian_coordinates
-    pub fn midpoint(locations: &[Location]) -> Self {
-        Self::midpoint_inner(locations.iter().map(Coordinates::from))
+    pub fn midpoint<'a>(locations: impl IntoIterator<Item = &'a Location>) -> Self {
+        Self::midpoint_inner(locations.into_iter().map(Coordinates::from))
     }
 
     fn midpoint_inner(locations: impl std::iter::Iterator<Item = Coordinates>) -> Self {
@@ -156,6 +157,19 @@ pub struct GeoIpLocation {
     pub entry_hostname: Option<String>,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub obfuscator_hostname: Option<String>,
+    /// Hosting provider of the exit relay.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub provider: Option<String>,
+    /// Whether the exit relay is owned by Mullvad, as opposed to rented.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub owned: Option<bool>,
+    /// Optional features that are in use on the tunnel to the relays.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub features: Option<RelayCapabilities>,
+    /// The selected location, if no relay in it was available and the exit relay is in a nearby
+    /// location instead.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub nearby_fallback_from: Option<String>,
 }
 
 impl From<AmIMullvad> for GeoIpLocation {
@@ -177,6 +191,10 @@ impl From<AmIMullvad> for GeoIpLocation {
             bridge_hostname: None,
             entry_hostname: None,
             obfuscator_hostname: None,
+            provider: None,
+            owned: None,
+            features: None,
+            nearby_fallback_from: None,
         }
     }
 }
@@ -0,0 +1,222 @@
+use crate::{auto_connect::NetworkInfo, relay_constraints::SelectedObfuscation};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use talpid_types::{
+    net::{DefaultRoute, IpVersion},
+    tunnel::ConnectionTimeouts,
+};
+
+/// The daemon's view of the network that the device is connected to.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CurrentNetwork {
+    /// The kind of network, as last reported by a frontend. The BSSID is never included.
+    pub network: NetworkInfo,
+    /// SHA-256 hash of the BSSID of the access point, if known.
+    pub bssid_hash: Option<String>,
+    /// Default route for IPv4 traffic outside the tunnel.
+    pub ipv4_route: Option<DefaultRoute>,
+    /// Default route for IPv6 traffic outside the tunnel.
+    pub ipv6_route: Option<DefaultRoute>,
+}
+
+impl CurrentNetwork {
+    /// Returns the interface that traffic outside the tunnel is sent through, preferring the one
+    /// used for IPv4.
+    pub fn default_interface(&self) -> Option<&str> {
+        self.ipv4_route
+            .as_ref()
+            .or(self.ipv6_route.as_ref())
+            .map(|route| route.interface.as_str())
+    }
+
+    /// Returns the address families that have a default route.
+    pub fn address_families(&self) -> Vec<IpVersion> {
+        let mut families = vec![];
+        if self.ipv4_route.is_some() {
+            families.push(IpVersion::V4);
+        }
+        if self.ipv6_route.is_some() {
+            families.push(IpVersion::V6);
+        }
+        families
+    }
+}
+
+/// Identifies a network that a [`NetworkProfile`] applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkId {
+    Wired,
+    /// A Wi-Fi network with the given SSID.
+    Wifi {
+        ssid: String,
+    },
+    /// Any other kind of network, such as a cellular network.
+    Other,
+}
+
+impl NetworkId {
+    /// Returns whether `network` is the network identified by `self`.
+    pub fn matches(&self, network: &NetworkInfo) -> bool {
+        match (self, network) {
+            (NetworkId::Wired, NetworkInfo::Wired) => true,
+            (
+                NetworkId::Wifi { ssid },
+                NetworkInfo::Wifi {
+                    ssid: Some(other), ..
+                },
+            ) => ssid == other,
+            (NetworkId::Other, NetworkInfo::Other) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for NetworkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkId::Wired => "wired network".fmt(f),
+            NetworkId::Wifi { ssid } => write!(f, "Wi-Fi network \"{}\"", ssid),
+            NetworkId::Other => "other network".fmt(f),
+        }
+    }
+}
+
+/// Connection parameters that replace the global settings while the device is on a particular
+/// network. Parameters that are `None` are taken from the global settings.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct NetworkProfile {
+    pub network: NetworkId,
+    /// Time to wait for a WireGuard tunnel to start passing traffic, in seconds.
+    #[serde(default)]
+    pub connect_timeout: Option<u32>,
+    /// Interval between WireGuard keepalive packets, in seconds.
+    #[serde(default)]
+    pub wireguard_keepalive: Option<u16>,
+    #[serde(default)]
+    pub obfuscation: Option<SelectedObfuscation>,
+    /// MTU of the WireGuard tunnel.
+    #[serde(default)]
+    pub mtu: Option<u16>,
+}
+
+impl NetworkProfile {
+    /// Shortest connect timeout that a profile may set. A shorter timeout would give up on every
+    /// attempt before the handshake can complete.
+    pub const MIN_CONNECT_TIMEOUT_SECS: u32 = ConnectionTimeouts::MIN_CONNECTING_TIMEOUT_SECS;
+    /// Longest interval between keepalive packets that a profile may set. Longer intervals do not
+    /// keep NAT mappings open.
+    pub const MAX_KEEPALIVE_SECS: u16 = 3600;
+    /// Smallest MTU that a profile may set. IPv6 requires at least 1280 bytes.
+    pub const MIN_MTU: u16 = 1280;
+    /// Largest MTU that a profile may set, leaving room for the WireGuard headers on a 1500 byte
+    /// link.
+    pub const MAX_MTU: u16 = 1420;
+
+    /// Returns a profile for `network` that does not change any parameters.
+    pub fn new(network: NetworkId) -> Self {
+        NetworkProfile {
+            network,
+            connect_timeout: None,
+            wireguard_keepalive: None,
+            obfuscation: None,
+            mtu: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if let Some(timeout) = self.connect_timeout {
+            if timeout < Self::MIN_CONNECT_TIMEOUT_SECS {
+                return Err("the connect timeout must be at least 4 seconds");
+            }
+        }
+        if let Some(keepalive) = self.wireguard_keepalive {
+            if !(1..=Self::MAX_KEEPALIVE_SECS).contains(&keepalive) {
+                return Err("the keepalive interval must be between 1 and 3600 seconds");
+            }
+        }
+        if let Some(mtu) = self.mtu {
+            if !(Self::MIN_MTU..=Self::MAX_MTU).contains(&mtu) {
+                return Err("the MTU must be between 1280 and 1420");
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the first profile in `profiles` that applies to `network`.
+    pub fn find<'a>(profiles: &'a [NetworkProfile], network: &NetworkInfo) -> Option<&'a Self> {
+        profiles
+            .iter()
+            .find(|profile| profile.network.matches(network))
+    }
+}
+
+impl fmt::Display for NetworkProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.network)?;
+        let mut empty = true;
+        if let Some(timeout) = self.connect_timeout {
+            write!(f, " connect timeout {}s", timeout)?;
+            empty = false;
+        }
+        if let Some(keepalive) = self.wireguard_keepalive {
+            write!(f, " keepalive {}s", keepalive)?;
+            empty = false;
+        }
+        if let Some(obfuscation) = self.obfuscation {
+            write!(f, " obfuscation {}", obfuscation)?;
+            empty = false;
+        }
+        if let Some(mtu) = self.mtu {
+            write!(f, " MTU {}", mtu)?;
+            empty = false;
+        }
+        if empty {
+            write!(f, " no overrides")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_profile() {
+        let profile = NetworkProfile {
+            connect_timeout: Some(NetworkProfile::MIN_CONNECT_TIMEOUT_SECS),
+            wireguard_keepalive: Some(25),
+            mtu: Some(NetworkProfile::MIN_MTU),
+            ..NetworkProfile::new(NetworkId::Wired)
+        };
+        assert!(profile.validate().is_ok());
+        assert!(NetworkProfile::new(NetworkId::Other).validate().is_ok());
+
+        let invalid = [
+            NetworkProfile {
+                connect_timeout: Some(0),
+                ..profile.clone()
+            },
+            NetworkProfile {
+                wireguard_keepalive: Some(0),
+                ..profile.clone()
+            },
+            NetworkProfile {
+                wireguard_keepalive: Some(NetworkProfile::MAX_KEEPALIVE_SECS + 1),
+                ..profile.clone()
+            },
+            NetworkProfile {
+                mtu: Some(NetworkProfile::MAX_MTU + 1),
+                ..profile.clone()
+            },
+            NetworkProfile {
+                mtu: Some(576),
+                ..profile
+            },
+        ];
+        for profile in invalid {
+            assert!(profile.validate().is_err(), "{:?} is valid", profile);
+        }
+    }
+}
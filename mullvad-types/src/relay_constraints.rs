@@ -9,7 +9,12 @@ use crate::{
 #[cfg(target_os = "android")]
 use jnix::{FromJava, IntoJava};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, fmt};
+use std::{
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
 use talpid_types::net::{openvpn::ProxySettings, IpVersion, TransportProtocol, TunnelType};
 
 pub trait Match<T> {
@@ -21,7 +26,7 @@ pub trait Set<T> {
 }
 
 /// Limits the set of [`crate::relay_list::Relay`]s that a `RelaySelector` may select.
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[cfg_attr(target_os = "android", derive(FromJava, IntoJava))]
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
@@ -172,6 +177,19 @@ impl RelaySettings {
             }),
         }
     }
+
+    /// Returns false if the relay settings explicitly do not allow for bridging (i.e. use UDP
+    /// instead of TCP)
+    pub fn supports_bridge(&self) -> bool {
+        match self {
+            RelaySettings::CustomTunnelEndpoint(endpoint) => {
+                endpoint.endpoint().protocol == TransportProtocol::Tcp
+            }
+            RelaySettings::Normal(constraints) => {
+                constraints.openvpn_constraints.transport_protocol() != Some(TransportProtocol::Udp)
+            }
+        }
+    }
 }
 
 /// Limits the set of [`crate::relay_list::Relay`]s that a `RelaySelector` may select.
@@ -192,6 +210,9 @@ pub struct RelayConstraints {
     pub wireguard_constraints: WireguardConstraints,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub openvpn_constraints: OpenVpnConstraints,
+    /// Select a relay in a nearby city if no relay in `location` matches the other constraints.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub nearby_locations: bool,
 }
 
 #[cfg(target_os = "android")]
@@ -204,6 +225,7 @@ impl Default for RelayConstraints {
             ownership: Constraint::default(),
             wireguard_constraints: WireguardConstraints::default(),
             openvpn_constraints: OpenVpnConstraints::default(),
+            nearby_locations: false,
         }
     }
 }
@@ -221,6 +243,7 @@ impl RelayConstraints {
             openvpn_constraints: update
                 .openvpn_constraints
                 .unwrap_or(self.openvpn_constraints),
+            nearby_locations: update.nearby_locations.unwrap_or(self.nearby_locations),
         }
     }
 }
@@ -248,7 +271,12 @@ impl fmt::Display for RelayConstraints {
         write!(f, " in ")?;
         match self.location {
             Constraint::Any => write!(f, "any location")?,
-            Constraint::Only(ref location_constraint) => location_constraint.fmt(f)?,
+            Constraint::Only(ref location_constraint) => {
+                location_constraint.fmt(f)?;
+                if self.nearby_locations {
+                    write!(f, " or nearby")?;
+                }
+            }
         }
         write!(f, " using ")?;
         match self.providers {
@@ -266,7 +294,7 @@ impl fmt::Display for RelayConstraints {
 
 /// Limits the set of [`crate::relay_list::Relay`]s used by a `RelaySelector` based on
 /// location.
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[cfg_attr(target_os = "android", derive(FromJava, IntoJava))]
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
@@ -344,7 +372,7 @@ impl Set<LocationConstraint> for LocationConstraint {
 }
 
 /// Limits the set of servers to choose based on ownership.
-#[derive(Copy, Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(Copy, Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub enum Ownership {
     MullvadOwned,
     Rented,
@@ -403,6 +431,15 @@ impl Match<Relay> for Providers {
     }
 }
 
+impl Hash for Providers {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // The iteration order of a `HashSet` is arbitrary, so hash the providers in sorted order.
+        let mut providers: Vec<&Provider> = self.providers.iter().collect();
+        providers.sort();
+        providers.hash(state);
+    }
+}
+
 impl From<Providers> for Vec<Provider> {
     fn from(providers: Providers) -> Vec<Provider> {
         providers.providers.into_iter().collect()
@@ -435,18 +472,184 @@ impl fmt::Display for LocationConstraint {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+/// Error returned when a location shorthand cannot be parsed.
+#[derive(err_derive::Error, Debug, Clone, Eq, PartialEq)]
+pub enum ParseLocationError {
+    #[error(display = "No location was given")]
+    Empty,
+
+    #[error(display = "Country codes must be two letters, got \"{}\"", _0)]
+    InvalidCountry(String),
+
+    #[error(display = "City codes must be three letters, got \"{}\"", _0)]
+    InvalidCity(String),
+
+    #[error(display = "Invalid hostname \"{}\"", _0)]
+    InvalidHostname(String),
+
+    #[error(display = "A location has at most three parts: <country>/<city>/<hostname>")]
+    TooManyParts,
+}
+
+impl LocationConstraint {
+    /// Returns the location in the shorthand format that [`LocationConstraint::from_str`]
+    /// accepts, such as `se`, `se/got` or `se-got-wg-001`.
+    pub fn shorthand(&self) -> String {
+        match self {
+            LocationConstraint::Country(country) => country.clone(),
+            LocationConstraint::City(country, city) => format!("{}/{}", country, city),
+            LocationConstraint::Hostname(country, city, hostname) => {
+                match location_from_hostname(hostname) {
+                    Some((host_country, host_city))
+                        if &host_country == country && &host_city == city =>
+                    {
+                        hostname.clone()
+                    }
+                    _ => format!("{}/{}/{}", country, city, hostname),
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for LocationConstraint {
+    type Err = ParseLocationError;
+
+    /// Parses a location on the form `<country>[/<city>[/<hostname>]]`. A hostname may also be
+    /// given on its own if it begins with the country and city codes, as in `se-got-wg-001`.
+    fn from_str(location: &str) -> Result<Self, Self::Err> {
+        let location = location.trim().to_lowercase();
+        if location.is_empty() {
+            return Err(ParseLocationError::Empty);
+        }
+
+        let parts: Vec<&str> = location.split('/').collect();
+        match parts[..] {
+            [hostname] if hostname.contains('-') => {
+                let (country, city) = location_from_hostname(hostname)
+                    .ok_or_else(|| ParseLocationError::InvalidHostname(hostname.to_owned()))?;
+                Ok(LocationConstraint::Hostname(
+                    country,
+                    city,
+                    hostname.to_owned(),
+                ))
+            }
+            [country] => Ok(LocationConstraint::Country(parse_country_code(country)?)),
+            [country, city] => Ok(LocationConstraint::City(
+                parse_country_code(country)?,
+                parse_city_code(city)?,
+            )),
+            [country, city, hostname] => Ok(LocationConstraint::Hostname(
+                parse_country_code(country)?,
+                parse_city_code(city)?,
+                parse_hostname(hostname)?,
+            )),
+            _ => Err(ParseLocationError::TooManyParts),
+        }
+    }
+}
+
+impl FromStr for Constraint<LocationConstraint> {
+    type Err = ParseLocationError;
+
+    /// Parses `any`, or a location shorthand as described in [`LocationConstraint::from_str`].
+    fn from_str(location: &str) -> Result<Self, Self::Err> {
+        if location.trim().eq_ignore_ascii_case("any") {
+            return Ok(Constraint::Any);
+        }
+        location.parse().map(Constraint::Only)
+    }
+}
+
+fn parse_country_code(country: &str) -> Result<CountryCode, ParseLocationError> {
+    if country.len() == 2 && country.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(country.to_owned())
+    } else {
+        Err(ParseLocationError::InvalidCountry(country.to_owned()))
+    }
+}
+
+fn parse_city_code(city: &str) -> Result<CityCode, ParseLocationError> {
+    if city.len() == 3 && city.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(city.to_owned())
+    } else {
+        Err(ParseLocationError::InvalidCity(city.to_owned()))
+    }
+}
+
+fn parse_hostname(hostname: &str) -> Result<Hostname, ParseLocationError> {
+    if !hostname.is_empty()
+        && hostname
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        Ok(hostname.to_owned())
+    } else {
+        Err(ParseLocationError::InvalidHostname(hostname.to_owned()))
+    }
+}
+
+/// Relay hostnames begin with the codes of the country and city that the relay is in, e.g.
+/// `se-got-wg-001`. Returns those codes, or `None` if the hostname does not follow that scheme.
+fn location_from_hostname(hostname: &str) -> Option<(CountryCode, CityCode)> {
+    let hostname = parse_hostname(hostname).ok()?;
+    let mut parts = hostname.split('-');
+    let country = parse_country_code(parts.next()?).ok()?;
+    let city = parse_city_code(parts.next()?).ok()?;
+    parts.next()?;
+    Some((country, city))
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct TransportPort {
     pub protocol: TransportProtocol,
     pub port: Constraint<u16>,
 }
 
+/// A tunnel protocol and transport that the relay selector tries when the port is not
+/// constrained. For WireGuard, TCP means that the tunnel is obfuscated using udp2tcp.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct PortSelectionStep {
+    pub tunnel_type: TunnelType,
+    pub transport: TransportPort,
+}
+
+impl PortSelectionStep {
+    /// Returns whether this step tunnels WireGuard over TCP.
+    pub fn uses_udp2tcp(&self) -> bool {
+        self.tunnel_type == TunnelType::Wireguard
+            && self.transport.protocol == TransportProtocol::Tcp
+    }
+}
+
+impl fmt::Display for PortSelectionStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{} ", self.tunnel_type)?;
+        match self.transport.port {
+            Constraint::Any => write!(f, "any port")?,
+            Constraint::Only(port) => write!(f, "port {}", port)?,
+        }
+        write!(f, "/{}", self.transport.protocol)?;
+        if self.uses_udp2tcp() {
+            write!(f, " (udp2tcp)")?;
+        }
+        Ok(())
+    }
+}
+
 /// [`Constraint`]s applicable to OpenVPN relays.
-#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct OpenVpnConstraints {
     pub port: Constraint<TransportPort>,
 }
 
+impl OpenVpnConstraints {
+    /// Returns the transport protocol, if one has been explicitly selected.
+    pub fn transport_protocol(&self) -> Option<TransportProtocol> {
+        self.port.option().map(|port| port.protocol)
+    }
+}
+
 impl fmt::Display for OpenVpnConstraints {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self.port {
@@ -511,6 +714,7 @@ pub enum SelectedObfuscation {
     #[default]
     Off,
     Udp2Tcp,
+    PortHopping,
 }
 
 impl fmt::Display for SelectedObfuscation {
@@ -519,6 +723,7 @@ impl fmt::Display for SelectedObfuscation {
             SelectedObfuscation::Auto => "auto".fmt(f),
             SelectedObfuscation::Off => "off".fmt(f),
             SelectedObfuscation::Udp2Tcp => "udp2tcp".fmt(f),
+            SelectedObfuscation::PortHopping => "port hopping".fmt(f),
         }
     }
 }
@@ -538,6 +743,37 @@ impl fmt::Display for Udp2TcpObfuscationSettings {
     }
 }
 
+/// Settings for rotating the WireGuard port among those advertised by the relays.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(default)]
+pub struct PortHoppingSettings {
+    /// Number of seconds to stay on a port while connected. If this is `None`, the port only
+    /// changes when reconnecting.
+    pub interval: Option<u32>,
+}
+
+impl PortHoppingSettings {
+    pub const DEFAULT_INTERVAL: u32 = 5 * 60;
+}
+
+impl Default for PortHoppingSettings {
+    fn default() -> Self {
+        PortHoppingSettings {
+            interval: Some(Self::DEFAULT_INTERVAL),
+        }
+    }
+}
+
+impl fmt::Display for PortHoppingSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.interval {
+            Some(interval) => write!(f, "every {} seconds", interval),
+            None => write!(f, "on reconnect only"),
+        }
+    }
+}
+
 /// Contains obfuscation settings
 #[derive(Default, Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -545,6 +781,7 @@ impl fmt::Display for Udp2TcpObfuscationSettings {
 pub struct ObfuscationSettings {
     pub selected_obfuscation: SelectedObfuscation,
     pub udp2tcp: Udp2TcpObfuscationSettings,
+    pub port_hopping: PortHoppingSettings,
 }
 
 /// Limits the set of bridge servers to use in `mullvad-daemon`.
@@ -619,27 +856,6 @@ pub enum RelaySettingsUpdate {
     Normal(RelayConstraintsUpdate),
 }
 
-impl RelaySettingsUpdate {
-    /// Returns false if the specified relay settings update explicitly do not allow for bridging
-    /// (i.e. use UDP instead of TCP)
-    pub fn supports_bridge(&self) -> bool {
-        match &self {
-            RelaySettingsUpdate::CustomTunnelEndpoint(endpoint) => {
-                endpoint.endpoint().protocol == TransportProtocol::Tcp
-            }
-            RelaySettingsUpdate::Normal(update) => !matches!(
-                &update.openvpn_constraints,
-                Some(OpenVpnConstraints {
-                    port: Constraint::Only(TransportPort {
-                        protocol: TransportProtocol::Udp,
-                        ..
-                    })
-                })
-            ),
-        }
-    }
-}
-
 /// Used in [`RelaySettings`] to change relay constraints in the daemon.
 #[derive(Debug, Default, Deserialize, Serialize)]
 #[cfg_attr(target_os = "android", derive(FromJava))]
@@ -657,4 +873,76 @@ pub struct RelayConstraintsUpdate {
     pub wireguard_constraints: Option<WireguardConstraints>,
     #[cfg_attr(target_os = "android", jnix(default))]
     pub openvpn_constraints: Option<OpenVpnConstraints>,
+    #[cfg_attr(target_os = "android", jnix(default))]
+    pub nearby_locations: Option<bool>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_location_shorthand() {
+        assert_eq!(
+            "SE".parse(),
+            Ok(LocationConstraint::Country("se".to_owned()))
+        );
+        assert_eq!(
+            "se/got".parse(),
+            Ok(LocationConstraint::City("se".to_owned(), "got".to_owned()))
+        );
+        let hostname = LocationConstraint::Hostname(
+            "se".to_owned(),
+            "got".to_owned(),
+            "se-got-wg-001".to_owned(),
+        );
+        assert_eq!("se-got-wg-001".parse(), Ok(hostname.clone()));
+        assert_eq!("se/got/se-got-wg-001".parse(), Ok(hostname));
+        assert_eq!(
+            "any".parse::<Constraint<LocationConstraint>>(),
+            Ok(Constraint::Any)
+        );
+
+        assert_eq!(
+            "".parse::<LocationConstraint>(),
+            Err(ParseLocationError::Empty)
+        );
+        assert_eq!(
+            "swe".parse::<LocationConstraint>(),
+            Err(ParseLocationError::InvalidCountry("swe".to_owned()))
+        );
+        assert_eq!(
+            "se/gothenburg".parse::<LocationConstraint>(),
+            Err(ParseLocationError::InvalidCity("gothenburg".to_owned()))
+        );
+        assert_eq!(
+            "relay-1".parse::<LocationConstraint>(),
+            Err(ParseLocationError::InvalidHostname("relay-1".to_owned()))
+        );
+        assert_eq!(
+            "se/got/se-got-wg-001/x".parse::<LocationConstraint>(),
+            Err(ParseLocationError::TooManyParts)
+        );
+    }
+
+    #[test]
+    fn test_location_shorthand_round_trip() {
+        let locations = [
+            LocationConstraint::Country("se".to_owned()),
+            LocationConstraint::City("se".to_owned(), "got".to_owned()),
+            LocationConstraint::Hostname(
+                "se".to_owned(),
+                "got".to_owned(),
+                "se-got-wg-001".to_owned(),
+            ),
+            LocationConstraint::Hostname(
+                "se".to_owned(),
+                "sto".to_owned(),
+                "se-got-wg-001".to_owned(),
+            ),
+        ];
+        for location in locations {
+            assert_eq!(location.shorthand().parse(), Ok(location));
+        }
+    }
 }
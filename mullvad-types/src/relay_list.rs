@@ -1,8 +1,15 @@
-use crate::location::{CityCode, CountryCode, Location};
+use crate::{
+    location::{CityCode, CountryCode, Location},
+    relay_constraints::LocationConstraint,
+};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
 use talpid_types::net::{
     openvpn::{ProxySettings, ShadowsocksProxySettings},
     wireguard, TransportProtocol,
@@ -30,6 +37,70 @@ impl RelayList {
     pub fn empty() -> Self {
         Self::default()
     }
+
+    /// Returns the relays in all countries and cities.
+    pub fn relays(&self) -> impl Iterator<Item = &Relay> + Clone {
+        self.countries
+            .iter()
+            .flat_map(|country| &country.cities)
+            .flat_map(|city| &city.relays)
+    }
+}
+
+/// A location in a [`RelayList`] that matched a search.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LocationSearchResult {
+    pub location: LocationConstraint,
+    /// Name of the location, translated to the language that was searched in if possible.
+    pub name: String,
+}
+
+/// Explains why no relay matches a set of constraints. The constraints are applied one at a time,
+/// and each step lists how many relays a constraint removed.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RelaySelectionHint {
+    /// Number of active relays before any constraint was applied.
+    pub total: u32,
+    /// The constraints that removed relays, in the order they were applied.
+    pub steps: Vec<RelaySelectionStep>,
+}
+
+/// A constraint that removed relays from the candidates. Used by [`RelaySelectionHint`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RelaySelectionStep {
+    /// Description of the constraint, such as "WireGuard port 4000".
+    pub constraint: String,
+    /// Number of relays removed by the constraint.
+    pub removed: u32,
+    /// Number of relays left after the constraint was applied.
+    pub remaining: u32,
+}
+
+impl fmt::Display for RelaySelectionHint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.total == 0 {
+            return write!(f, "the relay list is empty");
+        }
+        if self.steps.is_empty() {
+            return write!(f, "no constraint removed any of the {} relays", self.total);
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            if step.remaining == 0 && i > 0 {
+                write!(
+                    f,
+                    "{} removed the remaining {}",
+                    step.constraint, step.removed
+                )?;
+            } else {
+                let noun = if step.removed == 1 { "relay" } else { "relays" };
+                write!(f, "{} removed {} {}", step.constraint, step.removed, noun)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 /// A list of [`RelayListCity`]s within a country. Used by [`RelayList`].
@@ -77,8 +148,9 @@ pub struct Relay {
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub weight: u64,
     pub endpoint_data: RelayEndpointData,
+    /// Relays in the same city may share a single location.
     #[cfg_attr(target_os = "android", jnix(skip))]
-    pub location: Option<Location>,
+    pub location: Option<Arc<Location>>,
 }
 
 /// Specifies the type of a relay or relay-specific endpoint data.
@@ -99,6 +171,15 @@ impl RelayEndpointData {
         }
         panic!("not a wireguard endpoint");
     }
+
+    /// Returns the optional features supported by the relay. Only WireGuard relays support any
+    /// of these.
+    pub fn capabilities(&self) -> RelayCapabilities {
+        match self {
+            RelayEndpointData::Wireguard(wg) => wg.capabilities,
+            _ => RelayCapabilities::default(),
+        }
+    }
 }
 
 /// Data needed to connect to OpenVPN endpoints.
@@ -147,6 +228,91 @@ impl Default for WireguardEndpointData {
 pub struct WireguardRelayEndpointData {
     /// Public key used by the relay peer
     pub public_key: wireguard::PublicKey,
+    /// Optional features supported by the relay
+    #[serde(default = "RelayCapabilities::legacy")]
+    pub capabilities: RelayCapabilities,
+}
+
+/// Optional features that a relay may or may not support. This is used both to describe what a
+/// relay supports and what the current settings require of a relay.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RelayCapabilities {
+    /// The relay runs the tunnel config service, so a quantum-resistant PSK can be negotiated.
+    pub quantum_resistant: bool,
+    /// The relay supports DAITA (Defence Against AI-guided Traffic Analysis).
+    pub daita: bool,
+    /// The relay accepts udp2tcp obfuscated traffic on the advertised obfuscation ports.
+    pub udp2tcp: bool,
+}
+
+impl RelayCapabilities {
+    /// Capabilities assumed for relays in relay lists that do not advertise any. These are the
+    /// features that every WireGuard relay supported before capabilities were added to the relay
+    /// list.
+    pub fn legacy() -> Self {
+        Self {
+            quantum_resistant: true,
+            daita: false,
+            udp2tcp: true,
+        }
+    }
+
+    /// Returns `true` if no capability is set.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns the capabilities in `required` that are not supported by `self`.
+    pub fn missing(&self, required: &RelayCapabilities) -> RelayCapabilities {
+        RelayCapabilities {
+            quantum_resistant: required.quantum_resistant && !self.quantum_resistant,
+            daita: required.daita && !self.daita,
+            udp2tcp: required.udp2tcp && !self.udp2tcp,
+        }
+    }
+
+    /// Returns `true` if `self` supports every capability in `required`.
+    pub fn supports(&self, required: &RelayCapabilities) -> bool {
+        self.missing(required).is_empty()
+    }
+
+    /// Returns the capabilities supported by either `self` or `other`.
+    pub fn union(&self, other: &RelayCapabilities) -> RelayCapabilities {
+        RelayCapabilities {
+            quantum_resistant: self.quantum_resistant || other.quantum_resistant,
+            daita: self.daita || other.daita,
+            udp2tcp: self.udp2tcp || other.udp2tcp,
+        }
+    }
+
+    /// Returns the capabilities supported by both `self` and `other`.
+    pub fn intersection(&self, other: &RelayCapabilities) -> RelayCapabilities {
+        RelayCapabilities {
+            quantum_resistant: self.quantum_resistant && other.quantum_resistant,
+            daita: self.daita && other.daita,
+            udp2tcp: self.udp2tcp && other.udp2tcp,
+        }
+    }
+}
+
+impl fmt::Display for RelayCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (self.quantum_resistant, "quantum resistance"),
+            (self.daita, "DAITA"),
+            (self.udp2tcp, "udp2tcp"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| if enabled { Some(name) } else { None })
+        .collect::<Vec<_>>();
+
+        if names.is_empty() {
+            "none".fmt(f)
+        } else {
+            names.join(", ").fmt(f)
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
@@ -173,6 +339,7 @@ impl ShadowsocksEndpointData {
             peer: SocketAddr::new(addr, self.port),
             password: self.password.clone(),
             cipher: self.cipher.clone(),
+            protocol: self.protocol,
             #[cfg(target_os = "linux")]
             fwmark: Some(fwmark),
         })
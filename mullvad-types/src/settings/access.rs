@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Determines what local users may do through the management interface. This is only enforced
+/// on Linux and macOS, where the daemon can check the credentials of the connecting process.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ManagementAccessPolicy {
+    /// If this is false, every user that can open the socket has full control over the daemon.
+    pub enabled: bool,
+    /// Users other than root that may change settings and control the tunnel. Only root may
+    /// change the policy itself.
+    pub control_uids: Vec<u32>,
+    /// Groups whose members may change settings and control the tunnel.
+    pub control_gids: Vec<u32>,
+    /// Groups whose members may only query the state of the daemon. If this is empty, every
+    /// other local user is given read-only access.
+    pub read_only_gids: Vec<u32>,
+}
+
+/// What a user is allowed to do through the management interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccessRole {
+    /// May query the state of the daemon, but not change it.
+    ReadOnly,
+    /// May do anything except changing the access policy.
+    Control,
+    /// May do anything. Only root has this role.
+    Admin,
+}
+
+impl ManagementAccessPolicy {
+    /// Returns the role of a user with the user ID `uid` who is a member of the groups `gids`,
+    /// or `None` if the user may not use the management interface at all.
+    pub fn role(&self, uid: u32, gids: &[u32]) -> Option<AccessRole> {
+        if uid == 0 {
+            return Some(AccessRole::Admin);
+        }
+        if !self.enabled || self.control_uids.contains(&uid) {
+            return Some(AccessRole::Control);
+        }
+        if gids.iter().any(|gid| self.control_gids.contains(gid)) {
+            return Some(AccessRole::Control);
+        }
+        if self.read_only_gids.is_empty()
+            || gids.iter().any(|gid| self.read_only_gids.contains(gid))
+        {
+            return Some(AccessRole::ReadOnly);
+        }
+        None
+    }
+}
+
+impl fmt::Display for ManagementAccessPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn join(ids: &[u32]) -> String {
+            if ids.is_empty() {
+                return "none".to_owned();
+            }
+            ids.iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+
+        if !self.enabled {
+            return write!(f, "unrestricted");
+        }
+        write!(
+            f,
+            "control users: {}, control groups: {}, read-only groups: {}",
+            join(&self.control_uids),
+            join(&self.control_gids),
+            if self.read_only_gids.is_empty() {
+                "everyone".to_owned()
+            } else {
+                join(&self.read_only_gids)
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_access_roles() {
+        let policy = ManagementAccessPolicy {
+            enabled: true,
+            control_uids: vec![1000],
+            control_gids: vec![27],
+            read_only_gids: vec![],
+        };
+        assert_eq!(policy.role(0, &[0]), Some(AccessRole::Admin));
+        assert_eq!(policy.role(1000, &[1000]), Some(AccessRole::Control));
+        assert_eq!(policy.role(1001, &[1001, 27]), Some(AccessRole::Control));
+        assert_eq!(policy.role(1002, &[1002]), Some(AccessRole::ReadOnly));
+
+        let policy = ManagementAccessPolicy {
+            read_only_gids: vec![100],
+            ..policy
+        };
+        assert_eq!(policy.role(1002, &[1002, 100]), Some(AccessRole::ReadOnly));
+        assert_eq!(policy.role(1003, &[1003]), None);
+
+        let policy = ManagementAccessPolicy::default();
+        assert_eq!(policy.role(0, &[0]), Some(AccessRole::Admin));
+        assert_eq!(policy.role(1003, &[1003]), Some(AccessRole::Control));
+    }
+}
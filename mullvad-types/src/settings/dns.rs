@@ -1,7 +1,7 @@
 #[cfg(target_os = "android")]
 use jnix::{FromJava, IntoJava};
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
 #[serde(rename_all = "snake_case")]
@@ -42,5 +42,29 @@ pub struct DefaultDnsOptions {
 #[cfg_attr(target_os = "android", derive(FromJava, IntoJava))]
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
 pub struct CustomDnsOptions {
+    /// Resolvers in order of preference. Later resolvers are only used if earlier ones fail.
+    /// IPv6 resolvers are skipped while the tunnel has no IPv6 address. Windows keeps a separate
+    /// list per IP version, so there the order is only kept among resolvers of the same version.
     pub addresses: Vec<IpAddr>,
 }
+
+impl CustomDnsOptions {
+    /// Returns an error if any of the resolvers can't be sent queries.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        self.addresses
+            .iter()
+            .try_for_each(|address| validate_server(*address))
+    }
+}
+
+/// Returns an error if `address` is the unspecified address, the IPv4 broadcast address or a
+/// multicast address, since queries can't be sent to those.
+pub(super) fn validate_server(address: IpAddr) -> Result<(), &'static str> {
+    if address.is_unspecified() {
+        Err("DNS server address must not be unspecified")
+    } else if address.is_multicast() || address == IpAddr::V4(Ipv4Addr::BROADCAST) {
+        Err("DNS server address must be a unicast address")
+    } else {
+        Ok(())
+    }
+}
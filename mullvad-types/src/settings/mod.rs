@@ -1,8 +1,11 @@
 use crate::{
+    access_method::AccessMethod,
+    auto_connect::{AutoConnectPolicy, BackgroundWorkPolicy},
+    network::NetworkProfile,
     relay_constraints::{
         BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
-        ObfuscationSettings, RelayConstraints, RelaySettings, RelaySettingsUpdate,
-        SelectedObfuscation, WireguardConstraints,
+        ObfuscationSettings, PortSelectionStep, RelayConstraints, RelaySettings,
+        RelaySettingsUpdate, SelectedObfuscation, WireguardConstraints,
     },
     wireguard,
 };
@@ -10,10 +13,15 @@ use crate::{
 use jnix::IntoJava;
 use rand::Rng;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::net::IpAddr;
 #[cfg(target_os = "windows")]
 use std::{collections::HashSet, path::PathBuf};
-use talpid_types::net::{self, openvpn, GenericTunnelOptions};
+#[cfg(target_os = "linux")]
+use talpid_types::cgroup::SplitTunnelMode;
+use talpid_types::net::{self, openvpn, AllowedLinkConfigTraffic, GenericTunnelOptions};
+use talpid_types::tunnel::ConnectionTimeouts;
 
+mod access;
 mod dns;
 
 /// The version used by the current version of the code. Should always be the
@@ -73,22 +81,75 @@ pub struct Settings {
     pub obfuscation_settings: ObfuscationSettings,
     #[cfg_attr(target_os = "android", jnix(skip))]
     bridge_state: BridgeState,
+    /// Order in which tunnel protocols and ports are tried when they are not constrained. If empty,
+    /// the built-in order is used.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub auto_port_order: Vec<PortSelectionStep>,
     /// If the daemon should allow communication with private (LAN) networks.
     pub allow_lan: bool,
+    /// Multicast groups that may be used while connected, whether or not `allow_lan` is set.
+    /// Only supported on Linux and macOS.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub allowed_multicast_groups: Vec<IpAddr>,
+    /// DHCP and NDP traffic that the firewall allows on all interfaces, in every state.
+    /// Only supported on Linux and macOS.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub allowed_link_config_traffic: AllowedLinkConfigTraffic,
+    /// Hosts on the local network that may be communicated with even if `allow_lan` is not set.
+    /// Only supported on Linux and macOS.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub allowed_lan_hosts: Vec<IpAddr>,
     /// Extra level of kill switch. When this setting is on, the disconnected state will block
     /// the firewall to not allow any traffic in or out.
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub block_when_disconnected: bool,
     /// If the daemon should connect the VPN tunnel directly on start or not.
     pub auto_connect: bool,
+    /// Refines when to connect automatically, if `auto_connect` is enabled.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub auto_connect_policy: AutoConnectPolicy,
+    /// Connection parameters to use on specific networks. The first profile matching the current
+    /// network is applied.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub network_profiles: Vec<NetworkProfile>,
     /// Options that should be applied to tunnels of a specific type regardless of where the relays
     /// might be located.
     pub tunnel_options: TunnelOptions,
     /// Whether to notify users of beta updates.
     pub show_beta_releases: bool,
+    /// Whether the daemon may contact the API to check for new app versions.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub check_for_updates: bool,
+    /// Whether the daemon may look up the location of the device using the GeoIP service. If
+    /// not, only the location of the relay is known.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub fetch_location: bool,
+    /// On which networks the daemon may download the relay list and version information
+    /// periodically.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub background_work: BackgroundWorkPolicy,
+    /// Whether the daemon shows notifications through the OS itself, for when the GUI is not
+    /// running.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub system_notifications: bool,
+    /// Which local users may use the management interface, and for what.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub management_access: ManagementAccessPolicy,
+    /// Proxies added by the user for reaching the API. Enabled ones are tried before bridges when
+    /// the API cannot be reached.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub api_access_methods: Vec<AccessMethod>,
     /// Split tunneling settings
     #[cfg(windows)]
     pub split_tunnel: SplitTunnelSettings,
+    /// Whether processes in the split tunneling group are excluded from the tunnel, or are the
+    /// only processes that use it.
+    #[cfg(target_os = "linux")]
+    pub split_tunnel_mode: SplitTunnelMode,
+    /// Network interface whose devices may use the tunnel through this device, which then acts
+    /// as their gateway. `None` if the tunnel is not shared.
+    #[cfg(target_os = "linux")]
+    pub shared_interface: Option<String>,
     /// Temporary variable for a random number between 0 and 1 that determines if the user should
     /// use wireguard or openvpn when the automatic feature is set. This variable will be removed
     /// in future versions.
@@ -132,14 +193,30 @@ impl Default for Settings {
                 ..Default::default()
             },
             bridge_state: BridgeState::Auto,
+            auto_port_order: Vec::new(),
             allow_lan: false,
+            allowed_multicast_groups: vec![],
+            allowed_link_config_traffic: AllowedLinkConfigTraffic::default(),
+            allowed_lan_hosts: vec![],
             block_when_disconnected: false,
             auto_connect: false,
+            auto_connect_policy: AutoConnectPolicy::default(),
+            network_profiles: vec![],
             tunnel_options: TunnelOptions::default(),
             show_beta_releases: false,
+            check_for_updates: true,
+            fetch_location: true,
+            background_work: BackgroundWorkPolicy::default(),
+            system_notifications: false,
+            management_access: ManagementAccessPolicy::default(),
+            api_access_methods: vec![],
             wg_migration_rand_num: rand::thread_rng().gen_range(0.0..=1.0),
             #[cfg(windows)]
             split_tunnel: SplitTunnelSettings::default(),
+            #[cfg(target_os = "linux")]
+            split_tunnel_mode: SplitTunnelMode::default(),
+            #[cfg(target_os = "linux")]
+            shared_interface: None,
             settings_version: CURRENT_SETTINGS_VERSION,
         }
     }
@@ -151,12 +228,8 @@ impl Settings {
     }
 
     pub fn update_relay_settings(&mut self, update: RelaySettingsUpdate) -> bool {
-        let update_supports_bridge = update.supports_bridge();
         let new_settings = self.relay_settings.merge(update);
         if self.relay_settings != new_settings {
-            if !update_supports_bridge && BridgeState::On == self.bridge_state {
-                self.bridge_state = BridgeState::Auto;
-            }
             log::debug!(
                 "Changing relay settings:\n\tfrom: {}\n\tto: {}",
                 self.relay_settings,
@@ -183,9 +256,199 @@ impl Settings {
         }
     }
 
+    /// Returns whether bridge mode is on while the relay settings require UDP. Bridges only
+    /// proxy TCP, so the two cannot be combined.
+    pub fn has_incompatible_bridge_state(&self) -> bool {
+        self.bridge_state == BridgeState::On && !self.relay_settings.supports_bridge()
+    }
+
+    /// Returns whether split tunneling is in include mode while `block_when_disconnected` is set.
+    /// Include mode lets the traffic of every other process bypass the tunnel, which would break
+    /// the guarantee that no traffic leaves the device outside of the tunnel.
+    pub fn has_incompatible_split_tunnel_mode(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        {
+            self.block_when_disconnected && self.split_tunnel_mode == SplitTunnelMode::Include
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            false
+        }
+    }
+
+    /// Resets values that the daemon would never have saved, such as those from a settings file
+    /// that was edited by hand. Returns whether anything changed.
+    pub fn sanitize(&mut self) -> bool {
+        let mut changed = false;
+        #[cfg(target_os = "linux")]
+        if self.has_incompatible_split_tunnel_mode() {
+            log::warn!(
+                "Switching split tunneling to exclude mode since \"Always require VPN\" is on"
+            );
+            self.split_tunnel_mode = SplitTunnelMode::Exclude;
+            changed = true;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(interface) = &self.shared_interface {
+            if !net::is_valid_interface_name(interface) {
+                log::warn!(
+                    "Not sharing the tunnel with invalid interface \"{}\"",
+                    interface
+                );
+                self.shared_interface = None;
+                changed = true;
+            }
+        }
+        let hosts_len = self.allowed_lan_hosts.len();
+        self.allowed_lan_hosts.retain(|host| {
+            let is_lan = net::is_lan_address(host);
+            if !is_lan {
+                log::warn!(
+                    "Removing allowed LAN host {} outside the local network",
+                    host
+                );
+            }
+            is_lan
+        });
+        changed |= self.allowed_lan_hosts.len() != hosts_len;
+        let dns_servers = &mut self.tunnel_options.dns_options.custom_options.addresses;
+        let dns_servers_len = dns_servers.len();
+        dns_servers.retain(|server| match dns::validate_server(*server) {
+            Ok(()) => true,
+            Err(error) => {
+                log::warn!("Removing custom DNS server {}: {}", server, error);
+                false
+            }
+        });
+        changed |= dns_servers.len() != dns_servers_len;
+        if let Err(error) = self.tunnel_options.connection_timeouts.validate() {
+            log::warn!("Resetting invalid connection timeouts: {}", error);
+            self.tunnel_options.connection_timeouts = ConnectionTimeouts::default();
+            changed = true;
+        }
+        changed
+    }
+
     pub fn get_settings_version(&self) -> SettingsVersion {
         self.settings_version
     }
+
+    /// Applies every change in `update` and returns which settings actually changed.
+    pub fn apply_update(&mut self, update: SettingsUpdate) -> SettingsChanges {
+        fn set<T: PartialEq>(field: &mut T, value: Option<T>) -> bool {
+            match value {
+                Some(value) if *field != value => {
+                    *field = value;
+                    true
+                }
+                _ => false,
+            }
+        }
+
+        SettingsChanges {
+            relay_settings: update
+                .relay_settings
+                .map(|update| self.update_relay_settings(update))
+                .unwrap_or(false),
+            bridge_state: update
+                .bridge_state
+                .map(|state| self.set_bridge_state(state))
+                .unwrap_or(false),
+            obfuscation_settings: set(&mut self.obfuscation_settings, update.obfuscation_settings),
+            allow_lan: set(&mut self.allow_lan, update.allow_lan),
+            block_when_disconnected: set(
+                &mut self.block_when_disconnected,
+                update.block_when_disconnected,
+            ),
+            auto_connect: set(&mut self.auto_connect, update.auto_connect),
+            show_beta_releases: set(&mut self.show_beta_releases, update.show_beta_releases),
+            check_for_updates: set(&mut self.check_for_updates, update.check_for_updates),
+            fetch_location: set(&mut self.fetch_location, update.fetch_location),
+            background_work: set(&mut self.background_work, update.background_work),
+            enable_ipv6: set(
+                &mut self.tunnel_options.generic.enable_ipv6,
+                update.enable_ipv6,
+            ),
+            dns_options: set(&mut self.tunnel_options.dns_options, update.dns_options),
+            openvpn_mssfix: set(
+                &mut self.tunnel_options.openvpn.mssfix,
+                update.openvpn_mssfix,
+            ),
+            wireguard_mtu: set(
+                &mut self.tunnel_options.wireguard.options.mtu,
+                update.wireguard_mtu,
+            ),
+            quantum_resistant: set(
+                &mut self.tunnel_options.wireguard.options.use_pq_safe_psk,
+                update.quantum_resistant,
+            ),
+            connection_timeouts: set(
+                &mut self.tunnel_options.connection_timeouts,
+                update.connection_timeouts,
+            ),
+        }
+    }
+}
+
+/// A set of changes that are applied to [`Settings`] together. Fields that are `None` are left
+/// unchanged.
+#[derive(Debug, Default)]
+pub struct SettingsUpdate {
+    pub relay_settings: Option<RelaySettingsUpdate>,
+    pub bridge_state: Option<BridgeState>,
+    pub obfuscation_settings: Option<ObfuscationSettings>,
+    pub allow_lan: Option<bool>,
+    pub block_when_disconnected: Option<bool>,
+    pub auto_connect: Option<bool>,
+    pub show_beta_releases: Option<bool>,
+    pub check_for_updates: Option<bool>,
+    pub fetch_location: Option<bool>,
+    pub background_work: Option<BackgroundWorkPolicy>,
+    pub enable_ipv6: Option<bool>,
+    pub dns_options: Option<DnsOptions>,
+    /// `Some(None)` resets the mssfix to the default.
+    pub openvpn_mssfix: Option<Option<u16>>,
+    /// `Some(None)` resets the MTU to the default.
+    pub wireguard_mtu: Option<Option<u16>>,
+    pub quantum_resistant: Option<bool>,
+    pub connection_timeouts: Option<ConnectionTimeouts>,
+}
+
+/// Describes which settings were changed by [`Settings::apply_update`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SettingsChanges {
+    pub relay_settings: bool,
+    pub bridge_state: bool,
+    pub obfuscation_settings: bool,
+    pub allow_lan: bool,
+    pub block_when_disconnected: bool,
+    pub auto_connect: bool,
+    pub show_beta_releases: bool,
+    pub check_for_updates: bool,
+    pub fetch_location: bool,
+    pub background_work: bool,
+    pub enable_ipv6: bool,
+    pub dns_options: bool,
+    pub openvpn_mssfix: bool,
+    pub wireguard_mtu: bool,
+    pub quantum_resistant: bool,
+    pub connection_timeouts: bool,
+}
+
+impl SettingsChanges {
+    /// Returns whether any setting changed.
+    pub fn any(&self) -> bool {
+        *self != SettingsChanges::default()
+    }
+
+    /// Returns whether the tunnel options changed.
+    pub fn tunnel_options(&self) -> bool {
+        self.enable_ipv6
+            || self.dns_options
+            || self.openvpn_mssfix
+            || self.wireguard_mtu
+            || self.quantum_resistant
+    }
 }
 
 /// TunnelOptions holds configuration data that applies to all kinds of tunnels.
@@ -204,8 +467,12 @@ pub struct TunnelOptions {
     pub generic: GenericTunnelOptions,
     /// DNS options.
     pub dns_options: DnsOptions,
+    /// Timing of connection attempts.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub connection_timeouts: ConnectionTimeouts,
 }
 
+pub use access::{AccessRole, ManagementAccessPolicy};
 pub use dns::{CustomDnsOptions, DefaultDnsOptions, DnsOptions, DnsState};
 
 impl Default for TunnelOptions {
@@ -221,6 +488,157 @@ impl Default for TunnelOptions {
                 enable_ipv6: cfg!(target_os = "android"),
             },
             dns_options: DnsOptions::default(),
+            connection_timeouts: ConnectionTimeouts::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply_update_reports_effective_changes() {
+        let mut settings = Settings::default();
+        let changes = settings.apply_update(SettingsUpdate {
+            allow_lan: Some(true),
+            auto_connect: Some(settings.auto_connect),
+            wireguard_mtu: Some(Some(1280)),
+            ..Default::default()
+        });
+
+        assert!(settings.allow_lan);
+        assert_eq!(settings.tunnel_options.wireguard.options.mtu, Some(1280));
+        assert_eq!(
+            changes,
+            SettingsChanges {
+                allow_lan: true,
+                wireguard_mtu: true,
+                ..Default::default()
+            }
+        );
+        assert!(changes.tunnel_options());
+        assert!(!settings.apply_update(SettingsUpdate::default()).any());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_sanitize_shared_interface() {
+        let mut settings = Settings::default();
+        assert!(!settings.sanitize());
+
+        settings.shared_interface = Some("eth1".to_owned());
+        assert!(!settings.sanitize());
+        assert_eq!(settings.shared_interface.as_deref(), Some("eth1"));
+
+        settings.shared_interface = Some("../all".to_owned());
+        assert!(settings.sanitize());
+        assert_eq!(settings.shared_interface, None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_sanitize_split_tunnel_mode() {
+        let mut settings = Settings {
+            split_tunnel_mode: SplitTunnelMode::Include,
+            ..Settings::default()
+        };
+        assert!(!settings.sanitize());
+
+        settings.block_when_disconnected = true;
+        assert!(settings.has_incompatible_split_tunnel_mode());
+        assert!(settings.sanitize());
+        assert_eq!(settings.split_tunnel_mode, SplitTunnelMode::Exclude);
+        assert!(!settings.has_incompatible_split_tunnel_mode());
+    }
+
+    #[test]
+    fn test_sanitize_allowed_lan_hosts() {
+        let mut settings = Settings::default();
+        let printer: IpAddr = "192.168.1.20".parse().unwrap();
+        settings.allowed_lan_hosts = vec![printer, "8.8.8.8".parse().unwrap()];
+        assert!(settings.sanitize());
+        assert_eq!(settings.allowed_lan_hosts, vec![printer]);
+        assert!(!settings.sanitize());
+    }
+
+    #[test]
+    fn test_sanitize_connection_timeouts() {
+        let sanitize = |connecting_timeout_secs, initial_retry_delay_secs| {
+            let mut settings = Settings::default();
+            settings.tunnel_options.connection_timeouts = ConnectionTimeouts {
+                connecting_timeout_secs,
+                initial_retry_delay_secs,
+                max_retry_delay_secs: 5,
+                ..ConnectionTimeouts::default()
+            };
+            let changed = settings.sanitize();
+            (changed, settings.tunnel_options.connection_timeouts)
+        };
+
+        assert!(!sanitize(0, 5).0);
+        assert!(!sanitize(4, 1).0);
+        assert_eq!(sanitize(30, 0), (true, ConnectionTimeouts::default()));
+        assert_eq!(sanitize(1, 1), (true, ConnectionTimeouts::default()));
+        assert_eq!(sanitize(30, 10), (true, ConnectionTimeouts::default()));
+
+        let mut settings = Settings::default();
+        settings
+            .tunnel_options
+            .connection_timeouts
+            .offline_debounce_ms = ConnectionTimeouts::MAX_OFFLINE_DEBOUNCE_MS + 1;
+        assert!(settings.sanitize());
+        assert_eq!(
+            settings.tunnel_options.connection_timeouts,
+            ConnectionTimeouts::default()
+        );
+    }
+
+    #[test]
+    fn test_sanitize_custom_dns_servers() {
+        let resolver = "192.0.2.53".parse().unwrap();
+        let mut settings = Settings::default();
+        settings.tunnel_options.dns_options.custom_options.addresses = vec![
+            "0.0.0.0".parse().unwrap(),
+            resolver,
+            "255.255.255.255".parse().unwrap(),
+            "ff02::1".parse().unwrap(),
+        ];
+
+        assert!(settings.sanitize());
+        assert_eq!(
+            settings.tunnel_options.dns_options.custom_options.addresses,
+            vec![resolver]
+        );
+        assert!(!settings.sanitize());
+    }
+
+    #[test]
+    fn test_incompatible_bridge_state() {
+        use crate::relay_constraints::{OpenVpnConstraints, RelayConstraintsUpdate, TransportPort};
+        use talpid_types::net::TransportProtocol;
+
+        let openvpn_protocol = |protocol| {
+            RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+                openvpn_constraints: Some(OpenVpnConstraints {
+                    port: Constraint::Only(TransportPort {
+                        protocol,
+                        port: Constraint::Any,
+                    }),
+                }),
+                ..Default::default()
+            })
+        };
+
+        let mut settings = Settings::default();
+        settings.set_bridge_state(BridgeState::On);
+        assert!(!settings.has_incompatible_bridge_state());
+
+        settings.update_relay_settings(openvpn_protocol(TransportProtocol::Udp));
+        assert_eq!(settings.get_bridge_state(), BridgeState::On);
+        assert!(settings.has_incompatible_bridge_state());
+
+        settings.update_relay_settings(openvpn_protocol(TransportProtocol::Tcp));
+        assert!(!settings.has_incompatible_bridge_state());
+    }
+}
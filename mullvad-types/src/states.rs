@@ -5,7 +5,10 @@ use serde::{Deserialize, Serialize};
 use std::fmt;
 use talpid_types::{
     net::TunnelEndpoint,
-    tunnel::{ActionAfterDisconnect, ErrorState},
+    tunnel::{
+        ActionAfterDisconnect, ConnectingPhase, DisconnectReason, ErrorState, ReconnectReason,
+        TunnelHealth,
+    },
 };
 
 /// Represents the state the client strives towards.
@@ -34,14 +37,29 @@ impl fmt::Display for TargetState {
 #[cfg_attr(target_os = "android", derive(IntoJava))]
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
 pub enum TunnelState {
-    Disconnected,
+    Disconnected {
+        /// Why the tunnel was disconnected. Not set for the initial state.
+        #[serde(default)]
+        reason: Option<DisconnectReason>,
+    },
     Connecting {
         endpoint: TunnelEndpoint,
         location: Option<GeoIpLocation>,
+        /// Set if the tunnel is being reconnected automatically, or on request.
+        #[cfg_attr(target_os = "android", jnix(skip))]
+        #[serde(default)]
+        reconnect_reason: Option<ReconnectReason>,
+        /// How far the connection attempt has progressed.
+        #[cfg_attr(target_os = "android", jnix(skip))]
+        #[serde(default)]
+        phase: ConnectingPhase,
     },
     Connected {
         endpoint: TunnelEndpoint,
         location: Option<GeoIpLocation>,
+        /// Whether the tunnel is working well, as measured by the connectivity monitor.
+        #[serde(default)]
+        health: TunnelHealth,
     },
     Disconnecting(ActionAfterDisconnect),
     Error(ErrorState),
@@ -60,6 +78,6 @@ impl TunnelState {
 
     /// Returns true if the tunnel state is in the disconnected state.
     pub fn is_disconnected(&self) -> bool {
-        matches!(self, TunnelState::Disconnected)
+        matches!(self, TunnelState::Disconnected { .. })
     }
 }
@@ -3,8 +3,8 @@ use chrono::{offset::Utc, DateTime};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Deserializer, Serialize};
-use std::{convert::TryFrom, fmt, time::Duration};
-use talpid_types::net::wireguard;
+use std::{convert::TryFrom, fmt, net::IpAddr, time::Duration};
+use talpid_types::net::{wireguard, IpVersion};
 
 pub const MIN_ROTATION_INTERVAL: Duration = Duration::from_secs(1 * 24 * 60 * 60);
 pub const MAX_ROTATION_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
@@ -147,3 +147,16 @@ pub struct AssociatedAddresses {
     pub ipv4_address: ipnetwork::Ipv4Network,
     pub ipv6_address: ipnetwork::Ipv6Network,
 }
+
+impl AssociatedAddresses {
+    /// Returns the in-tunnel addresses to assign to the tunnel interface. The IPv6 address is
+    /// left out if `address_family` is IPv4. The IPv4 address is always included, since the
+    /// services on the relay are only reachable over IPv4.
+    pub fn tunnel_ips(&self, address_family: Option<IpVersion>) -> Vec<IpAddr> {
+        let mut addresses = vec![self.ipv4_address.ip().into()];
+        if address_family != Some(IpVersion::V4) {
+            addresses.push(self.ipv6_address.ip().into());
+        }
+        addresses
+    }
+}
@@ -0,0 +1,120 @@
+#[cfg(target_os = "linux")]
+use crate::offline::{PUBLIC_INTERNET_ADDRESS_V4, PUBLIC_INTERNET_ADDRESS_V6};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use talpid_routing::Node;
+#[cfg(target_os = "linux")]
+use talpid_routing::RouteManagerHandle;
+use talpid_types::{net::DefaultRoute, ErrorExt};
+
+/// Handle used to look up the current default routes.
+#[derive(Clone)]
+pub struct DefaultRouteHandle {
+    #[cfg(target_os = "linux")]
+    route_manager: RouteManagerHandle,
+    #[cfg(target_os = "linux")]
+    fwmark: u32,
+}
+
+impl DefaultRouteHandle {
+    pub(crate) fn new(
+        #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
+        #[cfg(target_os = "linux")] fwmark: u32,
+    ) -> Self {
+        DefaultRouteHandle {
+            #[cfg(target_os = "linux")]
+            route_manager,
+            #[cfg(target_os = "linux")]
+            fwmark,
+        }
+    }
+
+    /// Returns the IPv4 and IPv6 default routes, in that order. A route is `None` if it does not
+    /// exist or could not be determined.
+    pub async fn default_routes(&self) -> (Option<DefaultRoute>, Option<DefaultRoute>) {
+        self.get_default_routes().await.unwrap_or_else(|error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to obtain default routes")
+            );
+            (None, None)
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn get_default_routes(
+        &self,
+    ) -> Result<(Option<DefaultRoute>, Option<DefaultRoute>), talpid_routing::Error> {
+        let v4_route = self
+            .route_manager
+            .get_destination_route(PUBLIC_INTERNET_ADDRESS_V4, Some(self.fwmark))
+            .await?;
+        let v6_route = self
+            .route_manager
+            .get_destination_route(PUBLIC_INTERNET_ADDRESS_V6, Some(self.fwmark))
+            .await?;
+        Ok((
+            v4_route.and_then(|route| route_from_node(route.get_node())),
+            v6_route.and_then(|route| route_from_node(route.get_node())),
+        ))
+    }
+
+    #[cfg(target_os = "macos")]
+    async fn get_default_routes(
+        &self,
+    ) -> Result<(Option<DefaultRoute>, Option<DefaultRoute>), talpid_routing::Error> {
+        let (v4_node, v6_node) = talpid_routing::get_default_routes().await?;
+        Ok((
+            v4_node.as_ref().and_then(route_from_node),
+            v6_node.as_ref().and_then(route_from_node),
+        ))
+    }
+
+    #[cfg(target_os = "windows")]
+    async fn get_default_routes(
+        &self,
+    ) -> Result<(Option<DefaultRoute>, Option<DefaultRoute>), talpid_routing::Error> {
+        use talpid_windows_net::AddressFamily;
+
+        let v4_route = talpid_routing::get_best_default_route(AddressFamily::Ipv4)?;
+        let v6_route = talpid_routing::get_best_default_route(AddressFamily::Ipv6)?;
+        Ok((
+            v4_route.and_then(route_from_interface_and_gateway),
+            v6_route.and_then(route_from_interface_and_gateway),
+        ))
+    }
+
+    /// The routes are managed by the system on Android, so they are never reported.
+    #[cfg(target_os = "android")]
+    async fn get_default_routes(
+        &self,
+    ) -> Result<(Option<DefaultRoute>, Option<DefaultRoute>), talpid_routing::Error> {
+        Ok((None, None))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn route_from_node(node: &Node) -> Option<DefaultRoute> {
+    node.get_device().map(|interface| DefaultRoute {
+        interface: interface.to_owned(),
+        gateway: node.get_address(),
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn route_from_interface_and_gateway(
+    route: talpid_routing::InterfaceAndGateway,
+) -> Option<DefaultRoute> {
+    match talpid_windows_net::alias_from_luid(&route.iface) {
+        Ok(alias) => Some(DefaultRoute {
+            interface: alias.to_string_lossy().into_owned(),
+            gateway: Some(route.gateway.ip()),
+        }),
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to obtain alias of default interface")
+            );
+            None
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::net::IpAddr;
+use talpid_types::net::DnsBackendHealth;
 
 /// Stub error type for DNS errors on Android.
 #[derive(Debug, err_derive::Error)]
@@ -22,3 +23,13 @@ impl super::DnsMonitorT for DnsMonitor {
         Ok(())
     }
 }
+
+/// DNS is configured through the VPN service, so there is no separate backend to check.
+pub fn check_backend() -> DnsBackendHealth {
+    DnsBackendHealth {
+        backend: "VpnService".to_owned(),
+        error: None,
+        resolvers: vec![],
+        conflicting_managers: vec![],
+    }
+}
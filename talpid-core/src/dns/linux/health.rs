@@ -0,0 +1,249 @@
+use super::{
+    network_manager::NetworkManager,
+    resolvconf::Resolvconf,
+    systemd_resolved::{self, SystemdDbusError, SystemdResolved},
+};
+use std::{env, fs, net::IpAddr};
+use talpid_types::{
+    net::{DnsBackendHealth, InterfaceResolvers},
+    ErrorExt,
+};
+
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/// Names of programs that may generate `/etc/resolv.conf`, along with the text that they leave in
+/// its header.
+const RESOLV_CONF_GENERATORS: &[(&str, &str)] = &[
+    ("systemd-resolved", "systemd-resolved"),
+    ("NetworkManager", "NetworkManager"),
+    ("resolvconf", "resolvconf"),
+    ("dhcpcd", "dhcpcd"),
+    ("ConnMan", "Connection Manager"),
+    ("netconfig", "netconfig"),
+];
+
+/// Daemons that overwrite `/etc/resolv.conf` directly unless they are configured not to.
+const RESOLV_CONF_WRITERS: &[&str] = &["dhclient", "dhcpcd", "connmand"];
+
+/// D-Bus errors that mean that a service exists but did not reply in time.
+const DBUS_TIMEOUT_ERRORS: &[&str] = &[
+    "org.freedesktop.DBus.Error.NoReply",
+    "org.freedesktop.DBus.Error.Timeout",
+    "org.freedesktop.DBus.Error.TimedOut",
+];
+
+enum Backend {
+    SystemdResolved(Option<SystemdResolved>),
+    NetworkManager,
+    Resolvconf,
+    StaticResolvConf,
+}
+
+impl Backend {
+    fn name(&self) -> &'static str {
+        match self {
+            Backend::SystemdResolved(..) => "systemd-resolved",
+            Backend::NetworkManager => "network manager",
+            Backend::Resolvconf => "resolvconf",
+            Backend::StaticResolvConf => RESOLV_CONF_PATH,
+        }
+    }
+
+    /// Name of the program in [RESOLV_CONF_GENERATORS] that this backend writes
+    /// `/etc/resolv.conf` through, if any.
+    fn generator(&self) -> Option<&'static str> {
+        match self {
+            Backend::SystemdResolved(..) => Some("systemd-resolved"),
+            Backend::NetworkManager => Some("NetworkManager"),
+            Backend::Resolvconf => Some("resolvconf"),
+            Backend::StaticResolvConf => None,
+        }
+    }
+}
+
+/// Finds the backend that the DNS monitor would use, the same way that it does, and checks
+/// whether it responds.
+fn probe_backend() -> (Backend, Option<String>) {
+    let dns_module = env::var_os("TALPID_DNS_MODULE");
+
+    match dns_module.as_ref().and_then(|value| value.to_str()) {
+        Some("static-file") => (Backend::StaticResolvConf, probe_resolv_conf().err()),
+        Some("resolvconf") => (
+            Backend::Resolvconf,
+            Resolvconf::new().err().map(|error| error.display_chain()),
+        ),
+        Some("systemd") => match SystemdResolved::new() {
+            Ok(resolved) => (Backend::SystemdResolved(Some(resolved)), None),
+            Err(error) => (Backend::SystemdResolved(None), Some(error.display_chain())),
+        },
+        Some("network-manager") => (
+            Backend::NetworkManager,
+            NetworkManager::new()
+                .err()
+                .map(|error| error.display_chain()),
+        ),
+        Some(_) | None => detect_backend(),
+    }
+}
+
+fn detect_backend() -> (Backend, Option<String>) {
+    match SystemdResolved::new() {
+        Ok(resolved) => return (Backend::SystemdResolved(Some(resolved)), None),
+        // The DNS monitor falls back on other backends in this case too, but it most likely
+        // means that systemd-resolved is in use and has stopped working.
+        Err(systemd_resolved::Error::SystemdResolvedError(
+            SystemdDbusError::NoSystemdResolved(error),
+        )) if error
+            .name()
+            .map(|name| DBUS_TIMEOUT_ERRORS.contains(&name))
+            .unwrap_or(false) =>
+        {
+            return (
+                Backend::SystemdResolved(None),
+                Some("systemd-resolved did not respond".to_owned()),
+            );
+        }
+        Err(_) => (),
+    }
+    if NetworkManager::new().is_ok() {
+        return (Backend::NetworkManager, None);
+    }
+    if Resolvconf::new().is_ok() {
+        return (Backend::Resolvconf, None);
+    }
+    (Backend::StaticResolvConf, probe_resolv_conf().err())
+}
+
+fn probe_resolv_conf() -> Result<resolv_conf::Config, String> {
+    let contents = fs::read_to_string(RESOLV_CONF_PATH)
+        .map_err(|error| error.display_chain_with_msg("Failed to read /etc/resolv.conf"))?;
+    resolv_conf::Config::parse(contents)
+        .map_err(|error| error.display_chain_with_msg("Failed to parse /etc/resolv.conf"))
+}
+
+/// Returns the resolvers that systemd-resolved has for each interface.
+fn resolved_link_resolvers(resolved: &SystemdResolved) -> Vec<InterfaceResolvers> {
+    let interfaces = match nix::net::if_::if_nameindex() {
+        Ok(interfaces) => interfaces,
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to list network interfaces")
+            );
+            return vec![];
+        }
+    };
+    interfaces
+        .iter()
+        .filter_map(|interface| {
+            let state = resolved
+                .dbus_interface
+                .handle()
+                .get_dns(interface.index())
+                .ok()?;
+            if state.set_servers.is_empty() {
+                return None;
+            }
+            Some(InterfaceResolvers {
+                interface: interface.name().to_string_lossy().into_owned(),
+                servers: state.set_servers,
+            })
+        })
+        .collect()
+}
+
+/// Returns the programs other than `backend` that have generated `/etc/resolv.conf`, or that
+/// are running and may overwrite it.
+fn conflicting_managers(backend: &Backend, resolv_conf_header: &[String]) -> Vec<String> {
+    let mut managers: Vec<String> = RESOLV_CONF_GENERATORS
+        .iter()
+        .filter(|(name, _)| Some(*name) != backend.generator())
+        .filter(|(_, marker)| resolv_conf_header.iter().any(|line| line.contains(marker)))
+        .map(|(name, _)| name.to_string())
+        .collect();
+
+    // Other backends are only affected if these programs are misconfigured, so only check for
+    // them when the file is written directly.
+    if let Backend::StaticResolvConf = backend {
+        for writer in running_processes()
+            .into_iter()
+            .filter(|process| RESOLV_CONF_WRITERS.contains(&process.as_str()))
+        {
+            if !managers.contains(&writer) {
+                managers.push(writer);
+            }
+        }
+    }
+    managers
+}
+
+fn running_processes() -> Vec<String> {
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let comm = fs::read_to_string(path.join("comm")).ok()?;
+            Some(comm.trim().to_owned())
+        })
+        .collect()
+}
+
+/// Returns the comment lines at the top of `/etc/resolv.conf`.
+fn resolv_conf_header() -> Vec<String> {
+    fs::read_to_string(RESOLV_CONF_PATH)
+        .map(|contents| {
+            contents
+                .lines()
+                .take_while(|line| line.trim().is_empty() || line.starts_with('#'))
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn check_backend() -> DnsBackendHealth {
+    let (backend, error) = probe_backend();
+
+    let mut resolvers = match &backend {
+        Backend::SystemdResolved(Some(resolved)) => resolved_link_resolvers(resolved),
+        _ => vec![],
+    };
+    if let Ok(config) = probe_resolv_conf() {
+        resolvers.push(InterfaceResolvers {
+            interface: RESOLV_CONF_PATH.to_owned(),
+            servers: config
+                .nameservers
+                .into_iter()
+                .map(|server| -> IpAddr { server.into() })
+                .collect(),
+        });
+    }
+
+    DnsBackendHealth {
+        backend: backend.name().to_owned(),
+        error,
+        resolvers,
+        conflicting_managers: conflicting_managers(&backend, &resolv_conf_header()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_conflicting_managers() {
+        let header = vec![
+            "# Generated by NetworkManager".to_owned(),
+            "# Do not edit".to_owned(),
+        ];
+        assert!(conflicting_managers(&Backend::NetworkManager, &header).is_empty());
+        assert_eq!(
+            conflicting_managers(&Backend::Resolvconf, &header),
+            vec!["NetworkManager".to_owned()]
+        );
+    }
+}
@@ -1,3 +1,4 @@
+mod health;
 mod network_manager;
 mod resolvconf;
 mod static_resolv_conf;
@@ -10,6 +11,8 @@ use self::{
 use std::{env, fmt, net::IpAddr};
 use talpid_routing::RouteManagerHandle;
 
+pub use health::check_backend;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Errors that can happen in the Linux DNS monitor
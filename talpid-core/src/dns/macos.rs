@@ -1,7 +1,7 @@
 use futures::channel::mpsc;
 use parking_lot::Mutex;
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::HashMap,
     fmt,
     net::{AddrParseError, IpAddr},
     sync::{mpsc as sync_mpsc, Arc, Weak},
@@ -21,7 +21,10 @@ use system_configuration::{
     sys::schema_definitions::{kSCPropNetDNSServerAddresses, kSCPropNetInterfaceDeviceName},
 };
 use talpid_time::Instant;
-use talpid_types::tunnel::ErrorStateCause;
+use talpid_types::{
+    net::{DnsBackendHealth, InterfaceResolvers},
+    tunnel::ErrorStateCause,
+};
 
 use crate::tunnel_state_machine::TunnelCommand;
 
@@ -56,6 +59,7 @@ pub enum Error {
 }
 
 const STATE_PATH_PATTERN: &str = "State:/Network/Service/.*/DNS";
+const GLOBAL_DNS_STATE_PATH: &str = "State:/Network/Global/DNS";
 const SETUP_PATH_PATTERN: &str = "Setup:/Network/Service/.*/DNS";
 
 type ServicePath = String;
@@ -101,7 +105,7 @@ impl State {
                 self.backup = backup;
             }
             Some(old_settings) => {
-                if new_settings.address_set() != old_settings.address_set() {
+                if new_settings.server_addresses() != old_settings.server_addresses() {
                     for service_path in self.backup.keys() {
                         new_settings.save(store, service_path.as_str())?;
                     }
@@ -124,7 +128,7 @@ impl State {
                         true
                     }
                     Some(new_settings) => {
-                        if new_settings.address_set() != expected_settings.address_set() {
+                        if new_settings.server_addresses() != expected_settings.server_addresses() {
                             let servers = new_settings.server_addresses().join(",");
                             log::debug!("Detected DNS change [{}] for {}", servers, *path);
                             self.backup.insert(path.to_string(), Some(new_settings));
@@ -256,10 +260,6 @@ impl DnsSettings {
             .unwrap_or(Vec::new())
     }
 
-    pub fn address_set(&self) -> BTreeSet<String> {
-        BTreeSet::from_iter(self.server_addresses().into_iter())
-    }
-
     pub fn interface_config(&self, interface_path: &str) -> Result<Vec<IpAddr>> {
         let addresses = self
             .server_addresses()
@@ -390,6 +390,40 @@ impl DnsMonitor {
     }
 }
 
+/// Checks that configd responds with a DNS configuration, and returns the servers of every
+/// service that has any.
+pub fn check_backend() -> DnsBackendHealth {
+    let store = SCDynamicStoreBuilder::new("talpid-dns-check").build();
+
+    let error = match store.get(GLOBAL_DNS_STATE_PATH) {
+        Some(_) => None,
+        None => Some(format!("{} is missing", GLOBAL_DNS_STATE_PATH)),
+    };
+    let mut resolvers: Vec<InterfaceResolvers> = read_all_dns(&store)
+        .into_iter()
+        .filter(|(path, _)| path.starts_with("State:/"))
+        .filter_map(|(path, settings)| {
+            let settings = settings?;
+            let servers = settings.interface_config(&path).ok()?;
+            if servers.is_empty() {
+                return None;
+            }
+            Some(InterfaceResolvers {
+                interface: settings.name,
+                servers,
+            })
+        })
+        .collect();
+    resolvers.sort_by(|a, b| a.interface.cmp(&b.interface));
+
+    DnsBackendHealth {
+        backend: "SCDynamicStore".to_owned(),
+        error,
+        resolvers,
+        conflicting_managers: vec![],
+    }
+}
+
 fn parse_sc_config(
     config: &HashMap<String, Option<DnsSettings>>,
 ) -> Result<Option<(String, Vec<IpAddr>)>> {
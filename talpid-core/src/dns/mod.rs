@@ -1,6 +1,7 @@
 use std::net::IpAddr;
 #[cfg(target_os = "linux")]
 use talpid_routing::RouteManagerHandle;
+use talpid_types::net::DnsBackendHealth;
 
 #[cfg(target_os = "macos")]
 use {
@@ -29,9 +30,16 @@ mod imp;
 
 pub use self::imp::Error;
 
+/// Checks whether the mechanism used to configure DNS on this system is working, and returns the
+/// resolvers that are currently in effect. This may block for several seconds.
+pub fn check_backend() -> DnsBackendHealth {
+    imp::check_backend()
+}
+
 /// Sets and monitors system DNS settings. Makes sure the desired DNS servers are being used.
 pub struct DnsMonitor {
-    inner: imp::DnsMonitor,
+    /// `None` when the fake tunnel backend is in use and system DNS should be left untouched.
+    inner: Option<imp::DnsMonitor>,
 }
 
 impl DnsMonitor {
@@ -41,15 +49,18 @@ impl DnsMonitor {
         #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
         #[cfg(target_os = "macos")] tx: Weak<UnboundedSender<TunnelCommand>>,
     ) -> Result<Self, Error> {
+        if crate::tunnel::fake::is_enabled() {
+            return Ok(DnsMonitor { inner: None });
+        }
         Ok(DnsMonitor {
-            inner: imp::DnsMonitor::new(
+            inner: Some(imp::DnsMonitor::new(
                 #[cfg(target_os = "linux")]
                 handle,
                 #[cfg(target_os = "linux")]
                 route_manager,
                 #[cfg(target_os = "macos")]
                 tx,
-            )?,
+            )?),
         })
     }
 
@@ -57,10 +68,14 @@ impl DnsMonitor {
     /// changes.
     #[cfg(target_os = "macos")]
     pub fn get_system_config(&self) -> Result<Option<(String, Vec<IpAddr>)>, Error> {
-        self.inner.get_system_config()
+        match self.inner.as_ref() {
+            Some(inner) => inner.get_system_config(),
+            None => Ok(None),
+        }
     }
 
-    /// Set DNS to the given servers. And start monitoring the system for changes.
+    /// Set DNS to the given servers, in order of preference. And start monitoring the system for
+    /// changes.
     pub fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Error> {
         log::info!(
             "Setting DNS servers to {}",
@@ -70,14 +85,20 @@ impl DnsMonitor {
                 .collect::<Vec<String>>()
                 .join(", ")
         );
-        self.inner.set(interface, servers)
+        match self.inner.as_mut() {
+            Some(inner) => inner.set(interface, servers),
+            None => Ok(()),
+        }
     }
 
     /// Reset system DNS settings to what it was before being set by this instance.
     /// This succeeds if the interface does not exist.
     pub fn reset(&mut self) -> Result<(), Error> {
         log::info!("Resetting DNS");
-        self.inner.reset()
+        match self.inner.as_mut() {
+            Some(inner) => inner.reset(),
+            None => Ok(()),
+        }
     }
 
     /// Reset DNS settings to what they were before being set by this instance.
@@ -85,7 +106,10 @@ impl DnsMonitor {
     /// as the interface will be destroyed.
     pub fn reset_before_interface_removal(&mut self) -> Result<(), Error> {
         log::info!("Resetting DNS");
-        self.inner.reset_before_interface_removal()
+        match self.inner.as_mut() {
+            Some(inner) => inner.reset_before_interface_removal(),
+            None => Ok(()),
+        }
     }
 }
 
@@ -1,4 +1,5 @@
 use std::{env, fmt, net::IpAddr};
+use talpid_types::{net::DnsBackendHealth, ErrorExt};
 
 mod dnsapi;
 mod netsh;
@@ -74,3 +75,27 @@ impl fmt::Display for DnsMonitorHolder {
         }
     }
 }
+
+/// Checks that the DNS module selected by `TALPID_DNS_MODULE` can be used, and returns the name
+/// servers that are configured for each interface.
+pub fn check_backend() -> DnsBackendHealth {
+    let dns_module = env::var_os("TALPID_DNS_MODULE");
+
+    let (backend, error) = match dns_module.as_ref().and_then(|value| value.to_str()) {
+        Some("tcpip") => (
+            "TCP/IP registry parameter",
+            tcpip::check().err().map(|error| error.display_chain()),
+        ),
+        Some(_) | None => (
+            "netsh",
+            netsh::check().err().map(|error| error.display_chain()),
+        ),
+    };
+
+    DnsBackendHealth {
+        backend: backend.to_owned(),
+        error,
+        resolvers: tcpip::interface_resolvers(),
+        conflicting_managers: vec![],
+    }
+}
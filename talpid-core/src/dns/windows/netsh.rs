@@ -132,6 +132,11 @@ impl DnsMonitorT for DnsMonitor {
     }
 }
 
+/// Checks that netsh starts and exits successfully when given no commands.
+pub fn check() -> Result<(), Error> {
+    run_netsh_with_timeout(String::new(), NETSH_TIMEOUT)
+}
+
 fn run_netsh_with_timeout(netsh_input: String, timeout: Duration) -> Result<(), Error> {
     log::debug!("running netsh:\n{}", netsh_input);
 
@@ -1,10 +1,10 @@
 use crate::dns::DnsMonitorT;
-use std::{io, net::IpAddr};
-use talpid_types::ErrorExt;
+use std::{collections::BTreeMap, io, net::IpAddr};
+use talpid_types::{net::InterfaceResolvers, ErrorExt};
 use talpid_windows_net::{guid_from_luid, luid_from_alias};
 use windows_sys::{core::GUID, Win32::System::Com::StringFromGUID2};
 use winreg::{
-    enums::{HKEY_LOCAL_MACHINE, KEY_SET_VALUE},
+    enums::{HKEY_LOCAL_MACHINE, KEY_READ, KEY_SET_VALUE},
     transaction::Transaction,
     RegKey,
 };
@@ -101,8 +101,7 @@ fn config_interface<'a>(
         .map(|addr| addr.to_string())
         .collect::<Vec<String>>();
 
-    let reg_path =
-        format!(r#"SYSTEM\CurrentControlSet\Services\{service}\Parameters\Interfaces\{guid}"#,);
+    let reg_path = format!(r#"{}\{guid}"#, interfaces_path(service));
     let adapter_key = match RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey_transacted_with_flags(
         reg_path,
         transaction,
@@ -140,6 +139,53 @@ fn config_interface<'a>(
     Ok(())
 }
 
+/// Checks that the interface parameters can be read from the registry.
+pub fn check() -> io::Result<()> {
+    RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey_with_flags(interfaces_path("Tcpip"), KEY_READ)
+        .map(|_| ())
+}
+
+/// Returns the name servers of every interface that has any, keyed by interface GUID. Statically
+/// configured servers take precedence over those obtained using DHCP, as they do in Windows.
+pub fn interface_resolvers() -> Vec<InterfaceResolvers> {
+    let mut servers: BTreeMap<String, Vec<IpAddr>> = BTreeMap::new();
+
+    for service in ["Tcpip", "Tcpip6"] {
+        let interfaces = match RegKey::predef(HKEY_LOCAL_MACHINE)
+            .open_subkey_with_flags(interfaces_path(service), KEY_READ)
+        {
+            Ok(interfaces) => interfaces,
+            Err(_) => continue,
+        };
+        for guid in interfaces.enum_keys().filter_map(|guid| guid.ok()) {
+            let interface = match interfaces.open_subkey_with_flags(&guid, KEY_READ) {
+                Ok(interface) => interface,
+                Err(_) => continue,
+            };
+            let nameservers = ["NameServer", "DhcpNameServer"]
+                .iter()
+                .filter_map(|value| interface.get_value::<String, _>(value).ok())
+                .find(|value| !value.trim().is_empty())
+                .unwrap_or_default();
+            let addresses = nameservers
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter_map(|address| address.parse::<IpAddr>().ok());
+            servers.entry(guid).or_default().extend(addresses);
+        }
+    }
+
+    servers
+        .into_iter()
+        .filter(|(_, servers)| !servers.is_empty())
+        .map(|(interface, servers)| InterfaceResolvers { interface, servers })
+        .collect()
+}
+
+fn interfaces_path(service: &str) -> String {
+    format!(r#"SYSTEM\CurrentControlSet\Services\{service}\Parameters\Interfaces"#)
+}
+
 fn flush_dns_cache() -> Result<(), Error> {
     super::dnsapi::flush_resolver_cache().map_err(Error::FlushResolverCacheError)
 }
@@ -13,13 +13,18 @@ use std::{
     ffi::{CStr, CString},
     fs, io,
     net::{IpAddr, Ipv4Addr},
+    path::Path,
+};
+use talpid_types::{
+    cgroup::{SplitTunnelMode, TUNNEL_BOUND_SOCKET_MARK},
+    net::{self, AllowedLinkConfigTraffic, AllowedTunnelTraffic, Endpoint, TransportProtocol},
 };
-use talpid_types::net::{AllowedTunnelTraffic, Endpoint, TransportProtocol};
 
 /// Priority for rules that tag split tunneling packets. Equals NF_IP_PRI_MANGLE.
 const MANGLE_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_MANGLE;
 const PREROUTING_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_CONNTRACK + 1;
 const PROC_SYS_NET_IPV4_CONF_SRC_VALID_MARK: &str = "/proc/sys/net/ipv4/conf/all/src_valid_mark";
+const PROC_SYS_NET_IPV4_CONF: &str = "/proc/sys/net/ipv4/conf";
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -54,6 +59,10 @@ pub enum Error {
         _0
     )]
     LookupIfaceIndexError(String, #[error(source)] crate::linux::IfaceIndexLookupError),
+
+    /// The name of the shared interface cannot be used in a firewall rule.
+    #[error(display = "Invalid network interface name \"{}\"", _0)]
+    InvalidInterfaceName(String),
 }
 
 lazy_static! {
@@ -99,6 +108,9 @@ enum End {
 /// The Linux implementation for the firewall and DNS.
 pub struct Firewall {
     fwmark: u32,
+    /// Shared interface that IPv4 forwarding has been enabled on, and its previous forwarding
+    /// setting.
+    shared_forwarding: Option<(String, Vec<u8>)>,
 }
 
 struct FirewallTables {
@@ -113,10 +125,19 @@ impl Firewall {
     }
 
     pub fn new(fwmark: u32) -> Result<Self> {
-        Ok(Firewall { fwmark })
+        Ok(Firewall {
+            fwmark,
+            shared_forwarding: None,
+        })
     }
 
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
+        if policy.split_tunnel_mode() == SplitTunnelMode::Include {
+            log::warn!(
+                "Split tunneling is in include mode: only included processes are protected by \
+                 the tunnel, and all other traffic is allowed outside of it"
+            );
+        }
         let tables = FirewallTables {
             main: Table::new(&*TABLE_NAME, ProtoFamily::Inet),
             mangle_v4: Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
@@ -125,6 +146,7 @@ impl Firewall {
         let batch = PolicyBatch::new(&tables).finalize(&policy, self.fwmark)?;
         Self::send_and_process(&batch)?;
         Self::apply_kernel_config(&policy);
+        self.update_forwarding(&policy);
         self.verify_tables(&[&TABLE_NAME, &MANGLE_TABLE_NAME_V4, &MANGLE_TABLE_NAME_V6])
     }
 
@@ -145,6 +167,7 @@ impl Firewall {
         let batch = batch.finalize();
         log::debug!("Removing table and chain from netfilter");
         Self::send_and_process(&batch)?;
+        self.restore_forwarding();
         Ok(())
     }
 
@@ -161,6 +184,58 @@ impl Firewall {
         }
     }
 
+    /// Enables IPv4 forwarding on the shared interface and on the tunnel interface while
+    /// connected, so that packets can be routed between them. Forwarding is restored to its
+    /// previous setting in every other state. It is only enabled on the interfaces involved,
+    /// since enabling it globally affects how other interfaces are configured. The firewall rules
+    /// stop forwarded packets from going anywhere else.
+    fn update_forwarding(&mut self, policy: &FirewallPolicy) {
+        let shared_interface = match policy {
+            FirewallPolicy::Connected { .. } => policy.shared_interface(),
+            _ => None,
+        };
+        let forwarding_interface = self
+            .shared_forwarding
+            .as_ref()
+            .map(|(interface, _)| interface.as_str());
+        if forwarding_interface != shared_interface {
+            self.restore_forwarding();
+            if let Some(interface) = shared_interface {
+                match set_forwarding_sysctl(interface, b"1") {
+                    Ok(previous) => {
+                        self.shared_forwarding = Some((interface.to_owned(), previous));
+                    }
+                    Err(error) => {
+                        log::error!("Failed to enable IP forwarding on {}: {}", interface, error)
+                    }
+                }
+            }
+        }
+
+        if let (Some(_), FirewallPolicy::Connected { tunnel, .. }) = (shared_interface, policy) {
+            if let Err(error) = set_forwarding_sysctl(&tunnel.interface, b"1") {
+                log::error!(
+                    "Failed to enable IP forwarding on {}: {}",
+                    tunnel.interface,
+                    error
+                );
+            }
+        }
+    }
+
+    /// Restores the forwarding setting of the interface that is no longer forwarded.
+    fn restore_forwarding(&mut self) {
+        if let Some((interface, previous)) = self.shared_forwarding.take() {
+            if let Err(error) = set_forwarding_sysctl(&interface, &previous) {
+                log::error!(
+                    "Failed to restore IP forwarding setting of {}: {}",
+                    interface,
+                    error
+                );
+            }
+        }
+    }
+
     fn send_and_process(batch: &FinalizedBatch) -> Result<()> {
         let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(Error::NetlinkOpenError)?;
         socket.send_all(batch).map_err(Error::NetlinkSendError)?;
@@ -318,10 +393,14 @@ impl<'a> PolicyBatch<'a> {
     /// Finalize the nftnl message batch by adding every firewall rule needed to satisfy the given
     /// policy.
     pub fn finalize(mut self, policy: &FirewallPolicy, fwmark: u32) -> Result<FinalizedBatch> {
+        self.add_drop_shared_interface_rule(policy)?;
         self.add_loopback_rules()?;
         self.add_split_tunneling_rules(policy, fwmark)?;
-        self.add_dhcp_client_rules();
-        self.add_ndp_rules();
+        let link_config = policy.allowed_link_config_traffic();
+        self.add_dhcp_client_rules(link_config);
+        if link_config.ndp {
+            self.add_ndp_rules();
+        }
         self.add_policy_specific_rules(policy, fwmark)?;
 
         Ok(self.batch.finalize())
@@ -335,37 +414,45 @@ impl<'a> PolicyBatch<'a> {
             ..
         } = policy
         {
-            for server in dns_servers
-                .iter()
-                .filter(|server| !is_local_dns_address(tunnel, server))
-            {
+            let mode = policy.split_tunnel_mode();
+            for server in split_tunnel_dns_exemptions(tunnel, dns_servers, mode) {
                 let chain = if server.is_ipv4() {
                     &self.mangle_chain_v4
                 } else {
                     &self.mangle_chain_v6
                 };
-                let allow_rule = allow_tunnel_dns_rule(
-                    chain,
-                    &tunnel.interface,
-                    TransportProtocol::Udp,
-                    *server,
-                )?;
-                self.batch.add(&allow_rule, nftnl::MsgType::Add);
-                let allow_rule = allow_tunnel_dns_rule(
-                    chain,
-                    &tunnel.interface,
-                    TransportProtocol::Tcp,
-                    *server,
-                )?;
-                self.batch.add(&allow_rule, nftnl::MsgType::Add);
+                for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+                    let allow_rule = match mode {
+                        SplitTunnelMode::Exclude => {
+                            allow_tunnel_dns_rule(chain, &tunnel.interface, protocol, server)?
+                        }
+                        SplitTunnelMode::Include => allow_dns_rule(chain, protocol, server),
+                    };
+                    self.batch.add(&allow_rule, nftnl::MsgType::Add);
+                }
             }
         }
 
         let mangle_chains = [&self.mangle_chain_v4, &self.mangle_chain_v6];
         for chain in &mangle_chains {
+            // Sockets bound to the tunnel must stay in it, also in include mode
+            let mut rule = Rule::new(chain);
+            rule.add_expr(&nft_expr!(meta mark));
+            rule.add_expr(&nft_expr!(cmp == TUNNEL_BOUND_SOCKET_MARK));
+            add_verdict(&mut rule, &Verdict::Accept);
+            self.batch.add(&rule, nftnl::MsgType::Add);
+
             let mut rule = Rule::new(chain);
             rule.add_expr(&nft_expr!(meta cgroup));
-            rule.add_expr(&nft_expr!(cmp == split_tunnel::NET_CLS_CLASSID));
+            match policy.split_tunnel_mode() {
+                SplitTunnelMode::Exclude => {
+                    rule.add_expr(&nft_expr!(cmp == split_tunnel::NET_CLS_CLASSID));
+                }
+                // Treat everything but the included processes as excluded
+                SplitTunnelMode::Include => {
+                    rule.add_expr(&nft_expr!(cmp != split_tunnel::NET_CLS_INCLUDE_CLASSID));
+                }
+            }
             rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
             rule.add_expr(&nft_expr!(ct mark set));
             rule.add_expr(&nft_expr!(immediate data fwmark));
@@ -443,45 +530,85 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
-    fn add_dhcp_client_rules(&mut self) {
+    fn add_dhcp_client_rules(&mut self, allowed: AllowedLinkConfigTraffic) {
         use self::TransportProtocol::Udp;
-        // Outgoing DHCPv4 request
-        for chain in &[&self.out_chain, &self.forward_chain] {
-            let mut out_v4 = Rule::new(chain);
-            check_port(&mut out_v4, Udp, End::Src, super::DHCPV4_CLIENT_PORT);
-            check_ip(&mut out_v4, End::Dst, IpAddr::V4(Ipv4Addr::BROADCAST));
-            check_port(&mut out_v4, Udp, End::Dst, super::DHCPV4_SERVER_PORT);
-            add_verdict(&mut out_v4, &Verdict::Accept);
-            self.batch.add(&out_v4, nftnl::MsgType::Add);
+        if allowed.dhcpv4 {
+            // Outgoing DHCPv4 request
+            for chain in &[&self.out_chain, &self.forward_chain] {
+                let mut out_v4 = Rule::new(chain);
+                check_port(&mut out_v4, Udp, End::Src, super::DHCPV4_CLIENT_PORT);
+                check_ip(&mut out_v4, End::Dst, IpAddr::V4(Ipv4Addr::BROADCAST));
+                check_port(&mut out_v4, Udp, End::Dst, super::DHCPV4_SERVER_PORT);
+                add_verdict(&mut out_v4, &Verdict::Accept);
+                self.batch.add(&out_v4, nftnl::MsgType::Add);
+            }
         }
-        // Incoming DHCPv4 response
-        for chain in &[&self.in_chain, &self.forward_chain] {
-            let mut in_v4 = Rule::new(chain);
-            check_port(&mut in_v4, Udp, End::Src, super::DHCPV4_SERVER_PORT);
-            check_port(&mut in_v4, Udp, End::Dst, super::DHCPV4_CLIENT_PORT);
-            add_verdict(&mut in_v4, &Verdict::Accept);
-            self.batch.add(&in_v4, nftnl::MsgType::Add);
+        if allowed.dhcpv4 || allowed.dhcp_unicast {
+            // Incoming DHCPv4 response
+            for chain in &[&self.in_chain, &self.forward_chain] {
+                let mut in_v4 = Rule::new(chain);
+                check_port(&mut in_v4, Udp, End::Src, super::DHCPV4_SERVER_PORT);
+                check_port(&mut in_v4, Udp, End::Dst, super::DHCPV4_CLIENT_PORT);
+                add_verdict(&mut in_v4, &Verdict::Accept);
+                self.batch.add(&in_v4, nftnl::MsgType::Add);
+            }
         }
 
-        for chain in &[&self.out_chain, &self.forward_chain] {
-            for dhcpv6_server in &*super::DHCPV6_SERVER_ADDRS {
-                let mut out_v6 = Rule::new(chain);
-                check_net(&mut out_v6, End::Src, *super::IPV6_LINK_LOCAL);
-                check_port(&mut out_v6, Udp, End::Src, super::DHCPV6_CLIENT_PORT);
-                check_ip(&mut out_v6, End::Dst, *dhcpv6_server);
-                check_port(&mut out_v6, Udp, End::Dst, super::DHCPV6_SERVER_PORT);
-                add_verdict(&mut out_v6, &Verdict::Accept);
-                self.batch.add(&out_v6, nftnl::MsgType::Add);
+        if allowed.dhcpv6 {
+            for chain in &[&self.out_chain, &self.forward_chain] {
+                for dhcpv6_server in &*super::DHCPV6_SERVER_ADDRS {
+                    let mut out_v6 = Rule::new(chain);
+                    check_net(&mut out_v6, End::Src, *super::IPV6_LINK_LOCAL);
+                    check_port(&mut out_v6, Udp, End::Src, super::DHCPV6_CLIENT_PORT);
+                    check_ip(&mut out_v6, End::Dst, *dhcpv6_server);
+                    check_port(&mut out_v6, Udp, End::Dst, super::DHCPV6_SERVER_PORT);
+                    add_verdict(&mut out_v6, &Verdict::Accept);
+                    self.batch.add(&out_v6, nftnl::MsgType::Add);
+                }
+            }
+            for chain in &[&self.in_chain, &self.forward_chain] {
+                let mut in_v6 = Rule::new(chain);
+                check_net(&mut in_v6, End::Src, *super::IPV6_LINK_LOCAL);
+                check_port(&mut in_v6, Udp, End::Src, super::DHCPV6_SERVER_PORT);
+                check_net(&mut in_v6, End::Dst, *super::IPV6_LINK_LOCAL);
+                check_port(&mut in_v6, Udp, End::Dst, super::DHCPV6_CLIENT_PORT);
+                add_verdict(&mut in_v6, &Verdict::Accept);
+                self.batch.add(&in_v6, nftnl::MsgType::Add);
             }
         }
-        for chain in &[&self.in_chain, &self.forward_chain] {
-            let mut in_v6 = Rule::new(chain);
-            check_net(&mut in_v6, End::Src, *super::IPV6_LINK_LOCAL);
-            check_port(&mut in_v6, Udp, End::Src, super::DHCPV6_SERVER_PORT);
-            check_net(&mut in_v6, End::Dst, *super::IPV6_LINK_LOCAL);
-            check_port(&mut in_v6, Udp, End::Dst, super::DHCPV6_CLIENT_PORT);
-            add_verdict(&mut in_v6, &Verdict::Accept);
-            self.batch.add(&in_v6, nftnl::MsgType::Add);
+
+        if allowed.dhcp_unicast {
+            self.add_dhcp_unicast_rules();
+        }
+    }
+
+    /// Allows DHCP messages between the client and server ports on the local network, such as
+    /// DHCPv4 lease renewals and DHCPv6 messages sent to a server's unicast address.
+    fn add_dhcp_unicast_rules(&mut self) {
+        use self::TransportProtocol::Udp;
+        let ports = [
+            (false, super::DHCPV4_CLIENT_PORT, super::DHCPV4_SERVER_PORT),
+            (true, super::DHCPV6_CLIENT_PORT, super::DHCPV6_SERVER_PORT),
+        ];
+        for (ipv6, client_port, server_port) in ports {
+            for net in super::dhcp_unicast_nets(ipv6) {
+                let mut rule = Rule::new(&self.out_chain);
+                check_port(&mut rule, Udp, End::Src, client_port);
+                check_net(&mut rule, End::Dst, net);
+                check_port(&mut rule, Udp, End::Dst, server_port);
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+        }
+        // Incoming DHCPv6 responses from a server's unicast address. The corresponding DHCPv4
+        // responses are already allowed from any address.
+        for net in super::dhcp_unicast_nets(true) {
+            let mut rule = Rule::new(&self.in_chain);
+            check_net(&mut rule, End::Src, net);
+            check_port(&mut rule, Udp, End::Src, super::DHCPV6_SERVER_PORT);
+            check_port(&mut rule, Udp, End::Dst, super::DHCPV6_CLIENT_PORT);
+            add_verdict(&mut rule, &Verdict::Accept);
+            self.batch.add(&rule, nftnl::MsgType::Add);
         }
     }
 
@@ -562,9 +689,12 @@ impl<'a> PolicyBatch<'a> {
                 allow_lan,
                 allowed_endpoint,
                 allowed_tunnel_traffic,
+                ..
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint, fwmark);
-                self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
+                for endpoint in allowed_endpoint.endpoints() {
+                    self.add_allow_endpoint_rules(endpoint);
+                }
 
                 // Important to block DNS after allow relay rule (so the relay can operate
                 // over port 53) but before allow LAN (so DNS does not leak to the LAN)
@@ -591,10 +721,17 @@ impl<'a> PolicyBatch<'a> {
                 tunnel,
                 allow_lan,
                 dns_servers,
+                allowed_multicast_groups,
+                ..
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint, fwmark);
                 self.add_allow_dns_rules(tunnel, dns_servers, TransportProtocol::Udp)?;
                 self.add_allow_dns_rules(tunnel, dns_servers, TransportProtocol::Tcp)?;
+                // Shared devices use their own DNS servers, which are fine to reach as long as
+                // the requests go through the tunnel
+                if let Some(shared_interface) = policy.shared_interface() {
+                    self.add_connection_sharing_rules(shared_interface, &tunnel.interface)?;
+                }
                 // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
                 // can't leak to the wrong IPs in the tunnel or on the LAN.
                 self.add_drop_dns_rule();
@@ -602,14 +739,20 @@ impl<'a> PolicyBatch<'a> {
                 if *allow_lan {
                     self.add_block_cve_2019_14899(tunnel);
                 }
+                if !allowed_multicast_groups.is_empty() {
+                    self.add_allow_multicast_group_rules(allowed_multicast_groups);
+                }
                 *allow_lan
             }
             FirewallPolicy::Blocked {
                 allow_lan,
                 allowed_endpoint,
+                ..
             } => {
-                if let Some(endpoint) = allowed_endpoint {
-                    self.add_allow_endpoint_rules(&endpoint.endpoint);
+                if let Some(allowed_endpoint) = allowed_endpoint {
+                    for endpoint in allowed_endpoint.endpoints() {
+                        self.add_allow_endpoint_rules(endpoint);
+                    }
                 }
 
                 // Important to drop DNS before allowing LAN (to stop DNS leaking to the LAN)
@@ -620,6 +763,8 @@ impl<'a> PolicyBatch<'a> {
 
         if allow_lan {
             self.add_allow_lan_rules();
+        } else {
+            self.add_allow_lan_host_rules(policy.allowed_lan_hosts());
         }
 
         // Reject any remaining outgoing traffic
@@ -767,6 +912,64 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
+    /// Drops packets forwarded from the shared interface, if any, unless they go into the tunnel
+    /// while connected. This precedes every other rule in the forward chain, so that shared
+    /// devices can never reach anything outside the tunnel.
+    fn add_drop_shared_interface_rule(&mut self, policy: &FirewallPolicy) -> Result<()> {
+        let shared_interface = match policy.shared_interface() {
+            Some(shared_interface) => shared_interface_name(shared_interface)?,
+            None => return Ok(()),
+        };
+
+        let mut drop_rule = Rule::new(&self.forward_chain);
+        drop_rule.add_expr(&nft_expr!(meta iifname));
+        drop_rule.add_expr(&nft_expr!(
+            cmp == expr::InterfaceName::Exact(shared_interface)
+        ));
+        if let FirewallPolicy::Connected { tunnel, .. } = policy {
+            check_not_iface(&mut drop_rule, Direction::Out, &tunnel.interface)?;
+        }
+        add_verdict(&mut drop_rule, &Verdict::Drop);
+        self.batch.add(&drop_rule, nftnl::MsgType::Add);
+
+        Ok(())
+    }
+
+    /// Lets devices on `shared_interface` reach the internet through the tunnel, by forwarding
+    /// their IPv4 traffic into the tunnel and translating the source address to that of the
+    /// tunnel. Responses are allowed back by the rule for established in-tunnel connections.
+    fn add_connection_sharing_rules(
+        &mut self,
+        shared_interface: &str,
+        tunnel_interface: &str,
+    ) -> Result<()> {
+        let shared_interface = shared_interface_name(shared_interface)?;
+
+        let mut forward_rule = Rule::new(&self.forward_chain);
+        check_l3proto(&mut forward_rule, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        forward_rule.add_expr(&nft_expr!(meta iifname));
+        forward_rule.add_expr(&nft_expr!(
+            cmp == expr::InterfaceName::Exact(shared_interface.clone())
+        ));
+        check_iface(&mut forward_rule, Direction::Out, tunnel_interface)?;
+        add_verdict(&mut forward_rule, &Verdict::Accept);
+        self.batch.add(&forward_rule, nftnl::MsgType::Add);
+
+        let mut nat_rule = Rule::new(&self.nat_chain_v4);
+        nat_rule.add_expr(&nft_expr!(meta iifname));
+        nat_rule.add_expr(&nft_expr!(
+            cmp == expr::InterfaceName::Exact(shared_interface)
+        ));
+        check_iface(&mut nat_rule, Direction::Out, tunnel_interface)?;
+        nat_rule.add_expr(&nft_expr!(masquerade));
+        if *ADD_COUNTERS {
+            nat_rule.add_expr(&nft_expr!(counter));
+        }
+        self.batch.add(&nat_rule, nftnl::MsgType::Add);
+
+        Ok(())
+    }
+
     /// Blocks all outgoing DNS (port 53) on both TCP and UDP
     fn add_drop_dns_rule(&mut self) {
         for chain in &[&self.out_chain, &self.forward_chain] {
@@ -875,6 +1078,72 @@ impl<'a> PolicyBatch<'a> {
         self.add_dhcp_server_rules();
     }
 
+    fn add_allow_lan_host_rules(&mut self, hosts: &[IpAddr]) {
+        for host in super::lan_hosts(hosts) {
+            for chain in &[&self.out_chain, &self.forward_chain] {
+                let mut out_rule = Rule::new(chain);
+                check_ip(&mut out_rule, End::Dst, host);
+                add_verdict(&mut out_rule, &Verdict::Accept);
+                self.batch.add(&out_rule, nftnl::MsgType::Add);
+            }
+
+            let mut in_rule = Rule::new(&self.in_chain);
+            check_ip(&mut in_rule, End::Src, host);
+            add_verdict(&mut in_rule, &Verdict::Accept);
+            self.batch.add(&in_rule, nftnl::MsgType::Add);
+        }
+    }
+
+    fn add_allow_multicast_group_rules(&mut self, groups: &[IpAddr]) {
+        for chain in &[&self.out_chain, &self.forward_chain] {
+            for group in groups {
+                let mut rule = Rule::new(chain);
+                check_ip(&mut rule, End::Dst, *group);
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+            // Membership reports, without which switches and routers may stop forwarding the
+            // groups to us
+            for addr in &*super::MULTICAST_MEMBERSHIP_REPORT_ADDRS {
+                let mut rule = Rule::new(chain);
+                check_ip(&mut rule, End::Dst, *addr);
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+        }
+
+        for chain in &[&self.in_chain, &self.forward_chain] {
+            for group in groups {
+                let mut rule = Rule::new(chain);
+                check_ip(&mut rule, End::Dst, *group);
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+            for addr in &*super::MULTICAST_MEMBERSHIP_QUERY_ADDRS {
+                let mut rule = Rule::new(chain);
+                check_ip(&mut rule, End::Dst, *addr);
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+        }
+
+        // Unicast mDNS responses from the local network
+        if groups.iter().any(|group| super::MDNS_ADDRS.contains(group)) {
+            for net in &*super::ALLOWED_LAN_NETS {
+                let mut rule = Rule::new(&self.in_chain);
+                check_net(&mut rule, End::Src, *net);
+                check_port(
+                    &mut rule,
+                    TransportProtocol::Udp,
+                    End::Dst,
+                    super::MDNS_PORT,
+                );
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+        }
+    }
+
     fn add_dhcp_server_rules(&mut self) {
         use TransportProtocol::Udp;
         // Outgoing DHCPv4 response
@@ -906,6 +1175,22 @@ fn is_local_dns_address(tunnel: &tunnel::TunnelMetadata, server: &IpAddr) -> boo
         && Some(server) != tunnel.ipv6_gateway.map(IpAddr::from).as_ref()
 }
 
+/// Returns the DNS servers whose requests are exempt from split tunnel marking. In exclude mode,
+/// requests to the tunnel resolvers are exempt only if they are routed through the tunnel. In
+/// include mode, requests to all configured resolvers are exempt, since they are usually sent by a
+/// system resolver such as systemd-resolved, which is not an included process.
+fn split_tunnel_dns_exemptions(
+    tunnel: &tunnel::TunnelMetadata,
+    dns_servers: &[IpAddr],
+    mode: SplitTunnelMode,
+) -> Vec<IpAddr> {
+    dns_servers
+        .iter()
+        .filter(|server| mode == SplitTunnelMode::Include || !is_local_dns_address(tunnel, server))
+        .copied()
+        .collect()
+}
+
 fn allow_tunnel_dns_rule<'a>(
     chain: &'a Chain<'_>,
     iface: &str,
@@ -914,21 +1199,37 @@ fn allow_tunnel_dns_rule<'a>(
 ) -> Result<Rule<'a>> {
     let mut rule = Rule::new(chain);
     check_iface(&mut rule, Direction::Out, iface)?;
-    check_port(&mut rule, protocol, End::Dst, 53);
+    add_dns_host_match(&mut rule, chain, protocol, host);
+    add_verdict(&mut rule, &Verdict::Accept);
+    Ok(rule)
+}
+
+/// Accepts DNS requests to `host` regardless of which interface they are sent on.
+fn allow_dns_rule<'a>(chain: &'a Chain<'_>, protocol: TransportProtocol, host: IpAddr) -> Rule<'a> {
+    let mut rule = Rule::new(chain);
+    add_dns_host_match(&mut rule, chain, protocol, host);
+    add_verdict(&mut rule, &Verdict::Accept);
+    rule
+}
+
+fn add_dns_host_match(
+    rule: &mut Rule<'_>,
+    chain: &Chain<'_>,
+    protocol: TransportProtocol,
+    host: IpAddr,
+) {
+    check_port(rule, protocol, End::Dst, 53);
 
     let daddr = match host {
         IpAddr::V4(_) => nft_expr!(payload ipv4 daddr),
         IpAddr::V6(_) => nft_expr!(payload ipv6 daddr),
     };
     if chain.get_table().get_family() == ProtoFamily::Inet {
-        check_l3proto(&mut rule, host);
+        check_l3proto(rule, host);
     }
 
     rule.add_expr(&daddr);
     rule.add_expr(&nft_expr!(cmp == host));
-    add_verdict(&mut rule, &Verdict::Accept);
-
-    Ok(rule)
 }
 
 fn allow_interface_rule<'a>(
@@ -1066,3 +1367,61 @@ fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
 fn set_src_valid_mark_sysctl() -> io::Result<()> {
     fs::write(PROC_SYS_NET_IPV4_CONF_SRC_VALID_MARK, b"1")
 }
+
+/// Returns the name of the shared interface for use in a rule, if it is a valid name.
+fn shared_interface_name(interface: &str) -> Result<CString> {
+    if !net::is_valid_interface_name(interface) {
+        return Err(Error::InvalidInterfaceName(interface.to_owned()));
+    }
+    CString::new(interface).map_err(|_| Error::InvalidInterfaceName(interface.to_owned()))
+}
+
+/// Sets whether IPv4 packets received on `interface` may be forwarded, and returns the previous
+/// setting.
+fn set_forwarding_sysctl(interface: &str, value: &[u8]) -> io::Result<Vec<u8>> {
+    if !net::is_valid_interface_name(interface) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid interface name",
+        ));
+    }
+    let path = Path::new(PROC_SYS_NET_IPV4_CONF)
+        .join(interface)
+        .join("forwarding");
+    let previous = fs::read(&path)?;
+    fs::write(&path, value)?;
+    Ok(previous)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tunnel_metadata() -> tunnel::TunnelMetadata {
+        tunnel::TunnelMetadata {
+            interface: "wg-mullvad".to_owned(),
+            ips: vec!["10.64.0.2".parse().unwrap()],
+            ipv4_gateway: "10.64.0.1".parse().unwrap(),
+            ipv6_gateway: None,
+        }
+    }
+
+    #[test]
+    fn test_split_tunnel_dns_exemptions() {
+        let tunnel = tunnel_metadata();
+        let gateway = IpAddr::V4(tunnel.ipv4_gateway);
+        let private: IpAddr = "192.168.1.1".parse().unwrap();
+        let public: IpAddr = "8.8.8.8".parse().unwrap();
+        let servers = [gateway, private, public];
+
+        assert_eq!(
+            split_tunnel_dns_exemptions(&tunnel, &servers, SplitTunnelMode::Exclude),
+            vec![gateway, public]
+        );
+        // Non-included resolvers must still be able to reach every configured server
+        assert_eq!(
+            split_tunnel_dns_exemptions(&tunnel, &servers, SplitTunnelMode::Include),
+            servers.to_vec()
+        );
+    }
+}
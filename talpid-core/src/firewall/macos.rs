@@ -6,7 +6,7 @@ use std::{
     net::{IpAddr, Ipv4Addr},
 };
 use subslice::SubsliceExt;
-use talpid_types::net::{self, AllowedTunnelTraffic};
+use talpid_types::net::{self, AllowedLinkConfigTraffic, AllowedTunnelTraffic};
 
 pub use pfctl::Error;
 
@@ -65,8 +65,14 @@ impl Firewall {
         let mut new_filter_rules = vec![];
 
         new_filter_rules.append(&mut self.get_allow_loopback_rules()?);
-        new_filter_rules.append(&mut self.get_allow_dhcp_client_rules()?);
-        new_filter_rules.append(&mut self.get_allow_ndp_rules()?);
+        let link_config = policy.allowed_link_config_traffic();
+        new_filter_rules.append(&mut self.get_allow_dhcp_client_rules(link_config)?);
+        if link_config.dhcp_unicast {
+            new_filter_rules.append(&mut self.get_allow_dhcp_unicast_rules()?);
+        }
+        if link_config.ndp {
+            new_filter_rules.append(&mut self.get_allow_ndp_rules()?);
+        }
         new_filter_rules.append(&mut self.get_policy_specific_rules(&policy)?);
 
         let return_out_rule = self
@@ -120,9 +126,13 @@ impl Firewall {
                 allow_lan,
                 allowed_endpoint,
                 allowed_tunnel_traffic,
+                allowed_lan_hosts,
+                ..
             } => {
                 let mut rules = vec![self.get_allow_relay_rule(*peer_endpoint)?];
-                rules.push(self.get_allowed_endpoint_rule(allowed_endpoint.endpoint)?);
+                for endpoint in allowed_endpoint.endpoints() {
+                    rules.push(self.get_allowed_endpoint_rule(*endpoint)?);
+                }
 
                 // Important to block DNS after allow relay rule (so the relay can operate
                 // over port 53) but before allow LAN (so DNS does not leak to the LAN)
@@ -137,6 +147,8 @@ impl Firewall {
 
                 if *allow_lan {
                     rules.append(&mut self.get_allow_lan_rules()?);
+                } else {
+                    rules.append(&mut self.get_allow_lan_host_rules(allowed_lan_hosts)?);
                 }
                 Ok(rules)
             }
@@ -145,6 +157,9 @@ impl Firewall {
                 tunnel,
                 allow_lan,
                 dns_servers,
+                allowed_multicast_groups,
+                allowed_lan_hosts,
+                ..
             } => {
                 let mut rules = vec![];
 
@@ -168,6 +183,14 @@ impl Firewall {
 
                 if *allow_lan {
                     rules.append(&mut self.get_allow_lan_rules()?);
+                } else {
+                    rules.append(&mut self.get_allow_lan_host_rules(allowed_lan_hosts)?);
+                }
+
+                if !allowed_multicast_groups.is_empty() {
+                    rules.append(
+                        &mut self.get_allow_multicast_group_rules(allowed_multicast_groups)?,
+                    );
                 }
 
                 Ok(rules)
@@ -175,17 +198,23 @@ impl Firewall {
             FirewallPolicy::Blocked {
                 allow_lan,
                 allowed_endpoint,
+                allowed_lan_hosts,
                 ..
             } => {
                 let mut rules = Vec::new();
                 if let Some(allowed_endpoint) = allowed_endpoint {
-                    rules.push(self.get_allowed_endpoint_rule(allowed_endpoint.endpoint)?);
+                    for endpoint in allowed_endpoint.endpoints() {
+                        rules.push(self.get_allowed_endpoint_rule(*endpoint)?);
+                    }
                 }
 
                 if *allow_lan {
                     // Important to block DNS before allow LAN (so DNS does not leak to the LAN)
                     rules.append(&mut self.get_block_dns_rules()?);
                     rules.append(&mut self.get_allow_lan_rules()?);
+                } else if !allowed_lan_hosts.is_empty() {
+                    rules.append(&mut self.get_block_dns_rules()?);
+                    rules.append(&mut self.get_allow_lan_host_rules(allowed_lan_hosts)?);
                 }
 
                 Ok(rules)
@@ -364,6 +393,83 @@ impl Firewall {
         Ok(vec![lo0_rule])
     }
 
+    fn get_allow_lan_host_rules(&self, hosts: &[IpAddr]) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for host in super::lan_hosts(hosts) {
+            let allow_out = self
+                .create_rule_builder(FilterRuleAction::Pass)
+                .quick(true)
+                .direction(pfctl::Direction::Out)
+                .to(pfctl::Ip::from(host))
+                .build()?;
+            let allow_in = self
+                .create_rule_builder(FilterRuleAction::Pass)
+                .quick(true)
+                .direction(pfctl::Direction::In)
+                .from(pfctl::Ip::from(host))
+                .build()?;
+            rules.push(allow_out);
+            rules.push(allow_in);
+        }
+        Ok(rules)
+    }
+
+    fn get_allow_multicast_group_rules(&self, groups: &[IpAddr]) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for group in groups {
+            let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+            rule_builder.quick(true);
+            let allow_out = rule_builder
+                .direction(pfctl::Direction::Out)
+                .to(pfctl::Ip::from(*group))
+                .build()?;
+            let allow_in = rule_builder
+                .direction(pfctl::Direction::In)
+                .to(pfctl::Ip::from(*group))
+                .build()?;
+            rules.push(allow_out);
+            rules.push(allow_in);
+        }
+
+        // Membership reports and queries, without which switches and routers may stop
+        // forwarding the groups to us
+        for addr in &*super::MULTICAST_MEMBERSHIP_REPORT_ADDRS {
+            rules.push(
+                self.create_rule_builder(FilterRuleAction::Pass)
+                    .quick(true)
+                    .direction(pfctl::Direction::Out)
+                    .to(pfctl::Ip::from(*addr))
+                    .build()?,
+            );
+        }
+        for addr in &*super::MULTICAST_MEMBERSHIP_QUERY_ADDRS {
+            rules.push(
+                self.create_rule_builder(FilterRuleAction::Pass)
+                    .quick(true)
+                    .direction(pfctl::Direction::In)
+                    .to(pfctl::Ip::from(*addr))
+                    .build()?,
+            );
+        }
+
+        // Unicast mDNS responses from the local network
+        if groups.iter().any(|group| super::MDNS_ADDRS.contains(group)) {
+            for net in &*super::ALLOWED_LAN_NETS {
+                rules.push(
+                    self.create_rule_builder(FilterRuleAction::Pass)
+                        .quick(true)
+                        .direction(pfctl::Direction::In)
+                        .proto(pfctl::Proto::Udp)
+                        .from(pfctl::Ip::from(*net))
+                        .to(pfctl::Port::from(super::MDNS_PORT))
+                        .build()?,
+                );
+            }
+        }
+
+        Ok(rules)
+    }
+
     fn get_allow_lan_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
         let mut rules = vec![];
         for net in &*super::ALLOWED_LAN_NETS {
@@ -418,62 +524,101 @@ impl Firewall {
         Ok(rules)
     }
 
-    fn get_allow_dhcp_client_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
+    fn get_allow_dhcp_client_rules(
+        &self,
+        allowed: AllowedLinkConfigTraffic,
+    ) -> Result<Vec<pfctl::FilterRule>> {
         let mut dhcp_rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
         dhcp_rule_builder.quick(true).proto(pfctl::Proto::Udp);
 
         let mut rules = Vec::new();
 
         // DHCPv4
-        dhcp_rule_builder.af(pfctl::AddrFamily::Ipv4);
-        let allow_outgoing_dhcp_v4 = dhcp_rule_builder
-            .direction(pfctl::Direction::Out)
-            .from(pfctl::Port::from(super::DHCPV4_CLIENT_PORT))
-            .to(pfctl::Endpoint::new(
-                Ipv4Addr::BROADCAST,
-                pfctl::Port::from(super::DHCPV4_SERVER_PORT),
-            ))
-            .build()?;
-        let allow_incoming_dhcp_v4 = dhcp_rule_builder
-            .direction(pfctl::Direction::In)
-            .from(pfctl::Port::from(super::DHCPV4_SERVER_PORT))
-            .to(pfctl::Port::from(super::DHCPV4_CLIENT_PORT))
-            .build()?;
-        rules.push(allow_outgoing_dhcp_v4);
-        rules.push(allow_incoming_dhcp_v4);
+        if allowed.dhcpv4 {
+            dhcp_rule_builder.af(pfctl::AddrFamily::Ipv4);
+            let allow_outgoing_dhcp_v4 = dhcp_rule_builder
+                .direction(pfctl::Direction::Out)
+                .from(pfctl::Port::from(super::DHCPV4_CLIENT_PORT))
+                .to(pfctl::Endpoint::new(
+                    Ipv4Addr::BROADCAST,
+                    pfctl::Port::from(super::DHCPV4_SERVER_PORT),
+                ))
+                .build()?;
+            let allow_incoming_dhcp_v4 = dhcp_rule_builder
+                .direction(pfctl::Direction::In)
+                .from(pfctl::Port::from(super::DHCPV4_SERVER_PORT))
+                .to(pfctl::Port::from(super::DHCPV4_CLIENT_PORT))
+                .build()?;
+            rules.push(allow_outgoing_dhcp_v4);
+            rules.push(allow_incoming_dhcp_v4);
+        }
 
         // DHCPv6
-        dhcp_rule_builder.af(pfctl::AddrFamily::Ipv6);
-        for dhcpv6_server in &*super::DHCPV6_SERVER_ADDRS {
-            let allow_outgoing_dhcp_v6 = dhcp_rule_builder
-                .direction(pfctl::Direction::Out)
+        if allowed.dhcpv6 {
+            dhcp_rule_builder.af(pfctl::AddrFamily::Ipv6);
+            for dhcpv6_server in &*super::DHCPV6_SERVER_ADDRS {
+                let allow_outgoing_dhcp_v6 = dhcp_rule_builder
+                    .direction(pfctl::Direction::Out)
+                    .from(pfctl::Endpoint::new(
+                        IpNetwork::V6(*super::IPV6_LINK_LOCAL),
+                        pfctl::Port::from(super::DHCPV6_CLIENT_PORT),
+                    ))
+                    .to(pfctl::Endpoint::new(
+                        *dhcpv6_server,
+                        pfctl::Port::from(super::DHCPV6_SERVER_PORT),
+                    ))
+                    .build()?;
+                rules.push(allow_outgoing_dhcp_v6);
+            }
+            let allow_incoming_dhcp_v6 = dhcp_rule_builder
+                .direction(pfctl::Direction::In)
                 .from(pfctl::Endpoint::new(
-                    IpNetwork::V6(*super::IPV6_LINK_LOCAL),
-                    pfctl::Port::from(super::DHCPV6_CLIENT_PORT),
+                    pfctl::Ip::from(IpNetwork::V6(*super::IPV6_LINK_LOCAL)),
+                    pfctl::Port::from(super::DHCPV6_SERVER_PORT),
                 ))
                 .to(pfctl::Endpoint::new(
-                    *dhcpv6_server,
-                    pfctl::Port::from(super::DHCPV6_SERVER_PORT),
+                    pfctl::Ip::from(IpNetwork::V6(*super::IPV6_LINK_LOCAL)),
+                    pfctl::Port::from(super::DHCPV6_CLIENT_PORT),
                 ))
                 .build()?;
-            rules.push(allow_outgoing_dhcp_v6);
+            rules.push(allow_incoming_dhcp_v6);
         }
-        let allow_incoming_dhcp_v6 = dhcp_rule_builder
-            .direction(pfctl::Direction::In)
-            .from(pfctl::Endpoint::new(
-                pfctl::Ip::from(IpNetwork::V6(*super::IPV6_LINK_LOCAL)),
-                pfctl::Port::from(super::DHCPV6_SERVER_PORT),
-            ))
-            .to(pfctl::Endpoint::new(
-                pfctl::Ip::from(IpNetwork::V6(*super::IPV6_LINK_LOCAL)),
-                pfctl::Port::from(super::DHCPV6_CLIENT_PORT),
-            ))
-            .build()?;
-        rules.push(allow_incoming_dhcp_v6);
 
         Ok(rules)
     }
 
+    /// Allows DHCP messages between the client and server ports on the local network, such as
+    /// DHCPv4 lease renewals and DHCPv6 messages sent to a server's unicast address.
+    fn get_allow_dhcp_unicast_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = Vec::new();
+        let ports = [
+            (false, super::DHCPV4_CLIENT_PORT, super::DHCPV4_SERVER_PORT),
+            (true, super::DHCPV6_CLIENT_PORT, super::DHCPV6_SERVER_PORT),
+        ];
+        for (ipv6, client_port, server_port) in ports {
+            for net in super::dhcp_unicast_nets(ipv6) {
+                let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+                rule_builder.quick(true).proto(pfctl::Proto::Udp);
+                rules.push(
+                    rule_builder
+                        .clone()
+                        .direction(pfctl::Direction::Out)
+                        .from(pfctl::Port::from(client_port))
+                        .to(pfctl::Endpoint::new(net, pfctl::Port::from(server_port)))
+                        .build()?,
+                );
+                rules.push(
+                    rule_builder
+                        .direction(pfctl::Direction::In)
+                        .from(pfctl::Endpoint::new(net, pfctl::Port::from(server_port)))
+                        .to(pfctl::Port::from(client_port))
+                        .build()?,
+                );
+            }
+        }
+        Ok(rules)
+    }
+
     fn get_allow_ndp_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
         let mut ndp_rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
         ndp_rule_builder
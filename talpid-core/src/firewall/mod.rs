@@ -8,6 +8,10 @@ use std::{
     fmt,
     net::{Ipv4Addr, Ipv6Addr},
 };
+#[cfg(target_os = "linux")]
+use talpid_types::cgroup::SplitTunnelMode;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use talpid_types::net::AllowedLinkConfigTraffic;
 use talpid_types::net::{AllowedEndpoint, AllowedTunnelTraffic, Endpoint};
 
 #[cfg(target_os = "macos")]
@@ -57,6 +61,32 @@ lazy_static! {
         // Site-local IPv6 multicast.
         IpNetwork::V6(Ipv6Network::new(Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 0), 16).unwrap()),
     ];
+    /// Destinations of outgoing IGMP and MLD messages, which the host sends to join and leave
+    /// multicast groups. These are needed for the groups in `allowed_multicast_groups`.
+    #[cfg(all(unix, not(target_os = "android")))]
+    static ref MULTICAST_MEMBERSHIP_REPORT_ADDRS: [IpAddr; 4] = [
+        // IGMPv3 membership reports
+        IpAddr::V4(Ipv4Addr::new(224, 0, 0, 22)),
+        // IGMPv2 leave group messages
+        IpAddr::V4(Ipv4Addr::new(224, 0, 0, 2)),
+        // MLDv2 listener reports
+        IpAddr::V6(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x16)),
+        // MLDv1 listener done messages
+        IpAddr::V6(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2)),
+    ];
+    /// Destinations of incoming IGMP and MLD general queries, which routers use to find out which
+    /// groups the host still belongs to.
+    #[cfg(all(unix, not(target_os = "android")))]
+    static ref MULTICAST_MEMBERSHIP_QUERY_ADDRS: [IpAddr; 2] = [
+        IpAddr::V4(Ipv4Addr::new(224, 0, 0, 1)),
+        IpAddr::V6(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1)),
+    ];
+    /// mDNS group addresses. Responders may answer queries sent to these with unicast packets.
+    #[cfg(all(unix, not(target_os = "android")))]
+    static ref MDNS_ADDRS: [IpAddr; 2] = [
+        IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)),
+        IpAddr::V6(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb)),
+    ];
     static ref IPV6_LINK_LOCAL: Ipv6Network = Ipv6Network::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10).unwrap();
     /// The allowed target addresses of outbound DHCPv6 requests
     static ref DHCPV6_SERVER_ADDRS: [Ipv6Addr; 2] = [
@@ -78,8 +108,20 @@ const DHCPV4_CLIENT_PORT: u16 = 68;
 const DHCPV6_SERVER_PORT: u16 = 547;
 #[cfg(all(unix, not(target_os = "android")))]
 const DHCPV6_CLIENT_PORT: u16 = 546;
+
+/// Networks that DHCP messages sent directly to a server may be exchanged with. DHCP servers are
+/// always on the local network, so there's no reason to allow these ports to public addresses.
+#[cfg(all(unix, not(target_os = "android")))]
+fn dhcp_unicast_nets(ipv6: bool) -> impl Iterator<Item = IpNetwork> {
+    ALLOWED_LAN_NETS
+        .iter()
+        .copied()
+        .filter(move |net| net.is_ipv6() == ipv6)
+}
 #[cfg(all(unix, not(target_os = "android")))]
 const ROOT_UID: u32 = 0;
+#[cfg(all(unix, not(target_os = "android")))]
+const MDNS_PORT: u16 = 5353;
 
 #[cfg(any(all(unix, not(target_os = "android")), target_os = "windows"))]
 /// Returns whether an address belongs to a private subnet.
@@ -91,6 +133,22 @@ pub fn is_local_address(address: &IpAddr) -> bool {
         .any(|net| net.contains(address))
 }
 
+/// Returns the hosts that are private or link-local addresses. Any other host is logged and
+/// skipped, since allowing it would let traffic to the internet bypass the tunnel.
+#[cfg(not(target_os = "android"))]
+fn lan_hosts(hosts: &[IpAddr]) -> impl Iterator<Item = IpAddr> + '_ {
+    hosts.iter().copied().filter(|host| {
+        let is_lan = talpid_types::net::is_lan_address(host);
+        if !is_lan {
+            log::warn!(
+                "Ignoring allowed LAN host {} since it is not a LAN address",
+                host
+            );
+        }
+        is_lan
+    })
+}
+
 /// A enum that describes network security strategy
 ///
 /// # Firewall block/allow specification.
@@ -111,6 +169,18 @@ pub enum FirewallPolicy {
         allowed_endpoint: AllowedEndpoint,
         /// Networks for which to permit in-tunnel traffic.
         allowed_tunnel_traffic: AllowedTunnelTraffic,
+        /// Kinds of interface configuration traffic that are allowed on all interfaces.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        allowed_link_config_traffic: AllowedLinkConfigTraffic,
+        /// Hosts on the local network that are reachable even if `allow_lan` is false.
+        #[cfg(not(target_os = "android"))]
+        allowed_lan_hosts: Vec<IpAddr>,
+        /// Whether split tunneling excludes processes from the tunnel or only lets some use it.
+        #[cfg(target_os = "linux")]
+        split_tunnel_mode: SplitTunnelMode,
+        /// Interface whose devices may use the tunnel through this device, if any.
+        #[cfg(target_os = "linux")]
+        shared_interface: Option<String>,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -127,6 +197,22 @@ pub enum FirewallPolicy {
         /// Servers that are allowed to respond to DNS requests.
         #[cfg(not(target_os = "android"))]
         dns_servers: Vec<IpAddr>,
+        /// Multicast groups that may be sent to and received from on any interface, regardless
+        /// of `allow_lan`.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        allowed_multicast_groups: Vec<IpAddr>,
+        /// Kinds of interface configuration traffic that are allowed on all interfaces.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        allowed_link_config_traffic: AllowedLinkConfigTraffic,
+        /// Hosts on the local network that are reachable even if `allow_lan` is false.
+        #[cfg(not(target_os = "android"))]
+        allowed_lan_hosts: Vec<IpAddr>,
+        /// Whether split tunneling excludes processes from the tunnel or only lets some use it.
+        #[cfg(target_os = "linux")]
+        split_tunnel_mode: SplitTunnelMode,
+        /// Interface whose devices may use the tunnel through this device, if any.
+        #[cfg(target_os = "linux")]
+        shared_interface: Option<String>,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -138,6 +224,18 @@ pub enum FirewallPolicy {
         allow_lan: bool,
         /// Host that should be reachable while in the blocked state.
         allowed_endpoint: Option<AllowedEndpoint>,
+        /// Kinds of interface configuration traffic that are allowed on all interfaces.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        allowed_link_config_traffic: AllowedLinkConfigTraffic,
+        /// Hosts on the local network that are reachable even if `allow_lan` is false.
+        #[cfg(not(target_os = "android"))]
+        allowed_lan_hosts: Vec<IpAddr>,
+        /// Whether split tunneling excludes processes from the tunnel or only lets some use it.
+        #[cfg(target_os = "linux")]
+        split_tunnel_mode: SplitTunnelMode,
+        /// Interface whose devices may use the tunnel through this device, if any.
+        #[cfg(target_os = "linux")]
+        shared_interface: Option<String>,
         /// Desination port for DNS traffic redirection. Traffic destined to `127.0.0.1:53` will be
         /// redirected to `127.0.0.1:$dns_redirect_port`.
         #[cfg(target_os = "macos")]
@@ -145,6 +243,76 @@ pub enum FirewallPolicy {
     },
 }
 
+impl FirewallPolicy {
+    /// Returns the interface configuration traffic that the policy allows.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn allowed_link_config_traffic(&self) -> AllowedLinkConfigTraffic {
+        match self {
+            FirewallPolicy::Connecting {
+                allowed_link_config_traffic,
+                ..
+            }
+            | FirewallPolicy::Connected {
+                allowed_link_config_traffic,
+                ..
+            }
+            | FirewallPolicy::Blocked {
+                allowed_link_config_traffic,
+                ..
+            } => *allowed_link_config_traffic,
+        }
+    }
+
+    /// Returns the local hosts that the policy allows communication with regardless of
+    /// `allow_lan`.
+    #[cfg(not(target_os = "android"))]
+    pub fn allowed_lan_hosts(&self) -> &[IpAddr] {
+        match self {
+            FirewallPolicy::Connecting {
+                allowed_lan_hosts, ..
+            }
+            | FirewallPolicy::Connected {
+                allowed_lan_hosts, ..
+            }
+            | FirewallPolicy::Blocked {
+                allowed_lan_hosts, ..
+            } => allowed_lan_hosts,
+        }
+    }
+
+    /// Returns which processes split tunneling lets bypass the tunnel.
+    #[cfg(target_os = "linux")]
+    pub fn split_tunnel_mode(&self) -> SplitTunnelMode {
+        match self {
+            FirewallPolicy::Connecting {
+                split_tunnel_mode, ..
+            }
+            | FirewallPolicy::Connected {
+                split_tunnel_mode, ..
+            }
+            | FirewallPolicy::Blocked {
+                split_tunnel_mode, ..
+            } => *split_tunnel_mode,
+        }
+    }
+
+    /// Returns the interface that the tunnel is shared with, if any.
+    #[cfg(target_os = "linux")]
+    pub fn shared_interface(&self) -> Option<&str> {
+        match self {
+            FirewallPolicy::Connecting {
+                shared_interface, ..
+            }
+            | FirewallPolicy::Connected {
+                shared_interface, ..
+            }
+            | FirewallPolicy::Blocked {
+                shared_interface, ..
+            } => shared_interface.as_deref(),
+        }
+    }
+}
+
 impl fmt::Display for FirewallPolicy {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -224,7 +392,29 @@ impl fmt::Display for FirewallPolicy {
 /// Manages network security of the computer/device. Can apply and enforce firewall policies
 /// by manipulating the OS firewall and DNS settings.
 pub struct Firewall {
-    inner: imp::Firewall,
+    /// `None` when the fake tunnel backend is in use and no policies should be enforced.
+    inner: Option<Box<dyn FirewallPolicyApplier>>,
+}
+
+/// Enforces firewall policies on behalf of a [`Firewall`]. This is implemented by the system
+/// firewall, and can be replaced to run the tunnel state machine without changing the firewall
+/// rules of the host, e.g. in tests.
+pub trait FirewallPolicyApplier: Send {
+    /// Applies and starts enforcing `policy`, replacing any policy that was applied before.
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error>;
+
+    /// Removes any policy that is currently enforced.
+    fn reset_policy(&mut self) -> Result<(), Error>;
+}
+
+impl FirewallPolicyApplier for imp::Firewall {
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error> {
+        imp::Firewall::apply_policy(self, policy)
+    }
+
+    fn reset_policy(&mut self) -> Result<(), Error> {
+        imp::Firewall::reset_policy(self)
+    }
 }
 
 /// Arguments required when first initializing the firewall.
@@ -250,18 +440,32 @@ pub enum InitialFirewallState {
 impl Firewall {
     /// Creates a firewall instance with the given arguments.
     pub fn from_args(args: FirewallArguments) -> Result<Self, Error> {
+        if crate::tunnel::fake::is_enabled() {
+            return Ok(Firewall { inner: None });
+        }
         Ok(Firewall {
-            inner: imp::Firewall::from_args(args)?,
+            inner: Some(Box::new(imp::Firewall::from_args(args)?)),
         })
     }
 
+    /// Creates a firewall that enforces policies through `applier` instead of the system
+    /// firewall. No initial policy is applied.
+    pub fn from_applier(applier: Box<dyn FirewallPolicyApplier>) -> Self {
+        Firewall {
+            inner: Some(applier),
+        }
+    }
+
     /// Createsa new firewall instance.
     pub fn new(#[cfg(target_os = "linux")] fwmark: u32) -> Result<Self, Error> {
+        if crate::tunnel::fake::is_enabled() {
+            return Ok(Firewall { inner: None });
+        }
         Ok(Firewall {
-            inner: imp::Firewall::new(
+            inner: Some(Box::new(imp::Firewall::new(
                 #[cfg(target_os = "linux")]
                 fwmark,
-            )?,
+            )?)),
         })
     }
 
@@ -269,13 +473,54 @@ impl Firewall {
     /// until this method is called again with another policy, or until `reset_policy` is called.
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error> {
         log::info!("Applying firewall policy: {}", policy);
-        self.inner.apply_policy(policy)
+        match self.inner.as_mut() {
+            Some(inner) => inner.apply_policy(policy),
+            None => Ok(()),
+        }
     }
 
     /// Resets/removes any currently enforced `FirewallPolicy`. Returns the system to the same state
     /// it had before any policy was applied through this `Firewall` instance.
     pub fn reset_policy(&mut self) -> Result<(), Error> {
         log::info!("Resetting firewall policy");
-        self.inner.reset_policy()
+        match self.inner.as_mut() {
+            Some(inner) => inner.reset_policy(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(all(test, unix, not(target_os = "android")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dhcp_unicast_nets_are_private() {
+        let public_v4: IpAddr = "1.1.1.1".parse().unwrap();
+        let public_v6: IpAddr = "2606:4700:4700::1111".parse().unwrap();
+        let private_v4: IpAddr = "192.168.1.1".parse().unwrap();
+        let link_local_v6: IpAddr = "fe80::1".parse().unwrap();
+
+        assert!(dhcp_unicast_nets(false).all(|net| net.is_ipv4()));
+        assert!(dhcp_unicast_nets(true).all(|net| net.is_ipv6()));
+        assert!(!dhcp_unicast_nets(false).any(|net| net.contains(public_v4)));
+        assert!(!dhcp_unicast_nets(true).any(|net| net.contains(public_v6)));
+        assert!(dhcp_unicast_nets(false).any(|net| net.contains(private_v4)));
+        assert!(dhcp_unicast_nets(true).any(|net| net.contains(link_local_v6)));
+    }
+
+    #[test]
+    fn test_lan_hosts_skips_public_addresses() {
+        let hosts: Vec<IpAddr> = vec![
+            "192.168.1.10".parse().unwrap(),
+            "1.1.1.1".parse().unwrap(),
+            "fe80::1".parse().unwrap(),
+            "2606:4700:4700::1111".parse().unwrap(),
+        ];
+
+        assert_eq!(
+            lan_hosts(&hosts).collect::<Vec<_>>(),
+            vec![hosts[0], hosts[2]]
+        );
     }
 }
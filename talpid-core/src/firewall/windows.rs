@@ -3,7 +3,7 @@ use crate::tunnel::TunnelMetadata;
 use std::{ffi::CStr, io, net::IpAddr, path::Path, ptr};
 
 use self::winfw::*;
-use super::{FirewallArguments, FirewallPolicy, InitialFirewallState};
+use super::{lan_hosts, FirewallArguments, FirewallPolicy, InitialFirewallState};
 use talpid_types::{
     net::{AllowedEndpoint, AllowedTunnelTraffic, Endpoint},
     tunnel::FirewallPolicyError,
@@ -75,12 +75,12 @@ impl Firewall {
         allowed_endpoint: AllowedEndpoint,
         allow_lan: bool,
     ) -> Result<Self, Error> {
-        let cfg = &WinFwSettings::new(allow_lan);
+        let cfg = WinFwSettingsContainer::new(allow_lan, &[]);
         let allowed_endpoint = WinFwAllowedEndpointContainer::from(allowed_endpoint);
         unsafe {
             WinFw_InitializeBlocked(
                 WINFW_TIMEOUT_SECONDS,
-                &cfg,
+                &cfg.as_settings(),
                 &allowed_endpoint.as_endpoint(),
                 Some(log_sink),
                 LOGGING_CONTEXT.as_ptr(),
@@ -99,13 +99,14 @@ impl Firewall {
                 allow_lan,
                 allowed_endpoint,
                 allowed_tunnel_traffic,
+                allowed_lan_hosts,
                 relay_client,
             } => {
-                let cfg = &WinFwSettings::new(allow_lan);
+                let cfg = WinFwSettingsContainer::new(allow_lan, &allowed_lan_hosts);
 
                 self.set_connecting_state(
                     &peer_endpoint,
-                    &cfg,
+                    &cfg.as_settings(),
                     &tunnel,
                     &WinFwAllowedEndpointContainer::from(allowed_endpoint).as_endpoint(),
                     &allowed_tunnel_traffic,
@@ -117,18 +118,26 @@ impl Firewall {
                 tunnel,
                 allow_lan,
                 dns_servers,
+                allowed_lan_hosts,
                 relay_client,
             } => {
-                let cfg = &WinFwSettings::new(allow_lan);
-                self.set_connected_state(&peer_endpoint, &cfg, &tunnel, &dns_servers, &relay_client)
+                let cfg = WinFwSettingsContainer::new(allow_lan, &allowed_lan_hosts);
+                self.set_connected_state(
+                    &peer_endpoint,
+                    &cfg.as_settings(),
+                    &tunnel,
+                    &dns_servers,
+                    &relay_client,
+                )
             }
             FirewallPolicy::Blocked {
                 allow_lan,
                 allowed_endpoint,
+                allowed_lan_hosts,
             } => {
-                let cfg = &WinFwSettings::new(allow_lan);
+                let cfg = WinFwSettingsContainer::new(allow_lan, &allowed_lan_hosts);
                 self.set_blocked_state(
-                    &cfg,
+                    &cfg.as_settings(),
                     allowed_endpoint.map(|endpoint| WinFwAllowedEndpointContainer::from(endpoint)),
                 )
             }
@@ -143,7 +152,7 @@ impl Firewall {
     fn set_connecting_state(
         &mut self,
         endpoint: &Endpoint,
-        winfw_settings: &WinFwSettings,
+        winfw_settings: &WinFwSettings<'_>,
         tunnel_metadata: &Option<TunnelMetadata>,
         allowed_endpoint: &WinFwAllowedEndpoint<'_>,
         allowed_tunnel_traffic: &AllowedTunnelTraffic,
@@ -205,7 +214,7 @@ impl Firewall {
     fn set_connected_state(
         &mut self,
         endpoint: &Endpoint,
-        winfw_settings: &WinFwSettings,
+        winfw_settings: &WinFwSettings<'_>,
         tunnel_metadata: &TunnelMetadata,
         dns_servers: &[IpAddr],
         relay_client: &Path,
@@ -255,7 +264,7 @@ impl Firewall {
 
     fn set_blocked_state(
         &mut self,
-        winfw_settings: &WinFwSettings,
+        winfw_settings: &WinFwSettings<'_>,
         allowed_endpoint: Option<WinFwAllowedEndpointContainer>,
     ) -> Result<(), Error> {
         log::trace!("Applying 'blocked' firewall policy");
@@ -375,6 +384,7 @@ fn multibyte_to_wide(mb_string: &CStr, codepage: u32) -> Result<Vec<u16>, io::Er
 mod winfw {
     use super::{widestring_ip, AllowedEndpoint, AllowedTunnelTraffic, Error, WideCString};
     use libc;
+    use std::{net::IpAddr, ptr};
     use talpid_types::net::TransportProtocol;
 
     type LogSink =
@@ -386,6 +396,10 @@ mod winfw {
         ip: WideCString,
         port: u16,
         protocol: WinFwProt,
+        _extra_ip: Option<WideCString>,
+        extra_endpoint: Option<WinFwEndpoint>,
+        _resolver_ips: Box<[WideCString]>,
+        resolvers: Box<[WinFwEndpoint]>,
     }
 
     impl From<AllowedEndpoint> for WinFwAllowedEndpointContainer {
@@ -400,6 +414,33 @@ mod winfw {
                 .map(|client| client.as_ptr())
                 .collect::<Box<_>>();
             let ip = widestring_ip(endpoint.endpoint.address.ip());
+            let extra_ip = endpoint
+                .extra_endpoint
+                .map(|extra| widestring_ip(extra.address.ip()));
+            let extra_endpoint =
+                endpoint
+                    .extra_endpoint
+                    .zip(extra_ip.as_ref())
+                    .map(|(extra, ip)| WinFwEndpoint {
+                        ip: ip.as_ptr(),
+                        port: extra.address.port(),
+                        protocol: WinFwProt::from(extra.protocol),
+                    });
+            let resolver_ips = endpoint
+                .resolvers
+                .iter()
+                .map(|resolver| widestring_ip(resolver.address.ip()))
+                .collect::<Box<_>>();
+            let resolvers = endpoint
+                .resolvers
+                .iter()
+                .zip(resolver_ips.iter())
+                .map(|(resolver, ip)| WinFwEndpoint {
+                    ip: ip.as_ptr(),
+                    port: resolver.address.port(),
+                    protocol: WinFwProt::from(resolver.protocol),
+                })
+                .collect::<Box<_>>();
 
             WinFwAllowedEndpointContainer {
                 _clients: clients,
@@ -407,6 +448,10 @@ mod winfw {
                 ip,
                 port: endpoint.endpoint.address.port(),
                 protocol: WinFwProt::from(endpoint.endpoint.protocol),
+                _extra_ip: extra_ip,
+                extra_endpoint,
+                _resolver_ips: resolver_ips,
+                resolvers,
             }
         }
     }
@@ -421,6 +466,13 @@ mod winfw {
                     port: self.port,
                     protocol: self.protocol,
                 },
+                extra_endpoint: self
+                    .extra_endpoint
+                    .as_ref()
+                    .map(|endpoint| endpoint as *const _)
+                    .unwrap_or(ptr::null()),
+                num_resolvers: self.resolvers.len() as u32,
+                resolvers: self.resolvers.as_ptr(),
 
                 _phantom: std::marker::PhantomData,
             }
@@ -432,6 +484,9 @@ mod winfw {
         num_clients: u32,
         clients: *const *const libc::wchar_t,
         endpoint: WinFwEndpoint,
+        extra_endpoint: *const WinFwEndpoint,
+        num_resolvers: u32,
+        resolvers: *const WinFwEndpoint,
 
         _phantom: std::marker::PhantomData<&'a WinFwAllowedEndpointContainer>,
     }
@@ -483,21 +538,50 @@ mod winfw {
         }
     }
 
-    #[repr(C)]
-    pub struct WinFwSettings {
-        permitDhcp: bool,
-        permitLan: bool,
+    pub struct WinFwSettingsContainer {
+        permit_lan: bool,
+        _lan_hosts: Box<[WideCString]>,
+        lan_hosts_ptrs: Box<[*const u16]>,
     }
 
-    impl WinFwSettings {
-        pub fn new(permit_lan: bool) -> WinFwSettings {
+    impl WinFwSettingsContainer {
+        pub fn new(permit_lan: bool, lan_hosts: &[IpAddr]) -> Self {
+            let lan_hosts = super::lan_hosts(lan_hosts)
+                .map(widestring_ip)
+                .collect::<Box<_>>();
+            let lan_hosts_ptrs = lan_hosts
+                .iter()
+                .map(|host| host.as_ptr())
+                .collect::<Box<_>>();
+            WinFwSettingsContainer {
+                permit_lan,
+                _lan_hosts: lan_hosts,
+                lan_hosts_ptrs,
+            }
+        }
+
+        pub fn as_settings(&self) -> WinFwSettings<'_> {
             WinFwSettings {
                 permitDhcp: true,
-                permitLan: permit_lan,
+                permitLan: self.permit_lan,
+                numAllowedLanHosts: self.lan_hosts_ptrs.len() as u32,
+                allowedLanHosts: self.lan_hosts_ptrs.as_ptr(),
+
+                _phantom: std::marker::PhantomData,
             }
         }
     }
 
+    #[repr(C)]
+    pub struct WinFwSettings<'a> {
+        permitDhcp: bool,
+        permitLan: bool,
+        numAllowedLanHosts: u32,
+        allowedLanHosts: *const *const libc::wchar_t,
+
+        _phantom: std::marker::PhantomData<&'a WinFwSettingsContainer>,
+    }
+
     #[allow(dead_code)]
     #[repr(u32)]
     #[derive(Clone, Copy)]
@@ -548,7 +632,7 @@ mod winfw {
         #[link_name = "WinFw_InitializeBlocked"]
         pub fn WinFw_InitializeBlocked(
             timeout: libc::c_uint,
-            settings: &WinFwSettings,
+            settings: &WinFwSettings<'_>,
             allowed_endpoint: *const WinFwAllowedEndpoint<'_>,
             sink: Option<LogSink>,
             sink_context: *const u8,
@@ -559,7 +643,7 @@ mod winfw {
 
         #[link_name = "WinFw_ApplyPolicyConnecting"]
         pub fn WinFw_ApplyPolicyConnecting(
-            settings: &WinFwSettings,
+            settings: &WinFwSettings<'_>,
             relay: &WinFwEndpoint,
             relayClient: *const libc::wchar_t,
             tunnelIfaceAlias: *const libc::wchar_t,
@@ -569,7 +653,7 @@ mod winfw {
 
         #[link_name = "WinFw_ApplyPolicyConnected"]
         pub fn WinFw_ApplyPolicyConnected(
-            settings: &WinFwSettings,
+            settings: &WinFwSettings<'_>,
             relay: &WinFwEndpoint,
             relayClient: *const libc::wchar_t,
             tunnelIfaceAlias: *const libc::wchar_t,
@@ -581,7 +665,7 @@ mod winfw {
 
         #[link_name = "WinFw_ApplyPolicyBlocked"]
         pub fn WinFw_ApplyPolicyBlocked(
-            settings: &WinFwSettings,
+            settings: &WinFwSettings<'_>,
             allowed_endpoint: *const WinFwAllowedEndpoint<'_>,
         ) -> WinFwPolicyStatus;
 
@@ -120,7 +120,7 @@ impl<I: Iterator<Item = Duration>> Iterator for Jittered<I> {
 }
 
 /// Apply a jitter to a duration.
-fn jitter(dur: Duration) -> Duration {
+pub(crate) fn jitter(dur: Duration) -> Duration {
     apply_jitter(dur, rand::thread_rng().sample(OpenClosed01))
 }
 
@@ -15,6 +15,13 @@ pub mod window;
 
 mod offline;
 
+/// Notifications about the system going to sleep and waking up.
+#[cfg(not(target_os = "android"))]
+mod power;
+
+/// Queries the routes used by traffic outside the tunnel.
+pub mod default_route;
+
 /// Split tunneling
 pub mod split_tunnel;
 
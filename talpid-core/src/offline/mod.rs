@@ -1,4 +1,11 @@
-use futures::channel::mpsc::UnboundedSender;
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    FutureExt, StreamExt,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 #[cfg(any(target_os = "linux", target_os = "windows"))]
 use talpid_routing::RouteManagerHandle;
 #[cfg(target_os = "android")]
@@ -28,28 +35,44 @@ lazy_static::lazy_static! {
 }
 
 pub use self::imp::Error;
+#[cfg(target_os = "linux")]
+pub(crate) use self::imp::{PUBLIC_INTERNET_ADDRESS_V4, PUBLIC_INTERNET_ADDRESS_V6};
 
-pub struct MonitorHandle(Option<imp::MonitorHandle>);
+pub struct MonitorHandle {
+    monitor: Option<imp::MonitorHandle>,
+    debounce_delay: Arc<Mutex<Duration>>,
+}
 
 impl MonitorHandle {
     pub async fn host_is_offline(&self) -> bool {
-        match self.0.as_ref() {
+        match self.monitor.as_ref() {
             Some(monitor) => monitor.host_is_offline().await,
             None => false,
         }
     }
+
+    /// Sets how long the offline state must stay the same before a change is reported. It
+    /// applies from the next change. Zero reports every change at once.
+    pub fn set_debounce_delay(&self, delay: Duration) {
+        *self.debounce_delay.lock().unwrap() = delay;
+    }
 }
 
+/// Starts reporting changes to the offline state to `sender`. Connectivity often flaps when
+/// roaming between access points or when docking, so changes are only reported once they have
+/// lasted for `debounce_delay`.
 pub async fn spawn_monitor(
     sender: UnboundedSender<bool>,
+    debounce_delay: Duration,
     #[cfg(any(target_os = "linux", target_os = "windows"))] route_manager: RouteManagerHandle,
     #[cfg(target_os = "linux")] fwmark: Option<u32>,
     #[cfg(target_os = "android")] android_context: AndroidContext,
 ) -> Result<MonitorHandle, Error> {
+    let debounce_delay = Arc::new(Mutex::new(debounce_delay));
     let monitor = if !*FORCE_DISABLE_OFFLINE_MONITOR {
         Some(
             imp::spawn_monitor(
-                sender,
+                debounce(sender, debounce_delay.clone()),
                 #[cfg(any(target_os = "windows", target_os = "linux"))]
                 route_manager,
                 #[cfg(target_os = "linux")]
@@ -63,5 +86,82 @@ pub async fn spawn_monitor(
         None
     };
 
-    Ok(MonitorHandle(monitor))
+    Ok(MonitorHandle {
+        monitor,
+        debounce_delay,
+    })
+}
+
+/// Returns a sender whose offline state changes are forwarded to `sender` once they have stayed the
+/// same for `delay`. Changes that end up where the last reported state was are dropped.
+fn debounce(sender: UnboundedSender<bool>, delay: Arc<Mutex<Duration>>) -> UnboundedSender<bool> {
+    let (tx, mut rx) = mpsc::unbounded();
+    tokio::spawn(async move {
+        let mut reported = None;
+        while let Some(mut is_offline) = rx.next().await {
+            // The monitors only report changes, so the state before the first one is known
+            let previous = *reported.get_or_insert(!is_offline);
+            let delay = *delay.lock().unwrap();
+            while !delay.is_zero() {
+                futures::select! {
+                    new_state = rx.next() => match new_state {
+                        Some(new_state) => is_offline = new_state,
+                        None => return,
+                    },
+                    _ = Box::pin(talpid_time::sleep(delay)).fuse() => break,
+                }
+            }
+            if is_offline == previous {
+                log::debug!(
+                    "Ignoring connectivity changes that were undone within {:?}",
+                    delay
+                );
+                continue;
+            }
+            reported = Some(is_offline);
+            if sender.unbounded_send(is_offline).is_err() {
+                return;
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DELAY: Duration = Duration::from_millis(100);
+    const WAIT: Duration = Duration::from_millis(500);
+
+    #[tokio::test]
+    async fn test_debounce() {
+        let (tx, mut rx) = mpsc::unbounded();
+        let delay = Arc::new(Mutex::new(DELAY));
+        let debounced = debounce(tx, delay.clone());
+
+        // Changes that are undone before the delay has passed are not reported
+        debounced.unbounded_send(true).unwrap();
+        debounced.unbounded_send(false).unwrap();
+        tokio::time::sleep(WAIT).await;
+        assert!(rx.try_next().is_err());
+
+        // A burst of changes is reported once, with the state that it settled on
+        debounced.unbounded_send(true).unwrap();
+        debounced.unbounded_send(false).unwrap();
+        debounced.unbounded_send(true).unwrap();
+        let reported = tokio::time::timeout(WAIT, rx.next()).await.unwrap();
+        assert_eq!(reported, Some(true));
+        tokio::time::sleep(WAIT).await;
+        assert!(rx.try_next().is_err());
+
+        // Without a delay, every change is reported
+        *delay.lock().unwrap() = Duration::ZERO;
+        debounced.unbounded_send(false).unwrap();
+        debounced.unbounded_send(true).unwrap();
+        let reported = tokio::time::timeout(WAIT, rx.next()).await.unwrap();
+        assert_eq!(reported, Some(false));
+        let reported = tokio::time::timeout(WAIT, rx.next()).await.unwrap();
+        assert_eq!(reported, Some(true));
+    }
 }
@@ -1,12 +1,10 @@
 use talpid_routing::{get_best_default_route, CallbackHandle, EventType, RouteManagerHandle};
 
-use crate::window::{PowerManagementEvent, PowerManagementListener};
 use futures::channel::mpsc::UnboundedSender;
 use parking_lot::Mutex;
 use std::{
     io,
     sync::{Arc, Weak},
-    time::Duration,
 };
 use talpid_types::ErrorExt;
 use talpid_windows_net::AddressFamily;
@@ -31,40 +29,15 @@ impl BroadcastListener {
     pub async fn start(
         notify_tx: UnboundedSender<bool>,
         route_manager_handle: RouteManagerHandle,
-        mut power_mgmt_rx: PowerManagementListener,
     ) -> Result<Self, Error> {
         let notify_tx = Arc::new(notify_tx);
         let (v4_connectivity, v6_connectivity) = Self::check_initial_connectivity();
         let system_state = Arc::new(Mutex::new(SystemState {
             v4_connectivity,
             v6_connectivity,
-            suspended: false,
             notify_tx: Arc::downgrade(&notify_tx),
         }));
 
-        let state = system_state.clone();
-        tokio::spawn(async move {
-            while let Some(event) = power_mgmt_rx.next().await {
-                match event {
-                    PowerManagementEvent::Suspend => {
-                        log::debug!("Machine is preparing to enter sleep mode");
-                        apply_system_state_change(state.clone(), StateChange::Suspended(true));
-                    }
-                    PowerManagementEvent::ResumeAutomatic => {
-                        let state_copy = state.clone();
-                        tokio::spawn(async move {
-                            // Tunnel will be unavailable for approximately 2 seconds on a healthy
-                            // machine.
-                            tokio::time::sleep(Duration::from_secs(5)).await;
-                            log::debug!("Tunnel device is presumed to have been re-initialized");
-                            apply_system_state_change(state_copy, StateChange::Suspended(false));
-                        });
-                    }
-                    _ => (),
-                }
-            }
-        });
-
         let callback_handle =
             Self::setup_network_connectivity_listener(system_state.clone(), route_manager_handle)
                 .await?;
@@ -148,13 +121,11 @@ impl BroadcastListener {
 enum StateChange {
     NetworkV4Connectivity(bool),
     NetworkV6Connectivity(bool),
-    Suspended(bool),
 }
 
 struct SystemState {
     v4_connectivity: bool,
     v6_connectivity: bool,
-    suspended: bool,
     notify_tx: Weak<UnboundedSender<bool>>,
 }
 
@@ -169,10 +140,6 @@ impl SystemState {
             StateChange::NetworkV6Connectivity(connectivity) => {
                 self.v6_connectivity = connectivity;
             }
-
-            StateChange::Suspended(suspended) => {
-                self.suspended = suspended;
-            }
         };
 
         let new_state = self.is_offline_currently();
@@ -187,7 +154,7 @@ impl SystemState {
     }
 
     fn is_offline_currently(&self) -> bool {
-        (!self.v4_connectivity && !self.v6_connectivity) || self.suspended
+        !self.v4_connectivity && !self.v6_connectivity
     }
 }
 
@@ -206,11 +173,5 @@ pub async fn spawn_monitor(
     sender: UnboundedSender<bool>,
     route_manager_handle: RouteManagerHandle,
 ) -> Result<MonitorHandle, Error> {
-    let power_mgmt_rx = crate::window::PowerManagementListener::new();
-    BroadcastListener::start(sender, route_manager_handle, power_mgmt_rx).await
-}
-
-fn apply_system_state_change(state: Arc<Mutex<SystemState>>, change: StateChange) {
-    let mut state = state.lock();
-    state.apply_change(change);
+    BroadcastListener::start(sender, route_manager_handle).await
 }
@@ -0,0 +1,15 @@
+use super::PowerEvent;
+use futures::channel::mpsc::UnboundedSender;
+
+pub use talpid_dbus::logind::Error;
+
+pub fn spawn_monitor(sender: UnboundedSender<PowerEvent>) -> Result<(), Error> {
+    talpid_dbus::logind::watch_sleep(move |going_to_sleep| {
+        let event = if going_to_sleep {
+            PowerEvent::Suspend
+        } else {
+            PowerEvent::Resume
+        };
+        let _ = sender.unbounded_send(event);
+    })
+}
@@ -0,0 +1,102 @@
+use super::PowerEvent;
+use futures::channel::mpsc::UnboundedSender;
+use std::{ffi::c_void, ptr, sync::mpsc as sync_mpsc, thread};
+use system_configuration::core_foundation::{
+    base::TCFType,
+    runloop::{kCFRunLoopCommonModes, CFRunLoop, CFRunLoopSource, CFRunLoopSourceRef},
+};
+
+type IoConnect = u32;
+type IoObject = u32;
+type IoNotificationPortRef = *mut c_void;
+type IoServiceInterestCallback =
+    extern "C" fn(refcon: *mut c_void, service: IoObject, message_type: u32, argument: *mut c_void);
+
+const IO_MESSAGE_CAN_SYSTEM_SLEEP: u32 = 0xe0000270;
+const IO_MESSAGE_SYSTEM_WILL_SLEEP: u32 = 0xe0000280;
+const IO_MESSAGE_SYSTEM_HAS_POWERED_ON: u32 = 0xe0000300;
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IORegisterForSystemPower(
+        refcon: *mut c_void,
+        port: *mut IoNotificationPortRef,
+        callback: IoServiceInterestCallback,
+        notifier: *mut IoObject,
+    ) -> IoConnect;
+    fn IONotificationPortGetRunLoopSource(port: IoNotificationPortRef) -> CFRunLoopSourceRef;
+    fn IOAllowPowerChange(kernel_port: IoConnect, notification_id: isize) -> i32;
+}
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to register for system power notifications")]
+    RegisterError,
+}
+
+/// State of the notification callback. It lives as long as the run loop, which runs forever.
+struct Context {
+    sender: UnboundedSender<PowerEvent>,
+    root_port: IoConnect,
+}
+
+pub fn spawn_monitor(sender: UnboundedSender<PowerEvent>) -> Result<(), Error> {
+    let (result_tx, result_rx) = sync_mpsc::channel();
+    thread::spawn(move || {
+        let context = Box::into_raw(Box::new(Context {
+            sender,
+            root_port: 0,
+        }));
+        let mut port = ptr::null_mut();
+        let mut notifier = 0;
+        let root_port = unsafe {
+            IORegisterForSystemPower(
+                context as *mut c_void,
+                &mut port,
+                power_callback,
+                &mut notifier,
+            )
+        };
+        if root_port == 0 {
+            drop(unsafe { Box::from_raw(context) });
+            let _ = result_tx.send(Err(Error::RegisterError));
+            return;
+        }
+        // Notifications are only delivered once the run loop is running
+        unsafe { (*context).root_port = root_port };
+
+        let run_loop_source = unsafe {
+            CFRunLoopSource::wrap_under_get_rule(IONotificationPortGetRunLoopSource(port))
+        };
+        CFRunLoop::get_current().add_source(&run_loop_source, unsafe { kCFRunLoopCommonModes });
+        let _ = result_tx.send(Ok(()));
+
+        CFRunLoop::run_current();
+        log::error!("Power management run loop exited! It should run forever");
+    });
+    result_rx.recv().unwrap_or(Err(Error::RegisterError))
+}
+
+/// Called by the run loop for every power management message. Sleep must be allowed explicitly,
+/// which is done once the event has been passed on.
+extern "C" fn power_callback(
+    refcon: *mut c_void,
+    _service: IoObject,
+    message_type: u32,
+    argument: *mut c_void,
+) {
+    let context = unsafe { &*(refcon as *const Context) };
+    match message_type {
+        IO_MESSAGE_CAN_SYSTEM_SLEEP => unsafe {
+            IOAllowPowerChange(context.root_port, argument as isize);
+        },
+        IO_MESSAGE_SYSTEM_WILL_SLEEP => {
+            let _ = context.sender.unbounded_send(PowerEvent::Suspend);
+            unsafe { IOAllowPowerChange(context.root_port, argument as isize) };
+        }
+        IO_MESSAGE_SYSTEM_HAS_POWERED_ON => {
+            let _ = context.sender.unbounded_send(PowerEvent::Resume);
+        }
+        _ => (),
+    }
+}
@@ -0,0 +1,69 @@
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    FutureExt, StreamExt,
+};
+use std::time::Duration;
+
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod imp;
+
+#[cfg(target_os = "windows")]
+#[path = "windows.rs"]
+mod imp;
+
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod imp;
+
+pub use self::imp::Error;
+
+/// Tunnel devices and network adapters are unavailable for approximately 2 seconds after the
+/// system wakes up on a healthy machine, so resuming is reported once this has passed.
+const RESUME_DELAY: Duration = Duration::from_secs(5);
+
+/// Changes to the power state of the system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerEvent {
+    /// The system is about to sleep or hibernate.
+    Suspend,
+    /// The system has woken up, and the network should be usable again.
+    Resume,
+}
+
+/// Starts sending power events to `sender`. A suspend that is not followed by a resume, because
+/// the system went to sleep again before [`RESUME_DELAY`] had passed, is only reported once.
+pub fn spawn_monitor(sender: UnboundedSender<PowerEvent>) -> Result<(), Error> {
+    let (tx, mut rx) = mpsc::unbounded();
+    imp::spawn_monitor(tx)?;
+
+    tokio::spawn(async move {
+        let mut suspended = false;
+        while let Some(event) = rx.next().await {
+            match event {
+                PowerEvent::Suspend if suspended => continue,
+                PowerEvent::Suspend => {
+                    log::debug!("Machine is preparing to enter sleep mode");
+                    suspended = true;
+                }
+                PowerEvent::Resume => {
+                    log::debug!("Machine is resuming from sleep mode");
+                    futures::select! {
+                        event = rx.next() => match event {
+                            Some(PowerEvent::Suspend) => continue,
+                            Some(PowerEvent::Resume) => (),
+                            None => return,
+                        },
+                        _ = Box::pin(talpid_time::sleep(RESUME_DELAY)).fuse() => (),
+                    }
+                    log::debug!("Network is presumed to have been re-initialized");
+                    suspended = false;
+                }
+            }
+            if sender.unbounded_send(event).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(())
+}
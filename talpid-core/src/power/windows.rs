@@ -0,0 +1,23 @@
+use super::PowerEvent;
+use crate::window::{PowerManagementEvent, PowerManagementListener};
+use futures::channel::mpsc::UnboundedSender;
+
+/// Power events are read from a hidden window, which cannot fail to be created.
+pub type Error = std::convert::Infallible;
+
+pub fn spawn_monitor(sender: UnboundedSender<PowerEvent>) -> Result<(), Error> {
+    let mut power_mgmt_rx = PowerManagementListener::new();
+    tokio::spawn(async move {
+        while let Some(event) = power_mgmt_rx.next().await {
+            let event = match event {
+                PowerManagementEvent::Suspend => PowerEvent::Suspend,
+                PowerManagementEvent::ResumeAutomatic => PowerEvent::Resume,
+                _ => continue,
+            };
+            if sender.unbounded_send(event).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
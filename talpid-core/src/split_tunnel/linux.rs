@@ -3,7 +3,9 @@ use std::{
     io::{self, BufRead, BufReader, Write},
     path::PathBuf,
 };
-use talpid_types::cgroup::{find_net_cls_mount, SPLIT_TUNNEL_CGROUP_NAME};
+use talpid_types::cgroup::{
+    find_net_cls_mount, SplitTunnelMode, SPLIT_TUNNEL_CGROUP_NAME, SPLIT_TUNNEL_INCLUDE_CGROUP_NAME,
+};
 
 const DEFAULT_NET_CLS_DIR: &str = "/sys/fs/cgroup/net_cls";
 const NET_CLS_DIR_OVERRIDE_ENV_VAR: &str = "TALPID_NET_CLS_MOUNT_DIR";
@@ -11,6 +13,9 @@ const NET_CLS_DIR_OVERRIDE_ENV_VAR: &str = "TALPID_NET_CLS_MOUNT_DIR";
 /// Identifies packets coming from the cgroup.
 /// This should be an arbitrary but unique integer.
 pub const NET_CLS_CLASSID: u32 = 0x4d9f41;
+/// Identifies packets coming from the cgroup of included processes.
+/// This should be an arbitrary but unique integer.
+pub const NET_CLS_INCLUDE_CLASSID: u32 = 0x4d9f42;
 /// Value used to mark packets and associated connections.
 /// This should be an arbitrary but unique integer.
 pub const MARK: i32 = 0xf41;
@@ -24,7 +29,7 @@ pub enum Error {
     InitNetClsCGroup(#[error(source)] nix::Error),
 
     /// Unable to create cgroup.
-    #[error(display = "Unable to create cgroup for excluded processes")]
+    #[error(display = "Unable to create cgroup for split tunneling")]
     CreateCGroup(#[error(source)] io::Error),
 
     /// Unable to set class ID for cgroup.
@@ -48,9 +53,11 @@ pub enum Error {
     ListMounts(#[error(source)] io::Error),
 }
 
-/// Manages PIDs in the Linux Cgroup excluded from the VPN tunnel.
+/// Manages PIDs in the Linux Cgroups excluded from or included in the VPN tunnel. PIDs are added
+/// to and listed from the cgroup of the current [`SplitTunnelMode`].
 pub struct PidManager {
     net_cls_path: PathBuf,
+    mode: SplitTunnelMode,
 }
 
 impl PidManager {
@@ -61,11 +68,27 @@ impl PidManager {
     pub fn new() -> Result<PidManager, Error> {
         let manager = PidManager {
             net_cls_path: Self::create_cgroup()?,
+            mode: SplitTunnelMode::default(),
         };
-        manager.setup_exclusion_group()?;
+        manager.setup_group(SPLIT_TUNNEL_CGROUP_NAME, NET_CLS_CLASSID)?;
+        manager.setup_group(SPLIT_TUNNEL_INCLUDE_CGROUP_NAME, NET_CLS_INCLUDE_CLASSID)?;
         Ok(manager)
     }
 
+    /// Selects the cgroup that PIDs are added to, removed from, and listed from. PIDs in the
+    /// cgroup of the other mode are left as they are.
+    pub fn set_mode(&mut self, mode: SplitTunnelMode) {
+        self.mode = mode;
+    }
+
+    fn group_path(&self) -> PathBuf {
+        let group_name = match self.mode {
+            SplitTunnelMode::Exclude => SPLIT_TUNNEL_CGROUP_NAME,
+            SplitTunnelMode::Include => SPLIT_TUNNEL_INCLUDE_CGROUP_NAME,
+        };
+        self.net_cls_path.join(group_name)
+    }
+
     /// Set up cgroup used to track PIDs for split tunneling.
     fn create_cgroup() -> Result<PathBuf, Error> {
         if let Some(net_cls_path) = find_net_cls_mount().map_err(Error::ListMounts)? {
@@ -93,23 +116,20 @@ impl PidManager {
         Ok(net_cls_dir)
     }
 
-    fn setup_exclusion_group(&self) -> Result<(), Error> {
-        let exclusions_dir = self.net_cls_path.join(SPLIT_TUNNEL_CGROUP_NAME);
-        if !exclusions_dir.exists() {
-            fs::create_dir(exclusions_dir.clone()).map_err(Error::CreateCGroup)?;
+    fn setup_group(&self, group_name: &str, classid: u32) -> Result<(), Error> {
+        let group_dir = self.net_cls_path.join(group_name);
+        if !group_dir.exists() {
+            fs::create_dir(group_dir.clone()).map_err(Error::CreateCGroup)?;
         }
 
-        let classid_path = exclusions_dir.join("net_cls.classid");
-        fs::write(classid_path, NET_CLS_CLASSID.to_string().as_bytes())
-            .map_err(Error::SetCGroupClassId)
+        let classid_path = group_dir.join("net_cls.classid");
+        fs::write(classid_path, classid.to_string().as_bytes()).map_err(Error::SetCGroupClassId)
     }
 
-    /// Add a PID to the Cgroup to have it excluded from the tunnel.
+    /// Add a PID to the Cgroup to have it excluded from the tunnel, or included in it in include
+    /// mode.
     pub fn add(&self, pid: i32) -> Result<(), Error> {
-        let exclusions_path = self
-            .net_cls_path
-            .join(SPLIT_TUNNEL_CGROUP_NAME)
-            .join("cgroup.procs");
+        let exclusions_path = self.group_path().join("cgroup.procs");
 
         let mut file = fs::OpenOptions::new()
             .write(true)
@@ -121,7 +141,7 @@ impl PidManager {
             .map_err(Error::AddCGroupPid)
     }
 
-    /// Remove a PID from the Cgroup to have it included in the tunnel.
+    /// Remove a PID from the Cgroup to give it the default behavior of the current mode.
     pub fn remove(&self, pid: i32) -> Result<(), Error> {
         // FIXME: We remove PIDs from our cgroup here by adding
         //        them to the parent cgroup. This seems wrong.
@@ -137,12 +157,9 @@ impl PidManager {
             .map_err(Error::RemoveCGroupPid)
     }
 
-    /// Return a list of all PIDs currently in the Cgroup excluded from the tunnel.
+    /// Return a list of all PIDs currently in the Cgroup of the current mode.
     pub fn list(&self) -> Result<Vec<i32>, Error> {
-        let exclusions_path = self
-            .net_cls_path
-            .join(SPLIT_TUNNEL_CGROUP_NAME)
-            .join("cgroup.procs");
+        let exclusions_path = self.group_path().join("cgroup.procs");
 
         let file = fs::File::open(exclusions_path).map_err(Error::ListCGroupPids)?;
 
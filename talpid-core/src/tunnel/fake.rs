@@ -0,0 +1,307 @@
+//! A simulated tunnel that never touches the network. It is only used when talpid-core is built
+//! with the `fake-tunnel` feature and the `TALPID_FAKE_TUNNEL` environment variable is set, and
+//! lets frontends and tests move the daemon through every tunnel state on machines where a real
+//! tunnel cannot be created.
+//!
+//! Set `TALPID_FAKE_TUNNEL_FAILED_ATTEMPTS` to a number of connection attempts that should fail
+//! before one succeeds, to simulate an unreachable relay.
+
+use super::{TunnelArgs, TunnelEvent, TunnelMetadata};
+use futures::{
+    channel::oneshot,
+    future::{self, Either},
+};
+use rand::Rng;
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::mpsc as sync_mpsc,
+    time::Duration,
+};
+use talpid_types::net::{AllowedTunnelTraffic, TunnelParameters};
+
+const FAKE_INTERFACE_NAME: &str = "fake-mullvad";
+
+/// Range, in milliseconds, of the simulated delay before the interface is created.
+const INTERFACE_DELAY_MS: (u64, u64) = (100, 400);
+/// Range, in milliseconds, of the simulated handshake duration.
+const HANDSHAKE_DELAY_MS: (u64, u64) = (300, 1500);
+/// Extra handshake delay added for each failed attempt, mimicking a slow or unreachable relay.
+const RETRY_PENALTY_MS: u64 = 250;
+/// How often the simulated traffic counters are updated.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[cfg(feature = "fake-tunnel")]
+lazy_static::lazy_static! {
+    /// Replaces the real tunnel, firewall and DNS backends with no-op simulations.
+    static ref FAKE_TUNNEL_ENABLED: bool = std::env::var("TALPID_FAKE_TUNNEL")
+        .map(|v| v != "0")
+        .unwrap_or(false);
+
+    /// Number of connection attempts that fail before the handshake succeeds.
+    static ref FAILED_ATTEMPTS: u32 = std::env::var("TALPID_FAKE_TUNNEL_FAILED_ATTEMPTS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+}
+
+/// Returns whether the fake tunnel backend should be used instead of the real one. This is always
+/// `false` unless the `fake-tunnel` feature is enabled, since the firewall and DNS are left
+/// untouched while it is used.
+pub fn is_enabled() -> bool {
+    #[cfg(feature = "fake-tunnel")]
+    {
+        *FAKE_TUNNEL_ENABLED
+    }
+    #[cfg(not(feature = "fake-tunnel"))]
+    {
+        false
+    }
+}
+
+#[cfg(feature = "fake-tunnel")]
+fn failed_attempts() -> u32 {
+    *FAILED_ATTEMPTS
+}
+
+#[cfg(not(feature = "fake-tunnel"))]
+fn failed_attempts() -> u32 {
+    0
+}
+
+/// Errors that can be simulated by the fake tunnel.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// The handshake was set up to fail.
+    #[error(display = "Simulated handshake failure")]
+    HandshakeFailed,
+}
+
+/// Simulated traffic counters for the fake tunnel.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+}
+
+/// Monitor for a simulated tunnel.
+pub struct FakeTunnelMonitor {
+    result_rx: sync_mpsc::Receiver<Result<Stats, Error>>,
+}
+
+impl FakeTunnelMonitor {
+    /// Starts a simulated tunnel. Events are sent to `on_event` with delays resembling those of a
+    /// real connection, and the tunnel stays up until `tunnel_close_rx` fires.
+    pub fn start<L>(params: &TunnelParameters, args: TunnelArgs<'_, L>) -> Self
+    where
+        L: (Fn(TunnelEvent) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>)
+            + Send
+            + Clone
+            + Sync
+            + 'static,
+    {
+        log::warn!("Starting fake tunnel. No traffic will be routed through a VPN");
+
+        let metadata = fake_metadata(params);
+        let allowed_traffic = AllowedTunnelTraffic::Only(params.get_next_hop_endpoint());
+        let fail = args.retry_attempt < failed_attempts();
+        let retry_penalty = Duration::from_millis(RETRY_PENALTY_MS * u64::from(args.retry_attempt));
+        let on_event = args.on_event;
+        let tunnel_close_rx = args.tunnel_close_rx;
+        let (result_tx, result_rx) = sync_mpsc::channel();
+
+        args.runtime.spawn(async move {
+            let result = simulate(
+                metadata,
+                allowed_traffic,
+                retry_penalty,
+                fail,
+                on_event,
+                tunnel_close_rx,
+            )
+            .await;
+            let _ = result_tx.send(result);
+        });
+
+        FakeTunnelMonitor { result_rx }
+    }
+
+    /// Blocks until the simulated tunnel has been closed.
+    pub fn wait(self) -> Result<(), Error> {
+        if let Ok(result) = self.result_rx.recv() {
+            let stats = result?;
+            log::debug!(
+                "Fake tunnel closed. Sent {} bytes, received {} bytes",
+                stats.tx_bytes,
+                stats.rx_bytes
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Moves the simulated tunnel through its events until it is closed, or until the handshake
+/// fails if `fail` is set.
+async fn simulate<L>(
+    metadata: TunnelMetadata,
+    allowed_traffic: AllowedTunnelTraffic,
+    retry_penalty: Duration,
+    fail: bool,
+    on_event: L,
+    mut tunnel_close_rx: oneshot::Receiver<()>,
+) -> Result<Stats, Error>
+where
+    L: (Fn(TunnelEvent) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>)
+        + Send
+        + Sync
+        + 'static,
+{
+    let result = async {
+        sleep_or_close(random_delay(INTERFACE_DELAY_MS), &mut tunnel_close_rx).await?;
+        (on_event)(TunnelEvent::InterfaceUp(metadata.clone(), allowed_traffic)).await;
+
+        sleep_or_close(
+            random_delay(HANDSHAKE_DELAY_MS) + retry_penalty,
+            &mut tunnel_close_rx,
+        )
+        .await?;
+        if fail {
+            return Some(Err(Error::HandshakeFailed));
+        }
+        (on_event)(TunnelEvent::Up(metadata)).await;
+
+        let mut stats = Stats::default();
+        loop {
+            if sleep_or_close(STATS_INTERVAL, &mut tunnel_close_rx)
+                .await
+                .is_none()
+            {
+                return Some(Ok(stats));
+            }
+            let mut rng = rand::thread_rng();
+            stats.tx_bytes += rng.gen_range(1_000..50_000);
+            stats.rx_bytes += rng.gen_range(5_000..500_000);
+        }
+    }
+    .await
+    .unwrap_or_else(|| Ok(Stats::default()));
+
+    (on_event)(TunnelEvent::Down).await;
+    result
+}
+
+/// Sleeps for `duration`. Returns `None` if the tunnel was closed before the delay elapsed.
+async fn sleep_or_close(
+    duration: Duration,
+    tunnel_close_rx: &mut oneshot::Receiver<()>,
+) -> Option<()> {
+    let sleep = Box::pin(tokio::time::sleep(duration));
+    match future::select(sleep, tunnel_close_rx).await {
+        Either::Left(_) => Some(()),
+        Either::Right(_) => None,
+    }
+}
+
+fn random_delay((min, max): (u64, u64)) -> Duration {
+    Duration::from_millis(rand::thread_rng().gen_range(min..max))
+}
+
+fn fake_metadata(params: &TunnelParameters) -> TunnelMetadata {
+    match params {
+        TunnelParameters::Wireguard(params) => TunnelMetadata {
+            interface: FAKE_INTERFACE_NAME.to_string(),
+            ips: params.connection.tunnel.addresses.clone(),
+            ipv4_gateway: params.connection.ipv4_gateway,
+            ipv6_gateway: params.connection.ipv6_gateway,
+        },
+        TunnelParameters::OpenVpn(_) => TunnelMetadata {
+            interface: FAKE_INTERFACE_NAME.to_string(),
+            ips: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 2))],
+            ipv4_gateway: Ipv4Addr::new(10, 8, 0, 1),
+            ipv6_gateway: None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn metadata() -> TunnelMetadata {
+        TunnelMetadata {
+            interface: FAKE_INTERFACE_NAME.to_string(),
+            ips: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: None,
+        }
+    }
+
+    /// Runs a simulated tunnel that is closed after `duration`, and returns the names of the
+    /// events it sent along with its result.
+    fn run(fail: bool, duration: Duration) -> (Vec<&'static str>, Result<Stats, Error>) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let on_event = {
+            let events = events.clone();
+            move |event: TunnelEvent| -> std::pin::Pin<
+                Box<dyn std::future::Future<Output = ()> + Send>,
+            > {
+                let name = match event {
+                    TunnelEvent::InterfaceUp(..) => "interface up",
+                    TunnelEvent::Up(_) => "up",
+                    TunnelEvent::Down => "down",
+                    _ => "other",
+                };
+                events.lock().unwrap().push(name);
+                Box::pin(async {})
+            }
+        };
+        let (close_tx, close_rx) = oneshot::channel();
+
+        let result = runtime.block_on(async move {
+            tokio::spawn(async move {
+                tokio::time::sleep(duration).await;
+                let _ = close_tx.send(());
+            });
+            simulate(
+                metadata(),
+                AllowedTunnelTraffic::All,
+                Duration::ZERO,
+                fail,
+                on_event,
+                close_rx,
+            )
+            .await
+        });
+        let events = events.lock().unwrap().clone();
+        (events, result)
+    }
+
+    #[test]
+    fn test_connect_and_close() {
+        let (events, result) = run(false, Duration::from_secs(10));
+        assert_eq!(events, vec!["interface up", "up", "down"]);
+        let stats = result.unwrap();
+        assert!(stats.tx_bytes > 0 && stats.rx_bytes > 0);
+    }
+
+    #[test]
+    fn test_close_while_connecting() {
+        let (events, result) = run(false, Duration::from_millis(INTERFACE_DELAY_MS.0 / 2));
+        assert_eq!(events, vec!["down"]);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_handshake_failure() {
+        let (events, result) = run(true, Duration::from_secs(10));
+        assert_eq!(events, vec!["interface up", "down"]);
+        assert!(matches!(result, Err(Error::HandshakeFailed)));
+    }
+}
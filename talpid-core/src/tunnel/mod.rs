@@ -1,11 +1,13 @@
 use crate::logging;
-#[cfg(not(target_os = "android"))]
-use futures::channel::oneshot;
-use std::path;
+use futures::channel::{mpsc, oneshot};
+use std::{
+    path,
+    sync::{Arc, Mutex},
+};
 #[cfg(not(target_os = "android"))]
 use talpid_openvpn;
-#[cfg(any(target_os = "linux", target_os = "windows"))]
 use talpid_routing::RouteManagerHandle;
+use talpid_tunnel::tun_provider::TunProvider;
 pub use talpid_tunnel::{TunnelArgs, TunnelEvent, TunnelMetadata};
 #[cfg(not(target_os = "android"))]
 use talpid_types::net::openvpn as openvpn_types;
@@ -14,6 +16,9 @@ use talpid_types::net::{wireguard as wireguard_types, TunnelParameters};
 /// A module for all WireGuard related tunnel management.
 use talpid_wireguard;
 
+/// A simulated tunnel backend used for frontend development and testing.
+pub mod fake;
+
 const OPENVPN_LOG_FILENAME: &str = "openvpn.log";
 const WIREGUARD_LOG_FILENAME: &str = "wireguard.log";
 
@@ -53,6 +58,10 @@ pub enum Error {
     #[error(display = "Failed while listening for events from the Wireguard tunnel")]
     WireguardTunnelMonitoringError(#[error(source)] talpid_wireguard::Error),
 
+    /// The simulated tunnel failed
+    #[error(display = "The simulated tunnel failed")]
+    FakeTunnelError(#[error(source)] fake::Error),
+
     /// Could not detect and assign the correct mtu
     #[error(display = "Could not detect and assign a correct MTU for the Wireguard tunnel")]
     AssignMtuError,
@@ -80,6 +89,15 @@ impl TunnelMonitor {
             + Sync
             + 'static,
     {
+        if fake::is_enabled() {
+            return Ok(TunnelMonitor {
+                monitor: InternalTunnelMonitor::Fake(fake::FakeTunnelMonitor::start(
+                    tunnel_parameters,
+                    args,
+                )),
+            });
+        }
+
         Self::ensure_ipv6_can_be_used_if_enabled(tunnel_parameters)?;
         let log_file = Self::prepare_tunnel_log_file(tunnel_parameters, log_dir)?;
 
@@ -297,16 +315,115 @@ impl TunnelMonitor {
         }
     }
 
+    /// Returns a handle for updating the configuration of a running WireGuard tunnel, or `None`
+    /// if this is not a WireGuard tunnel.
+    pub fn wireguard_config_handle(&self) -> Option<talpid_wireguard::TunnelConfigHandle> {
+        match &self.monitor {
+            InternalTunnelMonitor::Wireguard(monitor) => Some(monitor.config_handle()),
+            _ => None,
+        }
+    }
+
     /// Consumes the monitor and blocks until the tunnel exits or there is an error.
     pub fn wait(self) -> Result<()> {
         self.monitor.wait().map_err(Error::from)
     }
 }
 
+/// Channel on which a tunnel reports its events to the tunnel state machine. Each event is paired
+/// with a sender that is signalled once the state machine has handled the event.
+pub type TunnelEventTx = mpsc::UnboundedSender<(TunnelEvent, oneshot::Sender<()>)>;
+
+/// Arguments for starting a tunnel through a [`TunnelProvider`].
+pub struct TunnelProviderArgs<'a> {
+    /// Tokio runtime handle.
+    pub runtime: tokio::runtime::Handle,
+    /// Resource directory path.
+    pub resource_dir: &'a path::Path,
+    /// Directory to store the tunnel log file in.
+    pub log_dir: &'a Option<path::PathBuf>,
+    /// Channel for reporting tunnel events.
+    pub event_tx: TunnelEventTx,
+    /// Fires when the tunnel should be closed.
+    pub tunnel_close_rx: oneshot::Receiver<()>,
+    /// Mutex to tunnel provider.
+    pub tun_provider: Arc<Mutex<TunProvider>>,
+    /// Connection retry attempts.
+    pub retry_attempt: u32,
+    /// Route manager handle.
+    pub route_manager: RouteManagerHandle,
+}
+
+/// A tunnel started by a [`TunnelProvider`].
+pub trait Tunnel: Send {
+    /// Returns a handle for updating the configuration of a running WireGuard tunnel, or `None`
+    /// if this is not supported.
+    fn wireguard_config_handle(&self) -> Option<talpid_wireguard::TunnelConfigHandle>;
+
+    /// Blocks until the tunnel exits or there is an error.
+    fn wait(self: Box<Self>) -> Result<()>;
+}
+
+impl Tunnel for TunnelMonitor {
+    fn wireguard_config_handle(&self) -> Option<talpid_wireguard::TunnelConfigHandle> {
+        TunnelMonitor::wireguard_config_handle(self)
+    }
+
+    fn wait(self: Box<Self>) -> Result<()> {
+        TunnelMonitor::wait(*self)
+    }
+}
+
+/// Starts the tunnels of the tunnel state machine. Replacing the provider lets tests drive the
+/// state machine with simulated tunnels.
+pub trait TunnelProvider: Send + Sync + 'static {
+    /// Starts a tunnel using `tunnel_parameters`. The tunnel reports its events on
+    /// `args.event_tx`, and must close when `args.tunnel_close_rx` fires.
+    fn start(
+        &self,
+        tunnel_parameters: &mut TunnelParameters,
+        args: TunnelProviderArgs<'_>,
+    ) -> Result<Box<dyn Tunnel>>;
+}
+
+/// Starts tunnels using a [`TunnelMonitor`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultTunnelProvider;
+
+impl TunnelProvider for DefaultTunnelProvider {
+    fn start(
+        &self,
+        tunnel_parameters: &mut TunnelParameters,
+        args: TunnelProviderArgs<'_>,
+    ) -> Result<Box<dyn Tunnel>> {
+        let event_tx = args.event_tx;
+        let on_event =
+            move |event| -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>> {
+                let (tx, rx) = oneshot::channel();
+                let _ = event_tx.unbounded_send((event, tx));
+                Box::pin(async move {
+                    let _ = rx.await;
+                })
+            };
+        let tunnel_args = TunnelArgs {
+            runtime: args.runtime,
+            resource_dir: args.resource_dir,
+            on_event,
+            tunnel_close_rx: args.tunnel_close_rx,
+            tun_provider: args.tun_provider,
+            retry_attempt: args.retry_attempt,
+            route_manager: args.route_manager,
+        };
+        let monitor = TunnelMonitor::start(tunnel_parameters, args.log_dir, tunnel_args)?;
+        Ok(Box::new(monitor))
+    }
+}
+
 enum InternalTunnelMonitor {
     #[cfg(not(target_os = "android"))]
     OpenVpn(talpid_openvpn::OpenVpnMonitor),
     Wireguard(talpid_wireguard::WireguardMonitor),
+    Fake(fake::FakeTunnelMonitor),
 }
 
 impl InternalTunnelMonitor {
@@ -315,6 +432,7 @@ impl InternalTunnelMonitor {
             #[cfg(not(target_os = "android"))]
             InternalTunnelMonitor::OpenVpn(tun) => tun.wait()?,
             InternalTunnelMonitor::Wireguard(tun) => tun.wait()?,
+            InternalTunnelMonitor::Fake(tun) => tun.wait()?,
         }
 
         Ok(())
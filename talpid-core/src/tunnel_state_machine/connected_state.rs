@@ -10,23 +10,38 @@ use crate::{
 use cfg_if::cfg_if;
 use futures::{
     channel::{mpsc, oneshot},
-    stream::Fuse,
+    stream::{Fuse, Inspect},
     StreamExt,
 };
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use talpid_types::{
-    net::TunnelParameters,
-    tunnel::{ErrorStateCause, FirewallPolicyError},
+    net::{IpVersion, TunnelParameters},
+    tunnel::{
+        ErrorStateCause, FirewallPolicyError, ReconnectReason, TunnelHealth, TunnelInterface,
+    },
     BoxedError, ErrorExt,
 };
 
 #[cfg(windows)]
 use crate::tunnel::TunnelMonitor;
 
-use super::connecting_state::TunnelCloseEvent;
+use super::connecting_state::{replace_private_key, TunnelCloseEvent, TunnelConfigSlot};
+
+type TunnelEventItem = (TunnelEvent, oneshot::Sender<()>);
 
 pub(crate) type TunnelEventsReceiver =
-    Fuse<mpsc::UnboundedReceiver<(TunnelEvent, oneshot::Sender<()>)>>;
+    Fuse<Inspect<mpsc::UnboundedReceiver<TunnelEventItem>, fn(&TunnelEventItem)>>;
+
+pub(crate) fn trace_tunnel_event((event, _): &TunnelEventItem) {
+    let name = match event {
+        TunnelEvent::AuthFailed(_) => "auth_failed",
+        TunnelEvent::InterfaceUp(..) => "interface_up",
+        TunnelEvent::Up(_) => "up",
+        TunnelEvent::HealthChanged(_) => "health_changed",
+        TunnelEvent::Down => "down",
+    };
+    tracing::debug!(event = name, "Received tunnel event");
+}
 
 pub struct ConnectedStateBootstrap {
     pub metadata: TunnelMetadata,
@@ -34,6 +49,7 @@ pub struct ConnectedStateBootstrap {
     pub tunnel_parameters: TunnelParameters,
     pub tunnel_close_event: TunnelCloseEvent,
     pub tunnel_close_tx: oneshot::Sender<()>,
+    pub tunnel_config: TunnelConfigSlot,
 }
 
 /// The tunnel is up and working.
@@ -43,6 +59,8 @@ pub struct ConnectedState {
     tunnel_parameters: TunnelParameters,
     tunnel_close_event: TunnelCloseEvent,
     tunnel_close_tx: oneshot::Sender<()>,
+    tunnel_config: TunnelConfigSlot,
+    health: TunnelHealth,
 }
 
 impl ConnectedState {
@@ -53,6 +71,63 @@ impl ConnectedState {
             tunnel_parameters: bootstrap.tunnel_parameters,
             tunnel_close_event: bootstrap.tunnel_close_event,
             tunnel_close_tx: bootstrap.tunnel_close_tx,
+            tunnel_config: bootstrap.tunnel_config,
+            health: TunnelHealth::Healthy,
+        }
+    }
+
+    /// Moves the entry peer of a WireGuard tunnel to `port` on the same relay, without closing
+    /// the tunnel. The firewall is updated first, so that the new endpoint can be reached.
+    /// Returns `false` if the tunnel is left on its old port.
+    fn hop_port(&mut self, shared_values: &mut SharedTunnelStateValues, port: u16) -> bool {
+        let old_port = match &self.tunnel_parameters {
+            // The peer of an obfuscated tunnel is reached through the local obfuscator
+            TunnelParameters::Wireguard(parameters) if parameters.obfuscation.is_none() => {
+                parameters.connection.peer.endpoint.port()
+            }
+            _ => return false,
+        };
+        let config_handle = match self.tunnel_config.lock().unwrap().clone() {
+            Some(config_handle) => config_handle,
+            None => return false,
+        };
+        self.set_entry_port(port);
+        if self.set_firewall_policy(shared_values).is_err() {
+            self.set_entry_port(old_port);
+            let _ = self.set_firewall_policy(shared_values);
+            return false;
+        }
+
+        match shared_values
+            .runtime
+            .block_on(config_handle.set_entry_port(port))
+        {
+            Ok(()) => {
+                log::info!("Moved the tunnel to port {} without reconnecting", port);
+                true
+            }
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to change the WireGuard port in place")
+                );
+                self.set_entry_port(old_port);
+                let _ = self.set_firewall_policy(shared_values);
+                false
+            }
+        }
+    }
+
+    fn set_entry_port(&mut self, port: u16) {
+        if let TunnelParameters::Wireguard(parameters) = &mut self.tunnel_parameters {
+            parameters.connection.peer.endpoint.set_port(port);
+        }
+    }
+
+    fn tunnel_interface(&self) -> TunnelInterface {
+        TunnelInterface {
+            name: self.metadata.interface.clone(),
+            ips: self.metadata.ips.clone(),
         }
     }
 
@@ -85,22 +160,68 @@ impl ConnectedState {
     fn get_dns_servers(&self, shared_values: &SharedTunnelStateValues) -> Vec<IpAddr> {
         #[cfg(not(target_os = "android"))]
         if let Some(ref servers) = shared_values.dns_servers {
-            servers.clone()
+            self.get_reachable_dns_servers(servers)
         } else {
-            let mut dns_ips = vec![self.metadata.ipv4_gateway.into()];
-            if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-                dns_ips.push(ipv6_gateway.into());
-            };
-            dns_ips
+            self.get_gateway_dns_servers()
         }
         #[cfg(target_os = "android")]
-        {
-            let mut dns_ips = vec![];
-            dns_ips.push(self.metadata.ipv4_gateway.into());
-            if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-                dns_ips.push(ipv6_gateway.into());
-            };
-            dns_ips
+        self.get_gateway_dns_servers()
+    }
+
+    /// Returns the resolvers in `servers` that can be reached, in their original order. If no
+    /// resolver remains, the resolvers in the tunnel are used instead, so that DNS does not stop
+    /// working entirely.
+    #[cfg(not(target_os = "android"))]
+    fn get_reachable_dns_servers(&self, servers: &[IpAddr]) -> Vec<IpAddr> {
+        let (reachable, unreachable) = partition_reachable_dns_servers(
+            servers,
+            self.address_family() != Some(IpVersion::V6),
+            self.metadata.ipv6_gateway.is_some(),
+        );
+        if unreachable.is_empty() {
+            return reachable;
+        }
+        log::warn!(
+            "Ignoring DNS servers that can't be reached through the tunnel: {}",
+            unreachable
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        if reachable.is_empty() {
+            log::warn!("No DNS server is reachable. Using the resolver in the tunnel");
+            return self.get_gateway_dns_servers();
+        }
+        reachable
+    }
+
+    /// Returns the resolvers in the tunnel, limited to the IP version given in the WireGuard
+    /// options, if any. The IPv4 resolver is used if there is no resolver of that version.
+    /// In an IPv6-only tunnel, the IPv6 resolver is preferred.
+    fn get_gateway_dns_servers(&self) -> Vec<IpAddr> {
+        let (ip_version, prefer_ipv6) = match &self.tunnel_parameters {
+            TunnelParameters::Wireguard(params) => (
+                params.options.dns_ip_version,
+                params.options.prefer_ipv6_dns
+                    || params.options.address_family == Some(IpVersion::V6),
+            ),
+            TunnelParameters::OpenVpn(_) => (None, false),
+        };
+        gateway_dns_servers(
+            self.metadata.ipv4_gateway,
+            self.metadata.ipv6_gateway,
+            ip_version,
+            prefer_ipv6,
+        )
+    }
+
+    /// Returns the only IP version that is routed through the tunnel, if there is one.
+    #[cfg(not(target_os = "android"))]
+    fn address_family(&self) -> Option<IpVersion> {
+        match &self.tunnel_parameters {
+            TunnelParameters::Wireguard(params) => params.options.address_family,
+            TunnelParameters::OpenVpn(_) => None,
         }
     }
 
@@ -111,6 +232,16 @@ impl ConnectedState {
             allow_lan: shared_values.allow_lan,
             #[cfg(not(target_os = "android"))]
             dns_servers: self.get_dns_servers(shared_values),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            allowed_multicast_groups: shared_values.allowed_multicast_groups.clone(),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            allowed_link_config_traffic: shared_values.allowed_link_config_traffic,
+            #[cfg(not(target_os = "android"))]
+            allowed_lan_hosts: shared_values.allowed_lan_hosts.clone(),
+            #[cfg(target_os = "linux")]
+            split_tunnel_mode: shared_values.split_tunnel_mode,
+            #[cfg(target_os = "linux")]
+            shared_interface: shared_values.shared_interface.clone(),
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(
                 &shared_values.resource_dir,
@@ -181,7 +312,7 @@ impl ConnectedState {
     }
 
     fn handle_commands(
-        self,
+        mut self,
         command: Option<TunnelCommand>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
@@ -196,6 +327,8 @@ impl ConnectedState {
                         Ok(()) => {
                             cfg_if! {
                                 if #[cfg(target_os = "android")] {
+                                    shared_values.reconnect_reason =
+                                        Some(ReconnectReason::SettingsChanged);
                                     self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
                                 } else {
                                     SameState(self.into())
@@ -214,6 +347,76 @@ impl ConnectedState {
                 let _ = tx.send(());
                 SameState(self.into())
             }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::AllowMulticastGroups(groups)) => {
+                if shared_values.allowed_multicast_groups == groups {
+                    return SameState(self.into());
+                }
+                shared_values.allowed_multicast_groups = groups;
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::AllowLinkConfigTraffic(allowed)) => {
+                if shared_values.allowed_link_config_traffic == allowed {
+                    return SameState(self.into());
+                }
+                shared_values.allowed_link_config_traffic = allowed;
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::AllowLanHosts(hosts)) => {
+                if shared_values.allowed_lan_hosts == hosts {
+                    return SameState(self.into());
+                }
+                shared_values.allowed_lan_hosts = hosts;
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetSplitTunnelMode(mode)) => {
+                if shared_values.split_tunnel_mode == mode {
+                    return SameState(self.into());
+                }
+                shared_values.split_tunnel_mode = mode;
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetSharedInterface(interface)) => {
+                if shared_values.shared_interface == interface {
+                    return SameState(self.into());
+                }
+                shared_values.shared_interface = interface;
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
             Some(TunnelCommand::Dns(servers)) => match shared_values.set_dns_servers(servers) {
                 Ok(true) => {
                     if let Err(error) = self.set_firewall_policy(shared_values) {
@@ -225,7 +428,10 @@ impl ConnectedState {
 
                     match self.set_dns(shared_values) {
                         #[cfg(target_os = "android")]
-                        Ok(()) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+                        Ok(()) => {
+                            shared_values.reconnect_reason = Some(ReconnectReason::SettingsChanged);
+                            self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                        }
                         #[cfg(not(target_os = "android"))]
                         Ok(()) => SameState(self.into()),
                         Err(error) => {
@@ -246,6 +452,10 @@ impl ConnectedState {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
+            Some(TunnelCommand::ConnectionTimeouts(timeouts)) => {
+                shared_values.set_connection_timeouts(timeouts);
+                SameState(self.into())
+            }
             Some(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
                 if is_offline {
@@ -257,10 +467,70 @@ impl ConnectedState {
                     SameState(self.into())
                 }
             }
+            Some(TunnelCommand::Pause) => {
+                shared_values.is_paused = true;
+                self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Block(ErrorStateCause::IsOffline),
+                )
+            }
+            Some(TunnelCommand::Resume) => {
+                shared_values.is_paused = false;
+                SameState(self.into())
+            }
             Some(TunnelCommand::Connect) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
-            Some(TunnelCommand::Disconnect) | None => {
+            Some(TunnelCommand::Reconnect) => {
+                // Only the allowed endpoint is reachable until the new tunnel is up, instead of
+                // whatever the policy for the closing tunnel let through
+                match ErrorState::set_firewall_policy(shared_values) {
+                    Ok(()) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
+            Some(TunnelCommand::RotateKey(private_key)) => {
+                if replace_private_key(
+                    shared_values,
+                    &self.tunnel_config,
+                    &mut self.tunnel_parameters,
+                    private_key,
+                ) {
+                    SameState(self.into())
+                } else {
+                    shared_values.reconnect_reason = Some(ReconnectReason::KeyRotated);
+                    self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                }
+            }
+            Some(TunnelCommand::HopPort(port, result_tx)) => {
+                let hopped = self.hop_port(shared_values, port);
+                let _ = result_tx.send(hopped);
+                if hopped {
+                    let tunnel_endpoint = self.tunnel_parameters.get_tunnel_endpoint();
+                    let health = self.health;
+                    let interface = self.tunnel_interface();
+                    Progress((
+                        self.into(),
+                        TunnelStateTransition::Connected(tunnel_endpoint, health, interface),
+                    ))
+                } else {
+                    SameState(self.into())
+                }
+            }
+            Some(TunnelCommand::Disconnect {
+                reason,
+                keep_blocking,
+            }) => {
+                shared_values.disconnect_reason = Some(reason);
+                shared_values.keep_blocking = keep_blocking;
+                self.disconnect(shared_values, AfterDisconnect::Nothing)
+            }
+            None => self.disconnect(shared_values, AfterDisconnect::Nothing),
+            Some(TunnelCommand::Shutdown { keep_firewall }) => {
+                shared_values.request_shutdown(keep_firewall);
                 self.disconnect(shared_values, AfterDisconnect::Nothing)
             }
             Some(TunnelCommand::Block(reason)) => {
@@ -280,7 +550,7 @@ impl ConnectedState {
     }
 
     fn handle_tunnel_events(
-        self,
+        mut self,
         event: Option<(TunnelEvent, oneshot::Sender<()>)>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
@@ -288,8 +558,18 @@ impl ConnectedState {
 
         match event {
             Some((TunnelEvent::Down, _)) | None => {
+                shared_values.reconnect_reason = Some(ReconnectReason::ConnectivityLost);
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
+            Some((TunnelEvent::HealthChanged(health), _)) => {
+                self.health = health;
+                let tunnel_endpoint = self.tunnel_parameters.get_tunnel_endpoint();
+                let interface = self.tunnel_interface();
+                Progress((
+                    self.into(),
+                    TunnelStateTransition::Connected(tunnel_endpoint, health, interface),
+                ))
+            }
             Some(_) => SameState(self.into()),
         }
     }
@@ -310,10 +590,53 @@ impl ConnectedState {
         log::info!("Tunnel closed. Reconnecting.");
         Self::reset_dns(shared_values);
         Self::reset_routes(shared_values);
+        shared_values.reconnect_reason = Some(ReconnectReason::ConnectivityLost);
         NewState(ConnectingState::enter(shared_values, 0))
     }
 }
 
+/// Returns the in-tunnel resolvers to use, in order of preference.
+fn gateway_dns_servers(
+    ipv4_gateway: Ipv4Addr,
+    ipv6_gateway: Option<Ipv6Addr>,
+    ip_version: Option<IpVersion>,
+    prefer_ipv6: bool,
+) -> Vec<IpAddr> {
+    let ipv4_gateway = IpAddr::V4(ipv4_gateway);
+    match (ip_version, ipv6_gateway.map(IpAddr::V6)) {
+        (Some(IpVersion::V4), _) => vec![ipv4_gateway],
+        (Some(IpVersion::V6), Some(ipv6_gateway)) => vec![ipv6_gateway],
+        (Some(IpVersion::V6), None) => {
+            log::warn!(
+                "Using the IPv4 resolver in the tunnel, since the tunnel has no IPv6 address"
+            );
+            vec![ipv4_gateway]
+        }
+        (None, None) => vec![ipv4_gateway],
+        (None, Some(ipv6_gateway)) if prefer_ipv6 => vec![ipv6_gateway, ipv4_gateway],
+        (None, Some(ipv6_gateway)) => vec![ipv4_gateway, ipv6_gateway],
+    }
+}
+
+/// Splits `servers` into the resolvers that can be reached through the tunnel and those that
+/// can't, keeping their order. Public IPv6 resolvers can't be reached if the tunnel has no IPv6
+/// address, and public IPv4 resolvers can't be reached if only IPv6 is routed through it.
+#[cfg(not(target_os = "android"))]
+fn partition_reachable_dns_servers(
+    servers: &[IpAddr],
+    tunnel_routes_ipv4: bool,
+    tunnel_has_ipv6: bool,
+) -> (Vec<IpAddr>, Vec<IpAddr>) {
+    servers.iter().partition(|server| {
+        let routed = if server.is_ipv4() {
+            tunnel_routes_ipv4
+        } else {
+            tunnel_has_ipv6
+        };
+        routed || crate::firewall::is_local_address(server)
+    })
+}
+
 impl TunnelState for ConnectedState {
     type Bootstrap = ConnectedStateBootstrap;
 
@@ -324,6 +647,7 @@ impl TunnelState for ConnectedState {
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
         let connected_state = ConnectedState::from(bootstrap);
         let tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
+        let interface = connected_state.tunnel_interface();
 
         if let Err(error) = connected_state.set_firewall_policy(shared_values) {
             DisconnectingState::enter(
@@ -347,7 +671,7 @@ impl TunnelState for ConnectedState {
         } else {
             (
                 TunnelStateWrapper::from(connected_state),
-                TunnelStateTransition::Connected(tunnel_endpoint),
+                TunnelStateTransition::Connected(tunnel_endpoint, TunnelHealth::Healthy, interface),
             )
         }
     }
@@ -379,3 +703,60 @@ impl TunnelState for ConnectedState {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gateway_dns_servers() {
+        let ipv4 = Ipv4Addr::new(10, 64, 0, 1);
+        let ipv6 = "fc00:bbbb:bbbb:bb01::1".parse::<Ipv6Addr>().unwrap();
+        let servers = |ipv6_gateway, ip_version, prefer_ipv6| {
+            gateway_dns_servers(ipv4, ipv6_gateway, ip_version, prefer_ipv6)
+        };
+
+        assert_eq!(
+            servers(Some(ipv6), None, false),
+            [IpAddr::from(ipv4), IpAddr::from(ipv6)]
+        );
+        assert_eq!(
+            servers(Some(ipv6), None, true),
+            [IpAddr::from(ipv6), IpAddr::from(ipv4)]
+        );
+        assert_eq!(servers(None, None, true), [IpAddr::from(ipv4)]);
+        assert_eq!(
+            servers(Some(ipv6), Some(IpVersion::V4), true),
+            [IpAddr::from(ipv4)]
+        );
+        assert_eq!(
+            servers(Some(ipv6), Some(IpVersion::V6), false),
+            [IpAddr::from(ipv6)]
+        );
+        assert_eq!(
+            servers(None, Some(IpVersion::V6), false),
+            [IpAddr::from(ipv4)]
+        );
+    }
+
+    #[cfg(not(target_os = "android"))]
+    #[test]
+    fn test_reachable_dns_servers_keep_order() {
+        let servers: Vec<IpAddr> = ["2001:db8::53", "192.0.2.53", "fd00::53", "198.51.100.53"]
+            .iter()
+            .map(|server| server.parse().unwrap())
+            .collect();
+
+        let (reachable, unreachable) = partition_reachable_dns_servers(&servers, true, true);
+        assert_eq!(reachable, servers);
+        assert!(unreachable.is_empty());
+
+        let (reachable, unreachable) = partition_reachable_dns_servers(&servers, true, false);
+        assert_eq!(reachable, [servers[1], servers[2], servers[3]]);
+        assert_eq!(unreachable, [servers[0]]);
+
+        let (reachable, unreachable) = partition_reachable_dns_servers(&servers, false, true);
+        assert_eq!(reachable, [servers[0], servers[2]]);
+        assert_eq!(unreachable, [servers[1], servers[3]]);
+    }
+}
@@ -5,7 +5,8 @@ use super::{
 };
 use crate::{
     firewall::FirewallPolicy,
-    tunnel::{self, TunnelMonitor},
+    future_retry,
+    tunnel::{self, Tunnel, TunnelProvider, TunnelProviderArgs},
 };
 use cfg_if::cfg_if;
 use futures::{
@@ -14,32 +15,80 @@ use futures::{
     FutureExt, StreamExt,
 };
 use std::{
+    net::IpAddr,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
     thread,
     time::{Duration, Instant},
 };
 use talpid_routing::RouteManager;
-use talpid_tunnel::{tun_provider::TunProvider, TunnelArgs, TunnelEvent, TunnelMetadata};
+use talpid_tunnel::{tun_provider::TunProvider, TunnelEvent, TunnelMetadata};
 use talpid_types::{
-    net::{AllowedTunnelTraffic, TunnelParameters},
-    tunnel::{ErrorStateCause, FirewallPolicyError},
+    net::{wireguard::PrivateKey, AllowedTunnelTraffic, TunnelParameters},
+    tunnel::{
+        ConnectingPhase, ConnectionTimeouts, ErrorStateCause, FirewallPolicyError, ReconnectReason,
+    },
     ErrorExt,
 };
 
 #[cfg(target_os = "android")]
 use talpid_tunnel::tun_provider;
 
-use super::connected_state::TunnelEventsReceiver;
+use super::connected_state::{trace_tunnel_event, TunnelEventsReceiver};
 
 pub(crate) type TunnelCloseEvent = Fuse<oneshot::Receiver<Option<ErrorStateCause>>>;
 
 #[cfg(target_os = "android")]
 const MAX_ATTEMPTS_WITH_SAME_TUN: u32 = 5;
 const MIN_TUNNEL_ALIVE_TIME: Duration = Duration::from_millis(1000);
+/// Number of attempts in a row against the same relay, after which another relay is asked for.
+const MAX_ATTEMPTS_PER_RELAY: u32 = 3;
 #[cfg(target_os = "windows")]
 const MAX_ADAPTER_FAIL_RETRIES: u32 = 4;
 
+/// Handle for updating the configuration of a WireGuard tunnel, once the tunnel has been started.
+pub(crate) type TunnelConfigSlot = Arc<Mutex<Option<talpid_wireguard::TunnelConfigHandle>>>;
+
+/// Replaces the private key of a running WireGuard tunnel without closing it, and updates
+/// `parameters` to match. Returns `false` if the key could not be replaced in place. OpenVPN
+/// tunnels do not use the key, so nothing is done for them.
+pub(super) fn replace_private_key(
+    shared_values: &SharedTunnelStateValues,
+    tunnel_config: &TunnelConfigSlot,
+    parameters: &mut TunnelParameters,
+    private_key: PrivateKey,
+) -> bool {
+    let parameters = match parameters {
+        TunnelParameters::Wireguard(parameters) => parameters,
+        TunnelParameters::OpenVpn(_) => return true,
+    };
+    let config_handle = match tunnel_config.lock().unwrap().clone() {
+        Some(config_handle) => config_handle,
+        None => {
+            log::debug!("Cannot replace the WireGuard key before the tunnel has started");
+            return false;
+        }
+    };
+
+    match shared_values
+        .runtime
+        .block_on(config_handle.set_private_key(private_key.clone()))
+    {
+        Ok(()) => {
+            log::info!("Replaced the WireGuard key without reconnecting");
+            parameters.connection.tunnel.private_key = private_key;
+            true
+        }
+        Err(error) => {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to replace the WireGuard key in place")
+            );
+            false
+        }
+    }
+}
+
 /// The tunnel has been started, but it is not established/functional.
 pub struct ConnectingState {
     tunnel_events: TunnelEventsReceiver,
@@ -48,6 +97,10 @@ pub struct ConnectingState {
     allowed_tunnel_traffic: AllowedTunnelTraffic,
     tunnel_close_event: TunnelCloseEvent,
     tunnel_close_tx: oneshot::Sender<()>,
+    tunnel_config: TunnelConfigSlot,
+    /// When to give up on the attempt if the tunnel has not come up by then.
+    connecting_deadline: Option<Instant>,
+    phase: ConnectingPhase,
     retry_attempt: u32,
 }
 
@@ -69,8 +122,19 @@ impl ConnectingState {
             allow_lan: shared_values.allow_lan,
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
             allowed_tunnel_traffic,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            allowed_link_config_traffic: shared_values.allowed_link_config_traffic,
+            #[cfg(not(target_os = "android"))]
+            allowed_lan_hosts: shared_values.allowed_lan_hosts.clone(),
+            #[cfg(target_os = "linux")]
+            split_tunnel_mode: shared_values.split_tunnel_mode,
+            #[cfg(target_os = "linux")]
+            shared_interface: shared_values.shared_interface.clone(),
             #[cfg(windows)]
-            relay_client: TunnelMonitor::get_relay_client(&shared_values.resource_dir, &params),
+            relay_client: tunnel::TunnelMonitor::get_relay_client(
+                &shared_values.resource_dir,
+                &params,
+            ),
         };
         shared_values
             .firewall
@@ -93,21 +157,16 @@ impl ConnectingState {
     fn start_tunnel(
         runtime: tokio::runtime::Handle,
         parameters: TunnelParameters,
+        tunnel_provider: Arc<dyn TunnelProvider>,
         log_dir: &Option<PathBuf>,
         resource_dir: &Path,
         tun_provider: Arc<Mutex<TunProvider>>,
         route_manager: &mut RouteManager,
         retry_attempt: u32,
+        timeouts: ConnectionTimeouts,
+        relay_attempts: Arc<Mutex<RelayAttempts>>,
     ) -> Self {
         let (event_tx, event_rx) = mpsc::unbounded();
-        let on_tunnel_event =
-            move |event| -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>> {
-                let (tx, rx) = oneshot::channel();
-                let _ = event_tx.unbounded_send((event, tx));
-                Box::pin(async move {
-                    let _ = rx.await;
-                })
-            };
 
         let route_manager_handle = route_manager.handle();
         let log_dir = log_dir.clone();
@@ -115,10 +174,36 @@ impl ConnectingState {
 
         let (tunnel_close_tx, tunnel_close_rx) = oneshot::channel();
         let (tunnel_close_event_tx, tunnel_close_event_rx) = oneshot::channel();
+        let tunnel_config = TunnelConfigSlot::default();
+        let monitor_tunnel_config = tunnel_config.clone();
 
         let mut tunnel_parameters = parameters.clone();
 
+        let delay = retry_delay(retry_attempt, &timeouts);
+        let connecting_deadline = timeouts
+            .connecting_timeout()
+            .map(|timeout| Instant::now() + delay + timeout);
+
         tokio::task::spawn_blocking(move || {
+            let mut tunnel_close_rx = tunnel_close_rx;
+            if !delay.is_zero() {
+                log::debug!("Waiting {:?} before connecting", delay);
+                let cancelled = runtime.block_on(async {
+                    futures::select! {
+                        _ = Box::pin(talpid_time::sleep(delay)).fuse() => false,
+                        _ = &mut tunnel_close_rx => true,
+                    }
+                });
+                if cancelled {
+                    if tunnel_close_event_tx.send(None).is_err() {
+                        log::warn!(
+                            "Tunnel state machine stopped before receiving tunnel closed event"
+                        );
+                    }
+                    return;
+                }
+            }
+
             let start = Instant::now();
 
             let route_manager_handle = match route_manager_handle {
@@ -140,19 +225,22 @@ impl ConnectingState {
                 }
             };
 
-            let args = TunnelArgs {
+            let args = TunnelProviderArgs {
                 runtime,
                 resource_dir: &resource_dir,
-                on_event: on_tunnel_event,
+                log_dir: &log_dir,
+                event_tx,
                 tunnel_close_rx,
                 tun_provider,
                 retry_attempt,
                 route_manager: route_manager_handle,
             };
 
-            let block_reason = match TunnelMonitor::start(&mut tunnel_parameters, &log_dir, args) {
+            let block_reason = match tunnel_provider.start(&mut tunnel_parameters, args) {
                 Ok(monitor) => {
-                    let reason = Self::wait_for_tunnel_monitor(monitor, retry_attempt);
+                    *monitor_tunnel_config.lock().unwrap() = monitor.wireguard_config_handle();
+                    let reason =
+                        Self::wait_for_tunnel_monitor(monitor, retry_attempt, &relay_attempts);
                     log::debug!("Tunnel monitor exited with block reason: {:?}", reason);
                     reason
                 }
@@ -205,19 +293,23 @@ impl ConnectingState {
         });
 
         ConnectingState {
-            tunnel_events: event_rx.fuse(),
+            tunnel_events: event_rx.inspect(trace_tunnel_event as fn(&_)).fuse(),
             tunnel_parameters: parameters,
             tunnel_metadata: None,
             allowed_tunnel_traffic: AllowedTunnelTraffic::None,
             tunnel_close_event: tunnel_close_event_rx.fuse(),
             tunnel_close_tx,
+            tunnel_config,
+            connecting_deadline,
+            phase: ConnectingPhase::OpeningDevice,
             retry_attempt,
         }
     }
 
     fn wait_for_tunnel_monitor(
-        tunnel_monitor: TunnelMonitor,
+        tunnel_monitor: Box<dyn Tunnel>,
         retry_attempt: u32,
+        relay_attempts: &Mutex<RelayAttempts>,
     ) -> Option<ErrorStateCause> {
         match tunnel_monitor.wait() {
             Ok(_) => None,
@@ -228,6 +320,16 @@ impl ConnectingState {
                     log::debug!("WireGuard tunnel timed out");
                     None
                 }
+                tunnel::Error::WireguardTunnelMonitoringError(
+                    talpid_wireguard::Error::PskNegotiationError(
+                        talpid_tunnel_config_client::Error::PskExchangeUnsupported,
+                    ),
+                ) => {
+                    // The relay list claimed otherwise, so the relay is not tried again
+                    log::warn!("The relay does not support the PSK exchange. Trying another relay");
+                    relay_attempts.lock().unwrap().reject();
+                    None
+                }
                 error @ tunnel::Error::WireguardTunnelMonitoringError(..)
                     if !should_retry(&error, retry_attempt) =>
                 {
@@ -255,9 +357,14 @@ impl ConnectingState {
             tunnel_parameters: self.tunnel_parameters,
             tunnel_close_event: self.tunnel_close_event,
             tunnel_close_tx: self.tunnel_close_tx,
+            tunnel_config: self.tunnel_config,
         }
     }
 
+    pub(super) fn retry_attempt(&self) -> u32 {
+        self.retry_attempt
+    }
+
     fn reset_routes(shared_values: &mut SharedTunnelStateValues) {
         if let Err(error) = shared_values.route_manager.clear_routes() {
             log::error!("{}", error.display_chain_with_msg("Failed to clear routes"));
@@ -301,6 +408,8 @@ impl ConnectingState {
             Ok(()) => {
                 cfg_if! {
                     if #[cfg(target_os = "android")] {
+                        shared_values.reconnect_reason =
+                            Some(talpid_types::tunnel::ReconnectReason::SettingsChanged);
                         self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
                     } else {
                         EventConsequence::SameState(self.into())
@@ -315,7 +424,7 @@ impl ConnectingState {
     }
 
     fn handle_commands(
-        self,
+        mut self,
         command: Option<TunnelCommand>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
@@ -350,14 +459,59 @@ impl ConnectingState {
             }
             Some(TunnelCommand::Dns(servers)) => match shared_values.set_dns_servers(servers) {
                 #[cfg(target_os = "android")]
-                Ok(true) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+                Ok(true) => {
+                    shared_values.reconnect_reason =
+                        Some(talpid_types::tunnel::ReconnectReason::SettingsChanged);
+                    self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                }
                 Ok(_) => SameState(self.into()),
                 Err(cause) => self.disconnect(shared_values, AfterDisconnect::Block(cause)),
             },
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::AllowMulticastGroups(groups)) => {
+                shared_values.allowed_multicast_groups = groups;
+                SameState(self.into())
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::AllowLinkConfigTraffic(allowed)) => {
+                if shared_values.allowed_link_config_traffic == allowed {
+                    return SameState(self.into());
+                }
+                shared_values.allowed_link_config_traffic = allowed;
+                self.reset_firewall(shared_values)
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::AllowLanHosts(hosts)) => {
+                if shared_values.allowed_lan_hosts == hosts {
+                    return SameState(self.into());
+                }
+                shared_values.allowed_lan_hosts = hosts;
+                self.reset_firewall(shared_values)
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetSplitTunnelMode(mode)) => {
+                if shared_values.split_tunnel_mode == mode {
+                    return SameState(self.into());
+                }
+                shared_values.split_tunnel_mode = mode;
+                self.reset_firewall(shared_values)
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetSharedInterface(interface)) => {
+                if shared_values.shared_interface == interface {
+                    return SameState(self.into());
+                }
+                shared_values.shared_interface = interface;
+                self.reset_firewall(shared_values)
+            }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
+            Some(TunnelCommand::ConnectionTimeouts(timeouts)) => {
+                shared_values.set_connection_timeouts(timeouts);
+                SameState(self.into())
+            }
             Some(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
                 if is_offline {
@@ -369,10 +523,59 @@ impl ConnectingState {
                     SameState(self.into())
                 }
             }
+            Some(TunnelCommand::Pause) => {
+                shared_values.is_paused = true;
+                self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Block(ErrorStateCause::IsOffline),
+                )
+            }
+            Some(TunnelCommand::Resume) => {
+                shared_values.is_paused = false;
+                SameState(self.into())
+            }
             Some(TunnelCommand::Connect) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
-            Some(TunnelCommand::Disconnect) | None => {
+            Some(TunnelCommand::Reconnect) => {
+                // Only the allowed endpoint is reachable until the new tunnel is up, instead of
+                // whatever the policy for the closing tunnel let through
+                match ErrorState::set_firewall_policy(shared_values) {
+                    Ok(()) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
+            Some(TunnelCommand::RotateKey(private_key)) => {
+                if replace_private_key(
+                    shared_values,
+                    &self.tunnel_config,
+                    &mut self.tunnel_parameters,
+                    private_key,
+                ) {
+                    SameState(self.into())
+                } else {
+                    shared_values.reconnect_reason = Some(ReconnectReason::KeyRotated);
+                    self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                }
+            }
+            Some(TunnelCommand::HopPort(_, result_tx)) => {
+                let _ = result_tx.send(false);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::Disconnect {
+                reason,
+                keep_blocking,
+            }) => {
+                shared_values.disconnect_reason = Some(reason);
+                shared_values.keep_blocking = keep_blocking;
+                self.disconnect(shared_values, AfterDisconnect::Nothing)
+            }
+            None => self.disconnect(shared_values, AfterDisconnect::Nothing),
+            Some(TunnelCommand::Shutdown { keep_firewall }) => {
+                shared_values.request_shutdown(keep_firewall);
                 self.disconnect(shared_values, AfterDisconnect::Nothing)
             }
             Some(TunnelCommand::Block(reason)) => {
@@ -421,6 +624,12 @@ impl ConnectingState {
                     );
                 }
 
+                let phase = match allowed_tunnel_traffic {
+                    AllowedTunnelTraffic::All => ConnectingPhase::VerifyingConnectivity,
+                    AllowedTunnelTraffic::None | AllowedTunnelTraffic::Only(_) => {
+                        ConnectingPhase::Handshaking
+                    }
+                };
                 self.allowed_tunnel_traffic = allowed_tunnel_traffic;
                 self.tunnel_metadata = Some(metadata);
 
@@ -430,6 +639,15 @@ impl ConnectingState {
                     &self.tunnel_metadata,
                     self.allowed_tunnel_traffic.clone(),
                 ) {
+                    Ok(()) if phase != self.phase => {
+                        self.phase = phase;
+                        let transition = TunnelStateTransition::Connecting(
+                            self.tunnel_parameters.get_tunnel_endpoint(),
+                            None,
+                            phase,
+                        );
+                        Progress((self.into(), transition))
+                    }
                     Ok(()) => SameState(self.into()),
                     Err(error) => self.disconnect(
                         shared_values,
@@ -441,7 +659,9 @@ impl ConnectingState {
                 shared_values,
                 self.into_connected_state_bootstrap(metadata),
             )),
-            Some((TunnelEvent::Down, _)) => SameState(self.into()),
+            Some((TunnelEvent::Down, _)) | Some((TunnelEvent::HealthChanged(_), _)) => {
+                SameState(self.into())
+            }
             None => {
                 // The channel was closed
                 log::debug!("The tunnel disconnected unexpectedly");
@@ -473,6 +693,81 @@ impl ConnectingState {
             self.retry_attempt + 1,
         ))
     }
+
+    fn handle_connecting_timeout(
+        self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence {
+        log::warn!(
+            "Tunnel did not come up in time. Reconnecting, attempt {}.",
+            self.retry_attempt + 1
+        );
+        let retry_attempt = self.retry_attempt + 1;
+        self.disconnect(shared_values, AfterDisconnect::Reconnect(retry_attempt))
+    }
+}
+
+/// Returns how long to wait before making the given connection attempt. The first attempt is made
+/// right away, so that reconnecting is not delayed, but repeated failures back off exponentially
+/// with jitter instead of retrying in a tight loop.
+fn retry_delay(retry_attempt: u32, timeouts: &ConnectionTimeouts) -> Duration {
+    let failed_attempts = match retry_attempt.checked_sub(1) {
+        Some(failed_attempts) => failed_attempts,
+        None => return Duration::ZERO,
+    };
+    let max_delay = timeouts.max_retry_delay();
+    let upper_bound = 2u32
+        .checked_pow(failed_attempts)
+        .and_then(|factor| timeouts.initial_retry_delay().checked_mul(factor))
+        .map(|delay| delay.min(max_delay))
+        .unwrap_or(max_delay);
+    future_retry::jitter(upper_bound)
+}
+
+/// Counts the connection attempts in a row against the same relay, so that a relay that keeps
+/// failing is rotated away from.
+#[derive(Debug, Default)]
+pub(super) struct RelayAttempts {
+    relay: Option<IpAddr>,
+    attempts: u32,
+}
+
+impl RelayAttempts {
+    /// Returns the relay that the given attempt should not be made against, since every one of
+    /// the last [`MAX_ATTEMPTS_PER_RELAY`] attempts against it failed, or it was rejected.
+    pub fn relay_to_avoid(&self, retry_attempt: u32) -> Option<IpAddr> {
+        if retry_attempt == 0 || self.attempts < MAX_ATTEMPTS_PER_RELAY {
+            return None;
+        }
+        self.relay
+    }
+
+    /// Makes the next attempt avoid the current relay, since it turned out not to support the
+    /// features that the tunnel requires.
+    pub fn reject(&mut self) {
+        self.attempts = MAX_ATTEMPTS_PER_RELAY;
+    }
+
+    /// Records that the given attempt is made against `relay`.
+    pub fn record(&mut self, retry_attempt: u32, relay: IpAddr) {
+        if retry_attempt == 0 || self.relay != Some(relay) {
+            self.relay = Some(relay);
+            self.attempts = 1;
+        } else {
+            self.attempts = self.attempts.saturating_add(1);
+        }
+    }
+}
+
+/// Returns the address of the relay that the tunnel is negotiated with: the entry relay when
+/// multihop is used, ignoring any bridge or obfuscator in front of it.
+fn tunnel_relay_address(parameters: &TunnelParameters) -> IpAddr {
+    let endpoint = parameters.get_tunnel_endpoint();
+    endpoint
+        .entry_endpoint
+        .unwrap_or(endpoint.endpoint)
+        .address
+        .ip()
 }
 
 #[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
@@ -532,18 +827,33 @@ impl TunnelState for ConnectingState {
         shared_values: &mut SharedTunnelStateValues,
         retry_attempt: u32,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
-        if shared_values.is_offline {
+        shared_values.keep_blocking = false;
+        if shared_values.is_offline || shared_values.is_paused {
             return ErrorState::enter(shared_values, ErrorStateCause::IsOffline);
         }
+        let avoid_relay = shared_values
+            .relay_attempts
+            .lock()
+            .unwrap()
+            .relay_to_avoid(retry_attempt);
+        if let Some(relay) = avoid_relay {
+            log::debug!("Asking for another relay than {}", relay);
+        }
         match shared_values.runtime.block_on(
             shared_values
                 .tunnel_parameters_generator
-                .generate(retry_attempt),
+                .generate(retry_attempt, avoid_relay),
         ) {
             Err(err) => {
                 ErrorState::enter(shared_values, ErrorStateCause::TunnelParameterError(err))
             }
             Ok(tunnel_parameters) => {
+                shared_values
+                    .relay_attempts
+                    .lock()
+                    .unwrap()
+                    .record(retry_attempt, tunnel_relay_address(&tunnel_parameters));
+
                 #[cfg(windows)]
                 if let Err(error) = shared_values.split_tunnel.set_tunnel_addresses(None) {
                     log::error!(
@@ -581,19 +891,27 @@ impl TunnelState for ConnectingState {
                         }
                     }
 
+                    let reconnect_reason = shared_values.reconnect_reason.take();
                     let connecting_state = Self::start_tunnel(
                         shared_values.runtime.clone(),
                         tunnel_parameters,
+                        shared_values.tunnel_provider.clone(),
                         &shared_values.log_dir,
                         &shared_values.resource_dir,
                         shared_values.tun_provider.clone(),
                         &mut shared_values.route_manager,
                         retry_attempt,
+                        shared_values.connection_timeouts,
+                        shared_values.relay_attempts.clone(),
                     );
                     let params = connecting_state.tunnel_parameters.clone();
                     (
                         TunnelStateWrapper::from(connecting_state),
-                        TunnelStateTransition::Connecting(params.get_tunnel_endpoint()),
+                        TunnelStateTransition::Connecting(
+                            params.get_tunnel_endpoint(),
+                            reconnect_reason,
+                            ConnectingPhase::OpeningDevice,
+                        ),
                     )
                 }
             }
@@ -606,13 +924,28 @@ impl TunnelState for ConnectingState {
         commands: &mut TunnelCommandReceiver,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
+        let connecting_deadline = self.connecting_deadline;
         let result = runtime.block_on(async {
+            let timeout = async move {
+                match connecting_deadline {
+                    Some(deadline) => {
+                        talpid_time::sleep(deadline.saturating_duration_since(Instant::now())).await
+                    }
+                    None => futures::future::pending().await,
+                }
+            };
+            // `None` means that the deadline of the attempt has passed
             futures::select! {
-                command = commands.next() => EventResult::Command(command),
-                event = self.tunnel_events.next() => EventResult::Event(event),
-                result = &mut self.tunnel_close_event => EventResult::Close(result),
+                command = commands.next() => Some(EventResult::Command(command)),
+                event = self.tunnel_events.next() => Some(EventResult::Event(event)),
+                result = &mut self.tunnel_close_event => Some(EventResult::Close(result)),
+                _ = Box::pin(timeout).fuse() => None,
             }
         });
+        let result = match result {
+            Some(result) => result,
+            None => return self.handle_connecting_timeout(shared_values),
+        };
 
         match result {
             EventResult::Command(command) => self.handle_commands(command, shared_values),
@@ -627,3 +960,49 @@ impl TunnelState for ConnectingState {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let timeouts = ConnectionTimeouts::default();
+        assert_eq!(retry_delay(0, &timeouts), Duration::ZERO);
+        for retry_attempt in 1..40 {
+            let upper_bound = timeouts
+                .initial_retry_delay()
+                .saturating_mul(1 << (retry_attempt - 1).min(31))
+                .min(timeouts.max_retry_delay());
+            assert!(retry_delay(retry_attempt, &timeouts) <= upper_bound);
+        }
+        assert!(retry_delay(u32::MAX, &timeouts) <= timeouts.max_retry_delay());
+    }
+
+    #[test]
+    fn test_relay_rotation() {
+        let relay: IpAddr = "192.0.2.1".parse().unwrap();
+        let other_relay: IpAddr = "192.0.2.2".parse().unwrap();
+        let mut attempts = RelayAttempts::default();
+
+        for retry_attempt in 0..MAX_ATTEMPTS_PER_RELAY {
+            assert_eq!(attempts.relay_to_avoid(retry_attempt), None);
+            attempts.record(retry_attempt, relay);
+        }
+        assert_eq!(attempts.relay_to_avoid(MAX_ATTEMPTS_PER_RELAY), Some(relay));
+        // A new connection may use any relay
+        assert_eq!(attempts.relay_to_avoid(0), None);
+
+        attempts.record(MAX_ATTEMPTS_PER_RELAY, other_relay);
+        assert_eq!(attempts.relay_to_avoid(MAX_ATTEMPTS_PER_RELAY + 1), None);
+
+        // The count starts over when a new connection is made
+        attempts.record(0, other_relay);
+        attempts.record(1, other_relay);
+        assert_eq!(attempts.relay_to_avoid(2), None);
+
+        // A rejected relay is avoided right away
+        attempts.reject();
+        assert_eq!(attempts.relay_to_avoid(2), Some(other_relay));
+    }
+}
@@ -20,10 +20,18 @@ impl DisconnectedState {
         shared_values: &mut SharedTunnelStateValues,
         should_reset_firewall: bool,
     ) {
-        let result = if shared_values.block_when_disconnected {
+        let result = if shared_values.blocks_when_disconnected() {
             let policy = FirewallPolicy::Blocked {
                 allow_lan: shared_values.allow_lan,
                 allowed_endpoint: Some(shared_values.allowed_endpoint.clone()),
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                allowed_link_config_traffic: shared_values.allowed_link_config_traffic,
+                #[cfg(not(target_os = "android"))]
+                allowed_lan_hosts: shared_values.allowed_lan_hosts.clone(),
+                #[cfg(target_os = "linux")]
+                split_tunnel_mode: shared_values.split_tunnel_mode,
+                #[cfg(target_os = "linux")]
+                shared_interface: shared_values.shared_interface.clone(),
                 #[cfg(target_os = "macos")]
                 dns_redirect_port: shared_values.filtering_resolver.listening_port(),
             };
@@ -51,7 +59,7 @@ impl DisconnectedState {
         shared_values: &mut SharedTunnelStateValues,
         should_reset_firewall: bool,
     ) {
-        if should_reset_firewall && !shared_values.block_when_disconnected {
+        if should_reset_firewall && !shared_values.blocks_when_disconnected() {
             if let Err(error) = shared_values.split_tunnel.clear_tunnel_addresses() {
                 log::error!(
                     "{}",
@@ -95,8 +103,10 @@ impl TunnelState for DisconnectedState {
         shared_values: &mut SharedTunnelStateValues,
         should_reset_firewall: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        shared_values.reconnect_reason = None;
+
         #[cfg(target_os = "macos")]
-        if shared_values.block_when_disconnected {
+        if shared_values.blocks_when_disconnected() {
             if let Err(err) = Self::setup_local_dns_config(shared_values) {
                 log::error!(
                     "{}",
@@ -114,6 +124,8 @@ impl TunnelState for DisconnectedState {
 
         #[cfg(windows)]
         Self::register_split_tunnel_addresses(shared_values, should_reset_firewall);
+        // While shutting down, the firewall rules are kept until DNS and routes have been restored
+        let should_reset_firewall = should_reset_firewall && !shared_values.is_shutting_down;
         Self::set_firewall_policy(shared_values, should_reset_firewall);
         #[cfg(target_os = "linux")]
         shared_values.reset_connectivity_check();
@@ -122,7 +134,7 @@ impl TunnelState for DisconnectedState {
 
         (
             TunnelStateWrapper::from(DisconnectedState),
-            TunnelStateTransition::Disconnected,
+            TunnelStateTransition::Disconnected(shared_values.disconnect_reason.take()),
         )
     }
 
@@ -134,6 +146,17 @@ impl TunnelState for DisconnectedState {
     ) -> EventConsequence {
         use self::EventConsequence::*;
 
+        if shared_values.is_shutting_down {
+            // The daemon closes the command channel once it has finished shutting down
+            return match runtime.block_on(commands.next()) {
+                Some(_) => SameState(self.into()),
+                None => {
+                    shared_values.restore_network();
+                    Finished
+                }
+            };
+        }
+
         match runtime.block_on(commands.next()) {
             Some(TunnelCommand::AllowLan(allow_lan)) => {
                 if shared_values.allow_lan != allow_lan {
@@ -163,14 +186,52 @@ impl TunnelState for DisconnectedState {
 
                 SameState(self.into())
             }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::AllowMulticastGroups(groups)) => {
+                shared_values.allowed_multicast_groups = groups;
+                SameState(self.into())
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::AllowLinkConfigTraffic(allowed)) => {
+                if shared_values.allowed_link_config_traffic != allowed {
+                    shared_values.allowed_link_config_traffic = allowed;
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::AllowLanHosts(hosts)) => {
+                if shared_values.allowed_lan_hosts != hosts {
+                    shared_values.allowed_lan_hosts = hosts;
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetSplitTunnelMode(mode)) => {
+                if shared_values.split_tunnel_mode != mode {
+                    shared_values.split_tunnel_mode = mode;
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetSharedInterface(interface)) => {
+                if shared_values.shared_interface != interface {
+                    shared_values.shared_interface = interface;
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
-                if shared_values.block_when_disconnected != block_when_disconnected {
-                    shared_values.block_when_disconnected = block_when_disconnected;
+                let was_blocking = shared_values.blocks_when_disconnected();
+                shared_values.block_when_disconnected = block_when_disconnected;
+                if was_blocking != shared_values.blocks_when_disconnected() {
                     Self::set_firewall_policy(shared_values, true);
                     #[cfg(windows)]
                     Self::register_split_tunnel_addresses(shared_values, true);
                     #[cfg(target_os = "macos")]
-                    if block_when_disconnected {
+                    if shared_values.blocks_when_disconnected() {
                         if let Err(err) = Self::setup_local_dns_config(shared_values) {
                             log::error!(
                                 "{}",
@@ -187,15 +248,62 @@ impl TunnelState for DisconnectedState {
                 }
                 SameState(self.into())
             }
+            Some(TunnelCommand::ConnectionTimeouts(timeouts)) => {
+                shared_values.set_connection_timeouts(timeouts);
+                SameState(self.into())
+            }
             Some(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
                 SameState(self.into())
             }
             Some(TunnelCommand::Connect) => NewState(ConnectingState::enter(shared_values, 0)),
+            Some(TunnelCommand::Reconnect) | Some(TunnelCommand::RotateKey(_)) => {
+                SameState(self.into())
+            }
+            Some(TunnelCommand::HopPort(_, result_tx)) => {
+                let _ = result_tx.send(false);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::Pause) => {
+                shared_values.is_paused = true;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::Resume) => {
+                shared_values.is_paused = false;
+                SameState(self.into())
+            }
             Some(TunnelCommand::Block(reason)) => {
                 Self::reset_dns(shared_values);
                 NewState(ErrorState::enter(shared_values, reason))
             }
+            Some(TunnelCommand::Disconnect { keep_blocking, .. }) => {
+                if keep_blocking && !shared_values.blocks_when_disconnected() {
+                    shared_values.keep_blocking = true;
+                    Self::set_firewall_policy(shared_values, false);
+                    #[cfg(windows)]
+                    Self::register_split_tunnel_addresses(shared_values, false);
+                    #[cfg(target_os = "macos")]
+                    if let Err(err) = Self::setup_local_dns_config(shared_values) {
+                        log::error!(
+                            "{}",
+                            err.display_chain_with_msg("Failed to configure host DNS")
+                        );
+                        return NewState(ErrorState::enter(
+                            shared_values,
+                            ErrorStateCause::SetDnsError,
+                        ));
+                    }
+                }
+                SameState(self.into())
+            }
+            Some(TunnelCommand::Shutdown { keep_firewall }) => {
+                let was_blocking = shared_values.blocks_when_disconnected();
+                shared_values.request_shutdown(keep_firewall);
+                if !was_blocking && shared_values.blocks_when_disconnected() {
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
             #[cfg(target_os = "android")]
             Some(TunnelCommand::BypassSocket(fd, done_tx)) => {
                 shared_values.bypass_socket(fd, done_tx);
@@ -207,7 +315,7 @@ impl TunnelState for DisconnectedState {
                 SameState(self.into())
             }
             None => {
-                Self::reset_dns(shared_values);
+                shared_values.restore_network();
                 Finished
             }
             Some(_) => SameState(self.into()),
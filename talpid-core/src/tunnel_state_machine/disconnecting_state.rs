@@ -36,17 +36,67 @@ impl DisconnectingState {
                     let _ = shared_values.set_dns_servers(servers);
                     AfterDisconnect::Nothing
                 }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::AllowMulticastGroups(groups)) => {
+                    shared_values.allowed_multicast_groups = groups;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::AllowLinkConfigTraffic(allowed)) => {
+                    shared_values.allowed_link_config_traffic = allowed;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::AllowLanHosts(hosts)) => {
+                    shared_values.allowed_lan_hosts = hosts;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetSplitTunnelMode(mode)) => {
+                    shared_values.split_tunnel_mode = mode;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetSharedInterface(interface)) => {
+                    shared_values.shared_interface = interface;
+                    AfterDisconnect::Nothing
+                }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Nothing
                 }
+                Some(TunnelCommand::ConnectionTimeouts(timeouts)) => {
+                    shared_values.set_connection_timeouts(timeouts);
+                    AfterDisconnect::Nothing
+                }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     AfterDisconnect::Nothing
                 }
-                Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
-                Some(TunnelCommand::Disconnect) | None => AfterDisconnect::Nothing,
+                Some(TunnelCommand::Pause) => {
+                    shared_values.is_paused = true;
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::Resume) => {
+                    shared_values.is_paused = false;
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::Connect) => after_disconnect.on_connect(false),
+                Some(TunnelCommand::Reconnect) => after_disconnect.on_connect(true),
+                Some(TunnelCommand::Disconnect { keep_blocking, .. }) => {
+                    shared_values.keep_blocking = keep_blocking;
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::RotateKey(_)) | None => AfterDisconnect::Nothing,
+                Some(TunnelCommand::HopPort(_, result_tx)) => {
+                    let _ = result_tx.send(false);
+                    AfterDisconnect::Nothing
+                }
                 Some(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
+                Some(TunnelCommand::Shutdown { keep_firewall }) => {
+                    shared_values.request_shutdown(keep_firewall);
+                    AfterDisconnect::Nothing
+                }
                 #[cfg(target_os = "android")]
                 Some(TunnelCommand::BypassSocket(fd, done_tx)) => {
                     shared_values.bypass_socket(fd, done_tx);
@@ -72,21 +122,71 @@ impl DisconnectingState {
                     let _ = shared_values.set_dns_servers(servers);
                     AfterDisconnect::Block(reason)
                 }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::AllowMulticastGroups(groups)) => {
+                    shared_values.allowed_multicast_groups = groups;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::AllowLinkConfigTraffic(allowed)) => {
+                    shared_values.allowed_link_config_traffic = allowed;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::AllowLanHosts(hosts)) => {
+                    shared_values.allowed_lan_hosts = hosts;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetSplitTunnelMode(mode)) => {
+                    shared_values.split_tunnel_mode = mode;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetSharedInterface(interface)) => {
+                    shared_values.shared_interface = interface;
+                    AfterDisconnect::Block(reason)
+                }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Block(reason)
                 }
+                Some(TunnelCommand::ConnectionTimeouts(timeouts)) => {
+                    shared_values.set_connection_timeouts(timeouts);
+                    AfterDisconnect::Block(reason)
+                }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
-                    if !is_offline && matches!(reason, ErrorStateCause::IsOffline) {
-                        AfterDisconnect::Reconnect(0)
-                    } else {
-                        AfterDisconnect::Block(reason)
-                    }
+                    Self::reconnect_if_online(shared_values, reason)
+                }
+                Some(TunnelCommand::Pause) => {
+                    shared_values.is_paused = true;
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::Resume) => {
+                    shared_values.is_paused = false;
+                    Self::reconnect_if_online(shared_values, reason)
+                }
+                Some(TunnelCommand::Connect) => AfterDisconnect::Block(reason).on_connect(false),
+                Some(TunnelCommand::Reconnect) => AfterDisconnect::Block(reason).on_connect(true),
+                Some(TunnelCommand::Disconnect {
+                    reason: disconnect_reason,
+                    keep_blocking,
+                }) => {
+                    shared_values.disconnect_reason = Some(disconnect_reason);
+                    shared_values.keep_blocking = keep_blocking;
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::RotateKey(_)) => AfterDisconnect::Block(reason),
+                Some(TunnelCommand::HopPort(_, result_tx)) => {
+                    let _ = result_tx.send(false);
+                    AfterDisconnect::Block(reason)
                 }
-                Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
-                Some(TunnelCommand::Disconnect) => AfterDisconnect::Nothing,
                 Some(TunnelCommand::Block(new_reason)) => AfterDisconnect::Block(new_reason),
+                Some(TunnelCommand::Shutdown { keep_firewall }) => {
+                    shared_values.request_shutdown(keep_firewall);
+                    AfterDisconnect::Nothing
+                }
                 #[cfg(target_os = "android")]
                 Some(TunnelCommand::BypassSocket(fd, done_tx)) => {
                     shared_values.bypass_socket(fd, done_tx);
@@ -113,10 +213,39 @@ impl DisconnectingState {
                     let _ = shared_values.set_dns_servers(servers);
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::AllowMulticastGroups(groups)) => {
+                    shared_values.allowed_multicast_groups = groups;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::AllowLinkConfigTraffic(allowed)) => {
+                    shared_values.allowed_link_config_traffic = allowed;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::AllowLanHosts(hosts)) => {
+                    shared_values.allowed_lan_hosts = hosts;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetSplitTunnelMode(mode)) => {
+                    shared_values.split_tunnel_mode = mode;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::SetSharedInterface(interface)) => {
+                    shared_values.shared_interface = interface;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                Some(TunnelCommand::ConnectionTimeouts(timeouts)) => {
+                    shared_values.set_connection_timeouts(timeouts);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     if is_offline {
@@ -125,9 +254,39 @@ impl DisconnectingState {
                         AfterDisconnect::Reconnect(retry_attempt)
                     }
                 }
-                Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(retry_attempt),
-                Some(TunnelCommand::Disconnect) | None => AfterDisconnect::Nothing,
+                Some(TunnelCommand::Pause) => {
+                    shared_values.is_paused = true;
+                    AfterDisconnect::Block(ErrorStateCause::IsOffline)
+                }
+                Some(TunnelCommand::Resume) => {
+                    shared_values.is_paused = false;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::Connect) => {
+                    AfterDisconnect::Reconnect(retry_attempt).on_connect(false)
+                }
+                Some(TunnelCommand::Reconnect) => {
+                    AfterDisconnect::Reconnect(retry_attempt).on_connect(true)
+                }
+                Some(TunnelCommand::RotateKey(_)) => AfterDisconnect::Reconnect(retry_attempt),
+                Some(TunnelCommand::HopPort(_, result_tx)) => {
+                    let _ = result_tx.send(false);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::Disconnect {
+                    reason,
+                    keep_blocking,
+                }) => {
+                    shared_values.disconnect_reason = Some(reason);
+                    shared_values.keep_blocking = keep_blocking;
+                    AfterDisconnect::Nothing
+                }
+                None => AfterDisconnect::Nothing,
                 Some(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
+                Some(TunnelCommand::Shutdown { keep_firewall }) => {
+                    shared_values.request_shutdown(keep_firewall);
+                    AfterDisconnect::Nothing
+                }
                 #[cfg(target_os = "android")]
                 Some(TunnelCommand::BypassSocket(fd, done_tx)) => {
                     shared_values.bypass_socket(fd, done_tx);
@@ -144,11 +303,30 @@ impl DisconnectingState {
         EventConsequence::SameState(self.into())
     }
 
+    /// Connects again after disconnecting if traffic was only going to be blocked because the
+    /// computer was offline or asleep, and neither is the case anymore.
+    fn reconnect_if_online(
+        shared_values: &SharedTunnelStateValues,
+        reason: ErrorStateCause,
+    ) -> AfterDisconnect {
+        if !shared_values.is_offline
+            && !shared_values.is_paused
+            && matches!(reason, ErrorStateCause::IsOffline)
+        {
+            AfterDisconnect::Reconnect(0)
+        } else {
+            AfterDisconnect::Block(reason)
+        }
+    }
+
     fn after_disconnect(
         self,
         block_reason: Option<ErrorStateCause>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        if shared_values.is_shutting_down {
+            return DisconnectedState::enter(shared_values, true);
+        }
         if let Some(reason) = block_reason {
             return ErrorState::enter(shared_values, reason);
         }
@@ -234,4 +412,42 @@ impl AfterDisconnect {
             AfterDisconnect::Reconnect(..) => ActionAfterDisconnect::Reconnect,
         }
     }
+
+    /// Returns what to do after disconnecting once a `Connect` command, or a `Reconnect` command
+    /// if `reconnect` is set, is received. Unlike `Connect`, `Reconnect` never opens a tunnel
+    /// when none was going to be opened.
+    fn on_connect(self, reconnect: bool) -> Self {
+        match self {
+            AfterDisconnect::Nothing if reconnect => AfterDisconnect::Nothing,
+            AfterDisconnect::Nothing | AfterDisconnect::Block(_) => AfterDisconnect::Reconnect(0),
+            AfterDisconnect::Reconnect(retry_attempt) => AfterDisconnect::Reconnect(retry_attempt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_only_restarts_opening_tunnel() {
+        assert!(matches!(
+            AfterDisconnect::Nothing.on_connect(true),
+            AfterDisconnect::Nothing
+        ));
+        assert!(matches!(
+            AfterDisconnect::Nothing.on_connect(false),
+            AfterDisconnect::Reconnect(0)
+        ));
+        for reconnect in [false, true] {
+            assert!(matches!(
+                AfterDisconnect::Block(ErrorStateCause::IsOffline).on_connect(reconnect),
+                AfterDisconnect::Reconnect(0)
+            ));
+            assert!(matches!(
+                AfterDisconnect::Reconnect(3).on_connect(reconnect),
+                AfterDisconnect::Reconnect(3)
+            ));
+        }
+    }
 }
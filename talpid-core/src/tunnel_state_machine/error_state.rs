@@ -7,7 +7,7 @@ use futures::StreamExt;
 #[cfg(target_os = "macos")]
 use std::net::Ipv4Addr;
 use talpid_types::{
-    tunnel::{self as talpid_tunnel, ErrorStateCause, FirewallPolicyError},
+    tunnel::{self as talpid_tunnel, ErrorStateCause, FirewallPolicyError, ReconnectReason},
     ErrorExt,
 };
 
@@ -17,12 +17,21 @@ pub struct ErrorState {
 }
 
 impl ErrorState {
-    fn set_firewall_policy(
+    /// Applies the policy of the error state, which blocks everything but the allowed endpoint.
+    pub(super) fn set_firewall_policy(
         shared_values: &mut SharedTunnelStateValues,
     ) -> Result<(), FirewallPolicyError> {
         let policy = FirewallPolicy::Blocked {
             allow_lan: shared_values.allow_lan,
             allowed_endpoint: Some(shared_values.allowed_endpoint.clone()),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            allowed_link_config_traffic: shared_values.allowed_link_config_traffic,
+            #[cfg(not(target_os = "android"))]
+            allowed_lan_hosts: shared_values.allowed_lan_hosts.clone(),
+            #[cfg(target_os = "linux")]
+            split_tunnel_mode: shared_values.split_tunnel_mode,
+            #[cfg(target_os = "linux")]
+            shared_interface: shared_values.shared_interface.clone(),
             #[cfg(target_os = "macos")]
             dns_redirect_port: shared_values.filtering_resolver.listening_port(),
         };
@@ -75,6 +84,20 @@ impl ErrorState {
             log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
         }
     }
+
+    /// Starts connecting if traffic was only blocked because the computer was offline or asleep,
+    /// and neither is the case anymore.
+    fn reconnect_if_online(self, shared_values: &mut SharedTunnelStateValues) -> EventConsequence {
+        if shared_values.is_offline
+            || shared_values.is_paused
+            || !matches!(self.block_reason, ErrorStateCause::IsOffline)
+        {
+            return EventConsequence::SameState(self.into());
+        }
+        Self::reset_dns(shared_values);
+        shared_values.reconnect_reason = Some(ReconnectReason::ConnectivityLost);
+        EventConsequence::NewState(ConnectingState::enter(shared_values, 0))
+    }
 }
 
 impl TunnelState for ErrorState {
@@ -84,6 +107,9 @@ impl TunnelState for ErrorState {
         shared_values: &mut SharedTunnelStateValues,
         block_reason: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        // A reconnect that ends up here is not continued
+        shared_values.reconnect_reason = None;
+
         #[cfg(windows)]
         if let Err(error) = shared_values.split_tunnel.set_tunnel_addresses(None) {
             log::error!(
@@ -172,25 +198,93 @@ impl TunnelState for ErrorState {
                     SameState(self.into())
                 }
             }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::AllowMulticastGroups(groups)) => {
+                shared_values.allowed_multicast_groups = groups;
+                SameState(self.into())
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::AllowLinkConfigTraffic(allowed)) => {
+                if shared_values.allowed_link_config_traffic != allowed {
+                    shared_values.allowed_link_config_traffic = allowed;
+                    let _ = Self::set_firewall_policy(shared_values);
+                }
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::AllowLanHosts(hosts)) => {
+                if shared_values.allowed_lan_hosts != hosts {
+                    shared_values.allowed_lan_hosts = hosts;
+                    let _ = Self::set_firewall_policy(shared_values);
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetSplitTunnelMode(mode)) => {
+                if shared_values.split_tunnel_mode != mode {
+                    shared_values.split_tunnel_mode = mode;
+                    let _ = Self::set_firewall_policy(shared_values);
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::SetSharedInterface(interface)) => {
+                if shared_values.shared_interface != interface {
+                    shared_values.shared_interface = interface;
+                    let _ = Self::set_firewall_policy(shared_values);
+                }
+                SameState(self.into())
+            }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
+            Some(TunnelCommand::ConnectionTimeouts(timeouts)) => {
+                shared_values.set_connection_timeouts(timeouts);
+                SameState(self.into())
+            }
             Some(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
-                if !is_offline && matches!(self.block_reason, ErrorStateCause::IsOffline) {
-                    Self::reset_dns(shared_values);
-                    NewState(ConnectingState::enter(shared_values, 0))
-                } else {
-                    SameState(self.into())
-                }
+                self.reconnect_if_online(shared_values)
+            }
+            Some(TunnelCommand::Pause) => {
+                shared_values.is_paused = true;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::Resume) => {
+                shared_values.is_paused = false;
+                self.reconnect_if_online(shared_values)
+            }
+            // The new key is used the next time a tunnel is opened
+            Some(TunnelCommand::RotateKey(_)) => SameState(self.into()),
+            Some(TunnelCommand::HopPort(_, result_tx)) => {
+                let _ = result_tx.send(false);
+                SameState(self.into())
             }
-            Some(TunnelCommand::Connect) => {
+            Some(TunnelCommand::Connect) | Some(TunnelCommand::Reconnect) => {
                 Self::reset_dns(shared_values);
 
                 NewState(ConnectingState::enter(shared_values, 0))
             }
-            Some(TunnelCommand::Disconnect) | None => {
+            Some(TunnelCommand::Disconnect {
+                reason,
+                keep_blocking,
+            }) => {
+                shared_values.disconnect_reason = Some(reason);
+                shared_values.keep_blocking = keep_blocking;
+                #[cfg(target_os = "linux")]
+                shared_values.reset_connectivity_check();
+                Self::reset_dns(shared_values);
+                NewState(DisconnectedState::enter(shared_values, true))
+            }
+            None => {
+                #[cfg(target_os = "linux")]
+                shared_values.reset_connectivity_check();
+                Self::reset_dns(shared_values);
+                NewState(DisconnectedState::enter(shared_values, true))
+            }
+            Some(TunnelCommand::Shutdown { keep_firewall }) => {
+                shared_values.request_shutdown(keep_firewall);
                 #[cfg(target_os = "linux")]
                 shared_values.reset_connectivity_check();
                 Self::reset_dns(shared_values);
@@ -6,7 +6,7 @@ mod error_state;
 
 use self::{
     connected_state::{ConnectedState, ConnectedStateBootstrap},
-    connecting_state::ConnectingState,
+    connecting_state::{ConnectingState, RelayAttempts},
     disconnected_state::DisconnectedState,
     disconnecting_state::{AfterDisconnect, DisconnectingState},
     error_state::ErrorState,
@@ -14,10 +14,12 @@ use self::{
 #[cfg(windows)]
 use crate::split_tunnel;
 use crate::{
+    default_route::DefaultRouteHandle,
     dns::DnsMonitor,
-    firewall::{Firewall, FirewallArguments, InitialFirewallState},
+    firewall::{Firewall, FirewallArguments, FirewallPolicyApplier, InitialFirewallState},
     mpsc::Sender,
     offline,
+    tunnel::{DefaultTunnelProvider, TunnelProvider},
 };
 #[cfg(windows)]
 use std::ffi::OsString;
@@ -26,7 +28,7 @@ use talpid_tunnel::{tun_provider::TunProvider, TunnelEvent};
 
 use futures::{
     channel::{mpsc, oneshot},
-    stream, StreamExt,
+    stream, Stream, StreamExt,
 };
 #[cfg(target_os = "android")]
 use std::os::unix::io::RawFd;
@@ -38,17 +40,22 @@ use std::{
     path::PathBuf,
     pin::Pin,
     sync::{Arc, Mutex},
-    time::Duration,
 };
 #[cfg(target_os = "android")]
-use talpid_types::{android::AndroidContext, ErrorExt};
+use talpid_types::android::AndroidContext;
+#[cfg(target_os = "linux")]
+use talpid_types::cgroup::SplitTunnelMode;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use talpid_types::net::AllowedLinkConfigTraffic;
 use talpid_types::{
-    net::{AllowedEndpoint, TunnelParameters},
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    net::{wireguard::PrivateKey, AllowedEndpoint, TunnelParameters},
+    tunnel::{
+        ConnectionTimeouts, DisconnectReason, ErrorStateCause, ParameterGenerationError,
+        ReconnectReason, TunnelStateTransition,
+    },
+    ErrorExt,
 };
 
-const TUNNEL_STATE_MACHINE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
-
 /// Errors that can happen when setting up or using the state machine.
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -100,9 +107,50 @@ pub struct InitialTunnelState {
     pub allowed_endpoint: AllowedEndpoint,
     /// Whether to reset any existing firewall rules when initializing the disconnected state.
     pub reset_firewall: bool,
+    /// Multicast groups to allow while connected, even if LAN traffic is blocked.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub allowed_multicast_groups: Vec<IpAddr>,
+    /// DHCP and NDP traffic to allow on all interfaces.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub allowed_link_config_traffic: AllowedLinkConfigTraffic,
+    /// Local hosts to allow communication with, even if LAN traffic is blocked.
+    #[cfg(not(target_os = "android"))]
+    pub allowed_lan_hosts: Vec<IpAddr>,
+    /// Whether split tunneling excludes processes from the tunnel or only lets some use it.
+    #[cfg(target_os = "linux")]
+    pub split_tunnel_mode: SplitTunnelMode,
+    /// Interface whose devices may use the tunnel through this device, if any.
+    #[cfg(target_os = "linux")]
+    pub shared_interface: Option<String>,
     /// Programs to exclude from the tunnel using the split tunnel driver.
     #[cfg(windows)]
     pub exclude_paths: Vec<OsString>,
+    /// Timing of connection attempts.
+    pub connection_timeouts: ConnectionTimeouts,
+}
+
+/// Backends that the state machine uses to open tunnels and to enforce firewall policies. The
+/// default backends act on the system. Integration tests can replace them with mocks, to drive the
+/// state machine and assert on its transitions and on the policies that it applies.
+pub struct TunnelStateMachineBackends {
+    /// Starts the tunnels.
+    pub tunnel_provider: Box<dyn TunnelProvider>,
+    /// Applies firewall policies. The system firewall is used if this is `None`.
+    pub firewall: Option<Box<dyn FirewallPolicyApplier>>,
+    /// Provides tunnel devices to the userspace tunnel implementations. Embedders that create the
+    /// device themselves, such as sandboxed ones, pass a provider that hands out that device.
+    /// A provider that creates new devices is used if this is `None`.
+    pub tun_provider: Option<TunProvider>,
+}
+
+impl Default for TunnelStateMachineBackends {
+    fn default() -> Self {
+        TunnelStateMachineBackends {
+            tunnel_provider: Box::new(DefaultTunnelProvider),
+            firewall: None,
+            tun_provider: None,
+        }
+    }
 }
 
 /// Identifiers for various network resources that should be unique to a given instance of a tunnel
@@ -120,6 +168,7 @@ pub struct LinuxNetworkingIdentifiers {
 pub async fn spawn(
     initial_settings: InitialTunnelState,
     tunnel_parameters_generator: impl TunnelParametersGenerator,
+    mut backends: TunnelStateMachineBackends,
     log_dir: Option<PathBuf>,
     resource_dir: PathBuf,
     state_change_listener: impl Sender<TunnelStateTransition> + Send + 'static,
@@ -132,30 +181,35 @@ pub async fn spawn(
     let (command_tx, command_rx) = mpsc::unbounded();
     let command_tx = Arc::new(command_tx);
 
-    let tun_provider = TunProvider::new(
-        #[cfg(target_os = "android")]
-        android_context.clone(),
-        #[cfg(target_os = "android")]
-        initial_settings.allow_lan,
-        #[cfg(target_os = "android")]
-        initial_settings.dns_servers.clone(),
-        #[cfg(target_os = "android")]
-        crate::firewall::ALLOWED_LAN_NETS
-            .iter()
-            .chain(crate::firewall::ALLOWED_LAN_MULTICAST_NETS.iter())
-            .cloned()
-            .collect(),
-    );
+    let tun_provider = backends.tun_provider.take().unwrap_or_else(|| {
+        TunProvider::new(
+            #[cfg(target_os = "android")]
+            android_context.clone(),
+            #[cfg(target_os = "android")]
+            initial_settings.allow_lan,
+            #[cfg(target_os = "android")]
+            initial_settings.dns_servers.clone(),
+            #[cfg(target_os = "android")]
+            crate::firewall::ALLOWED_LAN_NETS
+                .iter()
+                .chain(crate::firewall::ALLOWED_LAN_MULTICAST_NETS.iter())
+                .cloned()
+                .collect(),
+        )
+    });
 
     let (shutdown_tx, shutdown_rx) = oneshot::channel();
 
     let weak_command_tx = Arc::downgrade(&command_tx);
+    #[cfg(target_os = "linux")]
+    let fwmark = linux_ids.fwmark;
 
     let init_args = TunnelStateMachineInitArgs {
         settings: initial_settings,
         command_tx: weak_command_tx,
         offline_state_tx: offline_state_listener,
         tunnel_parameters_generator,
+        backends,
         tun_provider,
         log_dir,
         resource_dir,
@@ -174,6 +228,16 @@ pub async fn spawn(
 
     #[cfg(windows)]
     let split_tunnel = state_machine.shared_values.split_tunnel.handle();
+    let default_route = DefaultRouteHandle::new(
+        #[cfg(target_os = "linux")]
+        state_machine
+            .shared_values
+            .route_manager
+            .handle()
+            .map_err(Error::InitRouteManagerError)?,
+        #[cfg(target_os = "linux")]
+        fwmark,
+    );
 
     tokio::task::spawn_blocking(move || {
         state_machine.run(state_change_listener);
@@ -187,6 +251,7 @@ pub async fn spawn(
         shutdown_rx,
         #[cfg(windows)]
         split_tunnel,
+        default_route,
     })
 }
 
@@ -200,16 +265,59 @@ pub enum TunnelCommand {
     AllowEndpoint(AllowedEndpoint, oneshot::Sender<()>),
     /// Set DNS servers to use.
     Dns(Option<Vec<IpAddr>>),
+    /// Set multicast groups to allow through the firewall while connected.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    AllowMulticastGroups(Vec<IpAddr>),
+    /// Set which DHCP and NDP traffic the firewall allows on all interfaces.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    AllowLinkConfigTraffic(AllowedLinkConfigTraffic),
+    /// Set which local hosts the firewall allows communication with when LAN traffic is
+    /// blocked.
+    #[cfg(not(target_os = "android"))]
+    AllowLanHosts(Vec<IpAddr>),
+    /// Set whether split tunneling excludes processes from the tunnel or only lets some use it.
+    #[cfg(target_os = "linux")]
+    SetSplitTunnelMode(SplitTunnelMode),
+    /// Set the interface whose devices may use the tunnel through this device, or stop sharing
+    /// the tunnel.
+    #[cfg(target_os = "linux")]
+    SetSharedInterface(Option<String>),
     /// Enable or disable the block_when_disconnected feature.
     BlockWhenDisconnected(bool),
+    /// Set the timing of connection attempts. This takes effect from the next attempt.
+    ConnectionTimeouts(ConnectionTimeouts),
     /// Notify the state machine of the connectivity of the device.
     IsOffline(bool),
     /// Open tunnel connection.
     Connect,
-    /// Close tunnel connection.
-    Disconnect,
+    /// Close tunnel connection. The reason is reported when the disconnected state is entered.
+    /// If `keep_blocking` is set, traffic stays blocked once disconnected, even if
+    /// block_when_disconnected is disabled. The blocking policy then replaces the tunnel policy
+    /// directly, so that the firewall is never reset in between.
+    Disconnect {
+        reason: DisconnectReason,
+        keep_blocking: bool,
+    },
+    /// Restart the tunnel if one is open or being opened. Traffic stays blocked until the new
+    /// tunnel is up. Does nothing when disconnected.
+    Reconnect,
+    /// Replace the private key of an open WireGuard tunnel without closing it. The tunnel is
+    /// restarted if the key cannot be replaced in place. Does nothing if no tunnel is open.
+    RotateKey(PrivateKey),
+    /// Move the entry peer of an open WireGuard tunnel to another port on the same relay,
+    /// without closing the tunnel. Replies with whether the port could be changed in place.
+    HopPort(u16, oneshot::Sender<bool>),
+    /// The system is about to sleep. Any open tunnel is closed and traffic is blocked until
+    /// `Resume` is received.
+    Pause,
+    /// The system has woken up. Reconnects a tunnel that was closed by `Pause`.
+    Resume,
     /// Disconnect any open tunnel and block all network access
     Block(ErrorStateCause),
+    /// Disconnect any open tunnel and stop the state machine once disconnected. If
+    /// `keep_firewall` is set, traffic is blocked after disconnecting and the blocking rules are
+    /// left in place when the state machine exits.
+    Shutdown { keep_firewall: bool },
     /// Bypass a socket, allowing traffic to flow through outside the tunnel.
     #[cfg(target_os = "android")]
     BypassSocket(RawFd, oneshot::Sender<()>),
@@ -221,7 +329,108 @@ pub enum TunnelCommand {
     ),
 }
 
-type TunnelCommandReceiver = stream::Fuse<mpsc::UnboundedReceiver<TunnelCommand>>;
+impl TunnelCommand {
+    /// Short identifier of the command, used when tracing the state machine.
+    fn name(&self) -> &'static str {
+        match self {
+            TunnelCommand::AllowLan(..) => "allow_lan",
+            TunnelCommand::AllowEndpoint(..) => "allow_endpoint",
+            TunnelCommand::Dns(..) => "dns",
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            TunnelCommand::AllowMulticastGroups(..) => "allow_multicast_groups",
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            TunnelCommand::AllowLinkConfigTraffic(..) => "allow_link_config_traffic",
+            #[cfg(not(target_os = "android"))]
+            TunnelCommand::AllowLanHosts(..) => "allow_lan_hosts",
+            #[cfg(target_os = "linux")]
+            TunnelCommand::SetSplitTunnelMode(..) => "set_split_tunnel_mode",
+            #[cfg(target_os = "linux")]
+            TunnelCommand::SetSharedInterface(..) => "set_shared_interface",
+            TunnelCommand::BlockWhenDisconnected(..) => "block_when_disconnected",
+            TunnelCommand::ConnectionTimeouts(..) => "connection_timeouts",
+            TunnelCommand::IsOffline(..) => "is_offline",
+            TunnelCommand::Connect => "connect",
+            TunnelCommand::Disconnect { .. } => "disconnect",
+            TunnelCommand::Reconnect => "reconnect",
+            TunnelCommand::RotateKey(..) => "rotate_key",
+            TunnelCommand::HopPort(..) => "hop_port",
+            TunnelCommand::Pause => "pause",
+            TunnelCommand::Resume => "resume",
+            TunnelCommand::Block(..) => "block",
+            TunnelCommand::Shutdown { .. } => "shutdown",
+            #[cfg(target_os = "android")]
+            TunnelCommand::BypassSocket(..) => "bypass_socket",
+            #[cfg(windows)]
+            TunnelCommand::SetExcludedApps(..) => "set_excluded_apps",
+        }
+    }
+}
+
+type TunnelCommandReceiver =
+    stream::Fuse<stream::Inspect<mpsc::UnboundedReceiver<TunnelCommand>, fn(&TunnelCommand)>>;
+
+fn trace_command(command: &TunnelCommand) {
+    tracing::debug!(command = command.name(), "Received tunnel command");
+}
+
+/// Passes on changes to the offline state and power events to the state machine. Since the API
+/// cannot be reached while the system is asleep, it is reported as offline to `offline_state_tx`
+/// in the meantime.
+fn forward_connectivity_changes(
+    command_tx: std::sync::Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    offline_state_tx: mpsc::UnboundedSender<bool>,
+    initial_offline_rx: oneshot::Receiver<bool>,
+    offline_rx: mpsc::UnboundedReceiver<bool>,
+) {
+    let mut commands = stream::select(offline_rx.map(TunnelCommand::IsOffline), power_commands());
+    tokio::spawn(async move {
+        let mut is_offline = match initial_offline_rx.await {
+            Ok(is_offline) => is_offline,
+            Err(_) => return,
+        };
+        let mut is_paused = false;
+        let _ = offline_state_tx.unbounded_send(is_offline);
+
+        while let Some(command) = commands.next().await {
+            match command {
+                TunnelCommand::IsOffline(offline) => is_offline = offline,
+                TunnelCommand::Pause => is_paused = true,
+                TunnelCommand::Resume => is_paused = false,
+                _ => (),
+            }
+            if let Some(tx) = command_tx.upgrade() {
+                let _ = tx.unbounded_send(command);
+            } else {
+                break;
+            }
+            let _ = offline_state_tx.unbounded_send(is_offline || is_paused);
+        }
+    });
+}
+
+/// Returns `Pause` when the system is about to sleep and `Resume` once it has woken up.
+#[cfg(not(target_os = "android"))]
+fn power_commands() -> impl Stream<Item = TunnelCommand> + Send + 'static {
+    use crate::power::{self, PowerEvent};
+
+    let (power_tx, power_rx) = mpsc::unbounded();
+    if let Err(error) = power::spawn_monitor(power_tx) {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Unable to monitor system sleep")
+        );
+    }
+    power_rx.map(|event| match event {
+        PowerEvent::Suspend => TunnelCommand::Pause,
+        PowerEvent::Resume => TunnelCommand::Resume,
+    })
+}
+
+/// Sleep is handled by the app on Android.
+#[cfg(target_os = "android")]
+fn power_commands() -> impl Stream<Item = TunnelCommand> + Send + 'static {
+    stream::empty()
+}
 
 enum EventResult {
     Command(Option<TunnelCommand>),
@@ -237,6 +446,8 @@ enum EventResult {
 /// by the stream.
 struct TunnelStateMachine {
     current_state: Option<TunnelStateWrapper>,
+    /// Span covering the time spent in the current state.
+    current_span: tracing::Span,
     commands: TunnelCommandReceiver,
     shared_values: SharedTunnelStateValues,
 }
@@ -247,6 +458,7 @@ struct TunnelStateMachineInitArgs<G: TunnelParametersGenerator> {
     command_tx: std::sync::Weak<mpsc::UnboundedSender<TunnelCommand>>,
     offline_state_tx: mpsc::UnboundedSender<bool>,
     tunnel_parameters_generator: G,
+    backends: TunnelStateMachineBackends,
     tun_provider: TunProvider,
     log_dir: Option<PathBuf>,
     resource_dir: PathBuf,
@@ -311,7 +523,10 @@ impl TunnelStateMachine {
             fwmark: args.linux_ids.fwmark,
         };
 
-        let firewall = Firewall::from_args(fw_args).map_err(Error::InitFirewallError)?;
+        let firewall = match args.backends.firewall {
+            Some(applier) => Firewall::from_applier(applier),
+            None => Firewall::from_args(fw_args).map_err(Error::InitFirewallError)?,
+        };
 
         let dns_monitor = DnsMonitor::new(
             #[cfg(target_os = "linux")]
@@ -325,20 +540,17 @@ impl TunnelStateMachine {
         )
         .map_err(Error::InitDnsMonitorError)?;
 
-        let (offline_tx, mut offline_rx) = mpsc::unbounded();
-        let initial_offline_state_tx = args.offline_state_tx.clone();
-        tokio::spawn(async move {
-            while let Some(offline) = offline_rx.next().await {
-                if let Some(tx) = args.command_tx.upgrade() {
-                    let _ = tx.unbounded_send(TunnelCommand::IsOffline(offline));
-                } else {
-                    break;
-                }
-                let _ = args.offline_state_tx.unbounded_send(offline);
-            }
-        });
+        let (offline_tx, offline_rx) = mpsc::unbounded();
+        let (initial_offline_tx, initial_offline_rx) = oneshot::channel();
+        forward_connectivity_changes(
+            args.command_tx.clone(),
+            args.offline_state_tx,
+            initial_offline_rx,
+            offline_rx,
+        );
         let offline_monitor = offline::spawn_monitor(
             offline_tx,
+            args.settings.connection_timeouts.offline_debounce(),
             #[cfg(target_os = "linux")]
             route_manager
                 .handle()
@@ -353,7 +565,7 @@ impl TunnelStateMachine {
         .await
         .map_err(Error::OfflineMonitorError)?;
         let is_offline = offline_monitor.host_is_offline().await;
-        let _ = initial_offline_state_tx.unbounded_send(is_offline);
+        let _ = initial_offline_tx.send(is_offline);
 
         #[cfg(windows)]
         split_tunnel
@@ -367,13 +579,31 @@ impl TunnelStateMachine {
             firewall,
             dns_monitor,
             route_manager,
-            _offline_monitor: offline_monitor,
+            offline_monitor,
             allow_lan: args.settings.allow_lan,
             block_when_disconnected: args.settings.block_when_disconnected,
+            keep_blocking: false,
             is_offline,
+            is_paused: false,
+            relay_attempts: Arc::default(),
+            is_shutting_down: false,
             dns_servers: args.settings.dns_servers,
             allowed_endpoint: args.settings.allowed_endpoint,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            allowed_multicast_groups: args.settings.allowed_multicast_groups,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            allowed_link_config_traffic: args.settings.allowed_link_config_traffic,
+            #[cfg(not(target_os = "android"))]
+            allowed_lan_hosts: args.settings.allowed_lan_hosts,
+            #[cfg(target_os = "linux")]
+            split_tunnel_mode: args.settings.split_tunnel_mode,
+            #[cfg(target_os = "linux")]
+            shared_interface: args.settings.shared_interface,
+            connection_timeouts: args.settings.connection_timeouts,
+            reconnect_reason: None,
+            disconnect_reason: None,
             tunnel_parameters_generator: Box::new(args.tunnel_parameters_generator),
+            tunnel_provider: Arc::from(args.backends.tunnel_provider),
             tun_provider: Arc::new(Mutex::new(args.tun_provider)),
             log_dir: args.log_dir,
             resource_dir: args.resource_dir,
@@ -386,12 +616,17 @@ impl TunnelStateMachine {
         };
 
         tokio::task::spawn_blocking(move || {
-            let (initial_state, _) =
+            let (initial_state, transition) =
                 DisconnectedState::enter(&mut shared_values, args.settings.reset_firewall);
+            let current_span = state_span(&initial_state, &transition);
 
             Ok(TunnelStateMachine {
                 current_state: Some(initial_state),
-                commands: args.commands_rx.fuse(),
+                current_span,
+                commands: args
+                    .commands_rx
+                    .inspect(trace_command as fn(&TunnelCommand))
+                    .fuse(),
                 shared_values,
             })
         })
@@ -405,9 +640,14 @@ impl TunnelStateMachine {
         let runtime = self.shared_values.runtime.clone();
 
         while let Some(state_wrapper) = self.current_state.take() {
+            let span = self.current_span.clone();
+            let _entered = span.enter();
+
             match state_wrapper.handle_event(&runtime, &mut self.commands, &mut self.shared_values)
             {
                 NewState((state, transition)) => {
+                    self.current_span = state_span(&state, &transition);
+                    tracing::info!(next = state.name(), "Leaving tunnel state");
                     self.current_state = Some(state);
 
                     if let Err(error) = change_listener
@@ -421,6 +661,17 @@ impl TunnelStateMachine {
                 SameState(state) => {
                     self.current_state = Some(state);
                 }
+                Progress((state, transition)) => {
+                    self.current_state = Some(state);
+
+                    if let Err(error) = change_listener
+                        .send(transition)
+                        .map_err(|_| Error::SendStateChange)
+                    {
+                        log::error!("{}", error);
+                        break;
+                    }
+                }
                 Finished => (),
             }
         }
@@ -429,14 +680,43 @@ impl TunnelStateMachine {
     }
 }
 
+/// Creates a root span for a newly entered state. Fields describe the attempt and relay so that
+/// reconnect loops can be followed in an exported trace.
+fn state_span(state: &TunnelStateWrapper, transition: &TunnelStateTransition) -> tracing::Span {
+    let relay = match transition {
+        TunnelStateTransition::Connecting(endpoint, ..)
+        | TunnelStateTransition::Connected(endpoint, ..) => Some(endpoint.to_string()),
+        _ => None,
+    };
+    let reconnect_reason = match transition {
+        TunnelStateTransition::Connecting(_, Some(reason), _) => Some(reason.to_string()),
+        _ => None,
+    };
+    let cause = match transition {
+        TunnelStateTransition::Error(error_state) => Some(error_state.cause().to_string()),
+        _ => None,
+    };
+    tracing::info_span!(
+        parent: None,
+        "tunnel_state",
+        state = state.name(),
+        attempt = state.retry_attempt(),
+        relay = relay.as_deref(),
+        cause = cause.as_deref(),
+        reconnect_reason = reconnect_reason.as_deref(),
+    )
+}
+
 /// Trait for any type that can provide a stream of `TunnelParameters` to the `TunnelStateMachine`.
 pub trait TunnelParametersGenerator: Send + 'static {
     /// Given the number of consecutive failed retry attempts, it should yield a `TunnelParameters`
-    /// to establish a tunnel with.
+    /// to establish a tunnel with. If `avoid_relay` is set, attempts against the relay at that
+    /// address keep failing, so another relay should be picked if one matches the settings.
     /// If this returns `None` then the state machine goes into the `Error` state.
     fn generate(
         &mut self,
         retry_attempt: u32,
+        avoid_relay: Option<IpAddr>,
     ) -> Pin<Box<dyn Future<Output = Result<TunnelParameters, ParameterGenerationError>>>>;
 }
 
@@ -451,19 +731,54 @@ struct SharedTunnelStateValues {
     firewall: Firewall,
     dns_monitor: DnsMonitor,
     route_manager: RouteManager,
-    _offline_monitor: offline::MonitorHandle,
+    offline_monitor: offline::MonitorHandle,
     /// Should LAN access be allowed outside the tunnel.
     allow_lan: bool,
     /// Should network access be allowed when in the disconnected state.
     block_when_disconnected: bool,
+    /// Block network access in the disconnected state regardless of `block_when_disconnected`.
+    /// Set by the last disconnect command, and cleared when a new tunnel is requested.
+    keep_blocking: bool,
     /// True when the computer is known to be offline.
     is_offline: bool,
+    /// True while the system is asleep. The tunnel is kept closed, as if the computer was offline.
+    is_paused: bool,
+    /// Connection attempts in a row against the same relay. This is shared with the tunnel
+    /// monitor, which rejects relays that turn out not to support the features it requires.
+    relay_attempts: Arc<Mutex<RelayAttempts>>,
+    /// True once the state machine has been asked to shut down. It exits as soon as it has
+    /// entered the disconnected state.
+    is_shutting_down: bool,
     /// DNS servers to use (overriding default).
     dns_servers: Option<Vec<IpAddr>>,
     /// Endpoint that should not be blocked by the firewall.
     allowed_endpoint: AllowedEndpoint,
+    /// Multicast groups that are allowed while connected.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    allowed_multicast_groups: Vec<IpAddr>,
+    /// DHCP and NDP traffic that is allowed on all interfaces.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    allowed_link_config_traffic: AllowedLinkConfigTraffic,
+    /// Local hosts that are reachable even if LAN traffic is blocked.
+    #[cfg(not(target_os = "android"))]
+    allowed_lan_hosts: Vec<IpAddr>,
+    /// Whether split tunneling excludes processes from the tunnel or only lets some use it.
+    #[cfg(target_os = "linux")]
+    split_tunnel_mode: SplitTunnelMode,
+    /// Interface whose devices may use the tunnel through this device, if any.
+    #[cfg(target_os = "linux")]
+    shared_interface: Option<String>,
+    /// Timing of connection attempts.
+    connection_timeouts: ConnectionTimeouts,
+    /// Why the state machine decided to reconnect, if it did. This is reported by the next
+    /// connecting state.
+    reconnect_reason: Option<ReconnectReason>,
+    /// Why the tunnel is being disconnected. This is reported by the next disconnected state.
+    disconnect_reason: Option<DisconnectReason>,
     /// The generator of new `TunnelParameter`s
     tunnel_parameters_generator: Box<dyn TunnelParametersGenerator>,
+    /// Starts the tunnels.
+    tunnel_provider: Arc<dyn TunnelProvider>,
     /// The provider of tunnel devices.
     tun_provider: Arc<Mutex<TunProvider>>,
     /// Directory to store tunnel log file.
@@ -485,6 +800,49 @@ struct SharedTunnelStateValues {
 }
 
 impl SharedTunnelStateValues {
+    /// Marks the state machine as shutting down. Blocking when disconnected is enabled if the
+    /// firewall should be kept, so that no traffic leaks after the tunnel has been closed.
+    fn request_shutdown(&mut self, keep_firewall: bool) {
+        self.is_shutting_down = true;
+        self.disconnect_reason = Some(DisconnectReason::Shutdown);
+        if keep_firewall {
+            self.block_when_disconnected = true;
+        }
+    }
+
+    /// Returns whether traffic should be blocked in the disconnected state.
+    fn blocks_when_disconnected(&self) -> bool {
+        self.block_when_disconnected || self.keep_blocking
+    }
+
+    /// Restores DNS and routes, and then removes the firewall rules unless traffic should be
+    /// blocked after the state machine exits. Until then, the rules keep traffic from leaking
+    /// outside of the tunnel.
+    fn restore_network(&mut self) {
+        if let Err(error) = self.dns_monitor.reset() {
+            log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
+        }
+        #[cfg(not(windows))]
+        self.runtime.clone().block_on(self.route_manager.stop());
+        #[cfg(windows)]
+        self.route_manager.stop();
+
+        if !self.block_when_disconnected {
+            if let Err(error) = self.firewall.reset_policy() {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to reset firewall policy")
+                );
+            }
+        }
+    }
+
+    pub fn set_connection_timeouts(&mut self, timeouts: ConnectionTimeouts) {
+        self.offline_monitor
+            .set_debounce_delay(timeouts.offline_debounce());
+        self.connection_timeouts = timeouts;
+    }
+
     pub fn set_allow_lan(&mut self, allow_lan: bool) -> Result<(), ErrorStateCause> {
         if self.allow_lan != allow_lan {
             self.allow_lan = allow_lan;
@@ -579,6 +937,8 @@ enum EventConsequence {
     NewState((TunnelStateWrapper, TunnelStateTransition)),
     /// An event was received, but it was ignored by the state so no transition is performed.
     SameState(TunnelStateWrapper),
+    /// The state is kept, but has progressed in a way that is reported with a new transition.
+    Progress((TunnelStateWrapper, TunnelStateTransition)),
     /// The state machine has finished its execution.
     Finished,
 }
@@ -645,6 +1005,12 @@ macro_rules! state_wrapper {
                     })*
                 }
             }
+
+            fn name(&self) -> &'static str {
+                match self {
+                    $($wrapper_name::$state_variant(_) => stringify!($state_variant),)*
+                }
+            }
         }
     }
 }
@@ -659,22 +1025,34 @@ state_wrapper! {
     }
 }
 
+impl TunnelStateWrapper {
+    /// Number of consecutive failed attempts, if the state is part of a connection attempt.
+    fn retry_attempt(&self) -> Option<u32> {
+        match self {
+            TunnelStateWrapper::Connecting(state) => Some(state.retry_attempt()),
+            _ => None,
+        }
+    }
+}
+
 /// Handle used to control the tunnel state machine.
 pub struct TunnelStateMachineHandle {
     command_tx: Arc<mpsc::UnboundedSender<TunnelCommand>>,
     shutdown_rx: oneshot::Receiver<()>,
     #[cfg(windows)]
     split_tunnel: split_tunnel::SplitTunnelHandle,
+    default_route: DefaultRouteHandle,
 }
 
 impl TunnelStateMachineHandle {
-    /// Waits for the tunnel state machine to shut down.
-    /// This may fail after a timeout of `TUNNEL_STATE_MACHINE_SHUTDOWN_TIMEOUT`.
-    pub async fn try_join(self) {
+    /// Closes the command channel and waits for the tunnel state machine to exit. Once the
+    /// tunnel is disconnected, closing the channel makes the state machine restore DNS and routes
+    /// and remove its firewall rules, unless traffic should stay blocked.
+    pub async fn join(self) {
         drop(self.command_tx);
 
-        match tokio::time::timeout(TUNNEL_STATE_MACHINE_SHUTDOWN_TIMEOUT, self.shutdown_rx).await {
-            Ok(_) => log::info!("Tunnel state machine shut down"),
+        match self.shutdown_rx.await {
+            Ok(()) => log::info!("Tunnel state machine shut down"),
             Err(_) => log::error!("Tunnel state machine did not shut down gracefully"),
         }
     }
@@ -689,4 +1067,9 @@ impl TunnelStateMachineHandle {
     pub fn split_tunnel(&self) -> &split_tunnel::SplitTunnelHandle {
         &self.split_tunnel
     }
+
+    /// Returns a handle used to look up the default routes.
+    pub fn default_route(&self) -> &DefaultRouteHandle {
+        &self.default_route
+    }
 }
@@ -3,7 +3,9 @@
 pub use dbus;
 use dbus::blocking::SyncConnection;
 use std::sync::{Arc, Mutex};
+pub mod logind;
 pub mod network_manager;
+pub mod notifications;
 pub mod systemd;
 pub mod systemd_resolved;
 
@@ -0,0 +1,78 @@
+use dbus::{
+    arg::OwnedFd,
+    blocking::{Proxy, SyncConnection},
+    message::MatchRule,
+};
+use std::{thread, time::Duration};
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to create a DBus connection")]
+    ConnectError(#[error(source)] dbus::Error),
+
+    #[error(display = "Failed to listen for sleep signals")]
+    MatchSleepError(#[error(source)] dbus::Error),
+}
+
+const LOGIND_BUS: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const PREPARE_FOR_SLEEP: &str = "PrepareForSleep";
+const INHIBIT_METHOD: &str = "Inhibit";
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Calls `callback` with `true` when the system is about to sleep, and with `false` once it has
+/// woken up. Sleep is delayed until `callback` has returned, for at most the `InhibitDelayMaxSec`
+/// of logind.
+pub fn watch_sleep<F: FnMut(bool) + Send + 'static>(mut callback: F) -> Result<()> {
+    // The signals are processed on a thread of their own, so a separate connection is used
+    let connection = SyncConnection::new_system().map_err(Error::ConnectError)?;
+
+    let mut inhibitor = take_sleep_inhibitor(&connection);
+    let mut match_rule = MatchRule::new_signal(MANAGER_INTERFACE, PREPARE_FOR_SLEEP);
+    match_rule.path = Some(LOGIND_PATH.into());
+    connection
+        .add_match(
+            match_rule,
+            move |(going_to_sleep,): (bool,), connection, _message| {
+                callback(going_to_sleep);
+                if going_to_sleep {
+                    // Let the system sleep
+                    inhibitor.take();
+                } else if inhibitor.is_none() {
+                    inhibitor = take_sleep_inhibitor(connection);
+                }
+                true
+            },
+        )
+        .map_err(Error::MatchSleepError)?;
+
+    thread::spawn(move || loop {
+        if let Err(err) = connection.process(Duration::from_secs(60)) {
+            log::error!("Failed to process DBus messages: {}", err);
+            break;
+        }
+    });
+    Ok(())
+}
+
+/// Takes a lock that delays sleep until it is dropped. Sleep is not delayed if this fails.
+fn take_sleep_inhibitor(connection: &SyncConnection) -> Option<OwnedFd> {
+    let result: std::result::Result<(OwnedFd,), _> =
+        Proxy::new(LOGIND_BUS, LOGIND_PATH, RPC_TIMEOUT, connection).method_call(
+            MANAGER_INTERFACE,
+            INHIBIT_METHOD,
+            ("sleep", "Mullvad VPN", "Closing the tunnel", "delay"),
+        );
+    match result {
+        Ok((fd,)) => Some(fd),
+        Err(err) => {
+            log::warn!("Failed to delay sleep: {}", err);
+            None
+        }
+    }
+}
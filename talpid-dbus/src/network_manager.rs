@@ -43,6 +43,10 @@ const NM_SETTINGS_INTERFACE: &str = "org.freedesktop.NetworkManager.Settings";
 const NM_SETTINGS_CONNECTION_INTERFACE: &str = "org.freedesktop.NetworkManager.Settings.Connection";
 const NM_SETTINGS_PATH: &str = "/org/freedesktop/NetworkManager/Settings";
 const NM_CONNECTION_ACTIVE: &str = "org.freedesktop.NetworkManager.Connection.Active";
+const NM_DEVICE_WIRELESS: &str = "org.freedesktop.NetworkManager.Device.Wireless";
+const NM_ACCESS_POINT: &str = "org.freedesktop.NetworkManager.AccessPoint";
+const NM_CONNECTION_TYPE_WIRED: &str = "802-3-ethernet";
+const NM_CONNECTION_TYPE_WIFI: &str = "802-11-wireless";
 
 const NM_ADD_CONNECTION_VOLATILE: u32 = 0x2;
 
@@ -641,6 +645,54 @@ impl NetworkManager {
         Err(Error::DeviceNotFound)
     }
 
+    /// Returns the kind of network that NetworkManager routes the default traffic through, or
+    /// `None` if there is no primary connection.
+    pub fn primary_connection(&self) -> Result<Option<PrimaryConnection>> {
+        let connection_path: dbus::Path<'static> = self
+            .as_manager()
+            .get(NM_MANAGER, "PrimaryConnection")
+            .map_err(Error::Dbus)?;
+        if &*connection_path == "/" {
+            return Ok(None);
+        }
+        let connection = self.as_path(&connection_path);
+        let connection_type: String = connection
+            .get(NM_CONNECTION_ACTIVE, "Type")
+            .map_err(Error::Dbus)?;
+        match connection_type.as_str() {
+            NM_CONNECTION_TYPE_WIRED => return Ok(Some(PrimaryConnection::Wired)),
+            NM_CONNECTION_TYPE_WIFI => (),
+            "wireguard" | "tun" | "vpn" => return Ok(Some(PrimaryConnection::Tunnel)),
+            _ => return Ok(Some(PrimaryConnection::Other)),
+        }
+
+        let devices: Vec<dbus::Path<'static>> = connection
+            .get(NM_CONNECTION_ACTIVE, "Devices")
+            .map_err(Error::Dbus)?;
+        let device = devices.first().ok_or(Error::NoDevice)?;
+        let access_point: dbus::Path<'static> = self
+            .as_path(device)
+            .get(NM_DEVICE_WIRELESS, "ActiveAccessPoint")
+            .map_err(Error::Dbus)?;
+        if &*access_point == "/" {
+            return Ok(Some(PrimaryConnection::Wifi {
+                ssid: None,
+                bssid: None,
+            }));
+        }
+        let access_point = self.as_path(&access_point);
+        let ssid: Vec<u8> = access_point
+            .get(NM_ACCESS_POINT, "Ssid")
+            .map_err(Error::Dbus)?;
+        let bssid: String = access_point
+            .get(NM_ACCESS_POINT, "HwAddress")
+            .map_err(Error::Dbus)?;
+        Ok(Some(PrimaryConnection::Wifi {
+            ssid: Some(String::from_utf8_lossy(&ssid).into_owned()).filter(|ssid| !ssid.is_empty()),
+            bssid: Some(bssid).filter(|bssid| !bssid.is_empty()),
+        }))
+    }
+
     pub fn convert_address_to_dbus(address: &IpAddr) -> VariantMap {
         let mut map: VariantMap = HashMap::new();
         map.insert(
@@ -654,6 +706,19 @@ impl NetworkManager {
     }
 }
 
+/// Kind of network that the primary connection of NetworkManager uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrimaryConnection {
+    Wired,
+    Wifi {
+        ssid: Option<String>,
+        bssid: Option<String>,
+    },
+    /// A VPN or tunnel device, such as the one used by the app itself.
+    Tunnel,
+    Other,
+}
+
 #[derive(Debug)]
 struct DeviceStateChange {
     new_state: u32,
@@ -0,0 +1,99 @@
+//! Desktop notifications, shown through the session bus of a user.
+
+use dbus::{
+    arg::PropMap,
+    blocking::{stdintf::org_freedesktop_dbus::Properties, Connection, Proxy},
+};
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to connect to the bus")]
+    ConnectError(#[error(source)] dbus::Error),
+
+    #[error(display = "Failed to list the user sessions")]
+    ListSessionsError(#[error(source)] dbus::Error),
+
+    #[error(display = "Failed to send a notification")]
+    NotifyError(#[error(source)] dbus::Error),
+}
+
+const NOTIFICATIONS_BUS: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const NOTIFICATIONS_INTERFACE: &str = "org.freedesktop.Notifications";
+
+const LOGIND_BUS: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const LOGIND_MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const LOGIND_SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+
+/// Ask the notification server to choose how long the notification is shown.
+const DEFAULT_EXPIRE_TIMEOUT: i32 = -1;
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Returns the IDs of the users whose sessions are in the foreground of a local seat. Remote
+/// sessions, such as those over SSH, are left out.
+pub fn active_session_users() -> Result<Vec<u32>> {
+    let connection = crate::get_connection().map_err(Error::ConnectError)?;
+    let manager = Proxy::new(LOGIND_BUS, LOGIND_PATH, RPC_TIMEOUT, &*connection);
+    let (sessions,): (Vec<(String, u32, String, String, dbus::Path<'static>)>,) = manager
+        .method_call(LOGIND_MANAGER_INTERFACE, "ListSessions", ())
+        .map_err(Error::ListSessionsError)?;
+
+    let mut uids = vec![];
+    for (_id, uid, _user, seat, path) in sessions {
+        if seat.is_empty() || uids.contains(&uid) {
+            continue;
+        }
+        let session = Proxy::new(LOGIND_BUS, path, RPC_TIMEOUT, &*connection);
+        let is_active: bool = session
+            .get(LOGIND_SESSION_INTERFACE, "Active")
+            .map_err(Error::ListSessionsError)?;
+        let is_remote: bool = session
+            .get(LOGIND_SESSION_INTERFACE, "Remote")
+            .map_err(Error::ListSessionsError)?;
+        if is_active && !is_remote {
+            uids.push(uid);
+        }
+    }
+    Ok(uids)
+}
+
+/// Returns the address of the session bus of the user with the ID `uid`.
+pub fn session_bus_address(uid: u32) -> String {
+    format!("unix:path=/run/user/{}/bus", uid)
+}
+
+/// Shows a notification on the session bus of the current user. The session bus only accepts
+/// connections from the user that it belongs to, so this cannot be used by root to reach other
+/// users.
+pub fn notify(app_name: &str, summary: &str, body: &str) -> Result<()> {
+    let connection = Connection::new_session().map_err(Error::ConnectError)?;
+    let proxy = Proxy::new(
+        NOTIFICATIONS_BUS,
+        NOTIFICATIONS_PATH,
+        RPC_TIMEOUT,
+        &connection,
+    );
+    let _: (u32,) = proxy
+        .method_call(
+            NOTIFICATIONS_INTERFACE,
+            "Notify",
+            (
+                app_name,
+                0u32,
+                "",
+                summary,
+                body,
+                Vec::<String>::new(),
+                PropMap::new(),
+                DEFAULT_EXPIRE_TIMEOUT,
+            ),
+        )
+        .map_err(Error::NotifyError)?;
+    Ok(())
+}
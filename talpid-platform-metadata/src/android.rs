@@ -36,6 +36,11 @@ pub fn extra_metadata() -> HashMap<String, String> {
     metadata
 }
 
+/// Android only allows one VPN to be active at a time, so there is nothing to detect.
+pub fn conflicting_software() -> Vec<talpid_types::conflict::ConflictingSoftware> {
+    vec![]
+}
+
 fn get_prop(property: &str) -> Option<String> {
     command_stdout_lossy("getprop", &[property])
 }
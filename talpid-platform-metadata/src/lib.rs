@@ -14,7 +14,7 @@ mod imp;
 #[path = "android.rs"]
 mod imp;
 
-pub use self::imp::{extra_metadata, short_version, version};
+pub use self::imp::{conflicting_software, extra_metadata, short_version, version};
 
 #[cfg(target_os = "windows")]
 pub use self::imp::WindowsVersion;
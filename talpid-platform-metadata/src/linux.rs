@@ -1,5 +1,12 @@
 mod command;
 use command::command_stdout_lossy;
+use std::{collections::BTreeSet, fs, path::Path};
+use talpid_types::conflict::{ConflictKind, ConflictingSoftware};
+
+/// Name of the WireGuard interface created by the daemon.
+const OWN_TUNNEL_INTERFACE: &str = "wg-mullvad";
+/// Interface type used by both TUN devices and WireGuard interfaces.
+const ARPHRD_NONE: &str = "65534";
 
 pub fn version() -> String {
     // The OS version information is obtained first from the os-release file. If that
@@ -116,3 +123,88 @@ fn systemd_version() -> Option<(String, String)> {
     let version = systemd_version_output.lines().next()?.to_string();
     Some(("systemd".to_string(), version))
 }
+
+/// Returns tunnel interfaces, other than our own, that have a default route in the main routing
+/// table. These usually belong to another VPN client.
+pub fn conflicting_software() -> Vec<ConflictingSoftware> {
+    let ipv4_routes = fs::read_to_string("/proc/net/route").unwrap_or_default();
+    let ipv6_routes = fs::read_to_string("/proc/net/ipv6_route").unwrap_or_default();
+
+    parse_ipv4_default_routes(&ipv4_routes)
+        .chain(parse_ipv6_default_routes(&ipv6_routes))
+        .filter(|interface| *interface != OWN_TUNNEL_INTERFACE && is_tunnel_interface(interface))
+        .map(str::to_owned)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|name| ConflictingSoftware {
+            kind: ConflictKind::TunnelInterface,
+            name,
+        })
+        .collect()
+}
+
+fn is_tunnel_interface(interface: &str) -> bool {
+    let interface_dir = Path::new("/sys/class/net").join(interface);
+    interface_dir.join("tun_flags").exists()
+        || fs::read_to_string(interface_dir.join("type"))
+            .map(|if_type| if_type.trim() == ARPHRD_NONE)
+            .unwrap_or(false)
+}
+
+/// Each line in `/proc/net/route` after the header starts with the interface name, followed by
+/// the destination and gateway in hex:
+/// > wlan0   00000000    0101A8C0    0003 ...
+fn parse_ipv4_default_routes(routes: &str) -> impl Iterator<Item = &str> {
+    routes.lines().skip(1).filter_map(|line| {
+        let mut fields = line.split_whitespace();
+        let interface = fields.next()?;
+        let destination = fields.next()?;
+        let mask = fields.nth(5)?;
+        (destination == "00000000" && mask == "00000000").then(|| interface)
+    })
+}
+
+/// Each line in `/proc/net/ipv6_route` contains the destination and prefix length first, and the
+/// interface name last:
+/// > 00000000000000000000000000000000 00 ... 00000003 00000000 00000001 wlan0
+fn parse_ipv6_default_routes(routes: &str) -> impl Iterator<Item = &str> {
+    routes.lines().filter_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 10 {
+            return None;
+        }
+        let is_default = fields[0].chars().all(|c| c == '0') && fields[1] == "00";
+        is_default.then(|| fields[9])
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_ipv4_default_routes() {
+        let routes = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+tun0\t00000000\t00000000\t0001\t0\t0\t0\t00000000\t0\t0\t0
+tun0\t0000080A\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0
+";
+        assert_eq!(
+            parse_ipv4_default_routes(routes).collect::<Vec<_>>(),
+            vec!["wlan0", "tun0"]
+        );
+    }
+
+    #[test]
+    fn test_parse_ipv6_default_routes() {
+        let routes = "\
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003 wlan0
+fd000000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001 tun0
+";
+        assert_eq!(
+            parse_ipv6_default_routes(routes).collect::<Vec<_>>(),
+            vec!["wlan0"]
+        );
+    }
+}
@@ -1,5 +1,7 @@
 mod command;
 use command::command_stdout_lossy;
+use std::collections::BTreeSet;
+use talpid_types::conflict::{ConflictKind, ConflictingSoftware};
 
 pub fn version() -> String {
     let version = run_sw_vers().unwrap_or(String::from("N/A"));
@@ -29,3 +31,34 @@ fn parse_short_version_output(output: String) -> Option<(u32, u32)> {
     let minor = parts.next()?.parse().ok()?;
     Some((major, minor))
 }
+
+/// Returns `utun` interfaces that have an IPv4 default route. The daemon never adds a default
+/// route to its own tunnel interface, so these belong to other VPN clients. IPv6 is ignored since
+/// the system itself sets up link-local default routes on some `utun` interfaces.
+pub fn conflicting_software() -> Vec<ConflictingSoftware> {
+    let routes = command_stdout_lossy("netstat", &["-rn", "-f", "inet"]).unwrap_or_default();
+    parse_utun_default_routes(&routes)
+        .into_iter()
+        .map(|name| ConflictingSoftware {
+            kind: ConflictKind::TunnelInterface,
+            name,
+        })
+        .collect()
+}
+
+/// `netstat -rn -f inet` lists one route per line, with the interface in the fourth column:
+/// > default            link#20            UCSIg        utun3
+fn parse_utun_default_routes(routes: &str) -> BTreeSet<String> {
+    routes
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["default", _gateway, _flags, interface, ..] if interface.starts_with("utun") => {
+                    Some((*interface).to_owned())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
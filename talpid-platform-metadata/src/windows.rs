@@ -1,14 +1,42 @@
 use std::{
+    collections::BTreeSet,
     ffi::OsString,
     io, iter,
     mem::{self, MaybeUninit},
-    os::windows::ffi::OsStrExt,
+    os::windows::ffi::{OsStrExt, OsStringExt},
+    ptr, slice,
 };
-use windows_sys::Win32::System::{
-    LibraryLoader::{GetModuleHandleW, GetProcAddress},
-    SystemInformation::OSVERSIONINFOW,
+use talpid_types::conflict::{ConflictKind, ConflictingSoftware};
+use windows_sys::Win32::{
+    Foundation::{ERROR_SUCCESS, HANDLE, NO_ERROR},
+    NetworkManagement::{
+        IpHelper::{
+            FreeMibTable, GetIfEntry2, GetIpForwardTable2, MIB_IF_ROW2, MIB_IPFORWARD_TABLE2,
+        },
+        WindowsFilteringPlatform::{
+            FwpmEngineClose0, FwpmEngineOpen0, FwpmFreeMemory0, FwpmProviderCreateEnumHandle0,
+            FwpmProviderDestroyEnumHandle0, FwpmProviderEnum0, FWPM_PROVIDER0,
+        },
+    },
+    Networking::WinSock::AF_UNSPEC,
+    System::{
+        LibraryLoader::{GetModuleHandleW, GetProcAddress},
+        Rpc::RPC_C_AUTHN_WINNT,
+        SystemInformation::OSVERSIONINFOW,
+    },
 };
 
+/// Alias and WFP provider name used by the daemon itself.
+const OWN_NAME: &str = "mullvad";
+/// Interface type of Wintun and WireGuard-NT adapters.
+const IF_TYPE_PROP_VIRTUAL: u32 = 53;
+/// Substrings of adapter descriptions that identify TAP-based tunnel adapters.
+const TUNNEL_ADAPTER_DESCRIPTIONS: &[&str] = &["tap-windows", "wintun", "wireguard"];
+/// Substrings of WFP provider names that suggest a VPN client.
+const VPN_PROVIDER_NAMES: &[&str] = &["vpn", "wireguard"];
+/// Number of WFP providers to request per enumeration call.
+const PROVIDER_ENUM_BATCH_SIZE: u32 = 64;
+
 #[allow(non_camel_case_types)]
 type RTL_OSVERSIONINFOW = OSVERSIONINFOW;
 
@@ -36,6 +64,148 @@ pub fn extra_metadata() -> impl Iterator<Item = (String, String)> {
     std::iter::empty()
 }
 
+/// Returns tunnel adapters, other than our own, that have a default route, as well as WFP providers
+/// registered by other VPN clients.
+pub fn conflicting_software() -> Vec<ConflictingSoftware> {
+    let adapters = tunnel_adapters_with_default_route()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|name| ConflictingSoftware {
+            kind: ConflictKind::TunnelInterface,
+            name,
+        });
+    let providers = vpn_wfp_providers()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|name| ConflictingSoftware {
+            kind: ConflictKind::WfpProvider,
+            name,
+        });
+    adapters.chain(providers).collect()
+}
+
+fn tunnel_adapters_with_default_route() -> io::Result<BTreeSet<String>> {
+    let mut table: *mut MIB_IPFORWARD_TABLE2 = ptr::null_mut();
+    let status = unsafe { GetIpForwardTable2(AF_UNSPEC as u16, &mut table) };
+    if status != NO_ERROR as i32 {
+        return Err(io::Error::from_raw_os_error(status));
+    }
+    let rows = unsafe {
+        slice::from_raw_parts(&(*table).Table[0] as *const _, (*table).NumEntries as usize)
+    };
+
+    let mut adapters = BTreeSet::new();
+    for route in rows
+        .iter()
+        .filter(|route| route.DestinationPrefix.PrefixLength == 0)
+    {
+        let mut row: MIB_IF_ROW2 = unsafe { mem::zeroed() };
+        row.InterfaceLuid = route.InterfaceLuid;
+        if unsafe { GetIfEntry2(&mut row) } != NO_ERROR as i32 {
+            continue;
+        }
+        let alias = string_from_wide(&row.Alias);
+        let description = string_from_wide(&row.Description).to_lowercase();
+        if alias.to_lowercase().contains(OWN_NAME) {
+            continue;
+        }
+        let is_tunnel = row.Type == IF_TYPE_PROP_VIRTUAL
+            || TUNNEL_ADAPTER_DESCRIPTIONS
+                .iter()
+                .any(|name| description.contains(name));
+        if is_tunnel {
+            adapters.insert(alias);
+        }
+    }
+    unsafe { FreeMibTable(table as *mut _) };
+
+    Ok(adapters)
+}
+
+fn vpn_wfp_providers() -> io::Result<BTreeSet<String>> {
+    let mut engine: HANDLE = 0;
+    let status = unsafe {
+        FwpmEngineOpen0(
+            ptr::null(),
+            RPC_C_AUTHN_WINNT,
+            ptr::null(),
+            ptr::null(),
+            &mut engine,
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+
+    let result = enumerate_wfp_providers(engine);
+    unsafe { FwpmEngineClose0(engine) };
+
+    Ok(result?
+        .into_iter()
+        .filter(|name| {
+            let name = name.to_lowercase();
+            !name.contains(OWN_NAME) && VPN_PROVIDER_NAMES.iter().any(|vpn| name.contains(vpn))
+        })
+        .collect())
+}
+
+fn enumerate_wfp_providers(engine: HANDLE) -> io::Result<BTreeSet<String>> {
+    let mut enum_handle: HANDLE = 0;
+    let status = unsafe { FwpmProviderCreateEnumHandle0(engine, ptr::null(), &mut enum_handle) };
+    if status != ERROR_SUCCESS {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+
+    let mut names = BTreeSet::new();
+    let result = loop {
+        let mut entries: *mut *mut FWPM_PROVIDER0 = ptr::null_mut();
+        let mut num_entries = 0u32;
+        let status = unsafe {
+            FwpmProviderEnum0(
+                engine,
+                enum_handle,
+                PROVIDER_ENUM_BATCH_SIZE,
+                &mut entries,
+                &mut num_entries,
+            )
+        };
+        if status != ERROR_SUCCESS {
+            break Err(io::Error::from_raw_os_error(status as i32));
+        }
+        for i in 0..num_entries as usize {
+            let name = unsafe { (**entries.add(i)).displayData.name };
+            if !name.is_null() {
+                names.insert(unsafe { string_from_wide_ptr(name) });
+            }
+        }
+        unsafe { FwpmFreeMemory0(&mut entries as *mut _ as *mut _) };
+        if num_entries < PROVIDER_ENUM_BATCH_SIZE {
+            break Ok(names);
+        }
+    };
+    unsafe { FwpmProviderDestroyEnumHandle0(engine, enum_handle) };
+
+    result
+}
+
+fn string_from_wide(buffer: &[u16]) -> String {
+    let nul = buffer
+        .iter()
+        .position(|&c| c == 0u16)
+        .unwrap_or(buffer.len());
+    OsString::from_wide(&buffer[..nul])
+        .to_string_lossy()
+        .into_owned()
+}
+
+unsafe fn string_from_wide_ptr(ptr: *const u16) -> String {
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    string_from_wide(slice::from_raw_parts(ptr, len))
+}
+
 pub struct WindowsVersion {
     inner: RTL_OSVERSIONINFOW,
 }
@@ -5,18 +5,20 @@
 mod proto {
     tonic::include_proto!("tunnel_config");
 }
-use classic_mceliece_rust::{PublicKey, CRYPTO_PUBLICKEYBYTES};
 use proto::{
     post_quantum_secure_server::{PostQuantumSecure, PostQuantumSecureServer},
     PskRequestExperimentalV0, PskRequestExperimentalV1, PskResponseExperimentalV0,
     PskResponseExperimentalV1,
 };
+use talpid_tunnel_config_client::kem::{self, KemRegistry};
 use talpid_types::net::wireguard::PresharedKey;
 
 use tonic::{transport::Server, Request, Response, Status};
 
 #[derive(Debug, Default)]
-pub struct PostQuantumSecureImpl {}
+pub struct PostQuantumSecureImpl {
+    kems: KemRegistry,
+}
 
 #[tonic::async_trait]
 impl PostQuantumSecure for PostQuantumSecureImpl {
@@ -31,7 +33,6 @@ impl PostQuantumSecure for PostQuantumSecureImpl {
         &self,
         request: Request<PskRequestExperimentalV1>,
     ) -> Result<Response<PskResponseExperimentalV1>, Status> {
-        let mut rng = rand::thread_rng();
         let request = request.into_inner();
 
         println!("wg_pubkey: {:?}", request.wg_pubkey);
@@ -44,23 +45,14 @@ impl PostQuantumSecure for PostQuantumSecureImpl {
 
         for kem_pubkey in request.kem_pubkeys {
             println!("\tKEM algorithm: {}", kem_pubkey.algorithm_name);
-            let (ciphertext, shared_secret) = match kem_pubkey.algorithm_name.as_str() {
-                "Classic-McEliece-460896f" => {
-                    let key_data: [u8; CRYPTO_PUBLICKEYBYTES] =
-                        kem_pubkey.key_data.as_slice().try_into().unwrap();
-                    let public_key = PublicKey::from(&key_data);
-                    let (ciphertext, shared_secret) =
-                        classic_mceliece_rust::encapsulate_boxed(&public_key, &mut rng);
-                    (ciphertext.as_array().to_vec(), *shared_secret.as_array())
-                }
-                name => panic!("Unsupported KEM algorithm: {name}"),
-            };
+            let (ciphertext, shared_secret) = self
+                .kems
+                .encapsulate(&kem_pubkey.algorithm_name, &kem_pubkey.key_data)
+                .map_err(|error| Status::invalid_argument(error.to_string()))?;
 
             ciphertexts.push(ciphertext);
             println!("\tshared secret: {:?}", shared_secret);
-            for (psk_byte, shared_secret_byte) in psk_data.iter_mut().zip(shared_secret.iter()) {
-                *psk_byte ^= shared_secret_byte;
-            }
+            kem::xor_assign(&mut psk_data, &shared_secret);
         }
 
         let psk = PresharedKey::from(psk_data);
@@ -1,27 +1,71 @@
-use classic_mceliece_rust::{keypair_boxed, SharedSecret};
+use crate::kem::{shared_secret_from, Error, Kem, KemKeypair, SharedSecret};
+use classic_mceliece_rust::{
+    decapsulate_boxed, encapsulate_boxed, keypair_boxed, Ciphertext, PublicKey, SecretKey,
+    CRYPTO_CIPHERTEXTBYTES, CRYPTO_PUBLICKEYBYTES,
+};
 
-/// The `keypair_boxed` function needs just under 1 MiB of stack in debug
-/// builds. Even though it probably works to run it directly on the main
-/// thread on all OSes, we take this precaution and always generate the huge
-/// keys on a separate thread with a large enough stack.
-const STACK_SIZE: usize = 2 * 1024 * 1024;
+/// Use the smallest CME variant with NIST security level 3. This variant has significantly smaller
+/// keys than the larger variants, and is considered safe.
+pub const ALGORITHM_NAME: &str = "Classic-McEliece-460896f";
 
-pub use classic_mceliece_rust::{Ciphertext, PublicKey, SecretKey, CRYPTO_CIPHERTEXTBYTES};
+/// Classic McEliece implementation of [`Kem`].
+pub struct ClassicMcEliece;
 
-pub async fn generate_keys() -> (PublicKey<'static>, SecretKey<'static>) {
-    let (tx, rx) = tokio::sync::oneshot::channel();
+impl Kem for ClassicMcEliece {
+    fn algorithm_name(&self) -> &'static str {
+        ALGORITHM_NAME
+    }
 
-    std::thread::Builder::new()
-        .stack_size(STACK_SIZE)
-        .spawn(move || {
-            let keypair = keypair_boxed(&mut rand::thread_rng());
-            let _ = tx.send(keypair);
+    fn generate_keypair(&self) -> Box<dyn KemKeypair> {
+        let (public_key, secret_key) = keypair_boxed(&mut rand::thread_rng());
+        Box::new(Keypair {
+            public_key,
+            secret_key,
         })
-        .unwrap();
+    }
 
-    rx.await.unwrap()
+    fn encapsulate(&self, public_key: &[u8]) -> Result<(Vec<u8>, SharedSecret), Error> {
+        let key_data = <&[u8; CRYPTO_PUBLICKEYBYTES]>::try_from(public_key).map_err(|_| {
+            Error::InvalidPublicKeyLength {
+                algorithm: ALGORITHM_NAME,
+                actual: public_key.len(),
+                expected: CRYPTO_PUBLICKEYBYTES,
+            }
+        })?;
+        let public_key = PublicKey::from(key_data);
+        let (ciphertext, shared_secret) = encapsulate_boxed(&public_key, &mut rand::thread_rng());
+        Ok((
+            ciphertext.as_array().to_vec(),
+            shared_secret_from(shared_secret.as_array()),
+        ))
+    }
 }
 
-pub fn decapsulate(secret: &SecretKey, ciphertext: &Ciphertext) -> SharedSecret<'static> {
-    classic_mceliece_rust::decapsulate_boxed(ciphertext, secret)
+struct Keypair {
+    public_key: PublicKey<'static>,
+    secret_key: SecretKey<'static>,
+}
+
+impl KemKeypair for Keypair {
+    fn algorithm_name(&self) -> &'static str {
+        ALGORITHM_NAME
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        self.public_key.as_array().to_vec()
+    }
+
+    fn decapsulate(&self, ciphertext: &[u8]) -> Result<SharedSecret, Error> {
+        let ciphertext_array =
+            <[u8; CRYPTO_CIPHERTEXTBYTES]>::try_from(ciphertext).map_err(|_| {
+                Error::InvalidCiphertextLength {
+                    algorithm: ALGORITHM_NAME,
+                    actual: ciphertext.len(),
+                    expected: CRYPTO_CIPHERTEXTBYTES,
+                }
+            })?;
+        let ciphertext = Ciphertext::from(ciphertext_array);
+        let shared_secret = decapsulate_boxed(&ciphertext, &self.secret_key);
+        Ok(shared_secret_from(shared_secret.as_array()))
+    }
 }
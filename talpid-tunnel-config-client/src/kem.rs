@@ -0,0 +1,283 @@
+//! Registry of the key encapsulation mechanisms (KEMs) that can be mixed into the PSK negotiated
+//! with the tunnel config service. Adding support for a new KEM only requires implementing
+//! [`Kem`] for it and registering it in [`KemRegistry`].
+
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// Size of the shared secret that every KEM must output. The shared secrets of all KEMs used in
+/// an exchange are XORed together to form the WireGuard PSK.
+pub const SHARED_SECRET_BYTES: usize = 32;
+
+/// The `keypair_boxed` function of Classic McEliece needs just under 1 MiB of stack in debug
+/// builds. Even though it probably works to run it directly on the main thread on all OSes, we
+/// take this precaution and always generate the keys on a separate thread with a large enough
+/// stack, no matter the KEM.
+const KEYGEN_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+/// Shared secret output by a KEM. Kept on the heap so it can be passed around without being
+/// copied to a bunch of places on the stack, and zeroized when dropped.
+pub type SharedSecret = Zeroizing<Box<[u8; SHARED_SECRET_BYTES]>>;
+
+/// Copies `secret` into a new [`SharedSecret`].
+pub fn shared_secret_from(secret: &[u8; SHARED_SECRET_BYTES]) -> SharedSecret {
+    let mut shared_secret = Zeroizing::new(Box::new([0u8; SHARED_SECRET_BYTES]));
+    shared_secret.copy_from_slice(secret);
+    shared_secret
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// No KEM with the given name is registered.
+    UnsupportedAlgorithm(String),
+    /// The public key had the wrong size for the KEM.
+    InvalidPublicKeyLength {
+        algorithm: &'static str,
+        actual: usize,
+        expected: usize,
+    },
+    /// The ciphertext had the wrong size for the KEM.
+    InvalidCiphertextLength {
+        algorithm: &'static str,
+        actual: usize,
+        expected: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Error::*;
+        match self {
+            UnsupportedAlgorithm(name) => write!(f, "Unsupported KEM algorithm: {name}"),
+            InvalidPublicKeyLength {
+                algorithm,
+                actual,
+                expected,
+            } => write!(
+                f,
+                "Expected a {algorithm} public key of length {expected}, got {actual} bytes"
+            ),
+            InvalidCiphertextLength {
+                algorithm,
+                actual,
+                expected,
+            } => write!(
+                f,
+                "Expected a {algorithm} ciphertext of length {expected}, got {actual} bytes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A key encapsulation mechanism that can be used in the PSK exchange.
+pub trait Kem: Send + Sync {
+    /// Name of the algorithm, including which variant. Should be the same name/format that
+    /// `liboqs` uses, since that is what is sent to the server.
+    fn algorithm_name(&self) -> &'static str;
+
+    /// Generates a new key pair. This may be slow and use a lot of stack, so it should not be
+    /// called directly from async code. Use [`KemRegistry::generate_keypairs`] instead.
+    fn generate_keypair(&self) -> Box<dyn KemKeypair>;
+
+    /// Encapsulates a new shared secret for the given public key. Returns the ciphertext to send
+    /// to the peer along with the shared secret.
+    fn encapsulate(&self, public_key: &[u8]) -> Result<(Vec<u8>, SharedSecret), Error>;
+}
+
+/// The client side of a KEM exchange. Holds the secret key until the ciphertext is received.
+pub trait KemKeypair: Send {
+    /// Name of the algorithm this key pair belongs to.
+    fn algorithm_name(&self) -> &'static str;
+
+    /// The encoded public key to send to the peer.
+    fn public_key(&self) -> Vec<u8>;
+
+    /// Decapsulates the shared secret from a ciphertext received from the peer.
+    fn decapsulate(&self, ciphertext: &[u8]) -> Result<SharedSecret, Error>;
+}
+
+/// Collection of KEMs, looked up by algorithm name. The order of registration is preserved, and
+/// is the order in which a client offers the KEMs to the server.
+pub struct KemRegistry {
+    kems: Vec<Box<dyn Kem>>,
+}
+
+impl KemRegistry {
+    /// Creates a registry without any KEMs.
+    pub fn empty() -> Self {
+        Self { kems: vec![] }
+    }
+
+    /// Adds a KEM to the registry, replacing any existing KEM with the same algorithm name.
+    pub fn register(&mut self, kem: Box<dyn Kem>) {
+        let name = kem.algorithm_name();
+        match self.kems.iter_mut().find(|k| k.algorithm_name() == name) {
+            Some(existing) => *existing = kem,
+            None => self.kems.push(kem),
+        }
+    }
+
+    /// Returns the KEM with the given algorithm name, if any.
+    pub fn get(&self, algorithm_name: &str) -> Option<&dyn Kem> {
+        self.kems
+            .iter()
+            .find(|kem| kem.algorithm_name() == algorithm_name)
+            .map(|kem| kem.as_ref())
+    }
+
+    /// Returns the names of all registered KEMs, in registration order.
+    pub fn algorithm_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.kems.iter().map(|kem| kem.algorithm_name())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kems.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.kems.len()
+    }
+
+    /// Encapsulates a shared secret using the KEM with the given algorithm name.
+    pub fn encapsulate(
+        &self,
+        algorithm_name: &str,
+        public_key: &[u8],
+    ) -> Result<(Vec<u8>, SharedSecret), Error> {
+        self.get(algorithm_name)
+            .ok_or_else(|| Error::UnsupportedAlgorithm(algorithm_name.to_owned()))?
+            .encapsulate(public_key)
+    }
+
+    /// Generates one key pair for every registered KEM, in registration order. Key generation is
+    /// performed on a separate thread with a large stack.
+    pub async fn generate_keypairs(self: &std::sync::Arc<Self>) -> Vec<Box<dyn KemKeypair>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let registry = self.clone();
+
+        std::thread::Builder::new()
+            .stack_size(KEYGEN_STACK_SIZE)
+            .spawn(move || {
+                let keypairs = registry
+                    .kems
+                    .iter()
+                    .map(|kem| kem.generate_keypair())
+                    .collect::<Vec<_>>();
+                let _ = tx.send(keypairs);
+            })
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+}
+
+impl Default for KemRegistry {
+    /// Returns a registry containing all KEMs supported by this crate.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Box::new(crate::classic_mceliece::ClassicMcEliece));
+        registry
+    }
+}
+
+impl fmt::Debug for KemRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.algorithm_names()).finish()
+    }
+}
+
+/// Performs `dst = dst ^ src`.
+pub fn xor_assign(dst: &mut [u8; SHARED_SECRET_BYTES], src: &[u8; SHARED_SECRET_BYTES]) {
+    for (dst_byte, src_byte) in dst.iter_mut().zip(src.iter()) {
+        *dst_byte ^= src_byte;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    struct DummyKem(&'static str);
+
+    impl Kem for DummyKem {
+        fn algorithm_name(&self) -> &'static str {
+            self.0
+        }
+
+        fn generate_keypair(&self) -> Box<dyn KemKeypair> {
+            Box::new(DummyKeypair(self.0))
+        }
+
+        fn encapsulate(&self, _public_key: &[u8]) -> Result<(Vec<u8>, SharedSecret), Error> {
+            Ok((vec![], shared_secret_from(&[0u8; SHARED_SECRET_BYTES])))
+        }
+    }
+
+    struct DummyKeypair(&'static str);
+
+    impl KemKeypair for DummyKeypair {
+        fn algorithm_name(&self) -> &'static str {
+            self.0
+        }
+
+        fn public_key(&self) -> Vec<u8> {
+            vec![]
+        }
+
+        fn decapsulate(&self, _ciphertext: &[u8]) -> Result<SharedSecret, Error> {
+            Ok(shared_secret_from(&[0u8; SHARED_SECRET_BYTES]))
+        }
+    }
+
+    #[test]
+    fn test_register_replaces_same_name() {
+        let mut registry = KemRegistry::empty();
+        registry.register(Box::new(DummyKem("a")));
+        registry.register(Box::new(DummyKem("b")));
+        registry.register(Box::new(DummyKem("a")));
+
+        assert_eq!(registry.algorithm_names().collect::<Vec<_>>(), ["a", "b"]);
+        assert!(registry.get("b").is_some());
+        assert!(registry.get("c").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_generate_keypairs_in_registration_order() {
+        let mut registry = KemRegistry::empty();
+        registry.register(Box::new(DummyKem("a")));
+        registry.register(Box::new(DummyKem("b")));
+
+        let keypairs = Arc::new(registry).generate_keypairs().await;
+        let names = keypairs
+            .iter()
+            .map(|keypair| keypair.algorithm_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn test_unsupported_algorithm() {
+        let registry = KemRegistry::empty();
+        assert!(matches!(
+            registry.encapsulate("c", &[]),
+            Err(Error::UnsupportedAlgorithm(name)) if name == "c"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_classic_mceliece_roundtrip() {
+        let registry = Arc::new(KemRegistry::default());
+        let keypairs = registry.generate_keypairs().await;
+        assert_eq!(keypairs.len(), 1);
+
+        let keypair = &keypairs[0];
+        let (ciphertext, server_secret) = registry
+            .encapsulate(keypair.algorithm_name(), &keypair.public_key())
+            .unwrap();
+        let client_secret = keypair.decapsulate(&ciphertext).unwrap();
+        assert_eq!(server_secret, client_secret);
+    }
+}
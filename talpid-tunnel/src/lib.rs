@@ -11,7 +11,7 @@ pub mod network_interface;
 pub mod tun_provider;
 use futures::{channel::oneshot, future::BoxFuture};
 use talpid_routing::RouteManagerHandle;
-use talpid_types::net::AllowedTunnelTraffic;
+use talpid_types::{net::AllowedTunnelTraffic, tunnel::TunnelHealth};
 use tun_provider::TunProvider;
 
 /// Arguments for creating a tunnel.
@@ -57,6 +57,8 @@ pub enum TunnelEvent {
     InterfaceUp(TunnelMetadata, AllowedTunnelTraffic),
     /// Sent when the tunnel comes up and is ready for traffic.
     Up(TunnelMetadata),
+    /// Sent when the tunnel stops or resumes receiving traffic after coming up.
+    HealthChanged(TunnelHealth),
     /// Sent when the tunnel goes down.
     Down,
 }
@@ -3,7 +3,6 @@ use nix::fcntl;
 use std::{
     io,
     net::IpAddr,
-    ops::Deref,
     os::unix::io::{AsRawFd, IntoRawFd, RawFd},
 };
 use tun::{platform, Configuration, Device};
@@ -23,10 +22,16 @@ pub enum Error {
     /// Failure to set the tunnel device as up.
     #[error(display = "Failed to set the tunnel device as up")]
     SetUp(#[cause] NetworkInterfaceError),
+
+    /// Failure to duplicate the file descriptor of a device that was created by the embedder.
+    #[error(display = "Failed to duplicate the external tunnel device")]
+    DuplicateExternalDevice(#[cause] nix::Error),
 }
 
 /// Factory of tunnel devices on Unix systems.
-pub struct UnixTunProvider;
+pub struct UnixTunProvider {
+    external_device: Option<ExternalTunDevice>,
+}
 
 impl Default for UnixTunProvider {
     fn default() -> Self {
@@ -36,10 +41,34 @@ impl Default for UnixTunProvider {
 
 impl UnixTunProvider {
     pub fn new() -> Self {
-        UnixTunProvider
+        UnixTunProvider {
+            external_device: None,
+        }
+    }
+
+    /// Creates a provider that hands out a device created by the embedder, for environments where
+    /// the daemon may not create tunnel devices itself. The provider takes ownership of `fd`. The
+    /// embedder is responsible for assigning the tunnel addresses and for bringing the device up.
+    pub fn with_external_device(fd: RawFd, interface_name: String) -> Self {
+        UnixTunProvider {
+            external_device: Some(ExternalTunDevice { fd, interface_name }),
+        }
+    }
+
+    /// Returns whether the device is created by the embedder rather than by this provider.
+    pub fn has_external_device(&self) -> bool {
+        self.external_device.is_some()
     }
 
     pub fn get_tun(&mut self, config: TunConfig) -> Result<UnixTun, Error> {
+        if let Some(device) = &self.external_device {
+            let fd = nix::unistd::dup(device.fd).map_err(Error::DuplicateExternalDevice)?;
+            return Ok(UnixTun(TunDevice::External(ExternalTunDevice {
+                fd,
+                interface_name: device.interface_name.clone(),
+            })));
+        }
+
         let mut tunnel_device = TunnelDevice::new().map_err(Error::CreateTunnelDevice)?;
 
         for ip in config.addresses.iter() {
@@ -50,27 +79,51 @@ impl UnixTunProvider {
 
         tunnel_device.set_up(true).map_err(Error::SetUp)?;
 
-        Ok(UnixTun(tunnel_device))
+        Ok(UnixTun(TunDevice::Created(tunnel_device)))
     }
 }
 
 /// Generic tunnel device.
 ///
 /// Contains the file descriptor representing the device.
-pub struct UnixTun(TunnelDevice);
+pub struct UnixTun(TunDevice);
+
+enum TunDevice {
+    Created(TunnelDevice),
+    External(ExternalTunDevice),
+}
 
 impl UnixTun {
     /// Retrieve the tunnel interface name.
     pub fn interface_name(&self) -> &str {
-        self.get_name()
+        match &self.0 {
+            TunDevice::Created(device) => device.get_name(),
+            TunDevice::External(device) => &device.interface_name,
+        }
+    }
+}
+
+impl AsRawFd for UnixTun {
+    fn as_raw_fd(&self) -> RawFd {
+        match &self.0 {
+            TunDevice::Created(device) => device.as_raw_fd(),
+            TunDevice::External(device) => device.fd,
+        }
     }
 }
 
-impl Deref for UnixTun {
-    type Target = TunnelDevice;
+/// A tunnel device that was opened and configured outside of talpid. The file descriptor is
+/// closed when this is dropped.
+struct ExternalTunDevice {
+    fd: RawFd,
+    interface_name: String,
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl Drop for ExternalTunDevice {
+    fn drop(&mut self) {
+        if let Err(error) = nix::unistd::close(self.fd) {
+            log::error!("Failed to close external tunnel device: {}", error);
+        }
     }
 }
 
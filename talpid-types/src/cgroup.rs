@@ -1,6 +1,34 @@
-use std::{ffi::OsStr, fs, os::unix::ffi::OsStrExt, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{ffi::OsStr, fmt, fs, os::unix::ffi::OsStrExt, path::PathBuf};
 
 pub const SPLIT_TUNNEL_CGROUP_NAME: &str = "mullvad-exclusions";
+/// Cgroup of the processes that use the tunnel when [`SplitTunnelMode::Include`] is used.
+pub const SPLIT_TUNNEL_INCLUDE_CGROUP_NAME: &str = "mullvad-inclusions";
+/// Mark of sockets that are bound to the tunnel interface, such as those used for API requests
+/// while connected. Split tunneling never lets their traffic bypass the tunnel, whichever cgroup
+/// the process belongs to.
+pub const TUNNEL_BOUND_SOCKET_MARK: u32 = 0x6d6f6c66;
+
+/// Decides which processes split tunneling lets bypass the tunnel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitTunnelMode {
+    /// Processes in [`SPLIT_TUNNEL_CGROUP_NAME`] bypass the tunnel. All other traffic uses it.
+    #[default]
+    Exclude,
+    /// Only processes in [`SPLIT_TUNNEL_INCLUDE_CGROUP_NAME`] use the tunnel. All other traffic
+    /// bypasses it, and is not blocked even if the tunnel is down.
+    Include,
+}
+
+impl fmt::Display for SplitTunnelMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitTunnelMode::Exclude => f.write_str("exclude"),
+            SplitTunnelMode::Include => f.write_str("include"),
+        }
+    }
+}
 
 /// Find the path of the cgroup v1 net_cls controller mount if it exists
 pub fn find_net_cls_mount() -> std::io::Result<Option<PathBuf>> {
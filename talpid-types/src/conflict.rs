@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Software that is suspected of interfering with the tunnel, such as another VPN client.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct ConflictingSoftware {
+    pub kind: ConflictKind,
+    /// Name of the interface or provider that was detected.
+    pub name: String,
+}
+
+/// How a [`ConflictingSoftware`] was detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    /// A tunnel interface that does not belong to us has a default route.
+    TunnelInterface,
+    /// A third-party VPN has registered a provider with the Windows Filtering Platform.
+    WfpProvider,
+}
+
+impl fmt::Display for ConflictingSoftware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ConflictKind::TunnelInterface => {
+                write!(f, "tunnel interface \"{}\" with a default route", self.name)
+            }
+            ConflictKind::WfpProvider => write!(f, "WFP provider \"{}\"", self.name),
+        }
+    }
+}
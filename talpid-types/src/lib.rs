@@ -4,6 +4,7 @@ use std::{error::Error, fmt, fmt::Write};
 
 #[cfg(target_os = "android")]
 pub mod android;
+pub mod conflict;
 pub mod net;
 pub mod tunnel;
 
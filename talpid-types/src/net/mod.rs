@@ -10,6 +10,7 @@ use std::{
     str::FromStr,
 };
 
+pub mod obfs4;
 pub mod obfuscation;
 pub mod openvpn;
 pub mod proxy;
@@ -255,15 +256,31 @@ pub struct AllowedEndpoint {
     #[cfg(windows)]
     pub clients: Vec<PathBuf>,
     pub endpoint: Endpoint,
+    /// Host that is reachable in addition to `endpoint`, such as a proxy that is being tried
+    /// out.
+    pub extra_endpoint: Option<Endpoint>,
+    /// Encrypted DNS resolvers that may be used to look up the address of `endpoint`.
+    pub resolvers: Vec<Endpoint>,
+}
+
+impl AllowedEndpoint {
+    /// Returns `endpoint` followed by `extra_endpoint`, if there is one, and `resolvers`.
+    pub fn endpoints(&self) -> impl Iterator<Item = &Endpoint> {
+        std::iter::once(&self.endpoint)
+            .chain(&self.extra_endpoint)
+            .chain(&self.resolvers)
+    }
 }
 
 impl fmt::Display for AllowedEndpoint {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
-        #[cfg(not(windows))]
         write!(f, "{}", self.endpoint)?;
+        if let Some(endpoint) = &self.extra_endpoint {
+            write!(f, " and {}", endpoint)?;
+        }
         #[cfg(windows)]
         {
-            write!(f, "{} for", self.endpoint)?;
+            write!(f, " for")?;
             #[cfg(windows)]
             for client in &self.clients {
                 write!(
@@ -297,8 +314,120 @@ impl fmt::Display for AllowedTunnelTraffic {
     }
 }
 
+/// Traffic used to configure the addresses and routes of network interfaces. The firewall allows
+/// the enabled kinds on every interface, in all states, so that leases and routes on the physical
+/// network keep working while traffic is otherwise blocked. Only used on Linux and macOS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AllowedLinkConfigTraffic {
+    /// DHCPv4 requests broadcast by the client, and the responses to them.
+    pub dhcpv4: bool,
+    /// DHCPv6 requests sent to the DHCPv6 multicast groups, and the responses to them.
+    pub dhcpv6: bool,
+    /// DHCPv4 and DHCPv6 messages sent directly to a server rather than broadcast or multicast.
+    /// Some networks require these for renewing leases. Only servers on the local network are
+    /// allowed, and not by default.
+    pub dhcp_unicast: bool,
+    /// Router and neighbor discovery for IPv6.
+    pub ndp: bool,
+}
+
+impl Default for AllowedLinkConfigTraffic {
+    fn default() -> Self {
+        AllowedLinkConfigTraffic {
+            dhcpv4: true,
+            dhcpv6: true,
+            dhcp_unicast: false,
+            ndp: true,
+        }
+    }
+}
+
+impl fmt::Display for AllowedLinkConfigTraffic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let kinds: Vec<&str> = [
+            (self.dhcpv4, "DHCPv4"),
+            (self.dhcpv6, "DHCPv6"),
+            (self.dhcp_unicast, "unicast DHCP"),
+            (self.ndp, "NDP"),
+        ]
+        .into_iter()
+        .filter_map(|(allowed, name)| allowed.then(|| name))
+        .collect();
+        if kinds.is_empty() {
+            "none".fmt(f)
+        } else {
+            kinds.join(", ").fmt(f)
+        }
+    }
+}
+
+/// Route that traffic outside the tunnel takes to reach the internet.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct DefaultRoute {
+    /// Name of the interface that the route goes through.
+    pub interface: String,
+    /// Address of the gateway, if the route has one.
+    pub gateway: Option<IpAddr>,
+}
+
+/// Result of checking that the system DNS backend is working.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DnsBackendHealth {
+    /// Name of the mechanism used to configure DNS, e.g. "systemd-resolved".
+    pub backend: String,
+    /// Why the backend is not responding, or `None` if it is.
+    pub error: Option<String>,
+    /// Resolvers currently in effect, per interface.
+    pub resolvers: Vec<InterfaceResolvers>,
+    /// Other programs that are also managing `/etc/resolv.conf`.
+    pub conflicting_managers: Vec<String>,
+}
+
+impl DnsBackendHealth {
+    /// Returns whether the backend responded and nothing is competing with it.
+    pub fn is_healthy(&self) -> bool {
+        self.error.is_none() && self.conflicting_managers.is_empty()
+    }
+}
+
+impl fmt::Display for DnsBackendHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "DNS backend: {}", self.backend)?;
+        match &self.error {
+            Some(error) => write!(f, " (not responding: {})", error)?,
+            None => write!(f, " (responding)")?,
+        }
+        for resolvers in &self.resolvers {
+            write!(f, "\n{}", resolvers)?;
+        }
+        if !self.conflicting_managers.is_empty() {
+            write!(
+                f,
+                "\nAlso managing /etc/resolv.conf: {}",
+                self.conflicting_managers.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// DNS servers that are used for queries sent over an interface.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct InterfaceResolvers {
+    pub interface: String,
+    pub servers: Vec<IpAddr>,
+}
+
+impl fmt::Display for InterfaceResolvers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let servers: Vec<String> = self.servers.iter().map(IpAddr::to_string).collect();
+        write!(f, "{}: {}", self.interface, servers.join(", "))
+    }
+}
+
 /// IP protocol version.
-#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum IpVersion {
     #[default]
@@ -376,3 +505,81 @@ pub fn all_of_the_internet() -> Vec<ipnetwork::IpNetwork> {
         "::0/0".parse().expect("Failed to parse ipv6 network"),
     ]
 }
+
+/// Returns whether `name` could be the name of a network interface on Linux. Names that could
+/// escape a path such as `/proc/sys/net/ipv4/conf/<name>` are rejected.
+pub fn is_valid_interface_name(name: &str) -> bool {
+    // Names are limited to `IFNAMSIZ` bytes, including the terminating null byte
+    const MAX_LEN: usize = 15;
+    !name.is_empty()
+        && name.len() <= MAX_LEN
+        && name != "."
+        && name != ".."
+        && !name
+            .chars()
+            .any(|c| c == '/' || c == ':' || c == '\0' || c.is_whitespace())
+}
+
+/// Returns whether `addr` is in one of the private or link-local networks that allow-LAN permits.
+pub fn is_lan_address(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => addr.is_private() || addr.is_link_local(),
+        IpAddr::V6(addr) => {
+            let first_segment = addr.segments()[0];
+            // Link-local (fe80::/10) or unique local (fc00::/7)
+            first_segment & 0xffc0 == 0xfe80 || first_segment & 0xfe00 == 0xfc00
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lan_addresses() {
+        for addr in [
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.10",
+            "169.254.1.1",
+            "fe80::1",
+            "fd00::1",
+        ] {
+            let addr: IpAddr = addr.parse().unwrap();
+            assert!(is_lan_address(&addr), "{} is a LAN address", addr);
+        }
+        for addr in [
+            "8.8.8.8",
+            "172.32.0.1",
+            "127.0.0.1",
+            "224.0.0.1",
+            "2001:db8::1",
+            "::1",
+        ] {
+            let addr: IpAddr = addr.parse().unwrap();
+            assert!(!is_lan_address(&addr), "{} is not a LAN address", addr);
+        }
+    }
+
+    #[test]
+    fn test_interface_names() {
+        for name in ["eth0", "wlp2s0", "br-lan", "enx00e04c680001"] {
+            assert!(is_valid_interface_name(name), "{} should be valid", name);
+        }
+        let invalid = [
+            "",
+            ".",
+            "..",
+            "../../all",
+            "eth0/..",
+            "eth 0",
+            "eth0:1",
+            "a\0b",
+        ];
+        for name in invalid {
+            assert!(!is_valid_interface_name(name), "{:?} is invalid", name);
+        }
+        assert!(!is_valid_interface_name("abcdefghijklmnop"));
+    }
+}
@@ -0,0 +1,116 @@
+//! Settings for obfs4 bridges, which are reached through a pluggable transport client. Bridges
+//! are written as Tor bridge lines, without the fingerprint:
+//! `obfs4 192.0.2.1:443 cert=<base64> iat-mode=0`.
+
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::SocketAddr, str::FromStr};
+
+/// Settings for an obfs4 bridge.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Obfs4Settings {
+    pub peer: SocketAddr,
+    /// Public key and node ID of the bridge, encoded as base64.
+    pub cert: String,
+    pub iat_mode: IatMode,
+}
+
+/// Whether packet timing is obfuscated, in addition to packet lengths.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IatMode {
+    #[default]
+    Disabled,
+    Enabled,
+    Paranoid,
+}
+
+impl fmt::Display for IatMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self {
+            IatMode::Disabled => "0",
+            IatMode::Enabled => "1",
+            IatMode::Paranoid => "2",
+        };
+        f.write_str(mode)
+    }
+}
+
+#[derive(err_derive::Error, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    #[error(display = "Missing or invalid bridge address")]
+    InvalidAddress,
+
+    #[error(display = "Missing cert argument")]
+    MissingCert,
+
+    #[error(display = "Invalid iat-mode: {}", _0)]
+    InvalidIatMode(String),
+
+    #[error(display = "Unknown argument: {}", _0)]
+    UnknownArgument(String),
+}
+
+impl FromStr for Obfs4Settings {
+    type Err = ParseError;
+
+    fn from_str(bridge_line: &str) -> Result<Self, Self::Err> {
+        let mut words = bridge_line.split_whitespace().peekable();
+        if words.peek() == Some(&"obfs4") {
+            words.next();
+        }
+        let peer = words
+            .next()
+            .and_then(|address| address.parse().ok())
+            .ok_or(ParseError::InvalidAddress)?;
+
+        let mut cert = None;
+        let mut iat_mode = IatMode::default();
+        for argument in words {
+            match argument.split_once('=') {
+                Some(("cert", value)) => cert = Some(value.to_owned()),
+                Some(("iat-mode", "0")) => iat_mode = IatMode::Disabled,
+                Some(("iat-mode", "1")) => iat_mode = IatMode::Enabled,
+                Some(("iat-mode", "2")) => iat_mode = IatMode::Paranoid,
+                Some(("iat-mode", value)) => {
+                    return Err(ParseError::InvalidIatMode(value.to_owned()))
+                }
+                _ => return Err(ParseError::UnknownArgument(argument.to_owned())),
+            }
+        }
+
+        Ok(Obfs4Settings {
+            peer,
+            cert: cert.ok_or(ParseError::MissingCert)?,
+            iat_mode,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_bridge_line() {
+        let settings: Obfs4Settings = "obfs4 192.0.2.1:443 cert=c2VjcmV0+/ iat-mode=2"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            settings,
+            Obfs4Settings {
+                peer: "192.0.2.1:443".parse().unwrap(),
+                cert: "c2VjcmV0+/".to_owned(),
+                iat_mode: IatMode::Paranoid,
+            }
+        );
+
+        assert_eq!(
+            "192.0.2.1:443".parse::<Obfs4Settings>(),
+            Err(ParseError::MissingCert)
+        );
+        assert_eq!(
+            "192.0.2.1:443 cert=abc iat-mode=3".parse::<Obfs4Settings>(),
+            Err(ParseError::InvalidIatMode("3".to_owned()))
+        );
+    }
+}
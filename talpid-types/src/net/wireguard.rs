@@ -1,4 +1,4 @@
-use crate::net::{Endpoint, GenericTunnelOptions, TransportProtocol};
+use crate::net::{Endpoint, GenericTunnelOptions, IpVersion, TransportProtocol};
 use ipnetwork::IpNetwork;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
@@ -8,6 +8,7 @@ use std::{
     cmp, fmt,
     hash::{Hash, Hasher},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
 };
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
@@ -89,6 +90,29 @@ pub struct TunnelOptions {
     pub mtu: Option<u16>,
     /// Obtain a PSK using the relay config client.
     pub use_pq_safe_psk: bool,
+    /// Only route traffic of this IP version through the tunnel. If `V4`, no IPv6 address is
+    /// assigned. If `V6`, the IPv4 address is only used to reach the relay. If `None`, both IP
+    /// versions are used.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub address_family: Option<IpVersion>,
+    /// Only use the in-tunnel DNS resolver of this IP version. If `None`, the resolvers on both
+    /// the IPv4 and the IPv6 gateway are used.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub dns_ip_version: Option<IpVersion>,
+    /// Have the IPv6 in-tunnel resolver take precedence over the IPv4 one. Custom resolvers are
+    /// always used in the order they were given.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub prefer_ipv6_dns: bool,
+    /// Time to wait for the tunnel to start passing traffic before giving up on an attempt. If
+    /// `None`, the timeout grows with each attempt. This is not saved in the settings.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(skip)]
+    pub connect_timeout: Option<Duration>,
+    /// Interval between keepalive packets sent to the relay, in seconds. This is not saved in
+    /// the settings.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(skip)]
+    pub persistent_keepalive: Option<u16>,
     /// Temporary switch for wireguard-nt
     #[cfg(windows)]
     #[serde(default = "default_wgnt_setting")]
@@ -107,6 +131,11 @@ impl Default for TunnelOptions {
         Self {
             mtu: None,
             use_pq_safe_psk: false,
+            address_family: None,
+            dns_ip_version: None,
+            prefer_ipv6_dns: false,
+            connect_timeout: None,
+            persistent_keepalive: None,
             #[cfg(windows)]
             use_wireguard_nt: default_wgnt_setting(),
         }
@@ -2,20 +2,22 @@ use crate::net::TunnelEndpoint;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::fmt;
-#[cfg(target_os = "android")]
-use std::net::IpAddr;
+use std::{fmt, net::IpAddr, time::Duration};
 
 /// Event emitted from the states in `talpid_core::tunnel_state_machine` when the tunnel state
 /// machine enters a new state.
 #[derive(Clone, Debug)]
 pub enum TunnelStateTransition {
-    /// No connection is established and network is unsecured.
-    Disconnected,
-    /// Network is secured but tunnel is still connecting.
-    Connecting(TunnelEndpoint),
-    /// Tunnel is connected.
-    Connected(TunnelEndpoint),
+    /// No connection is established and network is unsecured. The reason is not set for the
+    /// initial state.
+    Disconnected(Option<DisconnectReason>),
+    /// Network is secured but tunnel is still connecting. The reason is set if the tunnel was
+    /// reconnected because of something that the state machine detected itself. The transition
+    /// is emitted again with the same endpoint whenever the attempt reaches a new phase.
+    Connecting(TunnelEndpoint, Option<ReconnectReason>, ConnectingPhase),
+    /// Tunnel is connected. The transition is emitted again whenever the health of the tunnel
+    /// changes, or when the tunnel is moved to another port on the same relay.
+    Connected(TunnelEndpoint, TunnelHealth, TunnelInterface),
     /// Disconnecting tunnel.
     Disconnecting(ActionAfterDisconnect),
     /// Tunnel is disconnected but usually secured by blocking all connections.
@@ -33,6 +35,259 @@ pub enum ActionAfterDisconnect {
     Reconnect,
 }
 
+/// How far a connection attempt has progressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectingPhase {
+    /// The tunnel is being started and its device is being opened.
+    #[default]
+    OpeningDevice,
+    /// The tunnel device is up, but keys are being exchanged with the relay before the tunnel
+    /// may carry other traffic.
+    Handshaking,
+    /// The tunnel device is up and routes are being added. For WireGuard, this includes checking
+    /// that traffic can pass through the relay.
+    VerifyingConnectivity,
+}
+
+impl fmt::Display for ConnectingPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            ConnectingPhase::OpeningDevice => "opening tunnel device",
+            ConnectingPhase::Handshaking => "exchanging keys",
+            ConnectingPhase::VerifyingConnectivity => "verifying connectivity",
+        };
+        f.write_str(description)
+    }
+}
+
+/// The network interface that a connected tunnel is running on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TunnelInterface {
+    /// Name of the interface.
+    pub name: String,
+    /// Addresses assigned to the interface.
+    pub ips: Vec<IpAddr>,
+}
+
+/// How well a connected tunnel is working, according to the tunnel's connectivity monitor. The
+/// monitor pings the relay through the tunnel to measure the latency and packet loss.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(target_os = "android", derive(IntoJava))]
+#[cfg_attr(target_os = "android", jnix(package = "net.mullvad.talpid.tunnel"))]
+pub enum TunnelHealth {
+    /// Traffic is being received from the relay.
+    #[default]
+    Healthy,
+    /// Nothing has been received from the relay for a while, not even replies to pings. The
+    /// tunnel is reconnected if this goes on for too long.
+    Unstable,
+    /// Replies to pings take a long time to arrive.
+    HighLatency,
+    /// Many pings are not answered, although other traffic is received.
+    PacketLoss,
+}
+
+impl TunnelHealth {
+    pub fn is_healthy(&self) -> bool {
+        *self == TunnelHealth::Healthy
+    }
+}
+
+impl fmt::Display for TunnelHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            TunnelHealth::Healthy => "healthy",
+            TunnelHealth::Unstable => "connection unstable",
+            TunnelHealth::HighLatency => "high latency",
+            TunnelHealth::PacketLoss => "packet loss",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Why a connected or connecting tunnel is being reconnected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconnectReason {
+    /// The user asked for a reconnect.
+    UserRequested,
+    /// A setting that affects the tunnel was changed.
+    SettingsChanged,
+    /// The WireGuard key was rotated, and the relay must be given the new key.
+    KeyRotated,
+    /// The user logged in to another account, or the device was revoked.
+    AccountChanged,
+    /// Time was added to an account that had run out of it.
+    AccountCredited,
+    /// Authentication failed earlier, and is being retried periodically.
+    AuthFailedRetry,
+    /// The relay was removed from the relay list or marked as inactive.
+    RelayMaintenance,
+    /// The tunnel stopped working, or the device came back online after losing connectivity.
+    ConnectivityLost,
+    /// The tunnel moved to a new port because port hopping is enabled.
+    PortHop,
+    /// The connection has had high latency, packet loss or been unstable for too long.
+    Unhealthy,
+}
+
+impl fmt::Display for ReconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            ReconnectReason::UserRequested => "requested by the user",
+            ReconnectReason::SettingsChanged => "settings changed",
+            ReconnectReason::KeyRotated => "WireGuard key rotated",
+            ReconnectReason::AccountChanged => "account or device changed",
+            ReconnectReason::AccountCredited => "time was added to the account",
+            ReconnectReason::AuthFailedRetry => "retrying after failed authentication",
+            ReconnectReason::RelayMaintenance => "relay is unavailable",
+            ReconnectReason::ConnectivityLost => "connectivity was lost",
+            ReconnectReason::PortHop => "hopping to a new port",
+            ReconnectReason::Unhealthy => "the connection was unhealthy",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Why the tunnel was disconnected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(target_os = "android", derive(IntoJava))]
+#[cfg_attr(target_os = "android", jnix(package = "net.mullvad.talpid.tunnel"))]
+pub enum DisconnectReason {
+    /// The user asked for the tunnel to be disconnected.
+    UserRequested,
+    /// The user logged out of the account.
+    LoggedOut,
+    /// The tunnel was connected by the auto-connect policy, which no longer applies to the
+    /// current network.
+    AutoConnectPolicy,
+    /// The daemon is shutting down.
+    Shutdown,
+    /// The tunnel was connected by the auto-connect policy, and failed without being able to
+    /// block traffic.
+    TunnelError,
+    /// The tunnel was connected by the auto-connect policy, and the account could not be used to
+    /// connect, such as when it is out of time.
+    AuthFailed,
+    /// The tunnel was connected by the auto-connect policy, and the device was revoked.
+    DeviceRevoked,
+    /// The tunnel was connected by the auto-connect policy, and the device went offline. The
+    /// policy connects the tunnel again once the device is back online.
+    Offline,
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            DisconnectReason::UserRequested => "requested by the user",
+            DisconnectReason::LoggedOut => "logged out",
+            DisconnectReason::AutoConnectPolicy => "auto-connect policy",
+            DisconnectReason::Shutdown => "the daemon is shutting down",
+            DisconnectReason::TunnelError => "the tunnel failed",
+            DisconnectReason::AuthFailed => "authentication failed",
+            DisconnectReason::DeviceRevoked => "the device was revoked",
+            DisconnectReason::Offline => "the device is offline",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Timing of the connection attempts made by the tunnel state machine. The defaults suit most
+/// networks, but may need to be relaxed on networks with very high latency.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectionTimeouts {
+    /// How long a connection attempt may take before it is aborted, in seconds, not counting the
+    /// delay before the attempt. Zero disables the timeout.
+    pub connecting_timeout_secs: u32,
+    /// Upper bound of the delay before the first retry, in seconds. It is doubled for every
+    /// failed attempt after that, up to `max_retry_delay_secs`.
+    pub initial_retry_delay_secs: u32,
+    /// Upper bound of the delay between two connection attempts, in seconds.
+    pub max_retry_delay_secs: u32,
+    /// How long connectivity must stay lost or restored before it is acted on, in milliseconds.
+    /// A burst of changes, such as when roaming between access points, is handled once it has
+    /// settled. Zero handles every change at once.
+    pub offline_debounce_ms: u32,
+}
+
+impl ConnectionTimeouts {
+    /// Shortest non-zero connecting timeout, in seconds. An attempt cannot succeed before the
+    /// first WireGuard connectivity check has had time to complete.
+    pub const MIN_CONNECTING_TIMEOUT_SECS: u32 = 4;
+
+    /// Longest offline debounce delay, in milliseconds. Connectivity that is lost for longer
+    /// should not go unnoticed.
+    pub const MAX_OFFLINE_DEBOUNCE_MS: u32 = 10_000;
+
+    /// Returns a description of why the timeouts cannot be used, if they cannot.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.connecting_timeout_secs != 0
+            && self.connecting_timeout_secs < Self::MIN_CONNECTING_TIMEOUT_SECS
+        {
+            return Err("the connecting timeout must be zero or at least 4 seconds");
+        }
+        // A zero delay would retry failed attempts in a tight loop
+        if self.initial_retry_delay_secs == 0 {
+            return Err("the initial retry delay must be at least one second");
+        }
+        if self.max_retry_delay_secs < self.initial_retry_delay_secs {
+            return Err("the maximum retry delay is shorter than the initial retry delay");
+        }
+        if self.offline_debounce_ms > Self::MAX_OFFLINE_DEBOUNCE_MS {
+            return Err("the offline debounce delay must be at most 10 seconds");
+        }
+        Ok(())
+    }
+
+    pub fn connecting_timeout(&self) -> Option<Duration> {
+        match self.connecting_timeout_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(u64::from(secs))),
+        }
+    }
+
+    pub fn initial_retry_delay(&self) -> Duration {
+        Duration::from_secs(u64::from(self.initial_retry_delay_secs))
+    }
+
+    pub fn max_retry_delay(&self) -> Duration {
+        Duration::from_secs(u64::from(self.max_retry_delay_secs))
+    }
+
+    pub fn offline_debounce(&self) -> Duration {
+        Duration::from_millis(u64::from(self.offline_debounce_ms))
+    }
+}
+
+impl Default for ConnectionTimeouts {
+    fn default() -> Self {
+        ConnectionTimeouts {
+            connecting_timeout_secs: 60,
+            initial_retry_delay_secs: 2,
+            max_retry_delay_secs: 60,
+            offline_debounce_ms: 1000,
+        }
+    }
+}
+
+impl fmt::Display for ConnectionTimeouts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.connecting_timeout_secs {
+            0 => write!(f, "no connecting timeout")?,
+            secs => write!(f, "connecting timeout {}s", secs)?,
+        }
+        write!(
+            f,
+            ", retry delay {}s up to {}s, offline debounce {}ms",
+            self.initial_retry_delay_secs, self.max_retry_delay_secs, self.offline_debounce_ms
+        )
+    }
+}
+
 /// Represents the tunnel state machine entering an error state during a [`TunnelStateTransition`].
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -134,6 +389,10 @@ pub enum ParameterGenerationError {
     /// Failure to resolve the hostname of a custom tunnel configuration
     #[error(display = "Can't resolve hostname for custom tunnel host")]
     CustomTunnelHostResultionError,
+    /// No relay matching the constraints supports the features required by the settings,
+    /// such as quantum-resistant tunnels or obfuscation
+    #[error(display = "No relay supports the features required by the settings")]
+    MissingRelayCapabilities,
 }
 
 /// Application that prevents setting the firewall policy.
@@ -1,9 +1,12 @@
 use std::{
     borrow::Cow,
     ffi::CString,
-    net::{Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+use talpid_types::net::{
+    obfuscation::ObfuscatorConfig, wireguard, GenericTunnelOptions, IpVersion,
 };
-use talpid_types::net::{obfuscation::ObfuscatorConfig, wireguard, GenericTunnelOptions};
 
 /// Config required to set up a single WireGuard tunnel
 #[derive(Clone)]
@@ -18,6 +21,11 @@ pub struct Config {
     pub ipv6_gateway: Option<Ipv6Addr>,
     /// Maximum transmission unit for the tunnel
     pub mtu: u16,
+    /// Interval between keepalive packets sent to each peer, in seconds
+    pub persistent_keepalive: Option<u16>,
+    /// Timeout for each attempt at establishing a connection, instead of the default that grows
+    /// with each attempt
+    pub connect_timeout: Option<Duration>,
     /// Firewall mark
     #[cfg(target_os = "linux")]
     pub fwmark: Option<u32>,
@@ -53,6 +61,10 @@ pub enum Error {
     /// Parameters don't contain any peers
     #[error(display = "No peers supplied")]
     NoPeersSuppliedError,
+
+    /// An IPv6-only tunnel was requested, but there is no IPv6 address or gateway
+    #[error(display = "No IPv6 tunnel IP or gateway for an IPv6-only tunnel")]
+    NoIpv6TunnelError,
 }
 
 impl Config {
@@ -86,12 +98,35 @@ impl Config {
             return Err(Error::NoPeersSuppliedError);
         }
         let mtu = wg_options.mtu.unwrap_or(DEFAULT_MTU);
+        let enable_ipv6 = match wg_options.address_family {
+            None => generic_options.enable_ipv6,
+            Some(IpVersion::V4) => false,
+            Some(IpVersion::V6) => true,
+        };
+        let ipv6_only = wg_options.address_family == Some(IpVersion::V6);
+        if ipv6_only
+            && (connection_config.ipv6_gateway.is_none()
+                || !tunnel.addresses.iter().any(|ip| ip.is_ipv6()))
+        {
+            return Err(Error::NoIpv6TunnelError);
+        }
+        // In an IPv6-only tunnel, the IPv4 address is only used to reach the services on the
+        // relay, such as the tunnel monitor and the PSK exchange. Other IPv4 traffic is not
+        // routed through the tunnel.
+        let gateway_net_v4 = ipnetwork::IpNetwork::from(IpAddr::V4(connection_config.ipv4_gateway));
         for peer in &mut peers {
             peer.allowed_ips = peer
                 .allowed_ips
                 .iter()
                 .cloned()
-                .filter(|ip| ip.is_ipv4() || generic_options.enable_ipv6)
+                .filter(|ip| ip.is_ipv4() || enable_ipv6)
+                .map(|ip| {
+                    if ipv6_only && ip.is_ipv4() && ip.prefix() == 0 {
+                        gateway_net_v4
+                    } else {
+                        ip
+                    }
+                })
                 .collect();
             if peer.allowed_ips.is_empty() {
                 return Err(Error::InvalidPeerIpError);
@@ -101,11 +136,9 @@ impl Config {
         if tunnel.addresses.is_empty() {
             return Err(Error::InvalidTunnelIpError);
         }
-        tunnel
-            .addresses
-            .retain(|ip| ip.is_ipv4() || generic_options.enable_ipv6);
+        tunnel.addresses.retain(|ip| ip.is_ipv4() || enable_ipv6);
 
-        let ipv6_gateway = if generic_options.enable_ipv6 {
+        let ipv6_gateway = if enable_ipv6 {
             connection_config.ipv6_gateway
         } else {
             None
@@ -117,10 +150,12 @@ impl Config {
             ipv4_gateway: connection_config.ipv4_gateway,
             ipv6_gateway,
             mtu,
+            persistent_keepalive: wg_options.persistent_keepalive,
+            connect_timeout: wg_options.connect_timeout,
             #[cfg(target_os = "linux")]
             fwmark: connection_config.fwmark,
             #[cfg(target_os = "linux")]
-            enable_ipv6: generic_options.enable_ipv6,
+            enable_ipv6,
             #[cfg(target_os = "windows")]
             use_wireguard_nt: wg_options.use_wireguard_nt,
             obfuscator_config,
@@ -151,6 +186,12 @@ impl Config {
             if let Some(ref psk) = peer.psk {
                 wg_conf.add("preshared_key", psk.as_bytes().as_ref());
             }
+            if let Some(keepalive) = self.persistent_keepalive {
+                wg_conf.add(
+                    "persistent_keepalive_interval",
+                    keepalive.to_string().as_str(),
+                );
+            }
             for addr in &peer.allowed_ips {
                 wg_conf.add("allowed_ip", addr.to_string().as_str());
             }
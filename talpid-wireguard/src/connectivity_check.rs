@@ -4,10 +4,12 @@ use crate::{
 };
 use std::{
     cmp,
+    collections::VecDeque,
     net::Ipv4Addr,
     sync::{mpsc, Mutex, Weak},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
+use talpid_types::tunnel::TunnelHealth;
 
 use super::{Tunnel, TunnelError};
 
@@ -26,6 +28,9 @@ const TRAFFIC_TIMEOUT: Duration = Duration::from_secs(120);
 /// Timeout for waiting on receiving traffic after sending the first ICMP packet.  Once this
 /// timeout is reached, it is assumed that the connection is lost.
 const PING_TIMEOUT: Duration = Duration::from_secs(15);
+/// Time without any traffic after sending the first ICMP packet, after which the connection is
+/// reported as unstable. It is not considered lost until `PING_TIMEOUT` is reached.
+const UNSTABLE_TIMEOUT: Duration = Duration::from_secs(5);
 /// Timeout for receiving traffic when establishing a connection.
 const ESTABLISH_TIMEOUT: Duration = Duration::from_secs(4);
 /// `ESTABLISH_TIMEOUT` is multiplied by this after each failed connection attempt.
@@ -35,6 +40,25 @@ const MAX_ESTABLISH_TIMEOUT: Duration = PING_TIMEOUT;
 /// Number of seconds to wait between sending ICMP packets
 const SECONDS_PER_PING: Duration = Duration::from_secs(3);
 
+/// Time between the ICMP packets sent to measure latency and packet loss once connected.
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+/// Time after which an unanswered probe is counted as lost.
+const PROBE_LOST_AFTER: Duration = Duration::from_secs(2);
+/// Number of recent probe results that latency and packet loss are computed from.
+const PROBE_WINDOW_SIZE: usize = 12;
+/// Number of probe results needed before packet loss is reported.
+const MIN_PROBE_SAMPLES: usize = 6;
+/// Share of lost probes at which the connection is reported as losing packets.
+const PACKET_LOSS_THRESHOLD: f32 = 0.25;
+/// Average round-trip time at which the connection is reported as having high latency.
+const HIGH_LATENCY_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Age after which WireGuard no longer uses the keys from a handshake.
+const REJECT_AFTER_TIME: Duration = Duration::from_secs(180);
+/// Time for which WireGuard keeps trying to complete a handshake while there is traffic to send.
+/// If the handshake is still not completed after this, the peer is considered dead.
+const REKEY_ATTEMPT_TIME: Duration = Duration::from_secs(90);
+
 /// Connectivity monitor errors
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -69,12 +93,23 @@ pub enum Error {
 ///
 /// Once a connection established, a connection is only considered broken once the connectivity
 /// monitor has started pinging and no traffic has been received for a duration of `PING_TIMEOUT`.
+/// Before that, it is reported as unstable once no traffic has been received for a duration of
+/// `UNSTABLE_TIMEOUT` after pinging started.
+///
+/// Once connected, a ping is also sent every `PROBE_INTERVAL` to measure latency and packet loss,
+/// and the connection is reported as degraded when either passes its threshold. The connection is
+/// considered broken if traffic is sent but the peer has not completed a handshake within
+/// `REKEY_ATTEMPT_TIME` after the keys from the previous one expired.
 pub struct ConnectivityMonitor {
     tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
     conn_state: ConnState,
+    health: TunnelHealth,
     initial_ping_timestamp: Option<Instant>,
     num_pings_sent: u32,
     pinger: Box<dyn Pinger>,
+    probes: ProbeWindow,
+    last_probe_timestamp: Option<Instant>,
+    handshake_pending_since: Option<Instant>,
     close_receiver: mpsc::Receiver<()>,
 }
 
@@ -97,24 +132,36 @@ impl ConnectivityMonitor {
         Ok(Self {
             tunnel_handle,
             conn_state: ConnState::new(now, Default::default()),
+            health: TunnelHealth::Healthy,
             initial_ping_timestamp: None,
             num_pings_sent: 0,
             pinger,
+            probes: ProbeWindow::default(),
+            last_probe_timestamp: None,
+            handshake_pending_since: None,
             close_receiver,
         })
     }
 
     // checks if the tunnel has ever worked. Intended to check if a connection to a tunnel is
-    // successfull at the start of a connection.
-    pub(super) fn establish_connectivity(&mut self, retry_attempt: u32) -> Result<bool, Error> {
+    // successfull at the start of a connection. If `timeout` is given, it is used for every
+    // attempt instead of a timeout that grows with `retry_attempt`.
+    pub(super) fn establish_connectivity(
+        &mut self,
+        retry_attempt: u32,
+        timeout: Option<Duration>,
+    ) -> Result<bool, Error> {
         // Send initial ping to prod WireGuard into connecting.
         self.pinger.send_icmp().map_err(Error::PingError)?;
-        self.establish_connectivity_inner(
-            retry_attempt,
-            ESTABLISH_TIMEOUT,
-            ESTABLISH_TIMEOUT_MULTIPLIER,
-            MAX_ESTABLISH_TIMEOUT,
-        )
+        match timeout {
+            Some(timeout) => self.establish_connectivity_inner(retry_attempt, timeout, 1, timeout),
+            None => self.establish_connectivity_inner(
+                retry_attempt,
+                ESTABLISH_TIMEOUT,
+                ESTABLISH_TIMEOUT_MULTIPLIER,
+                MAX_ESTABLISH_TIMEOUT,
+            ),
+        }
     }
 
     fn establish_connectivity_inner(
@@ -145,8 +192,10 @@ impl ConnectivityMonitor {
         Ok(false)
     }
 
-    pub(super) fn run(&mut self) -> Result<(), Error> {
-        self.wait_loop(REGULAR_LOOP_SLEEP)
+    /// Checks the connectivity of an established tunnel until it is lost or the monitor is shut
+    /// down. `on_health_change` is called whenever the connection degrades or recovers.
+    pub(super) fn run(&mut self, on_health_change: impl FnMut(TunnelHealth)) -> Result<(), Error> {
+        self.wait_loop(REGULAR_LOOP_SLEEP, on_health_change)
     }
 
     /// Returns true if monitor should be shut down
@@ -157,7 +206,11 @@ impl ConnectivityMonitor {
         }
     }
 
-    fn wait_loop(&mut self, iter_delay: Duration) -> Result<(), Error> {
+    fn wait_loop(
+        &mut self,
+        iter_delay: Duration,
+        mut on_health_change: impl FnMut(TunnelHealth),
+    ) -> Result<(), Error> {
         let mut last_iteration = Instant::now();
         while !self.should_shut_down(iter_delay) {
             let mut current_iteration = Instant::now();
@@ -166,6 +219,10 @@ impl ConnectivityMonitor {
                 if !self.check_connectivity(Instant::now())? {
                     return Ok(());
                 }
+                self.probe(Instant::now())?;
+                if let Some(health) = self.update_health() {
+                    on_health_change(health);
+                }
 
                 let end = Instant::now();
                 if end - current_iteration > Duration::from_secs(1) {
@@ -175,6 +232,8 @@ impl ConnectivityMonitor {
                 // Loop was suspended for too long, so it's safer to assume that the host still has
                 // connectivity.
                 self.reset_pinger();
+                self.probes.clear();
+                self.handshake_pending_since = None;
                 self.conn_state.reset_after_suspension(current_iteration);
             }
             last_iteration = current_iteration;
@@ -184,7 +243,54 @@ impl ConnectivityMonitor {
 
     /// Returns true if connection is established
     fn check_connectivity(&mut self, now: Instant) -> Result<bool, Error> {
-        self.check_connectivity_interval(now, PING_TIMEOUT)
+        if !self.check_connectivity_interval(now, PING_TIMEOUT)? {
+            return Ok(false);
+        }
+        Ok(!self.peer_is_dead(now))
+    }
+
+    /// Returns true if traffic has been sent for `REKEY_ATTEMPT_TIME` without the peer completing
+    /// a handshake, after the keys from the previous handshake expired.
+    fn peer_is_dead(&mut self, now: Instant) -> bool {
+        let expired = self
+            .conn_state
+            .stats()
+            .values()
+            .any(|stats| handshake_expired(stats.last_handshake, SystemTime::now()));
+        if !expired {
+            self.handshake_pending_since = None;
+            return false;
+        }
+        // WireGuard only initiates a handshake once there is traffic to send.
+        if self.handshake_pending_since.is_none() && self.conn_state.tx_timestamp() == Some(now) {
+            self.handshake_pending_since = Some(now);
+        }
+        self.handshake_pending_since
+            .map(|since| now.saturating_duration_since(since) >= REKEY_ATTEMPT_TIME)
+            .unwrap_or(false)
+    }
+
+    /// Records the results of earlier probes and sends a new one every `PROBE_INTERVAL`.
+    fn probe(&mut self, now: Instant) -> Result<(), Error> {
+        if !self.pinger.measures_round_trip_time() {
+            return Ok(());
+        }
+        let results = self
+            .pinger
+            .take_round_trip_times(PROBE_LOST_AFTER)
+            .map_err(Error::PingError)?;
+        for result in results {
+            self.probes.push(result);
+        }
+        let probe_due = self
+            .last_probe_timestamp
+            .map(|last_probe| now.saturating_duration_since(last_probe) >= PROBE_INTERVAL)
+            .unwrap_or(true);
+        if probe_due {
+            self.pinger.send_icmp().map_err(Error::PingError)?;
+            self.last_probe_timestamp = Some(now);
+        }
+        Ok(())
     }
 
     /// Returns true if connection is established
@@ -209,6 +315,38 @@ impl ConnectivityMonitor {
         }
     }
 
+    /// Returns the new health of the connection if it changed since the last call.
+    fn update_health(&mut self) -> Option<TunnelHealth> {
+        let packet_loss = self.probes.packet_loss();
+        let latency = self.probes.latency();
+        let health = if self.ping_timed_out(UNSTABLE_TIMEOUT) {
+            TunnelHealth::Unstable
+        } else if packet_loss
+            .map(|loss| loss >= PACKET_LOSS_THRESHOLD)
+            .unwrap_or(false)
+        {
+            TunnelHealth::PacketLoss
+        } else if latency
+            .map(|rtt| rtt >= HIGH_LATENCY_THRESHOLD)
+            .unwrap_or(false)
+        {
+            TunnelHealth::HighLatency
+        } else {
+            TunnelHealth::Healthy
+        };
+        if health == self.health {
+            return None;
+        }
+        log::debug!(
+            "Tunnel health changed to {}. Latency: {:?}, packet loss: {:?}",
+            health,
+            latency,
+            packet_loss
+        );
+        self.health = health;
+        Some(health)
+    }
+
     /// If None is returned, then the underlying tunnel has already been closed and all subsequent
     /// calls will also return None.
     fn get_stats(&self) -> Option<Result<StatsMap, Error>> {
@@ -255,6 +393,58 @@ impl ConnectivityMonitor {
     }
 }
 
+/// Returns true if the keys from a handshake completed at `last_handshake` have expired.
+fn handshake_expired(last_handshake: Option<SystemTime>, now: SystemTime) -> bool {
+    match last_handshake {
+        Some(last_handshake) => now
+            .duration_since(last_handshake)
+            .map(|age| age >= REJECT_AFTER_TIME)
+            .unwrap_or(false),
+        None => true,
+    }
+}
+
+/// The results of the most recent probes. `None` is a lost probe.
+#[derive(Default)]
+struct ProbeWindow {
+    results: VecDeque<Option<Duration>>,
+}
+
+impl ProbeWindow {
+    fn push(&mut self, result: Option<Duration>) {
+        if self.results.len() == PROBE_WINDOW_SIZE {
+            self.results.pop_front();
+        }
+        self.results.push_back(result);
+    }
+
+    fn clear(&mut self) {
+        self.results.clear();
+    }
+
+    /// Returns the share of probes that were lost, or `None` if there are too few results.
+    fn packet_loss(&self) -> Option<f32> {
+        if self.results.len() < MIN_PROBE_SAMPLES {
+            return None;
+        }
+        let lost = self
+            .results
+            .iter()
+            .filter(|result| result.is_none())
+            .count();
+        Some(lost as f32 / self.results.len() as f32)
+    }
+
+    /// Returns the average round-trip time of the probes that were answered.
+    fn latency(&self) -> Option<Duration> {
+        let round_trip_times: Vec<Duration> = self.results.iter().flatten().copied().collect();
+        if round_trip_times.is_empty() {
+            return None;
+        }
+        Some(round_trip_times.iter().sum::<Duration>() / round_trip_times.len() as u32)
+    }
+}
+
 enum ConnState {
     Connecting {
         start: Instant,
@@ -384,6 +574,20 @@ impl ConnState {
     pub fn connected(&self) -> bool {
         matches!(self, ConnState::Connected { .. })
     }
+
+    pub fn stats(&self) -> &StatsMap {
+        match self {
+            ConnState::Connecting { stats, .. } | ConnState::Connected { stats, .. } => stats,
+        }
+    }
+
+    /// Returns the last time outgoing traffic was observed
+    pub fn tx_timestamp(&self) -> Option<Instant> {
+        match self {
+            ConnState::Connecting { tx_timestamp, .. } => *tx_timestamp,
+            ConnState::Connected { tx_timestamp, .. } => Some(*tx_timestamp),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -402,7 +606,7 @@ mod test {
             atomic::{AtomicBool, Ordering},
             Arc, Mutex,
         },
-        time::{Duration, Instant},
+        time::{Duration, Instant, SystemTime},
     };
 
     /// Test if a newly created ConnState won't have timed out or consider itself connected
@@ -440,6 +644,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
         conn_state.update(Instant::now(), stats);
@@ -465,6 +670,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
         conn_state.update(connect_time, stats);
@@ -489,6 +695,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
         conn_state.update(start, stats);
@@ -500,6 +707,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 1,
+                last_handshake: None,
             },
         );
         conn_state.update(update_time, stats);
@@ -543,6 +751,7 @@ mod test {
                 stats::Stats {
                     tx_bytes: 0,
                     rx_bytes: 0,
+                    last_handshake: None,
                 },
             );
             let peers = Mutex::new(map);
@@ -567,6 +776,7 @@ mod test {
                         stats::Stats {
                             tx_bytes: 0,
                             rx_bytes: 0,
+                            last_handshake: None,
                         },
                     );
                     Ok(map)
@@ -617,9 +827,13 @@ mod test {
     ) -> ConnectivityMonitor {
         ConnectivityMonitor {
             conn_state: ConnState::new(now, Default::default()),
+            health: TunnelHealth::Healthy,
             initial_ping_timestamp: None,
             num_pings_sent: 0,
             pinger,
+            probes: ProbeWindow::default(),
+            last_probe_timestamp: None,
+            handshake_pending_since: None,
             close_receiver,
             tunnel_handle,
         }
@@ -633,6 +847,7 @@ mod test {
             stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         );
         ConnState::Connected {
@@ -660,6 +875,105 @@ mod test {
         assert!(!monitor.check_connectivity(now).unwrap())
     }
 
+    #[test]
+    /// Verify that the connection is reported as unstable once pings have gone unanswered for
+    /// `UNSTABLE_TIMEOUT`, and as healthy again once traffic is received.
+    fn test_health_changes() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let pinger = MockPinger::default();
+        let start = Instant::now() - (UNSTABLE_TIMEOUT + Duration::from_secs(1));
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+
+        // Mock the state - connectivity has been established
+        monitor.conn_state = connected_state(start);
+        // A ping was sent because no traffic was received
+        monitor.maybe_send_ping(start).unwrap();
+        assert_eq!(monitor.update_health(), Some(TunnelHealth::Unstable));
+        assert_eq!(monitor.update_health(), None);
+
+        monitor.reset_pinger();
+        assert_eq!(monitor.update_health(), Some(TunnelHealth::Healthy));
+    }
+
+    #[test]
+    /// Verify that packet loss and latency are only reported once there are enough probe results,
+    /// and that packet loss takes precedence over high latency.
+    fn test_probe_health() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let pinger = MockPinger::default();
+        let mut monitor = mock_monitor(Instant::now(), Box::new(pinger), tunnel, rx);
+
+        monitor.probes.push(None);
+        monitor.probes.push(Some(HIGH_LATENCY_THRESHOLD));
+        assert_eq!(monitor.probes.packet_loss(), None);
+        assert_eq!(monitor.update_health(), Some(TunnelHealth::HighLatency));
+
+        for _ in 0..MIN_PROBE_SAMPLES {
+            monitor.probes.push(Some(Duration::from_millis(50)));
+        }
+        assert_eq!(monitor.update_health(), Some(TunnelHealth::Healthy));
+
+        for _ in 0..(PROBE_WINDOW_SIZE / 2) {
+            monitor.probes.push(None);
+        }
+        assert_eq!(monitor.probes.packet_loss(), Some(0.5));
+        assert_eq!(monitor.update_health(), Some(TunnelHealth::PacketLoss));
+
+        monitor.probes.clear();
+        assert_eq!(monitor.update_health(), Some(TunnelHealth::Healthy));
+    }
+
+    #[test]
+    /// Verify that the peer is considered dead once traffic has been sent for
+    /// `REKEY_ATTEMPT_TIME` without a handshake, and alive again once a handshake completes.
+    fn test_dead_peer() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let pinger = MockPinger::default();
+        let start = Instant::now();
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+
+        // No handshake, and traffic was sent at `start`
+        monitor.conn_state = connected_state(start);
+        assert!(!monitor.peer_is_dead(start));
+        assert!(!monitor.peer_is_dead(start + REKEY_ATTEMPT_TIME / 2));
+        assert!(monitor.peer_is_dead(start + REKEY_ATTEMPT_TIME));
+
+        let mut stats = stats::StatsMap::new();
+        stats.insert(
+            [0u8; 32],
+            stats::Stats {
+                tx_bytes: 0,
+                rx_bytes: 0,
+                last_handshake: Some(SystemTime::now()),
+            },
+        );
+        monitor.conn_state = ConnState::Connected {
+            rx_timestamp: start,
+            tx_timestamp: start,
+            stats,
+        };
+        assert!(!monitor.peer_is_dead(start + REKEY_ATTEMPT_TIME));
+        assert_eq!(monitor.handshake_pending_since, None);
+    }
+
+    #[test]
+    /// Verify that the handshake expiry timer is not started while no traffic is sent.
+    fn test_dead_peer_idle() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let pinger = MockPinger::default();
+        let start = Instant::now();
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+
+        monitor.conn_state = connected_state(start);
+        let later = start + REKEY_ATTEMPT_TIME;
+        assert!(!monitor.peer_is_dead(later));
+        assert!(!monitor.peer_is_dead(later + REKEY_ATTEMPT_TIME));
+    }
+
     #[test]
     /// Verify that `check_connectivity()` returns `true` if the tunnel is connected and traffic is
     /// flowing constantly.
@@ -704,10 +1018,10 @@ mod test {
             let start = now - Duration::from_secs(1);
             let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, stop_rx);
 
-            let start_result = monitor.establish_connectivity(0);
+            let start_result = monitor.establish_connectivity(0, None);
             result_tx.send(start_result).unwrap();
 
-            let result = monitor.run().map(|_| true);
+            let result = monitor.run(|_| ()).map(|_| true);
             result_tx.send(result).unwrap();
         });
 
@@ -731,6 +1045,7 @@ mod test {
             stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         );
         let tunnel_stats = Mutex::new(map);
@@ -757,9 +1072,9 @@ mod test {
             let now = Instant::now();
             let start = now - Duration::from_secs(1);
             let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, stop_rx);
-            let start_result = monitor.establish_connectivity(0);
+            let start_result = monitor.establish_connectivity(0, None);
             result_tx.send(start_result).unwrap();
-            let end_result = monitor.run().map(|_| true);
+            let end_result = monitor.run(|_| ()).map(|_| true);
             result_tx.send(end_result).expect("Failed to send result");
         });
         assert!(result_rx
@@ -782,6 +1097,7 @@ mod test {
             stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         );
 
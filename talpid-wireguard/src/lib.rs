@@ -19,7 +19,7 @@ use std::{
     net::IpAddr,
     path::Path,
     pin::Pin,
-    sync::{mpsc as sync_mpsc, Arc, Mutex},
+    sync::{mpsc as sync_mpsc, Arc, Mutex, Weak},
     time::Duration,
 };
 use talpid_routing as routing;
@@ -32,8 +32,9 @@ use talpid_tunnel::{tun_provider::TunProvider, TunnelArgs, TunnelEvent, TunnelMe
 use talpid_types::BoxedError;
 use talpid_types::{
     net::{
-        obfuscation::ObfuscatorConfig, wireguard::PublicKey, AllowedTunnelTraffic, Endpoint,
-        TransportProtocol,
+        obfuscation::ObfuscatorConfig,
+        wireguard::{PrivateKey, PublicKey},
+        AllowedTunnelTraffic, Endpoint, TransportProtocol,
     },
     ErrorExt,
 };
@@ -91,6 +92,20 @@ pub enum Error {
     #[error(display = "Failed to negotiate PQ PSK")]
     PskNegotiationError(#[error(source)] talpid_tunnel_config_client::Error),
 
+    /// The private key of a tunnel with a negotiated PSK cannot be replaced, since the tunnel
+    /// uses an ephemeral key.
+    #[error(display = "The private key cannot be replaced on a tunnel with a negotiated PSK")]
+    ReplaceEphemeralKeyError,
+
+    /// The peers of a tunnel with a negotiated PSK cannot be updated, since the tunnel uses an
+    /// ephemeral configuration.
+    #[error(display = "The peers cannot be updated on a tunnel with a negotiated PSK")]
+    UpdateEphemeralPeerError,
+
+    /// The tunnel was closed before its configuration could be updated.
+    #[error(display = "The tunnel is closed")]
+    TunnelClosedError,
+
     /// Failed to set up IP interfaces.
     #[cfg(windows)]
     #[error(display = "Failed to set up IP interfaces")]
@@ -112,6 +127,69 @@ pub struct WireguardMonitor {
     close_msg_receiver: sync_mpsc::Receiver<CloseMsg>,
     pinger_stop_sender: sync_mpsc::Sender<()>,
     obfuscator: Arc<AsyncMutex<Option<ObfuscatorHandle>>>,
+    config_handle: TunnelConfigHandle,
+}
+
+/// Handle for updating the configuration of a running tunnel without closing it.
+#[derive(Clone)]
+pub struct TunnelConfigHandle {
+    tunnel: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+    /// The current configuration of the tunnel. This is `None` if a PSK is negotiated, since the
+    /// tunnel then uses an ephemeral key instead of the key that it was started with.
+    config: Arc<AsyncMutex<Option<Config>>>,
+    /// Whether the tunnel only allows traffic to the gateway. See
+    /// [`WireguardMonitor::patch_allowed_ips`].
+    gateway_only: bool,
+}
+
+impl TunnelConfigHandle {
+    /// Replaces the private key of the tunnel. The peers are left unchanged, so they must already
+    /// accept the new key.
+    pub async fn set_private_key(&self, private_key: PrivateKey) -> Result<()> {
+        self.update_config(Error::ReplaceEphemeralKeyError, |config| {
+            config.tunnel.private_key = private_key;
+        })
+        .await
+    }
+
+    /// Moves the entry peer to another port on the same relay. The other peers are left
+    /// unchanged.
+    pub async fn set_entry_port(&self, port: u16) -> Result<()> {
+        self.update_config(Error::UpdateEphemeralPeerError, |config| {
+            config.peers[0].endpoint.set_port(port);
+        })
+        .await
+    }
+
+    /// Applies `update` to the configuration of the tunnel. `ephemeral_error` is returned if the
+    /// tunnel uses a negotiated PSK, since its configuration is then not known.
+    async fn update_config(
+        &self,
+        ephemeral_error: Error,
+        update: impl FnOnce(&mut Config),
+    ) -> Result<()> {
+        let mut config = self.config.lock().await;
+        let config = config.as_mut().ok_or(ephemeral_error)?;
+        let mut new_config = config.clone();
+        update(&mut new_config);
+
+        let tunnel = self.tunnel.upgrade().ok_or(Error::TunnelClosedError)?;
+        let set_config_future = tunnel
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|tunnel| {
+                tunnel.set_config(
+                    WireguardMonitor::patch_allowed_ips(&new_config, self.gateway_only)
+                        .into_owned(),
+                )
+            })
+            .ok_or(Error::TunnelClosedError)?;
+        set_config_future.await.map_err(Error::TunnelError)?;
+
+        *config = new_config;
+        Ok(())
+    }
 }
 
 const INITIAL_PSK_EXCHANGE_TIMEOUT: Duration = Duration::from_secs(4);
@@ -272,13 +350,24 @@ impl WireguardMonitor {
 
         let event_callback = Box::new(on_event.clone());
         let (pinger_tx, pinger_rx) = sync_mpsc::channel();
+        let tunnel = Arc::new(Mutex::new(Some(tunnel)));
+        let config_handle = TunnelConfigHandle {
+            tunnel: Arc::downgrade(&tunnel),
+            config: Arc::new(AsyncMutex::new(if psk_negotiation.is_none() {
+                Some(config.clone())
+            } else {
+                None
+            })),
+            gateway_only: psk_negotiation.is_some(),
+        };
         let monitor = WireguardMonitor {
             runtime: args.runtime.clone(),
-            tunnel: Arc::new(Mutex::new(Some(tunnel))),
+            tunnel,
             event_callback,
             close_msg_receiver,
             pinger_stop_sender: pinger_tx,
             obfuscator: Arc::new(AsyncMutex::new(obfuscator)),
+            config_handle,
         };
 
         let gateway = config.ipv4_gateway;
@@ -346,8 +435,11 @@ impl WireguardMonitor {
                 .await;
             }
 
+            let connect_timeout = config.connect_timeout;
             let mut connectivity_monitor = tokio::task::spawn_blocking(move || {
-                match connectivity_monitor.establish_connectivity(args.retry_attempt) {
+                match connectivity_monitor
+                    .establish_connectivity(args.retry_attempt, connect_timeout)
+                {
                     Ok(true) => Ok(connectivity_monitor),
                     Ok(false) => {
                         log::warn!("Timeout while checking tunnel connection");
@@ -374,8 +466,13 @@ impl WireguardMonitor {
 
             (on_event)(TunnelEvent::Up(metadata)).await;
 
+            let runtime = tokio::runtime::Handle::current();
+            let on_health_event = on_event.clone();
             tokio::task::spawn_blocking(move || {
-                if let Err(error) = connectivity_monitor.run() {
+                let on_health_change = |health| {
+                    runtime.block_on((on_health_event)(TunnelEvent::HealthChanged(health)));
+                };
+                if let Err(error) = connectivity_monitor.run(on_health_change) {
                     log::error!(
                         "{}",
                         error.display_chain_with_msg("Connectivity monitor failed")
@@ -405,6 +502,11 @@ impl WireguardMonitor {
         Ok(monitor)
     }
 
+    /// Returns a handle for updating the configuration of the tunnel while it is running.
+    pub fn config_handle(&self) -> TunnelConfigHandle {
+        self.config_handle.clone()
+    }
+
     /// Replace `0.0.0.0/0`/`::/0` with the gateway IPs when `gateway_only` is true.
     /// Used to block traffic to other destinations while connecting on Android.
     fn patch_allowed_ips(config: &Config, gateway_only: bool) -> Cow<'_, Config> {
@@ -550,8 +652,10 @@ impl WireguardMonitor {
         #[cfg(windows)] route_manager_handle: crate::routing::RouteManagerHandle,
         #[cfg(windows)] setup_done_tx: mpsc::Sender<std::result::Result<(), BoxedError>>,
     ) -> Result<Box<dyn Tunnel>> {
+        // The kernel implementation creates its own device, so it can't use one that was handed
+        // to us by the embedder
         #[cfg(target_os = "linux")]
-        if !*FORCE_USERSPACE_WIREGUARD {
+        if !*FORCE_USERSPACE_WIREGUARD && !tun_provider.lock().unwrap().has_external_device() {
             if will_nm_manage_dns() {
                 match wireguard_kernel::NetworkManagerTunnel::new(runtime, config) {
                     Ok(tunnel) => {
@@ -1,14 +1,17 @@
-use byteorder::{NetworkEndian, WriteBytesExt};
+use byteorder::{ByteOrder, NetworkEndian, WriteBytesExt};
 use rand::Rng;
 use socket2::{Domain, Protocol, Socket, Type};
 use std::{
-    io::{self, Write},
+    collections::HashMap,
+    io::{self, Read, Write},
     net::{Ipv4Addr, SocketAddr},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 const SEND_RETRY_ATTEMPTS: u32 = 10;
+/// Large enough for any ICMP message received through the tunnel.
+const RECEIVE_BUFFER_SIZE: usize = 1500;
 
 /// Pinger errors
 #[derive(err_derive::Error, Debug)]
@@ -56,6 +59,8 @@ pub struct Pinger {
     addr: SocketAddr,
     id: u16,
     seq: u16,
+    /// Times at which unanswered echo requests were sent, by sequence number.
+    outstanding: HashMap<u16, Instant>,
 }
 
 impl Pinger {
@@ -80,6 +85,7 @@ impl Pinger {
             addr,
             id: rand::random(),
             seq: 0,
+            outstanding: HashMap::new(),
         })
     }
 
@@ -127,11 +133,58 @@ impl Pinger {
 impl super::Pinger for Pinger {
     fn send_icmp(&mut self) -> Result<()> {
         let mut message = [0u8; 50];
+        let seq = self.seq;
         self.construct_icmpv4_packet(&mut message)?;
-        self.send_ping_request(&message, self.addr)
+        self.send_ping_request(&message, self.addr)?;
+        self.outstanding.insert(seq, Instant::now());
+        Ok(())
+    }
+
+    fn measures_round_trip_time(&self) -> bool {
+        true
+    }
+
+    fn take_round_trip_times(&mut self, lost_after: Duration) -> Result<Vec<Option<Duration>>> {
+        let mut round_trip_times = vec![];
+        let mut buffer = [0u8; RECEIVE_BUFFER_SIZE];
+        loop {
+            match (&self.sock).read(&mut buffer) {
+                Ok(len) => {
+                    let sent = parse_echo_reply(&buffer[..len], self.id)
+                        .and_then(|seq| self.outstanding.remove(&seq));
+                    if let Some(sent) = sent {
+                        round_trip_times.push(Some(sent.elapsed()));
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => return Err(Error::Read(error)),
+            }
+        }
+        self.outstanding.retain(|_seq, sent| {
+            let lost = sent.elapsed() >= lost_after;
+            if lost {
+                round_trip_times.push(None);
+            }
+            !lost
+        });
+        Ok(round_trip_times)
     }
 }
 
+/// Returns the sequence number of an ICMP echo reply with the given ID. `packet` must start with
+/// the IPv4 header, which raw sockets include in received messages.
+fn parse_echo_reply(packet: &[u8], id: u16) -> Option<u16> {
+    const ICMP_ECHO_REPLY: u8 = 0x00;
+    const ICMP_HEADER_LEN: usize = 8;
+
+    let ip_header_len = usize::from(packet.first()? & 0x0f) * 4;
+    let icmp = packet.get(ip_header_len..ip_header_len + ICMP_HEADER_LEN)?;
+    if icmp[0] != ICMP_ECHO_REPLY || icmp[1] != 0 || NetworkEndian::read_u16(&icmp[4..6]) != id {
+        return None;
+    }
+    Some(NetworkEndian::read_u16(&icmp[6..8]))
+}
+
 trait PayloadWriter {
     fn packet_id(&mut self) -> u16;
     fn sequence_num(&mut self) -> u16;
@@ -145,7 +198,7 @@ impl PayloadWriter for Pinger {
 
     fn sequence_num(&mut self) -> u16 {
         let seq = self.seq;
-        self.seq += 1;
+        self.seq = self.seq.wrapping_add(1);
         seq
     }
 
@@ -238,6 +291,28 @@ mod test {
         assert_eq!(buffer, expected_packet);
     }
 
+    #[test]
+    fn test_parse_echo_reply() {
+        let mut packet = [
+            // IPv4 header, 20 bytes long
+            0x45, 0x00, 0x00, 0x24, 0x00, 0x00, 0x00, 0x00, 0x40, 0x01, 0x00, 0x00, 0x0a, 0x40,
+            0x00, 0x01, 0x0a, 0x40, 0x00, 0x02, // ICMP type - echo reply
+            0x00, // Code 0
+            0x00, // checksum
+            0x00, 0x00, // packet ID
+            0x1d, 0xcd, // sequence number
+            0x00, 0x07, // payload
+            0xb6, 0xe0, 0x87, 0x60, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(parse_echo_reply(&packet, 0x1dcd), Some(7));
+        assert_eq!(parse_echo_reply(&packet, 0x1dce), None);
+        assert_eq!(parse_echo_reply(&packet[..24], 0x1dcd), None);
+
+        // echo request
+        packet[20] = 0x08;
+        assert_eq!(parse_echo_reply(&packet, 0x1dcd), None);
+    }
+
     #[test]
     fn test_icmpv4_packet_too_short() {
         assert!(!construct_icmpv4_packet_inner(
@@ -8,12 +8,27 @@ mod imp;
 
 pub use imp::Error;
 
+use std::time::Duration;
+
 /// Trait for sending ICMP requests to get some traffic from a remote server
 pub trait Pinger: Send {
     /// Sends an ICMP packet
     fn send_icmp(&mut self) -> Result<(), Error>;
     /// Clears all resources used by the pinger.
     fn reset(&mut self) {}
+    /// Returns true if the pinger reads replies, so that `take_round_trip_times` can be used to
+    /// measure latency and packet loss.
+    fn measures_round_trip_time(&self) -> bool {
+        false
+    }
+    /// Returns the round-trip times of requests that were answered since the last call, and `None`
+    /// for every request that has gone unanswered for longer than `lost_after`.
+    fn take_round_trip_times(
+        &mut self,
+        _lost_after: Duration,
+    ) -> Result<Vec<Option<Duration>>, Error> {
+        Ok(vec![])
+    }
 }
 
 /// Create a new pinger
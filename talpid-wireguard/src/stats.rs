@@ -1,6 +1,8 @@
 #[cfg(target_os = "linux")]
 use super::wireguard_kernel::wg_message::{DeviceMessage, DeviceNla, PeerNla};
 
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 #[derive(err_derive::Error, Debug, PartialEq)]
 pub enum Error {
     #[error(display = "Failed to parse peer pubkey from string \"_0\"")]
@@ -21,6 +23,8 @@ pub enum Error {
 pub struct Stats {
     pub tx_bytes: u64,
     pub rx_bytes: u64,
+    /// When the latest handshake with the peer was completed, if one has been.
+    pub last_handshake: Option<SystemTime>,
 }
 
 /// A map from peer pubkeys to peer stats.
@@ -33,6 +37,8 @@ impl Stats {
         let mut peer = None;
         let mut tx_bytes = None;
         let mut rx_bytes = None;
+        let mut handshake_secs = 0;
+        let mut handshake_nanos = 0;
 
         // parts iterates over keys and values
         let parts = config.split('\n').filter_map(|line| {
@@ -51,6 +57,20 @@ impl Stats {
                     peer = Some(buffer);
                     tx_bytes = None;
                     rx_bytes = None;
+                    handshake_secs = 0;
+                    handshake_nanos = 0;
+                }
+                "last_handshake_time_sec" => {
+                    handshake_secs = value
+                        .trim()
+                        .parse()
+                        .map_err(|err| Error::IntParse(value.to_string(), err))?;
+                }
+                "last_handshake_time_nsec" => {
+                    handshake_nanos = value
+                        .trim()
+                        .parse()
+                        .map_err(|err| Error::IntParse(value.to_string(), err))?;
                 }
                 "rx_bytes" => {
                     rx_bytes = Some(
@@ -80,6 +100,7 @@ impl Stats {
                     Self {
                         tx_bytes: tx_bytes_val,
                         rx_bytes: rx_bytes_val,
+                        last_handshake: handshake_time(handshake_secs, handshake_nanos),
                     },
                 );
                 peer = None;
@@ -99,18 +120,32 @@ impl Stats {
                 for msg in peers {
                     let mut tx_bytes = 0;
                     let mut rx_bytes = 0;
+                    let mut last_handshake = None;
                     let mut pub_key = None;
 
                     for nla in &msg.0 {
                         match nla {
                             PeerNla::TxBytes(bytes) => tx_bytes = *bytes,
                             PeerNla::RxBytes(bytes) => rx_bytes = *bytes,
+                            PeerNla::LastHandshakeTime(time) => {
+                                last_handshake = handshake_time(
+                                    u64::try_from(time.tv_sec()).unwrap_or(0),
+                                    u32::try_from(time.tv_nsec()).unwrap_or(0),
+                                );
+                            }
                             PeerNla::PublicKey(key) => pub_key = Some(*key),
                             _ => continue,
                         }
                     }
                     if let Some(key) = pub_key {
-                        map.insert(key, Stats { tx_bytes, rx_bytes });
+                        map.insert(
+                            key,
+                            Stats {
+                                tx_bytes,
+                                rx_bytes,
+                                last_handshake,
+                            },
+                        );
                     }
                 }
             }
@@ -120,9 +155,19 @@ impl Stats {
     }
 }
 
+/// Returns the time of a handshake given as the time since the Unix epoch. WireGuard gives zero
+/// for peers that have not completed a handshake.
+fn handshake_time(secs: u64, nanos: u32) -> Option<SystemTime> {
+    if secs == 0 && nanos == 0 {
+        return None;
+    }
+    UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+}
+
 #[cfg(test)]
 mod test {
     use super::{Error, Stats};
+    use std::time::{Duration, UNIX_EPOCH};
 
     #[test]
     fn test_parsing() {
@@ -135,6 +180,10 @@ mod test {
         assert_eq!(actual_keys, [pubkey]);
         assert_eq!(stats[&pubkey].rx_bytes, 2396);
         assert_eq!(stats[&pubkey].tx_bytes, 2740);
+        assert_eq!(
+            stats[&pubkey].last_handshake,
+            Some(UNIX_EPOCH + Duration::new(1578420649, 369416131))
+        );
     }
 
     #[test]
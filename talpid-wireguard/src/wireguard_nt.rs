@@ -20,6 +20,7 @@ use std::{
     pin::Pin,
     ptr,
     sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use talpid_types::{BoxedError, ErrorExt};
 use talpid_windows_net as net;
@@ -312,6 +313,15 @@ bitflags! {
 }
 
 /// See `WIREGUARD_PEER` at https://git.zx2c4.com/wireguard-nt/tree/api/wireguard.h.
+/// Number of 100 ns intervals between 1601-01-01 and the Unix epoch.
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+/// Converts a `FILETIME` value to a `SystemTime`. Zero means that no handshake has been completed.
+fn filetime_to_system_time(filetime: u64) -> Option<SystemTime> {
+    let since_unix_epoch = filetime.checked_sub(FILETIME_UNIX_EPOCH)?;
+    UNIX_EPOCH.checked_add(Duration::from_nanos(since_unix_epoch.saturating_mul(100)))
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 #[repr(C, align(8))]
 struct WgPeer {
@@ -827,11 +837,14 @@ fn serialize_config(config: &Config) -> Result<Vec<MaybeUninit<u8>>> {
     buffer.extend(as_uninit_byte_slice(&header));
 
     for peer in &config.peers {
-        let flags = if peer.psk.is_some() {
+        let mut flags = if peer.psk.is_some() {
             WgPeerFlag::HAS_PRESHARED_KEY | WgPeerFlag::HAS_PUBLIC_KEY | WgPeerFlag::HAS_ENDPOINT
         } else {
             WgPeerFlag::HAS_PUBLIC_KEY | WgPeerFlag::HAS_ENDPOINT
         };
+        if config.persistent_keepalive.is_some() {
+            flags |= WgPeerFlag::HAS_PERSISTENT_KEEPALIVE;
+        }
         let wg_peer = WgPeer {
             flags,
             reserved: 0,
@@ -841,7 +854,7 @@ fn serialize_config(config: &Config) -> Result<Vec<MaybeUninit<u8>>> {
                 .as_ref()
                 .map(|psk| psk.as_bytes().clone())
                 .unwrap_or([0u8; WIREGUARD_KEY_LENGTH]),
-            persistent_keepalive: 0,
+            persistent_keepalive: config.persistent_keepalive.unwrap_or(0),
             endpoint: net::inet_sockaddr_from_socketaddr(peer.endpoint).into(),
             tx_bytes: 0,
             rx_bytes: 0,
@@ -951,6 +964,7 @@ impl Tunnel for WgNtTunnel {
                     Stats {
                         tx_bytes: peer.tx_bytes,
                         rx_bytes: peer.rx_bytes,
+                        last_handshake: filetime_to_system_time(peer.last_handshake),
                     },
                 );
             }
@@ -1023,6 +1037,8 @@ mod tests {
                 ipv4_gateway: "0.0.0.0".parse().unwrap(),
                 ipv6_gateway: None,
                 mtu: 0,
+                persistent_keepalive: None,
+                connect_timeout: None,
                 use_wireguard_nt: true,
                 obfuscator_config: None,
             }
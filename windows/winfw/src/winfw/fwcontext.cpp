@@ -1,5 +1,6 @@
 #include "stdafx.h"
 #include "fwcontext.h"
+#include "mullvadguids.h"
 #include "mullvadobjects.h"
 #include "objectpurger.h"
 #include "rules/ifirewallrule.h"
@@ -9,6 +10,7 @@
 #include "rules/baseline/permitndp.h"
 #include "rules/baseline/permitdhcpserver.h"
 #include "rules/baseline/permitlan.h"
+#include "rules/baseline/permitlanhosts.h"
 #include "rules/baseline/permitlanservice.h"
 #include "rules/baseline/permitloopback.h"
 #include "rules/baseline/permitvpntunnel.h"
@@ -64,6 +66,15 @@ void AppendSettingsRules
 		ruleset.emplace_back(std::make_unique<baseline::PermitLanService>());
 		ruleset.emplace_back(baseline::PermitDhcpServer::WithExtent(baseline::PermitDhcpServer::Extent::IPv4Only));
 	}
+	else if (0 != settings.numAllowedLanHosts)
+	{
+		std::vector<wfp::IpAddress> hosts;
+		hosts.reserve(settings.numAllowedLanHosts);
+		for (uint32_t i = 0; i < settings.numAllowedLanHosts; i++) {
+			hosts.emplace_back(settings.allowedLanHosts[i]);
+		}
+		ruleset.emplace_back(std::make_unique<baseline::PermitLanHosts>(hosts));
+	}
 
 	//
 	// DNS management
@@ -116,11 +127,39 @@ void AppendAllowedEndpointRules
 	}
 
 	ruleset.emplace_back(std::make_unique<baseline::PermitEndpoint>(
+		MullvadGuids::Filter_Baseline_PermitEndpoint(),
 		wfp::IpAddress(endpoint.endpoint.ip),
 		clients,
 		endpoint.endpoint.port,
 		endpoint.endpoint.protocol
 	));
+
+	if (nullptr != endpoint.extraEndpoint)
+	{
+		ruleset.emplace_back(std::make_unique<baseline::PermitEndpoint>(
+			MullvadGuids::Filter_Baseline_PermitExtraEndpoint(),
+			wfp::IpAddress(endpoint.extraEndpoint->ip),
+			clients,
+			endpoint.extraEndpoint->port,
+			endpoint.extraEndpoint->protocol
+		));
+	}
+
+	if (endpoint.numResolvers > MullvadGuids::MaxResolverEndpoints)
+	{
+		THROW_ERROR("Too many resolver endpoints");
+	}
+
+	for (uint32_t i = 0; i < endpoint.numResolvers; i++)
+	{
+		ruleset.emplace_back(std::make_unique<baseline::PermitEndpoint>(
+			MullvadGuids::Filter_Baseline_PermitResolverEndpoint(i),
+			wfp::IpAddress(endpoint.resolvers[i].ip),
+			clients,
+			endpoint.resolvers[i].port,
+			endpoint.resolvers[i].protocol
+		));
+	}
 }
 
 void AppendNetBlockedRules(FwContext::Ruleset &ruleset)
@@ -1,5 +1,6 @@
 #include "stdafx.h"
 #include "mullvadguids.h"
+#include <libcommon/error.h>
 #include <algorithm>
 #include <iterator>
 
@@ -118,6 +119,10 @@ MullvadGuids::DetailedIdentityRegistry MullvadGuids::DetailedRegistry(IdentityQu
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitLan_Outbound_Multicast_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitLanService_Inbound_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitLanService_Inbound_Ipv6()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitLanHosts_Outbound_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitLanHosts_Inbound_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitLanHosts_Outbound_Ipv6()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitLanHosts_Inbound_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitLoopback_Outbound_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitLoopback_Inbound_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitLoopback_Outbound_Ipv6()));
@@ -130,6 +135,10 @@ MullvadGuids::DetailedIdentityRegistry MullvadGuids::DetailedRegistry(IdentityQu
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitDhcpServer_Outbound_Response_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVpnRelay()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitEndpoint()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitExtraEndpoint()));
+	for (size_t i = 0; i < MaxResolverEndpoints; i++) {
+		registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitResolverEndpoint(i)));
+	}
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVpnTunnel_Outbound_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVpnTunnel_Outbound_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVpnTunnelService_Ipv4()));
@@ -494,6 +503,62 @@ const GUID &MullvadGuids::Filter_Baseline_PermitLanService_Inbound_Ipv6()
 	return g;
 }
 
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitLanHosts_Outbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0x6b2f9e41,
+		0x3a8c,
+		0x4d17,
+		{ 0xa5, 0x2e, 0x91, 0x0f, 0x7c, 0x63, 0xd8, 0x14 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitLanHosts_Inbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0x9f4c1d87,
+		0x52e0,
+		0x4b6a,
+		{ 0x8e, 0x13, 0x4a, 0xc7, 0x2d, 0x90, 0x5b, 0xe6 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitLanHosts_Outbound_Ipv6()
+{
+	static const GUID g =
+	{
+		0x1e7a3c52,
+		0xb9d4,
+		0x47f8,
+		{ 0x9c, 0x61, 0x0d, 0x38, 0xe4, 0x7b, 0xa2, 0x59 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitLanHosts_Inbound_Ipv6()
+{
+	static const GUID g =
+	{
+		0xd35b8f06,
+		0x6c21,
+		0x4e93,
+		{ 0xb7, 0x48, 0x2f, 0x95, 0x1a, 0xce, 0x03, 0x7d }
+	};
+
+	return g;
+}
+
 //static
 const GUID &MullvadGuids::Filter_Baseline_PermitLoopback_Outbound_Ipv4()
 {
@@ -662,6 +727,59 @@ const GUID &MullvadGuids::Filter_Baseline_PermitEndpoint()
 	return g;
 }
 
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitExtraEndpoint()
+{
+	static const GUID g =
+	{
+		0x3c8a5f21,
+		0x7b1e,
+		0x4d59,
+		{ 0x9e, 0x42, 0x6a, 0xd1, 0x0b, 0x73, 0xc5, 0x18 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitResolverEndpoint(size_t index)
+{
+	static const GUID g[MaxResolverEndpoints] =
+	{
+		{
+			0x5e0b7f4a,
+			0x2c91,
+			0x4a3d,
+			{ 0x8f, 0x16, 0xd2, 0x47, 0x90, 0x3b, 0xa1, 0x6c }
+		},
+		{
+			0x8d4f2a63,
+			0x1b7e,
+			0x4c05,
+			{ 0xa9, 0x3e, 0x57, 0x0c, 0xe8, 0x21, 0x4d, 0xb2 }
+		},
+		{
+			0x2a9c6e15,
+			0xf4d3,
+			0x4b87,
+			{ 0xb5, 0x62, 0x1e, 0xa8, 0x3f, 0x09, 0x7c, 0xd4 }
+		},
+		{
+			0xc71e3b98,
+			0x6a20,
+			0x4f1c,
+			{ 0x93, 0xd7, 0x4b, 0x85, 0x2e, 0x6f, 0x10, 0xa3 }
+		},
+	};
+
+	if (index >= MaxResolverEndpoints)
+	{
+		THROW_ERROR("Invalid resolver endpoint index");
+	}
+
+	return g[index];
+}
+
 //static
 const GUID &MullvadGuids::Filter_Baseline_PermitVpnTunnel_Outbound_Ipv4()
 {
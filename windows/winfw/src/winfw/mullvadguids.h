@@ -54,6 +54,11 @@ public:
 	static const GUID &Filter_Baseline_PermitLanService_Inbound_Ipv4();
 	static const GUID &Filter_Baseline_PermitLanService_Inbound_Ipv6();
 
+	static const GUID &Filter_Baseline_PermitLanHosts_Outbound_Ipv4();
+	static const GUID &Filter_Baseline_PermitLanHosts_Inbound_Ipv4();
+	static const GUID &Filter_Baseline_PermitLanHosts_Outbound_Ipv6();
+	static const GUID &Filter_Baseline_PermitLanHosts_Inbound_Ipv6();
+
 	static const GUID &Filter_Baseline_PermitLoopback_Outbound_Ipv4();
 	static const GUID &Filter_Baseline_PermitLoopback_Inbound_Ipv4();
 	static const GUID &Filter_Baseline_PermitLoopback_Outbound_Ipv6();
@@ -70,6 +75,10 @@ public:
 	static const GUID &Filter_Baseline_PermitVpnRelay();
 
 	static const GUID &Filter_Baseline_PermitEndpoint();
+	static const GUID &Filter_Baseline_PermitExtraEndpoint();
+
+	static constexpr size_t MaxResolverEndpoints = 4;
+	static const GUID &Filter_Baseline_PermitResolverEndpoint(size_t index);
 
 	static const GUID &Filter_Baseline_PermitVpnTunnel_Outbound_Ipv4();
 	static const GUID &Filter_Baseline_PermitVpnTunnel_Outbound_Ipv6();
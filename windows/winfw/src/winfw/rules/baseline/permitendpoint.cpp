@@ -35,12 +35,14 @@ const GUID &OutboundLayerFromIp(const wfp::IpAddress &ip)
 
 PermitEndpoint::PermitEndpoint
 (
+	const GUID &filterKey,
 	const wfp::IpAddress &address,
 	const std::vector<std::wstring> &clients,
 	uint16_t port,
 	WinFwProtocol protocol
 )
-	: m_address(address)
+	: m_filterKey(filterKey)
+	, m_address(address)
 	, m_clients(clients)
 	, m_port(port)
 	, m_protocol(protocol)
@@ -56,7 +58,7 @@ bool PermitEndpoint::apply(IObjectInstaller &objectInstaller)
 	//
 
 	filterBuilder
-		.key(MullvadGuids::Filter_Baseline_PermitEndpoint())
+		.key(m_filterKey)
 		.name(L"Permit outbound connections to a given endpoint")
 		.description(L"This filter is part of a rule that permits traffic to a specific endpoint")
 		.provider(MullvadGuids::Provider())
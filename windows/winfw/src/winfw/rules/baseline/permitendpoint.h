@@ -15,6 +15,7 @@ public:
 
 	PermitEndpoint
 	(
+		const GUID &filterKey,
 		const wfp::IpAddress &address,
 		const std::vector<std::wstring> &clients,
 		uint16_t port,
@@ -25,6 +26,7 @@ public:
 
 private:
 
+	const GUID m_filterKey;
 	const wfp::IpAddress m_address;
 	const std::vector<std::wstring> m_clients;
 	const uint16_t m_port;
@@ -0,0 +1,141 @@
+#include "stdafx.h"
+#include "permitlanhosts.h"
+#include <winfw/mullvadguids.h>
+#include <libwfp/filterbuilder.h>
+#include <libwfp/conditionbuilder.h>
+#include <libwfp/conditions/conditionip.h>
+
+using namespace wfp::conditions;
+
+namespace rules::baseline
+{
+
+PermitLanHosts::PermitLanHosts(const std::vector<wfp::IpAddress> &hosts)
+{
+	for (const auto &host : hosts)
+	{
+		if (wfp::IpAddress::Type::Ipv4 == host.type())
+		{
+			m_ipv4Hosts.push_back(host);
+		}
+		else
+		{
+			m_ipv6Hosts.push_back(host);
+		}
+	}
+}
+
+bool PermitLanHosts::apply(IObjectInstaller &objectInstaller)
+{
+	return applyIpv4(objectInstaller) && applyIpv6(objectInstaller);
+}
+
+bool PermitLanHosts::applyIpv4(IObjectInstaller &objectInstaller) const
+{
+	if (m_ipv4Hosts.empty())
+	{
+		return true;
+	}
+
+	wfp::FilterBuilder filterBuilder;
+
+	//
+	// #1 Permit outbound connections to the hosts.
+	//
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Baseline_PermitLanHosts_Outbound_Ipv4())
+		.name(L"Permit outbound connections to given LAN hosts (IPv4)")
+		.description(L"This filter is part of a rule that permits traffic with specific LAN hosts")
+		.provider(MullvadGuids::Provider())
+		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V4)
+		.sublayer(MullvadGuids::SublayerBaseline())
+		.weight(wfp::FilterBuilder::WeightClass::Medium)
+		.permit();
+
+	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+
+	for (const auto &host : m_ipv4Hosts)
+	{
+		conditionBuilder.add_condition(ConditionIp::Remote(host));
+	}
+
+	if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
+	{
+		return false;
+	}
+
+	//
+	// #2 Permit inbound connections from the hosts.
+	//
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Baseline_PermitLanHosts_Inbound_Ipv4())
+		.name(L"Permit inbound connections from given LAN hosts (IPv4)")
+		.layer(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
+
+	wfp::ConditionBuilder inboundConditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
+
+	for (const auto &host : m_ipv4Hosts)
+	{
+		inboundConditionBuilder.add_condition(ConditionIp::Remote(host));
+	}
+
+	return objectInstaller.addFilter(filterBuilder, inboundConditionBuilder);
+}
+
+bool PermitLanHosts::applyIpv6(IObjectInstaller &objectInstaller) const
+{
+	if (m_ipv6Hosts.empty())
+	{
+		return true;
+	}
+
+	wfp::FilterBuilder filterBuilder;
+
+	//
+	// #1 Permit outbound connections to the hosts.
+	//
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Baseline_PermitLanHosts_Outbound_Ipv6())
+		.name(L"Permit outbound connections to given LAN hosts (IPv6)")
+		.description(L"This filter is part of a rule that permits traffic with specific LAN hosts")
+		.provider(MullvadGuids::Provider())
+		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V6)
+		.sublayer(MullvadGuids::SublayerBaseline())
+		.weight(wfp::FilterBuilder::WeightClass::Medium)
+		.permit();
+
+	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+
+	for (const auto &host : m_ipv6Hosts)
+	{
+		conditionBuilder.add_condition(ConditionIp::Remote(host));
+	}
+
+	if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
+	{
+		return false;
+	}
+
+	//
+	// #2 Permit inbound connections from the hosts.
+	//
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Baseline_PermitLanHosts_Inbound_Ipv6())
+		.name(L"Permit inbound connections from given LAN hosts (IPv6)")
+		.layer(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+
+	wfp::ConditionBuilder inboundConditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+
+	for (const auto &host : m_ipv6Hosts)
+	{
+		inboundConditionBuilder.add_condition(ConditionIp::Remote(host));
+	}
+
+	return objectInstaller.addFilter(filterBuilder, inboundConditionBuilder);
+}
+
+}
@@ -0,0 +1,28 @@
+#pragma once
+
+#include <winfw/rules/ifirewallrule.h>
+#include <libwfp/ipaddress.h>
+#include <vector>
+
+namespace rules::baseline
+{
+
+class PermitLanHosts : public IFirewallRule
+{
+public:
+
+	PermitLanHosts(const std::vector<wfp::IpAddress> &hosts);
+	~PermitLanHosts() = default;
+
+	bool apply(IObjectInstaller &objectInstaller) override;
+
+private:
+
+	bool applyIpv4(IObjectInstaller &objectInstaller) const;
+	bool applyIpv6(IObjectInstaller &objectInstaller) const;
+
+	std::vector<wfp::IpAddress> m_ipv4Hosts;
+	std::vector<wfp::IpAddress> m_ipv6Hosts;
+};
+
+}
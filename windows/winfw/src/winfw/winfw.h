@@ -26,6 +26,10 @@ typedef struct tag_WinFwSettings
 
 	// Permit all traffic to and from private address ranges.
 	bool permitLan;
+
+	// Local hosts to permit all traffic to and from, even if `permitLan` is false.
+	uint32_t numAllowedLanHosts;
+	const wchar_t **allowedLanHosts;
 }
 WinFwSettings;
 
@@ -52,6 +56,13 @@ typedef struct tag_WinFwAllowedEndpoint
 	const wchar_t **clients;
 
 	WinFwEndpoint endpoint;
+
+	// An additional endpoint that the clients may reach, or nullptr.
+	const WinFwEndpoint *extraEndpoint;
+
+	// Encrypted DNS resolvers that the clients may use to look up the API host.
+	uint32_t numResolvers;
+	const WinFwEndpoint *resolvers;
 }
 WinFwAllowedEndpoint;
 